@@ -0,0 +1,43 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use plutus_engine::generator::{generate, GeneratorConfig};
+use plutus_engine::parser::{CsvBackend, CsvOptions};
+
+/// The number of rows in the generated workload
+const ROWS: u64 = 100_000;
+
+/// The standard synthetic workload, as written by the generate subcommand with its default
+/// settings for everything but the number of rows
+fn workload() -> Vec<u8> {
+    let config = GeneratorConfig {
+        rows: ROWS,
+        ..Default::default()
+    };
+    let mut csv = vec![];
+    generate(&config, &mut csv).unwrap();
+    csv
+}
+
+/// Compares how quickly each backend parses the workload into records
+fn bench_csv_parsing(c: &mut Criterion) {
+    let csv = workload();
+    let mut group = c.benchmark_group("csv_parsing");
+    group.throughput(Throughput::Bytes(csv.len() as u64));
+
+    for (name, backend) in [("csv", CsvBackend::Csv), ("fast", CsvBackend::Fast)] {
+        group.bench_with_input(BenchmarkId::new(name, ROWS), &csv, |b, csv| {
+            b.iter(|| {
+                let parser = backend.parser();
+                let options = CsvOptions::default();
+                let records = parser
+                    .records(Box::new(csv.as_slice()), b',', &options)
+                    .unwrap();
+                assert_eq!(records.count() as u64, ROWS);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_csv_parsing);
+criterion_main!(benches);
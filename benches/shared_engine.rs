@@ -0,0 +1,85 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use plutus_engine::engine::Engine;
+use plutus_engine::mapper::Record;
+use plutus_engine::shared::SharedEngine;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The number of records each thread processes per iteration
+const RECORDS_PER_THREAD: u32 = 2_000;
+
+/// Deposits and withdrawals spread across 1,000 clients
+fn records(thread_id: u32) -> Vec<Record> {
+    (0..RECORDS_PER_THREAD)
+        .map(|i| {
+            let transaction_id = thread_id * RECORDS_PER_THREAD + i;
+            let client_id = (transaction_id % 1_000) as u16;
+
+            if i % 4 == 3 {
+                Record::withdrawal(client_id, transaction_id, 0.5)
+            } else {
+                Record::deposit(client_id, transaction_id, 1.0)
+            }
+        })
+        .collect()
+}
+
+/// Processes every record from its own thread, using the given function
+fn run_threads(thread_count: u32, process: Arc<dyn Fn(&Record) + Send + Sync>) {
+    let handles: Vec<_> = (0..thread_count)
+        .map(|thread_id| {
+            let process = Arc::clone(&process);
+            let records = records(thread_id);
+
+            thread::spawn(move || records.iter().for_each(|record| process(record)))
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+/// Compares the sharded engine against a single engine behind one lock
+fn bench_shared_engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent_processing");
+
+    for thread_count in [1, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("single_lock", thread_count),
+            &thread_count,
+            |b, &thread_count| {
+                b.iter(|| {
+                    let engine = Arc::new(Mutex::new(Engine::default()));
+                    run_threads(
+                        thread_count,
+                        Arc::new(move |record: &Record| {
+                            let _ = engine.lock().unwrap().process(record);
+                        }),
+                    );
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("sharded", thread_count),
+            &thread_count,
+            |b, &thread_count| {
+                b.iter(|| {
+                    let engine = Arc::new(SharedEngine::default());
+                    run_threads(
+                        thread_count,
+                        Arc::new(move |record: &Record| {
+                            let _ = engine.process(record);
+                        }),
+                    );
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_shared_engine);
+criterion_main!(benches);
@@ -0,0 +1,295 @@
+use crate::error::{CliError, CliResult, LedgerError, LedgerResult, SourceError, SourceResult};
+use crate::mapper::{Account, LockState, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The rule an account closed by an operator is locked with
+pub const CLOSED_RULE: &str = "closed";
+
+/// The operations that can be made in an admin operations file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdminAction {
+    /// Lifts whatever lock is on the account
+    Unlock,
+
+    /// Sets the most that a single withdrawal can take from the account, or clears the limit when
+    /// the amount is left blank
+    SetLimit,
+
+    /// Closes the account, locking it so it no longer accepts deposits or withdrawals
+    Close,
+
+    /// Resolves a disputed transaction, even when the account's lock wouldn't permit a resolve
+    ForceResolve,
+
+    /// Releases a quarantined account, resolving the deposits that were held while it was
+    /// quarantined
+    Release,
+}
+
+impl AdminAction {
+    /// The name of the operation, as it's written in an admin operations file
+    pub fn name(self) -> &'static str {
+        match self {
+            AdminAction::Unlock => "unlock",
+            AdminAction::SetLimit => "set-limit",
+            AdminAction::Close => "close",
+            AdminAction::ForceResolve => "force-resolve",
+            AdminAction::Release => "release",
+        }
+    }
+}
+
+/// When the admin operations are applied, relative to the transactions in the file
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum AdminPhase {
+    /// Every operation is applied before the first transaction
+    #[default]
+    Before,
+
+    /// Every operation is applied once the last transaction has been applied
+    After,
+}
+
+impl FromStr for AdminPhase {
+    type Err = CliError;
+
+    fn from_str(phase: &str) -> CliResult<Self> {
+        match phase.to_lowercase().as_str() {
+            "before" => Ok(AdminPhase::Before),
+            "after" => Ok(AdminPhase::After),
+            _ => Err(CliError::InvalidValue(
+                "--admin-ops-phase".to_string(),
+                phase.to_string(),
+            )),
+        }
+    }
+}
+
+/// An operation made by an operator on a client's account, read from a row of an admin
+/// operations file and journaled once it's applied
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AdminOperation {
+    /// What the operator did
+    pub op: AdminAction,
+
+    /// The unique ID of the client whose account was operated on
+    pub client: u16,
+
+    /// The transaction a force-resolve resolves
+    #[serde(default)]
+    pub tx: Option<u32>,
+
+    /// The limit set by a set-limit
+    #[serde(default)]
+    pub amount: Option<f32>,
+
+    /// Who made the operation
+    pub operator: String,
+
+    /// When the operation was applied, in milliseconds since the unix epoch. It's stamped by the
+    /// journal's clock when the operation is journaled.
+    #[serde(default)]
+    pub recorded_at_ms: u64,
+}
+
+impl AdminOperation {
+    /// Applies the operation to the client's account
+    pub fn apply(&self, account: &mut Account) -> LedgerResult<()> {
+        match self.op {
+            AdminAction::Unlock => account.lock_state = LockState::Unlocked,
+            AdminAction::SetLimit => account.withdrawal_limit = self.amount,
+            AdminAction::Close => {
+                account.lock_state = LockState::AdminLock {
+                    rule: CLOSED_RULE.to_string(),
+                }
+            }
+            AdminAction::ForceResolve => {
+                // the tx is checked when the file is loaded
+                let tx = self.tx.unwrap_or_default();
+                if !is_disputed(account, tx) {
+                    return Err(LedgerError::NotDisputed(tx));
+                }
+
+                account.resolve(tx)?;
+            }
+            AdminAction::Release => {
+                let LockState::Quarantine { held, .. } = &account.lock_state else {
+                    return Err(LedgerError::NotQuarantined(self.client));
+                };
+
+                // deposits that have since been charged back or resolved are left alone
+                for tx in held.clone() {
+                    if is_disputed(account, tx) {
+                        account.resolve(tx)?;
+                    }
+                }
+                account.lock_state = LockState::Unlocked;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the account's transaction is being disputed
+fn is_disputed(account: &Account, tx: u32) -> bool {
+    account
+        .successful_transactions
+        .get(&tx)
+        .is_some_and(|transaction| transaction.current_state == TransactionType::Dispute)
+}
+
+/// Loads the operations in an admin operations file, along with the line each was read from.
+/// Operators must identify themselves, and every force-resolve must name its transaction.
+pub fn load_admin_operations(
+    file_path: impl AsRef<Path>,
+) -> SourceResult<Vec<(u64, AdminOperation)>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(file_path)
+        .map_err(SourceError::from)?;
+
+    let mut operations = vec![];
+    for (index, row) in reader.deserialize().enumerate() {
+        // the header is the first line
+        let line = index as u64 + 2;
+        let operation: AdminOperation = row.map_err(SourceError::from)?;
+
+        let invalid = |message: &str| SourceError::Parse {
+            line,
+            message: message.to_string(),
+        };
+        if operation.operator.is_empty() {
+            return Err(invalid(
+                "every operation must include the operator that made it",
+            ));
+        }
+        if operation.op == AdminAction::ForceResolve && operation.tx.is_none() {
+            return Err(invalid("a force-resolve must include the tx it resolves"));
+        }
+
+        operations.push((line, operation));
+    }
+
+    Ok(operations)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admin::{load_admin_operations, AdminAction, AdminOperation, CLOSED_RULE};
+    use crate::error::{LedgerError, SourceError};
+    use crate::mapper::{Account, LockState};
+    use crate::test_helpers::*;
+    use std::fs::File;
+    use std::io::{Error, Write};
+
+    // Tests that operations are loaded with their lines, and each one is applied to the account
+    #[test]
+    fn test_admin_operations() -> Result<(), Error> {
+        let (file_path, dir, mut file) = create_temp_file("admin.csv")?;
+        writeln!(file, "op,client,tx,amount,operator")?;
+        writeln!(file, "close,1,,,alice")?;
+        writeln!(file, "unlock,1,,,alice")?;
+        writeln!(file, "set-limit,1,,50,bob")?;
+        writeln!(file, "force-resolve,1,7,,bob")?;
+
+        let operations = load_admin_operations(&file_path).unwrap();
+        let lines: Vec<u64> = operations.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [2, 3, 4, 5]);
+        assert_eq!(operations[2].1.op, AdminAction::SetLimit);
+        assert_eq!(operations[2].1.operator, "bob");
+
+        let mut account = Account::default();
+        account.deposit(10.0, 7);
+        account.dispute(7);
+
+        let apply = |account: &mut Account, index: usize| operations[index].1.apply(account);
+        apply(&mut account, 0).unwrap();
+        assert_eq!(
+            account.lock_state,
+            LockState::AdminLock {
+                rule: CLOSED_RULE.to_string()
+            }
+        );
+        apply(&mut account, 1).unwrap();
+        assert_eq!(account.lock_state, LockState::Unlocked);
+        apply(&mut account, 2).unwrap();
+        assert_eq!(account.withdrawal_limit, Some(50.0));
+        apply(&mut account, 3).unwrap();
+        assert_account(&account, 10.0, 10.0, true);
+
+        // the transaction is no longer disputed
+        assert_eq!(apply(&mut account, 3), Err(LedgerError::NotDisputed(7)));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a release resolves the deposits held by the quarantine and unlocks the account,
+    // and that only quarantined accounts can be released
+    #[test]
+    fn test_admin_release() {
+        let release = AdminOperation {
+            op: AdminAction::Release,
+            client: 1,
+            tx: None,
+            amount: None,
+            operator: "alice".to_string(),
+            recorded_at_ms: 0,
+        };
+
+        let mut account = Account::default();
+        account.deposit(10.0, 1);
+        account.deposit(5.0, 2);
+        account.dispute(2);
+        account.deposit(3.0, 3);
+        account.dispute(3);
+        account.chargeback(3).unwrap();
+        account.lock_state = LockState::Quarantine {
+            rule: "1 disputes".to_string(),
+            held: vec![2, 3],
+        };
+        assert_account(&account, 10.0, 15.0, true);
+
+        release.apply(&mut account).unwrap();
+        assert_account(&account, 15.0, 15.0, true);
+        assert_eq!(account.lock_state, LockState::Unlocked);
+        assert_eq!(
+            release.apply(&mut account),
+            Err(LedgerError::NotQuarantined(1))
+        );
+    }
+
+    // Tests that operations without an operator, and force-resolves without a tx, are rejected
+    #[test]
+    fn test_load_admin_operations_invalid() -> Result<(), Error> {
+        let (file_path, dir, mut file) = create_temp_file("admin.csv")?;
+        writeln!(file, "op,client,tx,amount,operator")?;
+        writeln!(file, "unlock,1,,,alice")?;
+        writeln!(file, "force-resolve,1,,,alice")?;
+
+        assert!(matches!(
+            load_admin_operations(&file_path),
+            Err(SourceError::Parse { line: 3, .. })
+        ));
+
+        let mut file = File::create(&file_path)?;
+        writeln!(file, "op,client,tx,amount,operator")?;
+        writeln!(file, "unlock,1,,,")?;
+        assert!(matches!(
+            load_admin_operations(&file_path),
+            Err(SourceError::Parse { line: 2, .. })
+        ));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::{Account, AccountRecord};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Thresholds that raise an alert when an account crosses them, each rule is optional
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AlertRules {
+    /// Alerts when the available funds end the run below this amount
+    pub available_below: Option<f32>,
+
+    /// Alerts when the held funds end the run above this amount
+    pub held_above: Option<f32>,
+
+    /// Alerts when the total funds change by more than this percentage during the run
+    pub total_change_pct: Option<f32>,
+}
+
+/// The rule that an account broke
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlertKind {
+    /// The available funds are below the threshold
+    AvailableBelow,
+
+    /// The held funds are above the threshold
+    HeldAbove,
+
+    /// The total funds changed by more than the threshold percentage
+    TotalChange,
+}
+
+/// An account that broke one of the alert rules
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Alert {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The rule that was broken
+    pub kind: AlertKind,
+
+    /// The value that broke the rule, a percentage for total changes
+    pub value: f32,
+
+    /// The threshold of the rule
+    pub threshold: f32,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            AlertKind::AvailableBelow => write!(
+                f,
+                "client {} available funds {} are below {}",
+                self.client, self.value, self.threshold
+            ),
+            AlertKind::HeldAbove => write!(
+                f,
+                "client {} held funds {} are above {}",
+                self.client, self.value, self.threshold
+            ),
+            AlertKind::TotalChange => write!(
+                f,
+                "client {} total funds changed by {}%, more than {}%",
+                self.client, self.value, self.threshold
+            ),
+        }
+    }
+}
+
+impl AlertRules {
+    /// Whether any rule has been configured
+    pub fn is_empty(&self) -> bool {
+        self == &AlertRules::default()
+    }
+
+    /// Checks every account against the rules, ordered by client id. The change in total funds is
+    /// relative to the snapshot taken before the run, accounts that started with no funds have
+    /// nothing to compare against so they're skipped by that rule.
+    pub fn evaluate(
+        &self,
+        before: &HashMap<u16, AccountRecord>,
+        after: &HashMap<u16, Account>,
+    ) -> Vec<Alert> {
+        let mut client_ids: Vec<&u16> = after.keys().collect();
+        client_ids.sort();
+
+        let mut alerts = vec![];
+        for client_id in client_ids {
+            let account = AccountRecord::new(*client_id, &after[client_id]);
+            let mut alert = |kind, value, threshold| {
+                alerts.push(Alert {
+                    client: *client_id,
+                    kind,
+                    value,
+                    threshold,
+                })
+            };
+
+            if let Some(threshold) = self.available_below {
+                if account.available < threshold {
+                    alert(AlertKind::AvailableBelow, account.available, threshold);
+                }
+            }
+
+            if let Some(threshold) = self.held_above {
+                if account.held > threshold {
+                    alert(AlertKind::HeldAbove, account.held, threshold);
+                }
+            }
+
+            let previous = before.get(client_id);
+            if let (Some(threshold), Some(previous)) = (self.total_change_pct, previous) {
+                if previous.total != 0.0 {
+                    let change = account.total - previous.total;
+                    let change_pct = change * 100.0 / previous.total.abs();
+
+                    if change_pct.abs() > threshold {
+                        alert(AlertKind::TotalChange, change_pct, threshold);
+                    }
+                }
+            }
+        }
+
+        alerts
+    }
+}
+
+/// Writes the alerts report as a csv
+pub fn write_alerts_report(file_path: &Path, alerts: &[Alert]) -> SourceResult<()> {
+    let mut writer = csv::Writer::from_path(file_path).map_err(SourceError::from)?;
+    for alert in alerts {
+        writer.serialize(alert).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// Appends the alerts to a log file as one JSON object per line, so they can be picked up by
+/// whatever is tailing it
+pub fn log_alerts(file_path: &Path, alerts: &[Alert]) -> SourceResult<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+    let mut writer = BufWriter::new(file);
+
+    for alert in alerts {
+        serde_json::to_writer(&mut writer, alert)
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+        writeln!(writer).map_err(|err| SourceError::Io(err.to_string()))?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alerts::{Alert, AlertKind, AlertRules};
+    use crate::mapper::Account;
+    use crate::state::snapshot_balances;
+    use std::collections::HashMap;
+
+    // Tests that each rule raises an alert for the accounts that break it, and only those
+    #[test]
+    fn test_evaluate_alert_rules() {
+        let mut account_map = HashMap::from([
+            (1, Account::with_balances(100.0, 0.0)),
+            (2, Account::with_balances(100.0, 0.0)),
+        ]);
+        let before = snapshot_balances(&account_map);
+
+        account_map.get_mut(&1).unwrap().deposit(60.0, 1);
+        let account = account_map.get_mut(&2).unwrap();
+        account.deposit(10.0, 2);
+        account.dispute(2);
+        account_map.insert(3, Account::with_balances(5.0, 0.0));
+
+        let rules = AlertRules {
+            available_below: Some(10.0),
+            held_above: Some(5.0),
+            total_change_pct: Some(50.0),
+        };
+
+        assert_eq!(
+            rules.evaluate(&before, &account_map),
+            vec![
+                Alert {
+                    client: 1,
+                    kind: AlertKind::TotalChange,
+                    value: 60.0,
+                    threshold: 50.0,
+                },
+                Alert {
+                    client: 2,
+                    kind: AlertKind::HeldAbove,
+                    value: 10.0,
+                    threshold: 5.0,
+                },
+                Alert {
+                    client: 3,
+                    kind: AlertKind::AvailableBelow,
+                    value: 5.0,
+                    threshold: 10.0,
+                },
+            ]
+        );
+        assert!(AlertRules::default()
+            .evaluate(&before, &account_map)
+            .is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::clock::Clock;
+use crate::error::{CliError, EngineResult, SourceError, SourceResult};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// An operator's note about a transaction, appended to the journal so investigation context lives
+/// alongside the financial history. Notes never change any balances.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Annotation {
+    /// The unique ID of the transaction the note is about
+    pub tx: u32,
+
+    /// The note itself
+    pub note: String,
+
+    /// Who wrote the note
+    pub operator: String,
+
+    /// When the note was written, in milliseconds since the unix epoch
+    pub recorded_at_ms: u64,
+
+    /// The hex encoded HMAC-SHA256 of the other fields, using the operators' signing key
+    pub signature: String,
+}
+
+/// What the annotate subcommand was asked to write
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AnnotationSettings {
+    /// The transaction the note is about
+    pub tx: Option<u32>,
+
+    /// The note to write
+    pub note: Option<String>,
+
+    /// Who is writing the note, the current user when one isn't provided
+    pub operator: Option<String>,
+
+    /// A file containing the key notes are signed with
+    pub signing_key: Option<PathBuf>,
+}
+
+impl Annotation {
+    /// Creates a note about a transaction, timestamped by the clock and signed with the key
+    pub fn new(
+        tx: u32,
+        note: impl Into<String>,
+        operator: impl Into<String>,
+        key: &[u8],
+        clock: &dyn Clock,
+    ) -> Self {
+        let mut annotation = Annotation {
+            tx,
+            note: note.into(),
+            operator: operator.into(),
+            recorded_at_ms: clock.now_ms(),
+            signature: String::new(),
+        };
+        annotation.signature = annotation.sign(key);
+
+        annotation
+    }
+
+    /// Whether the note was signed with the key, and hasn't been altered since
+    pub fn verify(&self, key: &[u8]) -> bool {
+        self.sign(key) == self.signature
+    }
+
+    /// The signature of the note's fields, using the key
+    fn sign(&self, key: &[u8]) -> String {
+        // the fields are signed as JSON, so there's no ambiguity about where one ends
+        let message =
+            serde_json::to_vec(&(self.tx, &self.note, &self.operator, self.recorded_at_ms))
+                .expect("a tuple of strings and numbers can always be serialized");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(&message);
+
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Loads a signing key from a file, ignoring any whitespace around it
+pub fn load_signing_key(key_path: impl AsRef<Path>) -> SourceResult<Vec<u8>> {
+    let key = fs::read(key_path).map_err(|err| SourceError::Io(err.to_string()))?;
+    let key = key.trim_ascii();
+    if key.is_empty() {
+        return Err(SourceError::Io("the signing key is empty".to_string()));
+    }
+
+    Ok(key.to_vec())
+}
+
+/// Appends a signed note to the journal, as a line of JSON after the events already in it
+pub fn annotate(
+    journal_path: &Path,
+    settings: &AnnotationSettings,
+    clock: &dyn Clock,
+) -> EngineResult<()> {
+    let missing = |flag: &str| CliError::MissingFlag(flag.to_string());
+    let tx = settings.tx.ok_or_else(|| missing("--tx"))?;
+    let note = settings.note.as_deref().ok_or_else(|| missing("--note"))?;
+    let key_path = settings
+        .signing_key
+        .as_deref()
+        .ok_or_else(|| missing("--signing-key"))?;
+    let operator = settings
+        .operator
+        .clone()
+        .or_else(|| env::var("USER").ok())
+        .ok_or_else(|| missing("--operator"))?;
+
+    let annotation = Annotation::new(tx, note, operator, &load_signing_key(key_path)?, clock);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+    let mut line =
+        serde_json::to_vec(&annotation).map_err(|err| SourceError::Io(err.to_string()))?;
+    line.push(b'\n');
+    file.write_all(&line)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::annotate::{annotate, Annotation, AnnotationSettings};
+    use crate::clock::{FixedClock, SystemClock};
+    use crate::config::EngineConfig;
+    use crate::emit::{emit_events, JsonlSink};
+    use crate::engine::Engine;
+    use crate::error::{CliError, EngineError};
+    use crate::journal::{parse_entry, Journal, JournalEntry};
+    use crate::mapper::TransactionType;
+    use crate::test_helpers::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Error;
+
+    // Tests that a note only verifies with the key it was signed with, and only while unaltered
+    #[test]
+    fn test_verify_annotation() {
+        let clock = FixedClock::new(1_700_000_000_000);
+        let annotation = Annotation::new(
+            123,
+            "confirmed fraud, case #4512",
+            "alice",
+            b"secret",
+            &clock,
+        );
+        assert_eq!(annotation.recorded_at_ms, 1_700_000_000_000);
+        assert!(annotation.verify(b"secret"));
+        assert!(!annotation.verify(b"other"));
+
+        let altered = Annotation {
+            note: "false alarm".to_string(),
+            ..annotation
+        };
+        assert!(!altered.verify(b"secret"));
+    }
+
+    // Tests that notes are appended after the journal's events, and skipped when they're emitted
+    #[test]
+    fn test_annotate_journal() -> Result<(), Error> {
+        let (journal_path, dir, file) = create_temp_file("journal.log")?;
+        let key_path = dir.path().join("signing.key");
+        fs::write(&key_path, "secret\n")?;
+
+        let journal = Journal::open(&journal_path).unwrap();
+        let mut engine = Engine::new(HashMap::new(), EngineConfig::default(), journal);
+        engine
+            .process(&dummy_record(TransactionType::Deposit, Some(10.0)))
+            .unwrap();
+        engine.into_accounts().unwrap();
+
+        let mut settings = AnnotationSettings {
+            tx: Some(0),
+            operator: Some("alice".to_string()),
+            signing_key: Some(key_path),
+            ..Default::default()
+        };
+        assert_eq!(
+            annotate(journal_path.as_ref(), &settings, &SystemClock),
+            Err(EngineError::Cli(CliError::MissingFlag(
+                "--note".to_string()
+            )))
+        );
+
+        settings.note = Some("confirmed fraud, case #4512".to_string());
+        annotate(journal_path.as_ref(), &settings, &SystemClock).unwrap();
+
+        let journal = fs::read_to_string(&journal_path)?;
+        let entries: Vec<JournalEntry> = journal
+            .lines()
+            .map(|line| parse_entry(line).unwrap())
+            .collect();
+        assert!(matches!(entries[0], JournalEntry::Event(_)));
+        match &entries[1] {
+            JournalEntry::Annotation(annotation) => {
+                assert_eq!(annotation.tx, 0);
+                assert_eq!(annotation.operator, "alice");
+                assert!(annotation.verify(b"secret"));
+            }
+            entry => panic!("expected an annotation, found {:?}", entry),
+        }
+
+        let mut output = vec![];
+        let emitted = emit_events(&journal_path, &mut JsonlSink::new(&mut output)).unwrap();
+        assert_eq!(emitted, 1);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::clients::AccountFlags;
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::Account;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// The number of rounds of the Feistel network that client ids are passed through
+const ROUNDS: u8 = 4;
+
+/// Pseudonymizes client ids (and optionally notes) with a secret key, so outputs can be shared
+/// externally without exposing real customer identifiers. The same key always gives the same
+/// pseudonyms, so outputs from different runs can still be joined.
+#[derive(Clone)]
+pub struct Anonymizer {
+    /// The secret key the pseudonyms are derived from
+    key: Vec<u8>,
+
+    /// Whether notes are pseudonymized too, they're removed otherwise
+    notes: bool,
+}
+
+impl Anonymizer {
+    /// Creates an anonymizer that derives pseudonyms from the key
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Anonymizer {
+            key: key.into(),
+            notes: false,
+        }
+    }
+
+    /// Loads the key from a file, ignoring any whitespace around it
+    pub fn load(key_path: impl AsRef<Path>) -> SourceResult<Self> {
+        let key = fs::read(key_path).map_err(|err| SourceError::Io(err.to_string()))?;
+        let key = key.trim_ascii();
+        if key.is_empty() {
+            return Err(SourceError::Io(
+                "the anonymization key is empty".to_string(),
+            ));
+        }
+
+        Ok(Anonymizer::new(key))
+    }
+
+    /// Pseudonymizes notes rather than removing them
+    pub fn with_notes(mut self) -> Self {
+        self.notes = true;
+        self
+    }
+
+    /// The pseudonym of a client id. Ids are passed through a keyed Feistel network, so every id
+    /// has a different pseudonym that still fits in a client id.
+    pub fn client(&self, client_id: u16) -> u16 {
+        let [mut left, mut right] = client_id.to_be_bytes();
+        for round in 0..ROUNDS {
+            let mixed = left ^ self.digest(&[b'c', round, right])[0];
+            left = right;
+            right = mixed;
+        }
+
+        u16::from_be_bytes([left, right])
+    }
+
+    /// The pseudonym of a piece of text
+    pub fn text(&self, text: &str) -> String {
+        let digest = self.digest(&[b"t".as_slice(), text.as_bytes()].concat());
+        let hex: String = digest[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        format!("anon-{}", hex)
+    }
+
+    /// The accounts, keyed by the pseudonyms of their clients
+    pub fn accounts(&self, account_map: &HashMap<u16, Account>) -> HashMap<u16, Account> {
+        account_map
+            .iter()
+            .map(|(client_id, account)| (self.client(*client_id), account.clone()))
+            .collect()
+    }
+
+    /// The account flags, keyed by the pseudonyms of their clients. Notes are pseudonymized when
+    /// enabled, and removed otherwise.
+    pub fn flags(
+        &self,
+        account_flags: &BTreeMap<u16, AccountFlags>,
+    ) -> BTreeMap<u16, AccountFlags> {
+        account_flags
+            .iter()
+            .map(|(client_id, flags)| {
+                let note = flags
+                    .note
+                    .as_deref()
+                    .filter(|_| self.notes)
+                    .map(|note| self.text(note));
+                (
+                    self.client(*client_id),
+                    AccountFlags {
+                        note,
+                        ..flags.clone()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// The HMAC-SHA256 of the message, using the key
+    fn digest(&self, message: &[u8]) -> [u8; 32] {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(message);
+
+        mac.finalize().into_bytes().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::anonymize::Anonymizer;
+    use crate::clients::AccountFlags;
+    use std::collections::{BTreeMap, HashSet};
+
+    // Tests that every client id gets a different pseudonym, which only depends on the key
+    #[test]
+    fn test_client_pseudonyms() {
+        let anonymizer = Anonymizer::new("secret");
+
+        let pseudonyms: HashSet<u16> = (0..=u16::MAX).map(|id| anonymizer.client(id)).collect();
+        assert_eq!(pseudonyms.len(), u16::MAX as usize + 1);
+
+        assert_eq!(anonymizer.client(7), Anonymizer::new("secret").client(7));
+        assert_ne!(anonymizer.client(7), Anonymizer::new("other").client(7));
+    }
+
+    // Tests that notes are removed unless they're pseudonymized too
+    #[test]
+    fn test_flag_notes() {
+        let flags = BTreeMap::from([(
+            1,
+            AccountFlags {
+                vip: true,
+                note: Some("Alice".to_string()),
+                ..Default::default()
+            },
+        )]);
+
+        let anonymizer = Anonymizer::new("secret");
+        let pseudonym = anonymizer.client(1);
+        assert_eq!(anonymizer.flags(&flags)[&pseudonym].note, None);
+        assert!(anonymizer.flags(&flags)[&pseudonym].vip);
+
+        let anonymizer = anonymizer.with_notes();
+        let note = anonymizer.flags(&flags)[&pseudonym].note.clone().unwrap();
+        assert!(note.starts_with("anon-"));
+        assert_eq!(note, anonymizer.text("Alice"));
+    }
+}
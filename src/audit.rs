@@ -0,0 +1,267 @@
+use crate::admin::AdminOperation;
+use crate::clock::{Clock, SystemClock};
+use crate::error::{SourceError, SourceResult};
+use crate::journal::open_file;
+use crate::mapper::{serialize_with_precision, Account, Record, TransactionType};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The hash the first event in an audit log is chained to
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The balances of an account on one side of a mutation
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct AuditBalances {
+    /// The available funds in the account
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub available: f32,
+
+    /// The held funds in the account
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub held: f32,
+
+    /// The total funds in the account
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub total: f32,
+}
+
+impl AuditBalances {
+    /// The account's balances as they are now
+    pub fn of(account: &Account) -> Self {
+        AuditBalances {
+            available: account.available_funds,
+            held: account.held_funds,
+            total: account.total_funds,
+        }
+    }
+}
+
+/// A mutation of an account, by a record or an operator's operation, as it's written to the audit
+/// log
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AuditEvent {
+    /// The type of the record (e.g. deposit), or the operator's operation (e.g. force-resolve)
+    #[serde(rename = "type")]
+    pub mutation: &'static str,
+
+    /// The unique ID of the client whose account was changed
+    pub client: u16,
+
+    /// The unique ID of the transaction, when there is one
+    pub tx: Option<u32>,
+
+    /// The amount of the record, or the limit set by an operator, if it had one
+    pub amount: Option<f32>,
+
+    /// Who made the operation, when an operator changed the account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+
+    /// The balances before the account was changed
+    pub before: AuditBalances,
+
+    /// The balances once the account was changed
+    pub after: AuditBalances,
+
+    /// The lock state the account was left in, e.g. chargeback-lock
+    pub state: &'static str,
+
+    /// When the account was changed, in milliseconds since the unix epoch. It's stamped by the
+    /// audit log's clock when the event is recorded.
+    pub recorded_at_ms: u64,
+
+    /// The hex encoded SHA-256 of the line of the event before it in the audit log, or the genesis
+    /// hash for the first. It's set by the audit log when the event is recorded.
+    pub prev_hash: String,
+}
+
+impl AuditEvent {
+    /// The event for a record that was applied to the account, from its balances beforehand
+    pub fn applied(record: &Record, before: AuditBalances, account: &Account) -> Self {
+        AuditEvent {
+            mutation: record.transaction_type.name(),
+            client: record.client_id,
+            tx: Some(record.transaction_id),
+            amount: record.amount,
+            operator: None,
+            before,
+            after: AuditBalances::of(account),
+            state: account.lock_state.name(),
+            recorded_at_ms: 0,
+            prev_hash: String::new(),
+        }
+    }
+
+    /// The event for an operator's operation that was applied to the account, from its balances
+    /// beforehand
+    pub fn operated(operation: &AdminOperation, before: AuditBalances, account: &Account) -> Self {
+        AuditEvent {
+            mutation: operation.op.name(),
+            client: operation.client,
+            tx: operation.tx,
+            amount: operation.amount,
+            operator: Some(operation.operator.clone()),
+            before,
+            after: AuditBalances::of(account),
+            state: account.lock_state.name(),
+            recorded_at_ms: 0,
+            prev_hash: String::new(),
+        }
+    }
+
+    /// The event for disputed funds the escheatment sweep moved, out of the client's account
+    /// (along with the transaction that held them) or into the holding account
+    pub fn escheated(
+        client: u16,
+        tx: Option<u32>,
+        amount: f32,
+        before: AuditBalances,
+        account: &Account,
+    ) -> Self {
+        AuditEvent {
+            mutation: TransactionType::Escheated.name(),
+            client,
+            tx,
+            amount: Some(amount),
+            operator: None,
+            before,
+            after: AuditBalances::of(account),
+            state: account.lock_state.name(),
+            recorded_at_ms: 0,
+            prev_hash: String::new(),
+        }
+    }
+}
+
+/// Appends an event to a file for every mutation of an account as it's applied, one JSON object
+/// per line. Unlike the journal, each event has the balances from before the mutation too, so the
+/// trail stands on its own. Events are hash chained, each one including the SHA-256 of the line
+/// before it, so an event that's modified or dropped breaks the chain.
+pub struct AuditLog {
+    /// Where events are written
+    output: BufWriter<File>,
+
+    /// Stamps each event with when it was recorded
+    clock: Arc<dyn Clock>,
+
+    /// The hash of the last line in the audit log, which the next event is chained to
+    head: String,
+}
+
+impl AuditLog {
+    /// Opens an audit log, events are appended to any that were written by previous runs and
+    /// chained to the last of them
+    pub fn open(file_path: &Path) -> SourceResult<Self> {
+        Ok(AuditLog {
+            head: chain_head(file_path)?,
+            output: open_file(file_path)?,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Stamps events using the clock, rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Stamps an event with the current time, chains it to the event before it and writes it to
+    /// the audit log
+    pub fn record(&mut self, mut event: AuditEvent) -> SourceResult<()> {
+        event.recorded_at_ms = self.clock.now_ms();
+        event.prev_hash = std::mem::take(&mut self.head);
+
+        let line = serde_json::to_string(&event).map_err(|err| SourceError::Io(err.to_string()))?;
+        self.head = hash_line(&line);
+        writeln!(self.output, "{}", line).map_err(|err| SourceError::Io(err.to_string()))
+    }
+
+    /// The hash of the last event in the audit log, which proves the whole chain up to it
+    pub fn head(&self) -> &str {
+        &self.head
+    }
+
+    /// Flushes the audit log once every record has been applied
+    pub fn finish(mut self) -> SourceResult<()> {
+        self.output
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))
+    }
+}
+
+/// The hex encoded SHA-256 of a line of the audit log, without its line ending
+pub fn hash_line(line: &str) -> String {
+    format!("{:x}", Sha256::digest(line.as_bytes()))
+}
+
+/// The hash of the last line of an existing audit log, or the genesis hash when there isn't one
+fn chain_head(file_path: &Path) -> SourceResult<String> {
+    let file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(GENESIS_HASH.to_string()),
+        Err(err) => return Err(SourceError::Io(format!("{}: {}", file_path.display(), err))),
+    };
+
+    let mut head = GENESIS_HASH.to_string();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|err| SourceError::Io(err.to_string()))?;
+        if !line.trim().is_empty() {
+            head = hash_line(&line);
+        }
+    }
+
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::{hash_line, AuditBalances, AuditEvent, AuditLog, GENESIS_HASH};
+    use crate::mapper::{Account, Record};
+    use std::fs;
+    use tempfile::tempdir;
+
+    // Tests that each event is chained to the line before it, including the last line written by
+    // an earlier run, and that the head is the hash of the last line
+    #[test]
+    fn test_audit_chain() {
+        let dir = tempdir().unwrap();
+        let audit_path = dir.path().join("audit.jsonl");
+
+        let mut account = Account::default();
+        for (tx, amount) in [(1, 10.0), (2, 5.0), (3, 1.0)] {
+            // every run opens the audit log again, carrying on from its last line
+            let mut audit = AuditLog::open(&audit_path).unwrap();
+            let before = AuditBalances::of(&account);
+            account.deposit(amount, tx);
+            let deposit = Record::deposit(1, tx, amount);
+            audit
+                .record(AuditEvent::applied(&deposit, before, &account))
+                .unwrap();
+
+            let head = audit.head().to_string();
+            audit.finish().unwrap();
+            let text = fs::read_to_string(&audit_path).unwrap();
+            assert_eq!(head, hash_line(text.lines().last().unwrap()));
+        }
+
+        let text = fs::read_to_string(&audit_path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let prev_hash = |line: &str| {
+            let event: serde_json::Value = serde_json::from_str(line).unwrap();
+            event["prev_hash"].as_str().unwrap().to_string()
+        };
+        assert_eq!(lines.len(), 3);
+        assert_eq!(prev_hash(lines[0]), GENESIS_HASH);
+        assert_eq!(prev_hash(lines[1]), hash_line(lines[0]));
+        assert_eq!(prev_hash(lines[2]), hash_line(lines[1]));
+
+        // a modified event no longer matches the hash the next one was chained to
+        let tampered = lines[0].replace("\"amount\":10.0", "\"amount\":100.0");
+        assert_ne!(tampered, lines[0]);
+        assert_ne!(prev_hash(lines[1]), hash_line(&tampered));
+    }
+}
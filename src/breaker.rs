@@ -0,0 +1,193 @@
+use crate::mapper::{Record, TransactionType};
+use std::path::PathBuf;
+
+/// The number of records that have to be seen before the share of rejected records is checked
+/// while a run is in progress. It's always checked once the run has finished.
+pub const BREAKER_MIN_SAMPLE: u64 = 100;
+
+/// Safety limits on a single run. Exceeding any of them trips the circuit breaker, which halts
+/// the run before an obviously corrupt or wrongly scoped input file can reach the saved state.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BreakerLimits {
+    /// The most records a run can apply
+    pub max_rows: Option<u64>,
+
+    /// The most money a run can move, the sum of the absolute amounts of its deposits,
+    /// withdrawals and adjustments
+    pub max_total_movement: Option<f64>,
+
+    /// The largest share of records that can be rejected, as a percentage
+    pub max_rejects_pct: Option<f64>,
+
+    /// Where the accounts are saved when the breaker trips, as they were before the record that
+    /// tripped it
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl BreakerLimits {
+    /// Whether any limits were set
+    pub fn is_set(&self) -> bool {
+        self.max_rows.is_some()
+            || self.max_total_movement.is_some()
+            || self.max_rejects_pct.is_some()
+    }
+}
+
+/// Counts what a run has done, tripping once it exceeds one of its limits
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    /// The limits of the run
+    limits: BreakerLimits,
+
+    /// The number of records that have been seen
+    rows: u64,
+
+    /// The number of records that were rejected
+    rejects: u64,
+
+    /// The sum of the absolute amounts of the records that moved money
+    movement: f64,
+}
+
+impl CircuitBreaker {
+    /// Creates a breaker that trips once the limits are exceeded
+    pub fn new(limits: BreakerLimits) -> Self {
+        CircuitBreaker {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Where the accounts are saved when the breaker trips
+    pub fn checkpoint(&self) -> Option<&PathBuf> {
+        self.limits.checkpoint.as_ref()
+    }
+
+    /// Checks a record before it's applied, explaining why the breaker trips when applying it
+    /// would exceed a limit
+    pub fn admit(&mut self, record: &Record) -> Result<(), String> {
+        if let Some(max_rows) = self.limits.max_rows {
+            if self.rows >= max_rows {
+                return Err(format!("the run has more than {} rows", max_rows));
+            }
+        }
+
+        if let (Some(max_movement), Some(amount)) =
+            (self.limits.max_total_movement, movement(record))
+        {
+            if self.movement + amount > max_movement {
+                return Err(format!(
+                    "tx {} would move the run's total past {}",
+                    record.transaction_id, max_movement
+                ));
+            }
+        }
+
+        self.rows += 1;
+
+        Ok(())
+    }
+
+    /// Counts the outcome of a record, explaining why the breaker trips when too many records
+    /// have been rejected
+    pub fn count(&mut self, record: &Record, rejected: bool) -> Result<(), String> {
+        if rejected {
+            self.rejects += 1;
+        } else if let Some(amount) = movement(record) {
+            self.movement += amount;
+        }
+
+        if self.rows >= BREAKER_MIN_SAMPLE {
+            self.check_rejects()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks the share of rejected records once the run has finished, however many it had
+    pub fn check_rejects(&self) -> Result<(), String> {
+        let Some(max_rejects_pct) = self.limits.max_rejects_pct else {
+            return Ok(());
+        };
+
+        let rejects_pct = self.rejects as f64 * 100.0 / self.rows.max(1) as f64;
+        if rejects_pct > max_rejects_pct {
+            return Err(format!(
+                "{} of {} rows were rejected, more than {}%",
+                self.rejects, self.rows, max_rejects_pct
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The money a record moves, if it's a deposit, withdrawal, adjustment or transfer
+fn movement(record: &Record) -> Option<f64> {
+    match record.transaction_type {
+        TransactionType::Deposit
+        | TransactionType::Withdrawal
+        | TransactionType::Adjustment
+        | TransactionType::Transfer => record.amount.map(|amount| (amount as f64).abs()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::breaker::{BreakerLimits, CircuitBreaker, BREAKER_MIN_SAMPLE};
+    use crate::mapper::Record;
+
+    // Tests that each limit trips the breaker once it's exceeded
+    #[test]
+    fn test_circuit_breaker() {
+        let mut breaker = CircuitBreaker::new(BreakerLimits {
+            max_rows: Some(3),
+            ..Default::default()
+        });
+        for tx in 1..=3 {
+            breaker.admit(&Record::dispute(1, tx)).unwrap();
+        }
+        assert!(breaker.admit(&Record::dispute(1, 4)).is_err());
+
+        let mut breaker = CircuitBreaker::new(BreakerLimits {
+            max_total_movement: Some(100.0),
+            ..Default::default()
+        });
+        let withdrawal = Record::withdrawal(1, 2, 40.0);
+        breaker.admit(&Record::deposit(1, 1, 60.0)).unwrap();
+        breaker.count(&Record::deposit(1, 1, 60.0), false).unwrap();
+        breaker.admit(&withdrawal).unwrap();
+        // rejected records don't move any money
+        breaker.count(&withdrawal, true).unwrap();
+        breaker.admit(&Record::withdrawal(1, 3, 40.0)).unwrap();
+        breaker
+            .count(&Record::withdrawal(1, 3, 40.0), false)
+            .unwrap();
+        assert_eq!(
+            breaker.admit(&Record::deposit(1, 4, 0.5)),
+            Err("tx 4 would move the run's total past 100".to_string())
+        );
+
+        let mut breaker = CircuitBreaker::new(BreakerLimits {
+            max_rejects_pct: Some(10.0),
+            ..Default::default()
+        });
+        for tx in 0..BREAKER_MIN_SAMPLE as u32 {
+            let record = Record::dispute(1, tx);
+            breaker.admit(&record).unwrap();
+            // the share is only checked once there's a large enough sample
+            let rejected = tx < 11;
+            let result = breaker.count(&record, rejected);
+            assert_eq!(result.is_err(), tx == BREAKER_MIN_SAMPLE as u32 - 1);
+        }
+
+        let mut breaker = CircuitBreaker::new(BreakerLimits {
+            max_rejects_pct: Some(50.0),
+            ..Default::default()
+        });
+        breaker.admit(&Record::dispute(1, 1)).unwrap();
+        breaker.count(&Record::dispute(1, 1), true).unwrap();
+        assert!(breaker.check_rejects().is_err());
+    }
+}
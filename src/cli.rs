@@ -0,0 +1,2357 @@
+use crate::admin::AdminPhase;
+use crate::alerts::AlertRules;
+use crate::annotate::AnnotationSettings;
+use crate::breaker::BreakerLimits;
+use crate::clock::{Clock, FixedClock, SystemClock};
+use crate::config::{
+    DisputeAmountPolicy, EdgeCase, EngineConfig, EnginePolicy, FrozenAccountPolicy, OrderingPolicy,
+    PolicyAction, WithdrawalPolicy,
+};
+use crate::currency::parse_minor_units_override;
+use crate::emit::{KafkaSettings, SinkKind};
+use crate::error::{CliError, CliResult};
+use crate::escheat::EscheatmentSettings;
+use crate::fields::{BooleanStyle, FieldFormatter, FieldStyle};
+use crate::format::{
+    AutoDetector, ExtensionDetector, ForcedFormat, FormatDetector, InputFormat, SniffingDetector,
+};
+use crate::generator::GeneratorConfig;
+use crate::graph::GraphFormat;
+use crate::index::{FindQuery, TransactionState};
+use crate::journal::BatchConfig;
+use crate::lock::LockMode;
+use crate::mapper::{DecimalSeparator, OutputFormat, OutputVersion};
+use crate::metadata::ENGINE_VERSION;
+use crate::parser::{ColumnMapping, CsvBackend, CsvOptions, Delimiter};
+use crate::partition::RangePartitioner;
+use crate::redact::Redaction;
+use crate::reorder::BufferWindow;
+use crate::screening::parse_countries;
+use crate::state::StateFormat;
+use crate::storage::StoreLocation;
+use crate::timestamp::parse_duration;
+use crate::trends::TrendConfig;
+use clap::builder::{IntoResettable, OsStringValueParser, TypedValueParser, ValueParser};
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Id};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The subcommands that can be run, the first argument selects one
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Applies a file of transactions to client accounts and outputs the result
+    #[default]
+    Process,
+
+    /// Checks that every transaction in a file can be applied, without outputting or saving
+    /// anything. The run fails with the code of the first record that's rejected
+    /// (plutus validate transactions.csv)
+    Validate,
+
+    /// Applies a file of transactions and writes the headline figures of the run as JSON, rather
+    /// than the accounts (plutus report transactions.csv summary.json). The run's postings are
+    /// reconciled with the accounts instead with plutus report reconcile transactions.csv
+    Report,
+
+    /// Writes a saved state file in a human readable format (plutus export-state state.bin)
+    ExportState,
+
+    /// Converts a human readable state file into the binary format used by --load-state
+    /// (plutus import-state state.json state.bin)
+    ImportState,
+
+    /// Re-emits the events in a journal to a sink, so downstream systems can rebuild from them
+    /// (plutus emit-events journal.log --sink jsonl)
+    EmitEvents,
+
+    /// Exports a graph linking the transactions in a journal to the rows that referred to them
+    /// (plutus graph journal.log graph.dot --format dot)
+    Graph,
+
+    /// Reports data quality statistics for a file of transactions without applying them
+    /// (plutus profile transactions.csv)
+    Profile,
+
+    /// Checks every row of a file of transactions for problems without applying them, writing
+    /// them as JSON. The run fails when any are found (plutus lint transactions.csv lint.json)
+    Lint,
+
+    /// Serves the HTTP API, answering questions about the accounts from the journal
+    /// (plutus serve journal.log --addr 127.0.0.1:8080)
+    Serve,
+
+    /// Serves balance and transaction queries over a saved state, without accepting changes
+    /// (plutus serve-readonly state.bin --addr 127.0.0.1:8081)
+    ServeReadonly,
+
+    /// Generates a file of pseudo-random transactions from a seed
+    /// (plutus generate transactions.csv --seed 42)
+    Generate,
+
+    /// Compares the run metadata in a directory over time, highlighting runs that deviate from the
+    /// trailing average (plutus trends runs/ --chart)
+    Trends,
+
+    /// Searches the transaction index saved alongside the state
+    /// (plutus find state.idx --state disputed --min-amount 1000)
+    Find,
+
+    /// Appends a signed, timestamped operator note about a transaction to the journal
+    /// (plutus annotate journal.log --tx 123 --note "confirmed fraud, case #4512")
+    Annotate,
+
+    /// Upgrades a state file written by an older engine to the current version of the format
+    /// (plutus migrate-state old.bin new.bin)
+    MigrateState,
+
+    /// Runs the ordered stages of a TOML runbook in one invocation, only writing its outputs once
+    /// every stage has succeeded (plutus run nightly.toml)
+    Run,
+}
+
+impl Command {
+    /// Every subcommand, in the order they're listed in the help
+    const ALL: [Command; 17] = [
+        Command::Process,
+        Command::Validate,
+        Command::Report,
+        Command::ExportState,
+        Command::ImportState,
+        Command::EmitEvents,
+        Command::Graph,
+        Command::Profile,
+        Command::Lint,
+        Command::Serve,
+        Command::ServeReadonly,
+        Command::Generate,
+        Command::Trends,
+        Command::Find,
+        Command::Annotate,
+        Command::MigrateState,
+        Command::Run,
+    ];
+
+    /// The name the subcommand is run with
+    fn name(self) -> &'static str {
+        match self {
+            Command::Process => "process",
+            Command::Validate => "validate",
+            Command::Report => "report",
+            Command::ExportState => "export-state",
+            Command::ImportState => "import-state",
+            Command::EmitEvents => "emit-events",
+            Command::Graph => "graph",
+            Command::Profile => "profile",
+            Command::Lint => "lint",
+            Command::Serve => "serve",
+            Command::ServeReadonly => "serve-readonly",
+            Command::Generate => "generate",
+            Command::Trends => "trends",
+            Command::Find => "find",
+            Command::Annotate => "annotate",
+            Command::MigrateState => "migrate-state",
+            Command::Run => "run",
+        }
+    }
+
+    /// A one line description of the subcommand, shown in the help
+    fn about(self) -> &'static str {
+        match self {
+            Command::Process => {
+                "Applies a file of transactions and outputs the accounts (the default)"
+            }
+            Command::Validate => "Checks that every transaction in a file can be applied",
+            Command::Report => "Applies a file of transactions and outputs a summary of the run",
+            Command::ExportState => "Writes a saved state file in a human readable format",
+            Command::ImportState => "Converts a human readable state file into the binary format",
+            Command::EmitEvents => "Re-emits the events in a journal to a sink",
+            Command::Graph => "Exports a graph linking transactions to their disputes",
+            Command::Profile => "Reports data quality statistics for a file of transactions",
+            Command::Lint => "Reports the problems in a file of transactions without applying it",
+            Command::Serve => "Serves the HTTP API over a journal",
+            Command::ServeReadonly => "Serves balance and transaction queries over a saved state",
+            Command::Generate => "Generates a file of pseudo-random transactions",
+            Command::Trends => "Compares the run metadata in a directory over time",
+            Command::Find => "Searches a transaction index",
+            Command::Annotate => "Appends a signed operator note to a journal",
+            Command::MigrateState => "Upgrades a state file to the current version of the format",
+            Command::Run => "Runs the stages of a runbook, writing its outputs if they all succeed",
+        }
+    }
+
+    /// Finds the subcommand with the given name
+    fn from_name(name: &str) -> Option<Command> {
+        Command::ALL
+            .into_iter()
+            .find(|command| command.name() == name)
+    }
+
+    /// The definition of the subcommand, along with the arguments it takes
+    fn definition(self) -> clap::Command {
+        let subcommand = clap::Command::new(self.name())
+            .about(self.about())
+            .args_override_self(true)
+            .args(self.args());
+
+        match self {
+            Command::Report => subcommand.subcommand(
+                clap::Command::new("reconcile")
+                    .about("Reconciles the money the run moved with the balances of the accounts")
+                    .args_override_self(true)
+                    .args(self.args()),
+            ),
+            _ => subcommand,
+        }
+    }
+
+    /// The arguments the subcommand takes. Only processing reads several paths, the rest take an
+    /// optional second path to write to, and each only takes the flags it uses.
+    fn args(self) -> Vec<Arg> {
+        let paths = match self {
+            Command::Process => vec![files_arg()],
+            Command::Run => vec![file_arg()],
+            _ => vec![file_arg(), output_arg()],
+        };
+
+        let flags = match self {
+            Command::Process => [
+                process_args(),
+                run_args(),
+                input_args(),
+                engine_args(),
+                account_args(),
+            ]
+            .concat(),
+            Command::Validate | Command::Report => {
+                [run_args(), input_args(), engine_args(), account_args()].concat()
+            }
+            Command::Run => [report_args(), input_args(), engine_args(), account_args()].concat(),
+            Command::Profile | Command::Lint => input_args(),
+            Command::ExportState | Command::ImportState => {
+                let help = "The format the state is exported to or imported from (json or bin)";
+                vec![value("format", "FORMAT", help, parsed::<StateFormat>()).id("state-format")]
+            }
+            Command::Graph => {
+                let help = "The format the graph is exported in (dot or csv)";
+                vec![value("format", "FORMAT", help, parsed::<GraphFormat>()).id("graph-format")]
+            }
+            Command::MigrateState => {
+                let help = "The version a state file being migrated was written with";
+                vec![value("from-version", "VERSION", help, parsed::<u16>())]
+            }
+            Command::EmitEvents => emit_args(),
+            Command::Serve => [server_args(), state_args(), engine_args()].concat(),
+            Command::ServeReadonly => address_args(),
+            Command::Generate => generator_args(),
+            Command::Trends => trend_args(),
+            Command::Find => find_args(),
+            Command::Annotate => annotation_args(),
+        };
+
+        [paths, flags, logging_args()].concat()
+    }
+}
+
+/// The options provided on the command line
+#[derive(Debug, Default, PartialEq)]
+pub struct CliArgs {
+    /// The subcommand to run, processing transactions when one isn't provided
+    pub command: Command,
+
+    /// The path of the file to read data from
+    pub file_path: PathBuf,
+
+    /// The paths of the files to read after the first, merged with it into one stream of records
+    pub more_file_paths: Vec<PathBuf>,
+
+    /// The path of the file to write data to, std out is written to when one isn't provided
+    pub output_path: Option<PathBuf>,
+
+    /// Whether the run fails when any record is rejected, rather than only on a fatal error
+    pub strict: bool,
+
+    /// Whether only a fatal error is reported, rather than every rejected record
+    pub quiet: bool,
+
+    /// The fields that are redacted from the report of the run and the rejections file
+    pub redaction: Redaction,
+
+    /// The format that state files are exported to or imported from
+    pub state_format: StateFormat,
+
+    /// The format the linkage graph is exported in
+    pub graph_format: GraphFormat,
+
+    /// The version of the format a state file being migrated was written with, detected from the
+    /// file when one isn't provided
+    pub from_version: Option<u16>,
+
+    /// The version of the account output, version 2 adds why each account is locked
+    pub output_version: OutputVersion,
+
+    /// The format the account output is written in
+    pub output_format: OutputFormat,
+
+    /// How amounts and booleans are rendered in the account output, when it's been configured
+    pub field_style: Option<FieldStyle>,
+
+    /// How csv and tsv files are read, e.g. which parser they're read with
+    pub csv: CsvOptions,
+
+    /// The TOML file the header row's columns are renamed by, see ColumnMapping
+    pub column_mapping_file: Option<PathBuf>,
+
+    /// Columns of the header row that are renamed (header, column), overriding the mapping file
+    pub column_renames: Vec<(String, String)>,
+
+    /// Skips format detection, the file is read in this format regardless of its name or contents
+    pub force_format: Option<InputFormat>,
+
+    /// Whether to inspect the contents of a file when its extension isn't recognised
+    pub sniff_format: bool,
+
+    /// Whether to choose the format of a file by inspecting it, rather than by its extension
+    pub auto_format: bool,
+
+    /// A file containing account state saved by a previous run, that transactions are applied to
+    pub load_state: Option<PathBuf>,
+
+    /// A file to save the account state to once every transaction has been applied
+    pub save_state: Option<PathBuf>,
+
+    /// Whether to process the transactions without saving state. When state has been loaded, only
+    /// the changes to each account are output.
+    pub simulate: bool,
+
+    /// What to do when another run holds the lock on a state file
+    pub lock_mode: LockMode,
+
+    /// A file to save a search index of the transactions to, alongside the state
+    pub save_index: Option<PathBuf>,
+
+    /// The conditions a transaction must meet to be found
+    pub find: FindQuery,
+
+    /// The operator note to append to a journal
+    pub annotation: AnnotationSettings,
+
+    /// A file to append an event to for every transaction that's applied
+    pub journal: Option<PathBuf>,
+
+    /// The number of records an account can go untouched before it's moved to cold storage
+    pub demote_after: Option<u64>,
+
+    /// The most transactions that are kept in their accounts, the rest are spilled to disk
+    pub max_resident_transactions: Option<usize>,
+
+    /// The directory spilled transactions are written to, the system's temporary directory when
+    /// one isn't provided
+    pub spill_dir: Option<PathBuf>,
+
+    /// Where the accounts in cold storage are kept
+    pub store: StoreLocation,
+
+    /// Whether withdrawals rejected for insufficient funds are retried after later deposits
+    pub retry_withdrawals: bool,
+
+    /// The number of records a rejected withdrawal can be retried for, it can be retried for the
+    /// rest of the run when there isn't one. Setting it enables retries.
+    pub retry_window: Option<u64>,
+
+    /// How long dispute, resolve, chargeback and void rows wait for the transaction they
+    /// reference, when they arrive before it. They're applied as they arrive when there isn't one.
+    pub dispute_buffer: Option<BufferWindow>,
+
+    /// How the journal is batched, when it's written by a background thread
+    pub journal_batch: Option<BatchConfig>,
+
+    /// Thresholds that raise an alert when an account crosses them
+    pub alert_rules: AlertRules,
+
+    /// A file to write the alerts report to
+    pub alerts_report: Option<PathBuf>,
+
+    /// A file to append an event to for every alert that's raised
+    pub alert_log: Option<PathBuf>,
+
+    /// The sink that journaled events are re-emitted to
+    pub sink: SinkKind,
+
+    /// The address the server listens on, the default is used when one isn't provided
+    pub addr: Option<String>,
+
+    /// A directory to process each csv dropped into as it lands, rather than reading a file
+    pub watch: Option<PathBuf>,
+
+    /// The directory files are moved to once they've been processed from the watched directory
+    pub archive: Option<PathBuf>,
+
+    /// The address the server accepts transactions on over TCP, rather than serving the HTTP API
+    pub tcp: Option<String>,
+
+    /// The number of shards the accounts are split into when serving over TCP
+    pub shards: Option<usize>,
+
+    /// The ranges of client ids the shards hold, rather than hashing clients onto the shards
+    pub shard_ranges: Option<RangePartitioner>,
+
+    /// A unix socket the server listens on for a new process to hand its state over to
+    pub handover_socket: Option<PathBuf>,
+
+    /// The unix socket of a running server to take the state over from, rather than loading it
+    pub take_over: Option<PathBuf>,
+
+    /// Settings for the trends report
+    pub trends: TrendConfig,
+
+    /// Settings for generating a file of transactions
+    pub generator: GeneratorConfig,
+
+    /// Where to connect to when events are emitted to Kafka
+    pub kafka: KafkaSettings,
+
+    /// A file to write the metadata of the run to, describing what produced its outputs
+    pub run_metadata: Option<PathBuf>,
+
+    /// A csv of the clients that have been onboarded, transactions for any other client are rejected
+    pub onboarded_clients: Option<PathBuf>,
+
+    /// A file to write the funds reversed by chargebacks to, posted against the loss account
+    pub loss_report: Option<PathBuf>,
+
+    /// The ledger account chargeback losses are posted to, the default is used when one isn't
+    /// provided
+    pub loss_account: Option<String>,
+
+    /// A file containing the key that client ids are pseudonymized with before outputs and
+    /// reports are written
+    pub anonymize: Option<PathBuf>,
+
+    /// Whether notes are pseudonymized along with client ids, rather than removed
+    pub anonymize_notes: bool,
+
+    /// An admin sidecar csv of flags (vip, under_review, do_not_lock) and notes for accounts
+    pub admin_flags: Option<PathBuf>,
+
+    /// A csv of operations operators have made on accounts (unlock, set-limit, close and
+    /// force-resolve)
+    pub admin_ops: Option<PathBuf>,
+
+    /// Whether the admin operations are applied before or after the transactions
+    pub admin_ops_phase: AdminPhase,
+
+    /// A file to write when each client was first seen and their transaction count to
+    pub clients_report: Option<PathBuf>,
+
+    /// A clients metadata csv with a country column, used to screen clients in restricted countries
+    pub client_metadata: Option<PathBuf>,
+
+    /// A csv of the clients whose transactions are always held for compliance review
+    pub denylist: Option<PathBuf>,
+
+    /// A file to write the transactions that were held for compliance review to
+    pub compliance_report: Option<PathBuf>,
+
+    /// Settings for sweeping long-held disputed funds to the escheatment holding account
+    pub escheatment: EscheatmentSettings,
+
+    /// Safety limits that halt the run when they're exceeded
+    pub breaker: BreakerLimits,
+
+    /// A directory to write each business day's closing balances and the day summaries to,
+    /// splitting the run into days by the records' timestamps
+    pub daily_cutover: Option<PathBuf>,
+
+    /// The hour (UTC, 0-23) that business days end at, midnight when one isn't provided
+    pub cutover_hour: u8,
+
+    /// A directory to export the run to as Parquet fact and dimension tables, for loading into an
+    /// OLAP warehouse
+    pub olap_export: Option<PathBuf>,
+
+    /// A file to write every rejected record to, with its line, code and error
+    pub rejections: Option<PathBuf>,
+
+    /// A JSON lines file to write the result of every input record to, whether it was accepted,
+    /// rejected or ignored, with the line it was read from
+    pub tx_results: Option<PathBuf>,
+
+    /// A JSON lines file to append an event to for every mutation of an account, with its
+    /// balances before and after it
+    pub audit_log: Option<PathBuf>,
+
+    /// Whether the aggregate figures of the run are reported along with the rejections
+    pub stats: bool,
+
+    /// A JSON file to write the aggregate figures of the run to, see RunStats
+    pub stats_out: Option<PathBuf>,
+
+    /// How much of the engine's tracing is logged to std err, once for every -v. RUST_LOG is used
+    /// when it's 0.
+    pub verbosity: u8,
+
+    /// The OTLP/HTTP endpoint the engine's spans are exported to, e.g. a Jaeger collector
+    pub otlp_endpoint: Option<String>,
+
+    /// Whether reporting reconciles the run's postings with the accounts, rather than summarizing
+    /// the run, see Reconciliation
+    pub reconcile: bool,
+
+    /// A file of the idempotency keys that have been applied, so re-sent records are only
+    /// applied once across runs
+    pub idempotency_keys: Option<PathBuf>,
+
+    /// The ISO 4217 code of the currency the amounts are in
+    pub currency: Option<String>,
+
+    /// The minor units of currencies that aren't in the ISO 4217 table, or that should differ
+    /// from it
+    pub minor_unit_overrides: BTreeMap<String, u8>,
+
+    /// The time (unix timestamp) the run treats as now, for reproducible runs. The system's clock
+    /// is used when one isn't provided.
+    pub now: Option<u64>,
+
+    /// The TOML file the engine policy is loaded from, when it isn't the default
+    pub policy_file: Option<PathBuf>,
+
+    /// The actions of individual edge cases, overriding the policy file
+    pub edge_cases: Vec<(EdgeCase, PolicyAction)>,
+
+    /// Settings that control how the engine applies transactions
+    pub config: EngineConfig,
+}
+
+impl CliArgs {
+    /// Parses the command line arguments, the first of which is the name of the program. Paths are
+    /// kept as they were provided, so they don't need to be valid UTF-8.
+    pub fn parse<T: Into<OsString> + Clone>(
+        args: impl IntoIterator<Item = T>,
+    ) -> CliResult<CliArgs> {
+        let matches = definition()
+            .try_get_matches_from(args)
+            .map_err(usage_error)?;
+
+        // the subcommand is optional, processing is the default. A subcommand is followed by the
+        // file it reads and the flags it takes, so nothing can come before it.
+        let (command, matches) = match matches.subcommand() {
+            Some((name, subcommand_matches)) => {
+                let provided =
+                    |id: &&str| matches.value_source(id) == Some(ValueSource::CommandLine);
+                match matches.ids().map(Id::as_str).find(provided) {
+                    Some("file") => return Err(CliError::UnexpectedArg(name.to_string())),
+                    Some(flag) => return Err(CliError::UnexpectedArg(format!("--{}", flag))),
+                    None => (
+                        Command::from_name(name).unwrap_or_default(),
+                        subcommand_matches,
+                    ),
+                }
+            }
+            None => (Command::Process, &matches),
+        };
+
+        // reconciling is a mode of reporting, e.g. plutus report reconcile transactions.csv
+        let (reconcile, matches) = match matches.subcommand() {
+            Some((name, _)) if matches.get_one::<OsString>("file").is_some() => {
+                return Err(CliError::UnexpectedArg(name.to_string()))
+            }
+            Some((_, reconcile_matches)) => (true, reconcile_matches),
+            None => (false, matches),
+        };
+
+        let mut cli_args = CliArgs::from_matches(&Values::new(command, matches));
+        cli_args.command = command;
+        cli_args.reconcile = reconcile;
+
+        // error when an argument for file path wasn't provided, which watching a directory
+        // doesn't need
+        let mut file_paths = matches
+            .get_many::<OsString>("file")
+            .into_iter()
+            .flatten()
+            .map(PathBuf::from);
+        match file_paths
+            .next()
+            .filter(|path| !path.as_os_str().is_empty())
+        {
+            Some(file_path) => cli_args.file_path = file_path,
+            None if cli_args.watch.is_some() => {}
+            None => return Err(CliError::MissingArg),
+        }
+        cli_args.more_file_paths = file_paths.collect();
+
+        // edge cases provided as flags override the policy file, whichever order they're in
+        if let Some(policy_path) = &cli_args.policy_file {
+            cli_args.config.policy = EnginePolicy::load(policy_path)?;
+        }
+        for (case, action) in cli_args.edge_cases.iter().copied() {
+            cli_args.config.policy.set(case, action);
+        }
+
+        // so are columns renamed as flags
+        if let Some(mapping_path) = &cli_args.column_mapping_file {
+            cli_args.csv.columns = ColumnMapping::load(mapping_path)?;
+        }
+        for (header, column) in &cli_args.column_renames {
+            let value = format!("{}={}", header, column);
+            let invalid = |_| CliError::InvalidValue("--column".to_string(), value);
+            cli_args
+                .csv
+                .columns
+                .rename(header, column)
+                .map_err(invalid)?;
+        }
+
+        // validating is a simulation that fails on the first rejected record
+        if cli_args.command == Command::Validate {
+            cli_args.simulate = true;
+            cli_args.strict = true;
+        }
+
+        Ok(cli_args)
+    }
+
+    /// The options a subcommand was run with. Flags the subcommand doesn't take are left as their
+    /// defaults.
+    fn from_matches(values: &Values) -> CliArgs {
+        // --format reads the file in a format, or detects it automatically
+        let format = values.get::<Option<InputFormat>>("format");
+
+        let mut cli_args = CliArgs {
+            output_path: values.get("output"),
+            strict: values.flag("strict"),
+            quiet: values.flag("quiet"),
+            redaction: values.get("redact").unwrap_or_default(),
+            state_format: values.get("state-format").unwrap_or_default(),
+            graph_format: values.get("graph-format").unwrap_or_default(),
+            from_version: values.get("from-version"),
+            output_version: values.get("output-version").unwrap_or_default(),
+            output_format: values.get("output-format").unwrap_or_default(),
+            column_mapping_file: values.get("columns"),
+            column_renames: values.all("column"),
+            force_format: values.get("force-format").or(format.flatten()),
+            sniff_format: values.flag("sniff-format"),
+            auto_format: format == Some(None),
+            load_state: values.get("load-state"),
+            save_state: values.get("save-state"),
+            simulate: values.flag("simulate"),
+            save_index: values.get("save-index"),
+            journal: values.get("journal"),
+            demote_after: values.get("demote-after"),
+            max_resident_transactions: values.get("max-resident-transactions"),
+            spill_dir: values.get("spill-dir"),
+            store: values.get("store").unwrap_or_default(),
+            retry_withdrawals: values.flag("retry-withdrawals"),
+            retry_window: values.get("retry-window"),
+            dispute_buffer: values.get("dispute-buffer"),
+            alerts_report: values.get("alerts-report"),
+            alert_log: values.get("alert-log"),
+            sink: values.get("sink").unwrap_or_default(),
+            addr: values.get("addr"),
+            watch: values.get("watch"),
+            archive: values.get("archive"),
+            tcp: values.get("tcp"),
+            shards: values.get("shards"),
+            shard_ranges: values.get("shard-ranges"),
+            handover_socket: values.get("handover-socket"),
+            take_over: values.get("take-over"),
+            run_metadata: values.get("run-metadata"),
+            onboarded_clients: values.get("onboarded-clients"),
+            loss_report: values.get("loss-report"),
+            loss_account: values.get("loss-account"),
+            anonymize: values.get("anonymize"),
+            anonymize_notes: values.flag("anonymize-notes"),
+            admin_flags: values.get("admin-flags"),
+            admin_ops: values.get("admin-ops"),
+            admin_ops_phase: values.get("admin-ops-phase").unwrap_or_default(),
+            clients_report: values.get("clients-report"),
+            client_metadata: values.get("client-metadata"),
+            denylist: values.get("denylist"),
+            compliance_report: values.get("compliance-report"),
+            daily_cutover: values.get("daily-cutover"),
+            cutover_hour: values.get("cutover-hour").unwrap_or_default(),
+            olap_export: values.get("olap-export"),
+            rejections: values.get("rejections"),
+            tx_results: values.get("tx-results"),
+            audit_log: values.get("audit-log"),
+            stats: values.flag("stats"),
+            stats_out: values.get("stats-out"),
+            verbosity: values.get("verbose").unwrap_or_default(),
+            otlp_endpoint: values.get("otlp-endpoint"),
+            idempotency_keys: values.get("idempotency-keys"),
+            currency: values.get("currency"),
+            minor_unit_overrides: values.all("minor-units").into_iter().collect(),
+            now: values.get("now"),
+            policy_file: values.get("policy"),
+            edge_cases: values.all("edge-case"),
+            ..Default::default()
+        };
+
+        // --wait and --no-wait override each other, as do --addr and --port
+        if values.flag("wait") {
+            cli_args.lock_mode = LockMode::Wait;
+        } else if values.flag("no-wait") {
+            cli_args.lock_mode = LockMode::NoWait;
+        }
+        if let Some(port) = values.get::<u16>("port") {
+            cli_args.addr = Some(format!("127.0.0.1:{}", port));
+        }
+
+        let styles = ["bool-format", "amount-decimals", "decimal-comma"];
+        if styles.iter().any(|id| values.provided(id)) {
+            cli_args.field_style = Some(FieldStyle {
+                booleans: values.get("bool-format").unwrap_or_default(),
+                decimals: values.get("amount-decimals"),
+                decimal_comma: values.flag("decimal-comma"),
+            });
+        }
+
+        let csv = &mut cli_args.csv;
+        csv.backend = values.get("csv-backend").unwrap_or_default();
+        csv.no_headers = values.flag("no-headers");
+        csv.delimiter = values.get("delimiter");
+        csv.lenient_amounts = values.get("lenient-amounts");
+
+        let batching = ["journal-batch-size", "journal-outbox-capacity"];
+        if batching.iter().any(|id| values.provided(id)) {
+            let mut batch = BatchConfig::default();
+            batch.batch_size = values.get("journal-batch-size").unwrap_or(batch.batch_size);
+            batch.outbox_capacity = values
+                .get("journal-outbox-capacity")
+                .unwrap_or(batch.outbox_capacity);
+            cli_args.journal_batch = Some(batch);
+        }
+
+        cli_args.find = FindQuery {
+            client: values.get("client"),
+            state: values.get("state"),
+            min_amount: values.get("min-amount"),
+            max_amount: values.get("max-amount"),
+        };
+        cli_args.annotation = AnnotationSettings {
+            tx: values.get("tx"),
+            note: values.get("note"),
+            operator: values.get("operator"),
+            signing_key: values.get("signing-key"),
+        };
+        cli_args.alert_rules = AlertRules {
+            available_below: values.get("alert-available-below"),
+            held_above: values.get("alert-held-above"),
+            total_change_pct: values.get("alert-total-change-pct"),
+        };
+        cli_args.kafka = KafkaSettings {
+            brokers: values.get("kafka-brokers"),
+            topic: values.get("kafka-topic"),
+        };
+        cli_args.escheatment = EscheatmentSettings {
+            after_days: values.get("escheat-after-days"),
+            holding_account: values.get("escheat-account"),
+            as_of: values.get("escheat-as-of"),
+            report: values.get("escheatment-report"),
+        };
+        cli_args.breaker = BreakerLimits {
+            max_rows: values.get("max-rows"),
+            max_total_movement: values.get("max-total-movement"),
+            max_rejects_pct: values.get("max-rejects-pct"),
+            checkpoint: values.get("breaker-checkpoint"),
+        };
+
+        let generator = &mut cli_args.generator;
+        generator.rows = values.get("rows").unwrap_or(generator.rows);
+        generator.clients = values.get("clients").unwrap_or(generator.clients);
+        generator.seed = values.get("seed").unwrap_or(generator.seed);
+        generator.dispute_probability = values
+            .get("dispute-probability")
+            .unwrap_or(generator.dispute_probability);
+        generator.chargeback_probability = values
+            .get("chargeback-probability")
+            .unwrap_or(generator.chargeback_probability);
+        generator.invalid_rate = values.get("invalid-rate").unwrap_or(generator.invalid_rate);
+
+        let trends = &mut cli_args.trends;
+        trends.trailing = values.get("trailing").unwrap_or(trends.trailing);
+        trends.deviation_pct = values.get("deviation-pct").unwrap_or(trends.deviation_pct);
+        trends.chart = values.flag("chart");
+
+        let config = &mut cli_args.config;
+        config.allow_admin_ops = values.flag("allow-admin-ops");
+        config.skip_malformed = values.flag("skip-malformed");
+        config.invariants.available_non_negative = values.flag("check-available");
+        config.invariants.enabled =
+            values.flag("check-invariants") || config.invariants.available_non_negative;
+        config.quarantine.disputes = values.get("quarantine-disputes");
+        config.quarantine.chargebacks = values.get("quarantine-chargebacks");
+        config.screening.restricted_countries =
+            values.get("restricted-countries").unwrap_or_default();
+        config.dispute_amount_policy = values.get("dispute-amount-policy").unwrap_or_default();
+        config.frozen_account_policy = values.get("frozen-account-policy").unwrap_or_default();
+        config.ordering_policy = values.get("ordering-policy").unwrap_or_default();
+        config.withdrawal_policy = values.get("withdrawal-policy").unwrap_or_default();
+        config.settlement_cutoff = values.get("settlement-cutoff");
+        config.dispute_window = values.get("dispute-window");
+
+        cli_args
+    }
+
+    /// Renders the amounts and booleans of the account output, when field formatting was configured
+    pub fn field_formatter(&self) -> Option<&dyn FieldFormatter> {
+        self.field_style
+            .as_ref()
+            .map(|style| style as &dyn FieldFormatter)
+    }
+
+    /// The detector used to decide which format the file is in. The extension check is strict
+    /// unless a format is forced, or automatic detection or content sniffing is enabled.
+    pub fn format_detector(&self) -> Box<dyn FormatDetector> {
+        match (self.force_format, self.auto_format, self.sniff_format) {
+            (Some(format), _, _) => Box::new(ForcedFormat(format)),
+            (None, true, _) => Box::new(AutoDetector),
+            (None, false, true) => Box::new(SniffingDetector),
+            (None, false, false) => Box::new(ExtensionDetector),
+        }
+    }
+
+    /// The clock the run gets the current time from, stopped at the time provided with --now
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        match self.now {
+            Some(now) => Arc::new(FixedClock::from_secs(now)),
+            None => Arc::new(SystemClock),
+        }
+    }
+}
+
+/// The typed values of the arguments a subcommand was run with
+struct Values<'a> {
+    /// What the subcommand was run with
+    matches: &'a ArgMatches,
+
+    /// The arguments the subcommand takes
+    taken: BTreeSet<Id>,
+
+    /// The arguments any subcommand takes
+    known: BTreeSet<Id>,
+}
+
+impl<'a> Values<'a> {
+    /// The values of the arguments the subcommand was run with
+    fn new(command: Command, matches: &'a ArgMatches) -> Self {
+        let ids = |command: Command| command.args().into_iter().map(|arg| arg.get_id().clone());
+
+        Values {
+            matches,
+            taken: ids(command).collect(),
+            known: Command::ALL.into_iter().flat_map(ids).collect(),
+        }
+    }
+
+    /// The value of a flag, none when it wasn't provided or the subcommand doesn't take it. Reading
+    /// a flag no subcommand takes, or as a different type than it's parsed to, is a bug, so it
+    /// panics.
+    fn get<T: Clone + Send + Sync + 'static>(&self, id: &str) -> Option<T> {
+        match self.takes(id) {
+            true => self.matches.get_one::<T>(id).cloned(),
+            false => None,
+        }
+    }
+
+    /// Every value of a flag that can be provided more than once, in the order they were provided
+    fn all<T: Clone + Send + Sync + 'static>(&self, id: &str) -> Vec<T> {
+        match self.takes(id) {
+            true => self
+                .matches
+                .get_many::<T>(id)
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            false => vec![],
+        }
+    }
+
+    /// Whether a switch was provided
+    fn flag(&self, id: &str) -> bool {
+        self.get(id).unwrap_or_default()
+    }
+
+    /// Whether a flag was provided on the command line, rather than left as its default
+    fn provided(&self, id: &str) -> bool {
+        self.takes(id) && self.matches.value_source(id) == Some(ValueSource::CommandLine)
+    }
+
+    /// Whether the subcommand takes the flag
+    fn takes(&self, id: &str) -> bool {
+        assert!(self.known.contains(id), "No subcommand takes {}", id);
+        self.taken.contains(id)
+    }
+}
+
+/// The definition of the command line. Transactions are processed when a subcommand isn't provided.
+fn definition() -> clap::Command {
+    clap::Command::new("plutus")
+        .bin_name("plutus")
+        .version(ENGINE_VERSION)
+        .about("Applies transactions to client accounts")
+        .args_override_self(true)
+        .subcommand_precedence_over_arg(true)
+        .args(Command::Process.args())
+        .subcommands(Command::ALL.map(Command::definition))
+}
+
+/// The file every subcommand reads from
+fn file_arg() -> Arg {
+    Arg::new("file")
+        .value_name("FILE")
+        .value_parser(value_parser!(OsString))
+        .help("The file to read from")
+}
+
+/// The optional second path most subcommands write to
+fn output_arg() -> Arg {
+    Arg::new("output")
+        .value_name("OUTPUT")
+        .value_parser(value_parser!(PathBuf))
+        .help("The file to write to, std out when one isn't provided")
+}
+
+/// The files processing reads from, which are merged into one stream of records
+fn files_arg() -> Arg {
+    file_arg()
+        .num_args(1..)
+        .help("The files to read from, merged by their timestamps or else read in turn")
+}
+
+/// A flag that's followed by a value, which is parsed into the type the flag takes
+fn value(
+    name: &'static str,
+    value_name: &'static str,
+    help: &'static str,
+    parser: impl IntoResettable<ValueParser>,
+) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_name(value_name)
+        .help(help)
+        .value_parser(parser)
+        .allow_hyphen_values(true)
+}
+
+/// A flag that's followed by a path
+fn path(name: &'static str, value_name: &'static str, help: &'static str) -> Arg {
+    value(name, value_name, help, value_parser!(PathBuf))
+}
+
+/// A flag that's either provided or not
+fn switch(name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .help(help)
+        .action(ArgAction::SetTrue)
+}
+
+/// The flags only processing takes, as the other subcommands write to their second path
+fn process_args() -> Vec<Arg> {
+    vec![
+        path(
+            "output",
+            "PATH",
+            "The file to write to, rather than std out",
+        ),
+        path(
+            "watch",
+            "DIR",
+            "Processes each csv dropped into the directory as it lands",
+        ),
+        path(
+            "archive",
+            "DIR",
+            "The directory watched files are moved to once processed",
+        ),
+    ]
+}
+
+/// The flags that control how the outcome of a run is reported
+fn report_args() -> Vec<Arg> {
+    vec![
+        switch("strict", "Fails the run when any record is rejected"),
+        switch(
+            "quiet",
+            "Only reports a fatal error, not every rejected record",
+        ),
+        value(
+            "redact",
+            "FIELDS",
+            "Masks amounts (or amounts:bucket) and descriptions in logs",
+            parsed::<Redaction>(),
+        ),
+    ]
+}
+
+/// The flags of a file of transactions being read
+fn input_args() -> Vec<Arg> {
+    vec![
+        value(
+            "format",
+            "FORMAT",
+            "The format to read, or auto to detect it from the contents",
+            parsed_with(input_format),
+        )
+        .overrides_with("force-format"),
+        value(
+            "force-format",
+            "FORMAT",
+            "Reads the file in this format regardless of its name",
+            parsed::<InputFormat>(),
+        )
+        .overrides_with("format"),
+        switch(
+            "sniff-format",
+            "Inspects the file when its extension isn't recognised",
+        ),
+        value(
+            "csv-backend",
+            "BACKEND",
+            "The csv parser, csv or the experimental fast parser",
+            parsed::<CsvBackend>(),
+        ),
+        switch(
+            "no-headers",
+            "Reads csv files without a header row, by column position",
+        ),
+        value(
+            "delimiter",
+            "CHAR",
+            "The column delimiter of csv files, e.g. ; or tab, or auto",
+            parsed::<Delimiter>(),
+        ),
+        path(
+            "columns",
+            "PATH",
+            "A TOML file of the header row's columns that are renamed",
+        ),
+        value(
+            "column",
+            "HEADER=COLUMN",
+            "Reads the header row's column as one the engine reads",
+            checked(parse_column),
+        )
+        .action(ArgAction::Append),
+        value(
+            "lenient-amounts",
+            "DECIMAL",
+            "Reads amounts like $1,234.56, point or comma decimals",
+            parsed::<DecimalSeparator>(),
+        ),
+        value(
+            "currency",
+            "CODE",
+            "The ISO 4217 code of the currency the amounts are in",
+            parsed::<String>(),
+        ),
+        value(
+            "minor-units",
+            "CODE=N",
+            "Overrides the minor unit of a currency",
+            checked(parse_minor_units_override),
+        )
+        .action(ArgAction::Append),
+    ]
+}
+
+/// The flags that control how the engine applies transactions
+fn engine_args() -> Vec<Arg> {
+    vec![
+        value(
+            "dispute-amount-policy",
+            "POLICY",
+            "How the amount of a dispute is checked",
+            parsed::<DisputeAmountPolicy>(),
+        ),
+        value(
+            "frozen-account-policy",
+            "POLICY",
+            "What a frozen account still accepts",
+            parsed::<FrozenAccountPolicy>(),
+        ),
+        value(
+            "ordering-policy",
+            "POLICY",
+            "The order the records are applied in",
+            parsed::<OrderingPolicy>(),
+        ),
+        value(
+            "withdrawal-policy",
+            "POLICY",
+            "Which funds a withdrawal can draw on",
+            parsed::<WithdrawalPolicy>(),
+        ),
+        value(
+            "settlement-cutoff",
+            "SECS",
+            "Transactions before this can no longer be voided",
+            parsed::<u64>(),
+        ),
+        value(
+            "dispute-window",
+            "DURATION",
+            "How long a transaction can be disputed, e.g. 90d",
+            checked(parse_duration),
+        ),
+        path(
+            "policy",
+            "PATH",
+            "Loads what happens in each edge case from a TOML file",
+        ),
+        value(
+            "edge-case",
+            "CASE=ACTION",
+            "What happens in an edge case, e.g. not-disputed=warn",
+            checked(parse_edge_case),
+        )
+        .action(ArgAction::Append),
+        switch("allow-admin-ops", "Applies admin adjustments"),
+        switch(
+            "skip-malformed",
+            "Rejects rows that can't be parsed, rather than failing",
+        ),
+        switch(
+            "check-invariants",
+            "Fails the run when a record leaves balances inconsistent",
+        ),
+        switch(
+            "check-available",
+            "Checks invariants, and that available funds aren't negative",
+        ),
+        value(
+            "quarantine-disputes",
+            "N",
+            "Quarantines clients with N disputes in the run",
+            parsed::<u32>(),
+        ),
+        value(
+            "quarantine-chargebacks",
+            "N",
+            "Quarantines clients with N chargebacks in the run",
+            parsed::<u32>(),
+        ),
+        value(
+            "restricted-countries",
+            "CODES",
+            "Holds transactions for clients in these countries",
+            checked(|value| Some(parse_countries(value))),
+        ),
+        now_arg(),
+    ]
+}
+
+/// The flag that stops the run's clock
+fn now_arg() -> Arg {
+    value(
+        "now",
+        "SECS",
+        "The time (unix timestamp) the run treats as now",
+        parsed::<u64>(),
+    )
+}
+
+/// The file of the idempotency keys that have been applied, taken by batch runs and the server
+fn idempotency_arg() -> Arg {
+    path(
+        "idempotency-keys",
+        "PATH",
+        "Applies records with the same key only once",
+    )
+}
+
+/// The flags of the state the accounts are loaded from and saved to
+fn state_args() -> Vec<Arg> {
+    vec![
+        path(
+            "load-state",
+            "PATH",
+            "Applies the transactions to a previously saved state",
+        )
+        .visible_alias("snapshot-in"),
+        path(
+            "save-state",
+            "PATH",
+            "Saves the account state once the run has finished",
+        )
+        .visible_alias("snapshot-out"),
+    ]
+}
+
+/// The flags of a run of the engine over a file, along with the outputs it writes
+fn run_args() -> Vec<Arg> {
+    let args = vec![
+        switch(
+            "simulate",
+            "Processes the transactions without saving anything",
+        ),
+        switch("wait", "Waits for another run to release the state files")
+            .overrides_with("no-wait"),
+        switch("no-wait", "Fails when another run holds the state files").overrides_with("wait"),
+        path(
+            "save-index",
+            "PATH",
+            "Saves a search index of the transactions",
+        ),
+        path(
+            "journal",
+            "PATH",
+            "Appends an event for every transaction that's applied",
+        ),
+        value(
+            "journal-batch-size",
+            "N",
+            "Writes the journal from a background thread in batches",
+            checked(positive),
+        ),
+        value(
+            "journal-outbox-capacity",
+            "N",
+            "The number of events the journal can queue",
+            checked(positive),
+        ),
+        value(
+            "demote-after",
+            "N",
+            "Moves accounts untouched for N records to cold storage",
+            parsed::<u64>(),
+        ),
+        value(
+            "max-resident-transactions",
+            "N",
+            "Spills all but N transactions to disk",
+            parsed::<usize>(),
+        ),
+        path(
+            "spill-dir",
+            "PATH",
+            "The directory spilled transactions are written to",
+        ),
+        value(
+            "store",
+            "STORE",
+            "Keeps accounts in cold storage in sqlite://path.db",
+            parsed::<StoreLocation>(),
+        ),
+        switch(
+            "retry-withdrawals",
+            "Retries withdrawals rejected for insufficient funds",
+        ),
+        value(
+            "retry-window",
+            "N",
+            "The number of records a withdrawal can be retried for",
+            parsed::<u64>(),
+        ),
+        value(
+            "dispute-buffer",
+            "WINDOW",
+            "Parks disputes for N records or Ns until their tx",
+            parsed::<BufferWindow>(),
+        ),
+        value(
+            "alert-available-below",
+            "AMOUNT",
+            "Alerts when available funds drop below this",
+            parsed::<f32>(),
+        ),
+        value(
+            "alert-held-above",
+            "AMOUNT",
+            "Alerts when held funds rise above this",
+            parsed::<f32>(),
+        ),
+        value(
+            "alert-total-change-pct",
+            "PCT",
+            "Alerts when total funds change by this much",
+            parsed::<f32>(),
+        ),
+        path(
+            "alerts-report",
+            "PATH",
+            "Writes the alerts that were raised",
+        ),
+        path(
+            "alert-log",
+            "PATH",
+            "Appends an event for every alert that's raised",
+        ),
+        path(
+            "run-metadata",
+            "PATH",
+            "Writes what produced the outputs of the run",
+        ),
+        path(
+            "onboarded-clients",
+            "PATH",
+            "Rejects transactions for clients not in this csv",
+        ),
+        path(
+            "loss-report",
+            "PATH",
+            "Writes the funds reversed by chargebacks",
+        ),
+        value(
+            "loss-account",
+            "ACCOUNT",
+            "The ledger account chargeback losses are posted to",
+            parsed::<String>(),
+        ),
+        path(
+            "anonymize",
+            "KEY",
+            "Pseudonymizes client ids in outputs with the key in this file",
+        ),
+        switch(
+            "anonymize-notes",
+            "Pseudonymizes notes rather than removing them",
+        ),
+        path(
+            "admin-flags",
+            "PATH",
+            "An admin csv of flags and notes for accounts",
+        ),
+        path(
+            "admin-ops",
+            "PATH",
+            "A csv of operations operators made on accounts",
+        ),
+        value(
+            "admin-ops-phase",
+            "PHASE",
+            "Applies the admin operations before or after",
+            parsed::<AdminPhase>(),
+        ),
+        path(
+            "clients-report",
+            "PATH",
+            "Writes when each client was first seen",
+        ),
+        path(
+            "client-metadata",
+            "PATH",
+            "A clients csv with a country column, for screening",
+        ),
+        path(
+            "denylist",
+            "PATH",
+            "Holds every transaction for the clients in this csv",
+        ),
+        path(
+            "compliance-report",
+            "PATH",
+            "Writes the transactions held for compliance review",
+        ),
+        value(
+            "escheat-after-days",
+            "DAYS",
+            "Sweeps funds held for this long to escheatment",
+            parsed::<u64>(),
+        ),
+        value(
+            "escheat-account",
+            "CLIENT",
+            "The account escheated funds are moved to",
+            parsed::<u16>(),
+        ),
+        value(
+            "escheat-as-of",
+            "SECS",
+            "The time held funds are aged against",
+            parsed::<u64>(),
+        ),
+        path(
+            "escheatment-report",
+            "PATH",
+            "Writes the funds that were escheated",
+        ),
+        value(
+            "max-rows",
+            "N",
+            "Halts the run when it has more rows than this",
+            parsed::<u64>(),
+        ),
+        value(
+            "max-total-movement",
+            "AMOUNT",
+            "Halts the run when it moves more than this",
+            parsed::<f64>(),
+        ),
+        value(
+            "max-rejects-pct",
+            "PCT",
+            "Halts the run when more rows than this are rejected",
+            parsed::<f64>(),
+        ),
+        path(
+            "breaker-checkpoint",
+            "PATH",
+            "Saves the accounts here when the run is halted",
+        ),
+        path(
+            "daily-cutover",
+            "DIR",
+            "Writes each business day's closing balances here",
+        ),
+        value(
+            "cutover-hour",
+            "HOUR",
+            "The hour (UTC, 0-23) that business days end at",
+            checked(hour),
+        ),
+        path(
+            "olap-export",
+            "DIR",
+            "Exports the run here as Parquet fact and dimension tables",
+        ),
+        idempotency_arg(),
+        path("rejections", "PATH", "Writes every rejected record"),
+        path(
+            "tx-results",
+            "PATH",
+            "Writes each record's result, accepted, rejected or ignored",
+        ),
+        path(
+            "audit-log",
+            "PATH",
+            "Appends every change to an account, with balances before it",
+        ),
+        switch(
+            "stats",
+            "Reports the run's figures, e.g. rows read and tx/sec, on std err",
+        ),
+        path("stats-out", "PATH", "Writes the run's figures as JSON"),
+    ];
+
+    [report_args(), state_args(), args].concat()
+}
+
+/// The flags of how the accounts are output
+fn account_args() -> Vec<Arg> {
+    vec![
+        value(
+            "output-version",
+            "VERSION",
+            "The version of the account output (1 or 2)",
+            parsed::<OutputVersion>(),
+        ),
+        value(
+            "output-format",
+            "FORMAT",
+            "The format of the account output (csv, json or jsonl)",
+            parsed::<OutputFormat>(),
+        ),
+        value(
+            "bool-format",
+            "STYLE",
+            "Renders booleans as true-false or 1-0",
+            parsed::<BooleanStyle>(),
+        ),
+        value(
+            "amount-decimals",
+            "N",
+            "Renders amounts with exactly N decimal places",
+            parsed::<usize>(),
+        ),
+        switch("decimal-comma", "Renders amounts with a decimal comma"),
+    ]
+}
+
+/// The flags of the address a server listens on
+fn address_args() -> Vec<Arg> {
+    vec![
+        value(
+            "addr",
+            "ADDR",
+            "The address the server listens on",
+            parsed::<String>(),
+        )
+        .overrides_with("port"),
+        value(
+            "port",
+            "PORT",
+            "The port the server listens on, on localhost",
+            parsed::<u16>(),
+        )
+        .overrides_with("addr"),
+    ]
+}
+
+/// The flags of the servers
+fn server_args() -> Vec<Arg> {
+    let args = vec![
+        value(
+            "tcp",
+            "ADDR",
+            "Accepts csv or JSONL transactions over TCP on the address",
+            parsed::<String>(),
+        ),
+        value(
+            "shards",
+            "N",
+            "The number of shards the TCP server splits accounts into",
+            checked(positive),
+        ),
+        value(
+            "shard-ranges",
+            "STARTS",
+            "Shards clients by ranges of ids starting at these",
+            parsed::<RangePartitioner>(),
+        ),
+        path(
+            "handover-socket",
+            "PATH",
+            "Hands the state over to a new server on the socket",
+        ),
+        path(
+            "take-over",
+            "PATH",
+            "Takes the state over from the server on the socket",
+        ),
+        idempotency_arg(),
+    ];
+
+    [address_args(), args].concat()
+}
+
+/// The flags of where journaled events are re-emitted to
+fn emit_args() -> Vec<Arg> {
+    vec![
+        value(
+            "sink",
+            "SINK",
+            "Where journaled events are re-emitted to",
+            parsed::<SinkKind>(),
+        ),
+        value(
+            "kafka-brokers",
+            "BROKERS",
+            "The Kafka brokers events are emitted to",
+            parsed::<String>(),
+        ),
+        value(
+            "kafka-topic",
+            "TOPIC",
+            "The Kafka topic events are emitted to",
+            parsed::<String>(),
+        ),
+    ]
+}
+
+/// The flags of the transactions that are generated
+fn generator_args() -> Vec<Arg> {
+    vec![
+        value(
+            "rows",
+            "N",
+            "The number of transactions to generate",
+            parsed::<u64>(),
+        ),
+        value(
+            "clients",
+            "N",
+            "The number of clients to generate transactions for",
+            parsed::<u16>(),
+        ),
+        value(
+            "seed",
+            "SEED",
+            "The seed transactions are generated from",
+            parsed::<u64>(),
+        ),
+        value(
+            "dispute-probability",
+            "P",
+            "The chance a generated deposit is disputed",
+            checked(probability),
+        ),
+        value(
+            "chargeback-probability",
+            "P",
+            "The chance a generated dispute is charged back",
+            checked(probability),
+        ),
+        value(
+            "invalid-rate",
+            "P",
+            "The chance a generated row is invalid",
+            checked(probability),
+        ),
+    ]
+}
+
+/// The flags of the trends report
+fn trend_args() -> Vec<Arg> {
+    vec![
+        value(
+            "trailing",
+            "N",
+            "The number of runs the trailing average is taken over",
+            checked(positive),
+        ),
+        value(
+            "deviation-pct",
+            "PCT",
+            "How far a run can deviate before it's highlighted",
+            parsed::<f64>(),
+        ),
+        switch("chart", "Draws a chart of the trends"),
+    ]
+}
+
+/// The conditions of a search of the transaction index
+fn find_args() -> Vec<Arg> {
+    vec![
+        value(
+            "client",
+            "CLIENT",
+            "Finds the transactions of this client",
+            parsed::<u16>(),
+        ),
+        value(
+            "state",
+            "STATE",
+            "Finds transactions in this state",
+            parsed::<TransactionState>(),
+        ),
+        value(
+            "min-amount",
+            "AMOUNT",
+            "Finds transactions of at least this amount",
+            parsed::<f32>(),
+        ),
+        value(
+            "max-amount",
+            "AMOUNT",
+            "Finds transactions of at most this amount",
+            parsed::<f32>(),
+        ),
+    ]
+}
+
+/// The flags of an operator note
+fn annotation_args() -> Vec<Arg> {
+    vec![
+        value(
+            "tx",
+            "TX",
+            "The transaction an annotation is about",
+            parsed::<u32>(),
+        ),
+        value(
+            "note",
+            "NOTE",
+            "The note to annotate the transaction with",
+            parsed::<String>(),
+        ),
+        value(
+            "operator",
+            "NAME",
+            "The operator writing the annotation",
+            parsed::<String>(),
+        ),
+        path("signing-key", "PATH", "The key annotations are signed with"),
+        now_arg(),
+    ]
+}
+
+/// The flags every subcommand takes, controlling what the engine logs and exports
+fn logging_args() -> Vec<Arg> {
+    vec![
+        Arg::new("verbose")
+            .long("verbose")
+            .short('v')
+            .help("Logs why each record did what it did, -vv logs every record")
+            .action(ArgAction::Count),
+        value(
+            "otlp-endpoint",
+            "URL",
+            "Exports the engine's spans to an OTLP/HTTP collector",
+            parsed::<String>(),
+        ),
+    ]
+}
+
+/// The error of a value that isn't valid for its flag
+type ValueError = Box<dyn Error + Send + Sync>;
+
+/// Parses the value of a flag into the type the flag takes, see parsed_with
+fn parsed<T>() -> impl TypedValueParser<Value = T>
+where
+    T: FromStr + Clone + Send + Sync + 'static,
+    T::Err: Into<ValueError>,
+{
+    parsed_with(T::from_str)
+}
+
+/// Parses the value of a flag, which must be valid UTF-8. The engine's own errors are reported as
+/// they are, any other error as an invalid value for the flag.
+fn parsed_with<T, E>(
+    parse: impl Fn(&str) -> Result<T, E> + Clone + Send + Sync + 'static,
+) -> impl TypedValueParser<Value = T>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Into<ValueError>,
+{
+    OsStringValueParser::new().try_map(move |value| -> Result<T, ValueError> {
+        let value = value.into_string().map_err(|_| "not valid UTF-8")?;
+        parse(&value).map_err(Into::into)
+    })
+}
+
+/// Parses the value of a flag, which is invalid when the parser doesn't return anything
+fn checked<T>(parse: fn(&str) -> Option<T>) -> impl TypedValueParser<Value = T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    parsed_with(move |value: &str| parse(value).ok_or("invalid value"))
+}
+
+/// Parses the format a file of transactions is read in, none when it's detected automatically
+fn input_format(value: &str) -> CliResult<Option<InputFormat>> {
+    match value.eq_ignore_ascii_case("auto") {
+        true => Ok(None),
+        false => value.parse().map(Some),
+    }
+}
+
+/// Parses a column of the header row along with the column it's read as (e.g. `customer=client`)
+fn parse_column(value: &str) -> Option<(String, String)> {
+    let (header, column) = value.split_once('=')?;
+
+    Some((header.to_string(), column.to_string()))
+}
+
+/// Parses an edge case along with the action taken in it (e.g. `unknown-transaction=warn`), none
+/// when either isn't valid or the edge case can't take the action
+fn parse_edge_case(value: &str) -> Option<(EdgeCase, PolicyAction)> {
+    let (case, action) = value.split_once('=')?;
+    let (case, action): (EdgeCase, PolicyAction) = (case.parse().ok()?, action.parse().ok()?);
+
+    (action != PolicyAction::Apply || case.can_apply()).then_some((case, action))
+}
+
+/// Parses a number that must be positive
+fn positive(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|number| *number > 0)
+}
+
+/// Parses a probability, which must be between zero and one
+fn probability(value: &str) -> Option<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|probability| (0.0..=1.0).contains(probability))
+}
+
+/// Parses an hour of the day (0-23)
+fn hour(value: &str) -> Option<u8> {
+    value.parse().ok().filter(|hour| *hour < 24)
+}
+
+/// Converts an error raised while parsing the command line into the engine's error. Asking for the
+/// help or version prints it and exits.
+fn usage_error(err: clap::Error) -> CliError {
+    let context = |kind| match err.get(kind) {
+        Some(ContextValue::String(value)) => Some(value.clone()),
+        _ => None,
+    };
+    let arg = context(ContextKind::InvalidArg)
+        .or_else(|| context(ContextKind::InvalidSubcommand))
+        .unwrap_or_default();
+    // the argument is described along with its value (e.g. --journal <PATH>)
+    let flag = arg
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    match err.kind() {
+        ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => err.exit(),
+        ErrorKind::UnknownArgument if arg.starts_with('-') => CliError::UnknownFlag(arg),
+        ErrorKind::UnknownArgument | ErrorKind::InvalidSubcommand => CliError::UnexpectedArg(arg),
+        ErrorKind::InvalidValue => CliError::MissingFlagValue(flag),
+        ErrorKind::ValueValidation => match err.source().and_then(|err| err.downcast_ref()) {
+            Some(cli_err) => CliError::clone(cli_err),
+            None => {
+                let value = context(ContextKind::InvalidValue).unwrap_or_default();
+                CliError::InvalidValue(flag, value)
+            }
+        },
+        _ => CliError::Usage(err.kind().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{definition, CliArgs, Command, Values};
+    use crate::config::{EnginePolicy, PolicyAction};
+    use crate::error::CliError;
+    use crate::fields::BooleanStyle;
+    use crate::format::InputFormat;
+    use crate::graph::GraphFormat;
+    use crate::lock::LockMode;
+    use crate::mapper::{DecimalSeparator, OutputFormat};
+    use crate::parser::{ColumnMapping, CsvBackend, Delimiter};
+    use crate::state::StateFormat;
+    use crate::storage::StoreLocation;
+    use crate::test_helpers::create_temp_file;
+    use std::io::Write;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::path::PathBuf;
+
+    /// Builds command line arguments, including the name of the program
+    fn args(args: &[&str]) -> Vec<String> {
+        std::iter::once("plutus")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect()
+    }
+
+    // Tests that every subcommand's arguments are defined consistently, e.g. without two flags
+    // sharing a name
+    #[test]
+    fn test_definition() {
+        definition().debug_assert();
+    }
+
+    // Tests that flags are read as the type they're parsed to, that the flags of other subcommands
+    // read as not provided, and that a flag no subcommand takes can't be read
+    #[test]
+    fn test_values() {
+        let matches = definition()
+            .try_get_matches_from(args(&["transactions.csv", "--cutover-hour", "6"]))
+            .unwrap();
+        let values = Values::new(Command::Process, &matches);
+
+        assert_eq!(values.get::<u8>("cutover-hour"), Some(6));
+        assert!(values.provided("cutover-hour"));
+        assert_eq!(values.get::<String>("tcp"), None);
+        assert!(values.all::<String>("column").is_empty());
+        assert!(!values.flag("strict"));
+
+        let read = |read: fn(&Values) -> bool| catch_unwind(AssertUnwindSafe(|| read(&values)));
+        assert!(read(|values| values.get::<u8>("cutover-hours").is_some()).is_err());
+        assert!(read(|values| values.get::<String>("cutover-hour").is_some()).is_err());
+    }
+
+    // Tests that flags are parsed regardless of where they appear relative to the file path
+    #[test]
+    fn test_parse_flags() {
+        let cli_args = CliArgs::parse(args(&[
+            "--sniff-format",
+            "data.txt",
+            "--force-format",
+            "CSV",
+        ]));
+
+        assert_eq!(
+            cli_args,
+            Ok(CliArgs {
+                file_path: PathBuf::from("data.txt"),
+                force_format: Some(InputFormat::Csv),
+                sniff_format: true,
+                ..Default::default()
+            })
+        );
+    }
+
+    // Tests that --format chooses how transactions are read, except for the state and graph
+    // subcommands
+    #[test]
+    fn test_parse_format() {
+        let cli_args = CliArgs::parse(args(&["data.gz", "--format", "auto"])).unwrap();
+        assert!(cli_args.auto_format);
+
+        let cli_args = CliArgs::parse(args(&["data.txt", "--format", "jsonl"])).unwrap();
+        assert_eq!(cli_args.force_format, Some(InputFormat::Jsonl));
+
+        let cli_args =
+            CliArgs::parse(args(&["export-state", "state.bin", "--format", "json"])).unwrap();
+        assert_eq!(cli_args.state_format, StateFormat::Json);
+        assert_eq!(cli_args.force_format, None);
+
+        let cli_args = CliArgs::parse(args(&["graph", "journal.log", "--format", "csv"])).unwrap();
+        assert_eq!(cli_args.graph_format, GraphFormat::Csv);
+
+        let cli_args = CliArgs::parse(args(&["data.csv", "--output-format", "jsonl"])).unwrap();
+        assert_eq!(cli_args.output_format, OutputFormat::Jsonl);
+        assert_eq!(cli_args.force_format, None);
+        assert_eq!(cli_args.field_style, None);
+        assert!(!cli_args.csv.no_headers);
+
+        let cli_args =
+            CliArgs::parse(args(&["data.csv", "--no-headers", "--csv-backend", "fast"])).unwrap();
+        assert!(cli_args.csv.no_headers);
+        assert_eq!(cli_args.csv.backend, CsvBackend::Fast);
+
+        let delimiter = |value| {
+            CliArgs::parse(args(&["data.csv", "--delimiter", value])).map(|args| args.csv.delimiter)
+        };
+        assert_eq!(delimiter(";"), Ok(Some(Delimiter::Fixed(b';'))));
+        assert_eq!(delimiter("tab"), Ok(Some(Delimiter::Fixed(b'\t'))));
+        assert_eq!(delimiter("auto"), Ok(Some(Delimiter::Auto)));
+
+        let cli_args = CliArgs::parse(args(&["data.csv", "--lenient-amounts", "comma"])).unwrap();
+        assert_eq!(cli_args.csv.lenient_amounts, Some(DecimalSeparator::Comma));
+
+        let cli_args =
+            CliArgs::parse(args(&["data.csv", "--stats", "--stats-out", "stats.json"])).unwrap();
+        assert!(cli_args.stats);
+        assert_eq!(cli_args.stats_out, Some(PathBuf::from("stats.json")));
+
+        let cli_args = CliArgs::parse(args(&["data.csv", "--audit-log", "audit.jsonl"])).unwrap();
+        assert_eq!(cli_args.audit_log, Some(PathBuf::from("audit.jsonl")));
+
+        let verbosity = |flags: &[&str]| {
+            let flags = ["data.csv"]
+                .iter()
+                .chain(flags)
+                .copied()
+                .collect::<Vec<_>>();
+            CliArgs::parse(args(&flags)).unwrap().verbosity
+        };
+        assert_eq!(verbosity(&[]), 0);
+        assert_eq!(verbosity(&["-v"]), 1);
+        assert_eq!(verbosity(&["-vv"]), 2);
+        assert_eq!(verbosity(&["-v", "--verbose", "-v"]), 3);
+
+        let endpoint = "http://localhost:4318/v1/traces";
+        let cli_args = CliArgs::parse(args(&["serve", "journal.log", "--otlp-endpoint", endpoint]));
+        assert_eq!(cli_args.unwrap().otlp_endpoint, Some(endpoint.to_string()));
+        assert_eq!(
+            delimiter("ab"),
+            Err(CliError::InvalidValue(
+                "--delimiter".to_string(),
+                "ab".to_string()
+            ))
+        );
+
+        let cli_args = CliArgs::parse(args(&[
+            "data.csv",
+            "--bool-format",
+            "1-0",
+            "--amount-decimals",
+            "2",
+            "--decimal-comma",
+        ]))
+        .unwrap();
+        let style = cli_args.field_style.unwrap();
+        assert_eq!(style.booleans, BooleanStyle::OneZero);
+        assert_eq!(style.decimals, Some(2));
+        assert!(style.decimal_comma);
+
+        let result = CliArgs::parse(args(&["data.csv", "--bool-format", "yes-no"]));
+        assert!(matches!(result, Err(CliError::InvalidValue(..))));
+    }
+
+    // Tests that the state flags are parsed along with their values
+    #[test]
+    fn test_parse_state_flags() {
+        let cli_args = CliArgs::parse(args(&[
+            "data.csv",
+            "--load-state",
+            "in.bin",
+            "--save-state",
+            "out.bin",
+            "--simulate",
+            "--store",
+            "sqlite://accounts.db",
+        ]))
+        .unwrap();
+
+        assert_eq!(cli_args.load_state, Some(PathBuf::from("in.bin")));
+        assert_eq!(cli_args.save_state, Some(PathBuf::from("out.bin")));
+        assert!(cli_args.simulate);
+        assert_eq!(
+            cli_args.store,
+            StoreLocation::Sqlite(PathBuf::from("accounts.db"))
+        );
+
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--store", "postgres://db"])),
+            Err(CliError::UnknownStore("postgres://db".to_string()))
+        );
+    }
+
+    // Tests that subcommands are parsed along with the paths they take
+    #[test]
+    fn test_parse_state_subcommands() {
+        let export_args = CliArgs::parse(args(&["export-state", "state.bin", "--format", "json"]));
+        assert_eq!(
+            export_args,
+            Ok(CliArgs {
+                command: Command::ExportState,
+                file_path: PathBuf::from("state.bin"),
+                state_format: StateFormat::Json,
+                ..Default::default()
+            })
+        );
+
+        let import_args =
+            CliArgs::parse(args(&["import-state", "state.json", "state.bin"])).unwrap();
+        assert_eq!(import_args.command, Command::ImportState);
+        assert_eq!(import_args.output_path, Some(PathBuf::from("state.bin")));
+
+        // only processing reads several files
+        let process_args = CliArgs::parse(args(&["data.csv", "other.csv", "*.jsonl"])).unwrap();
+        assert_eq!(process_args.file_path, PathBuf::from("data.csv"));
+        assert_eq!(
+            process_args.more_file_paths,
+            vec![PathBuf::from("other.csv"), PathBuf::from("*.jsonl")]
+        );
+        let process_args = CliArgs::parse(args(&["process", "data.csv", "other.csv"])).unwrap();
+        assert_eq!(
+            process_args.more_file_paths,
+            vec![PathBuf::from("other.csv")]
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["validate", "data.csv", "out.csv", "other.csv"])),
+            Err(CliError::UnexpectedArg("other.csv".to_string()))
+        );
+    }
+
+    // Tests that unknown flags, unknown formats and missing flag values are rejected
+    #[test]
+    fn test_parse_invalid_flags() {
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--loud"])),
+            Err(CliError::UnknownFlag("--loud".to_string()))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--force-format", "xml"])),
+            Err(CliError::UnknownFormat("xml".to_string()))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--force-format"])),
+            Err(CliError::MissingFlagValue("--force-format".to_string()))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--journal-batch-size", "0"])),
+            Err(CliError::InvalidValue(
+                "--journal-batch-size".to_string(),
+                "0".to_string()
+            ))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--redact", "amounts,balances"])),
+            Err(CliError::InvalidValue(
+                "--redact".to_string(),
+                "balances".to_string()
+            ))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["serve", "journal.log", "--shard-ranges", "500,100"])),
+            Err(CliError::InvalidValue(
+                "--shard-ranges".to_string(),
+                "500,100".to_string()
+            ))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--dispute-window", "3w"])),
+            Err(CliError::InvalidValue(
+                "--dispute-window".to_string(),
+                "3w".to_string()
+            ))
+        );
+    }
+
+    // Tests that paths which aren't valid UTF-8 are kept exactly as they were provided
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_non_utf8_paths() {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+
+        let path = OsString::from_vec(b"transactions-\xff.csv".to_vec());
+        let cli_args = CliArgs::parse([
+            OsString::from("plutus"),
+            path.clone(),
+            OsString::from("--journal"),
+            path.clone(),
+        ])
+        .unwrap();
+
+        assert_eq!(cli_args.file_path, PathBuf::from(&path));
+        assert_eq!(cli_args.journal, Some(PathBuf::from(&path)));
+        assert_eq!(
+            CliArgs::parse([
+                OsString::from("plutus"),
+                path.clone(),
+                "--format".into(),
+                path
+            ]),
+            Err(CliError::InvalidValue(
+                "--format".to_string(),
+                "transactions-\u{FFFD}.csv".to_string()
+            ))
+        );
+    }
+
+    // Tests that validating is a strict simulation, and that each subcommand only takes the flags
+    // that follow it
+    #[test]
+    fn test_parse_run_subcommands() {
+        let validate_args = CliArgs::parse(args(&["validate", "data.csv", "--quiet"])).unwrap();
+        assert_eq!(validate_args.command, Command::Validate);
+        assert!(validate_args.simulate && validate_args.strict && validate_args.quiet);
+
+        let snapshot_args = CliArgs::parse(args(&[
+            "day2.csv",
+            "--snapshot-in",
+            "1.bin",
+            "--snapshot-out",
+            "2.bin",
+        ]))
+        .unwrap();
+        assert_eq!(snapshot_args.load_state, Some(PathBuf::from("1.bin")));
+        assert_eq!(snapshot_args.save_state, Some(PathBuf::from("2.bin")));
+
+        let report_args = CliArgs::parse(args(&["report", "data.csv", "summary.json"])).unwrap();
+        assert_eq!(report_args.command, Command::Report);
+        assert_eq!(report_args.output_path, Some(PathBuf::from("summary.json")));
+        assert!(!report_args.reconcile);
+
+        let reconcile_args = CliArgs::parse(args(&[
+            "report",
+            "reconcile",
+            "data.csv",
+            "reconciliation.json",
+        ]))
+        .unwrap();
+        assert_eq!(reconcile_args.command, Command::Report);
+        assert!(reconcile_args.reconcile);
+        assert_eq!(reconcile_args.file_path, PathBuf::from("data.csv"));
+        assert_eq!(
+            reconcile_args.output_path,
+            Some(PathBuf::from("reconciliation.json"))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["report", "data.csv", "reconcile"])),
+            Err(CliError::UnexpectedArg("reconcile".to_string()))
+        );
+
+        let process_args = CliArgs::parse(args(&[
+            "process", "data.csv", "--output", "out.csv", "--strict",
+        ]))
+        .unwrap();
+        assert_eq!(process_args.command, Command::Process);
+        assert_eq!(process_args.output_path, Some(PathBuf::from("out.csv")));
+        assert!(process_args.strict && !process_args.simulate);
+        assert_eq!(
+            CliArgs::parse(args(&["--output", "out.csv", "process", "data.csv"])),
+            Err(CliError::UnexpectedArg("--output".to_string()))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["serve", "journal.log", "--strict"])),
+            Err(CliError::UnknownFlag("--strict".to_string()))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["generate", "data.csv", "--rows", "many"])),
+            Err(CliError::InvalidValue(
+                "--rows".to_string(),
+                "many".to_string()
+            ))
+        );
+
+        let serve_args = CliArgs::parse(args(&[
+            "serve",
+            "journal.log",
+            "--load-state",
+            "state.bin",
+            "--dispute-window",
+            "60d",
+        ]))
+        .unwrap();
+        assert_eq!(serve_args.command, Command::Serve);
+        assert_eq!(serve_args.load_state, Some(PathBuf::from("state.bin")));
+        assert_eq!(serve_args.config.dispute_window, Some(60 * 86_400));
+
+        let tcp_args = CliArgs::parse(args(&["serve", "journal.log", "--tcp", "0.0.0.0:9000"]));
+        assert_eq!(tcp_args.unwrap().tcp, Some("0.0.0.0:9000".to_string()));
+
+        let handover_args = CliArgs::parse(args(&[
+            "serve",
+            "journal.log",
+            "--take-over",
+            "old.sock",
+            "--handover-socket",
+            "new.sock",
+        ]))
+        .unwrap();
+        assert_eq!(handover_args.take_over, Some(PathBuf::from("old.sock")));
+        assert_eq!(
+            handover_args.handover_socket,
+            Some(PathBuf::from("new.sock"))
+        );
+
+        let run_args = CliArgs::parse(args(&["run", "nightly.toml", "--currency", "USD"])).unwrap();
+        assert_eq!(run_args.command, Command::Run);
+        assert_eq!(run_args.file_path, PathBuf::from("nightly.toml"));
+        assert_eq!(
+            CliArgs::parse(args(&["run", "nightly.toml", "out.csv"])),
+            Err(CliError::UnexpectedArg("out.csv".to_string()))
+        );
+
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "validate"])),
+            Err(CliError::UnexpectedArg("validate".to_string()))
+        );
+
+        // watching a directory replaces the file, but only when processing
+        let watch_args = CliArgs::parse(args(&["--watch", "drop", "--archive", "done"])).unwrap();
+        assert_eq!(watch_args.watch, Some(PathBuf::from("drop")));
+        assert_eq!(watch_args.archive, Some(PathBuf::from("done")));
+        assert_eq!(
+            CliArgs::parse(args(&["validate", "--watch", "drop"])),
+            Err(CliError::UnknownFlag("--watch".to_string()))
+        );
+        assert_eq!(
+            CliArgs::parse(args(&["validate"])),
+            Err(CliError::MissingArg)
+        );
+    }
+
+    // Tests that when flags override each other, the last one provided wins
+    #[test]
+    fn test_parse_flag_order() {
+        let cli_args = CliArgs::parse(args(&[
+            "data.csv",
+            "--no-wait",
+            "--minor-units",
+            "XTS=1",
+            "--wait",
+            "--minor-units",
+            "XTS=3",
+        ]))
+        .unwrap();
+
+        assert_eq!(cli_args.lock_mode, LockMode::Wait);
+        assert_eq!(cli_args.minor_unit_overrides.get("XTS"), Some(&3));
+    }
+
+    // Tests that the header row's columns are renamed by a TOML file, with --column overriding it
+    #[test]
+    fn test_parse_column_mapping() -> Result<(), std::io::Error> {
+        let (mapping_path, dir, mut file) = create_temp_file("columns.toml")?;
+        writeln!(file, "txn_type = \"type\"")?;
+        writeln!(file, "customer = \"client\"")?;
+
+        let cli_args = CliArgs::parse(args(&[
+            "data.csv",
+            "--column",
+            "customer=destination",
+            "--columns",
+            &mapping_path,
+            "--column",
+            "value=amount",
+        ]))
+        .unwrap();
+        let mut expected = ColumnMapping::default();
+        let renames = [
+            ("txn_type", "type"),
+            ("customer", "destination"),
+            ("value", "amount"),
+        ];
+        for (header, column) in renames {
+            expected.rename(header, column).unwrap();
+        }
+        assert_eq!(cli_args.csv.columns, expected);
+
+        for value in ["value=price", "value"] {
+            assert_eq!(
+                CliArgs::parse(args(&["data.csv", "--column", value])),
+                Err(CliError::InvalidValue(
+                    "--column".to_string(),
+                    value.to_string()
+                ))
+            );
+        }
+
+        writeln!(file, "txn_id = \"id\"")?;
+        assert!(matches!(
+            CliArgs::parse(args(&["data.csv", "--columns", &mapping_path])),
+            Err(CliError::InvalidColumnMapping(..))
+        ));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the clock is stopped at the time provided with --now
+    #[test]
+    fn test_parse_now() {
+        let cli_args = CliArgs::parse(args(&["data.csv", "--now", "1700000000"])).unwrap();
+        assert_eq!(cli_args.now, Some(1_700_000_000));
+        assert_eq!(cli_args.clock().now_ms(), 1_700_000_000_000);
+
+        assert_eq!(
+            CliArgs::parse(args(&["data.csv", "--now", "yesterday"])),
+            Err(CliError::InvalidValue(
+                "--now".to_string(),
+                "yesterday".to_string()
+            ))
+        );
+    }
+
+    // Tests that the engine policy is loaded from a TOML file, with --edge-case overriding it
+    // whichever order they're provided in
+    #[test]
+    fn test_parse_engine_policy() -> Result<(), std::io::Error> {
+        let (policy_path, dir, mut file) = create_temp_file("policy.toml")?;
+        writeln!(file, "unknown-transaction = \"warn\"")?;
+        writeln!(file, "withdrawal-dispute = \"error\"")?;
+
+        let cli_args = CliArgs::parse(args(&[
+            "data.csv",
+            "--edge-case",
+            "withdrawal-dispute=apply",
+            "--policy",
+            &policy_path,
+            "--edge-case",
+            "locked-deposit=ignore",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cli_args.config.policy,
+            EnginePolicy {
+                unknown_transaction: PolicyAction::Warn,
+                not_disputed: PolicyAction::Ignore,
+                locked_deposit: PolicyAction::Ignore,
+                withdrawal_dispute: PolicyAction::Apply,
+            }
+        );
+
+        for value in ["not-disputed=apply", "late-deposit=warn", "not-disputed"] {
+            assert_eq!(
+                CliArgs::parse(args(&["data.csv", "--edge-case", value])),
+                Err(CliError::InvalidValue(
+                    "--edge-case".to_string(),
+                    value.to_string()
+                ))
+            );
+        }
+
+        writeln!(file, "not-disputed = \"apply\"")?;
+        assert!(matches!(
+            CliArgs::parse(args(&["data.csv", "--policy", &policy_path])),
+            Err(CliError::InvalidPolicy(..))
+        ));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
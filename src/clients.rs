@@ -0,0 +1,391 @@
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::{Account, LockState};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// A row of the clients metadata file, any columns other than the client id and country are
+/// ignored
+#[derive(Debug, Deserialize)]
+struct ClientMetadata {
+    /// The unique ID of the client
+    client: u16,
+
+    /// The country the client is registered in, as an ISO 3166 code
+    #[serde(default)]
+    country: Option<String>,
+}
+
+/// A row of the admin sidecar file, the flags operations have set on a client's account
+#[derive(Debug, Deserialize)]
+struct AdminSidecarRow {
+    /// The unique ID of the client
+    client: u16,
+
+    /// Whether the client is a VIP
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    vip: bool,
+
+    /// Whether the account is under review
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    under_review: bool,
+
+    /// Whether chargebacks leave the account unlocked
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    do_not_lock: bool,
+
+    /// A free text note about the account
+    #[serde(default)]
+    note: Option<String>,
+
+    /// The kind of lock an operator has put on the account, if any
+    #[serde(default, deserialize_with = "deserialize_lock")]
+    lock: Option<SidecarLock>,
+
+    /// The rule the lock was put on for
+    #[serde(default)]
+    lock_rule: Option<String>,
+}
+
+/// The kinds of lock that can be put on an account in the admin sidecar file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SidecarLock {
+    Risk,
+    Admin,
+    Compliance,
+}
+
+/// Operational overrides for an account, kept out of the transaction feed. Flags that are left
+/// blank are off.
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct AccountFlags {
+    /// The client is a VIP, this is only passed through to reports
+    pub vip: bool,
+
+    /// The account is under review, so deposits are held rather than made available. Resolving
+    /// a held deposit releases it.
+    pub under_review: bool,
+
+    /// Chargebacks don't lock the account
+    pub do_not_lock: bool,
+
+    /// A free text note about the account, passed through to reports
+    pub note: Option<String>,
+
+    /// The lock an operator has put on the account, applied when the run starts
+    pub lock: Option<LockState>,
+}
+
+impl From<AdminSidecarRow> for AccountFlags {
+    fn from(row: AdminSidecarRow) -> Self {
+        // locks without a rule are attributed to the sidecar file itself
+        let rule = row.lock_rule.unwrap_or_else(|| "admin-flags".to_string());
+        let lock = row.lock.map(|lock| match lock {
+            SidecarLock::Risk => LockState::RiskLock { rule },
+            SidecarLock::Admin => LockState::AdminLock { rule },
+            SidecarLock::Compliance => LockState::ComplianceHold { rule },
+        });
+
+        AccountFlags {
+            vip: row.vip,
+            under_review: row.under_review,
+            do_not_lock: row.do_not_lock,
+            note: row.note,
+            lock,
+        }
+    }
+}
+
+/// When a client was first seen and how active they've been, as output to the clients report
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ClientActivity {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The first transaction that was applied to the client's account
+    pub first_seen_tx: Option<u32>,
+
+    /// The number of transactions that have been applied to the client's account
+    pub transaction_count: u32,
+
+    /// Whether the client is a VIP
+    pub vip: bool,
+
+    /// Whether the account is under review
+    pub under_review: bool,
+
+    /// Whether chargebacks leave the account unlocked
+    pub do_not_lock: bool,
+
+    /// The note about the account from the admin sidecar file
+    pub note: Option<String>,
+
+    /// Why the account is locked (e.g. chargeback-lock), or unlocked
+    pub lock_state: &'static str,
+
+    /// The transaction or rule that locked the account
+    pub lock_trigger: Option<String>,
+}
+
+impl ClientActivity {
+    /// Creates the report row for a client's account
+    pub fn new(client: u16, account: &Account, flags: Option<&AccountFlags>) -> Self {
+        let flags = flags.cloned().unwrap_or_default();
+        ClientActivity {
+            client,
+            first_seen_tx: account.first_seen_tx,
+            transaction_count: account.transaction_count,
+            vip: flags.vip,
+            under_review: flags.under_review,
+            do_not_lock: flags.do_not_lock,
+            note: flags.note,
+            lock_state: account.lock_state.name(),
+            lock_trigger: account.lock_state.trigger(),
+        }
+    }
+}
+
+/// Loads the client ids from a csv with a client column, e.g. the onboarded or denylisted clients
+pub fn load_client_ids(file_path: impl AsRef<Path>) -> SourceResult<BTreeSet<u16>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::Fields)
+        .flexible(true)
+        .from_path(file_path)
+        .map_err(SourceError::from)?;
+
+    reader
+        .deserialize()
+        .map(|row| {
+            row.map(|metadata: ClientMetadata| metadata.client)
+                .map_err(SourceError::from)
+        })
+        .collect()
+}
+
+/// Loads the country of each client from the clients metadata file. Clients without a country
+/// are left out, and codes are upper cased.
+pub fn load_client_countries(file_path: impl AsRef<Path>) -> SourceResult<BTreeMap<u16, String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(file_path)
+        .map_err(SourceError::from)?;
+
+    let mut countries = BTreeMap::new();
+    for row in reader.deserialize() {
+        let metadata: ClientMetadata = row.map_err(SourceError::from)?;
+        if let Some(country) = metadata.country.filter(|country| !country.is_empty()) {
+            countries.insert(metadata.client, country.to_uppercase());
+        }
+    }
+
+    Ok(countries)
+}
+
+/// Loads the flags of each client from an admin sidecar csv, with a client column and optional
+/// vip, under_review, do_not_lock and note columns
+pub fn load_account_flags(
+    file_path: impl AsRef<Path>,
+) -> SourceResult<BTreeMap<u16, AccountFlags>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(file_path)
+        .map_err(SourceError::from)?;
+
+    reader
+        .deserialize()
+        .map(|row| {
+            row.map(|row: AdminSidecarRow| (row.client, row.into()))
+                .map_err(SourceError::from)
+        })
+        .collect()
+}
+
+/// Reads a flag that may be left blank, or written as true/false, yes/no or 1/0
+fn deserialize_flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    match value.trim().to_lowercase().as_str() {
+        "" | "false" | "no" | "0" => Ok(false),
+        "true" | "yes" | "1" => Ok(true),
+        other => Err(serde::de::Error::custom(format!("invalid flag: {}", other))),
+    }
+}
+
+/// Reads the kind of lock on an account, which may be left blank
+fn deserialize_lock<'de, D>(deserializer: D) -> Result<Option<SidecarLock>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    match value.trim().to_lowercase().as_str() {
+        "" => Ok(None),
+        "risk" => Ok(Some(SidecarLock::Risk)),
+        "admin" => Ok(Some(SidecarLock::Admin)),
+        "compliance" => Ok(Some(SidecarLock::Compliance)),
+        other => Err(serde::de::Error::custom(format!("invalid lock: {}", other))),
+    }
+}
+
+/// Writes when each client was first seen, their transaction count and the flags set on their
+/// account to a csv, ordered by client id
+pub fn write_clients_report(
+    file_path: impl AsRef<Path>,
+    account_map: &HashMap<u16, Account>,
+    account_flags: &BTreeMap<u16, AccountFlags>,
+) -> SourceResult<()> {
+    let mut rows: Vec<ClientActivity> = account_map
+        .iter()
+        .map(|(client_id, account)| {
+            ClientActivity::new(*client_id, account, account_flags.get(client_id))
+        })
+        .collect();
+    rows.sort_by_key(|row| row.client);
+
+    let mut writer = csv::Writer::from_path(file_path).map_err(SourceError::from)?;
+    for row in rows {
+        writer.serialize(row).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clients::{
+        load_account_flags, load_client_countries, load_client_ids, write_clients_report,
+        AccountFlags,
+    };
+    use crate::mapper::{Account, LockState};
+    use crate::test_helpers::*;
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::fs;
+    use std::io::{Error, Write};
+
+    // Tests that the client ids are loaded from the metadata file, ignoring any other columns
+    #[test]
+    fn test_load_client_ids() -> Result<(), Error> {
+        let (file_path, dir, mut file) = create_temp_file("clients.csv")?;
+        writeln!(file, "client, name")?;
+        writeln!(file, "2, Bob")?;
+        writeln!(file, "1, Alice")?;
+
+        assert_eq!(load_client_ids(&file_path).unwrap(), BTreeSet::from([1, 2]));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the countries are loaded from the metadata file, skipping clients without one
+    #[test]
+    fn test_load_client_countries() -> Result<(), Error> {
+        let (file_path, dir, mut file) = create_temp_file("clients.csv")?;
+        writeln!(file, "client, name, country")?;
+        writeln!(file, "1, Alice, gb")?;
+        writeln!(file, "2, Bob, ")?;
+        writeln!(file, "3, Carol, IR")?;
+
+        assert_eq!(
+            load_client_countries(&file_path).unwrap(),
+            BTreeMap::from([(1, "GB".to_string()), (3, "IR".to_string())])
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the report contains the first transaction and transaction count of each client
+    #[test]
+    fn test_write_clients_report() -> Result<(), Error> {
+        let (file_path, dir, file) = create_temp_file("clients_report.csv")?;
+
+        let mut active = Account::default();
+        active.record_activity(4);
+        active.record_activity(9);
+        let account_map = HashMap::from([(3, active), (1, Account::default())]);
+
+        let account_flags = BTreeMap::from([(
+            3,
+            AccountFlags {
+                vip: true,
+                note: Some("priority".to_string()),
+                ..Default::default()
+            },
+        )]);
+
+        write_clients_report(&file_path, &account_map, &account_flags).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file_path)?,
+            "client,first_seen_tx,transaction_count,vip,under_review,do_not_lock,note,lock_state,lock_trigger\n\
+             1,,0,false,false,false,,unlocked,\n\
+             3,4,2,true,false,false,priority,unlocked,\n"
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the flags are loaded from the admin sidecar file, treating blank flags as off
+    #[test]
+    fn test_load_account_flags() -> Result<(), Error> {
+        let (file_path, dir, mut file) = create_temp_file("admin.csv")?;
+        writeln!(
+            file,
+            "client, vip, under_review, do_not_lock, note, lock, lock_rule"
+        )?;
+        writeln!(file, "1, yes, , true, , risk, velocity")?;
+        writeln!(file, "2, , 1, , manual review, , ")?;
+        writeln!(file, "3, , , , , admin, ")?;
+
+        assert_eq!(
+            load_account_flags(&file_path).unwrap(),
+            BTreeMap::from([
+                (
+                    1,
+                    AccountFlags {
+                        vip: true,
+                        do_not_lock: true,
+                        lock: Some(LockState::RiskLock {
+                            rule: "velocity".to_string()
+                        }),
+                        ..Default::default()
+                    }
+                ),
+                (
+                    2,
+                    AccountFlags {
+                        under_review: true,
+                        note: Some("manual review".to_string()),
+                        ..Default::default()
+                    }
+                ),
+                (
+                    3,
+                    AccountFlags {
+                        lock: Some(LockState::AdminLock {
+                            rule: "admin-flags".to_string()
+                        }),
+                        ..Default::default()
+                    }
+                ),
+            ])
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the engine gets the current time from, wherever it needs to know what time it is (e.g.
+/// journal timestamps, escheatment ageing and run metadata)
+pub trait Clock: Send + Sync {
+    /// The current time in milliseconds since the unix epoch
+    fn now_ms(&self) -> u64;
+
+    /// The current time in seconds since the unix epoch
+    fn now_secs(&self) -> u64 {
+        self.now_ms() / 1_000
+    }
+}
+
+/// The system's clock, the default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64)
+    }
+}
+
+/// A clock that only moves when it's told to, for reproducible runs and tests. Clones share the
+/// same time, so a test can advance the clock it handed to the engine.
+#[derive(Debug, Default, Clone)]
+pub struct FixedClock {
+    /// The current time in milliseconds since the unix epoch
+    now_ms: Arc<AtomicU64>,
+}
+
+impl FixedClock {
+    /// Creates a clock stopped at the given time, in milliseconds since the unix epoch
+    pub fn new(now_ms: u64) -> Self {
+        FixedClock {
+            now_ms: Arc::new(AtomicU64::new(now_ms)),
+        }
+    }
+
+    /// Creates a clock stopped at the given time, in seconds since the unix epoch
+    pub fn from_secs(now_secs: u64) -> Self {
+        FixedClock::new(now_secs.saturating_mul(1_000))
+    }
+
+    /// Moves the clock to the given time, in milliseconds since the unix epoch
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms.store(now_ms, Ordering::SeqCst);
+    }
+
+    /// Moves the clock forward by the given number of milliseconds
+    pub fn advance(&self, ms: u64) {
+        self.now_ms.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for FixedClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,546 @@
+use crate::clients::AccountFlags;
+use crate::currency::Currency;
+use crate::error::{CliError, CliResult, LedgerError, LedgerResult};
+use crate::quarantine::QuarantineRules;
+use crate::screening::Screening;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use toml_edit::DocumentMut;
+
+/// Settings that control how the engine applies transactions to accounts
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct EngineConfig {
+    /// Whether admin-only transactions (e.g. adjustment) can be processed
+    pub allow_admin_ops: bool,
+
+    /// Which funds can be withdrawn while transactions are being disputed
+    pub withdrawal_policy: WithdrawalPolicy,
+
+    /// The clients that transactions can be applied to. When set, onboarding is closed and
+    /// transactions for any other client are rejected.
+    pub onboarded_clients: Option<BTreeSet<u16>>,
+
+    /// What to do with the amount of dispute, resolve and chargeback rows
+    pub dispute_amount_policy: DisputeAmountPolicy,
+
+    /// Operational overrides for individual accounts, from the admin sidecar file
+    pub account_flags: BTreeMap<u16, AccountFlags>,
+
+    /// Whether records that share a timestamp are applied in the order they were received
+    pub ordering_policy: OrderingPolicy,
+
+    /// Whether accounts frozen by a chargeback or a risk rule still accept deposits
+    pub frozen_account_policy: FrozenAccountPolicy,
+
+    /// The currency of the amounts, when set they can't be more precise than its minor unit
+    pub currency: Option<Currency>,
+
+    /// Which clients have their transactions held for compliance review
+    pub screening: Screening,
+
+    /// The fraud rules that quarantine clients
+    pub quarantine: QuarantineRules,
+
+    /// When the run's transactions settle, as a unix timestamp. When set, only voids with an
+    /// earlier timestamp are applied.
+    pub settlement_cutoff: Option<u64>,
+
+    /// How long after a transaction it can be disputed, in seconds. When set, disputes with a
+    /// timestamp later than that are rejected, rather than holding the funds.
+    pub dispute_window: Option<u64>,
+
+    /// Whether rows that can't be parsed are rejected like any other record, rather than ending
+    /// the run
+    pub skip_malformed: bool,
+
+    /// What happens to rows in the edge cases that specs disagree on
+    pub policy: EnginePolicy,
+
+    /// Which invariants of the balances are checked after every transaction, a debug mode
+    pub invariants: InvariantChecks,
+}
+
+impl EngineConfig {
+    /// Errors when onboarding is closed and the client hasn't been onboarded
+    pub fn check_onboarded(&self, client_id: u16) -> LedgerResult<()> {
+        match &self.onboarded_clients {
+            Some(clients) if !clients.contains(&client_id) => {
+                Err(LedgerError::UnknownClient(client_id))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Which invariants of an account's balances are checked after every transaction applied to it.
+/// It's a debug mode for catching rounding and state bugs, so a broken invariant ends the run.
+#[derive(Debug, Default, Clone, Copy, Serialize, PartialEq)]
+pub struct InvariantChecks {
+    /// Whether the total funds are checked to be the sum of the available and held funds, and
+    /// the held funds to not be negative
+    pub enabled: bool,
+
+    /// Whether the available funds are checked to not be negative as well. They legitimately go
+    /// negative in some cases (e.g. a deposit disputed after it was spent), so it's optional.
+    pub available_non_negative: bool,
+}
+
+/// Controls which funds a withdrawal can draw on while an account has open disputes. Tenants
+/// disagree on this, so it's configured per run.
+#[derive(Debug, Default, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WithdrawalPolicy {
+    /// Only the available funds can be withdrawn, held funds are untouchable
+    #[default]
+    AvailableOnly,
+
+    /// Held funds can be withdrawn as well, up to the total funds. The available funds may go
+    /// negative, and the total funds will if a held transaction is later charged back.
+    IncludeHeld,
+
+    /// No withdrawals are allowed while any transaction on the account is being disputed
+    FreezeDuringDispute,
+}
+
+/// Controls what happens to the amount of dispute, resolve and chargeback rows. These rows
+/// shouldn't have one, but some feeds populate it anyway.
+#[derive(Debug, Default, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputeAmountPolicy {
+    /// The amount is ignored
+    #[default]
+    Ignore,
+
+    /// The amount must match the referenced transaction's amount, the row is rejected otherwise
+    Validate,
+
+    /// A dispute only holds the amount, rather than the whole transaction. Resolves and
+    /// chargebacks settle whatever was held, so their amounts are ignored.
+    Partial,
+}
+
+/// Controls the order records that share a timestamp are applied in. Feeds with second
+/// granularity timestamps don't say whether a resolve or a withdrawal in the same second happened
+/// first, but the order decides whether the withdrawal succeeds.
+#[derive(Debug, Default, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrderingPolicy {
+    /// Records are applied in the order they were received
+    #[default]
+    AsReceived,
+
+    /// A resolve is applied before any withdrawals for the same client that it directly follows,
+    /// when they share a timestamp. Records without a timestamp are applied as received.
+    ResolvesFirst,
+}
+
+/// Controls which funds movements accounts frozen by a chargeback or a risk rule still accept.
+/// Withdrawals are always rejected, but tenants disagree on whether deposits should be.
+#[derive(Debug, Default, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrozenAccountPolicy {
+    /// Deposits are still accepted, so funds owed to the client aren't bounced
+    AllowDeposits,
+
+    /// Deposits, withdrawals and voids are all rejected, the balances can't move until the
+    /// account is unlocked
+    #[default]
+    RejectAll,
+}
+
+/// Edge cases in applying a row that graders and specs disagree on, each handled as the
+/// `EnginePolicy` says
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeCase {
+    /// A dispute, resolve, chargeback, void or refund of a transaction that isn't in the client's
+    /// account
+    UnknownTransaction,
+
+    /// A resolve or chargeback of a transaction that isn't being disputed
+    NotDisputed,
+
+    /// A deposit to a locked account, when its lock still permits deposits
+    LockedDeposit,
+
+    /// A dispute of a withdrawal, rather than a deposit
+    WithdrawalDispute,
+}
+
+impl EdgeCase {
+    /// Every edge case, in the order they're documented
+    pub const ALL: [EdgeCase; 4] = [
+        EdgeCase::UnknownTransaction,
+        EdgeCase::NotDisputed,
+        EdgeCase::LockedDeposit,
+        EdgeCase::WithdrawalDispute,
+    ];
+
+    /// The name the edge case is configured with
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeCase::UnknownTransaction => "unknown-transaction",
+            EdgeCase::NotDisputed => "not-disputed",
+            EdgeCase::LockedDeposit => "locked-deposit",
+            EdgeCase::WithdrawalDispute => "withdrawal-dispute",
+        }
+    }
+
+    /// Whether rows in the edge case can be applied as usual. Rows referencing a transaction that
+    /// isn't in the account, or isn't being disputed, have nothing to apply.
+    pub fn can_apply(self) -> bool {
+        matches!(self, EdgeCase::LockedDeposit | EdgeCase::WithdrawalDispute)
+    }
+}
+
+/// What the engine does with a row in one of the edge cases
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyAction {
+    /// The row is applied as usual, only for the edge cases that can be
+    Apply,
+
+    /// The row is accepted without changing the account, and nothing is reported
+    Ignore,
+
+    /// The row is accepted without changing the account, and a warning is reported for it. Warnings
+    /// don't fail the run, even when it's strict.
+    Warn,
+
+    /// The row is rejected like any other record, with the code of the edge case's error
+    Error,
+}
+
+/// Configures what happens to rows in each of the edge cases that specs disagree on. The defaults
+/// are the engine's original behavior: rows referencing an unknown or undisputed transaction are
+/// silently ignored, and deposits to locked accounts and disputes of withdrawals are applied.
+///
+/// A policy can be loaded from a TOML file, with a key for each edge case that isn't left as the
+/// default:
+///
+/// ```toml
+/// unknown-transaction = "warn"
+/// withdrawal-dispute = "error"
+/// ```
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EnginePolicy {
+    /// What happens to a row referencing a transaction that isn't in the client's account
+    pub unknown_transaction: PolicyAction,
+
+    /// What happens to a resolve or chargeback of a transaction that isn't being disputed
+    pub not_disputed: PolicyAction,
+
+    /// What happens to a deposit to a locked account that still permits them
+    pub locked_deposit: PolicyAction,
+
+    /// What happens to a dispute of a withdrawal
+    pub withdrawal_dispute: PolicyAction,
+}
+
+impl Default for EnginePolicy {
+    fn default() -> Self {
+        EnginePolicy {
+            unknown_transaction: PolicyAction::Ignore,
+            not_disputed: PolicyAction::Ignore,
+            locked_deposit: PolicyAction::Apply,
+            withdrawal_dispute: PolicyAction::Apply,
+        }
+    }
+}
+
+impl EnginePolicy {
+    /// Reads a policy from a TOML file
+    pub fn load(file_path: &Path) -> CliResult<Self> {
+        let invalid =
+            |message: String| CliError::InvalidPolicy(file_path.display().to_string(), message);
+        let text = fs::read_to_string(file_path).map_err(|err| invalid(err.to_string()))?;
+
+        EnginePolicy::parse(&text).map_err(invalid)
+    }
+
+    /// Parses a policy, where edge cases without a key are left as the default
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let document: DocumentMut = text.parse().map_err(|err| format!("{}", err))?;
+
+        let mut policy = EnginePolicy::default();
+        for (key, item) in document.iter() {
+            let case: EdgeCase = key
+                .parse()
+                .map_err(|_| format!("unknown edge case `{}`", key))?;
+            let action = item
+                .as_str()
+                .and_then(|action| action.parse().ok())
+                .filter(|action| *action != PolicyAction::Apply || case.can_apply())
+                .ok_or_else(|| format!("`{}` isn't an action the edge case can take", key))?;
+            policy.set(case, action);
+        }
+
+        Ok(policy)
+    }
+
+    /// What happens to rows in the edge case
+    pub fn action(&self, case: EdgeCase) -> PolicyAction {
+        match case {
+            EdgeCase::UnknownTransaction => self.unknown_transaction,
+            EdgeCase::NotDisputed => self.not_disputed,
+            EdgeCase::LockedDeposit => self.locked_deposit,
+            EdgeCase::WithdrawalDispute => self.withdrawal_dispute,
+        }
+    }
+
+    /// Sets what happens to rows in the edge case
+    pub fn set(&mut self, case: EdgeCase, action: PolicyAction) {
+        match case {
+            EdgeCase::UnknownTransaction => self.unknown_transaction = action,
+            EdgeCase::NotDisputed => self.not_disputed = action,
+            EdgeCase::LockedDeposit => self.locked_deposit = action,
+            EdgeCase::WithdrawalDispute => self.withdrawal_dispute = action,
+        }
+    }
+}
+
+impl FromStr for EdgeCase {
+    type Err = CliError;
+
+    fn from_str(case: &str) -> CliResult<Self> {
+        EdgeCase::ALL
+            .into_iter()
+            .find(|edge_case| edge_case.name() == case)
+            .ok_or_else(|| CliError::UnknownPolicy(case.to_string()))
+    }
+}
+
+impl FromStr for PolicyAction {
+    type Err = CliError;
+
+    fn from_str(action: &str) -> CliResult<Self> {
+        match action {
+            "apply" => Ok(PolicyAction::Apply),
+            "ignore" => Ok(PolicyAction::Ignore),
+            "warn" => Ok(PolicyAction::Warn),
+            "error" => Ok(PolicyAction::Error),
+            _ => Err(CliError::UnknownPolicy(action.to_string())),
+        }
+    }
+}
+
+impl FromStr for FrozenAccountPolicy {
+    type Err = CliError;
+
+    fn from_str(policy: &str) -> CliResult<Self> {
+        match policy {
+            "allow-deposits" => Ok(FrozenAccountPolicy::AllowDeposits),
+            "reject-all" => Ok(FrozenAccountPolicy::RejectAll),
+            _ => Err(CliError::UnknownPolicy(policy.to_string())),
+        }
+    }
+}
+
+impl FromStr for OrderingPolicy {
+    type Err = CliError;
+
+    fn from_str(policy: &str) -> CliResult<Self> {
+        match policy {
+            "as-received" => Ok(OrderingPolicy::AsReceived),
+            "resolves-first" => Ok(OrderingPolicy::ResolvesFirst),
+            _ => Err(CliError::UnknownPolicy(policy.to_string())),
+        }
+    }
+}
+
+impl FromStr for DisputeAmountPolicy {
+    type Err = CliError;
+
+    fn from_str(policy: &str) -> CliResult<Self> {
+        match policy {
+            "ignore" => Ok(DisputeAmountPolicy::Ignore),
+            "validate" => Ok(DisputeAmountPolicy::Validate),
+            "partial" => Ok(DisputeAmountPolicy::Partial),
+            _ => Err(CliError::UnknownPolicy(policy.to_string())),
+        }
+    }
+}
+
+impl FromStr for WithdrawalPolicy {
+    type Err = CliError;
+
+    fn from_str(policy: &str) -> CliResult<Self> {
+        match policy {
+            "available-only" => Ok(WithdrawalPolicy::AvailableOnly),
+            "include-held" => Ok(WithdrawalPolicy::IncludeHeld),
+            "freeze-during-dispute" => Ok(WithdrawalPolicy::FreezeDuringDispute),
+            _ => Err(CliError::UnknownPolicy(policy.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        DisputeAmountPolicy, EdgeCase, EngineConfig, EnginePolicy, FrozenAccountPolicy,
+        OrderingPolicy, PolicyAction, WithdrawalPolicy,
+    };
+    use crate::error::{CliError, LedgerError};
+    use crate::test_helpers::create_temp_file;
+    use serde::Serialize;
+    use std::collections::BTreeSet;
+    use std::fmt::Debug;
+    use std::io::{Error, Write};
+    use std::path::Path;
+    use std::str::FromStr;
+
+    /// Checks that each policy is parsed from its name, the same name it's serialized with, and
+    /// that anything else is rejected
+    fn assert_parses<T>(policies: &[(&str, T)])
+    where
+        T: FromStr<Err = CliError> + Serialize + Debug + PartialEq,
+    {
+        for (name, policy) in policies {
+            assert_eq!(&name.parse::<T>().unwrap(), policy);
+            assert_eq!(
+                serde_json::to_string(policy).unwrap(),
+                format!("\"{}\"", name)
+            );
+        }
+
+        for unknown in ["", "unknown", "Reject-All", "include_held"] {
+            assert_eq!(
+                unknown.parse::<T>(),
+                Err(CliError::UnknownPolicy(unknown.to_string()))
+            );
+        }
+    }
+
+    // Tests that every policy is parsed from its name, and that unknown names are rejected
+    #[test]
+    fn test_policy_from_str() {
+        assert_parses(&[
+            ("available-only", WithdrawalPolicy::AvailableOnly),
+            ("include-held", WithdrawalPolicy::IncludeHeld),
+            (
+                "freeze-during-dispute",
+                WithdrawalPolicy::FreezeDuringDispute,
+            ),
+        ]);
+        assert_parses(&[
+            ("ignore", DisputeAmountPolicy::Ignore),
+            ("validate", DisputeAmountPolicy::Validate),
+            ("partial", DisputeAmountPolicy::Partial),
+        ]);
+        assert_parses(&[
+            ("allow-deposits", FrozenAccountPolicy::AllowDeposits),
+            ("reject-all", FrozenAccountPolicy::RejectAll),
+        ]);
+        assert_parses(&[
+            ("as-received", OrderingPolicy::AsReceived),
+            ("resolves-first", OrderingPolicy::ResolvesFirst),
+        ]);
+        assert_parses(&[
+            ("apply", PolicyAction::Apply),
+            ("ignore", PolicyAction::Ignore),
+            ("warn", PolicyAction::Warn),
+            ("error", PolicyAction::Error),
+        ]);
+
+        for case in EdgeCase::ALL {
+            assert_eq!(case.name().parse::<EdgeCase>().unwrap(), case);
+        }
+        assert_eq!(
+            "unknown_transaction".parse::<EdgeCase>(),
+            Err(CliError::UnknownPolicy("unknown_transaction".to_string()))
+        );
+    }
+
+    // Tests that a TOML policy sets the edge cases it has keys for, leaving the rest as the
+    // default, and that unknown edge cases, unknown actions and applying rows that can't be
+    // applied are rejected
+    #[test]
+    fn test_engine_policy_parse() {
+        let policy =
+            EnginePolicy::parse("unknown-transaction = \"warn\"\nlocked-deposit = \"error\"\n")
+                .unwrap();
+        assert_eq!(
+            policy.action(EdgeCase::UnknownTransaction),
+            PolicyAction::Warn
+        );
+        assert_eq!(policy.action(EdgeCase::LockedDeposit), PolicyAction::Error);
+        assert_eq!(policy.action(EdgeCase::NotDisputed), PolicyAction::Ignore);
+        assert_eq!(
+            policy.action(EdgeCase::WithdrawalDispute),
+            PolicyAction::Apply
+        );
+        assert_eq!(EnginePolicy::parse("").unwrap(), EnginePolicy::default());
+
+        assert_eq!(
+            EnginePolicy::parse("double-dispute = \"warn\""),
+            Err("unknown edge case `double-dispute`".to_string())
+        );
+        let invalid = "`not-disputed` isn't an action the edge case can take".to_string();
+        assert_eq!(
+            EnginePolicy::parse("not-disputed = \"shout\""),
+            Err(invalid.clone())
+        );
+        assert_eq!(
+            EnginePolicy::parse("not-disputed = 1"),
+            Err(invalid.clone())
+        );
+        assert_eq!(
+            EnginePolicy::parse("not-disputed = \"apply\""),
+            Err(invalid)
+        );
+        assert!(EnginePolicy::parse("not-disputed = ").is_err());
+    }
+
+    // Tests that a policy is loaded from a file, and that a file that can't be read or parsed is
+    // reported against its path
+    #[test]
+    fn test_engine_policy_load() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("policy.toml")?;
+        writeln!(file, "withdrawal-dispute = \"ignore\"")?;
+
+        let policy = EnginePolicy::load(Path::new(&file_path_str)).unwrap();
+        assert_eq!(
+            policy.action(EdgeCase::WithdrawalDispute),
+            PolicyAction::Ignore
+        );
+
+        let missing = dir.path().join("missing.toml");
+        let err = EnginePolicy::load(&missing).unwrap_err();
+        assert!(
+            matches!(err, CliError::InvalidPolicy(path, _) if path == missing.display().to_string())
+        );
+
+        writeln!(file, "locked-deposit = \"maybe\"")?;
+        assert_eq!(
+            EnginePolicy::load(Path::new(&file_path_str)),
+            Err(CliError::InvalidPolicy(
+                file_path_str.clone(),
+                "`locked-deposit` isn't an action the edge case can take".to_string()
+            ))
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that any client is accepted while onboarding is open, and only onboarded clients once
+    // it's closed
+    #[test]
+    fn test_check_onboarded() {
+        assert_eq!(EngineConfig::default().check_onboarded(7), Ok(()));
+
+        let config = EngineConfig {
+            onboarded_clients: Some(BTreeSet::from([1, 2])),
+            ..Default::default()
+        };
+        assert_eq!(config.check_onboarded(2), Ok(()));
+        assert_eq!(
+            config.check_onboarded(7),
+            Err(LedgerError::UnknownClient(7))
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::error::{CliError, CliResult};
+use round::round;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The ISO 4217 currencies whose minor unit isn't 2, along with their minor unit. Every other
+/// active currency code has a minor unit of 2.
+const MINOR_UNIT_EXCEPTIONS: [(&str, u8); 26] = [
+    ("BHD", 3),
+    ("BIF", 0),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("DJF", 0),
+    ("GNF", 0),
+    ("IQD", 3),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("OMR", 3),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("TND", 3),
+    ("UGX", 0),
+    ("UYI", 0),
+    ("UYW", 4),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+];
+
+/// The ISO 4217 currencies with a minor unit of 2
+const TWO_MINOR_UNITS: [&str; 131] = [
+    "AED", "AFN", "ALL", "AMD", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT", "BGN",
+    "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD", "CDF", "CHE",
+    "CHF", "CHW", "CNY", "COP", "COU", "CRC", "CUP", "CVE", "CZK", "DKK", "DOP", "DZD", "EGP",
+    "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GTQ", "GYD", "HKD",
+    "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IRR", "JMD", "KES", "KGS", "KHR", "KPW", "KYD",
+    "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP",
+    "MRU", "MUR", "MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR",
+    "NZD", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "QAR", "RON", "RSD", "RUB", "SAR", "SBD",
+    "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL",
+    "THB", "TJS", "TMT", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "USD", "USN", "UYU", "UZS",
+    "VES",
+];
+
+/// The currency of the amounts in a run, which decides how many decimal places they can have
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Currency {
+    /// The ISO 4217 code of the currency, e.g. JPY
+    pub code: String,
+
+    /// The number of decimal places of the currency's minor unit, e.g. 0 for JPY
+    pub minor_units: u8,
+}
+
+impl Currency {
+    /// Finds a currency by its code, in the overrides or the built-in ISO 4217 table. Codes aren't
+    /// case sensitive.
+    pub fn resolve(code: &str, overrides: &BTreeMap<String, u8>) -> CliResult<Self> {
+        let code = code.to_uppercase();
+        let minor_units = overrides
+            .get(&code)
+            .copied()
+            .or_else(|| iso_minor_units(&code))
+            .ok_or_else(|| CliError::UnknownCurrency(code.clone()))?;
+
+        Ok(Currency { code, minor_units })
+    }
+
+    /// Rounds an amount to the currency's minor unit
+    pub fn round(&self, amount: f32) -> f32 {
+        round(amount as f64, self.minor_units as i32) as f32
+    }
+
+    /// Whether an amount has more decimal places than the currency's minor unit. Amounts only
+    /// have a precision of up to four places past the decimal, anything beyond that is ignored.
+    pub fn is_too_precise(&self, amount: f32) -> bool {
+        round(amount as f64, self.minor_units as i32) != round(amount as f64, 4)
+    }
+}
+
+/// The minor unit of an ISO 4217 currency, when the code is known
+fn iso_minor_units(code: &str) -> Option<u8> {
+    MINOR_UNIT_EXCEPTIONS
+        .iter()
+        .find(|(exception, _)| *exception == code)
+        .map(|(_, minor_units)| *minor_units)
+        .or_else(|| TWO_MINOR_UNITS.contains(&code).then_some(2))
+}
+
+/// Parses a minor unit override, e.g. XTS=3
+pub fn parse_minor_units_override(value: &str) -> Option<(String, u8)> {
+    let (code, minor_units) = value.split_once('=')?;
+    let minor_units = minor_units
+        .trim()
+        .parse()
+        .ok()
+        .filter(|units| *units <= 4)?;
+
+    Some((code.trim().to_uppercase(), minor_units))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::currency::{parse_minor_units_override, Currency};
+    use crate::error::CliError;
+    use std::collections::BTreeMap;
+
+    // Tests that currencies are found in the built-in table, unless they've been overridden
+    #[test]
+    fn test_resolve_currency() {
+        let overrides = BTreeMap::from([("JPY".to_string(), 2), ("XTS".to_string(), 3)]);
+
+        assert_eq!(
+            Currency::resolve("usd", &BTreeMap::new())
+                .unwrap()
+                .minor_units,
+            2
+        );
+        assert_eq!(
+            Currency::resolve("BHD", &BTreeMap::new())
+                .unwrap()
+                .minor_units,
+            3
+        );
+        assert_eq!(
+            Currency::resolve("JPY", &BTreeMap::new())
+                .unwrap()
+                .minor_units,
+            0
+        );
+        assert_eq!(Currency::resolve("JPY", &overrides).unwrap().minor_units, 2);
+        assert_eq!(Currency::resolve("XTS", &overrides).unwrap().minor_units, 3);
+        assert_eq!(
+            Currency::resolve("XTS", &BTreeMap::new()),
+            Err(CliError::UnknownCurrency("XTS".to_string()))
+        );
+    }
+
+    // Tests that amounts are checked against, and rounded to, the currency's minor unit
+    #[test]
+    fn test_minor_units() {
+        let yen = Currency::resolve("JPY", &BTreeMap::new()).unwrap();
+        assert!(yen.is_too_precise(100.5));
+        assert!(!yen.is_too_precise(100.0));
+        assert_eq!(yen.round(99.99), 100.0);
+
+        let dinar = Currency::resolve("BHD", &BTreeMap::new()).unwrap();
+        assert!(!dinar.is_too_precise(1.125));
+        assert!(dinar.is_too_precise(1.1255));
+
+        assert_eq!(
+            parse_minor_units_override("xts=3"),
+            Some(("XTS".to_string(), 3))
+        );
+        assert_eq!(parse_minor_units_override("XTS"), None);
+        assert_eq!(parse_minor_units_override("XTS=5"), None);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::anonymize::Anonymizer;
+use crate::currency::Currency;
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::AccountRecord;
+use crate::storage::TieredAccounts;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The number of seconds in a day
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Splits a run into business days using the records' timestamps, writing each day's closing
+/// balances as the run rolls over into the next day. Accounts carry straight on into the next
+/// day, so a multi-day file is processed in a single run.
+///
+/// Days only roll forward. Records without a timestamp, or with one from an earlier day, belong
+/// to the day that's currently open.
+pub struct DailyCutover {
+    /// The directory the closing balances and day summaries are written to
+    dir: PathBuf,
+
+    /// The hour (UTC) that business days end at
+    cutover_hour: u8,
+
+    /// The business day that's currently open, once a timestamp has been seen
+    current_day: Option<u64>,
+
+    /// The number of records read during the open day
+    records: u64,
+
+    /// The number of records rejected during the open day
+    rejections: u64,
+
+    /// The summaries of the days that have closed
+    summaries: Vec<DaySummary>,
+
+    /// Rounds the closing balances to the currency's minor unit, when there is one
+    currency: Option<Currency>,
+
+    /// Pseudonymizes the client ids of the closing balances, when anonymizing outputs
+    anonymizer: Option<Anonymizer>,
+}
+
+/// The activity of a business day and the balances it closed with
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DaySummary {
+    /// The business day, as YYYY-MM-DD
+    pub date: String,
+
+    /// The number of records read during the day
+    pub records: u64,
+
+    /// The number of records rejected during the day
+    pub rejections: u64,
+
+    /// The number of accounts at the close of the day
+    pub accounts: u64,
+
+    /// The total funds across every account at the close of the day
+    pub total_funds: f64,
+
+    /// The held funds across every account at the close of the day
+    pub held_funds: f64,
+
+    /// The number of locked accounts at the close of the day
+    pub locked_accounts: u64,
+}
+
+impl DailyCutover {
+    /// Creates a cutover that writes to the directory, with days ending at the given hour (UTC)
+    pub fn new(dir: impl Into<PathBuf>, cutover_hour: u8) -> Self {
+        DailyCutover {
+            dir: dir.into(),
+            cutover_hour,
+            current_day: None,
+            records: 0,
+            rejections: 0,
+            summaries: vec![],
+            currency: None,
+            anonymizer: None,
+        }
+    }
+
+    /// Rounds the closing balances to the currency's minor unit
+    pub fn with_currency(mut self, currency: Option<Currency>) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    /// Pseudonymizes the client ids of the closing balances
+    pub fn with_anonymizer(mut self, anonymizer: Option<Anonymizer>) -> Self {
+        self.anonymizer = anonymizer;
+        self
+    }
+
+    /// Closes the open day when a record's timestamp falls in a later day, before the record is
+    /// applied
+    pub fn roll(&mut self, timestamp: Option<u64>, accounts: &TieredAccounts) -> SourceResult<()> {
+        let Some(day) = timestamp.map(|timestamp| self.business_day(timestamp)) else {
+            return Ok(());
+        };
+
+        match self.current_day {
+            Some(current_day) if day > current_day => {
+                self.close_day(current_day, accounts)?;
+                self.current_day = Some(day);
+            }
+            None => self.current_day = Some(day),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Counts a record towards the open day, once it's been applied or rejected
+    pub fn count(&mut self, rejected: bool) {
+        self.records += 1;
+        if rejected {
+            self.rejections += 1;
+        }
+    }
+
+    /// Closes the day that's still open, writing the summary of every day to days.csv in the
+    /// directory. Returns the summaries.
+    pub fn finish(mut self, accounts: &TieredAccounts) -> SourceResult<Vec<DaySummary>> {
+        if let Some(current_day) = self.current_day {
+            self.close_day(current_day, accounts)?;
+        }
+
+        write_rows(&self.dir.join("days.csv"), &self.summaries)?;
+
+        Ok(self.summaries)
+    }
+
+    /// The business day a timestamp falls in, as the number of days since the unix epoch
+    fn business_day(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_sub(self.cutover_hour as u64 * 3_600) / SECONDS_PER_DAY
+    }
+
+    /// Writes the closing balances of a day to closing-YYYY-MM-DD.csv in the directory, and
+    /// summarizes it
+    fn close_day(&mut self, day: u64, accounts: &TieredAccounts) -> SourceResult<()> {
+        let date = civil_date(day);
+        let records = accounts.records()?;
+
+        self.summaries.push(DaySummary {
+            date: date.clone(),
+            records: self.records,
+            rejections: self.rejections,
+            accounts: records.len() as u64,
+            total_funds: records.iter().map(|record| record.total as f64).sum(),
+            held_funds: records.iter().map(|record| record.held as f64).sum(),
+            locked_accounts: records.iter().filter(|record| record.locked).count() as u64,
+        });
+        self.records = 0;
+        self.rejections = 0;
+
+        let mut records: Vec<AccountRecord> = records
+            .into_iter()
+            .map(|record| match &self.currency {
+                Some(currency) => record.in_currency(currency),
+                None => record,
+            })
+            .map(|record| match &self.anonymizer {
+                Some(anonymizer) => AccountRecord {
+                    client: anonymizer.client(record.client),
+                    ..record
+                },
+                None => record,
+            })
+            .collect();
+        records.sort_by_key(|record| record.client);
+
+        write_rows(&self.dir.join(format!("closing-{}.csv", date)), &records)
+    }
+}
+
+/// Writes the rows to a csv, creating its directory when it doesn't exist
+fn write_rows<T: Serialize>(file_path: &Path, rows: &[T]) -> SourceResult<()> {
+    if let Some(dir) = file_path.parent() {
+        fs::create_dir_all(dir).map_err(|err| SourceError::Io(err.to_string()))?;
+    }
+
+    let mut writer = csv::Writer::from_path(file_path).map_err(SourceError::from)?;
+    for row in rows {
+        writer.serialize(row).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// The date (YYYY-MM-DD) of a number of days since the unix epoch, in the proleptic Gregorian
+/// calendar
+pub(crate) fn civil_date(days: u64) -> String {
+    // shift the epoch to 0000-03-01, so leap days fall at the end of each 400 year era
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cutover::civil_date;
+
+    // Tests that days since the epoch are converted to the right dates, including leap days
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(11_016), "2000-02-29");
+        assert_eq!(civil_date(19_782), "2024-02-29");
+        assert_eq!(civil_date(20_453), "2025-12-31");
+    }
+}
@@ -0,0 +1,268 @@
+use crate::error::{CliError, CliResult, EngineResult, SourceError, SourceResult};
+use crate::journal::{parse_entry, write_event, AccountEvent, JournalEntry};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The sinks that journaled events can be re-emitted to
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SinkKind {
+    /// One JSON object per line, written to a file or std out
+    #[default]
+    Jsonl,
+
+    /// A Kafka topic, keyed by client id. Requires the `kafka` feature.
+    Kafka,
+}
+
+impl FromStr for SinkKind {
+    type Err = CliError;
+
+    fn from_str(sink: &str) -> CliResult<Self> {
+        match sink.to_lowercase().as_str() {
+            "jsonl" => Ok(SinkKind::Jsonl),
+            "kafka" => Ok(SinkKind::Kafka),
+            _ => Err(CliError::UnknownSink(sink.to_string())),
+        }
+    }
+}
+
+/// Somewhere that events can be sent to, so downstream systems can rebuild their projections
+pub trait EventSink {
+    /// Sends an event to the sink
+    fn emit(&mut self, event: &AccountEvent) -> SourceResult<()>;
+
+    /// Ensures every event has been delivered
+    fn flush(&mut self) -> SourceResult<()>;
+}
+
+/// Writes events as one JSON object per line
+pub struct JsonlSink<W: Write> {
+    /// Where the events are written to
+    writer: W,
+}
+
+impl<W: Write> JsonlSink<W> {
+    /// Creates a sink that writes to the given writer
+    pub fn new(writer: W) -> Self {
+        JsonlSink { writer }
+    }
+}
+
+impl<W: Write> EventSink for JsonlSink<W> {
+    fn emit(&mut self, event: &AccountEvent) -> SourceResult<()> {
+        write_event(&mut self.writer, event)
+    }
+
+    fn flush(&mut self) -> SourceResult<()> {
+        self.writer
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))
+    }
+}
+
+/// Where to connect to when emitting events to Kafka
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KafkaSettings {
+    /// A comma separated list of the brokers to bootstrap from
+    pub brokers: Option<String>,
+
+    /// The topic that events are produced to
+    pub topic: Option<String>,
+}
+
+/// Re-emits every event in a journal to the sink, in the order they were journaled. Returns the
+/// number of events that were emitted.
+pub fn emit_events(
+    journal_path: impl AsRef<Path>,
+    sink: &mut dyn EventSink,
+) -> SourceResult<usize> {
+    let file = File::open(journal_path).map_err(|err| SourceError::Io(err.to_string()))?;
+    let mut emitted = 0;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| SourceError::Io(err.to_string()))?;
+
+        // tolerate blank lines, e.g. a trailing newline added by hand
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = parse_entry(&line).map_err(|err| SourceError::Parse {
+            line: index as u64 + 1,
+            message: err.to_string(),
+        })?;
+
+        // notes aren't events, so downstream systems never see them
+        let JournalEntry::Event(event) = entry else {
+            continue;
+        };
+        sink.emit(&event)?;
+        emitted += 1;
+    }
+
+    sink.flush()?;
+
+    Ok(emitted)
+}
+
+/// Opens the selected sink and re-emits the journal to it. JSONL is written to the output file,
+/// or std out when one isn't provided.
+pub fn emit_events_to(
+    journal_path: &Path,
+    sink: SinkKind,
+    output_path: Option<&Path>,
+    kafka: &KafkaSettings,
+) -> EngineResult<usize> {
+    let mut sink: Box<dyn EventSink> = match (sink, output_path) {
+        (SinkKind::Jsonl, Some(output_path)) => {
+            let file = File::create(output_path).map_err(|err| SourceError::Io(err.to_string()))?;
+            Box::new(JsonlSink::new(BufWriter::new(file)))
+        }
+        (SinkKind::Jsonl, None) => Box::new(JsonlSink::new(io::stdout())),
+        (SinkKind::Kafka, _) => kafka_sink(kafka)?,
+    };
+
+    Ok(emit_events(journal_path, sink.as_mut())?)
+}
+
+/// Connects to Kafka using the settings provided on the command line
+#[cfg(feature = "kafka")]
+fn kafka_sink(settings: &KafkaSettings) -> EngineResult<Box<dyn EventSink>> {
+    let brokers = settings
+        .brokers
+        .as_deref()
+        .ok_or_else(|| CliError::MissingFlagValue("--kafka-brokers".to_string()))?;
+    let topic = settings
+        .topic
+        .as_deref()
+        .ok_or_else(|| CliError::MissingFlagValue("--kafka-topic".to_string()))?;
+
+    Ok(Box::new(kafka::KafkaSink::connect(brokers, topic)?))
+}
+
+/// Kafka support wasn't compiled in
+#[cfg(not(feature = "kafka"))]
+fn kafka_sink(_settings: &KafkaSettings) -> EngineResult<Box<dyn EventSink>> {
+    Err(CliError::UnavailableSink("kafka".to_string()).into())
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use crate::emit::EventSink;
+    use crate::error::{SourceError, SourceResult};
+    use crate::journal::AccountEvent;
+    use rdkafka::config::ClientConfig;
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use rdkafka::producer::{BaseRecord, DefaultProducerContext, Producer, ThreadedProducer};
+    use std::thread;
+    use std::time::Duration;
+
+    /// How long to wait for every event to be delivered once the journal has been emitted
+    const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Produces events to a Kafka topic, keyed by client id so each client's events stay ordered
+    pub struct KafkaSink {
+        /// Delivers the queued events from a background thread
+        producer: ThreadedProducer<DefaultProducerContext>,
+
+        /// The topic that events are produced to
+        topic: String,
+    }
+
+    impl KafkaSink {
+        /// Creates a producer for the brokers
+        pub fn connect(brokers: &str, topic: &str) -> SourceResult<KafkaSink> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .set("enable.idempotence", "true")
+                .create()
+                .map_err(kafka_error)?;
+
+            Ok(KafkaSink {
+                producer,
+                topic: topic.to_string(),
+            })
+        }
+    }
+
+    impl EventSink for KafkaSink {
+        fn emit(&mut self, event: &AccountEvent) -> SourceResult<()> {
+            let key = event.client.to_string();
+            let payload =
+                serde_json::to_string(event).map_err(|err| SourceError::Io(err.to_string()))?;
+
+            loop {
+                let record = BaseRecord::to(&self.topic).key(&key).payload(&payload);
+
+                match self.producer.send(record) {
+                    Ok(()) => return Ok(()),
+                    // wait for the background thread to deliver some of the queue
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                        thread::sleep(Duration::from_millis(10))
+                    }
+                    Err((err, _)) => return Err(kafka_error(err)),
+                }
+            }
+        }
+
+        fn flush(&mut self) -> SourceResult<()> {
+            self.producer.flush(FLUSH_TIMEOUT).map_err(kafka_error)
+        }
+    }
+
+    /// Wraps an error raised by the Kafka client
+    fn kafka_error(err: KafkaError) -> SourceError {
+        SourceError::Io(format!("Kafka: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emit::{emit_events, JsonlSink};
+    use crate::error::SourceError;
+    use crate::test_helpers::*;
+    use std::io::{Error, Write};
+
+    // Tests that every journaled event is re-emitted in order, skipping blank lines
+    #[test]
+    fn test_emit_events_to_jsonl() -> Result<(), Error> {
+        let (journal_path, dir, mut file) = create_temp_file("journal.log")?;
+        let events = [
+            r#"{"client":1,"tx":1,"type":"deposit","amount":5.0,"reason":null,"available":5.0,"held":0.0,"total":5.0,"locked":false,"recorded_at_ms":1}"#,
+            r#"{"client":2,"tx":2,"type":"dispute","amount":null,"reason":null,"available":0.0,"held":1.0,"total":1.0,"locked":false,"recorded_at_ms":2}"#,
+        ];
+        writeln!(file, "{}\n\n{}", events[0], events[1])?;
+
+        let mut output = vec![];
+        let emitted = emit_events(&journal_path, &mut JsonlSink::new(&mut output)).unwrap();
+
+        assert_eq!(emitted, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{}\n{}\n", events[0], events[1])
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a malformed event stops the replay with the line it was on
+    #[test]
+    fn test_emit_events_malformed_event() -> Result<(), Error> {
+        let (journal_path, dir, mut file) = create_temp_file("journal.log")?;
+        writeln!(file, "{{\"client\":1}}")?;
+
+        let err = emit_events(&journal_path, &mut JsonlSink::new(vec![])).unwrap_err();
+
+        assert!(matches!(err, SourceError::Parse { line: 1, .. }));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1106 @@
+use crate::admin::{AdminAction, AdminOperation, AdminPhase};
+use crate::audit::{AuditBalances, AuditEvent, AuditLog};
+use crate::breaker::CircuitBreaker;
+use crate::clients::AccountFlags;
+use crate::clock::Clock;
+use crate::config::{DisputeAmountPolicy, EdgeCase, EngineConfig, PolicyAction};
+use crate::cutover::{DailyCutover, DaySummary};
+use crate::error::{EngineError, EngineResult, ExitReport, LedgerError, LedgerResult, SourceError};
+use crate::escheat::{escheat_held_funds, Escheatment, EscheatmentSettings};
+use crate::idempotency::IdempotencyKeys;
+use crate::journal::{AccountEvent, Journal};
+use crate::losses::LossLedger;
+use crate::mapper::{Account, AccountRecord, LockState, Record, Transaction, TransactionType};
+use crate::olap::OlapExport;
+use crate::owners::TransactionOwners;
+use crate::quarantine::{Quarantine, QuarantineEvent};
+use crate::reorder::{BufferOutcome, BufferWindow, DisputeBuffer};
+use crate::results::{Footprint, IgnoreReason, TxResult, TxResults};
+use crate::retry::{RetryOutcome, RetryQueue};
+use crate::spill::{TransactionSpill, TransactionStore};
+use crate::state::save_state;
+use crate::storage::{AccountStore, TieredAccounts};
+use futures_core::Stream;
+use round::round;
+use std::collections::{HashMap, HashSet};
+use std::future::poll_fn;
+use std::pin::pin;
+use std::sync::Arc;
+use tracing::{debug, info_span};
+
+/// The number of records applied from a stream between each time the task yields to the runtime
+const STREAM_YIELD_INTERVAL: u64 = 1_024;
+
+/// Applies transaction records to client accounts, journaling each one that's applied
+#[derive(Default)]
+pub struct Engine {
+    /// The client accounts, keyed by client id
+    accounts: TieredAccounts,
+
+    /// Settings that control how transactions are applied
+    config: EngineConfig,
+
+    /// Where applied transactions are journaled
+    journal: Journal,
+
+    /// Withdrawals rejected for insufficient funds, waiting to be retried, when retries are enabled
+    retries: Option<RetryQueue>,
+
+    /// Rows referencing transactions that haven't arrived yet, when they're buffered
+    disputes: Option<DisputeBuffer>,
+
+    /// The funds reversed out of accounts by chargebacks
+    losses: LossLedger,
+
+    /// The transactions that settled in an earlier run, which can no longer be voided
+    settled: HashSet<(u16, u32)>,
+
+    /// The client each deposit, withdrawal and transfer belongs to, so rows referencing a
+    /// transaction can be checked against the whole ledger. It isn't spilled, so it grows with
+    /// every transaction even when the resident transactions are capped.
+    owners: TransactionOwners,
+
+    /// Splits the run into business days, when daily cutover is enabled
+    days: Option<DailyCutover>,
+
+    /// The idempotency keys of the records that have been applied
+    idempotency_keys: IdempotencyKeys,
+
+    /// The operations from an admin operations file that haven't been applied yet, along with the
+    /// line each was read from
+    admin_operations: Vec<(u64, AdminOperation)>,
+
+    /// When the admin operations are applied, relative to the records
+    admin_phase: AdminPhase,
+
+    /// Quarantines clients that cross a fraud rule
+    quarantine: Quarantine,
+
+    /// Spills transactions that haven't been referenced recently out of their accounts, when the
+    /// number kept in memory is capped
+    spill: Option<TransactionSpill>,
+
+    /// Halts the run once it exceeds one of its safety limits, when they're set
+    breaker: Option<CircuitBreaker>,
+
+    /// Gathers the run into fact and dimension tables, when an OLAP export is enabled
+    olap: Option<OlapExport>,
+
+    /// Where the result of each input record is written, when they're written
+    results: Option<TxResults>,
+
+    /// Where every mutation of an account is audited, when it's enabled
+    audit: Option<AuditLog>,
+
+    /// What the escheatment sweep run once every record has been applied does, and the clock
+    /// held funds are aged against, when it's enabled
+    escheatment: Option<(EscheatmentSettings, Arc<dyn Clock>)>,
+
+    /// Why the last record that was applied had no effect on its account, when it didn't. It's
+    /// only worked out when results are written.
+    ignored: Option<IgnoreReason>,
+
+    /// The edge cases the engine policy warns about, for the records applied since they were
+    /// last taken
+    warnings: Vec<LedgerError>,
+}
+
+impl Engine {
+    /// Creates an engine that applies transactions to the given accounts
+    pub fn new(
+        mut accounts: HashMap<u16, Account>,
+        config: EngineConfig,
+        journal: Journal,
+    ) -> Self {
+        for (client_id, account) in accounts.iter_mut() {
+            apply_sidecar_lock(account, config.account_flags.get(client_id));
+        }
+
+        Engine {
+            quarantine: Quarantine::new(config.quarantine.clone()),
+            settled: settled_transactions(&accounts),
+            owners: TransactionOwners::of(&accounts),
+            accounts: TieredAccounts::new(accounts),
+            config,
+            journal,
+            retries: None,
+            disputes: None,
+            losses: LossLedger::default(),
+            days: None,
+            idempotency_keys: IdempotencyKeys::default(),
+            admin_operations: vec![],
+            admin_phase: AdminPhase::default(),
+            spill: None,
+            breaker: None,
+            olap: None,
+            results: None,
+            audit: None,
+            escheatment: None,
+            ignored: None,
+            warnings: vec![],
+        }
+    }
+
+    /// Demotes accounts to a compact cold tier once they've gone untouched for the given number
+    /// of records, reducing memory for runs with many mostly idle clients
+    pub fn with_cold_storage(mut self, demote_after: u64) -> Self {
+        self.accounts = self.accounts.with_demotion(demote_after);
+        self
+    }
+
+    /// Keeps the accounts in the cold tier in the store, e.g. a database, rather than in memory
+    pub fn with_account_store(mut self, store: Box<dyn AccountStore>) -> Self {
+        self.accounts = self.accounts.with_store(store);
+        self
+    }
+
+    /// Keeps at most the given number of transactions in their accounts, spilling the ones that
+    /// were referenced least recently to the store. They're moved back when a row references them,
+    /// and once processing finishes. The owner of every transaction, the settled transactions and
+    /// the idempotency keys are still kept in memory.
+    pub fn with_transaction_spill(
+        mut self,
+        max_resident: usize,
+        store: Box<dyn TransactionStore>,
+    ) -> Self {
+        self.spill = Some(TransactionSpill::new(max_resident, store));
+        self
+    }
+
+    /// Parks withdrawals that are rejected for insufficient funds, re-presenting them after each
+    /// later deposit by the same client. Withdrawals stay parked for the given number of records,
+    /// or for the rest of the run.
+    pub fn with_withdrawal_retries(mut self, window: Option<u64>) -> Self {
+        self.retries = Some(RetryQueue::new(window));
+        self
+    }
+
+    /// Parks dispute, resolve, chargeback, void and refund rows that arrive before the transaction
+    /// they reference, applying them once it does. Rows stay parked for the window, after which
+    /// they expire.
+    pub fn with_dispute_buffer(mut self, window: BufferWindow) -> Self {
+        self.disputes = Some(DisputeBuffer::new(window));
+        self
+    }
+
+    /// Splits the run into business days by the records' timestamps, writing each day's closing
+    /// balances once the next day starts
+    pub fn with_daily_cutover(mut self, days: DailyCutover) -> Self {
+        self.days = Some(days);
+        self
+    }
+
+    /// Exports the run as fact and dimension tables once the accounts are collected, including a
+    /// snapshot of the balances at the end of each business day
+    pub fn with_olap_export(mut self, olap: OlapExport) -> Self {
+        self.olap = Some(olap);
+        self
+    }
+
+    /// Writes the result of each input record, whether it was accepted, rejected or ignored, along
+    /// with the line it was read from
+    pub fn with_tx_results(mut self, results: TxResults) -> Self {
+        self.results = Some(results);
+        self
+    }
+
+    /// Writes an event to the audit log for every mutation of an account, with its balances
+    /// before and after it
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Sweeps disputed funds that have been held for too long to the holding account once every
+    /// record has been applied, see escheat
+    pub fn with_escheatment(
+        mut self,
+        settings: EscheatmentSettings,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        self.escheatment = Some((settings, clock));
+        self
+    }
+
+    /// The hash of the last event in the audit log, none when mutations aren't audited
+    pub fn audit_head(&self) -> Option<String> {
+        self.audit.as_ref().map(|audit| audit.head().to_string())
+    }
+
+    /// Halts processing once the run exceeds one of the breaker's limits, saving the accounts as
+    /// they were to its checkpoint
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Applies each idempotency key at most once, including the keys applied by earlier runs
+    pub fn with_idempotency_keys(mut self, keys: IdempotencyKeys) -> Self {
+        self.idempotency_keys = keys;
+        self
+    }
+
+    /// Holds operations from an admin operations file, to be applied in their own phase before or
+    /// after the records
+    pub fn with_admin_operations(
+        mut self,
+        operations: Vec<(u64, AdminOperation)>,
+        phase: AdminPhase,
+    ) -> Self {
+        self.admin_operations = operations;
+        self.admin_phase = phase;
+        self
+    }
+
+    /// Takes the admin operations that are applied in the given phase, along with the line each
+    /// was read from. There are none once they've been taken.
+    pub fn take_admin_operations(&mut self, phase: AdminPhase) -> Vec<(u64, AdminOperation)> {
+        if phase == self.admin_phase {
+            std::mem::take(&mut self.admin_operations)
+        } else {
+            vec![]
+        }
+    }
+
+    /// Applies an operator's operation to an existing account, journaling it along with the
+    /// operator that made it. A LedgerError means the operation was rejected and the account is
+    /// unchanged.
+    pub fn apply_admin(&mut self, operation: &AdminOperation) -> EngineResult<()> {
+        if !self.accounts.contains(operation.client) {
+            return Err(LedgerError::NoAccount(operation.client).into());
+        }
+
+        let account = self.accounts.get_mut(operation.client)?;
+        let held = match &account.lock_state {
+            LockState::Quarantine { held, .. } => held.len(),
+            _ => 0,
+        };
+        let before = AuditBalances::of(account);
+        operation.apply(account)?;
+        self.journal.record_operation(operation.clone())?;
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(AuditEvent::operated(operation, before, account))?;
+        }
+
+        if operation.op == AdminAction::Release {
+            self.quarantine
+                .release(operation.client, &operation.operator, held);
+        }
+
+        Ok(())
+    }
+
+    /// Applies a record to its client's account. A LedgerError means the record was rejected and
+    /// the account is unchanged, any other error means the record couldn't be journaled.
+    pub fn process(&mut self, record: &Record) -> EngineResult<()> {
+        if let Some(Err(reason)) = self.breaker.as_mut().map(|breaker| breaker.admit(record)) {
+            return Err(self.trip(reason));
+        }
+
+        // the day closes before the first record of the next one is applied
+        if let Some(days) = self.days.as_mut() {
+            days.roll(record.timestamp, &self.accounts)?;
+        }
+        if let Some(olap) = self.olap.as_mut() {
+            olap.roll(record.timestamp, &self.accounts)?;
+        }
+
+        let result = self.apply(record);
+        let rejected = matches!(result, Err(EngineError::Ledger(_)));
+        if let Some(disputes) = self.disputes.as_mut() {
+            disputes.tick(record.timestamp);
+        }
+        if let Some(days) = self.days.as_mut() {
+            days.count(rejected);
+        }
+        if let Some(olap) = self.olap.as_mut() {
+            olap.count(record, &result);
+        }
+
+        if result.is_ok() || rejected {
+            let breaker = self.breaker.as_mut();
+            if let Some(Err(reason)) = breaker.map(|breaker| breaker.count(record, rejected)) {
+                return Err(self.trip(reason));
+            }
+        }
+
+        result
+    }
+
+    /// Writes the result of a record that was processed, or rejected before it could be. Records
+    /// that failed for any other reason end the run, so they don't have a result.
+    pub fn write_result(
+        &mut self,
+        line: u64,
+        record: Option<&Record>,
+        result: Result<(), &EngineError>,
+    ) -> EngineResult<()> {
+        let Some(results) = self.results.as_mut() else {
+            return Ok(());
+        };
+
+        match (record, result) {
+            (Some(record), Ok(())) => results.write(&TxResult::applied(line, record, self.ignored)),
+            (record, Err(err)) => results.write(&TxResult::rejected(line, record, err)),
+            (None, Ok(())) => Ok(()),
+        }
+    }
+
+    /// Checks the share of records that were rejected once every record has been applied, which
+    /// trips the circuit breaker when it's too large
+    pub fn check_limits(&mut self) -> EngineResult<()> {
+        match self.breaker.as_ref().map(CircuitBreaker::check_rejects) {
+            Some(Err(reason)) => Err(self.trip(reason)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Halts the run once the circuit breaker trips, saving the accounts to its checkpoint. The
+    /// journal is flushed, so it matches the checkpoint.
+    fn trip(&mut self, reason: String) -> EngineError {
+        let checkpoint = self
+            .breaker
+            .as_ref()
+            .and_then(|breaker| breaker.checkpoint().cloned());
+        let saved = match checkpoint {
+            Some(checkpoint_path) => self.save_checkpoint(&checkpoint_path),
+            None => Ok(()),
+        };
+
+        match saved {
+            Ok(()) => SourceError::Tripped(reason).into(),
+            Err(err) => err,
+        }
+    }
+
+    /// Saves every account, including the transactions that were spilled, to the file
+    fn save_checkpoint(&mut self, checkpoint_path: &std::path::Path) -> EngineResult<()> {
+        self.journal.flush()?;
+        if let Some(spill) = self.spill.as_mut() {
+            spill.restore_all(&mut self.accounts)?;
+        }
+
+        Ok(save_state(checkpoint_path, &self.accounts.snapshot()?)?)
+    }
+
+    /// Applies each record from an async source (e.g. a network connection) as it arrives, so the
+    /// same account logic can be driven without blocking a thread while the source is waited on.
+    /// Records are applied as they are by process, with each one that's rejected or warned about
+    /// added to the report along with its position in the stream. The task yields to the runtime
+    /// every so often, so a source that's always ready doesn't starve the runtime's other tasks.
+    pub async fn process_stream(
+        &mut self,
+        records: impl Stream<Item = Record>,
+        report: &mut ExitReport,
+    ) -> EngineResult<()> {
+        let mut records = pin!(records);
+        let mut position = 0;
+        while let Some(record) = poll_fn(|cx| records.as_mut().poll_next(cx)).await {
+            position += 1;
+            report.records += 1;
+            let result = self.process(&record);
+            for warning in self.take_warnings() {
+                report.warn(position, warning);
+            }
+
+            let outcome = match result {
+                Ok(()) => self.check_invariants(position, &record),
+                Err(EngineError::Ledger(err)) => {
+                    report.reject(position, err);
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            };
+            if outcome.is_err() {
+                report.fail_at(position);
+            }
+            outcome?;
+
+            if position % STREAM_YIELD_INTERVAL == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the disputed funds that have been held for too long to the holding account, once
+    /// every record has been applied, returning what was moved. Each change is audited, like the
+    /// changes records make. There are none when escheatment isn't enabled.
+    pub fn escheat(&mut self) -> EngineResult<Vec<Escheatment>> {
+        let Some((settings, clock)) = self.escheatment.as_ref() else {
+            return Ok(vec![]);
+        };
+        if let Some(spill) = self.spill.as_mut() {
+            spill.restore_all(&mut self.accounts)?;
+        }
+
+        let mut accounts = self.accounts.snapshot()?;
+        let audit = self.audit.as_mut();
+        let escheatments = escheat_held_funds(&mut accounts, settings, clock.as_ref(), audit)?;
+        let changed = escheatments
+            .iter()
+            .flat_map(|escheatment| [escheatment.client, escheatment.holding_account]);
+        for client_id in changed {
+            if let Some(account) = accounts.get(&client_id) {
+                *self.accounts.get_mut(client_id)? = account.clone();
+            }
+        }
+
+        Ok(escheatments)
+    }
+
+    /// Closes the business day that's still open, returning the summary of every day. There are
+    /// none when daily cutover isn't enabled.
+    pub fn finish_days(&mut self) -> EngineResult<Vec<DaySummary>> {
+        match self.days.take() {
+            Some(days) => Ok(days.finish(&self.accounts)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Applies a record to its client's account, see process
+    fn apply(&mut self, record: &Record) -> EngineResult<()> {
+        // a parked row counts as applied, as it can still be applied once its transaction arrives
+        let owners = &self.owners;
+        let disputes = self.disputes.as_mut();
+        let known = |transaction_id| owners.contains(transaction_id);
+        if let Some(disputes) = disputes.filter(|disputes| disputes.should_park(record, known)) {
+            disputes.park(record);
+            self.ignored = None;
+            return Ok(());
+        }
+
+        let key = record.idempotency_key.as_deref();
+        let credit = info_span!("validate").in_scope(|| self.validate(record))?;
+        let _apply = info_span!("apply").entered();
+
+        // if the Account hasn't been seen yet, add it using Account::default()
+        let account = self.accounts.get_mut(record.client_id)?;
+        apply_sidecar_lock(account, self.config.account_flags.get(&record.client_id));
+
+        // a spilled transaction is moved back before the row referencing it is checked
+        let spill = self.spill.as_mut();
+        if let (Some(spill), true) = (spill, record.transaction_type.references_transaction()) {
+            spill.restore(record.client_id, record.transaction_id, account)?;
+        }
+
+        // rejected records still count towards how long the other accounts have been idle
+        let total_before = account.total_funds;
+        let before = AuditBalances::of(account);
+        let footprint = self
+            .results
+            .is_some()
+            .then(|| Footprint::of(record, account));
+        let mut ignored = None;
+        let warning = edge_case(record, account)
+            .filter(|(case, _)| self.config.policy.action(*case) == PolicyAction::Warn);
+        let result = check_unsettled(record, account, &self.settled)
+            .and_then(|()| process_transaction_record(record, account, &self.config));
+        let result = match (result, self.retries.as_mut()) {
+            (Ok(()), retries) => {
+                ignored = footprint.and_then(|footprint| footprint.ignored(record, account));
+                if let Some((_, warning)) = warning {
+                    self.warnings.push(warning);
+                }
+                self.journal.record(AccountEvent::new(record, account))?;
+                if let Some(audit) = self.audit.as_mut() {
+                    audit.record(AuditEvent::applied(record, before, account))?;
+                }
+                self.quarantine.observe(record, account);
+
+                // a chargeback of a transaction that isn't disputed doesn't reverse anything
+                let reversed = total_before - account.total_funds;
+                if record.transaction_type == TransactionType::Chargeback && reversed != 0.0 {
+                    self.losses.record(record.client_id, reversed);
+                }
+
+                // a deposit may have freed up enough funds for the client's parked withdrawals
+                if let (Some(retries), TransactionType::Deposit) =
+                    (retries, record.transaction_type)
+                {
+                    let (journal, audit) = (&mut self.journal, self.audit.as_mut());
+                    retries.retry(account, record.client_id, &self.config, journal, audit)?;
+                }
+                Ok(())
+            }
+            (Err(LedgerError::InsufficientFunds(..)), Some(retries))
+                if record.transaction_type == TransactionType::Withdrawal =>
+            {
+                retries.park(record);
+                Ok(())
+            }
+            (Err(err), _) => Err(err),
+        };
+
+        if let Some(retries) = self.retries.as_mut() {
+            retries.tick();
+        }
+        self.accounts.tick()?;
+
+        // a parked withdrawal counts as applied, as it can still be applied by a retry
+        result?;
+
+        // the destination of a transfer is credited once its source has been debited
+        if let Some(credit) = credit {
+            let destination = self.accounts.get_mut(credit.client_id)?;
+            apply_sidecar_lock(
+                destination,
+                self.config.account_flags.get(&credit.client_id),
+            );
+            let before = AuditBalances::of(destination);
+            process_transaction_record(&credit, destination, &self.config)?;
+            self.journal
+                .record(AccountEvent::new(&credit, destination))?;
+            if let Some(audit) = self.audit.as_mut() {
+                audit.record(AuditEvent::applied(&credit, before, destination))?;
+            }
+        }
+        if let Some(spill) = self.spill.as_mut() {
+            spill.touch(record.client_id, record.transaction_id);
+            spill.evict(&mut self.accounts)?;
+        }
+        self.idempotency_keys.insert(key)?;
+        self.owners.insert(record);
+        if record.transaction_type.creates_transaction() {
+            self.apply_parked(record.transaction_id)?;
+        }
+
+        // the rows applied once this transaction arrived have their own results
+        self.ignored = ignored;
+        Ok(())
+    }
+
+    /// Checks that a record can be applied before its account is touched, returning the side of
+    /// a transfer that's applied to its destination
+    fn validate(&mut self, record: &Record) -> EngineResult<Option<Record>> {
+        let key = record.idempotency_key.as_deref();
+        self.idempotency_keys.check(key, record.transaction_id)?;
+        self.config.check_onboarded(record.client_id)?;
+        self.config
+            .screening
+            .screen(record.client_id, record.transaction_id)?;
+        self.owners.check(record)?;
+
+        self.transfer_credit(record)
+    }
+
+    /// Checks that the destination of a transfer can be credited before either account is
+    /// changed. Returns the side of the transfer that's applied to the destination, none when the
+    /// record isn't a transfer.
+    fn transfer_credit(&mut self, record: &Record) -> EngineResult<Option<Record>> {
+        let Some(credit) = record.transfer_credit()? else {
+            return Ok(None);
+        };
+
+        self.config.check_onboarded(credit.client_id)?;
+        self.config
+            .screening
+            .screen(credit.client_id, credit.transaction_id)?;
+
+        // an unknown destination is only opened once its source has been debited, so a rejected
+        // transfer doesn't add an account
+        let mut unknown = Account::default();
+        let destination = match self.accounts.contains(credit.client_id) {
+            true => self.accounts.get_mut(credit.client_id)?,
+            false => &mut unknown,
+        };
+        apply_sidecar_lock(
+            destination,
+            self.config.account_flags.get(&credit.client_id),
+        );
+        check_destination(&credit, destination, &self.config)?;
+
+        Ok(Some(credit))
+    }
+
+    /// Applies the rows that were parked waiting for a transaction once it's arrived, in the order
+    /// they were received
+    fn apply_parked(&mut self, transaction_id: u32) -> EngineResult<()> {
+        let Some(disputes) = self.disputes.as_mut() else {
+            return Ok(());
+        };
+
+        for parked in disputes.release(transaction_id) {
+            let applied = match self.apply(&parked) {
+                Ok(()) => true,
+                Err(EngineError::Ledger(_)) => false,
+                Err(err) => return Err(err),
+            };
+            if let Some(disputes) = self.disputes.as_mut() {
+                disputes.settle(&parked, applied);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expires the withdrawals that are still parked, returning what happened to every withdrawal
+    /// that was parked. There are none when retries aren't enabled.
+    pub fn take_retry_outcomes(&mut self) -> Vec<RetryOutcome> {
+        self.retries
+            .take()
+            .map_or_else(Vec::new, RetryQueue::finish)
+    }
+
+    /// Expires the rows that are still waiting for their transaction, returning what happened to
+    /// every row that was parked. There are none when disputes aren't buffered.
+    pub fn take_buffered_disputes(&mut self) -> Vec<BufferOutcome> {
+        self.disputes
+            .take()
+            .map_or_else(Vec::new, DisputeBuffer::finish)
+    }
+
+    /// Returns every client that entered or left quarantine so far
+    pub fn take_quarantine_events(&mut self) -> Vec<QuarantineEvent> {
+        self.quarantine.take_events()
+    }
+
+    /// Checks the invariants of the accounts a record was applied to, when they're being checked.
+    /// A broken invariant is a bug rather than a bad record, so it ends the run with the line of
+    /// the record that broke it.
+    pub fn check_invariants(&mut self, line: u64, record: &Record) -> EngineResult<()> {
+        let invariants = self.config.invariants;
+        if !invariants.enabled {
+            return Ok(());
+        }
+
+        // a transfer changes its destination's balances too
+        let destination = record
+            .destination
+            .filter(|_| record.transaction_type == TransactionType::Transfer);
+        for client in [Some(record.client_id), destination].into_iter().flatten() {
+            if !self.accounts.contains(client) {
+                continue;
+            }
+
+            let account = self.accounts.get_mut(client)?;
+            if let Some(message) = account.broken_invariant(invariants.available_non_negative) {
+                return Err(SourceError::Invariant {
+                    line,
+                    client,
+                    message,
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the edge cases the engine policy warned about since they were last taken
+    pub fn take_warnings(&mut self) -> Vec<LedgerError> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// The client a deposit or withdrawal belongs to, when it's been seen
+    pub fn owner(&self, transaction_id: u32) -> Option<u16> {
+        self.owners.get(transaction_id)
+    }
+
+    /// A copy of one of a client's transactions, none when the client doesn't have an account or
+    /// the transaction isn't in it. A spilled transaction is moved back into the account first.
+    pub fn transaction(
+        &mut self,
+        client_id: u16,
+        transaction_id: u32,
+    ) -> EngineResult<Option<Transaction>> {
+        if !self.accounts.contains(client_id) {
+            return Ok(None);
+        }
+
+        let account = self.accounts.get_mut(client_id)?;
+        if let Some(spill) = self.spill.as_mut() {
+            spill.restore(client_id, transaction_id, account)?;
+        }
+
+        Ok(account
+            .successful_transactions
+            .get(&transaction_id)
+            .cloned())
+    }
+
+    /// The current output record of a client's account, if the client has been seen
+    pub fn account(&self, client_id: u16) -> EngineResult<Option<AccountRecord>> {
+        Ok(self.accounts.record(client_id)?)
+    }
+
+    /// The current output records of every account, ordered by client id
+    pub fn accounts(&self) -> EngineResult<Vec<AccountRecord>> {
+        let mut records = self.accounts.records()?;
+        records.sort_by_key(|record| record.client);
+
+        Ok(records)
+    }
+
+    /// A copy of every account, so it can be saved while the engine carries on. Transactions that
+    /// have been spilled aren't included.
+    pub fn snapshot(&self) -> EngineResult<HashMap<u16, Account>> {
+        Ok(self.accounts.snapshot()?)
+    }
+
+    /// Ensures every transaction that's been applied so far has been written to the journal. A
+    /// batched journal can't record any more afterwards, see Journal::flush.
+    pub fn flush_journal(&mut self) -> EngineResult<()> {
+        Ok(self.journal.flush()?)
+    }
+
+    /// The settings that control how transactions are applied
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Returns the funds reversed out of accounts by chargebacks so far, starting a new ledger
+    pub fn take_losses(&mut self) -> LossLedger {
+        std::mem::take(&mut self.losses)
+    }
+
+    /// Finishes processing, returning the client accounts
+    pub fn into_accounts(mut self) -> EngineResult<HashMap<u16, Account>> {
+        self.journal.flush()?;
+        if let Some(spill) = self.spill.as_mut() {
+            spill.restore_all(&mut self.accounts)?;
+        }
+
+        let accounts = self.accounts.into_accounts()?;
+        if let Some(olap) = self.olap.take() {
+            olap.finish(&accounts)?;
+        }
+        if let Some(results) = self.results.take() {
+            results.finish()?;
+        }
+        if let Some(audit) = self.audit.take() {
+            audit.finish()?;
+        }
+
+        Ok(accounts)
+    }
+}
+
+/// Triggers the relevant logic for updating a client's account, using a record (Record)
+pub fn process_transaction_record(
+    record: &Record,
+    account: &mut Account,
+    config: &EngineConfig,
+) -> LedgerResult<()> {
+    // admin transactions are only processed when they've been explicitly allowed
+    if record.transaction_type.is_admin() && !config.allow_admin_ops {
+        return Err(LedgerError::AdminOpsDisabled(record.transaction_type));
+    }
+
+    // locked accounts only permit some types of transaction, depending on why they were locked
+    if !account
+        .lock_state
+        .permits(record.transaction_type, config.frozen_account_policy)
+    {
+        return Err(LedgerError::AccountLocked(
+            record.client_id,
+            record.transaction_type,
+            account.lock_state.name(),
+        ));
+    }
+
+    // amounts can't be more precise than the currency's minor unit
+    if let (Some(currency), Some(amount)) = (&config.currency, record.amount) {
+        if currency.is_too_precise(amount) {
+            return Err(LedgerError::InvalidPrecision(record.transaction_id, amount));
+        }
+    }
+
+    // a negative deposit would take funds away, and a negative withdrawal or transfer add them
+    if let (true, Some(amount)) = (record.transaction_type.creates_transaction(), record.amount) {
+        if amount <= 0.0 || amount.is_nan() {
+            return Err(LedgerError::NonPositiveAmount(
+                record.transaction_id,
+                amount,
+            ));
+        }
+    }
+
+    check_referenced_amount(record, account, config.dispute_amount_policy)?;
+
+    // transactions settle at the cutoff, so voids after it (or without a timestamp) are too late
+    let cutoff = config.settlement_cutoff;
+    if let (TransactionType::Void, Some(cutoff)) = (record.transaction_type, cutoff) {
+        if record.timestamp.is_none_or(|timestamp| timestamp >= cutoff) {
+            return Err(LedgerError::Settled(record.transaction_id));
+        }
+    }
+
+    // disputes can only be made within the window, when both rows have a timestamp to measure it
+    let window = config.dispute_window;
+    if let (TransactionType::Dispute, Some(window)) = (record.transaction_type, window) {
+        let occurred_at = account
+            .successful_transactions
+            .get(&record.transaction_id)
+            .and_then(|transaction| transaction.timestamp);
+        if let (Some(occurred_at), Some(timestamp)) = (occurred_at, record.timestamp) {
+            let elapsed = timestamp.saturating_sub(occurred_at);
+            if elapsed > window {
+                let transaction_id = record.transaction_id;
+                return Err(LedgerError::DisputeWindowExpired(
+                    transaction_id,
+                    elapsed,
+                    window,
+                ));
+            }
+        }
+    }
+
+    // edge cases that specs disagree on are handled as the policy says
+    if let Some((case, err)) = edge_case(record, account) {
+        match config.policy.action(case) {
+            PolicyAction::Apply => {}
+            PolicyAction::Ignore | PolicyAction::Warn => {
+                debug!(?case, "ignored, as the engine policy says: {}", err);
+                return Ok(());
+            }
+            PolicyAction::Error => return Err(err),
+        }
+    }
+
+    let footprint = Footprint::of(record, account);
+    let flags = config.account_flags.get(&record.client_id);
+    match record.transaction_type {
+        TransactionType::Deposit => {
+            // the amount field is optional, only process it when it's been defined
+            if let Some(amount) = record.amount {
+                account.deposit(amount, record.transaction_id);
+                account.occurred_at(record.transaction_id, record.timestamp);
+
+                // deposits to an account under review are held until they're resolved, and deposits
+                // to a quarantined account until it's released
+                if let LockState::Quarantine { held, .. } = &mut account.lock_state {
+                    held.push(record.transaction_id);
+                    account.dispute(record.transaction_id);
+                } else if flags.is_some_and(|flags| flags.under_review) {
+                    account.dispute(record.transaction_id);
+                }
+            }
+        }
+        TransactionType::Withdrawal => {
+            // the amount field is optional, only process it when it's been defined
+            if let Some(amount) = record.amount {
+                account.withdraw_with_policy(
+                    amount,
+                    record.transaction_id,
+                    config.withdrawal_policy,
+                )?;
+                account.occurred_at(record.transaction_id, record.timestamp);
+            }
+        }
+        TransactionType::Dispute => {
+            match (config.dispute_amount_policy, record.amount) {
+                (DisputeAmountPolicy::Partial, Some(amount)) => {
+                    account.dispute_partial(record.transaction_id, amount)?
+                }
+                _ => account.dispute(record.transaction_id),
+            }
+            account.hold_since(record.transaction_id, record.timestamp);
+        }
+        TransactionType::Resolve => account.resolve(record.transaction_id)?,
+        TransactionType::Chargeback => {
+            let lock_state = account.lock_state.clone();
+            account.chargeback(record.transaction_id)?;
+
+            // some accounts must stay usable after a chargeback, unless they were already locked
+            if flags.is_some_and(|flags| flags.do_not_lock) {
+                account.lock_state = lock_state;
+            }
+        }
+        TransactionType::Void => account.void(record.transaction_id)?,
+        TransactionType::Refund => account.refund(record.transaction_id)?,
+        TransactionType::Escheated => return Err(LedgerError::SweepOnly(record.transaction_id)),
+        TransactionType::Adjustment => {
+            // every adjustment must explain why it was made
+            if record.reason.is_none() {
+                return Err(LedgerError::MissingReason(record.transaction_id));
+            }
+
+            if let Some(amount) = record.amount {
+                account.adjust(amount)?;
+            }
+        }
+        TransactionType::Unlock => {
+            // like adjustments, every unlock must explain why it was made
+            if record.reason.is_none() {
+                return Err(LedgerError::MissingReason(record.transaction_id));
+            }
+
+            account.lock_state = LockState::Unlocked;
+        }
+        TransactionType::Transfer => {
+            // a transfer is applied to the source's account, then to the destination's
+            if let Some(amount) = record.amount {
+                if record.is_transfer_credit() {
+                    account.transfer_in(amount);
+                } else {
+                    account.transfer_out(
+                        amount,
+                        record.transaction_id,
+                        config.withdrawal_policy,
+                    )?;
+                }
+            }
+        }
+    }
+
+    // only records that changed the account count towards its activity
+    if footprint.ignored(record, account).is_none() {
+        account.record_activity(record.transaction_id);
+    }
+
+    Ok(())
+}
+
+/// Moves each resolve in a batch of records that share a timestamp ahead of the withdrawals for
+/// the same client that it directly follows, keeping everything else in the order it was received.
+/// Records are paired with their line numbers, so rejections can still be reported against them.
+pub fn order_resolves_first(batch: &mut [(u64, Record)]) {
+    for index in 0..batch.len() {
+        let resolve = &batch[index].1;
+        if resolve.transaction_type != TransactionType::Resolve {
+            continue;
+        }
+
+        // the records of other clients don't affect this client's account, so they're skipped over
+        let client_id = resolve.client_id;
+        let mut target = index;
+        for (earlier, (_, record)) in batch[..index].iter().enumerate().rev() {
+            if record.client_id != client_id {
+                continue;
+            }
+            if record.transaction_type != TransactionType::Withdrawal {
+                break;
+            }
+            target = earlier;
+        }
+
+        batch[target..=index].rotate_right(1);
+    }
+}
+
+/// Puts the lock from the admin sidecar file on an account, unless it's already locked
+pub(crate) fn apply_sidecar_lock(account: &mut Account, flags: Option<&AccountFlags>) {
+    if let Some(lock) = flags.and_then(|flags| flags.lock.as_ref()) {
+        if !account.lock_state.is_locked() {
+            account.lock_state = lock.clone();
+        }
+    }
+}
+
+/// The transactions of the accounts loaded at the start of a run, which settled in an earlier run
+pub(crate) fn settled_transactions(accounts: &HashMap<u16, Account>) -> HashSet<(u16, u32)> {
+    accounts
+        .iter()
+        .flat_map(|(client_id, account)| {
+            account
+                .successful_transactions
+                .keys()
+                .map(|transaction_id| (*client_id, *transaction_id))
+        })
+        .collect()
+}
+
+/// Errors when the destination of a transfer is locked, so it can't be credited
+pub(crate) fn check_destination(
+    credit: &Record,
+    account: &Account,
+    config: &EngineConfig,
+) -> LedgerResult<()> {
+    let policy = config.frozen_account_policy;
+    if !account
+        .lock_state
+        .permits(TransactionType::Transfer, policy)
+    {
+        return Err(LedgerError::AccountLocked(
+            credit.client_id,
+            TransactionType::Transfer,
+            account.lock_state.name(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Errors when a void references a transaction that settled in an earlier run. Only the
+/// transactions applied during the same run can be voided.
+pub(crate) fn check_unsettled(
+    record: &Record,
+    account: &Account,
+    settled: &HashSet<(u16, u32)>,
+) -> LedgerResult<()> {
+    let key = (record.client_id, record.transaction_id);
+    if record.transaction_type == TransactionType::Void
+        && account
+            .successful_transactions
+            .contains_key(&record.transaction_id)
+        && settled.contains(&key)
+    {
+        return Err(LedgerError::Settled(record.transaction_id));
+    }
+
+    Ok(())
+}
+
+/// The edge case the record is in, when it's in one of those the engine policy configures, along
+/// with the error it's rejected with when the policy says it should be
+fn edge_case(record: &Record, account: &Account) -> Option<(EdgeCase, LedgerError)> {
+    let transaction_id = record.transaction_id;
+    let transaction = account.successful_transactions.get(&transaction_id);
+
+    match (record.transaction_type, transaction) {
+        (TransactionType::Deposit, _) if account.lock_state.is_locked() => Some((
+            EdgeCase::LockedDeposit,
+            LedgerError::AccountLocked(
+                record.client_id,
+                TransactionType::Deposit,
+                account.lock_state.name(),
+            ),
+        )),
+        (transaction_type, None) if transaction_type.references_transaction() => Some((
+            EdgeCase::UnknownTransaction,
+            LedgerError::UnknownTransaction(transaction_id),
+        )),
+        (TransactionType::Dispute, Some(transaction)) if transaction.is_withdrawal() => Some((
+            EdgeCase::WithdrawalDispute,
+            LedgerError::WithdrawalDispute(transaction_id),
+        )),
+        (TransactionType::Resolve | TransactionType::Chargeback, Some(transaction))
+            if transaction.current_state != TransactionType::Dispute =>
+        {
+            Some((
+                EdgeCase::NotDisputed,
+                LedgerError::NotDisputed(transaction_id),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Errors when the policy requires the amount of a row that references a transaction to match the
+/// transaction's amount, and it doesn't. Rows referencing unknown transactions are left alone.
+fn check_referenced_amount(
+    record: &Record,
+    account: &Account,
+    policy: DisputeAmountPolicy,
+) -> LedgerResult<()> {
+    if policy != DisputeAmountPolicy::Validate || !record.transaction_type.references_transaction()
+    {
+        return Ok(());
+    }
+
+    let referenced = account.successful_transactions.get(&record.transaction_id);
+    if let (Some(amount), Some(transaction)) = (record.amount, referenced) {
+        // amounts only have a precision of up to four places past the decimal
+        if round(amount as f64, 4) != round(transaction.amount as f64, 4) {
+            return Err(LedgerError::AmountMismatch(
+                record.transaction_id,
+                amount,
+                transaction.amount,
+            ));
+        }
+    }
+
+    Ok(())
+}
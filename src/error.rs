@@ -0,0 +1,831 @@
+use crate::alerts::Alert;
+use crate::cutover::DaySummary;
+use crate::escheat::Escheatment;
+use crate::journal::SinkSummary;
+use crate::losses::LossLedger;
+use crate::mapper::TransactionType;
+use crate::merge::SourceCursor;
+use crate::quarantine::QuarantineEvent;
+use crate::reconcile::Postings;
+use crate::redact::Redaction;
+use crate::reorder::BufferOutcome;
+use crate::retry::RetryOutcome;
+use crate::screening::HoldReason;
+use crate::stats::{RecordTally, RunStats};
+use std::fmt;
+use std::path::Path;
+use thiserror::Error;
+
+/// A generic result type for EngineError variants
+pub type EngineResult<T> = Result<T, EngineError>;
+
+/// A generic result type for CliError variants
+pub type CliResult<T> = Result<T, CliError>;
+
+/// A generic result type for SourceError variants
+pub type SourceResult<T> = Result<T, SourceError>;
+
+/// A generic result type for LedgerError variants
+pub type LedgerResult<T> = Result<T, LedgerError>;
+
+/// Top level error for the engine, layered by where in the pipeline the failure happened
+#[derive(Debug, Error, PartialEq)]
+pub enum EngineError {
+    /// Something was wrong with the command line arguments
+    #[error(transparent)]
+    Cli(#[from] CliError),
+
+    /// The transaction data couldn't be read or parsed
+    #[error(transparent)]
+    Source(#[from] SourceError),
+
+    /// A transaction couldn't be applied to an account
+    #[error(transparent)]
+    Ledger(#[from] LedgerError),
+}
+
+impl EngineError {
+    /// The stable numeric code of the underlying error, also used as the process exit code
+    pub fn code(&self) -> i32 {
+        match self {
+            EngineError::Cli(err) => err.code(),
+            EngineError::Source(err) => err.code(),
+            EngineError::Ledger(err) => err.code(),
+        }
+    }
+
+    /// The name of the underlying error's variant, e.g. InsufficientFunds
+    pub fn variant_name(&self) -> String {
+        match self {
+            EngineError::Cli(err) => variant_name(err),
+            EngineError::Source(err) => variant_name(err),
+            EngineError::Ledger(err) => variant_name(err),
+        }
+    }
+}
+
+/// The name of an error's variant, e.g. InsufficientFunds, taken from its Debug representation
+pub(crate) fn variant_name(err: &impl fmt::Debug) -> String {
+    let debug = format!("{:?}", err);
+
+    debug
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Errors caused by the command line arguments (codes 10-19, then 100-119)
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum CliError {
+    /// A file path to read transaction data from, wasn't provided
+    #[error("An argument for file path must be provided, like so: cargo run -- some_file_path")]
+    MissingArg,
+
+    /// The file does not have a csv extension (.csv)
+    #[error("The file must have a csv extension")]
+    InvalidExtension,
+
+    /// The file doesn't exist
+    #[error("Incorrect file path argument provided: {0}")]
+    NonExistentFile(String),
+
+    /// The format passed to --force-format or --format isn't supported
+    #[error("Unsupported format: {0}")]
+    UnknownFormat(String),
+
+    /// The format of the file couldn't be detected from its contents
+    #[error("Couldn't detect the format of {0}, use --force-format csv to read it anyway")]
+    UndetectedFormat(String),
+
+    /// A flag that the engine doesn't recognise was provided
+    #[error("Unknown flag: {0}")]
+    UnknownFlag(String),
+
+    /// A flag that requires a value was provided without one
+    #[error("A value must be provided for the flag: {0}")]
+    MissingFlagValue(String),
+
+    /// More positional arguments were provided than the subcommand takes
+    #[error("Unexpected argument: {0}")]
+    UnexpectedArg(String),
+
+    /// The value passed to a policy flag isn't one of its options
+    #[error("Unknown policy: {0}")]
+    UnknownPolicy(String),
+
+    /// The value passed to a flag isn't valid for it (e.g. a non-numeric size)
+    #[error("Invalid value for {0}: {1}")]
+    InvalidValue(String, String),
+
+    /// The value passed to --sink isn't one of the supported sinks
+    #[error("Unknown sink: {0}")]
+    UnknownSink(String),
+
+    /// The sink is supported, but this build wasn't compiled with the feature it needs
+    #[error("The {0} sink isn't available, rebuild with --features {0}")]
+    UnavailableSink(String),
+
+    /// The currency passed to --currency isn't in the ISO 4217 table or the overrides
+    #[error("Unknown currency: {0}, provide its minor unit with --minor-units {0}=2")]
+    UnknownCurrency(String),
+
+    /// A subcommand was run without a flag it needs
+    #[error("The {0} flag must be provided")]
+    MissingFlag(String),
+
+    /// The format of the file was detected, but the engine can't read it
+    #[error("{1} is a {0} file, which can't be read, convert it to csv or jsonl first")]
+    UnreadableFormat(String, String),
+
+    /// A subcommand that writes to a file was run without an output path, along with the
+    /// subcommand's usage
+    #[error("An output file path must be provided, like so: {0}")]
+    MissingOutputPath(String),
+
+    /// The command line couldn't be parsed for any other reason, run with --help for the usage
+    #[error("Invalid arguments: {0}, run with --help for the usage")]
+    Usage(String),
+
+    /// The value passed to --store isn't one of the supported stores
+    #[error("Unknown store: {0}, use memory or sqlite://path.db")]
+    UnknownStore(String),
+
+    /// The store is supported, but this build wasn't compiled with the feature it needs
+    #[error("The {0} store isn't available, rebuild with --features {0}")]
+    UnavailableStore(String),
+
+    /// The engine policy file couldn't be read, or one of its edge cases isn't valid
+    #[error("Invalid engine policy {0}: {1}")]
+    InvalidPolicy(String, String),
+
+    /// The column mapping file couldn't be read, or one of its columns isn't valid
+    #[error("Invalid column mapping {0}: {1}")]
+    InvalidColumnMapping(String, String),
+
+    /// Spans can't be exported, as this build wasn't compiled with the otel feature
+    #[error("OTLP export isn't available, rebuild with --features otel")]
+    UnavailableExporter,
+}
+
+impl CliError {
+    /// The stable numeric code for the error
+    pub fn code(&self) -> i32 {
+        match self {
+            CliError::MissingArg => 10,
+            CliError::InvalidExtension => 11,
+            CliError::NonExistentFile(_) => 12,
+            CliError::UnknownFormat(_) => 13,
+            CliError::UndetectedFormat(_) => 14,
+            CliError::UnknownFlag(_) => 15,
+            CliError::MissingFlagValue(_) => 16,
+            CliError::UnexpectedArg(_) => 17,
+            CliError::MissingOutputPath(_) => 18,
+            CliError::UnknownPolicy(_) => 19,
+            CliError::InvalidValue(..) => 100,
+            CliError::UnknownSink(_) => 101,
+            CliError::UnavailableSink(_) => 102,
+            CliError::UnknownCurrency(_) => 103,
+            CliError::MissingFlag(_) => 104,
+            CliError::UnreadableFormat(..) => 105,
+            CliError::Usage(_) => 106,
+            CliError::UnknownStore(_) => 107,
+            CliError::UnavailableStore(_) => 108,
+            CliError::InvalidPolicy(..) => 109,
+            CliError::InvalidColumnMapping(..) => 110,
+            CliError::UnavailableExporter => 111,
+        }
+    }
+}
+
+/// Errors caused while reading transaction data (codes 20-29, then 120-139)
+#[derive(Debug, Error, PartialEq)]
+pub enum SourceError {
+    /// The file couldn't be opened or read
+    #[error("Failed to read transaction data: {0}")]
+    Io(String),
+
+    /// A row couldn't be parsed into a Record
+    #[error("Malformed record on line {line}: {message}")]
+    Parse { line: u64, message: String },
+
+    /// Account state couldn't be loaded from, or saved to, a file
+    #[error("Failed to load or save state using {0}: {1}")]
+    State(String, String),
+
+    /// Another run holds the lock on a state file, and waiting wasn't allowed
+    #[error("State file {0} is locked by another run")]
+    Locked(String),
+
+    /// A runbook couldn't be read, or one of its stages isn't valid
+    #[error("Invalid runbook {0}: {1}")]
+    Runbook(String, String),
+
+    /// State couldn't be handed over to, or taken over from, another engine process
+    #[error("Failed to hand over state between engine processes: {0}")]
+    Handover(String),
+
+    /// The run exceeded one of its safety limits, so the circuit breaker halted it
+    #[error("Circuit breaker tripped: {0}")]
+    Tripped(String),
+
+    /// Linting found problems in a file of transactions, the number of them
+    #[error("Found {0} problems in the transaction file")]
+    Lint(usize),
+
+    /// An account's balances broke an invariant after a record was applied to it
+    #[error("Invariant broken by the record on line {line}, for client {client}: {message}")]
+    Invariant {
+        line: u64,
+        client: u16,
+        message: String,
+    },
+
+    /// A watched drop directory, or the archive files are moved to, couldn't be read or written
+    #[error("Failed to watch {0}: {1}")]
+    Watch(String, String),
+
+    /// The balances the run closed with don't match what its records posted, the number of
+    /// balances that don't
+    #[error("Found {0} discrepancies reconciling the accounts")]
+    Unreconciled(usize),
+}
+
+impl SourceError {
+    /// The stable numeric code for the error
+    pub fn code(&self) -> i32 {
+        match self {
+            SourceError::Io(_) => 20,
+            SourceError::Parse { .. } => 21,
+            SourceError::State(..) => 22,
+            SourceError::Locked(_) => 23,
+            SourceError::Runbook(..) => 24,
+            SourceError::Handover(_) => 25,
+            SourceError::Tripped(_) => 26,
+            SourceError::Lint(_) => 27,
+            SourceError::Invariant { .. } => 28,
+            SourceError::Watch(..) => 29,
+            SourceError::Unreconciled(_) => 120,
+        }
+    }
+}
+
+impl From<csv::Error> for SourceError {
+    fn from(err: csv::Error) -> Self {
+        // errors with a position happened while parsing a row, the rest are io related
+        match err.position() {
+            Some(position) => SourceError::Parse {
+                line: position.line(),
+                message: err.to_string(),
+            },
+            None => SourceError::Io(err.to_string()),
+        }
+    }
+}
+
+/// Errors caused while applying a transaction to an account (codes 30-39, then 140-159)
+#[derive(Debug, Error, PartialEq)]
+pub enum LedgerError {
+    /// Withdrawal amount is bigger than available funds
+    #[error("Failed withdrawal, amount: {0} is greater than available funds: {1}")]
+    InsufficientFunds(f32, f32),
+
+    /// An admin transaction was provided without --allow-admin-ops
+    #[error("{0:?} transactions require the --allow-admin-ops flag")]
+    AdminOpsDisabled(TransactionType),
+
+    /// An admin transaction was provided without a reason code
+    #[error("Transaction {0} must include a reason code")]
+    MissingReason(u32),
+
+    /// A withdrawal was attempted while the account has an open dispute
+    #[error("Failed withdrawal {0}, the account has an open dispute")]
+    OpenDispute(u32),
+
+    /// A transaction was provided for a client that hasn't been onboarded, while onboarding is closed
+    #[error("Client {0} hasn't been onboarded")]
+    UnknownClient(u16),
+
+    /// The amount of a row that references a transaction doesn't match the transaction's amount
+    #[error("Transaction {0} has an amount of {2}, but the row referencing it has {1}")]
+    AmountMismatch(u32, f32, f32),
+
+    /// A partial dispute's amount isn't positive, or is more than the disputed transaction's amount
+    #[error("Invalid amount {1} for a partial dispute of transaction {0}")]
+    InvalidDisputeAmount(u32, f32),
+
+    /// An amount has more decimal places than the currency's minor unit allows
+    #[error("Transaction {0} has an amount of {1}, which is too precise for the currency")]
+    InvalidPrecision(u32, f32),
+
+    /// The client is in a restricted country or on the denylist, so the transaction is held for
+    /// compliance review
+    #[error("Transaction {1} for client {0} is held for compliance review, {2}")]
+    ComplianceHold(u16, u32, HoldReason),
+
+    /// A void referenced a transaction that has already settled
+    #[error("Transaction {0} has settled, so it can't be voided")]
+    Settled(u32),
+
+    /// The account is locked, and its lock doesn't permit the type of transaction
+    #[error("{1:?} transactions aren't permitted for client {0}, the account has a {2}")]
+    AccountLocked(u16, TransactionType, &'static str),
+
+    /// A row tried to escheat a transaction, which only the escheatment sweep can do
+    #[error("Transaction {0} can only be escheated by the escheatment sweep")]
+    SweepOnly(u32),
+
+    /// The record's idempotency key has already been applied, in this run or an earlier one
+    #[error("Transaction {1} reuses idempotency key {0}, which has already been applied")]
+    DuplicateKey(String, u32),
+
+    /// Releasing the disputed funds would leave the account with negative held funds
+    #[error("Transaction {0} would release {1} of held funds, but the account only holds {2}")]
+    HeldFundsUnderflow(u32, f32, f32),
+
+    /// A row referenced a transaction that belongs to a different client
+    #[error("Transaction {0} belongs to client {2}, not client {1}")]
+    ClientMismatch(u32, u16, u16),
+
+    /// A deposit or withdrawal reused the id of a transaction that's already been seen
+    #[error("Transaction {0} has already been seen, transaction ids must be unique")]
+    DuplicateTransaction(u32),
+
+    /// An admin operation was made on a client that doesn't have an account
+    #[error("Client {0} doesn't have an account")]
+    NoAccount(u16),
+
+    /// A withdrawal was for more than the limit an operator set on the account
+    #[error("Failed withdrawal {0}, amount: {1} is over the account's limit of {2}")]
+    OverLimit(u32, f32, f32),
+
+    /// A force-resolve, or a resolve or chargeback under the engine policy, named a transaction
+    /// that isn't being disputed
+    #[error("Transaction {0} isn't being disputed, so it can't be resolved or charged back")]
+    NotDisputed(u32),
+
+    /// A release was made on a client that isn't quarantined
+    #[error("Client {0} isn't quarantined, so it can't be released")]
+    NotQuarantined(u16),
+
+    /// A dispute was made after the dispute window of the transaction it references had closed
+    #[error("Failed dispute of transaction {0}, made {1}s after it, outside the {2}s window")]
+    DisputeWindowExpired(u32, u64, u64),
+
+    /// A transfer didn't name a destination client, or named the client it was from
+    #[error("Transfer {0} must name a destination client other than its own")]
+    InvalidDestination(u32),
+
+    /// A refund referenced a transaction that isn't a withdrawal, or one that's already been
+    /// disputed, voided or refunded
+    #[error("Transaction {0} can't be refunded, its state is {}", .1.name())]
+    NotRefundable(u32, TransactionType),
+
+    /// A deposit, withdrawal or transfer had an amount of zero or less, which would move funds the
+    /// wrong way or not at all
+    #[error("Transaction {0} has an amount of {1}, amounts must be greater than zero")]
+    NonPositiveAmount(u32, f32),
+
+    /// A row referenced a transaction that isn't in the client's account, under the engine policy
+    #[error("Transaction {0} isn't in the client's account")]
+    UnknownTransaction(u32),
+
+    /// A dispute referenced a withdrawal, under the engine policy
+    #[error("Transaction {0} is a withdrawal, which can't be disputed")]
+    WithdrawalDispute(u32),
+
+    /// The transaction referenced by the row can't move from its current state to the row's, e.g.
+    /// a void of a transaction that's being disputed or has already been voided
+    #[error("Transaction {0} can't go from {} to {}", .1.name(), .2.name())]
+    InvalidTransition(u32, TransactionType, TransactionType),
+}
+
+impl LedgerError {
+    /// The stable numeric code for the error
+    pub fn code(&self) -> i32 {
+        match self {
+            LedgerError::InsufficientFunds(..) => 30,
+            LedgerError::AdminOpsDisabled(_) => 31,
+            LedgerError::MissingReason(_) => 32,
+            LedgerError::OpenDispute(_) => 33,
+            LedgerError::UnknownClient(_) => 34,
+            LedgerError::AmountMismatch(..) => 35,
+            LedgerError::InvalidDisputeAmount(..) => 36,
+            LedgerError::InvalidPrecision(..) => 37,
+            LedgerError::ComplianceHold(..) => 38,
+            LedgerError::Settled(_) => 39,
+            LedgerError::AccountLocked(..) => 140,
+            LedgerError::SweepOnly(_) => 141,
+            LedgerError::DuplicateKey(..) => 142,
+            LedgerError::HeldFundsUnderflow(..) => 143,
+            LedgerError::ClientMismatch(..) => 144,
+            LedgerError::DuplicateTransaction(_) => 145,
+            LedgerError::NoAccount(_) => 146,
+            LedgerError::OverLimit(..) => 147,
+            LedgerError::NotDisputed(_) => 148,
+            LedgerError::NotQuarantined(_) => 149,
+            LedgerError::DisputeWindowExpired(..) => 150,
+            LedgerError::InvalidDestination(_) => 151,
+            LedgerError::NotRefundable(..) => 152,
+            LedgerError::NonPositiveAmount(..) => 153,
+            LedgerError::UnknownTransaction(_) => 154,
+            LedgerError::WithdrawalDispute(_) => 155,
+            LedgerError::InvalidTransition(..) => 156,
+        }
+    }
+}
+
+/// A record that was rejected, or warned about, along with the file and line it was read from
+#[derive(Debug, PartialEq)]
+pub struct Rejection {
+    /// The line of the file that the record was read from
+    pub line: u64,
+
+    /// The file the record was read from, when it was read from one
+    pub file: Option<String>,
+
+    /// The reason the record was rejected, or warned about
+    pub error: EngineError,
+}
+
+impl Rejection {
+    /// Where the record was read from, e.g. `line 3 of transactions.csv`
+    pub fn location(&self) -> String {
+        location(self.file.as_deref(), self.line)
+    }
+}
+
+/// Aggregates every error raised during a run, so they can be reported once execution has finished
+#[derive(Debug, Default)]
+pub struct ExitReport {
+    /// The number of records that were read
+    pub records: u64,
+
+    /// Records that were skipped because they couldn't be applied
+    pub rejections: Vec<Rejection>,
+
+    /// Records in an edge case the engine policy warns about, which were accepted without
+    /// changing their account
+    pub warnings: Vec<Rejection>,
+
+    /// The error that terminated the run early, if there was one
+    pub fatal: Option<EngineError>,
+
+    /// The file and line of the record that was being applied when the run was terminated, when
+    /// a record terminated it
+    pub fatal_at: Option<(Option<String>, u64)>,
+
+    /// The files the records were read from, in the order they were provided
+    pub inputs: Vec<String>,
+
+    /// Which of the inputs the record being applied was read from
+    pub cursor: SourceCursor,
+
+    /// How the batched journal kept up with the records being applied, when it was enabled
+    pub journal: Option<SinkSummary>,
+
+    /// Accounts that broke one of the alert rules
+    pub alerts: Vec<Alert>,
+
+    /// What happened to the withdrawals that were parked for retrying
+    pub retries: Vec<RetryOutcome>,
+
+    /// What happened to the rows that were parked waiting for the transaction they reference
+    pub buffered: Vec<BufferOutcome>,
+
+    /// The funds reversed out of accounts by chargebacks
+    pub losses: LossLedger,
+
+    /// The activity and closing balances of each business day, when daily cutover was enabled
+    pub days: Vec<DaySummary>,
+
+    /// The clients that entered or left quarantine
+    pub quarantine: Vec<QuarantineEvent>,
+
+    /// The records that were read, tallied by type
+    pub tally: RecordTally,
+
+    /// The aggregate figures of the run, when they're reported along with the rejections
+    pub stats: Option<RunStats>,
+
+    /// The money that moved through the accounts, when the run is reconciled
+    pub postings: Option<Postings>,
+
+    /// The disputed funds that were moved to the holding account once the records were applied
+    pub escheatments: Vec<Escheatment>,
+
+    /// The hash of the last event in the audit log, when mutations are audited
+    pub audit_head: Option<String>,
+
+    /// Whether a rejected record fails the run, with the code of the first one
+    pub strict: bool,
+
+    /// Whether only the fatal error is reported
+    pub quiet: bool,
+
+    /// The fields that are redacted from the report and the rejections file
+    pub redaction: Redaction,
+}
+
+impl ExitReport {
+    /// Adds a rejected record, read from the line of the current input, to the report
+    pub fn reject(&mut self, line: u64, error: impl Into<EngineError>) {
+        let file = self.input();
+        self.reject_in(file, line, error);
+    }
+
+    /// Adds a rejected record, read from the line of a file other than the inputs (e.g. the admin
+    /// operations file), to the report
+    pub fn reject_in(&mut self, file: Option<String>, line: u64, error: impl Into<EngineError>) {
+        self.rejections.push(Rejection {
+            line,
+            file,
+            error: error.into(),
+        });
+    }
+
+    /// Adds a record the engine policy warns about to the report
+    pub fn warn(&mut self, line: u64, error: impl Into<EngineError>) {
+        self.warnings.push(Rejection {
+            line,
+            file: self.input(),
+            error: error.into(),
+        });
+    }
+
+    /// Reads the records from a single file
+    pub fn read_from(&mut self, file_path: &Path) {
+        self.read_from_all(
+            vec![file_path.display().to_string()],
+            SourceCursor::default(),
+        );
+    }
+
+    /// Reads the records from several files, the cursor tracking which one the record being
+    /// applied was read from
+    pub fn read_from_all(&mut self, inputs: Vec<String>, cursor: SourceCursor) {
+        self.inputs = inputs;
+        self.cursor = cursor;
+    }
+
+    /// Records that the record on the line of the current input terminated the run
+    pub fn fail_at(&mut self, line: u64) {
+        self.fatal_at = Some((self.input(), line));
+    }
+
+    /// The file the record being applied was read from, when records are read from files
+    fn input(&self) -> Option<String> {
+        self.inputs.get(self.cursor.get()).cloned()
+    }
+
+    /// Where the record that terminated the run was read from, e.g. ` at line 3 of a.csv`, or
+    /// nothing when a record didn't terminate it
+    fn fatal_location(&self) -> String {
+        match &self.fatal_at {
+            Some((file, line)) => format!(" at {}", location(file.as_deref(), *line)),
+            None => String::new(),
+        }
+    }
+
+    /// The number of rows that were rejected because they couldn't be parsed
+    pub fn malformed(&self) -> usize {
+        self.rejections
+            .iter()
+            .filter(|rejection| {
+                matches!(
+                    rejection.error,
+                    EngineError::Source(SourceError::Parse { .. })
+                )
+            })
+            .count()
+    }
+
+    /// Writes every rejected record to a csv, with the file and line it was read from and why it
+    /// was rejected
+    pub fn write_rejections(&self, file_path: impl AsRef<Path>) -> SourceResult<()> {
+        let mut writer = csv::Writer::from_path(file_path).map_err(SourceError::from)?;
+        writer
+            .write_record(["file", "line", "code", "error"])
+            .map_err(SourceError::from)?;
+        for rejection in &self.rejections {
+            writer
+                .write_record([
+                    rejection.file.clone().unwrap_or_default(),
+                    rejection.line.to_string(),
+                    rejection.error.code().to_string(),
+                    self.redaction.error(&rejection.error),
+                ])
+                .map_err(SourceError::from)?;
+        }
+
+        writer
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))
+    }
+
+    /// The code the process should exit with. Rejected records don't fail the run, only fatal errors
+    /// do, unless the run is strict
+    pub fn exit_code(&self) -> i32 {
+        match (&self.fatal, self.rejections.first()) {
+            (Some(err), _) => err.code(),
+            (None, Some(rejection)) if self.strict => rejection.error.code(),
+            (None, _) => 0,
+        }
+    }
+}
+
+impl fmt::Display for ExitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // a quiet run only reports the error that ended it
+        if self.quiet {
+            return match &self.fatal {
+                Some(err) => writeln!(
+                    f,
+                    "Error executing run{}! [{}] {}",
+                    self.fatal_location(),
+                    err.code(),
+                    self.redaction.error(err)
+                ),
+                None => Ok(()),
+            };
+        }
+
+        for rejection in &self.rejections {
+            writeln!(
+                f,
+                "Rejected record on {} [{}]: {}",
+                rejection.location(),
+                rejection.error.code(),
+                self.redaction.error(&rejection.error)
+            )?;
+        }
+
+        for warning in &self.warnings {
+            writeln!(
+                f,
+                "Warning for record on {} [{}]: {}",
+                warning.location(),
+                warning.error.code(),
+                self.redaction.error(&warning.error)
+            )?;
+        }
+
+        let malformed = self.malformed();
+        if malformed > 0 {
+            writeln!(f, "Skipped {} malformed records", malformed)?;
+        }
+
+        for retry in &self.retries {
+            writeln!(f, "Retried {}", retry)?;
+        }
+
+        for buffered in &self.buffered {
+            writeln!(f, "Buffered {}", buffered)?;
+        }
+
+        for alert in &self.alerts {
+            writeln!(f, "Alert: {}", self.redaction.alert(alert))?;
+        }
+
+        for event in &self.quarantine {
+            writeln!(f, "Quarantine: {}", self.redaction.quarantine(event))?;
+        }
+
+        if !self.losses.is_empty() {
+            writeln!(
+                f,
+                "Chargeback losses: {}",
+                self.redaction.losses(&self.losses)
+            )?;
+        }
+
+        if let Some(journal) = &self.journal {
+            writeln!(f, "Journal: {}", journal)?;
+        }
+
+        if let Some(head) = &self.audit_head {
+            writeln!(f, "Audit chain head: {}", head)?;
+        }
+
+        if let Some(stats) = &self.stats {
+            writeln!(f, "Stats: {}", self.redaction.stats(stats))?;
+        }
+
+        if let Some(err) = &self.fatal {
+            writeln!(
+                f,
+                "Error executing run{}! [{}] {}",
+                self.fatal_location(),
+                err.code(),
+                self.redaction.error(err)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Describes the line of a file a record was read from, the line alone when it wasn't read from a
+/// file
+fn location(file: Option<&str>, line: u64) -> String {
+    match file {
+        Some(file) => format!("line {} of {}", line, file),
+        None => format!("line {}", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{EngineError, ExitReport, LedgerError, Rejection, SourceError};
+    use crate::merge::SourceCursor;
+    use crate::test_helpers::*;
+    use std::fs;
+    use std::io::Error;
+
+    // Tests that rejections and warnings are reported against the input the cursor is on, and that
+    // operations read from elsewhere are reported against their line alone
+    #[test]
+    fn test_exit_report_locations() {
+        let cursor = SourceCursor::default();
+        let mut report = ExitReport::default();
+        report.read_from_all(
+            vec!["a.csv".to_string(), "b.csv".to_string()],
+            cursor.clone(),
+        );
+
+        report.reject(3, LedgerError::UnknownClient(2));
+        cursor.set(1);
+        report.warn(4, LedgerError::UnknownTransaction(9));
+        report.reject_in(None, 2, LedgerError::NoAccount(5));
+
+        assert_eq!(
+            report.rejections,
+            vec![
+                Rejection {
+                    line: 3,
+                    file: Some("a.csv".to_string()),
+                    error: LedgerError::UnknownClient(2).into(),
+                },
+                Rejection {
+                    line: 2,
+                    file: None,
+                    error: LedgerError::NoAccount(5).into(),
+                },
+            ]
+        );
+        assert_eq!(report.warnings[0].location(), "line 4 of b.csv");
+        assert_eq!(report.rejections[1].location(), "line 2");
+    }
+
+    // Tests that the error that ended the run names the record that raised it, when a record did
+    #[test]
+    fn test_exit_report_fatal_location() {
+        let mut report = ExitReport {
+            quiet: true,
+            fatal: Some(EngineError::Source(SourceError::State(
+                "state.json".to_string(),
+                "denied".to_string(),
+            ))),
+            ..ExitReport::default()
+        };
+        assert!(report.to_string().starts_with("Error executing run! [22]"));
+
+        report.read_from("transactions.csv".as_ref());
+        report.fail_at(7);
+        assert_eq!(
+            report.fatal_at,
+            Some((Some("transactions.csv".to_string()), 7))
+        );
+        assert!(report
+            .to_string()
+            .starts_with("Error executing run at line 7 of transactions.csv! [22]"));
+    }
+
+    // Tests that the rejections csv has the file each rejected record was read from, left empty
+    // when it wasn't read from an input
+    #[test]
+    fn test_write_rejections() -> Result<(), Error> {
+        let (file_path_str, dir, file) = create_temp_file("rejections.csv")?;
+        let mut report = ExitReport::default();
+        report.read_from("transactions.csv".as_ref());
+        report.reject(3, LedgerError::UnknownClient(2));
+        report.reject_in(None, 2, LedgerError::NoAccount(5));
+
+        report.write_rejections(&file_path_str).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file_path_str)?,
+            format!(
+                "file,line,code,error\ntransactions.csv,3,{},{}\n,2,{},{}\n",
+                LedgerError::UnknownClient(2).code(),
+                LedgerError::UnknownClient(2),
+                LedgerError::NoAccount(5).code(),
+                LedgerError::NoAccount(5)
+            )
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
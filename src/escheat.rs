@@ -0,0 +1,252 @@
+use crate::audit::{AuditBalances, AuditEvent, AuditLog};
+use crate::clock::Clock;
+use crate::error::{CliError, EngineResult, SourceError, SourceResult};
+use crate::mapper::{serialize_with_precision, Account, TransactionType};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// The number of seconds in a day
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// What the escheatment sweep was asked to do, it only runs when a period is provided
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EscheatmentSettings {
+    /// How many days funds can be held by a dispute before they're escheated
+    pub after_days: Option<u64>,
+
+    /// The client id of the holding account that escheated funds are moved to
+    pub holding_account: Option<u16>,
+
+    /// The unix timestamp that held funds are aged against, the current time when one isn't
+    /// provided
+    pub as_of: Option<u64>,
+
+    /// A file to write the escheated funds to
+    pub report: Option<PathBuf>,
+}
+
+/// Disputed funds that were moved to the holding account, as output to the escheatment report
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Escheatment {
+    /// The unique ID of the client the funds were held for
+    pub client: u16,
+
+    /// The unique ID of the disputed transaction
+    pub tx: u32,
+
+    /// The funds that were moved
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub amount: f32,
+
+    /// When the funds were first held, as a unix timestamp
+    pub held_since: u64,
+
+    /// How many whole days the funds had been held for
+    pub held_days: u64,
+
+    /// The client id of the holding account the funds were moved to
+    pub holding_account: u16,
+}
+
+/// Sweeps disputed funds that have been held for longer than the settings allow to the holding
+/// account, returning what was moved. Nothing is swept unless a period was provided. Funds are
+/// aged against the clock, unless the settings provide the time to age them against. Every
+/// change to an account is written to the audit log, when there is one.
+pub fn escheat_held_funds(
+    account_map: &mut HashMap<u16, Account>,
+    settings: &EscheatmentSettings,
+    clock: &dyn Clock,
+    audit: Option<&mut AuditLog>,
+) -> EngineResult<Vec<Escheatment>> {
+    let Some(after_days) = settings.after_days else {
+        return Ok(vec![]);
+    };
+    let holding_account = settings
+        .holding_account
+        .ok_or_else(|| CliError::MissingFlag("--escheat-account".to_string()))?;
+    let as_of = settings.as_of.unwrap_or_else(|| clock.now_secs());
+
+    Ok(sweep(
+        account_map,
+        after_days,
+        holding_account,
+        as_of,
+        audit,
+    )?)
+}
+
+/// Moves the held funds of every transaction that's been disputed for at least the number of
+/// days to the holding account, in order of client and transaction. Disputes without a
+/// timestamp can't be aged, so they're left alone.
+pub fn sweep(
+    account_map: &mut HashMap<u16, Account>,
+    after_days: u64,
+    holding_account: u16,
+    as_of: u64,
+    mut audit: Option<&mut AuditLog>,
+) -> SourceResult<Vec<Escheatment>> {
+    let mut escheatments = vec![];
+
+    let ordered_accounts: BTreeMap<&u16, &mut Account> = account_map
+        .iter_mut()
+        .filter(|(client_id, _)| **client_id != holding_account)
+        .collect();
+    for (client_id, account) in ordered_accounts {
+        let mut expired: Vec<(u32, u64)> = account
+            .successful_transactions
+            .iter()
+            .filter(|(_, transaction)| transaction.current_state == TransactionType::Dispute)
+            .filter_map(|(tx, transaction)| Some((*tx, transaction.held_since?)))
+            .filter(|(_, held_since)| {
+                as_of.saturating_sub(*held_since) / SECONDS_PER_DAY >= after_days
+            })
+            .collect();
+        expired.sort_unstable();
+
+        for (tx, held_since) in expired {
+            let before = AuditBalances::of(account);
+            if let Some(amount) = account.escheat(tx) {
+                if let Some(audit) = audit.as_mut() {
+                    let event =
+                        AuditEvent::escheated(*client_id, Some(tx), amount, before, account);
+                    audit.record(event)?;
+                }
+                escheatments.push(Escheatment {
+                    client: *client_id,
+                    tx,
+                    amount,
+                    held_since,
+                    held_days: as_of.saturating_sub(held_since) / SECONDS_PER_DAY,
+                    holding_account,
+                });
+            }
+        }
+    }
+
+    let escheated: f32 = escheatments
+        .iter()
+        .map(|escheatment| escheatment.amount)
+        .sum();
+    if !escheatments.is_empty() {
+        let holding = account_map.entry(holding_account).or_default();
+        let before = AuditBalances::of(holding);
+        holding.available_funds += escheated;
+        holding.total_funds += escheated;
+        if let Some(audit) = audit {
+            audit.record(AuditEvent::escheated(
+                holding_account,
+                None,
+                escheated,
+                before,
+                holding,
+            ))?;
+        }
+    }
+
+    Ok(escheatments)
+}
+
+/// Writes the funds that were escheated to a csv, for regulatory reporting. The report always
+/// has the real client ids.
+pub fn write_escheatment_report(
+    file_path: impl AsRef<Path>,
+    escheatments: &[Escheatment],
+) -> SourceResult<()> {
+    let mut writer = csv::Writer::from_path(file_path).map_err(SourceError::from)?;
+    for escheatment in escheatments {
+        writer.serialize(escheatment).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EngineConfig;
+    use crate::engine::Engine;
+    use crate::escheat::{sweep, write_escheatment_report, Escheatment};
+    use crate::journal::Journal;
+    use crate::mapper::{Record, TransactionType};
+    use crate::test_helpers::*;
+    use approx::assert_relative_eq;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Error;
+
+    /// A record with a timestamp, in days since the unix epoch
+    fn on_day(mut record: Record, day: u64) -> Record {
+        record.timestamp = Some(day * 86_400);
+        record
+    }
+
+    // Tests that only funds disputed for long enough are moved to the holding account, and that
+    // they're reported
+    #[test]
+    fn test_sweep() -> Result<(), Error> {
+        let mut engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        for record in [
+            on_day(Record::deposit(1, 1, 10.0), 0),
+            on_day(Record::deposit(1, 2, 5.0), 0),
+            on_day(Record::deposit(2, 3, 7.5), 0),
+            on_day(Record::dispute(1, 1), 10),
+            on_day(Record::dispute(1, 2), 100),
+            on_day(Record::dispute(2, 3), 20),
+            // disputing again doesn't restart the clock
+            on_day(Record::dispute(2, 3), 150),
+            Record::deposit(3, 4, 1.0),
+            Record::dispute(3, 4),
+        ] {
+            engine.process(&record).unwrap();
+        }
+        let mut account_map = engine.into_accounts().unwrap();
+
+        let escheatments = sweep(&mut account_map, 180, 999, 200 * 86_400, None).unwrap();
+
+        assert_eq!(
+            escheatments,
+            vec![
+                Escheatment {
+                    client: 1,
+                    tx: 1,
+                    amount: 10.0,
+                    held_since: 10 * 86_400,
+                    held_days: 190,
+                    holding_account: 999,
+                },
+                Escheatment {
+                    client: 2,
+                    tx: 3,
+                    amount: 7.5,
+                    held_since: 20 * 86_400,
+                    held_days: 180,
+                    holding_account: 999,
+                },
+            ]
+        );
+        assert_account(&account_map[&1], 0.0, 5.0, true);
+        assert_relative_eq!(account_map[&1].held_funds, 5.0);
+        assert_eq!(
+            account_map[&1].successful_transactions[&1].current_state,
+            TransactionType::Escheated
+        );
+        assert_account(&account_map[&999], 17.5, 17.5, true);
+        assert_relative_eq!(account_map[&3].held_funds, 1.0);
+
+        let (file_path, dir, file) = create_temp_file("escheatment.csv")?;
+        write_escheatment_report(&file_path, &escheatments).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file_path)?,
+            "client,tx,amount,held_since,held_days,holding_account\n\
+             1,1,10.0,864000,190,999\n\
+             2,3,7.5,1728000,180,999\n"
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use crate::error::{CliError, CliResult};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Controls how amounts and booleans are rendered by an output, without changing the serde impls
+/// of the rows being written. Amounts have already been rounded by the row when they're rendered.
+pub trait FieldFormatter {
+    /// Renders an amount
+    fn amount(&self, amount: f64) -> String;
+
+    /// Renders a boolean (e.g. whether an account is locked)
+    fn boolean(&self, value: bool) -> String;
+}
+
+/// How booleans are rendered
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum BooleanStyle {
+    /// true or false
+    #[default]
+    TrueFalse,
+
+    /// 1 or 0
+    OneZero,
+}
+
+impl FromStr for BooleanStyle {
+    type Err = CliError;
+
+    fn from_str(style: &str) -> CliResult<Self> {
+        match style.to_lowercase().as_str() {
+            "true-false" => Ok(BooleanStyle::TrueFalse),
+            "1-0" | "one-zero" => Ok(BooleanStyle::OneZero),
+            _ => Err(CliError::InvalidValue(
+                "--bool-format".to_string(),
+                style.to_string(),
+            )),
+        }
+    }
+}
+
+/// The field formatting that can be configured from the command line
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FieldStyle {
+    /// How booleans are rendered
+    pub booleans: BooleanStyle,
+
+    /// The number of decimal places every amount is padded or rounded to, when amounts are fixed
+    /// width
+    pub decimals: Option<usize>,
+
+    /// Whether amounts use a decimal comma (e.g. 1,5) rather than a decimal point
+    pub decimal_comma: bool,
+}
+
+impl FieldFormatter for FieldStyle {
+    fn amount(&self, amount: f64) -> String {
+        let text = match self.decimals {
+            Some(decimals) => format!("{:.*}", decimals, amount),
+            // the same rendering as an unformatted amount, e.g. 1.0 rather than 1
+            None => format!("{:?}", amount),
+        };
+
+        if self.decimal_comma {
+            text.replace('.', ",")
+        } else {
+            text
+        }
+    }
+
+    fn boolean(&self, value: bool) -> String {
+        match (self.booleans, value) {
+            (BooleanStyle::TrueFalse, value) => value.to_string(),
+            (BooleanStyle::OneZero, true) => "1".to_string(),
+            (BooleanStyle::OneZero, false) => "0".to_string(),
+        }
+    }
+}
+
+/// A row serialized with its amounts and booleans rendered by the formatter, or as it is when
+/// there isn't one. Any serde output can write it, so each sink can have its own formatter.
+pub struct Formatted<'a, T> {
+    /// The row being written
+    value: T,
+
+    /// Renders the row's amounts and booleans
+    formatter: Option<&'a dyn FieldFormatter>,
+}
+
+impl<'a, T> Formatted<'a, T> {
+    /// Wraps a row, so it's rendered by the formatter
+    pub fn new(value: T, formatter: Option<&'a dyn FieldFormatter>) -> Self {
+        Formatted { value, formatter }
+    }
+}
+
+impl<T: Serialize> Serialize for Formatted<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.formatter {
+            Some(formatter) => self.value.serialize(FieldSerializer {
+                inner: serializer,
+                formatter,
+            }),
+            None => self.value.serialize(serializer),
+        }
+    }
+}
+
+/// Passes everything through to the inner serializer, except for floats and booleans which are
+/// rendered by the formatter. The fields of structs, and the values of options, are formatted too.
+struct FieldSerializer<'a, S> {
+    /// The serializer of the output
+    inner: S,
+
+    /// Renders the amounts and booleans
+    formatter: &'a dyn FieldFormatter,
+}
+
+impl<'a, S: Serializer> Serializer for FieldSerializer<'a, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = S::SerializeSeq;
+    type SerializeTuple = S::SerializeTuple;
+    type SerializeTupleStruct = S::SerializeTupleStruct;
+    type SerializeTupleVariant = S::SerializeTupleVariant;
+    type SerializeMap = S::SerializeMap;
+    type SerializeStruct = FieldStruct<'a, S::SerializeStruct>;
+    type SerializeStructVariant = S::SerializeStructVariant;
+
+    fn serialize_bool(self, v: bool) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_str(&self.formatter.boolean(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<S::Ok, S::Error> {
+        // widened through its shortest representation, so 0.1 isn't rendered as 0.10000000149
+        let v = v.to_string().parse().unwrap_or(v as f64);
+        self.inner.serialize_str(&self.formatter.amount(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_str(&self.formatter.amount(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i64(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_char(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_str(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<S::Ok, S::Error> {
+        let formatter = self.formatter;
+        self.inner
+            .serialize_some(&Formatted::new(value, Some(formatter)))
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_unit_variant(name, variant_index, variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let formatter = self.formatter;
+        self.inner
+            .serialize_newtype_struct(name, &Formatted::new(value, Some(formatter)))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let formatter = self.formatter;
+        self.inner.serialize_newtype_variant(
+            name,
+            variant_index,
+            variant,
+            &Formatted::new(value, Some(formatter)),
+        )
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<S::SerializeSeq, S::Error> {
+        self.inner.serialize_seq(len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<S::SerializeTuple, S::Error> {
+        self.inner.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeTupleStruct, S::Error> {
+        self.inner.serialize_tuple_struct(name, len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeTupleVariant, S::Error> {
+        self.inner
+            .serialize_tuple_variant(name, variant_index, variant, len)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<S::SerializeMap, S::Error> {
+        self.inner.serialize_map(len)
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        Ok(FieldStruct {
+            inner: self.inner.serialize_struct(name, len)?,
+            formatter: self.formatter,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeStructVariant, S::Error> {
+        self.inner
+            .serialize_struct_variant(name, variant_index, variant, len)
+    }
+}
+
+/// Formats each field of a struct as it's serialized
+struct FieldStruct<'a, S> {
+    /// The struct serializer of the output
+    inner: S,
+
+    /// Renders the amounts and booleans
+    formatter: &'a dyn FieldFormatter,
+}
+
+impl<S: SerializeStruct> SerializeStruct for FieldStruct<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        self.inner
+            .serialize_field(key, &Formatted::new(value, Some(self.formatter)))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fields::{BooleanStyle, FieldFormatter, FieldStyle, Formatted};
+    use crate::mapper::{Account, AccountRecord, LockState};
+
+    // Tests that amounts and booleans are rendered by the style, in both csv and JSON
+    #[test]
+    fn test_formatted_account_record() {
+        let mut account = Account::with_balances(1.23456, 0.5);
+        account.lock_state = LockState::ChargebackLock { tx: 1 };
+        let record = AccountRecord::new(7, &account);
+
+        let style = FieldStyle {
+            booleans: BooleanStyle::OneZero,
+            decimals: Some(4),
+            decimal_comma: true,
+        };
+        let formatter: Option<&dyn FieldFormatter> = Some(&style);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .serialize(Formatted::new(&record, formatter))
+            .unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "client,available,held,total,locked\n7,\"1,2346\",\"0,5000\",\"1,7346\",1\n"
+        );
+
+        let json = serde_json::to_string(&Formatted::new(&record, formatter)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"client":7,"available":"1,2346","held":"0,5000","total":"1,7346","locked":"1"}"#
+        );
+
+        // without a formatter, the row is written as it always has been
+        let json = serde_json::to_string(&Formatted::new(&record, None)).unwrap();
+        assert_eq!(json, serde_json::to_string(&record).unwrap());
+    }
+
+    // Tests that amounts keep their usual rendering unless they're fixed width
+    #[test]
+    fn test_field_style_amount() {
+        let style = FieldStyle::default();
+        assert_eq!(style.amount(1.0), "1.0");
+        assert_eq!(style.amount(0.1234), "0.1234");
+        assert_eq!(style.boolean(false), "false");
+
+        let style = FieldStyle {
+            decimals: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(style.amount(1.0), "1.00");
+        assert_eq!(style.amount(-2.346), "-2.35");
+    }
+}
@@ -0,0 +1,330 @@
+use crate::error::{CliError, CliResult, SourceError, SourceResult};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/// We should only be reading data from .csv files, unless told otherwise
+pub const VALID_FILE_EXTENSION: &str = "csv";
+
+/// The columns we expect to find in the header row of a csv file
+const CSV_HEADERS: [&str; 3] = ["type", "client", "tx"];
+
+/// The magic bytes that gzip compressed files start with
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// The magic bytes that parquet files start with
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+/// How much of a file is inspected when detecting its format
+const SNIFF_LEN: u64 = 8 * 1024;
+
+/// The formats that transaction data can be read from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// Comma separated values, with a header row
+    Csv,
+
+    /// Tab separated values, with a header row
+    Tsv,
+
+    /// A JSON object per line, with the same fields as the csv columns
+    Jsonl,
+}
+
+impl InputFormat {
+    /// The format that files with the extension are expected to be in
+    fn from_extension(extension: &str) -> Option<InputFormat> {
+        match extension.to_lowercase().as_str() {
+            "csv" => Some(InputFormat::Csv),
+            "tsv" | "tab" => Some(InputFormat::Tsv),
+            "jsonl" | "ndjson" => Some(InputFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = CliError;
+
+    fn from_str(format: &str) -> CliResult<Self> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "tsv" => Ok(InputFormat::Tsv),
+            "jsonl" => Ok(InputFormat::Jsonl),
+            _ => Err(CliError::UnknownFormat(format.to_string())),
+        }
+    }
+}
+
+/// Decides which format the data in a file is stored in
+pub trait FormatDetector {
+    /// Returns the format of the file, or an error when it can't be read by the engine
+    fn detect(&self, path: &Path) -> CliResult<InputFormat>;
+}
+
+/// Strictly requires the file to have a .csv extension. This is the default detector.
+pub struct ExtensionDetector;
+
+impl FormatDetector for ExtensionDetector {
+    fn detect(&self, path: &Path) -> CliResult<InputFormat> {
+        match path.extension() {
+            // non csv files are considered invalid
+            Some(extension) if extension == VALID_FILE_EXTENSION => Ok(InputFormat::Csv),
+            _ => Err(CliError::InvalidExtension),
+        }
+    }
+}
+
+/// Treats the extension as a hint. When it isn't recognised, the header row of the file is
+/// inspected instead.
+pub struct SniffingDetector;
+
+impl FormatDetector for SniffingDetector {
+    fn detect(&self, path: &Path) -> CliResult<InputFormat> {
+        if let Ok(format) = ExtensionDetector.detect(path) {
+            return Ok(format);
+        }
+
+        let file =
+            File::open(path).map_err(|_| CliError::NonExistentFile(path.display().to_string()))?;
+
+        // the first line with any content should be the header row
+        let header = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default();
+
+        if is_csv_header(&header) {
+            Ok(InputFormat::Csv)
+        } else {
+            Err(CliError::UndetectedFormat(path.display().to_string()))
+        }
+    }
+}
+
+/// Inspects the file to choose its format, for `--format auto`. Gzip compressed files are looked
+/// inside of, then the first line decides the format. The extension (ignoring any .gz) is only
+/// used when the first line doesn't.
+pub struct AutoDetector;
+
+impl FormatDetector for AutoDetector {
+    fn detect(&self, path: &Path) -> CliResult<InputFormat> {
+        let non_existent = || CliError::NonExistentFile(path.display().to_string());
+        let mut start = vec![];
+        open_input(path)
+            .map_err(|_| non_existent())?
+            .take(SNIFF_LEN)
+            .read_to_end(&mut start)
+            .map_err(|_| non_existent())?;
+
+        if start.starts_with(PARQUET_MAGIC) {
+            return Err(CliError::UnreadableFormat(
+                "parquet".to_string(),
+                path.display().to_string(),
+            ));
+        }
+
+        let first_line = String::from_utf8_lossy(&start)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_string();
+        let from_content = if first_line.starts_with('{') {
+            Some(InputFormat::Jsonl)
+        } else if is_header(&first_line, '\t') {
+            Some(InputFormat::Tsv)
+        } else if is_csv_header(&first_line) {
+            Some(InputFormat::Csv)
+        } else {
+            None
+        };
+
+        // a compressed file's extension is the one before .gz (e.g. transactions.csv.gz)
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        let from_extension = Path::new(name)
+            .extension()
+            .and_then(|extension| InputFormat::from_extension(&extension.to_string_lossy()));
+
+        from_content
+            .or(from_extension)
+            .ok_or_else(|| CliError::UndetectedFormat(path.display().to_string()))
+    }
+}
+
+/// Skips detection entirely, the file is always read in the given format
+pub struct ForcedFormat(pub InputFormat);
+
+impl FormatDetector for ForcedFormat {
+    fn detect(&self, _path: &Path) -> CliResult<InputFormat> {
+        Ok(self.0)
+    }
+}
+
+/// Opens a file of transaction data, decompressing it as it's read when it's gzip compressed
+pub fn open_input(path: &Path) -> SourceResult<Box<dyn Read>> {
+    let file = File::open(path).map_err(|err| SourceError::Io(err.to_string()))?;
+    let mut reader = BufReader::new(file);
+    let start = reader
+        .fill_buf()
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+
+    if start.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(GzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Whether a line looks like the header row of a transactions csv (e.g. type,client,tx,amount)
+fn is_csv_header(line: &str) -> bool {
+    is_header(line, ',')
+}
+
+/// Whether a line looks like a header row, with the columns separated by the delimiter
+fn is_header(line: &str, delimiter: char) -> bool {
+    let columns: Vec<String> = line
+        .split(delimiter)
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+
+    columns.len() >= CSV_HEADERS.len()
+        && CSV_HEADERS
+            .iter()
+            .zip(columns.iter())
+            .all(|(expected, column)| expected == column)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::CliError;
+    use crate::format::{
+        is_csv_header, AutoDetector, ExtensionDetector, FormatDetector, InputFormat,
+        SniffingDetector,
+    };
+    use crate::test_helpers::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::{Error, Write};
+    use std::path::Path;
+
+    // Tests that header rows are recognised regardless of whitespace and casing
+    #[test]
+    fn test_is_csv_header() {
+        assert!(is_csv_header("type,client,tx,amount"));
+        assert!(is_csv_header(" Type , client,tx ,amount"));
+        assert!(!is_csv_header("deposit,1,1,1.0"));
+        assert!(!is_csv_header(""));
+    }
+
+    // Tests that the strict detector only accepts files with a csv extension
+    #[test]
+    fn test_extension_detector() {
+        assert_eq!(
+            ExtensionDetector.detect(Path::new("transactions.csv")),
+            Ok(InputFormat::Csv)
+        );
+        assert_eq!(
+            ExtensionDetector.detect(Path::new("transactions.txt")),
+            Err(CliError::InvalidExtension)
+        );
+    }
+
+    // Tests that csv data is detected from the header row of a file without a csv extension
+    #[test]
+    fn test_sniffing_detector() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.txt")?;
+        add_transactions_to_temp_file(vec!["deposit,1,1,1.0"], &mut file)?;
+
+        assert_eq!(
+            SniffingDetector.detect(Path::new(&file_path_str)),
+            Ok(InputFormat::Csv)
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a file without a header row isn't detected as csv data
+    #[test]
+    fn test_sniffing_detector_no_header() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions")?;
+        writeln!(file, "deposit,1,1,1.0")?;
+
+        assert_eq!(
+            SniffingDetector.detect(Path::new(&file_path_str)),
+            Err(CliError::UndetectedFormat(file_path_str.clone()))
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the format is chosen from the first line, looking inside gzip compressed files,
+    // with the extension as a fallback
+    #[test]
+    fn test_auto_detector() -> Result<(), Error> {
+        let (_, dir, file) = create_temp_file("transactions.csv")?;
+        let detect = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            AutoDetector.detect(&path)
+        };
+
+        assert_eq!(
+            detect("a.txt", b"type,client,tx,amount\n"),
+            Ok(InputFormat::Csv)
+        );
+        assert_eq!(
+            detect("b.csv", b"type\tclient\ttx\tamount\n"),
+            Ok(InputFormat::Tsv)
+        );
+        assert_eq!(
+            detect("c", b"\n{\"type\":\"deposit\"}\n"),
+            Ok(InputFormat::Jsonl)
+        );
+        assert_eq!(
+            detect("d.tsv", b"deposit\t1\t1\t1.0\n"),
+            Ok(InputFormat::Tsv)
+        );
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\n")?;
+        assert_eq!(detect("e.gz", &encoder.finish()?), Ok(InputFormat::Csv));
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"deposit,1,1,1.0\n")?;
+        assert_eq!(detect("f.csv.gz", &encoder.finish()?), Ok(InputFormat::Csv));
+
+        let parquet_path = dir.path().join("g.parquet").display().to_string();
+        assert_eq!(
+            detect("g.parquet", b"PAR1\x15\x04"),
+            Err(CliError::UnreadableFormat(
+                "parquet".to_string(),
+                parquet_path
+            ))
+        );
+        assert!(matches!(
+            detect("h.txt", b"deposit,1,1,1.0\n"),
+            Err(CliError::UndetectedFormat(_))
+        ));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
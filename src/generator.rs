@@ -0,0 +1,196 @@
+use crate::error::{SourceError, SourceResult};
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// Settings for generating a file of transactions. The same settings always generate the same
+/// file, so a scenario can be shared by its seed instead of the file itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    /// The number of rows to generate, excluding the header
+    pub rows: u64,
+
+    /// The number of distinct clients that transactions are spread across
+    pub clients: u16,
+
+    /// Seeds the pseudo-random number generator
+    pub seed: u64,
+
+    /// The probability that a row disputes an earlier deposit
+    pub dispute_probability: f64,
+
+    /// The probability that a dispute ends in a chargeback rather than a resolve
+    pub chargeback_probability: f64,
+
+    /// The probability that a row is deliberately invalid
+    pub invalid_rate: f64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            rows: 1_000,
+            clients: 100,
+            seed: 0,
+            dispute_probability: 0.05,
+            chargeback_probability: 0.2,
+            invalid_rate: 0.0,
+        }
+    }
+}
+
+/// A small, self-contained pseudo-random number generator (SplitMix64). It's implemented here
+/// rather than taken from a crate, so the output for a seed never changes between versions.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    /// The next 64 random bits
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A random number in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A random number in [0, bound)
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound.max(1)
+    }
+
+    /// Whether an event with the given probability happened
+    fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
+
+/// The kinds of invalid rows that are injected
+const INVALID_ROWS: [[&str; 4]; 3] = [
+    ["reversal", "1", "1", "1.0"],
+    ["deposit", "1", "not-a-tx", "1.0"],
+    ["deposit", "1", "1", "not-an-amount"],
+];
+
+/// Writes a generated file of transactions as csv
+pub fn generate(config: &GeneratorConfig, output: impl Write) -> SourceResult<()> {
+    let mut rng = SplitMix64(config.seed);
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(output);
+    let mut deposits: Vec<(u16, u32)> = vec![];
+    let mut disputes: VecDeque<(u16, u32)> = VecDeque::new();
+    let mut next_tx: u32 = 1;
+
+    writer
+        .write_record(["type", "client", "tx", "amount"])
+        .map_err(SourceError::from)?;
+
+    for _ in 0..config.rows {
+        if rng.chance(config.invalid_rate) {
+            let row = INVALID_ROWS[rng.below(INVALID_ROWS.len() as u64) as usize];
+            writer.write_record(row).map_err(SourceError::from)?;
+            continue;
+        }
+
+        let row: [String; 4] = if !deposits.is_empty() && rng.chance(config.dispute_probability) {
+            // dispute a random deposit that isn't already disputed
+            let (client, tx) = deposits.swap_remove(rng.below(deposits.len() as u64) as usize);
+            disputes.push_back((client, tx));
+            referencing_row("dispute", client, tx)
+        } else if !disputes.is_empty() && rng.chance(0.5) {
+            // settle the oldest dispute
+            let (client, tx) = disputes.pop_front().expect("disputes isn't empty");
+            match rng.chance(config.chargeback_probability) {
+                true => referencing_row("chargeback", client, tx),
+                false => referencing_row("resolve", client, tx),
+            }
+        } else {
+            let client = rng.below(config.clients as u64) as u16 + 1;
+            let tx = next_tx;
+            next_tx += 1;
+
+            // amounts have up to four places past the decimal
+            let amount = format!("{:.4}", rng.below(10_000_000) as f64 / 10_000.0);
+            let transaction_type = match rng.chance(0.7) {
+                true => {
+                    deposits.push((client, tx));
+                    "deposit"
+                }
+                false => "withdrawal",
+            };
+
+            [
+                transaction_type.to_string(),
+                client.to_string(),
+                tx.to_string(),
+                amount,
+            ]
+        };
+
+        writer.write_record(&row).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// A row that references an earlier transaction, without an amount
+fn referencing_row(transaction_type: &str, client: u16, tx: u32) -> [String; 4] {
+    [
+        transaction_type.to_string(),
+        client.to_string(),
+        tx.to_string(),
+        String::new(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::{generate, GeneratorConfig};
+
+    /// Generates a file into memory
+    fn generated(config: &GeneratorConfig) -> String {
+        let mut output = vec![];
+        generate(config, &mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    // Tests that the same seed always generates the same file, and a different seed doesn't
+    #[test]
+    fn test_generate_is_deterministic() {
+        let config = GeneratorConfig {
+            rows: 200,
+            dispute_probability: 0.3,
+            chargeback_probability: 0.5,
+            ..Default::default()
+        };
+
+        let file = generated(&config);
+
+        assert_eq!(file.lines().count(), 201);
+        assert!(file.contains("dispute,") && file.contains("chargeback,"));
+        assert_eq!(file, generated(&config));
+        assert_ne!(file, generated(&GeneratorConfig { seed: 1, ..config }));
+    }
+
+    // Tests that every row is invalid when the invalid rate is one
+    #[test]
+    fn test_generate_invalid_rows() {
+        let config = GeneratorConfig {
+            rows: 20,
+            invalid_rate: 1.0,
+            ..Default::default()
+        };
+
+        let file = generated(&config);
+
+        assert!(file
+            .lines()
+            .skip(1)
+            .all(|row| row.starts_with("reversal") || row.contains("not-a")));
+    }
+}
@@ -0,0 +1,233 @@
+use crate::emit::{emit_events, EventSink};
+use crate::error::{CliError, CliResult, SourceError, SourceResult};
+use crate::journal::AccountEvent;
+use crate::mapper::TransactionType;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The formats the linkage graph can be exported in
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    /// A Graphviz digraph, which can be rendered with `dot -Tsvg`
+    #[default]
+    Dot,
+
+    /// A csv edge list, which can be loaded into most graph tools
+    Csv,
+}
+
+impl FromStr for GraphFormat {
+    type Err = CliError;
+
+    fn from_str(format: &str) -> CliResult<Self> {
+        match format.to_lowercase().as_str() {
+            "dot" => Ok(GraphFormat::Dot),
+            "csv" => Ok(GraphFormat::Csv),
+            _ => Err(CliError::UnknownFormat(format.to_string())),
+        }
+    }
+}
+
+/// A link between two nodes of the graph. Clients link to the transactions they made, and each
+/// row that refers to a transaction (e.g. a dispute) links the step of the transaction's chain
+/// before it to a new step.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Edge {
+    /// The node the edge starts at (e.g. client:1 or tx:7)
+    pub from: String,
+
+    /// The node the edge ends at (e.g. tx:7 or tx:7#1 for the first step after it)
+    pub to: String,
+
+    /// The type of the row that made the link
+    pub relation: TransactionType,
+
+    /// The client of the row that made the link
+    pub client: u16,
+
+    /// The amount of the row, if it had one
+    pub amount: Option<f32>,
+}
+
+/// Links transactions to the disputes, resolves, chargebacks and voids that referred to them, so
+/// dispute chains can be followed from the client that made the transaction
+#[derive(Debug, Default)]
+pub struct LinkageGraph {
+    /// Every link, in the order the rows were applied
+    edges: Vec<Edge>,
+
+    /// The number of steps each transaction's chain has taken
+    steps: HashMap<u32, u32>,
+}
+
+impl LinkageGraph {
+    /// Links an applied row into the graph
+    pub fn add(&mut self, event: &AccountEvent) {
+        let tx = event.tx;
+        let (from, to) = if event.transaction_type.references_transaction() {
+            let step = self.steps.entry(tx).or_insert(0);
+            let from = step_node(tx, *step);
+            *step += 1;
+            (from, step_node(tx, *step))
+        } else {
+            (format!("client:{}", event.client), step_node(tx, 0))
+        };
+
+        self.edges.push(Edge {
+            from,
+            to,
+            relation: event.transaction_type,
+            client: event.client,
+            amount: event.amount,
+        });
+    }
+
+    /// Every link, in the order the rows were applied
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Writes the graph as a Graphviz digraph, with each edge labelled by the row that made it
+    pub fn write_dot(&self, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "digraph linkage {{")?;
+        for edge in &self.edges {
+            writeln!(
+                output,
+                "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                edge.relation.name()
+            )?;
+        }
+        writeln!(output, "}}")?;
+
+        output.flush()
+    }
+
+    /// Writes the graph as a csv edge list
+    pub fn write_csv(&self, output: impl Write) -> SourceResult<()> {
+        let mut writer = csv::Writer::from_writer(output);
+        for edge in &self.edges {
+            writer.serialize(edge).map_err(SourceError::from)?;
+        }
+
+        writer
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))
+    }
+}
+
+impl EventSink for LinkageGraph {
+    fn emit(&mut self, event: &AccountEvent) -> SourceResult<()> {
+        self.add(event);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> SourceResult<()> {
+        Ok(())
+    }
+}
+
+/// Builds the linkage graph from the events in a journal and writes it to the output file, or std
+/// out when one isn't provided. Returns the number of edges that were written.
+pub fn export_graph(
+    journal_path: &Path,
+    output_path: Option<&Path>,
+    format: GraphFormat,
+) -> SourceResult<usize> {
+    let mut graph = LinkageGraph::default();
+    emit_events(journal_path, &mut graph)?;
+
+    let output: Box<dyn Write> = match output_path {
+        Some(path) => {
+            let file = File::create(path).map_err(|err| SourceError::Io(err.to_string()))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(io::stdout()),
+    };
+
+    match format {
+        GraphFormat::Dot => graph
+            .write_dot(output)
+            .map_err(|err| SourceError::Io(err.to_string()))?,
+        GraphFormat::Csv => graph.write_csv(output)?,
+    }
+
+    Ok(graph.edges().len())
+}
+
+/// The node for a step of a transaction's chain, the transaction itself is step zero
+fn step_node(tx: u32, step: u32) -> String {
+    match step {
+        0 => format!("tx:{}", tx),
+        step => format!("tx:{}#{}", tx, step),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::{Edge, LinkageGraph};
+    use crate::journal::AccountEvent;
+    use crate::mapper::{Account, Record, TransactionType};
+
+    /// Builds the event for a row
+    fn event(record: Record) -> AccountEvent {
+        AccountEvent::new(&record, &Account::default())
+    }
+
+    // Tests that a dispute chain is linked step by step from the client that made the transaction
+    #[test]
+    fn test_linkage_graph() {
+        let mut graph = LinkageGraph::default();
+        graph.add(&event(Record::deposit(1, 7, 10.0)));
+        graph.add(&event(Record::dispute(1, 7)));
+        graph.add(&event(Record::resolve(1, 7)));
+        graph.add(&event(Record::withdrawal(2, 8, 1.0)));
+        graph.add(&event(Record::dispute(1, 7)));
+
+        let links: Vec<(&str, &str)> = graph
+            .edges()
+            .iter()
+            .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                ("client:1", "tx:7"),
+                ("tx:7", "tx:7#1"),
+                ("tx:7#1", "tx:7#2"),
+                ("client:2", "tx:8"),
+                ("tx:7#2", "tx:7#3"),
+            ]
+        );
+        assert_eq!(
+            graph.edges()[0],
+            Edge {
+                from: "client:1".to_string(),
+                to: "tx:7".to_string(),
+                relation: TransactionType::Deposit,
+                client: 1,
+                amount: Some(10.0),
+            }
+        );
+
+        let mut dot = vec![];
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert_eq!(
+            dot.lines().nth(1),
+            Some("  \"client:1\" -> \"tx:7\" [label=\"deposit\"];")
+        );
+        assert_eq!(dot.lines().last(), Some("}"));
+
+        let mut csv = vec![];
+        graph.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().next(), Some("from,to,relation,client,amount"));
+        assert_eq!(csv.lines().nth(2), Some("tx:7,tx:7#1,dispute,1,"));
+    }
+}
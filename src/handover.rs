@@ -0,0 +1,366 @@
+use crate::config::EngineConfig;
+use crate::engine::process_transaction_record;
+use crate::error::{SourceError, SourceResult};
+use crate::journal::{parse_entry, AccountEvent, JournalEntry};
+use crate::mapper::{Account, Record, TransactionType};
+use crate::state::{read_state, write_state, StateFormat};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+/// Sent by the new process once it's loaded the checkpoint, asking the old one to stop serving
+const READY: u8 = 1;
+
+/// The accounts of a running engine as of a point in its journal. A process taking over loads
+/// them while the old process carries on, then replays whatever was journaled after that point.
+#[derive(Debug, Default, PartialEq)]
+pub struct Checkpoint {
+    /// The accounts, as of the journal offset
+    pub accounts: HashMap<u16, Account>,
+
+    /// The length of the journal when the accounts were copied
+    pub journal_offset: u64,
+}
+
+/// The old process's side of a handover, listening on a unix socket for the process that takes
+/// over from it. The socket file is removed once the listener is dropped.
+pub struct HandoverListener {
+    /// Accepts the process taking over, without blocking
+    listener: UnixListener,
+
+    /// Where the socket was bound
+    socket_path: PathBuf,
+}
+
+impl HandoverListener {
+    /// Listens on the socket, replacing the file of a process that didn't stop cleanly
+    pub fn bind(socket_path: &Path) -> SourceResult<Self> {
+        if socket_path.exists() {
+            fs::remove_file(socket_path).map_err(handover_error)?;
+        }
+
+        let listener = UnixListener::bind(socket_path).map_err(handover_error)?;
+        listener.set_nonblocking(true).map_err(handover_error)?;
+
+        Ok(HandoverListener {
+            listener,
+            socket_path: socket_path.to_path_buf(),
+        })
+    }
+
+    /// The handover to a process that's connected to the socket, none when there isn't one
+    pub fn accept(&self) -> SourceResult<Option<Handover>> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).map_err(handover_error)?;
+                Ok(Some(Handover {
+                    stream,
+                    sender: None,
+                }))
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(handover_error(err)),
+        }
+    }
+}
+
+impl Drop for HandoverListener {
+    fn drop(&mut self) {
+        // the process taking over binds the same path once it's been released
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+/// A handover to a new process, on the old process's side
+pub struct Handover {
+    /// The connection to the new process
+    stream: UnixStream,
+
+    /// Sends the checkpoint in the background, so requests are still answered while it's sent
+    sender: Option<JoinHandle<SourceResult<()>>>,
+}
+
+impl Handover {
+    /// Starts sending the checkpoint to the new process
+    pub fn send_checkpoint(&mut self, checkpoint: Checkpoint) -> SourceResult<()> {
+        let mut stream = self.stream.try_clone().map_err(handover_error)?;
+        self.sender = Some(thread::spawn(move || {
+            write_checkpoint(&mut stream, &checkpoint)
+        }));
+
+        Ok(())
+    }
+
+    /// Whether the new process has loaded the checkpoint and is ready to take over, without
+    /// waiting for it. An error means the new process went away, so the handover is abandoned.
+    pub fn is_ready(&mut self) -> SourceResult<bool> {
+        let mut byte = [0];
+        self.stream.set_nonblocking(true).map_err(handover_error)?;
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false).map_err(handover_error)?;
+
+        match read {
+            Ok(1) if byte[0] == READY => Ok(true),
+            Ok(0) => Err(SourceError::Handover(
+                "the new process disconnected".to_string(),
+            )),
+            Ok(_) => Err(SourceError::Handover("unexpected message".to_string())),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(handover_error(err)),
+        }
+    }
+
+    /// Tells the new process that this one has stopped serving, and where the journal ended
+    pub fn release(mut self, journal_offset: u64) -> SourceResult<()> {
+        if let Some(sender) = self.sender.take() {
+            sender
+                .join()
+                .map_err(|_| SourceError::Handover("the checkpoint wasn't sent".to_string()))??;
+        }
+
+        self.stream
+            .write_all(&journal_offset.to_le_bytes())
+            .and_then(|_| self.stream.flush())
+            .map_err(handover_error)
+    }
+}
+
+/// Takes over from the engine process listening on the socket, returning its accounts once it's
+/// stopped serving. Everything it journaled after its checkpoint is replayed, and checked against
+/// the balances it journaled, so no transaction it applied is lost.
+pub fn take_over(
+    socket_path: &Path,
+    journal_path: &Path,
+    config: &EngineConfig,
+) -> SourceResult<HashMap<u16, Account>> {
+    let mut stream = UnixStream::connect(socket_path).map_err(handover_error)?;
+    let checkpoint = read_checkpoint(&mut stream)?;
+
+    stream.write_all(&[READY]).map_err(handover_error)?;
+    let journal_end = read_u64(&mut stream)?;
+
+    let mut accounts = checkpoint.accounts;
+    replay_journal(
+        journal_path,
+        checkpoint.journal_offset..journal_end,
+        &mut accounts,
+        config,
+    )?;
+
+    Ok(accounts)
+}
+
+/// Applies the events journaled within the range of the journal to the accounts. Notes and admin
+/// operations don't change any balances the server applied, so they're skipped.
+pub fn replay_journal(
+    journal_path: &Path,
+    range: std::ops::Range<u64>,
+    accounts: &mut HashMap<u16, Account>,
+    config: &EngineConfig,
+) -> SourceResult<()> {
+    let mut file = File::open(journal_path).map_err(handover_error)?;
+    file.seek(SeekFrom::Start(range.start))
+        .map_err(handover_error)?;
+    let tail = BufReader::new(file.take(range.end.saturating_sub(range.start)));
+
+    for (index, line) in tail.lines().enumerate() {
+        let line = line.map_err(handover_error)?;
+        let mismatch = |message: String| {
+            SourceError::Handover(format!(
+                "line {} of the journal's tail {}",
+                index + 1,
+                message
+            ))
+        };
+
+        let event = match parse_entry(&line) {
+            Ok(JournalEntry::Event(event)) => event,
+            Ok(_) => continue,
+            Err(err) => return Err(mismatch(err.to_string())),
+        };
+
+        let record = replayed_record(&event);
+        let account = accounts.entry(event.client).or_default();
+        process_transaction_record(&record, account, config)
+            .map_err(|err| mismatch(err.to_string()))?;
+
+        let replayed = AccountEvent {
+            recorded_at_ms: event.recorded_at_ms,
+            ..AccountEvent::new(&record, account)
+        };
+        if replayed != event {
+            return Err(mismatch(
+                "doesn't match the balances it was journaled with".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The record an event was journaled for. Disputes were held from when they were journaled.
+fn replayed_record(event: &AccountEvent) -> Record {
+    let timestamp = (event.transaction_type == TransactionType::Dispute)
+        .then_some(event.recorded_at_ms / 1_000);
+
+    Record {
+        transaction_type: event.transaction_type,
+        client_id: event.client,
+        transaction_id: event.tx,
+        amount: event.amount,
+        reason: event.reason.clone(),
+        timestamp,
+        idempotency_key: None,
+        destination: event.destination,
+    }
+}
+
+/// Writes the journal offset, then the length and bytes of the accounts in the binary state format
+fn write_checkpoint(stream: &mut UnixStream, checkpoint: &Checkpoint) -> SourceResult<()> {
+    let mut state = vec![];
+    write_state(&mut state, &checkpoint.accounts, StateFormat::Binary)
+        .map_err(SourceError::Handover)?;
+
+    let mut writer = BufWriter::new(stream);
+    writer
+        .write_all(&checkpoint.journal_offset.to_le_bytes())
+        .and_then(|_| writer.write_all(&(state.len() as u64).to_le_bytes()))
+        .and_then(|_| writer.write_all(&state))
+        .and_then(|_| writer.flush())
+        .map_err(handover_error)
+}
+
+/// Reads a checkpoint written by write_checkpoint
+fn read_checkpoint(stream: &mut UnixStream) -> SourceResult<Checkpoint> {
+    let journal_offset = read_u64(stream)?;
+    let len = read_u64(stream)?;
+    let accounts =
+        read_state(stream.take(len), StateFormat::Binary).map_err(SourceError::Handover)?;
+
+    Ok(Checkpoint {
+        accounts,
+        journal_offset,
+    })
+}
+
+/// Reads a little endian u64
+fn read_u64(stream: &mut UnixStream) -> SourceResult<u64> {
+    let mut bytes = [0; 8];
+    stream.read_exact(&mut bytes).map_err(handover_error)?;
+
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Wraps an io error raised during a handover
+fn handover_error(err: io::Error) -> SourceError {
+    SourceError::Handover(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EngineConfig;
+    use crate::handover::{replay_journal, take_over, Checkpoint, HandoverListener};
+    use crate::journal::{AccountEvent, Journal};
+    use crate::mapper::{Account, Record};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::thread;
+    use tempfile::tempdir;
+
+    // Tests that the new process gets the checkpoint's accounts, with everything the old process
+    // journaled after the checkpoint replayed onto them
+    #[test]
+    fn test_take_over() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("handover.sock");
+        let journal_path = dir.path().join("journal.log");
+
+        let mut account = Account::default();
+        account.deposit(10.0, 1);
+        let mut journal = Journal::open(&journal_path).unwrap();
+        journal
+            .record(AccountEvent::new(&Record::deposit(1, 1, 10.0), &account))
+            .unwrap();
+        journal.flush().unwrap();
+        let checkpoint = Checkpoint {
+            accounts: HashMap::from([(1, account.clone())]),
+            journal_offset: fs::metadata(&journal_path).unwrap().len(),
+        };
+
+        let listener = HandoverListener::bind(&socket_path).unwrap();
+        assert!(listener.accept().unwrap().is_none());
+        let new_process = {
+            let (socket_path, journal_path) = (socket_path.clone(), journal_path.clone());
+            thread::spawn(move || take_over(&socket_path, &journal_path, &EngineConfig::default()))
+        };
+
+        let mut handover = loop {
+            if let Some(handover) = listener.accept().unwrap() {
+                break handover;
+            }
+            thread::yield_now();
+        };
+        handover.send_checkpoint(checkpoint).unwrap();
+
+        // the old process carries on while the checkpoint is loaded
+        let dispute = Record::dispute(1, 1);
+        account.dispute(1);
+        journal
+            .record(AccountEvent::new(&dispute, &account))
+            .unwrap();
+        journal.flush().unwrap();
+
+        while !handover.is_ready().unwrap() {
+            thread::yield_now();
+        }
+        drop(listener);
+        assert!(!socket_path.exists());
+        handover
+            .release(fs::metadata(&journal_path).unwrap().len())
+            .unwrap();
+
+        let accounts = new_process.join().unwrap().unwrap();
+        assert_eq!(accounts[&1].held_funds, 10.0);
+        assert_eq!(accounts[&1].available_funds, 0.0);
+    }
+
+    // Tests that a tail that doesn't match the balances it was journaled with is refused
+    #[test]
+    fn test_replay_journal_mismatch() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("journal.log");
+
+        let mut account = Account::default();
+        account.deposit(5.0, 1);
+        let mut journal = Journal::open(&journal_path).unwrap();
+        journal
+            .record(AccountEvent::new(&Record::deposit(1, 1, 5.0), &account))
+            .unwrap();
+        journal.flush().unwrap();
+        let end = fs::metadata(&journal_path).unwrap().len();
+
+        // the checkpoint already had the deposit, so replaying it again gives other balances
+        let mut accounts = HashMap::from([(1, account)]);
+        let err = replay_journal(
+            &journal_path,
+            0..end,
+            &mut accounts,
+            &EngineConfig::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), 25);
+
+        let mut accounts = HashMap::new();
+        replay_journal(
+            &journal_path,
+            0..end,
+            &mut accounts,
+            &EngineConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(accounts[&1].available_funds, 5.0);
+    }
+}
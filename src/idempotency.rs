@@ -0,0 +1,155 @@
+use crate::error::{LedgerError, LedgerResult, SourceError, SourceResult};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+/// The idempotency keys of the records that have been applied, so a record that's re-sent is only
+/// applied once even when it has a different transaction id. When a file backs the keys, each new
+/// key is appended to it as soon as its record is applied, so they carry over between runs.
+#[derive(Default)]
+pub struct IdempotencyKeys {
+    /// Every key that's been applied, in this run or an earlier one
+    keys: HashSet<String>,
+
+    /// The file new keys are appended to, when they're persisted
+    file: Option<BufWriter<File>>,
+}
+
+impl IdempotencyKeys {
+    /// Loads the keys in the file, appending new keys to it. The file is created when it doesn't
+    /// exist yet.
+    pub fn open(file_path: impl AsRef<Path>) -> SourceResult<Self> {
+        let file_path = file_path.as_ref();
+        let keys = read_keys(file_path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(IdempotencyKeys {
+            keys,
+            file: Some(BufWriter::new(file)),
+        })
+    }
+
+    /// Loads the keys in the file without ever changing it, for simulations
+    pub fn load(file_path: impl AsRef<Path>) -> SourceResult<Self> {
+        Ok(IdempotencyKeys {
+            keys: read_keys(file_path.as_ref())?,
+            file: None,
+        })
+    }
+
+    /// Errors when a record with the key has already been applied
+    pub fn check(&self, key: Option<&str>, transaction_id: u32) -> LedgerResult<()> {
+        match key {
+            Some(key) if self.keys.contains(key) => {
+                Err(LedgerError::DuplicateKey(key.to_string(), transaction_id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Remembers the key of a record that was applied, appending it to the file when there is one
+    pub fn insert(&mut self, key: Option<&str>) -> SourceResult<()> {
+        let Some(key) = key.filter(|key| self.keys.insert(key.to_string())) else {
+            return Ok(());
+        };
+
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", key)
+                .and_then(|()| file.flush())
+                .map_err(|err| SourceError::Io(err.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of keys that have been applied
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether no keys have been applied
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Reads the keys in a file, one per line. A file that doesn't exist has no keys.
+fn read_keys(file_path: &Path) -> SourceResult<HashSet<String>> {
+    match fs::read_to_string(file_path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(String::from)
+            .collect()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(err) => Err(SourceError::Io(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EngineConfig;
+    use crate::engine::Engine;
+    use crate::error::{EngineError, LedgerError};
+    use crate::idempotency::IdempotencyKeys;
+    use crate::journal::Journal;
+    use crate::mapper::Record;
+    use crate::test_helpers::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Error;
+
+    /// A deposit carrying an idempotency key
+    fn keyed_deposit(transaction_id: u32, key: &str) -> Record {
+        Record {
+            idempotency_key: Some(key.to_string()),
+            ..Record::deposit(1, transaction_id, 10.0)
+        }
+    }
+
+    // Tests that a re-sent record is rejected even with a new transaction id, across runs, and
+    // that simulations never add keys
+    #[test]
+    fn test_idempotency_keys() -> Result<(), Error> {
+        let (keys_path, dir, file) = create_temp_file("keys.txt")?;
+        fs::remove_file(&keys_path)?;
+
+        let keys = IdempotencyKeys::open(&keys_path).unwrap();
+        let mut engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_idempotency_keys(keys);
+        engine.process(&keyed_deposit(1, "abc")).unwrap();
+        assert_eq!(
+            engine.process(&keyed_deposit(2, "abc")),
+            Err(EngineError::Ledger(LedgerError::DuplicateKey(
+                "abc".to_string(),
+                2
+            )))
+        );
+        engine.process(&Record::deposit(1, 3, 10.0)).unwrap();
+        let accounts = engine.into_accounts().unwrap();
+        assert_account(&accounts[&1], 20.0, 20.0, true);
+
+        assert_eq!(fs::read_to_string(&keys_path)?, "abc\n");
+
+        let keys = IdempotencyKeys::load(&keys_path).unwrap();
+        assert_eq!(keys.len(), 1);
+        let mut engine = Engine::new(accounts, EngineConfig::default(), Journal::default())
+            .with_idempotency_keys(keys);
+        assert!(engine.process(&keyed_deposit(4, "abc")).is_err());
+        engine.process(&keyed_deposit(5, "def")).unwrap();
+        engine.into_accounts().unwrap();
+
+        assert_eq!(fs::read_to_string(&keys_path)?, "abc\n");
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
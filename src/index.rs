@@ -0,0 +1,340 @@
+use crate::error::{CliError, CliResult, SourceError, SourceResult};
+use crate::mapper::{Account, Transaction, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The state a transaction was left in, as it's searched for
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransactionState {
+    /// A deposit that hasn't been disputed
+    Deposited,
+
+    /// A withdrawal
+    Withdrawn,
+
+    /// A transaction that's being disputed
+    Disputed,
+
+    /// A transaction whose dispute was resolved
+    Resolved,
+
+    /// A transaction that was charged back
+    ChargedBack,
+
+    /// A transaction that was voided before it settled
+    Voided,
+
+    /// A transaction whose held funds were moved to the escheatment holding account
+    Escheated,
+
+    /// A withdrawal that was refunded
+    Refunded,
+}
+
+impl From<TransactionType> for TransactionState {
+    fn from(transaction_type: TransactionType) -> Self {
+        match transaction_type {
+            TransactionType::Withdrawal | TransactionType::Transfer => TransactionState::Withdrawn,
+            TransactionType::Dispute => TransactionState::Disputed,
+            TransactionType::Resolve => TransactionState::Resolved,
+            TransactionType::Chargeback => TransactionState::ChargedBack,
+            TransactionType::Void => TransactionState::Voided,
+            TransactionType::Refund => TransactionState::Refunded,
+            TransactionType::Escheated => TransactionState::Escheated,
+            TransactionType::Deposit | TransactionType::Adjustment | TransactionType::Unlock => {
+                TransactionState::Deposited
+            }
+        }
+    }
+}
+
+impl FromStr for TransactionState {
+    type Err = CliError;
+
+    fn from_str(state: &str) -> CliResult<Self> {
+        match state {
+            "deposited" => Ok(TransactionState::Deposited),
+            "withdrawn" => Ok(TransactionState::Withdrawn),
+            "disputed" => Ok(TransactionState::Disputed),
+            "resolved" => Ok(TransactionState::Resolved),
+            "charged-back" => Ok(TransactionState::ChargedBack),
+            "voided" => Ok(TransactionState::Voided),
+            "refunded" => Ok(TransactionState::Refunded),
+            "escheated" => Ok(TransactionState::Escheated),
+            _ => Err(CliError::InvalidValue(
+                "--state".to_string(),
+                state.to_string(),
+            )),
+        }
+    }
+}
+
+/// A transaction in the index
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IndexEntry {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The unique ID of the transaction
+    pub tx: u32,
+
+    /// The amount of the transaction
+    pub amount: f32,
+
+    /// The state the transaction was left in
+    pub state: TransactionState,
+
+    /// When the transaction occurred, as a unix timestamp, when its row had one
+    pub timestamp: Option<u64>,
+}
+
+impl IndexEntry {
+    /// Creates the entry of one of a client's transactions
+    pub fn new(client: u16, tx: u32, transaction: &Transaction) -> Self {
+        IndexEntry {
+            client,
+            tx,
+            amount: transaction.amount,
+            state: transaction.current_state.into(),
+            timestamp: transaction.timestamp,
+        }
+    }
+}
+
+/// A search index over the transactions in the saved state, so investigators can find the
+/// transactions they're after without loading every account
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct TransactionIndex {
+    /// Every transaction, ordered by amount, so amount ranges are contiguous
+    entries: Vec<IndexEntry>,
+
+    /// The positions of each client's transactions in the entries
+    by_client: BTreeMap<u16, Vec<u32>>,
+
+    /// The positions of the transactions in each state in the entries
+    by_state: BTreeMap<TransactionState, Vec<u32>>,
+}
+
+/// The conditions a transaction must meet to be found, each one is optional
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FindQuery {
+    /// Only the transactions of this client
+    pub client: Option<u16>,
+
+    /// Only the transactions in this state
+    pub state: Option<TransactionState>,
+
+    /// Only the transactions with at least this amount
+    pub min_amount: Option<f32>,
+
+    /// Only the transactions with at most this amount
+    pub max_amount: Option<f32>,
+}
+
+impl TransactionIndex {
+    /// Indexes the transactions of every account
+    pub fn build(account_map: &HashMap<u16, Account>) -> Self {
+        let mut entries: Vec<IndexEntry> = account_map
+            .iter()
+            .flat_map(|(client_id, account)| {
+                account
+                    .successful_transactions
+                    .iter()
+                    .map(|(transaction_id, transaction)| {
+                        IndexEntry::new(*client_id, *transaction_id, transaction)
+                    })
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            a.amount
+                .total_cmp(&b.amount)
+                .then(a.client.cmp(&b.client))
+                .then(a.tx.cmp(&b.tx))
+        });
+
+        let mut index = TransactionIndex::default();
+        for (position, entry) in entries.iter().enumerate() {
+            index
+                .by_client
+                .entry(entry.client)
+                .or_default()
+                .push(position as u32);
+            index
+                .by_state
+                .entry(entry.state)
+                .or_default()
+                .push(position as u32);
+        }
+        index.entries = entries;
+
+        index
+    }
+
+    /// Finds the transactions that meet every condition of the query, ordered by amount
+    pub fn find(&self, query: &FindQuery) -> Vec<&IndexEntry> {
+        // the amount range is a contiguous run of positions, since the entries are ordered by it
+        let start = query.min_amount.map_or(0, |min| {
+            self.entries.partition_point(|entry| entry.amount < min)
+        });
+        let end = query.max_amount.map_or(self.entries.len(), |max| {
+            self.entries.partition_point(|entry| entry.amount <= max)
+        });
+        let in_range = start..end.max(start);
+
+        // narrow the search down with the smallest of the lists the query can use
+        let client_positions = query
+            .client
+            .map(|client| self.by_client.get(&client).map_or(&[][..], Vec::as_slice));
+        let state_positions = query
+            .state
+            .map(|state| self.by_state.get(&state).map_or(&[][..], Vec::as_slice));
+        let smallest = [client_positions, state_positions]
+            .into_iter()
+            .flatten()
+            .min_by_key(|positions| positions.len());
+        let candidates: Box<dyn Iterator<Item = usize>> = match smallest {
+            Some(positions) => Box::new(positions.iter().map(|position| *position as usize)),
+            None => Box::new(in_range.clone()),
+        };
+
+        // the lists hold positions in ascending order, so the entries are found in amount order
+        candidates
+            .filter(|position| in_range.contains(position))
+            .map(|position| &self.entries[position])
+            .filter(|entry| query.client.is_none_or(|client| entry.client == client))
+            .filter(|entry| query.state.is_none_or(|state| entry.state == state))
+            .collect()
+    }
+}
+
+/// Saves the index of the accounts' transactions alongside their state
+pub fn save_index(file_path: &Path, account_map: &HashMap<u16, Account>) -> SourceResult<()> {
+    let index_error = |err: String| SourceError::State(file_path.display().to_string(), err);
+    let file = File::create(file_path).map_err(|err| index_error(err.to_string()))?;
+
+    let mut writer = BufWriter::new(file);
+    bincode::serialize_into(&mut writer, &TransactionIndex::build(account_map))
+        .map_err(|err| index_error(err.to_string()))?;
+    writer.flush().map_err(|err| index_error(err.to_string()))
+}
+
+/// Loads an index that was saved alongside the state
+pub fn load_index(file_path: &Path) -> SourceResult<TransactionIndex> {
+    let index_error = |err: String| SourceError::State(file_path.display().to_string(), err);
+    let file = File::open(file_path).map_err(|err| index_error(err.to_string()))?;
+
+    bincode::deserialize_from(BufReader::new(file)).map_err(|err| index_error(err.to_string()))
+}
+
+/// Searches a saved index, writing the transactions that were found as a csv to the output file
+/// or std out
+pub fn find_transactions(
+    index_path: &Path,
+    output_path: Option<&Path>,
+    query: &FindQuery,
+) -> SourceResult<()> {
+    let index = load_index(index_path)?;
+
+    let output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(File::create(path).map_err(|err| SourceError::Io(err.to_string()))?),
+        None => Box::new(io::stdout()),
+    };
+
+    let mut writer = csv::Writer::from_writer(output);
+    for entry in index.find(query) {
+        writer.serialize(entry).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::{load_index, save_index, FindQuery, TransactionIndex, TransactionState};
+    use crate::mapper::Account;
+    use std::collections::HashMap;
+    use std::io::Error;
+    use tempfile::tempdir;
+
+    /// Accounts with a mix of transactions, two of which are being disputed
+    fn accounts() -> HashMap<u16, Account> {
+        let mut first = Account::default();
+        first.deposit(1500.0, 1);
+        first.deposit(20.0, 2);
+        first.deposit(900.0, 3);
+        first.dispute(1);
+        first.dispute(2);
+
+        let mut second = Account::default();
+        second.deposit(2000.0, 4);
+        second.deposit(5000.0, 6);
+        second.dispute(4);
+        second.withdraw(100.0, 5).expect("ok");
+
+        HashMap::from([(1, first), (2, second)])
+    }
+
+    /// The transaction ids of the entries that were found
+    fn find(index: &TransactionIndex, query: FindQuery) -> Vec<u32> {
+        index
+            .find(&query)
+            .into_iter()
+            .map(|entry| entry.tx)
+            .collect()
+    }
+
+    // Tests that transactions are found by their state, client and amount
+    #[test]
+    fn test_find() {
+        let index = TransactionIndex::build(&accounts());
+
+        let large_disputes = FindQuery {
+            state: Some(TransactionState::Disputed),
+            min_amount: Some(1000.0),
+            ..Default::default()
+        };
+        assert_eq!(find(&index, large_disputes), vec![1, 4]);
+
+        let client_range = FindQuery {
+            client: Some(2),
+            min_amount: Some(100.0),
+            max_amount: Some(2000.0),
+            ..Default::default()
+        };
+        assert_eq!(find(&index, client_range), vec![5, 4]);
+
+        assert_eq!(find(&index, FindQuery::default()), vec![2, 5, 3, 1, 4, 6]);
+
+        let empty_range = FindQuery {
+            min_amount: Some(10.0),
+            max_amount: Some(1.0),
+            ..Default::default()
+        };
+        assert!(find(&index, empty_range).is_empty());
+    }
+
+    // Tests that a saved index is loaded as it was saved
+    #[test]
+    fn test_save_and_load_index() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let index_path = dir.path().join("state.idx");
+
+        save_index(&index_path, &accounts()).unwrap();
+
+        assert_eq!(
+            load_index(&index_path).unwrap(),
+            TransactionIndex::build(&accounts())
+        );
+
+        dir.close()?;
+
+        Ok(())
+    }
+}
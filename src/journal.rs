@@ -0,0 +1,477 @@
+use crate::admin::AdminOperation;
+use crate::annotate::Annotation;
+use crate::clock::{Clock, SystemClock};
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::{Account, Record, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// A transaction that was applied to an account, along with the resulting balances
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccountEvent {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The unique ID of the transaction
+    pub tx: u32,
+
+    /// The type of transaction that was applied
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+
+    /// The amount of the transaction, if it had one
+    pub amount: Option<f32>,
+
+    /// The reason code of an admin transaction
+    pub reason: Option<String>,
+
+    /// The client a transfer's funds were moved to. The transfer is journaled for both accounts,
+    /// the destination's event is the one for that client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<u16>,
+
+    /// The available funds once the transaction was applied
+    pub available: f32,
+
+    /// The held funds once the transaction was applied
+    pub held: f32,
+
+    /// The total funds once the transaction was applied
+    pub total: f32,
+
+    /// Whether the account was locked once the transaction was applied
+    pub locked: bool,
+
+    /// When the transaction was applied, in milliseconds since the unix epoch. It's stamped by
+    /// the journal's clock when the event is recorded.
+    #[serde(default)]
+    pub recorded_at_ms: u64,
+}
+
+impl AccountEvent {
+    /// Creates the event for a record that was applied to an account
+    pub fn new(record: &Record, account: &Account) -> Self {
+        AccountEvent {
+            client: record.client_id,
+            tx: record.transaction_id,
+            transaction_type: record.transaction_type,
+            amount: record.amount,
+            reason: record.reason.clone(),
+            destination: record.destination,
+            available: account.available_funds,
+            held: account.held_funds,
+            total: account.total_funds,
+            locked: account.lock_state.is_locked(),
+            recorded_at_ms: 0,
+        }
+    }
+}
+
+/// A line of the journal; an event, an operator's note about a transaction or an operation an
+/// operator made on an account
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum JournalEntry {
+    /// A transaction that was applied to an account
+    Event(AccountEvent),
+
+    /// A note that was appended by the annotate subcommand
+    Annotation(Annotation),
+
+    /// An operation from an admin operations file
+    Operation(AdminOperation),
+}
+
+/// Parses a line of the journal. Lines that are none of them are reported with the reason they
+/// aren't an event, since that's what most lines are.
+pub fn parse_entry(line: &str) -> serde_json::Result<JournalEntry> {
+    serde_json::from_str(line)
+        .map(JournalEntry::Event)
+        .or_else(|err| {
+            serde_json::from_str(line)
+                .map(JournalEntry::Annotation)
+                .or_else(|_| serde_json::from_str(line).map(JournalEntry::Operation))
+                .map_err(|_| err)
+        })
+}
+
+/// Controls how events are batched when the journal is written by a background thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchConfig {
+    /// The most events that are written and flushed together
+    pub batch_size: usize,
+
+    /// The most events that can be waiting to be written. Applying records only blocks on the
+    /// journal once the outbox is full.
+    pub outbox_capacity: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            batch_size: 100,
+            outbox_capacity: 10_000,
+        }
+    }
+}
+
+/// Counters that are updated as a background writer drains the outbox
+#[derive(Debug, Default)]
+pub struct SinkStats {
+    /// The number of events that have been written
+    written: AtomicUsize,
+
+    /// The number of batches that have been written
+    batches: AtomicUsize,
+
+    /// The number of events waiting in the outbox
+    pending: AtomicUsize,
+
+    /// The most events that were ever waiting in the outbox at once
+    max_lag: AtomicUsize,
+}
+
+impl SinkStats {
+    /// Records that an event was added to the outbox
+    fn queued(&self) {
+        let pending = self.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_lag.fetch_max(pending, Ordering::Relaxed);
+    }
+
+    /// Records that a batch of events was written
+    fn written(&self, events: usize) {
+        self.pending.fetch_sub(events, Ordering::Relaxed);
+        self.written.fetch_add(events, Ordering::Relaxed);
+        self.batches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A snapshot of the counters, for reporting once a run has finished
+    pub fn summary(&self) -> SinkSummary {
+        SinkSummary {
+            events: self.written.load(Ordering::Relaxed),
+            batches: self.batches.load(Ordering::Relaxed),
+            max_lag: self.max_lag.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// How much a background writer wrote, and how far it fell behind the records being applied
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SinkSummary {
+    /// The number of events that were written
+    pub events: usize,
+
+    /// The number of batches the events were written in
+    pub batches: usize,
+
+    /// The most events that were ever waiting to be written at once
+    pub max_lag: usize,
+}
+
+impl fmt::Display for SinkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} events written in {} batches, max lag {} events",
+            self.events, self.batches, self.max_lag
+        )
+    }
+}
+
+/// Where the journal writes its events
+#[derive(Default)]
+enum JournalTarget {
+    /// Journaling is disabled
+    #[default]
+    Disabled,
+
+    /// Events are written to the file as they're recorded
+    File(BufWriter<File>),
+
+    /// Events are queued in a bounded outbox and written in batches by a background thread
+    Batched(Outbox),
+}
+
+/// The sending half of a background writer
+struct Outbox {
+    /// Queues entries for the writer, dropped once the journal has been flushed
+    sender: Option<SyncSender<JournalEntry>>,
+
+    /// The background writer, which returns the first error it ran into
+    handle: Option<JoinHandle<SourceResult<()>>>,
+
+    /// Counters shared with the writer
+    stats: Arc<SinkStats>,
+}
+
+/// Appends an event to a file for every transaction that's applied, one JSON object per line
+pub struct Journal {
+    /// Where events are written
+    target: JournalTarget,
+
+    /// Stamps each event with when it was recorded
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal {
+            target: JournalTarget::Disabled,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl Journal {
+    /// Opens a journal file, events are appended to any that were written by previous runs
+    pub fn open(file_path: impl AsRef<Path>) -> SourceResult<Journal> {
+        Ok(Journal {
+            target: JournalTarget::File(open_file(file_path)?),
+            ..Default::default()
+        })
+    }
+
+    /// Opens a journal file that's written by a background thread, so a slow disk doesn't stall
+    /// the records being applied
+    pub fn open_batched(file_path: impl AsRef<Path>, config: BatchConfig) -> SourceResult<Journal> {
+        let writer = open_file(file_path)?;
+        let (sender, receiver) = mpsc::sync_channel(config.outbox_capacity.max(1));
+        let stats = Arc::new(SinkStats::default());
+
+        let writer_stats = Arc::clone(&stats);
+        let batch_size = config.batch_size.max(1);
+        let handle =
+            thread::spawn(move || write_batches(writer, receiver, batch_size, &writer_stats));
+
+        Ok(Journal {
+            target: JournalTarget::Batched(Outbox {
+                sender: Some(sender),
+                handle: Some(handle),
+                stats,
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// Stamps events using the clock, rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The counters of the background writer, when the journal is batched
+    pub fn stats(&self) -> Option<Arc<SinkStats>> {
+        match &self.target {
+            JournalTarget::Batched(outbox) => Some(Arc::clone(&outbox.stats)),
+            _ => None,
+        }
+    }
+
+    /// Stamps an event with the current time and writes it to the journal, does nothing when
+    /// journaling is disabled
+    pub fn record(&mut self, mut event: AccountEvent) -> SourceResult<()> {
+        event.recorded_at_ms = self.clock.now_ms();
+        self.write(JournalEntry::Event(event))
+    }
+
+    /// Stamps an admin operation with the current time and writes it to the journal, does nothing
+    /// when journaling is disabled
+    pub fn record_operation(&mut self, mut operation: AdminOperation) -> SourceResult<()> {
+        operation.recorded_at_ms = self.clock.now_ms();
+        self.write(JournalEntry::Operation(operation))
+    }
+
+    /// Writes an entry to the journal, or queues it for the background writer
+    fn write(&mut self, entry: JournalEntry) -> SourceResult<()> {
+        match &mut self.target {
+            JournalTarget::Disabled => Ok(()),
+            JournalTarget::File(writer) => write_event(writer, &entry),
+            JournalTarget::Batched(outbox) => {
+                let sender = outbox.sender.as_ref().ok_or_else(writer_stopped)?;
+
+                // the writer only hangs up after it's failed, flush reports the reason
+                outbox.stats.queued();
+                sender.send(entry).map_err(|_| writer_stopped())
+            }
+        }
+    }
+
+    /// Ensures every event has been written to the journal file. A batched journal waits for its
+    /// outbox to drain, and no more events can be recorded afterwards.
+    pub fn flush(&mut self) -> SourceResult<()> {
+        match &mut self.target {
+            JournalTarget::Disabled => Ok(()),
+            JournalTarget::File(writer) => writer
+                .flush()
+                .map_err(|err| SourceError::Io(err.to_string())),
+            JournalTarget::Batched(outbox) => {
+                // hanging up lets the writer finish once it's drained the outbox
+                outbox.sender.take();
+
+                match outbox.handle.take() {
+                    Some(handle) => handle.join().map_err(|_| writer_stopped())?,
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Opens a file for appending events to
+pub(crate) fn open_file(file_path: impl AsRef<Path>) -> SourceResult<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+
+    Ok(BufWriter::new(file))
+}
+
+/// Writes an event, or any other entry, as a line of JSON
+pub(crate) fn write_event(writer: &mut impl Write, event: &impl Serialize) -> SourceResult<()> {
+    serde_json::to_writer(&mut *writer, event).map_err(|err| SourceError::Io(err.to_string()))?;
+    writeln!(writer).map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// Drains the outbox until the journal hangs up, writing and flushing up to a batch of events at
+/// a time
+fn write_batches(
+    mut writer: BufWriter<File>,
+    receiver: Receiver<JournalEntry>,
+    batch_size: usize,
+    stats: &SinkStats,
+) -> SourceResult<()> {
+    let mut batch = Vec::with_capacity(batch_size);
+
+    while let Ok(entry) = receiver.recv() {
+        batch.push(entry);
+
+        // take whatever else is already waiting, without waiting for a full batch
+        batch.extend(receiver.try_iter().take(batch_size - 1));
+
+        for entry in batch.iter() {
+            write_event(&mut writer, entry)?;
+        }
+        writer
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        stats.written(batch.len());
+        batch.clear();
+    }
+
+    Ok(())
+}
+
+/// The error raised when the background writer is no longer running
+fn writer_stopped() -> SourceError {
+    SourceError::Io("the journal writer stopped unexpectedly".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::FixedClock;
+    use crate::config::EngineConfig;
+    use crate::engine::Engine;
+    use crate::journal::{AccountEvent, BatchConfig, Journal};
+    use crate::mapper::TransactionType;
+    use crate::test_helpers::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Error;
+    use std::sync::Arc;
+
+    // Tests that an event is journaled for every applied record, but not for rejected records,
+    // stamped by the journal's clock
+    #[test]
+    fn test_journal_applied_records() -> Result<(), Error> {
+        let (file_path_str, dir, file) = create_temp_file("journal.log")?;
+
+        let clock = FixedClock::new(1_700_000_000_000);
+        let journal = Journal::open(&file_path_str)
+            .unwrap()
+            .with_clock(Arc::new(clock));
+        let mut engine = Engine::new(HashMap::new(), EngineConfig::default(), journal);
+
+        let mut deposit = dummy_record(TransactionType::Deposit, Some(10.0));
+        deposit.client_id = 4;
+        let withdrawal = dummy_record(TransactionType::Withdrawal, Some(5.0));
+
+        engine.process(&deposit).unwrap();
+        assert!(engine.process(&withdrawal).is_err());
+        engine.into_accounts().unwrap();
+
+        let events: Vec<AccountEvent> = fs::read_to_string(&file_path_str)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![AccountEvent {
+                client: 4,
+                tx: 0,
+                transaction_type: TransactionType::Deposit,
+                amount: Some(10.0),
+                reason: None,
+                destination: None,
+                available: 10.0,
+                held: 0.0,
+                total: 10.0,
+                locked: false,
+                recorded_at_ms: 1_700_000_000_000,
+            }]
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a batched journal writes every event in order, and reports how it was written
+    #[test]
+    fn test_batched_journal() -> Result<(), Error> {
+        let (file_path_str, dir, file) = create_temp_file("journal.log")?;
+
+        let config = BatchConfig {
+            batch_size: 3,
+            outbox_capacity: 2,
+        };
+        let mut journal = Journal::open_batched(&file_path_str, config).unwrap();
+        let stats = journal.stats().unwrap();
+
+        for tx in 0..10 {
+            let mut record = dummy_record(TransactionType::Deposit, Some(1.0));
+            record.transaction_id = tx;
+            journal
+                .record(AccountEvent::new(&record, &Default::default()))
+                .unwrap();
+        }
+        journal.flush().unwrap();
+
+        let txs: Vec<u32> = fs::read_to_string(&file_path_str)?
+            .lines()
+            .map(|line| serde_json::from_str::<AccountEvent>(line).unwrap().tx)
+            .collect();
+        assert_eq!(txs, (0..10).collect::<Vec<u32>>());
+
+        let summary = stats.summary();
+        assert_eq!(summary.events, 10);
+        assert!(summary.batches >= 4 && summary.batches <= 10);
+        assert!(summary.max_lag >= 1);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
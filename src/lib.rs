@@ -0,0 +1,96 @@
+//! Plutus is a toy payments engine for reading and writing financial transactions. Records can be
+//! constructed directly (e.g. `Record::deposit(1, 1, 10.0)`) and applied to client accounts with
+//! an `Engine`, without going through the command line. For the simplest cases, `process_csv_str`
+//! and `process_reader` run a whole csv through the engine and return the resulting accounts.
+//!
+//! The types needed to embed the engine are re-exported here, the binary is a thin wrapper around
+//! `reader::run`.
+//!
+//! ```
+//! use plutus_engine::{Engine, Record};
+//!
+//! let mut engine = Engine::new(Default::default(), Default::default(), Default::default());
+//! engine.process(&Record::deposit(1, 1, 100.0)).unwrap();
+//! engine.process(&Record::withdrawal(1, 2, 40.0)).unwrap();
+//!
+//! let accounts = engine.into_accounts().unwrap();
+//! assert_eq!(accounts[&1].available_funds, 60.0);
+//! ```
+//!
+//! Failures are typed, so callers can match on why a record wasn't applied. A `LedgerError` means
+//! the record was rejected and its account is unchanged.
+//!
+//! ```
+//! use plutus_engine::{Engine, EngineError, LedgerError, Record};
+//!
+//! let mut engine = Engine::new(Default::default(), Default::default(), Default::default());
+//! engine.process(&Record::deposit(1, 1, 10.0)).unwrap();
+//! let result = engine.process(&Record::withdrawal(1, 2, 40.0));
+//! assert!(matches!(result, Err(EngineError::Ledger(LedgerError::InsufficientFunds(..)))));
+//! ```
+
+pub mod admin;
+pub mod alerts;
+pub mod annotate;
+pub mod anonymize;
+pub mod audit;
+pub mod breaker;
+pub mod cli;
+pub mod clients;
+pub mod clock;
+pub mod config;
+pub mod currency;
+pub mod cutover;
+pub mod emit;
+pub mod engine;
+pub mod error;
+pub mod escheat;
+pub mod fields;
+pub mod format;
+pub mod generator;
+pub mod graph;
+pub mod handover;
+pub mod idempotency;
+pub mod index;
+pub mod journal;
+pub mod lint;
+pub mod lock;
+pub mod logging;
+pub mod losses;
+pub mod mapper;
+pub mod merge;
+pub mod metadata;
+pub mod migrate;
+pub mod olap;
+pub mod owners;
+pub mod parser;
+pub mod partition;
+pub mod profile;
+pub mod quarantine;
+pub mod reader;
+pub mod reconcile;
+pub mod redact;
+pub mod reorder;
+pub mod results;
+pub mod retry;
+pub mod runbook;
+pub mod screening;
+pub mod server;
+pub mod shared;
+pub mod sink;
+pub mod spill;
+pub mod state;
+pub mod stats;
+pub mod storage;
+pub mod tcp;
+pub mod telemetry;
+mod test_helpers;
+pub mod timestamp;
+pub mod trends;
+pub mod watch;
+
+pub use config::EngineConfig;
+pub use engine::Engine;
+pub use error::{EngineError, EngineResult, ExitReport, LedgerError, SourceError};
+pub use mapper::{Account, AccountRecord, Record, TransactionType};
+pub use reader::{process_csv_str, process_reader};
@@ -0,0 +1,252 @@
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::{Record, TransactionType};
+use csv::{ReaderBuilder, Trim};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The most decimal places an amount can have, the precision balances are kept to
+const MAX_DECIMALS: usize = 4;
+
+/// The problems found in a file of transactions, gathered without applying any of them
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Lint {
+    /// The number of rows, excluding the header
+    pub rows: usize,
+
+    /// How many problems of each kind were found
+    pub counts: BTreeMap<IssueKind, usize>,
+
+    /// Every problem that was found, in the order of the rows
+    pub issues: Vec<LintIssue>,
+}
+
+impl Lint {
+    /// Whether the file is free of problems
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// A single problem with a row
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LintIssue {
+    /// The line the row was read from
+    pub line: u64,
+
+    /// What kind of problem it is
+    pub kind: IssueKind,
+
+    /// A description of the problem
+    pub message: String,
+}
+
+/// The kinds of problem a row can have
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IssueKind {
+    /// A value is missing, or isn't of its column's type (e.g. an unknown type or a non numeric tx)
+    Malformed,
+
+    /// An amount has more than 4 decimal places
+    TooPrecise,
+
+    /// A deposit, withdrawal or transfer has a negative amount
+    NegativeAmount,
+
+    /// A row references a transaction that isn't created earlier in the file
+    UnknownTransaction,
+
+    /// A row references a transaction that belongs to another client
+    ClientMismatch,
+
+    /// A deposit, withdrawal or transfer reuses the tx id of an earlier one
+    DuplicateTransaction,
+}
+
+/// Reads every row of a csv and checks it for problems: values that aren't of their column's
+/// type, amounts that are negative or too precise, and rows referencing transactions that don't
+/// exist. Every row is checked, rather than stopping at the first problem.
+pub fn lint_csv(file_path: impl AsRef<Path>) -> SourceResult<Lint> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::Fields)
+        .flexible(true)
+        .from_path(file_path)
+        .map_err(SourceError::from)?;
+    let headers = reader.headers().map_err(SourceError::from)?.clone();
+    let amount_column = headers.iter().position(|header| header == "amount");
+
+    // the client of every transaction created so far, keyed by its tx id
+    let mut created: HashMap<u32, u16> = HashMap::new();
+    let mut issues = vec![];
+    let mut rows = 0;
+
+    for result in reader.records() {
+        let row = result.map_err(SourceError::from)?;
+        let line = row.position().map_or(0, |position| position.line());
+        let mut issue = |kind, message| {
+            issues.push(LintIssue {
+                line,
+                kind,
+                message,
+            })
+        };
+        rows += 1;
+
+        let record: Record = match row.deserialize(Some(&headers)) {
+            Ok(record) => record,
+            Err(err) => {
+                issue(IssueKind::Malformed, err.to_string());
+                continue;
+            }
+        };
+
+        // the precision is counted on the text, as parsing the amount would lose it
+        let amount = amount_column.and_then(|index| row.get(index)).unwrap_or("");
+        let places = amount
+            .split_once('.')
+            .map_or(0, |(_, decimals)| decimals.len());
+        if places > MAX_DECIMALS {
+            let message = format!(
+                "amount {} has {} decimal places, more than {}",
+                amount, places, MAX_DECIMALS
+            );
+            issue(IssueKind::TooPrecise, message);
+        }
+
+        // adjustments are the only transactions that can take funds away with a negative amount
+        let negative = record.amount.is_some_and(|amount| amount < 0.0);
+        if negative && record.transaction_type != TransactionType::Adjustment {
+            let message = format!("{} has a negative amount", record.transaction_type.name());
+            issue(IssueKind::NegativeAmount, message);
+        }
+
+        let tx = record.transaction_id;
+        if record.transaction_type.references_transaction() {
+            match created.get(&tx) {
+                None => {
+                    let message = format!("tx {} isn't created earlier in the file", tx);
+                    issue(IssueKind::UnknownTransaction, message);
+                }
+                Some(&client) if client != record.client_id => {
+                    let message = format!("tx {} belongs to client {}", tx, client);
+                    issue(IssueKind::ClientMismatch, message);
+                }
+                Some(_) => {}
+            }
+        } else if record.transaction_type.creates_transaction() {
+            match created.entry(tx) {
+                Entry::Vacant(entry) => {
+                    entry.insert(record.client_id);
+                }
+                Entry::Occupied(_) => {
+                    let message = format!("tx {} is already used by an earlier transaction", tx);
+                    issue(IssueKind::DuplicateTransaction, message);
+                }
+            }
+        }
+    }
+
+    let mut counts = BTreeMap::new();
+    for issue in &issues {
+        *counts.entry(issue.kind).or_insert(0) += 1;
+    }
+
+    Ok(Lint {
+        rows,
+        counts,
+        issues,
+    })
+}
+
+/// Writes the problems found in a file as JSON to the output file, or std out when one isn't
+/// provided
+pub fn write_lint(lint: &Lint, output_path: Option<&Path>) -> SourceResult<()> {
+    let mut writer: Box<dyn Write> = match output_path {
+        Some(output_path) => {
+            let file = File::create(output_path).map_err(|err| SourceError::Io(err.to_string()))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(io::stdout()),
+    };
+
+    serde_json::to_writer_pretty(&mut writer, lint)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+    writeln!(writer).map_err(|err| SourceError::Io(err.to_string()))?;
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::{lint_csv, IssueKind};
+    use crate::test_helpers::*;
+    use std::collections::BTreeMap;
+    use std::io::Error;
+
+    // Tests that every kind of problem is found, on the line it's on, without stopping early
+    #[test]
+    fn test_lint_csv() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,1.5",
+            "deposit,1,2,10.12345",
+            "withdrawal,2,3,-4",
+            "adjustment,2,4,-4",
+            "deposit,x,5,2",
+            "reversal,1,6,",
+            "dispute,1,7,",
+            "dispute,2,1,",
+            "deposit,2,1,3",
+            "resolve,1,1,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let lint = lint_csv(&file_path_str).unwrap();
+
+        assert_eq!(lint.rows, 10);
+        assert!(!lint.is_clean());
+        let lines: Vec<(u64, IssueKind)> = lint
+            .issues
+            .iter()
+            .map(|issue| (issue.line, issue.kind))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (3, IssueKind::TooPrecise),
+                (4, IssueKind::NegativeAmount),
+                (6, IssueKind::Malformed),
+                (7, IssueKind::Malformed),
+                (8, IssueKind::UnknownTransaction),
+                (9, IssueKind::ClientMismatch),
+                (10, IssueKind::DuplicateTransaction),
+            ]
+        );
+        assert_eq!(lint.counts[&IssueKind::Malformed], 2);
+        assert_eq!(
+            lint.issues[4].message,
+            "tx 7 isn't created earlier in the file"
+        );
+
+        drop(file);
+        dir.close()?;
+
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec!["deposit,1,1,1.5", "dispute,1,1,", "resolve,1,1,"];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let lint = lint_csv(&file_path_str).unwrap();
+        assert!(lint.is_clean());
+        assert_eq!((lint.rows, lint.counts), (3, BTreeMap::new()));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::{SourceError, SourceResult};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+/// What to do when another run holds the lock on a state file
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LockMode {
+    /// Wait until the other run releases it
+    #[default]
+    Wait,
+
+    /// Fail straight away
+    NoWait,
+}
+
+/// Advisory locks on the state files used by a run, so concurrent runs can't both mutate the same
+/// state and silently lose updates. State that's only loaded is locked shared, so runs that only
+/// read it can overlap, while state that's saved is locked exclusively. The locks are held on a
+/// `.lock` file next to each state file, and released when this is dropped.
+#[derive(Debug)]
+pub struct StateLock {
+    /// The lock files, which hold the locks while they're open
+    _files: Vec<File>,
+}
+
+impl StateLock {
+    /// Locks the state files of a run. The same path is only locked once, exclusively when it's
+    /// saved to.
+    pub fn acquire(
+        load_path: Option<&Path>,
+        save_path: Option<&Path>,
+        mode: LockMode,
+    ) -> SourceResult<Self> {
+        let mut paths: Vec<(&Path, bool)> =
+            save_path.map(|path| (path, true)).into_iter().collect();
+        if let Some(load_path) = load_path.filter(|path| Some(*path) != save_path) {
+            paths.push((load_path, false));
+        }
+
+        // runs always lock in the same order, so two of them can't wait on each other
+        paths.sort();
+
+        let files = paths
+            .into_iter()
+            .map(|(path, exclusive)| lock(path, exclusive, mode))
+            .collect::<SourceResult<_>>()?;
+
+        Ok(StateLock { _files: files })
+    }
+}
+
+/// Opens the lock file of a state file and locks it
+fn lock(state_path: &Path, exclusive: bool, mode: LockMode) -> SourceResult<File> {
+    let lock_path = lock_path(state_path);
+    let lock_error = |err: String| SourceError::State(lock_path.display().to_string(), err);
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|err| lock_error(err.to_string()))?;
+
+    let result = match (mode, exclusive) {
+        (LockMode::Wait, true) => file.lock().map_err(TryLockError::Error),
+        (LockMode::Wait, false) => file.lock_shared().map_err(TryLockError::Error),
+        (LockMode::NoWait, true) => file.try_lock(),
+        (LockMode::NoWait, false) => file.try_lock_shared(),
+    };
+
+    match result {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(SourceError::Locked(state_path.display().to_string())),
+        Err(TryLockError::Error(err)) => Err(lock_error(err.to_string())),
+    }
+}
+
+/// The path of the lock file for a state file, e.g. state.bin.lock
+fn lock_path(state_path: &Path) -> PathBuf {
+    let mut lock_path = OsString::from(state_path.as_os_str());
+    lock_path.push(".lock");
+
+    PathBuf::from(lock_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SourceError;
+    use crate::lock::{LockMode, StateLock};
+    use std::io::Error;
+    use tempfile::tempdir;
+
+    // Tests that a state file being saved can't be locked by another run until it's released,
+    // while state that's only loaded can be shared
+    #[test]
+    fn test_state_lock() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let state_path = dir.path().join("state.bin");
+        let other_path = dir.path().join("other.bin");
+
+        let saving =
+            StateLock::acquire(Some(&state_path), Some(&state_path), LockMode::NoWait).unwrap();
+        let loading = StateLock::acquire(Some(&state_path), None, LockMode::NoWait);
+        assert_eq!(
+            loading.unwrap_err(),
+            SourceError::Locked(state_path.display().to_string())
+        );
+
+        drop(saving);
+        let first =
+            StateLock::acquire(Some(&state_path), Some(&other_path), LockMode::NoWait).unwrap();
+        let second = StateLock::acquire(Some(&state_path), None, LockMode::NoWait);
+        assert!(second.is_ok());
+        assert!(StateLock::acquire(None, Some(&other_path), LockMode::NoWait).is_err());
+
+        drop(first);
+        drop(second);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use crate::error::EngineResult;
+use crate::telemetry::{otlp_exporter, Exporter};
+use std::io;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+/// The environment variable that sets which spans and events are logged, when -v isn't provided
+pub const LOG_ENV: &str = "RUST_LOG";
+
+/// Logs the engine's spans and events to std err, so it can be seen why a record did what it did.
+/// -v logs the debug events (e.g. a dispute of an unknown transaction being ignored) and -vv
+/// every span and trace event too. Without either, RUST_LOG is used, or only warnings are logged
+/// when it isn't set. Logging can only be set up once, so later calls leave it as it was.
+///
+/// With an OTLP endpoint, the spans of the servers' stages are exported to it too, whatever is
+/// logged, until the exporter that's returned is dropped.
+pub fn init_tracing(verbosity: u8, otlp_endpoint: Option<&str>) -> EngineResult<Option<Exporter>> {
+    let filter = match verbosity {
+        0 => EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new("warn")),
+        1 => EnvFilter::new("warn,plutus_engine=debug"),
+        _ => EnvFilter::new("warn,plutus_engine=trace"),
+    };
+    let (layer, exporter) = match otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, exporter) = otlp_exporter(endpoint)?;
+            (Some(layer), Some(exporter))
+        }
+        None => (None, None),
+    };
+
+    let _ = tracing_subscriber::registry()
+        .with(layer)
+        .with(fmt::layer().with_writer(io::stderr).with_filter(filter))
+        .try_init();
+
+    Ok(exporter)
+}
@@ -0,0 +1,165 @@
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::serialize_with_precision;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// The ledger account chargeback losses are posted to, when one isn't configured
+pub const DEFAULT_LOSS_ACCOUNT: &str = "chargeback-losses";
+
+/// The funds reversed out of client accounts by chargebacks during a run
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LossLedger {
+    /// The losses of each client that had a chargeback
+    losses: BTreeMap<u16, ClientLoss>,
+}
+
+/// The chargebacks of a single client
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ClientLoss {
+    /// The number of chargebacks that reversed funds
+    pub chargebacks: u32,
+
+    /// The funds that were reversed out of the client's total
+    pub amount: f32,
+}
+
+/// A row of the loss report, posting a client's losses against the loss ledger account
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LossPosting<'a> {
+    /// The ledger account the loss is posted to
+    pub ledger_account: &'a str,
+
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The number of chargebacks that reversed funds
+    pub chargebacks: u32,
+
+    /// The funds that were reversed out of the client's total
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub amount: f32,
+}
+
+impl LossLedger {
+    /// Records a chargeback that reversed the amount out of a client's total funds
+    pub fn record(&mut self, client_id: u16, amount: f32) {
+        let loss = self.losses.entry(client_id).or_default();
+        loss.chargebacks += 1;
+        loss.amount += amount;
+    }
+
+    /// Whether there weren't any chargebacks
+    pub fn is_empty(&self) -> bool {
+        self.losses.is_empty()
+    }
+
+    /// The losses of a client, when they had a chargeback
+    pub fn get(&self, client_id: u16) -> Option<&ClientLoss> {
+        self.losses.get(&client_id)
+    }
+
+    /// The same losses, with each client id mapped to another. The mapping must not give two
+    /// clients the same id.
+    pub fn map_clients(&self, map: impl Fn(u16) -> u16) -> LossLedger {
+        LossLedger {
+            losses: self
+                .losses
+                .iter()
+                .map(|(client_id, loss)| (map(*client_id), *loss))
+                .collect(),
+        }
+    }
+
+    /// The funds reversed by every chargeback
+    pub fn total(&self) -> f32 {
+        self.losses.values().map(|loss| loss.amount).sum()
+    }
+
+    /// The number of chargebacks that reversed funds
+    pub fn chargebacks(&self) -> u32 {
+        self.losses.values().map(|loss| loss.chargebacks).sum()
+    }
+
+    /// The number of clients that had a chargeback
+    pub fn clients(&self) -> usize {
+        self.losses.len()
+    }
+
+    /// The postings of each client's losses against the ledger account, ordered by client id
+    pub fn postings<'a>(&self, ledger_account: &'a str) -> Vec<LossPosting<'a>> {
+        self.losses
+            .iter()
+            .map(|(client_id, loss)| LossPosting {
+                ledger_account,
+                client: *client_id,
+                chargebacks: loss.chargebacks,
+                amount: loss.amount,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for LossLedger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} reversed by {} chargebacks across {} clients",
+            self.total(),
+            self.chargebacks(),
+            self.clients()
+        )
+    }
+}
+
+/// Writes the losses of each client, posted against the ledger account, to a csv
+pub fn write_loss_report(
+    file_path: impl AsRef<Path>,
+    losses: &LossLedger,
+    ledger_account: &str,
+) -> SourceResult<()> {
+    let mut writer = csv::Writer::from_path(file_path).map_err(SourceError::from)?;
+    for posting in losses.postings(ledger_account) {
+        writer.serialize(posting).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::losses::{write_loss_report, LossLedger};
+    use crate::test_helpers::*;
+    use approx::assert_relative_eq;
+    use std::fs;
+    use std::io::Error;
+
+    // Tests that each client's losses are posted against the ledger account, ordered by client
+    #[test]
+    fn test_write_loss_report() -> Result<(), Error> {
+        let (file_path, dir, file) = create_temp_file("loss_report.csv")?;
+
+        let mut losses = LossLedger::default();
+        losses.record(3, 10.5);
+        losses.record(1, 2.25);
+        losses.record(3, 4.0);
+        assert_relative_eq!(losses.total(), 16.75);
+
+        write_loss_report(&file_path, &losses, "writeoffs").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file_path)?,
+            "ledger_account,client,chargebacks,amount\n\
+             writeoffs,1,1,2.25\n\
+             writeoffs,3,2,14.5\n"
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
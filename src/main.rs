@@ -1,13 +1,10 @@
+use plutus_engine::reader::run;
 use std::process;
-use crate::reader::run;
-
-mod mapper;
-mod test_helpers;
-mod reader;
 
 fn main() {
-    if let Err(err) = run() {
-        eprintln!("Error executing run! {}", err);
-        process::exit(1);
-    }
+    let report = run();
+
+    // report any rejected records and the error that terminated execution, if there was one
+    eprint!("{}", report);
+    process::exit(report.exit_code());
 }
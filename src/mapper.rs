@@ -1,36 +1,15 @@
+use crate::config::{FrozenAccountPolicy, WithdrawalPolicy};
+use crate::currency::Currency;
+use crate::error::{CliError, CliResult, LedgerError, LedgerResult};
+use crate::timestamp::deserialize_timestamp;
 use round::round;
 use serde::{Deserialize, Serialize, Serializer};
-use std::collections::HashMap;
-use thiserror::Error;
-
-/// We should only be reading data from .csv files
-pub const VALID_FILE_EXTENSION: &str = "csv";
-
-/// A generic result type for ReaderError variants
-pub type ReaderResult<T> = anyhow::Result<T, ReaderError>;
-
-/// Custom error that wraps relevant reader errors
-#[derive(Debug, Error, PartialEq)]
-pub enum ReaderError {
-    /// The file does not have a csv extension (.csv)
-    #[error("The file must have a csv extension")]
-    InvalidExtensionError,
-
-    /// Withdrawal amount is bigger than available funds
-    #[error("Failed withdrawal, amount: {0} is greater than available funds: {1}")]
-    InsufficientFundsError(f32, f32),
-
-    /// A file path to read transaction data from, wasn't provided
-    #[error("An argument for file path must be provided, like so: cargo run -- some_file_path")]
-    MissingArgError,
-
-    /// The file doesn't exist
-    #[error("Incorrect file path argument provided: {0}")]
-    NonExistentFileError(String),
-}
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use tracing::debug;
 
 /// The various types of transactions
-#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     /// A credit to the client's asset account
@@ -47,20 +26,230 @@ pub enum TransactionType {
 
     /// The final state of a dispute and represents the client reversing a transaction
     Chargeback,
+
+    /// An admin correction that directly credits (positive amount) or debits (negative amount)
+    /// the available funds, outside of the deposit and withdrawal flow
+    Adjustment,
+
+    /// A cancellation of a deposit or withdrawal that hasn't settled yet, reversing it as if it
+    /// never happened. Unlike a dispute, nothing is contested by the client.
+    Void,
+
+    /// Disputed funds that were held for too long, and moved to the escheatment holding account.
+    /// Only the escheatment sweep puts transactions in this state, rows of this type are rejected.
+    Escheated,
+
+    /// A move of funds from the client's account to the destination client's, which debits one
+    /// and credits the other together, or changes neither
+    Transfer,
+
+    /// A merchant's refund of an earlier withdrawal, crediting its amount back to the client. A
+    /// withdrawal can only be refunded once.
+    Refund,
+
+    /// An admin action that lifts whatever lock is on the account, e.g. once a chargeback has
+    /// been investigated
+    Unlock,
+}
+
+impl TransactionType {
+    /// The name of the type, as it's written in transaction files
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Void => "void",
+            TransactionType::Escheated => "escheated",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Refund => "refund",
+            TransactionType::Unlock => "unlock",
+        }
+    }
+
+    /// Whether the transaction can only be processed when admin operations are allowed
+    pub fn is_admin(&self) -> bool {
+        matches!(self, TransactionType::Adjustment | TransactionType::Unlock)
+    }
+
+    /// Whether the transaction moves funds under its own id, which no other deposit, withdrawal
+    /// or transfer can reuse
+    pub fn creates_transaction(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+        )
+    }
+
+    /// Whether the transaction refers to an earlier one by its id, rather than moving funds itself
+    pub fn references_transaction(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Void
+                | TransactionType::Refund
+        )
+    }
+}
+
+/// Whether an account is locked, why, and what locked it. The kind of lock decides which
+/// transactions are still permitted.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockState {
+    /// Every transaction is permitted
+    #[default]
+    Unlocked,
+
+    /// A chargeback of the transaction locked the account, funds can't be withdrawn
+    ChargebackLock { tx: u32 },
+
+    /// A risk rule locked the account, funds can't be withdrawn
+    RiskLock { rule: String },
+
+    /// An operator locked the account, only disputes and admin transactions are permitted
+    AdminLock { rule: String },
+
+    /// The account is held for compliance review, only admin transactions are permitted
+    ComplianceHold { rule: String },
+
+    /// A fraud rule quarantined the account, funds can't be withdrawn and deposits are held until
+    /// an operator releases it
+    Quarantine { rule: String, held: Vec<u32> },
+}
+
+impl LockState {
+    /// Whether the account is locked
+    pub fn is_locked(&self) -> bool {
+        *self != LockState::Unlocked
+    }
+
+    /// The name of the lock, as it's output
+    pub fn name(&self) -> &'static str {
+        match self {
+            LockState::Unlocked => "unlocked",
+            LockState::ChargebackLock { .. } => "chargeback-lock",
+            LockState::RiskLock { .. } => "risk-lock",
+            LockState::AdminLock { .. } => "admin-lock",
+            LockState::ComplianceHold { .. } => "compliance-hold",
+            LockState::Quarantine { .. } => "quarantine",
+        }
+    }
+
+    /// The transaction or rule that locked the account
+    pub fn trigger(&self) -> Option<String> {
+        match self {
+            LockState::Unlocked => None,
+            LockState::ChargebackLock { tx } => Some(format!("tx {}", tx)),
+            LockState::RiskLock { rule }
+            | LockState::AdminLock { rule }
+            | LockState::ComplianceHold { rule }
+            | LockState::Quarantine { rule, .. } => Some(rule.clone()),
+        }
+    }
+
+    /// Whether a transaction of the type can be applied while the account is in this state. The
+    /// policy decides whether accounts frozen by a chargeback or a risk rule accept deposits.
+    /// Transfers are only applied between accounts that aren't locked.
+    pub fn permits(&self, transaction_type: TransactionType, policy: FrozenAccountPolicy) -> bool {
+        match self {
+            LockState::Unlocked => true,
+            LockState::ChargebackLock { .. } | LockState::RiskLock { .. } => match policy {
+                FrozenAccountPolicy::AllowDeposits => !matches!(
+                    transaction_type,
+                    TransactionType::Withdrawal | TransactionType::Transfer
+                ),
+                FrozenAccountPolicy::RejectAll => !matches!(
+                    transaction_type,
+                    TransactionType::Deposit
+                        | TransactionType::Withdrawal
+                        | TransactionType::Void
+                        | TransactionType::Transfer
+                        | TransactionType::Refund
+                ),
+            },
+            LockState::AdminLock { .. } => !matches!(
+                transaction_type,
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Void
+                    | TransactionType::Transfer
+                    | TransactionType::Refund
+            ),
+            LockState::ComplianceHold { .. } => transaction_type.is_admin(),
+            LockState::Quarantine { .. } => !matches!(
+                transaction_type,
+                TransactionType::Withdrawal | TransactionType::Transfer
+            ),
+        }
+    }
 }
 
 /// The relevant details of a transaction
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Transaction {
     /// A decimal value with a precision of up to four places past the decimal
     pub amount: f32,
 
     /// The type of transaction (e.g. dispute)
     pub current_state: TransactionType,
+
+    /// The portion of the amount that's held, when only part of the transaction was disputed
+    #[serde(default)]
+    pub disputed_amount: Option<f32>,
+
+    /// When the transaction's funds were first held by its current dispute, as a unix timestamp.
+    /// None when it isn't disputed, or the dispute didn't have a timestamp.
+    #[serde(default)]
+    pub held_since: Option<u64>,
+
+    /// The type the transaction was applied as, a deposit or a withdrawal, which decides how its
+    /// disputes move funds. State that doesn't have it treats the transaction as a deposit.
+    #[serde(default = "deposit_type")]
+    pub transaction_type: TransactionType,
+
+    /// When the deposit or withdrawal occurred, as a unix timestamp. None when its row didn't
+    /// have one.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+impl Transaction {
+    /// The amount that's held while the transaction is disputed
+    pub fn held_amount(&self) -> f32 {
+        self.disputed_amount.unwrap_or(self.amount)
+    }
+
+    /// Whether a dispute can be opened on the transaction. A transaction can go through any
+    /// number of dispute cycles, disputed again once its last dispute was resolved, but only one
+    /// dispute can be open at a time, and a chargeback, void, refund or escheatment is final.
+    pub fn is_disputable(&self) -> bool {
+        matches!(
+            self.current_state,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Resolve
+        )
+    }
+
+    /// Whether the transaction was a withdrawal. Disputing a withdrawal contests funds that have
+    /// already left the account, so they're held on top of the available funds rather than taken
+    /// out of them, and a chargeback credits them back to the client.
+    pub fn is_withdrawal(&self) -> bool {
+        self.transaction_type == TransactionType::Withdrawal
+    }
+}
+
+/// The type of transactions in state that doesn't record it, see Transaction::transaction_type
+fn deposit_type() -> TransactionType {
+    TransactionType::Deposit
 }
 
 /// The structure of each row of data in the file
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Record {
     /// The type of transaction that occurred (e.g. deposit)
     #[serde(rename = "type")]
@@ -77,10 +266,33 @@ pub struct Record {
     /// A decimal value with a precision of up to four places past the decimal
     #[serde(default)]
     pub amount: Option<f32>,
+
+    /// The reason code of an admin transaction (e.g. adjustment)
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// When the transaction occurred in seconds since the unix epoch, for feeds that provide it.
+    /// It's read from epoch seconds, epoch milliseconds or RFC 3339, see parse_timestamp. Feeds
+    /// often only have second granularity, so many transactions can share a timestamp.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_timestamp",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timestamp: Option<u64>,
+
+    /// A key the partner assigns to the record, so a re-sent record is only ever applied once
+    /// even when it's given a new transaction id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+
+    /// The client a transfer's funds are moved to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<u16>,
 }
 
 /// The details of the client account that's output to std out
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct AccountRecord {
     /// The unique ID of the client
     pub client: u16,
@@ -101,8 +313,418 @@ pub struct AccountRecord {
     pub locked: bool,
 }
 
+impl Record {
+    /// Creates a deposit record
+    pub fn deposit(client_id: u16, transaction_id: u32, amount: f32) -> Self {
+        Record::new(
+            TransactionType::Deposit,
+            client_id,
+            transaction_id,
+            Some(amount),
+        )
+    }
+
+    /// Creates a withdrawal record
+    pub fn withdrawal(client_id: u16, transaction_id: u32, amount: f32) -> Self {
+        Record::new(
+            TransactionType::Withdrawal,
+            client_id,
+            transaction_id,
+            Some(amount),
+        )
+    }
+
+    /// Creates a dispute record, referencing a previous transaction
+    pub fn dispute(client_id: u16, transaction_id: u32) -> Self {
+        Record::new(TransactionType::Dispute, client_id, transaction_id, None)
+    }
+
+    /// Creates a resolve record, referencing a disputed transaction
+    pub fn resolve(client_id: u16, transaction_id: u32) -> Self {
+        Record::new(TransactionType::Resolve, client_id, transaction_id, None)
+    }
+
+    /// Creates a chargeback record, referencing a disputed transaction
+    pub fn chargeback(client_id: u16, transaction_id: u32) -> Self {
+        Record::new(TransactionType::Chargeback, client_id, transaction_id, None)
+    }
+
+    /// Creates a void record, referencing a deposit or withdrawal that hasn't settled
+    pub fn void(client_id: u16, transaction_id: u32) -> Self {
+        Record::new(TransactionType::Void, client_id, transaction_id, None)
+    }
+
+    /// Creates a refund record, referencing an earlier withdrawal
+    pub fn refund(client_id: u16, transaction_id: u32) -> Self {
+        Record::new(TransactionType::Refund, client_id, transaction_id, None)
+    }
+
+    /// Creates an adjustment record along with the reason code explaining it
+    pub fn adjustment(
+        client_id: u16,
+        transaction_id: u32,
+        amount: f32,
+        reason: impl Into<String>,
+    ) -> Self {
+        Record {
+            reason: Some(reason.into()),
+            ..Record::new(
+                TransactionType::Adjustment,
+                client_id,
+                transaction_id,
+                Some(amount),
+            )
+        }
+    }
+
+    /// Creates a transfer record, moving the amount to the destination client
+    pub fn transfer(client_id: u16, transaction_id: u32, amount: f32, destination: u16) -> Self {
+        Record {
+            destination: Some(destination),
+            ..Record::new(
+                TransactionType::Transfer,
+                client_id,
+                transaction_id,
+                Some(amount),
+            )
+        }
+    }
+
+    /// The side of a transfer that's applied to its destination, none when the record isn't a
+    /// transfer. Errors when the transfer doesn't name a client other than its own.
+    pub fn transfer_credit(&self) -> LedgerResult<Option<Record>> {
+        if self.transaction_type != TransactionType::Transfer {
+            return Ok(None);
+        }
+
+        match self.destination {
+            Some(destination) if destination != self.client_id => Ok(Some(Record {
+                client_id: destination,
+                ..self.clone()
+            })),
+            _ => Err(LedgerError::InvalidDestination(self.transaction_id)),
+        }
+    }
+
+    /// Whether the record is the side of a transfer that's applied to its destination
+    pub fn is_transfer_credit(&self) -> bool {
+        self.transaction_type == TransactionType::Transfer
+            && self.destination == Some(self.client_id)
+    }
+
+    /// Creates an unlock record along with the reason code explaining it
+    pub fn unlock(client_id: u16, transaction_id: u32, reason: impl Into<String>) -> Self {
+        Record {
+            reason: Some(reason.into()),
+            ..Record::new(TransactionType::Unlock, client_id, transaction_id, None)
+        }
+    }
+
+    /// Creates a record without a reason code
+    fn new(
+        transaction_type: TransactionType,
+        client_id: u16,
+        transaction_id: u32,
+        amount: Option<f32>,
+    ) -> Self {
+        Record {
+            transaction_type,
+            client_id,
+            transaction_id,
+            amount,
+            reason: None,
+            timestamp: None,
+            idempotency_key: None,
+            destination: None,
+        }
+    }
+}
+
+impl AccountRecord {
+    /// Creates the record that's output for a client's account
+    pub fn new(client: u16, account: &Account) -> Self {
+        AccountRecord {
+            client,
+            available: account.available_funds,
+            held: account.held_funds,
+            total: account.total_funds,
+            locked: account.lock_state.is_locked(),
+        }
+    }
+
+    /// Rounds the balances to the currency's minor unit
+    pub fn in_currency(self, currency: &Currency) -> Self {
+        AccountRecord {
+            available: currency.round(self.available),
+            held: currency.round(self.held),
+            total: currency.round(self.total),
+            ..self
+        }
+    }
+}
+
+/// The version of the account output. Version 2 adds why each account is locked.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OutputVersion {
+    /// The original columns, with only whether the account is locked
+    #[default]
+    V1,
+
+    /// The original columns, followed by the lock state and what triggered it
+    V2,
+}
+
+impl FromStr for OutputVersion {
+    type Err = CliError;
+
+    fn from_str(version: &str) -> CliResult<Self> {
+        match version.to_lowercase().as_str() {
+            "1" | "v1" => Ok(OutputVersion::V1),
+            "2" | "v2" => Ok(OutputVersion::V2),
+            _ => Err(CliError::InvalidValue(
+                "--output-version".to_string(),
+                version.to_string(),
+            )),
+        }
+    }
+}
+
+/// The formats the account output can be written in
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Comma separated values, with a header row
+    #[default]
+    Csv,
+
+    /// A JSON array of the accounts
+    Json,
+
+    /// A JSON object per line for each account
+    Jsonl,
+}
+
+impl FromStr for OutputFormat {
+    type Err = CliError;
+
+    fn from_str(format: &str) -> CliResult<Self> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::Jsonl),
+            _ => Err(CliError::UnknownFormat(format.to_string())),
+        }
+    }
+}
+
+/// The character that separates the whole and fractional parts of amounts that are read
+/// leniently, see normalize_amount
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecimalSeparator {
+    /// 1,234.56, where commas group the thousands
+    Point,
+
+    /// 1.234,56, where points group the thousands
+    Comma,
+}
+
+impl DecimalSeparator {
+    /// The separator itself
+    fn char(&self) -> char {
+        match self {
+            DecimalSeparator::Point => '.',
+            DecimalSeparator::Comma => ',',
+        }
+    }
+
+    /// Whether the character groups the thousands of an amount, which is the other separator or
+    /// any of the spaces and marks locales use for it
+    fn groups(&self, c: char) -> bool {
+        c != self.char() && matches!(c, '.' | ',' | ' ' | '\'' | '_' | '\u{a0}' | '\u{202f}')
+    }
+}
+
+impl FromStr for DecimalSeparator {
+    type Err = CliError;
+
+    fn from_str(separator: &str) -> CliResult<Self> {
+        match separator.to_lowercase().as_str() {
+            "point" | "." => Ok(DecimalSeparator::Point),
+            "comma" | "," => Ok(DecimalSeparator::Comma),
+            _ => Err(CliError::InvalidValue(
+                "--lenient-amounts".to_string(),
+                separator.to_string(),
+            )),
+        }
+    }
+}
+
+/// Rewrites an amount written for people (e.g. `$1,234.56` or `-1.234,56 €`) as a plain decimal
+/// (`1234.56`), ready to be converted to an amount. Currency symbols and codes are only stripped
+/// from either end, so none when anything but digits and separators is left between them.
+///
+/// A grouping character is only accepted when it splits the whole part into groups of exactly
+/// three digits (after a first group of one to three), all with the same character, so an amount
+/// whose separators are ambiguous (e.g. `1,50` when the decimal separator is a point) is none
+/// rather than being read as something a hundred times larger.
+pub fn normalize_amount(value: &str, decimal: DecimalSeparator) -> Option<String> {
+    // spaces can group the thousands too, but only the ones between digits are kept
+    let is_symbol = |c: char| {
+        let separator = matches!(c, '.' | ',' | '\'' | '_');
+        !c.is_ascii_digit() && c != '-' && (c.is_whitespace() || !separator)
+    };
+    let value = value.trim_matches(is_symbol);
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => ("-", value.trim_start_matches(is_symbol)),
+        None => ("", value),
+    };
+
+    let (whole, fraction) = match value.split_once(decimal.char()) {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (value, None),
+    };
+    if fraction.is_some_and(|fraction| !fraction.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+
+    let whole = match whole.chars().find(|c| !c.is_ascii_digit()) {
+        None => whole.to_string(),
+        Some(separator) if decimal.groups(separator) => {
+            let mut groups = whole.split(separator);
+            let first = groups.next().unwrap_or_default();
+            let digits = |group: &str| group.chars().all(|c| c.is_ascii_digit());
+            let grouped = (1..=3).contains(&first.len())
+                && digits(first)
+                && groups.all(|group| group.len() == 3 && digits(group));
+            if !grouped {
+                return None;
+            }
+            whole.replace(separator, "")
+        }
+        Some(_) => return None,
+    };
+
+    let amount = match fraction {
+        Some(fraction) => format!("{}{}.{}", sign, whole, fraction),
+        None => format!("{}{}", sign, whole),
+    };
+    amount
+        .bytes()
+        .any(|byte| byte.is_ascii_digit())
+        .then_some(amount)
+}
+
+/// The details of the client account that's output to std out by version 2 of the output
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AccountRecordV2 {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The available funds in the account
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub available: f32,
+
+    /// The held funds in the account
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub held: f32,
+
+    /// The total funds in the account
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub total: f32,
+
+    /// Whether the account is locked
+    pub locked: bool,
+
+    /// Why the account is locked (e.g. chargeback-lock), or unlocked
+    pub lock_state: &'static str,
+
+    /// The transaction or rule that locked the account
+    pub lock_trigger: Option<String>,
+}
+
+impl AccountRecordV2 {
+    /// Creates the record that's output for a client's account
+    pub fn new(client: u16, account: &Account) -> Self {
+        AccountRecordV2 {
+            client,
+            available: account.available_funds,
+            held: account.held_funds,
+            total: account.total_funds,
+            locked: account.lock_state.is_locked(),
+            lock_state: account.lock_state.name(),
+            lock_trigger: account.lock_state.trigger(),
+        }
+    }
+
+    /// Rounds the balances to the currency's minor unit
+    pub fn in_currency(self, currency: &Currency) -> Self {
+        AccountRecordV2 {
+            available: currency.round(self.available),
+            held: currency.round(self.held),
+            total: currency.round(self.total),
+            ..self
+        }
+    }
+}
+
+/// How a client account changed during a run, relative to the state it was loaded with
+#[derive(Debug, Serialize, PartialEq)]
+pub struct AccountDiff {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The change in available funds
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub available_change: f32,
+
+    /// The change in held funds
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub held_change: f32,
+
+    /// The change in total funds
+    #[serde(serialize_with = "serialize_with_precision")]
+    pub total_change: f32,
+
+    /// Whether the account was locked before the run
+    pub was_locked: bool,
+
+    /// Whether the account is locked after the run
+    pub locked: bool,
+}
+
+impl AccountDiff {
+    /// Compares the output record of an account before and after a run
+    pub fn new(before: &AccountRecord, after: &AccountRecord) -> Self {
+        AccountDiff {
+            client: after.client,
+            available_change: after.available - before.available,
+            held_change: after.held - before.held,
+            total_change: after.total - before.total,
+            was_locked: before.locked,
+            locked: after.locked,
+        }
+    }
+
+    /// Rounds the changes to the currency's minor unit
+    pub fn in_currency(self, currency: &Currency) -> Self {
+        AccountDiff {
+            available_change: currency.round(self.available_change),
+            held_change: currency.round(self.held_change),
+            total_change: currency.round(self.total_change),
+            ..self
+        }
+    }
+
+    /// Whether anything about the account changed, ignoring differences beyond 4 decimals
+    pub fn has_changes(&self) -> bool {
+        self.was_locked != self.locked
+            || [self.available_change, self.held_change, self.total_change]
+                .iter()
+                .any(|change| round(*change as f64, 4) != 0.0)
+    }
+}
+
 /// The details of a client's account
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Account {
     /// The total funds that are available for trading, staking, withdrawal, etc
     pub available_funds: f32,
@@ -113,14 +735,59 @@ pub struct Account {
     /// The total funds that are available or held
     pub total_funds: f32,
 
-    /// Whether the account is locked
-    pub is_locked: bool,
+    /// Whether the account is locked, and why
+    pub lock_state: LockState,
 
     /// Data about the transactions that have been successfully executed (id, amount, current state)
+    #[serde(serialize_with = "serialize_ordered")]
     pub successful_transactions: HashMap<u32, Transaction>,
+
+    /// The first transaction that was applied to the account, when the client was first seen
+    #[serde(default)]
+    pub first_seen_tx: Option<u32>,
+
+    /// The number of transactions that have been applied to the account
+    #[serde(default)]
+    pub transaction_count: u32,
+
+    /// The most that a single withdrawal can take from the account, set by an operator
+    #[serde(default)]
+    pub withdrawal_limit: Option<f32>,
 }
 
 impl Account {
+    /// Creates an unlocked account with the given balances, the total funds are the sum of both
+    pub fn with_balances(available_funds: f32, held_funds: f32) -> Self {
+        Account {
+            available_funds,
+            held_funds,
+            total_funds: available_funds + held_funds,
+            ..Default::default()
+        }
+    }
+
+    /// Describes the first invariant of the balances that's broken, none when they all hold. The
+    /// total funds must be the sum of the available and held funds, and the held funds (and the
+    /// available funds, when they're checked) can't be negative. Balances are compared at 4
+    /// decimals of precision, so float error alone never breaks an invariant.
+    pub fn broken_invariant(&self, available_non_negative: bool) -> Option<String> {
+        let [available, held, total] =
+            [self.available_funds, self.held_funds, self.total_funds].map(|funds| funds as f64);
+
+        if round(available + held - total, 4) != 0.0 {
+            Some(format!(
+                "total funds {} aren't the sum of available funds {} and held funds {}",
+                total, available, held
+            ))
+        } else if round(held, 4) < 0.0 {
+            Some(format!("held funds {} are negative", held))
+        } else if available_non_negative && round(available, 4) < 0.0 {
+            Some(format!("available funds {} are negative", available))
+        } else {
+            None
+        }
+    }
+
     /// Updates a client account when a deposit transaction occurs
     pub fn deposit(&mut self, amount: f32, transaction_id: u32) {
         self.available_funds += amount;
@@ -130,19 +797,28 @@ impl Account {
             Transaction {
                 amount,
                 current_state: TransactionType::Deposit,
+                disputed_amount: None,
+                held_since: None,
+                transaction_type: TransactionType::Deposit,
+                timestamp: None,
             },
         );
     }
 
     /// Updates a client account when a withdrawal transaction occurs
-    pub fn withdraw(&mut self, amount: f32, transaction_id: u32) -> ReaderResult<()> {
-        // if a client account contains insufficient available funds, ensure the withdrawal fails
-        if amount > self.available_funds {
-            return Err(ReaderError::InsufficientFundsError(
-                amount,
-                self.available_funds,
-            ));
-        }
+    pub fn withdraw(&mut self, amount: f32, transaction_id: u32) -> LedgerResult<()> {
+        self.withdraw_with_policy(amount, transaction_id, WithdrawalPolicy::default())
+    }
+
+    /// Updates a client account when a withdrawal transaction occurs, using the policy to decide
+    /// which funds can be withdrawn while transactions are being disputed
+    pub fn withdraw_with_policy(
+        &mut self,
+        amount: f32,
+        transaction_id: u32,
+        policy: WithdrawalPolicy,
+    ) -> LedgerResult<()> {
+        self.check_withdrawable(amount, transaction_id, policy)?;
 
         self.available_funds -= amount;
         self.total_funds -= amount;
@@ -151,57 +827,368 @@ impl Account {
             Transaction {
                 amount,
                 current_state: TransactionType::Withdrawal,
+                disputed_amount: None,
+                held_since: None,
+                transaction_type: TransactionType::Withdrawal,
+                timestamp: None,
             },
         );
 
         Ok(())
     }
 
-    /// Updates a client account when a dispute transaction occurs
-    pub fn dispute(&mut self, transaction_id: u32) {
-        if let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) {
-            // we only want to update the account if the transaction hasn't been disputed yet
-            if TransactionType::Dispute == transaction.current_state {
-                return;
+    /// Updates a client account when it's the source of a transfer, which can only take the
+    /// funds a withdrawal could. Transfers aren't kept with the account's transactions, so they
+    /// can't be disputed or voided.
+    pub fn transfer_out(
+        &mut self,
+        amount: f32,
+        transaction_id: u32,
+        policy: WithdrawalPolicy,
+    ) -> LedgerResult<()> {
+        self.check_withdrawable(amount, transaction_id, policy)?;
+
+        self.available_funds -= amount;
+        self.total_funds -= amount;
+
+        Ok(())
+    }
+
+    /// Updates a client account when it's the destination of a transfer
+    pub fn transfer_in(&mut self, amount: f32) {
+        self.available_funds += amount;
+        self.total_funds += amount;
+    }
+
+    /// Errors when the amount can't be taken out of the account, because it's over the account's
+    /// limit or more than the policy lets be withdrawn
+    fn check_withdrawable(
+        &self,
+        amount: f32,
+        transaction_id: u32,
+        policy: WithdrawalPolicy,
+    ) -> LedgerResult<()> {
+        if let Some(limit) = self.withdrawal_limit.filter(|limit| amount > *limit) {
+            return Err(LedgerError::OverLimit(transaction_id, amount, limit));
+        }
+
+        let withdrawable_funds = match policy {
+            WithdrawalPolicy::AvailableOnly => self.available_funds,
+            WithdrawalPolicy::IncludeHeld => self.total_funds,
+            WithdrawalPolicy::FreezeDuringDispute => {
+                if self.has_open_dispute() {
+                    return Err(LedgerError::OpenDispute(transaction_id));
+                }
+
+                self.available_funds
             }
+        };
+
+        // if a client account contains insufficient funds, ensure the withdrawal fails
+        if amount > withdrawable_funds {
+            return Err(LedgerError::InsufficientFunds(amount, withdrawable_funds));
+        }
 
-            self.available_funds -= transaction.amount;
-            self.held_funds += transaction.amount;
-            transaction.current_state = TransactionType::Dispute;
+        Ok(())
+    }
+
+    /// Whether any of the account's transactions are currently being disputed
+    pub fn has_open_dispute(&self) -> bool {
+        self.successful_transactions
+            .values()
+            .any(|transaction| transaction.current_state == TransactionType::Dispute)
+    }
+
+    /// Updates a client account when an adjustment occurs. Positive amounts credit the available
+    /// funds and negative amounts debit them.
+    pub fn adjust(&mut self, amount: f32) -> LedgerResult<()> {
+        // a debit can't take more than the available funds
+        if self.available_funds + amount < 0.0 {
+            return Err(LedgerError::InsufficientFunds(
+                -amount,
+                self.available_funds,
+            ));
+        }
+
+        self.available_funds += amount;
+        self.total_funds += amount;
+
+        Ok(())
+    }
+
+    /// Records that a transaction was applied to the account
+    pub fn record_activity(&mut self, transaction_id: u32) {
+        self.first_seen_tx.get_or_insert(transaction_id);
+        self.transaction_count += 1;
+    }
+
+    /// Updates a client account when a dispute transaction occurs. A disputed deposit's funds are
+    /// moved from the available funds to the held funds, while a disputed withdrawal's are held
+    /// on top of them, as they've already left the account.
+    pub fn dispute(&mut self, transaction_id: u32) {
+        let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) else {
+            debug!(
+                tx = transaction_id,
+                "ignored dispute of a transaction not in the account"
+            );
+            return;
+        };
+
+        // only transactions that aren't being disputed, and haven't been settled some other way,
+        // can be disputed
+        if !transaction.is_disputable() {
+            let state = transaction.current_state.name();
+            debug!(
+                tx = transaction_id,
+                state, "ignored dispute of a transaction in this state"
+            );
+            return;
+        }
+
+        let amount = transaction.amount;
+        if transaction.is_withdrawal() {
+            self.total_funds += amount;
+        } else {
+            self.available_funds -= amount;
         }
+        self.held_funds += amount;
+        transaction.current_state = TransactionType::Dispute;
+        transaction.disputed_amount = None;
     }
 
-    /// Updates a client account when a resolve transaction occurs
-    pub fn resolve(&mut self, transaction_id: u32) {
+    /// Records when a deposit or withdrawal occurred, once it's been applied
+    pub fn occurred_at(&mut self, transaction_id: u32, timestamp: Option<u64>) {
         if let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) {
-            // we only want to update the account if the transaction is currently being disputed
-            if TransactionType::Dispute == transaction.current_state {
-                self.held_funds -= transaction.amount;
-                self.available_funds += transaction.amount;
-                transaction.current_state = TransactionType::Resolve;
-            }
+            transaction.timestamp = timestamp;
         }
     }
 
-    /// Updates a client account when a chargeback transaction occurs
-    pub fn chargeback(&mut self, transaction_id: u32) {
+    /// Records when a disputed transaction's funds were first held, so they can be aged. Disputes
+    /// of transactions that are already disputed don't change it.
+    pub fn hold_since(&mut self, transaction_id: u32, timestamp: Option<u64>) {
         if let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) {
-            // we only want to update the account if the transaction is currently being disputed
-            if TransactionType::Dispute == transaction.current_state {
-                self.held_funds -= transaction.amount;
-                self.total_funds -= transaction.amount;
-                // for chargebacks, immediately freeze the account
-                self.is_locked = true;
-                transaction.current_state = TransactionType::Chargeback;
+            if transaction.current_state == TransactionType::Dispute {
+                transaction.held_since = transaction.held_since.or(timestamp);
             }
         }
     }
+
+    /// Updates a client account when a dispute of part of a transaction occurs, only the given
+    /// amount is held. The amount must be positive and no more than the transaction's amount.
+    pub fn dispute_partial(&mut self, transaction_id: u32, amount: f32) -> LedgerResult<()> {
+        let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) else {
+            debug!(
+                tx = transaction_id,
+                "ignored dispute of a transaction not in the account"
+            );
+            return Ok(());
+        };
+
+        // only transactions that aren't being disputed, and haven't been settled some other way,
+        // can be disputed
+        if !transaction.is_disputable() {
+            let state = transaction.current_state.name();
+            debug!(
+                tx = transaction_id,
+                state, "ignored dispute of a transaction in this state"
+            );
+            return Ok(());
+        }
+
+        if amount <= 0.0 || amount > transaction.amount {
+            return Err(LedgerError::InvalidDisputeAmount(transaction_id, amount));
+        }
+
+        if transaction.is_withdrawal() {
+            self.total_funds += amount;
+        } else {
+            self.available_funds -= amount;
+        }
+        self.held_funds += amount;
+        transaction.current_state = TransactionType::Dispute;
+        transaction.disputed_amount = Some(amount);
+
+        Ok(())
+    }
+
+    /// Updates a client account when a resolve transaction occurs. The held funds can't go
+    /// negative, so the resolve is rejected when the account holds less than the dispute. A
+    /// disputed deposit's funds are released back to the available funds, while a disputed
+    /// withdrawal stands, so its funds are released out of the account.
+    pub fn resolve(&mut self, transaction_id: u32) -> LedgerResult<()> {
+        let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) else {
+            debug!(
+                tx = transaction_id,
+                "ignored resolve of a transaction not in the account"
+            );
+            return Ok(());
+        };
+
+        // we only want to update the account if the transaction is currently being disputed
+        if TransactionType::Dispute != transaction.current_state {
+            let state = transaction.current_state.name();
+            debug!(
+                tx = transaction_id,
+                state, "ignored resolve of a transaction not disputed"
+            );
+            return Ok(());
+        }
+
+        check_held_funds(transaction_id, transaction.held_amount(), self.held_funds)?;
+        self.held_funds -= transaction.held_amount();
+        if transaction.is_withdrawal() {
+            self.total_funds -= transaction.held_amount();
+        } else {
+            self.available_funds += transaction.held_amount();
+        }
+        transaction.current_state = TransactionType::Resolve;
+        transaction.held_since = None;
+
+        Ok(())
+    }
+
+    /// Updates a client account when a void transaction occurs, reversing a deposit or withdrawal.
+    /// A deposit can't be voided once its funds have been spent. Transactions that have been
+    /// disputed are left to the dispute flow, so voiding them, or anything that's already been
+    /// voided or refunded, is an invalid transition.
+    pub fn void(&mut self, transaction_id: u32) -> LedgerResult<()> {
+        let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) else {
+            debug!(
+                tx = transaction_id,
+                "ignored void of a transaction not in the account"
+            );
+            return Ok(());
+        };
+
+        let amount = transaction.amount;
+        let reversal = match transaction.current_state {
+            TransactionType::Deposit if amount > self.available_funds => {
+                return Err(LedgerError::InsufficientFunds(amount, self.available_funds));
+            }
+            TransactionType::Deposit => -amount,
+            TransactionType::Withdrawal => amount,
+            state => {
+                let void = TransactionType::Void;
+                return Err(LedgerError::InvalidTransition(transaction_id, state, void));
+            }
+        };
+
+        self.available_funds += reversal;
+        self.total_funds += reversal;
+        transaction.current_state = TransactionType::Void;
+
+        Ok(())
+    }
+
+    /// Updates a client account when a refund transaction occurs, crediting a withdrawal's amount
+    /// back to the available funds. Only withdrawals that haven't been disputed, voided or refunded
+    /// already can be refunded.
+    pub fn refund(&mut self, transaction_id: u32) -> LedgerResult<()> {
+        let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) else {
+            debug!(
+                tx = transaction_id,
+                "ignored refund of a transaction not in the account"
+            );
+            return Ok(());
+        };
+
+        if !transaction.is_withdrawal() || transaction.current_state != TransactionType::Withdrawal
+        {
+            return Err(LedgerError::NotRefundable(
+                transaction_id,
+                transaction.current_state,
+            ));
+        }
+
+        self.available_funds += transaction.amount;
+        self.total_funds += transaction.amount;
+        transaction.current_state = TransactionType::Refund;
+
+        Ok(())
+    }
+
+    /// Updates a client account when a chargeback transaction occurs. As with resolves, it's
+    /// rejected when the account holds less than the dispute. A charged back deposit's funds leave
+    /// the account, while a charged back withdrawal's are credited back to the client.
+    pub fn chargeback(&mut self, transaction_id: u32) -> LedgerResult<()> {
+        let Some(transaction) = self.successful_transactions.get_mut(&transaction_id) else {
+            debug!(
+                tx = transaction_id,
+                "ignored chargeback of a transaction not in the account"
+            );
+            return Ok(());
+        };
+
+        // we only want to update the account if the transaction is currently being disputed
+        if TransactionType::Dispute != transaction.current_state {
+            let state = transaction.current_state.name();
+            debug!(
+                tx = transaction_id,
+                state, "ignored chargeback of a transaction not disputed"
+            );
+            return Ok(());
+        }
+
+        check_held_funds(transaction_id, transaction.held_amount(), self.held_funds)?;
+        self.held_funds -= transaction.held_amount();
+        if transaction.is_withdrawal() {
+            self.available_funds += transaction.held_amount();
+        } else {
+            self.total_funds -= transaction.held_amount();
+        }
+        // for chargebacks, immediately freeze the account
+        self.lock_state = LockState::ChargebackLock { tx: transaction_id };
+        transaction.current_state = TransactionType::Chargeback;
+        transaction.held_since = None;
+
+        Ok(())
+    }
+
+    /// Moves the held funds of a disputed transaction out of the account, to be escheated.
+    /// Returns the amount that was moved, or None when the transaction isn't being disputed.
+    pub fn escheat(&mut self, transaction_id: u32) -> Option<f32> {
+        let transaction = self.successful_transactions.get_mut(&transaction_id)?;
+        if transaction.current_state != TransactionType::Dispute {
+            return None;
+        }
+
+        let amount = transaction.held_amount();
+        self.held_funds -= amount;
+        self.total_funds -= amount;
+        transaction.current_state = TransactionType::Escheated;
+
+        Some(amount)
+    }
+}
+
+/// Errors when releasing the amount would leave the account with negative held funds. They're
+/// compared at 4 decimals of precision, so float error alone never rejects a release.
+fn check_held_funds(transaction_id: u32, amount: f32, held_funds: f32) -> LedgerResult<()> {
+    if round(held_funds as f64 - amount as f64, 4) < 0.0 {
+        return Err(LedgerError::HeldFundsUnderflow(
+            transaction_id,
+            amount,
+            held_funds,
+        ));
+    }
+
+    Ok(())
 }
 
 /// Ensures that f32 values are serialized with 4 decimals of precision
-fn serialize_with_precision<S>(val: &f32, s: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
+pub(crate) fn serialize_with_precision<S>(val: &f32, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
 {
     s.serialize_f64(round(*val as f64, 4))
-}
\ No newline at end of file
+}
+
+/// Ensures that the entries of a HashMap are serialized in order of their keys
+fn serialize_ordered<K, V, S>(map: &HashMap<K, V>, s: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    s.collect_map(map.iter().collect::<BTreeMap<&K, &V>>())
+}
@@ -0,0 +1,274 @@
+use crate::error::{CliError, CliResult, SourceResult};
+use crate::mapper::Record;
+use crate::parser::Records;
+use std::fs;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The records of several sources read as one stream, ordered by their timestamps. Each source is
+/// expected to be in order already, so the earliest record at the head of any source is taken
+/// next. A record without a timestamp takes the timestamp of the record before it in its source,
+/// so sources without any are read one after another, in the order they were provided, as are
+/// records that share a timestamp.
+pub struct MergedSource<'a> {
+    /// The sources, in the order they were provided
+    sources: Vec<Peekable<Records<'a>>>,
+
+    /// The timestamp of the latest record taken from each source
+    latest: Vec<Option<u64>>,
+
+    /// Which source the latest record was taken from
+    cursor: SourceCursor,
+}
+
+impl<'a> MergedSource<'a> {
+    /// Merges the sources, which are in the order they were provided
+    pub fn new(sources: Vec<Records<'a>>) -> Self {
+        MergedSource {
+            latest: vec![None; sources.len()],
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+            cursor: SourceCursor::default(),
+        }
+    }
+
+    /// Tracks which source the latest record was taken from, so it can be reported against the
+    /// file it was read from
+    pub fn cursor(&self) -> SourceCursor {
+        self.cursor.clone()
+    }
+}
+
+/// The index of the source a merged stream's latest record was taken from, shared between the
+/// stream and whatever reports on its records
+#[derive(Debug, Clone, Default)]
+pub struct SourceCursor(Arc<AtomicUsize>);
+
+impl SourceCursor {
+    /// The index of the source
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Moves the cursor to the source
+    pub(crate) fn set(&self, index: usize) {
+        self.0.store(index, Ordering::Relaxed)
+    }
+}
+
+impl Iterator for MergedSource<'_> {
+    type Item = SourceResult<(u64, Record)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // None sorts before any timestamp, so a source without them is read before the sources
+        // after it
+        let mut earliest: Option<(usize, Option<u64>)> = None;
+        for (index, source) in self.sources.iter_mut().enumerate() {
+            let timestamp = match source.peek() {
+                None => continue,
+                // an error is returned straight away, rather than once its source comes up
+                Some(Err(_)) => {
+                    self.cursor.set(index);
+                    return source.next();
+                }
+                Some(Ok((_, record))) => record.timestamp.or(self.latest[index]),
+            };
+            if earliest.is_none_or(|(_, earliest)| timestamp < earliest) {
+                earliest = Some((index, timestamp));
+            }
+        }
+
+        let (index, timestamp) = earliest?;
+        self.latest[index] = timestamp;
+        self.cursor.set(index);
+        self.sources[index].next()
+    }
+}
+
+/// Expands a path whose file name is a glob pattern (e.g. `exports/2024-*.csv`) into the files
+/// it matches, ordered by name. `*` matches any run of characters and `?` any one character. A
+/// path without a pattern is returned as it is.
+pub fn expand_pattern(path: &Path) -> CliResult<Vec<PathBuf>> {
+    let Some(pattern) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    if !pattern.contains(['*', '?']) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let no_matches = || CliError::NonExistentFile(path.display().to_string());
+    let entries = fs::read_dir(dir).map_err(|_| no_matches())?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter(|entry| {
+            let name = entry.file_name();
+            name.to_str()
+                .is_some_and(|name| matches_pattern(pattern, name))
+        })
+        .map(|entry| path.with_file_name(entry.file_name()))
+        .collect();
+    if paths.is_empty() {
+        return Err(no_matches());
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+/// Whether a file name matches a glob pattern
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // where to resume after the last `*`, when the characters after it stop matching
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SourceError;
+    use crate::mapper::Record;
+    use crate::merge::{expand_pattern, matches_pattern, MergedSource};
+    use crate::parser::Records;
+    use crate::test_helpers::*;
+    use std::fs::File;
+    use std::io::Error;
+    use std::path::PathBuf;
+
+    // Tests that records are merged by timestamp, falling back to the order of the sources
+    #[test]
+    fn test_merged_source() {
+        let at = |record: Record, timestamp| Record {
+            timestamp: Some(timestamp),
+            ..record
+        };
+        let source = |records: Vec<Record>| -> Records {
+            Box::new(
+                records
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, record)| Ok((index as u64 + 2, record))),
+            )
+        };
+
+        let merged: Vec<u32> = MergedSource::new(vec![
+            source(vec![
+                at(Record::deposit(1, 1, 1.0), 100),
+                Record::deposit(1, 2, 1.0),
+                at(Record::deposit(1, 3, 1.0), 300),
+            ]),
+            source(vec![
+                at(Record::deposit(2, 4, 1.0), 50),
+                at(Record::deposit(2, 5, 1.0), 100),
+                at(Record::deposit(2, 6, 1.0), 200),
+            ]),
+        ])
+        .map(|result| result.unwrap().1.transaction_id)
+        .collect();
+        assert_eq!(merged, vec![4, 1, 2, 5, 6, 3]);
+
+        // without timestamps, each source is read in turn
+        let merged: Vec<(u64, u32)> = MergedSource::new(vec![
+            source(vec![Record::deposit(1, 1, 1.0), Record::deposit(1, 2, 1.0)]),
+            source(vec![Record::deposit(2, 3, 1.0)]),
+        ])
+        .map(|result| {
+            result
+                .map(|(line, record)| (line, record.transaction_id))
+                .unwrap()
+        })
+        .collect();
+        assert_eq!(merged, vec![(2, 1), (3, 2), (2, 3)]);
+    }
+
+    // Tests that the cursor follows the source each record was taken from, including the source
+    // of an error
+    #[test]
+    fn test_source_cursor() {
+        let at = |transaction_id, timestamp| Record {
+            timestamp: Some(timestamp),
+            ..Record::deposit(1, transaction_id, 1.0)
+        };
+        let first: Records = Box::new(vec![Ok((2, at(1, 100))), Ok((3, at(2, 300)))].into_iter());
+        let second: Records = Box::new(
+            vec![
+                Ok((2, at(3, 200))),
+                Err(SourceError::Parse {
+                    line: 3,
+                    message: "invalid digit".to_string(),
+                }),
+            ]
+            .into_iter(),
+        );
+
+        let merged = MergedSource::new(vec![first, second]);
+        let cursor = merged.cursor();
+        assert_eq!(cursor.get(), 0);
+
+        let mut sources = vec![];
+        for result in merged {
+            sources.push((cursor.get(), result.is_ok()));
+        }
+        assert_eq!(sources, vec![(0, true), (1, true), (1, false), (0, true)]);
+    }
+
+    // Tests that a glob pattern in a file name expands to the files it matches, in name order
+    #[test]
+    fn test_expand_pattern() -> Result<(), Error> {
+        assert!(matches_pattern("*.csv", "jan.csv"));
+        assert!(matches_pattern("2024-??.csv", "2024-03.csv"));
+        assert!(matches_pattern("a*b*c", "aXbYbZc"));
+        assert!(!matches_pattern("2024-??.csv", "2024-3.csv"));
+        assert!(!matches_pattern("*.csv", "jan.csv.gz"));
+
+        let (file_path_str, dir, file) = create_temp_file("feb.csv")?;
+        File::create(dir.path().join("jan.csv"))?;
+        File::create(dir.path().join("notes.txt"))?;
+        let pattern = dir.path().join("*.csv");
+
+        assert_eq!(
+            expand_pattern(&pattern).unwrap(),
+            vec![PathBuf::from(&file_path_str), dir.path().join("jan.csv")]
+        );
+        assert_eq!(
+            expand_pattern(&dir.path().join("feb.csv")).unwrap(),
+            vec![PathBuf::from(&file_path_str)]
+        );
+        assert!(expand_pattern(&dir.path().join("*.jsonl")).is_err());
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
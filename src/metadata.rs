@@ -0,0 +1,309 @@
+use crate::clock::Clock;
+use crate::config::EngineConfig;
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::{Account, TransactionType};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// The version of the engine that produced an output
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A file that was read or written during a run, along with the SHA-256 of its contents
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Artifact {
+    /// The path of the file, or std out. Paths that aren't valid UTF-8 are written lossily.
+    pub path: String,
+
+    /// The hex encoded SHA-256 of the contents of the file
+    pub sha256: String,
+}
+
+impl Artifact {
+    /// Calculates the digest of a file
+    fn from_file(path: &Path) -> SourceResult<Self> {
+        Ok(Artifact {
+            path: path.to_string_lossy().into_owned(),
+            sha256: digest_file(path)?,
+        })
+    }
+}
+
+/// The headline figures of a run, so runs can be compared over time
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RunSummary {
+    /// The number of records that were read
+    pub records: u64,
+
+    /// The number of records that were rejected
+    pub rejections: u64,
+
+    /// The fraction of the records that were rejected
+    pub reject_rate: f64,
+
+    /// The total funds across every account
+    pub total_funds: f64,
+
+    /// The held funds across every account
+    pub held_funds: f64,
+
+    /// The number of transactions that are being disputed
+    pub open_disputes: u64,
+
+    /// The number of accounts that are locked
+    pub locked_accounts: u64,
+
+    /// The number of accounts
+    pub accounts: u64,
+}
+
+impl RunSummary {
+    /// Summarizes the accounts at the end of a run, along with how many records were rejected
+    pub fn new(account_map: &HashMap<u16, Account>, records: u64, rejections: u64) -> Self {
+        let accounts = account_map.values();
+
+        RunSummary {
+            records,
+            rejections,
+            reject_rate: if records == 0 {
+                0.0
+            } else {
+                rejections as f64 / records as f64
+            },
+            total_funds: accounts
+                .clone()
+                .map(|account| account.total_funds as f64)
+                .sum(),
+            held_funds: accounts
+                .clone()
+                .map(|account| account.held_funds as f64)
+                .sum(),
+            open_disputes: accounts
+                .clone()
+                .flat_map(|account| account.successful_transactions.values())
+                .filter(|transaction| transaction.current_state == TransactionType::Dispute)
+                .count() as u64,
+            locked_accounts: accounts
+                .filter(|account| account.lock_state.is_locked())
+                .count() as u64,
+            accounts: account_map.len() as u64,
+        }
+    }
+}
+
+/// Writes the headline figures of a run as JSON
+pub fn write_summary(mut output: impl Write, summary: &RunSummary) -> SourceResult<()> {
+    serde_json::to_writer_pretty(&mut output, summary)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+    writeln!(output).map_err(|err| SourceError::Io(err.to_string()))?;
+
+    output
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// Describes exactly what produced the outputs of a run, so any of them can be traced back to the
+/// engine version, config and inputs that created it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// A unique identifier for the run
+    pub run_id: String,
+
+    /// The version of the engine
+    pub engine_version: String,
+
+    /// The hex encoded SHA-256 of the config the transactions were applied with
+    pub config_hash: String,
+
+    /// The files that were read
+    pub inputs: Vec<Artifact>,
+
+    /// The files that were written
+    pub outputs: Vec<Artifact>,
+
+    /// When the run started, in milliseconds since the unix epoch
+    pub started_at_ms: u64,
+
+    /// When the run finished, in milliseconds since the unix epoch
+    pub finished_at_ms: u64,
+
+    /// The headline figures of the run, when it processed transactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<RunSummary>,
+
+    /// Files whose digests are calculated once the run has finished
+    #[serde(skip)]
+    pending_inputs: Vec<PathBuf>,
+
+    /// Files whose digests are calculated once the run has finished
+    #[serde(skip)]
+    pending_outputs: Vec<PathBuf>,
+}
+
+impl RunMetadata {
+    /// Starts recording the metadata for a run that uses the given config, timed by the clock
+    pub fn start(config: &EngineConfig, clock: &dyn Clock) -> Self {
+        let started_at_ms = clock.now_ms();
+        let config_json = serde_json::to_vec(config).unwrap_or_default();
+
+        RunMetadata {
+            run_id: format!("{:x}-{:x}", started_at_ms, process::id()),
+            engine_version: ENGINE_VERSION.to_string(),
+            config_hash: format!("{:x}", Sha256::digest(config_json)),
+            inputs: vec![],
+            outputs: vec![],
+            started_at_ms,
+            finished_at_ms: started_at_ms,
+            summary: None,
+            pending_inputs: vec![],
+            pending_outputs: vec![],
+        }
+    }
+
+    /// Records a file that was read during the run
+    pub fn add_input(&mut self, path: impl AsRef<Path>) {
+        self.pending_inputs.push(path.as_ref().to_path_buf());
+    }
+
+    /// Records a file that was written during the run
+    pub fn add_output(&mut self, path: impl AsRef<Path>) {
+        self.pending_outputs.push(path.as_ref().to_path_buf());
+    }
+
+    /// Records the headline figures of the run
+    pub fn set_summary(&mut self, summary: RunSummary) {
+        self.summary = Some(summary);
+    }
+
+    /// Records an output whose digest was calculated while it was written (e.g. std out)
+    pub fn add_output_digest(&mut self, path: &str, sha256: String) {
+        self.outputs.push(Artifact {
+            path: path.to_string(),
+            sha256,
+        });
+    }
+
+    /// Finishes the run, calculating the digest of every file and writing the metadata as JSON
+    pub fn write(mut self, file_path: impl AsRef<Path>, clock: &dyn Clock) -> SourceResult<()> {
+        self.finished_at_ms = clock.now_ms();
+
+        for path in std::mem::take(&mut self.pending_inputs) {
+            self.inputs.push(Artifact::from_file(&path)?);
+        }
+        for path in std::mem::take(&mut self.pending_outputs) {
+            self.outputs.push(Artifact::from_file(&path)?);
+        }
+
+        let file = File::create(file_path).map_err(|err| SourceError::Io(err.to_string()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &self)
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+        writeln!(writer).map_err(|err| SourceError::Io(err.to_string()))
+    }
+}
+
+/// Passes everything written through to another writer, calculating its SHA-256 along the way
+pub struct DigestWriter<W: Write> {
+    /// Where the data is written to
+    inner: W,
+
+    /// The digest of everything written so far
+    hasher: Sha256,
+}
+
+impl<W: Write> DigestWriter<W> {
+    /// Wraps a writer
+    pub fn new(inner: W) -> Self {
+        DigestWriter {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The hex encoded SHA-256 of everything written so far
+    pub fn digest(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The hex encoded SHA-256 of the contents of a file
+fn digest_file(path: &Path) -> SourceResult<String> {
+    let mut file =
+        File::open(path).map_err(|err| SourceError::Io(format!("{}: {}", path.display(), err)))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|err| SourceError::Io(err.to_string()))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::FixedClock;
+    use crate::config::EngineConfig;
+    use crate::metadata::{DigestWriter, RunMetadata};
+    use crate::test_helpers::*;
+    use std::fs;
+    use std::io::{Error, Write};
+
+    /// The SHA-256 of "abc"
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    // Tests that the digest of everything written through a DigestWriter is calculated
+    #[test]
+    fn test_digest_writer() -> Result<(), Error> {
+        let mut writer = DigestWriter::new(vec![]);
+        write!(writer, "a")?;
+        write!(writer, "bc")?;
+
+        assert_eq!(writer.digest(), ABC_SHA256);
+
+        Ok(())
+    }
+
+    // Tests that the metadata contains the digests of every input and output
+    #[test]
+    fn test_write_run_metadata() -> Result<(), Error> {
+        let (input_path, dir, mut file) = create_temp_file("transactions.csv")?;
+        write!(file, "abc")?;
+        let metadata_path = dir.path().join("run.json").display().to_string();
+
+        let clock = FixedClock::new(1_000);
+        let mut metadata = RunMetadata::start(&EngineConfig::default(), &clock);
+        metadata.add_input(&input_path);
+        metadata.add_output_digest("stdout", "digest".to_string());
+        clock.advance(250);
+        metadata.write(&metadata_path, &clock).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&metadata_path)?)?;
+
+        assert_eq!(json["engine_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["inputs"][0]["path"], input_path.as_str());
+        assert_eq!(json["inputs"][0]["sha256"], ABC_SHA256);
+        assert_eq!(json["outputs"][0]["sha256"], "digest");
+        assert_eq!(json["config_hash"].as_str().unwrap().len(), 64);
+        assert_eq!(json["started_at_ms"], 1_000);
+        assert_eq!(json["finished_at_ms"], 1_250);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
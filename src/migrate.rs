@@ -0,0 +1,828 @@
+use crate::error::{CliError, EngineResult, SourceError};
+use crate::mapper::{Account, LockState, Transaction, TransactionType};
+use crate::state::{load_state, save_state, state_version, STATE_HEADER_LEN, STATE_VERSION};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// The versions of the binary state format that older engines wrote without a header, newest
+/// first. Bincode encodes fields by position, so each is decoded with the layout it was written in.
+///
+/// - 1: balances, the locked flag and each transaction's amount and state
+/// - 2: adds when the client was first seen and their transaction count
+/// - 3: adds the disputed amount of partially disputed transactions
+/// - 4: replaces the locked flag with the lock state
+const LEGACY_VERSIONS: [u16; 4] = [4, 3, 2, 1];
+
+/// The version that added the header, with the same layout as version 4. Version 6 added when
+/// each transaction's funds were first held.
+const FIRST_VERSIONED: u16 = 5;
+
+/// The version that added when each transaction's funds were first held. Version 7 added the
+/// withdrawal limit operators can set on an account.
+const HELD_SINCE_VERSION: u16 = 6;
+
+/// The version that added the withdrawal limit. Version 8 added the type each transaction was
+/// applied as, so disputes of withdrawals move funds the right way.
+const WITHDRAWAL_LIMIT_VERSION: u16 = 7;
+
+/// The version that added the type each transaction was applied as. Version 9 added when each
+/// deposit and withdrawal occurred.
+const TRANSACTION_TYPE_VERSION: u16 = 8;
+
+/// A transaction, as written by versions 1 and 2
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct TransactionV1 {
+    amount: f32,
+    current_state: TransactionType,
+}
+
+/// A transaction, as written by versions 3 to 5
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct TransactionV3 {
+    amount: f32,
+    current_state: TransactionType,
+    disputed_amount: Option<f32>,
+}
+
+/// A transaction, as written by versions 6 and 7
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct TransactionV6 {
+    amount: f32,
+    current_state: TransactionType,
+    disputed_amount: Option<f32>,
+    held_since: Option<u64>,
+}
+
+/// A transaction, as written by version 8
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct TransactionV8 {
+    amount: f32,
+    current_state: TransactionType,
+    disputed_amount: Option<f32>,
+    held_since: Option<u64>,
+    transaction_type: TransactionType,
+}
+
+/// An account, as written by version 1
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct AccountV1 {
+    available_funds: f32,
+    held_funds: f32,
+    total_funds: f32,
+    is_locked: bool,
+    successful_transactions: HashMap<u32, TransactionV1>,
+}
+
+/// An account, as written by version 2
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct AccountV2 {
+    available_funds: f32,
+    held_funds: f32,
+    total_funds: f32,
+    is_locked: bool,
+    successful_transactions: HashMap<u32, TransactionV1>,
+    first_seen_tx: Option<u32>,
+    transaction_count: u32,
+}
+
+/// An account, as written by version 3
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct AccountV3 {
+    available_funds: f32,
+    held_funds: f32,
+    total_funds: f32,
+    is_locked: bool,
+    successful_transactions: HashMap<u32, TransactionV3>,
+    first_seen_tx: Option<u32>,
+    transaction_count: u32,
+}
+
+/// An account, as written by versions 4 and 5
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct AccountV4 {
+    available_funds: f32,
+    held_funds: f32,
+    total_funds: f32,
+    lock_state: LockState,
+    successful_transactions: HashMap<u32, TransactionV3>,
+    first_seen_tx: Option<u32>,
+    transaction_count: u32,
+}
+
+/// An account, as written by version 6
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct AccountV6 {
+    available_funds: f32,
+    held_funds: f32,
+    total_funds: f32,
+    lock_state: LockState,
+    successful_transactions: HashMap<u32, TransactionV6>,
+    first_seen_tx: Option<u32>,
+    transaction_count: u32,
+}
+
+/// An account, as written by version 7
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct AccountV7 {
+    available_funds: f32,
+    held_funds: f32,
+    total_funds: f32,
+    lock_state: LockState,
+    successful_transactions: HashMap<u32, TransactionV6>,
+    first_seen_tx: Option<u32>,
+    transaction_count: u32,
+    withdrawal_limit: Option<f32>,
+}
+
+/// An account, as written by version 8
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct AccountV8 {
+    available_funds: f32,
+    held_funds: f32,
+    total_funds: f32,
+    lock_state: LockState,
+    successful_transactions: HashMap<u32, TransactionV8>,
+    first_seen_tx: Option<u32>,
+    transaction_count: u32,
+    withdrawal_limit: Option<f32>,
+}
+
+impl From<TransactionV1> for TransactionV3 {
+    fn from(transaction: TransactionV1) -> Self {
+        TransactionV3 {
+            amount: transaction.amount,
+            current_state: transaction.current_state,
+            disputed_amount: None,
+        }
+    }
+}
+
+impl From<TransactionV3> for TransactionV6 {
+    fn from(transaction: TransactionV3) -> Self {
+        TransactionV6 {
+            amount: transaction.amount,
+            current_state: transaction.current_state,
+            disputed_amount: transaction.disputed_amount,
+            held_since: None,
+        }
+    }
+}
+
+impl From<TransactionV6> for TransactionV8 {
+    fn from(transaction: TransactionV6) -> Self {
+        // older engines disputed withdrawals as if they were deposits, so only withdrawals that
+        // were never disputed are known to be withdrawals. The rest keep moving funds the way
+        // their disputes did.
+        let transaction_type = match transaction.current_state {
+            TransactionType::Withdrawal => TransactionType::Withdrawal,
+            _ => TransactionType::Deposit,
+        };
+
+        TransactionV8 {
+            amount: transaction.amount,
+            current_state: transaction.current_state,
+            disputed_amount: transaction.disputed_amount,
+            held_since: transaction.held_since,
+            transaction_type,
+        }
+    }
+}
+
+impl From<TransactionV6> for Transaction {
+    fn from(transaction: TransactionV6) -> Self {
+        TransactionV8::from(transaction).into()
+    }
+}
+
+impl From<TransactionV8> for Transaction {
+    fn from(transaction: TransactionV8) -> Self {
+        Transaction {
+            amount: transaction.amount,
+            current_state: transaction.current_state,
+            disputed_amount: transaction.disputed_amount,
+            held_since: transaction.held_since,
+            transaction_type: transaction.transaction_type,
+            timestamp: None,
+        }
+    }
+}
+
+impl From<AccountV1> for AccountV2 {
+    fn from(account: AccountV1) -> Self {
+        AccountV2 {
+            available_funds: account.available_funds,
+            held_funds: account.held_funds,
+            total_funds: account.total_funds,
+            is_locked: account.is_locked,
+            successful_transactions: account.successful_transactions,
+            first_seen_tx: None,
+            transaction_count: 0,
+        }
+    }
+}
+
+impl From<AccountV2> for AccountV3 {
+    fn from(account: AccountV2) -> Self {
+        AccountV3 {
+            available_funds: account.available_funds,
+            held_funds: account.held_funds,
+            total_funds: account.total_funds,
+            is_locked: account.is_locked,
+            successful_transactions: account
+                .successful_transactions
+                .into_iter()
+                .map(|(tx, transaction)| (tx, transaction.into()))
+                .collect(),
+            first_seen_tx: account.first_seen_tx,
+            transaction_count: account.transaction_count,
+        }
+    }
+}
+
+impl From<AccountV3> for AccountV4 {
+    fn from(account: AccountV3) -> Self {
+        // older engines only locked accounts on chargebacks, so the lock is put down to the
+        // latest transaction that was charged back
+        let charged_back = account
+            .successful_transactions
+            .iter()
+            .filter(|(_, transaction)| transaction.current_state == TransactionType::Chargeback)
+            .map(|(tx, _)| *tx)
+            .max();
+        let lock_state = match (account.is_locked, charged_back) {
+            (false, _) => LockState::Unlocked,
+            (true, Some(tx)) => LockState::ChargebackLock { tx },
+            (true, None) => LockState::AdminLock {
+                rule: "migrated".to_string(),
+            },
+        };
+
+        AccountV4 {
+            available_funds: account.available_funds,
+            held_funds: account.held_funds,
+            total_funds: account.total_funds,
+            lock_state,
+            successful_transactions: account.successful_transactions,
+            first_seen_tx: account.first_seen_tx,
+            transaction_count: account.transaction_count,
+        }
+    }
+}
+
+impl From<AccountV1> for Account {
+    fn from(account: AccountV1) -> Self {
+        AccountV2::from(account).into()
+    }
+}
+
+impl From<AccountV2> for Account {
+    fn from(account: AccountV2) -> Self {
+        AccountV3::from(account).into()
+    }
+}
+
+impl From<AccountV3> for Account {
+    fn from(account: AccountV3) -> Self {
+        AccountV4::from(account).into()
+    }
+}
+
+impl From<AccountV4> for AccountV6 {
+    fn from(account: AccountV4) -> Self {
+        AccountV6 {
+            available_funds: account.available_funds,
+            held_funds: account.held_funds,
+            total_funds: account.total_funds,
+            lock_state: account.lock_state,
+            successful_transactions: account
+                .successful_transactions
+                .into_iter()
+                .map(|(tx, transaction)| (tx, transaction.into()))
+                .collect(),
+            first_seen_tx: account.first_seen_tx,
+            transaction_count: account.transaction_count,
+        }
+    }
+}
+
+impl From<AccountV4> for Account {
+    fn from(account: AccountV4) -> Self {
+        AccountV6::from(account).into()
+    }
+}
+
+impl From<AccountV6> for AccountV7 {
+    fn from(account: AccountV6) -> Self {
+        AccountV7 {
+            available_funds: account.available_funds,
+            held_funds: account.held_funds,
+            total_funds: account.total_funds,
+            lock_state: account.lock_state,
+            successful_transactions: account.successful_transactions,
+            first_seen_tx: account.first_seen_tx,
+            transaction_count: account.transaction_count,
+            withdrawal_limit: None,
+        }
+    }
+}
+
+impl From<AccountV6> for Account {
+    fn from(account: AccountV6) -> Self {
+        AccountV7::from(account).into()
+    }
+}
+
+impl From<AccountV7> for AccountV8 {
+    fn from(account: AccountV7) -> Self {
+        AccountV8 {
+            available_funds: account.available_funds,
+            held_funds: account.held_funds,
+            total_funds: account.total_funds,
+            lock_state: account.lock_state,
+            successful_transactions: account
+                .successful_transactions
+                .into_iter()
+                .map(|(tx, transaction)| (tx, transaction.into()))
+                .collect(),
+            first_seen_tx: account.first_seen_tx,
+            transaction_count: account.transaction_count,
+            withdrawal_limit: account.withdrawal_limit,
+        }
+    }
+}
+
+impl From<AccountV7> for Account {
+    fn from(account: AccountV7) -> Self {
+        AccountV8::from(account).into()
+    }
+}
+
+impl From<AccountV8> for Account {
+    fn from(account: AccountV8) -> Self {
+        Account {
+            available_funds: account.available_funds,
+            held_funds: account.held_funds,
+            total_funds: account.total_funds,
+            lock_state: account.lock_state,
+            successful_transactions: account
+                .successful_transactions
+                .into_iter()
+                .map(|(tx, transaction)| (tx, transaction.into()))
+                .collect(),
+            first_seen_tx: account.first_seen_tx,
+            transaction_count: account.transaction_count,
+            withdrawal_limit: account.withdrawal_limit,
+        }
+    }
+}
+
+/// Upgrades a state file written by an older engine to the current version of the format,
+/// returning the version it was written with. The version is detected from the file unless one is
+/// provided, and state that's already current is saved unchanged.
+pub fn migrate_state(
+    input_path: &Path,
+    output_path: Option<&Path>,
+    from_version: Option<u16>,
+) -> EngineResult<u16> {
+    let usage = "plutus migrate-state old.bin state.bin";
+    let output_path = output_path.ok_or_else(|| CliError::MissingOutputPath(usage.to_string()))?;
+    if let Some(version) = from_version.filter(|version| !LEGACY_VERSIONS.contains(version)) {
+        return Err(
+            CliError::InvalidValue("--from-version".to_string(), version.to_string()).into(),
+        );
+    }
+
+    let state_error = |err: String| SourceError::State(input_path.display().to_string(), err);
+    let bytes = fs::read(input_path).map_err(|err| state_error(err.to_string()))?;
+
+    let (version, account_map) = match state_version(&bytes) {
+        Some(STATE_VERSION) => (STATE_VERSION, load_state(input_path)?),
+        Some(FIRST_VERSIONED) => {
+            let account_map = decode_as::<AccountV4>(&bytes[STATE_HEADER_LEN..])
+                .map_err(|err| state_error(err.to_string()))?;
+            (FIRST_VERSIONED, account_map)
+        }
+        Some(HELD_SINCE_VERSION) => {
+            let account_map = decode_as::<AccountV6>(&bytes[STATE_HEADER_LEN..])
+                .map_err(|err| state_error(err.to_string()))?;
+            (HELD_SINCE_VERSION, account_map)
+        }
+        Some(WITHDRAWAL_LIMIT_VERSION) => {
+            let account_map = decode_as::<AccountV7>(&bytes[STATE_HEADER_LEN..])
+                .map_err(|err| state_error(err.to_string()))?;
+            (WITHDRAWAL_LIMIT_VERSION, account_map)
+        }
+        Some(TRANSACTION_TYPE_VERSION) => {
+            let account_map = decode_as::<AccountV8>(&bytes[STATE_HEADER_LEN..])
+                .map_err(|err| state_error(err.to_string()))?;
+            (TRANSACTION_TYPE_VERSION, account_map)
+        }
+        Some(version) => {
+            return Err(state_error(format!(
+                "version {} of the state format is newer than this engine",
+                version
+            ))
+            .into())
+        }
+        None => decode_legacy(&bytes, from_version).map_err(state_error)?,
+    };
+
+    save_state(output_path, &account_map)?;
+
+    Ok(version)
+}
+
+/// Decodes state that was written without a header, trying each legacy version from the newest
+/// unless the version is known
+fn decode_legacy(
+    bytes: &[u8],
+    from_version: Option<u16>,
+) -> Result<(u16, HashMap<u16, Account>), String> {
+    let versions = match from_version {
+        Some(version) => vec![version],
+        None => LEGACY_VERSIONS.to_vec(),
+    };
+
+    for version in versions {
+        let decoded = match version {
+            1 => decode_as::<AccountV1>(bytes),
+            2 => decode_as::<AccountV2>(bytes),
+            3 => decode_as::<AccountV3>(bytes),
+            _ => decode_as::<AccountV4>(bytes),
+        };
+        if let Ok(account_map) = decoded {
+            return Ok((version, account_map));
+        }
+    }
+
+    Err("the state doesn't match any version of the state format".to_string())
+}
+
+/// Decodes every account with a legacy layout, upgrading them to the current one. The whole of the
+/// state must be decoded, so a layout that only matches the start of it is rejected.
+fn decode_as<T: DeserializeOwned + Into<Account>>(
+    bytes: &[u8],
+) -> bincode::Result<HashMap<u16, Account>> {
+    let account_map: BTreeMap<u16, T> = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)?;
+
+    Ok(account_map
+        .into_iter()
+        .map(|(client_id, account)| (client_id, account.into()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{CliError, EngineError, SourceError};
+    use crate::mapper::{Account, LockState, Transaction, TransactionType};
+    use crate::migrate::{
+        migrate_state, AccountV1, AccountV3, AccountV4, AccountV6, AccountV7, AccountV8,
+        TransactionV1, TransactionV3, TransactionV6, TransactionV8,
+    };
+    use crate::state::{load_state, save_state};
+    use crate::test_helpers::*;
+    use std::collections::{BTreeMap, HashMap};
+    use std::fs;
+    use std::io::Error;
+
+    // Tests that state written by the first version is upgraded, with locks put down to the
+    // transaction that was charged back
+    #[test]
+    fn test_migrate_state_v1() -> Result<(), Error> {
+        let (old_path, dir, file) = create_temp_file("old.bin")?;
+        let new_path = dir.path().join("new.bin");
+
+        let locked = AccountV1 {
+            available_funds: 5.0,
+            held_funds: 0.0,
+            total_funds: 5.0,
+            is_locked: true,
+            successful_transactions: HashMap::from([
+                (
+                    1,
+                    TransactionV1 {
+                        amount: 10.0,
+                        current_state: TransactionType::Chargeback,
+                    },
+                ),
+                (
+                    2,
+                    TransactionV1 {
+                        amount: 5.0,
+                        current_state: TransactionType::Deposit,
+                    },
+                ),
+            ]),
+        };
+        let old_state = BTreeMap::from([(3_u16, locked)]);
+        fs::write(&old_path, bincode::serialize(&old_state).unwrap())?;
+
+        assert!(matches!(
+            load_state(&old_path),
+            Err(SourceError::State(_, message)) if message.contains("migrate-state")
+        ));
+
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), None),
+            Ok(1)
+        );
+
+        let account_map = load_state(&new_path).unwrap();
+        let account = &account_map[&3];
+        assert_eq!(account.lock_state, LockState::ChargebackLock { tx: 1 });
+        assert_eq!(account.available_funds, 5.0);
+        assert_eq!(
+            account.successful_transactions[&2],
+            Transaction {
+                amount: 5.0,
+                current_state: TransactionType::Deposit,
+                disputed_amount: None,
+                held_since: None,
+                transaction_type: TransactionType::Deposit,
+                timestamp: None,
+            }
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that state written by the first version with a header is upgraded
+    #[test]
+    fn test_migrate_state_v5() -> Result<(), Error> {
+        let (old_path, dir, file) = create_temp_file("old.bin")?;
+        let new_path = dir.path().join("new.bin");
+
+        let old_state = BTreeMap::from([(
+            4_u16,
+            AccountV4 {
+                available_funds: 0.0,
+                held_funds: 8.0,
+                total_funds: 8.0,
+                lock_state: LockState::RiskLock {
+                    rule: "velocity".to_string(),
+                },
+                successful_transactions: HashMap::from([(
+                    9,
+                    TransactionV3 {
+                        amount: 8.0,
+                        current_state: TransactionType::Dispute,
+                        disputed_amount: None,
+                    },
+                )]),
+                first_seen_tx: Some(9),
+                transaction_count: 2,
+            },
+        )]);
+        let mut bytes = b"PLUTUS".to_vec();
+        bytes.extend(5_u16.to_le_bytes());
+        bytes.extend(bincode::serialize(&old_state).unwrap());
+        fs::write(&old_path, bytes)?;
+
+        assert!(load_state(&old_path).is_err());
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), None),
+            Ok(5)
+        );
+
+        let account_map = load_state(&new_path).unwrap();
+        assert_eq!(account_map[&4].held_funds, 8.0);
+        assert_eq!(account_map[&4].successful_transactions[&9].held_since, None);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that state written before withdrawal limits is upgraded, keeping when funds were held
+    #[test]
+    fn test_migrate_state_v6() -> Result<(), Error> {
+        let (old_path, dir, file) = create_temp_file("old.bin")?;
+        let new_path = dir.path().join("new.bin");
+
+        let transaction = TransactionV6 {
+            amount: 3.0,
+            current_state: TransactionType::Dispute,
+            disputed_amount: None,
+            held_since: Some(1_700_000_000),
+        };
+        let old_state = BTreeMap::from([(
+            2_u16,
+            AccountV6 {
+                available_funds: 1.0,
+                held_funds: 3.0,
+                total_funds: 4.0,
+                lock_state: LockState::Unlocked,
+                successful_transactions: HashMap::from([(5, transaction.clone())]),
+                first_seen_tx: Some(5),
+                transaction_count: 3,
+            },
+        )]);
+        let mut bytes = b"PLUTUS".to_vec();
+        bytes.extend(6_u16.to_le_bytes());
+        bytes.extend(bincode::serialize(&old_state).unwrap());
+        fs::write(&old_path, bytes)?;
+
+        assert!(load_state(&old_path).is_err());
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), None),
+            Ok(6)
+        );
+
+        let account_map = load_state(&new_path).unwrap();
+        assert_eq!(
+            account_map[&2].successful_transactions[&5],
+            Transaction::from(transaction)
+        );
+        assert_eq!(account_map[&2].withdrawal_limit, None);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that state written before transactions had a type is upgraded, with withdrawals that
+    // haven't been disputed known to be withdrawals
+    #[test]
+    fn test_migrate_state_v7() -> Result<(), Error> {
+        let (old_path, dir, file) = create_temp_file("old.bin")?;
+        let new_path = dir.path().join("new.bin");
+
+        let transaction = |amount, current_state| TransactionV6 {
+            amount,
+            current_state,
+            disputed_amount: None,
+            held_since: None,
+        };
+        let old_state = BTreeMap::from([(
+            2_u16,
+            AccountV7 {
+                available_funds: 6.0,
+                held_funds: 0.0,
+                total_funds: 6.0,
+                lock_state: LockState::Unlocked,
+                successful_transactions: HashMap::from([
+                    (1, transaction(10.0, TransactionType::Deposit)),
+                    (2, transaction(4.0, TransactionType::Withdrawal)),
+                ]),
+                first_seen_tx: Some(1),
+                transaction_count: 2,
+                withdrawal_limit: Some(50.0),
+            },
+        )]);
+        let mut bytes = b"PLUTUS".to_vec();
+        bytes.extend(7_u16.to_le_bytes());
+        bytes.extend(bincode::serialize(&old_state).unwrap());
+        fs::write(&old_path, bytes)?;
+
+        assert!(load_state(&old_path).is_err());
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), None),
+            Ok(7)
+        );
+
+        let account = &load_state(&new_path).unwrap()[&2];
+        assert!(!account.successful_transactions[&1].is_withdrawal());
+        assert!(account.successful_transactions[&2].is_withdrawal());
+        assert_eq!(account.withdrawal_limit, Some(50.0));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that state written before transactions had a timestamp is upgraded, keeping the type
+    // each transaction was applied as
+    #[test]
+    fn test_migrate_state_v8() -> Result<(), Error> {
+        let (old_path, dir, file) = create_temp_file("old.bin")?;
+        let new_path = dir.path().join("new.bin");
+
+        let old_state = BTreeMap::from([(
+            3_u16,
+            AccountV8 {
+                available_funds: 2.0,
+                held_funds: 4.0,
+                total_funds: 6.0,
+                lock_state: LockState::Unlocked,
+                successful_transactions: HashMap::from([(
+                    7,
+                    TransactionV8 {
+                        amount: 4.0,
+                        current_state: TransactionType::Dispute,
+                        disputed_amount: None,
+                        held_since: Some(1_700_000_000),
+                        transaction_type: TransactionType::Withdrawal,
+                    },
+                )]),
+                first_seen_tx: Some(6),
+                transaction_count: 3,
+                withdrawal_limit: None,
+            },
+        )]);
+        let mut bytes = b"PLUTUS".to_vec();
+        bytes.extend(8_u16.to_le_bytes());
+        bytes.extend(bincode::serialize(&old_state).unwrap());
+        fs::write(&old_path, bytes)?;
+
+        assert!(load_state(&old_path).is_err());
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), None),
+            Ok(8)
+        );
+
+        let account = &load_state(&new_path).unwrap()[&3];
+        assert_eq!(
+            account.successful_transactions[&7],
+            Transaction {
+                amount: 4.0,
+                current_state: TransactionType::Dispute,
+                disputed_amount: None,
+                held_since: Some(1_700_000_000),
+                transaction_type: TransactionType::Withdrawal,
+                timestamp: None,
+            }
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that partially disputed amounts are kept when the version is provided, and that
+    // current state is saved unchanged
+    #[test]
+    fn test_migrate_state_from_version() -> Result<(), Error> {
+        let (old_path, dir, file) = create_temp_file("old.bin")?;
+        let new_path = dir.path().join("new.bin");
+
+        let disputed = TransactionV3 {
+            amount: 10.0,
+            current_state: TransactionType::Dispute,
+            disputed_amount: Some(4.0),
+        };
+        let old_state = BTreeMap::from([(
+            1_u16,
+            AccountV3 {
+                available_funds: 6.0,
+                held_funds: 4.0,
+                total_funds: 10.0,
+                is_locked: false,
+                successful_transactions: HashMap::from([(1, disputed.clone())]),
+                first_seen_tx: Some(1),
+                transaction_count: 2,
+            },
+        )]);
+        fs::write(&old_path, bincode::serialize(&old_state).unwrap())?;
+
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), Some(7)),
+            Err(EngineError::Cli(CliError::InvalidValue(
+                "--from-version".to_string(),
+                "7".to_string()
+            )))
+        );
+        assert_eq!(
+            migrate_state(old_path.as_ref(), None, Some(3))
+                .unwrap_err()
+                .to_string(),
+            "An output file path must be provided, like so: plutus migrate-state old.bin state.bin"
+        );
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), Some(3)),
+            Ok(3)
+        );
+
+        let account_map = load_state(&new_path).unwrap();
+        let migrated = Transaction::from(TransactionV6::from(disputed));
+        assert_eq!(account_map[&1].successful_transactions[&1], migrated);
+        assert_eq!(account_map[&1].first_seen_tx, Some(1));
+        assert_eq!(account_map[&1].lock_state, LockState::Unlocked);
+
+        let current = HashMap::from([(2, Account::with_balances(1.0, 0.0))]);
+        save_state(&old_path, &current).unwrap();
+        assert_eq!(
+            migrate_state(old_path.as_ref(), Some(&new_path), None),
+            Ok(9)
+        );
+        assert_eq!(load_state(&new_path).unwrap(), current);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,561 @@
+use crate::anonymize::Anonymizer;
+use crate::currency::Currency;
+use crate::cutover::civil_date;
+use crate::error::{variant_name, EngineError, EngineResult, SourceError, SourceResult};
+use crate::mapper::{Account, AccountRecord, Record};
+use crate::storage::TieredAccounts;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, FloatType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The number of seconds in a day
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// The outcome of a record that was applied, the rest are the codes they were rejected with
+const APPLIED: i32 = 0;
+
+/// The files the export is written to, relative to its directory
+pub const OLAP_FILES: [&str; 4] = [
+    "transactions.parquet",
+    "clients.parquet",
+    "outcomes.parquet",
+    "daily_balances.parquet",
+];
+
+/// Gathers the results of a run into a star schema, written as Parquet once the run finishes, so
+/// a warehouse can load them without re-deriving them from the transaction files:
+/// - transactions.parquet: a fact for each record, with the business day and outcome it had
+/// - clients.parquet: a dimension of each client's account
+/// - outcomes.parquet: a dimension of each outcome, applied or the error a record was rejected with
+/// - daily_balances.parquet: every account's closing balances at the end of each business day
+///
+/// Business days are split by the records' timestamps as they are for daily cutover. Records
+/// without a timestamp belong to the day that's open, or the first day when none is.
+pub struct OlapExport {
+    /// The directory the tables are written to
+    dir: PathBuf,
+
+    /// The hour (UTC) that business days end at
+    cutover_hour: u8,
+
+    /// The business day of the run, when none of the records have a timestamp
+    today: u64,
+
+    /// The business day that's currently open, once a timestamp has been seen
+    current_day: Option<u64>,
+
+    /// The first business day, once a timestamp has been seen
+    first_day: Option<u64>,
+
+    /// A fact for each record that was applied or rejected
+    facts: Vec<TransactionFact>,
+
+    /// The name of each outcome the records had, keyed by its code
+    outcomes: BTreeMap<i32, String>,
+
+    /// The closing balances of each day that's closed
+    snapshots: Vec<(u64, AccountRecord)>,
+
+    /// Rounds the closing balances to the currency's minor unit, when there is one
+    currency: Option<Currency>,
+
+    /// Pseudonymizes the client ids, when anonymizing outputs
+    anonymizer: Option<Anonymizer>,
+}
+
+/// A record, as a fact of the export
+struct TransactionFact {
+    /// The unique ID of the client
+    client: u16,
+
+    /// The unique ID of the transaction
+    tx: u32,
+
+    /// The type of the transaction
+    transaction_type: &'static str,
+
+    /// The amount of the transaction, when it has one
+    amount: Option<f32>,
+
+    /// The time of the transaction, when it has one
+    timestamp: Option<u64>,
+
+    /// The business day the record was applied in, none until a day has been opened
+    day: Option<u64>,
+
+    /// Whether the record was applied, or the code it was rejected with
+    outcome: i32,
+}
+
+impl OlapExport {
+    /// Creates an export that's written to the directory, with business days ending at the hour
+    /// (UTC). Runs without any timestamps are exported as of the day of the current time.
+    pub fn new(dir: impl Into<PathBuf>, cutover_hour: u8, now_secs: u64) -> Self {
+        let mut export = OlapExport {
+            dir: dir.into(),
+            cutover_hour,
+            today: 0,
+            current_day: None,
+            first_day: None,
+            facts: vec![],
+            outcomes: BTreeMap::from([(APPLIED, "Applied".to_string())]),
+            snapshots: vec![],
+            currency: None,
+            anonymizer: None,
+        };
+        export.today = export.business_day(now_secs);
+
+        export
+    }
+
+    /// Rounds the closing balances to the currency's minor unit
+    pub fn with_currency(mut self, currency: Option<Currency>) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    /// Pseudonymizes the client ids of every table
+    pub fn with_anonymizer(mut self, anonymizer: Option<Anonymizer>) -> Self {
+        self.anonymizer = anonymizer;
+        self
+    }
+
+    /// Takes a snapshot of the open day's closing balances when a record's timestamp falls in a
+    /// later day, before the record is applied
+    pub fn roll(&mut self, timestamp: Option<u64>, accounts: &TieredAccounts) -> SourceResult<()> {
+        let Some(day) = timestamp.map(|timestamp| self.business_day(timestamp)) else {
+            return Ok(());
+        };
+
+        match self.current_day {
+            Some(current_day) if day > current_day => {
+                for record in accounts.records()? {
+                    self.snapshots.push((current_day, record));
+                }
+                self.current_day = Some(day);
+            }
+            None => {
+                self.current_day = Some(day);
+                self.first_day = Some(day);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Adds a fact for a record once it's been applied or rejected. Records that failed for any
+    /// other reason end the run, so they aren't facts.
+    pub fn count(&mut self, record: &Record, result: &EngineResult<()>) {
+        let outcome = match result {
+            Ok(()) => APPLIED,
+            Err(EngineError::Ledger(err)) => {
+                self.outcomes
+                    .entry(err.code())
+                    .or_insert_with(|| format!("LedgerError::{}", variant_name(err)));
+                err.code()
+            }
+            Err(_) => return,
+        };
+
+        self.facts.push(TransactionFact {
+            client: record.client_id,
+            tx: record.transaction_id,
+            transaction_type: record.transaction_type.name(),
+            amount: record.amount,
+            timestamp: record.timestamp,
+            day: self.current_day,
+            outcome,
+        });
+    }
+
+    /// Takes a snapshot of the final day's closing balances, then writes every table
+    pub fn finish(mut self, accounts: &HashMap<u16, Account>) -> SourceResult<()> {
+        let last_day = self.current_day.unwrap_or(self.today);
+        for (client_id, account) in accounts {
+            self.snapshots
+                .push((last_day, AccountRecord::new(*client_id, account)));
+        }
+
+        fs::create_dir_all(&self.dir).map_err(|err| SourceError::Io(err.to_string()))?;
+        self.write_transactions()?;
+        self.write_clients(accounts)?;
+        self.write_outcomes()?;
+        self.write_daily_balances()
+    }
+
+    /// The business day a timestamp falls in, as the number of days since the unix epoch
+    fn business_day(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_sub(self.cutover_hour as u64 * 3_600) / SECONDS_PER_DAY
+    }
+
+    /// The client id as it's exported
+    fn client(&self, client_id: u16) -> i32 {
+        match &self.anonymizer {
+            Some(anonymizer) => anonymizer.client(client_id) as i32,
+            None => client_id as i32,
+        }
+    }
+
+    /// Writes the fact table
+    fn write_transactions(&self) -> SourceResult<()> {
+        let undated = self.first_day.unwrap_or(self.today);
+        let facts = &self.facts;
+
+        write_table(
+            &self.dir.join(OLAP_FILES[0]),
+            "transactions",
+            vec![
+                ("seq", Column::Int64((1..=facts.len() as i64).collect())),
+                (
+                    "client",
+                    Column::Int32(facts.iter().map(|f| self.client(f.client)).collect()),
+                ),
+                (
+                    "tx",
+                    Column::Int64(facts.iter().map(|fact| fact.tx as i64).collect()),
+                ),
+                (
+                    "type",
+                    Column::Text(facts.iter().map(|f| f.transaction_type.into()).collect()),
+                ),
+                (
+                    "amount",
+                    Column::OptionalFloat(facts.iter().map(|f| f.amount).collect()),
+                ),
+                (
+                    "timestamp",
+                    Column::OptionalInt64(
+                        facts
+                            .iter()
+                            .map(|f| f.timestamp.map(|ts| ts as i64))
+                            .collect(),
+                    ),
+                ),
+                (
+                    "date",
+                    Column::Text(
+                        facts
+                            .iter()
+                            .map(|f| civil_date(f.day.unwrap_or(undated)))
+                            .collect(),
+                    ),
+                ),
+                (
+                    "outcome",
+                    Column::Int32(facts.iter().map(|fact| fact.outcome).collect()),
+                ),
+            ],
+        )
+    }
+
+    /// Writes the dimension of each client's account, ordered by client
+    fn write_clients(&self, accounts: &HashMap<u16, Account>) -> SourceResult<()> {
+        let mut clients: Vec<(i32, &Account)> = accounts
+            .iter()
+            .map(|(client_id, account)| (self.client(*client_id), account))
+            .collect();
+        clients.sort_by_key(|(client_id, _)| *client_id);
+
+        write_table(
+            &self.dir.join(OLAP_FILES[1]),
+            "clients",
+            vec![
+                (
+                    "client",
+                    Column::Int32(clients.iter().map(|(id, _)| *id).collect()),
+                ),
+                (
+                    "first_seen_tx",
+                    Column::OptionalInt64(
+                        clients
+                            .iter()
+                            .map(|(_, account)| account.first_seen_tx.map(i64::from))
+                            .collect(),
+                    ),
+                ),
+                (
+                    "transaction_count",
+                    Column::Int64(
+                        clients
+                            .iter()
+                            .map(|(_, account)| account.transaction_count as i64)
+                            .collect(),
+                    ),
+                ),
+                (
+                    "lock_state",
+                    Column::Text(
+                        clients
+                            .iter()
+                            .map(|(_, account)| account.lock_state.name().to_string())
+                            .collect(),
+                    ),
+                ),
+                (
+                    "withdrawal_limit",
+                    Column::OptionalFloat(
+                        clients
+                            .iter()
+                            .map(|(_, account)| account.withdrawal_limit)
+                            .collect(),
+                    ),
+                ),
+            ],
+        )
+    }
+
+    /// Writes the dimension of each outcome the records had, ordered by code
+    fn write_outcomes(&self) -> SourceResult<()> {
+        write_table(
+            &self.dir.join(OLAP_FILES[2]),
+            "outcomes",
+            vec![
+                (
+                    "outcome",
+                    Column::Int32(self.outcomes.keys().copied().collect()),
+                ),
+                (
+                    "name",
+                    Column::Text(self.outcomes.values().cloned().collect()),
+                ),
+                (
+                    "applied",
+                    Column::Bool(self.outcomes.keys().map(|code| *code == APPLIED).collect()),
+                ),
+            ],
+        )
+    }
+
+    /// Writes the closing balances of each day, ordered by day and client
+    fn write_daily_balances(&self) -> SourceResult<()> {
+        let mut snapshots: Vec<(u64, i32, AccountRecord)> = self
+            .snapshots
+            .iter()
+            .map(|(day, record)| {
+                let record = match &self.currency {
+                    Some(currency) => record.clone().in_currency(currency),
+                    None => record.clone(),
+                };
+                (*day, self.client(record.client), record)
+            })
+            .collect();
+        snapshots.sort_by_key(|(day, client_id, _)| (*day, *client_id));
+
+        write_table(
+            &self.dir.join(OLAP_FILES[3]),
+            "daily_balances",
+            vec![
+                (
+                    "date",
+                    Column::Text(snapshots.iter().map(|s| civil_date(s.0)).collect()),
+                ),
+                (
+                    "client",
+                    Column::Int32(snapshots.iter().map(|s| s.1).collect()),
+                ),
+                (
+                    "available",
+                    Column::Float(snapshots.iter().map(|s| s.2.available).collect()),
+                ),
+                (
+                    "held",
+                    Column::Float(snapshots.iter().map(|s| s.2.held).collect()),
+                ),
+                (
+                    "total",
+                    Column::Float(snapshots.iter().map(|s| s.2.total).collect()),
+                ),
+                (
+                    "locked",
+                    Column::Bool(snapshots.iter().map(|s| s.2.locked).collect()),
+                ),
+            ],
+        )
+    }
+}
+
+/// The values of a column of a table, in the order of its rows
+enum Column {
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    OptionalInt64(Vec<Option<i64>>),
+    Float(Vec<f32>),
+    OptionalFloat(Vec<Option<f32>>),
+    Bool(Vec<bool>),
+    Text(Vec<String>),
+}
+
+impl Column {
+    /// The column's definition in a Parquet message type
+    fn schema(&self, name: &str) -> String {
+        let (repetition, physical_type) = match self {
+            Column::Int32(_) => ("REQUIRED", "INT32"),
+            Column::Int64(_) => ("REQUIRED", "INT64"),
+            Column::OptionalInt64(_) => ("OPTIONAL", "INT64"),
+            Column::Float(_) => ("REQUIRED", "FLOAT"),
+            Column::OptionalFloat(_) => ("OPTIONAL", "FLOAT"),
+            Column::Bool(_) => ("REQUIRED", "BOOLEAN"),
+            Column::Text(_) => ("REQUIRED", "BYTE_ARRAY"),
+        };
+        let annotation = if matches!(self, Column::Text(_)) {
+            " (UTF8)"
+        } else {
+            ""
+        };
+
+        format!("{} {} {}{};", repetition, physical_type, name, annotation)
+    }
+}
+
+/// Writes the columns to a Parquet file as a single row group
+fn write_table(file_path: &Path, name: &str, columns: Vec<(&str, Column)>) -> SourceResult<()> {
+    let fields: String = columns
+        .iter()
+        .map(|(name, column)| column.schema(name))
+        .collect();
+    let schema =
+        parse_message_type(&format!("message {} {{ {} }}", name, fields)).map_err(parquet_error)?;
+
+    let file = File::create(file_path).map_err(|err| SourceError::Io(err.to_string()))?;
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer =
+        SerializedFileWriter::new(file, Arc::new(schema), properties).map_err(parquet_error)?;
+    let mut row_group = writer.next_row_group().map_err(parquet_error)?;
+
+    for (_, column) in columns {
+        let Some(mut column_writer) = row_group.next_column().map_err(parquet_error)? else {
+            break;
+        };
+
+        match column {
+            Column::Int32(values) => column_writer
+                .typed::<Int32Type>()
+                .write_batch(&values, None, None),
+            Column::Int64(values) => column_writer
+                .typed::<Int64Type>()
+                .write_batch(&values, None, None),
+            Column::OptionalInt64(values) => {
+                let (values, levels) = definition_levels(values);
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)
+            }
+            Column::Float(values) => column_writer
+                .typed::<FloatType>()
+                .write_batch(&values, None, None),
+            Column::OptionalFloat(values) => {
+                let (values, levels) = definition_levels(values);
+                column_writer
+                    .typed::<FloatType>()
+                    .write_batch(&values, Some(&levels), None)
+            }
+            Column::Bool(values) => column_writer
+                .typed::<BoolType>()
+                .write_batch(&values, None, None),
+            Column::Text(values) => {
+                let values: Vec<ByteArray> =
+                    values.iter().map(|value| value.as_str().into()).collect();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)
+            }
+        }
+        .map_err(parquet_error)?;
+        column_writer.close().map_err(parquet_error)?;
+    }
+
+    row_group.close().map_err(parquet_error)?;
+    writer.close().map_err(parquet_error)?;
+
+    Ok(())
+}
+
+/// The values of an optional column that are present, along with the definition level of each
+/// row (1 when it has a value, 0 when it's null)
+fn definition_levels<T>(values: Vec<Option<T>>) -> (Vec<T>, Vec<i16>) {
+    let levels = values.iter().map(|value| value.is_some() as i16).collect();
+
+    (values.into_iter().flatten().collect(), levels)
+}
+
+/// Wraps an error raised while writing a Parquet file
+fn parquet_error(err: parquet::errors::ParquetError) -> SourceError {
+    SourceError::Io(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{EngineError, LedgerError};
+    use crate::mapper::Record;
+    use crate::olap::{OlapExport, OLAP_FILES};
+    use crate::storage::TieredAccounts;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    // Tests that the facts, dimensions and daily snapshots of a run are written as Parquet
+    #[test]
+    fn test_olap_export() {
+        let dir = tempdir().unwrap();
+        let mut export = OlapExport::new(dir.path(), 0, 0);
+        let mut accounts = TieredAccounts::new(HashMap::new());
+
+        let deposit = Record {
+            timestamp: Some(86_400),
+            ..Record::deposit(1, 1, 10.0)
+        };
+        export.roll(deposit.timestamp, &accounts).unwrap();
+        accounts.get_mut(1).unwrap().deposit(10.0, 1);
+        export.count(&deposit, &Ok(()));
+
+        let withdrawal = Record {
+            timestamp: Some(2 * 86_400),
+            ..Record::withdrawal(1, 2, 50.0)
+        };
+        export.roll(withdrawal.timestamp, &accounts).unwrap();
+        let rejected: EngineError = LedgerError::InsufficientFunds(50.0, 10.0).into();
+        export.count(&withdrawal, &Err(rejected));
+
+        export.finish(&accounts.into_accounts().unwrap()).unwrap();
+
+        let rows = |file_name: &str| {
+            let file = File::open(dir.path().join(file_name)).unwrap();
+            let reader = SerializedFileReader::new(file).unwrap();
+            reader
+                .get_row_iter(None)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+
+        let facts = rows(OLAP_FILES[0]);
+        assert_eq!(facts.len(), 2);
+        assert_eq!(facts[0].get_string(6).unwrap(), "1970-01-02");
+        assert_eq!(facts[1].get_int(7).unwrap(), 30);
+        assert!(facts[1].get_float(4).is_ok());
+
+        let outcomes = rows(OLAP_FILES[2]);
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(
+            outcomes[1].get_string(1).unwrap(),
+            "LedgerError::InsufficientFunds"
+        );
+
+        // a snapshot of each day the run covered
+        let balances = rows(OLAP_FILES[3]);
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].get_string(0).unwrap(), "1970-01-02");
+        assert_eq!(balances[1].get_string(0).unwrap(), "1970-01-03");
+        assert_eq!(balances[1].get_float(2).unwrap(), 10.0);
+
+        assert_eq!(rows(OLAP_FILES[1]).len(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::error::{LedgerError, LedgerResult};
+use crate::mapper::{Account, Record};
+use std::collections::HashMap;
+
+/// The client each deposit, withdrawal and transfer belongs to, keyed by transaction id, so rows
+/// can be checked against the whole ledger rather than only their own client's account. Both
+/// `Engine` and `SharedEngine` check every record against it before it's applied.
+#[derive(Debug, Default)]
+pub struct TransactionOwners {
+    /// The client of each transaction that's been applied
+    owners: HashMap<u32, u16>,
+}
+
+impl TransactionOwners {
+    /// The owners of the accounts' transactions, e.g. those loaded from an earlier run
+    pub fn of(accounts: &HashMap<u16, Account>) -> Self {
+        let owners = accounts
+            .iter()
+            .flat_map(|(client_id, account)| {
+                account
+                    .successful_transactions
+                    .keys()
+                    .map(|transaction_id| (*transaction_id, *client_id))
+            })
+            .collect();
+
+        TransactionOwners { owners }
+    }
+
+    /// Errors when the record reuses the id of a transaction that's already been seen, or
+    /// references a transaction that belongs to a different client
+    pub fn check(&self, record: &Record) -> LedgerResult<()> {
+        self.check_owner(record)?;
+        self.check_unique(record)
+    }
+
+    /// Errors when a row references a transaction that belongs to a different client. Rows
+    /// referencing unknown transactions are left alone.
+    pub fn check_owner(&self, record: &Record) -> LedgerResult<()> {
+        if !record.transaction_type.references_transaction() {
+            return Ok(());
+        }
+
+        match self.owners.get(&record.transaction_id) {
+            Some(owner) if *owner != record.client_id => Err(LedgerError::ClientMismatch(
+                record.transaction_id,
+                record.client_id,
+                *owner,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Errors when a deposit, withdrawal or transfer reuses the id of a transaction that's already
+    /// been seen, by any client. Replays would otherwise overwrite the original transaction and
+    /// credit or debit the account again.
+    pub fn check_unique(&self, record: &Record) -> LedgerResult<()> {
+        let creates_transaction = record.transaction_type.creates_transaction();
+        if creates_transaction && self.owners.contains_key(&record.transaction_id) {
+            return Err(LedgerError::DuplicateTransaction(record.transaction_id));
+        }
+
+        Ok(())
+    }
+
+    /// Remembers the client of a record that created a transaction, once it's been applied
+    pub fn insert(&mut self, record: &Record) {
+        if record.transaction_type.creates_transaction() {
+            self.owners.insert(record.transaction_id, record.client_id);
+        }
+    }
+
+    /// Checks that the record doesn't reuse a transaction id and remembers its client straight
+    /// away, so records applied at the same time from other threads can't reuse it either. The
+    /// id is released again when the record is rejected.
+    pub fn reserve(&mut self, record: &Record) -> LedgerResult<()> {
+        self.check_unique(record)?;
+        self.insert(record);
+
+        Ok(())
+    }
+
+    /// Forgets the client of a record that was reserved but couldn't be applied
+    pub fn release(&mut self, record: &Record) {
+        if record.transaction_type.creates_transaction() {
+            self.owners.remove(&record.transaction_id);
+        }
+    }
+
+    /// The client a transaction belongs to, when it's been seen
+    pub fn get(&self, transaction_id: u32) -> Option<u16> {
+        self.owners.get(&transaction_id).copied()
+    }
+
+    /// Whether a transaction has been seen
+    pub fn contains(&self, transaction_id: u32) -> bool {
+        self.owners.contains_key(&transaction_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::LedgerError;
+    use crate::mapper::{Account, Record};
+    use crate::owners::TransactionOwners;
+    use std::collections::HashMap;
+
+    // Tests that reused transaction ids and references to another client's transactions are
+    // rejected, including for transactions loaded with the accounts
+    #[test]
+    fn test_check() {
+        let mut account = Account::default();
+        account.deposit(5.0, 1);
+        let mut owners = TransactionOwners::of(&HashMap::from([(1, account)]));
+
+        owners.insert(&Record::deposit(2, 2, 1.0));
+        owners.insert(&Record::dispute(2, 3));
+        assert_eq!(owners.get(2), Some(2));
+        assert!(!owners.contains(3));
+
+        assert_eq!(
+            owners.check(&Record::deposit(2, 1, 1.0)),
+            Err(LedgerError::DuplicateTransaction(1))
+        );
+        assert_eq!(
+            owners.check(&Record::dispute(1, 2)),
+            Err(LedgerError::ClientMismatch(2, 1, 2))
+        );
+        assert_eq!(owners.check(&Record::dispute(2, 2)), Ok(()));
+        assert_eq!(owners.check(&Record::dispute(1, 9)), Ok(()));
+    }
+
+    // Tests that a reserved transaction id can't be reused until it's released
+    #[test]
+    fn test_reserve() {
+        let mut owners = TransactionOwners::default();
+        let deposit = Record::deposit(1, 1, 1.0);
+
+        owners.reserve(&deposit).unwrap();
+        assert_eq!(
+            owners.reserve(&Record::withdrawal(2, 1, 1.0)),
+            Err(LedgerError::DuplicateTransaction(1))
+        );
+
+        owners.release(&deposit);
+        assert!(owners.reserve(&Record::withdrawal(2, 1, 1.0)).is_ok());
+        assert_eq!(owners.get(1), Some(2));
+    }
+}
@@ -0,0 +1,806 @@
+use crate::error::{CliError, CliResult, SourceError, SourceResult};
+use crate::format::InputFormat;
+use crate::mapper::{normalize_amount, DecimalSeparator, Record, TransactionType};
+use crate::timestamp::parse_timestamp;
+use csv::{ReaderBuilder, StringRecord, Trim};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+use toml_edit::DocumentMut;
+
+/// Where the engine reads records from, e.g. a csv file, JSON lines, a socket or a test's records.
+/// Each record comes with the line (or position) it was read from, so rejections can be reported
+/// against it. Every source is applied by the same processing loop, see reader::apply_records.
+pub trait TransactionSource: Iterator<Item = SourceResult<(u64, Record)>> {}
+
+impl<T: Iterator<Item = SourceResult<(u64, Record)>>> TransactionSource for T {}
+
+/// The records parsed from a source, along with the line each was read from
+pub type Records<'a> = Box<dyn TransactionSource + 'a>;
+
+/// The columns of a csv or tsv file without a header row, in the order they're read in
+pub const HEADERLESS_COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "reason",
+    "timestamp",
+    "idempotency_key",
+    "destination",
+];
+
+/// How csv and tsv files are read
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// Which parser the rows are read with
+    pub backend: CsvBackend,
+
+    /// Whether the files don't start with a header row, so their columns are read by position,
+    /// see HEADERLESS_COLUMNS
+    pub no_headers: bool,
+
+    /// What separates the columns, when it isn't the format's own delimiter (a comma for csv and
+    /// a tab for tsv)
+    pub delimiter: Option<Delimiter>,
+
+    /// The header row's columns that are renamed before the rows are read
+    pub columns: ColumnMapping,
+
+    /// The decimal separator of amounts written for people (e.g. `$1,234.56`), which are read
+    /// leniently when it's provided, see normalize_amount
+    pub lenient_amounts: Option<DecimalSeparator>,
+}
+
+/// Renames the columns in a header row to the ones records are read from, so files with their own
+/// headers (e.g. txn_type rather than type) can be read without being rewritten. Columns that
+/// aren't renamed are read by their own name.
+///
+/// A mapping can be loaded from a TOML file, with a key for each column that's renamed:
+///
+/// ```toml
+/// txn_type = "type"
+/// customer = "client"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ColumnMapping(BTreeMap<String, String>);
+
+impl ColumnMapping {
+    /// Reads a mapping from a TOML file
+    pub fn load(file_path: &Path) -> CliResult<Self> {
+        let invalid = |message: String| {
+            CliError::InvalidColumnMapping(file_path.display().to_string(), message)
+        };
+        let text = fs::read_to_string(file_path).map_err(|err| invalid(err.to_string()))?;
+
+        ColumnMapping::parse(&text).map_err(invalid)
+    }
+
+    /// Parses a mapping, where each key is a header that's renamed to its value
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let document: DocumentMut = text.parse().map_err(|err| format!("{}", err))?;
+
+        let mut mapping = ColumnMapping::default();
+        for (header, item) in document.iter() {
+            let column = item
+                .as_str()
+                .ok_or_else(|| format!("`{}` must be renamed to a column name", header))?;
+            mapping.rename(header, column)?;
+        }
+
+        Ok(mapping)
+    }
+
+    /// Renames the header to the column, which must be one that records are read from (see
+    /// HEADERLESS_COLUMNS)
+    pub fn rename(&mut self, header: &str, column: &str) -> Result<(), String> {
+        if !HEADERLESS_COLUMNS.contains(&column) {
+            return Err(format!("`{}` isn't a column records are read from", column));
+        }
+
+        self.0.insert(header.to_string(), column.to_string());
+        Ok(())
+    }
+
+    /// The column that's read from the header, which is the header itself when it isn't renamed
+    pub fn column<'a>(&'a self, header: &'a str) -> &'a str {
+        self.0.get(header).map_or(header, String::as_str)
+    }
+}
+
+/// The delimiters that are looked for when the delimiter is detected
+const DETECTED_DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// The character that separates the columns of a csv or tsv file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delimiter {
+    /// Columns are always separated by the character
+    Fixed(u8),
+
+    /// Whichever of DETECTED_DELIMITERS appears most in the first line of the file separates its
+    /// columns. When none do, it's the format's own delimiter.
+    Auto,
+}
+
+impl Delimiter {
+    /// The delimiter the input is read with. Detecting it peeks at the first line, which is still
+    /// read by the parser afterwards.
+    fn resolve(&self, input: &mut impl BufRead, default: u8) -> SourceResult<u8> {
+        match self {
+            Delimiter::Fixed(delimiter) => Ok(*delimiter),
+            Delimiter::Auto => {
+                let start = input
+                    .fill_buf()
+                    .map_err(|err| SourceError::Io(err.to_string()))?;
+                Ok(detect_delimiter(start).unwrap_or(default))
+            }
+        }
+    }
+}
+
+impl FromStr for Delimiter {
+    type Err = CliError;
+
+    fn from_str(delimiter: &str) -> CliResult<Self> {
+        match delimiter.to_lowercase().as_str() {
+            "auto" => Ok(Delimiter::Auto),
+            "tab" | "\\t" | "\t" => Ok(Delimiter::Fixed(b'\t')),
+            // a single character that can't appear in a value or quote one
+            _ => match delimiter.as_bytes() {
+                [byte] if byte.is_ascii_punctuation() && *byte != b'"' => {
+                    Ok(Delimiter::Fixed(*byte))
+                }
+                _ => Err(CliError::InvalidValue(
+                    "--delimiter".to_string(),
+                    delimiter.to_string(),
+                )),
+            },
+        }
+    }
+}
+
+/// The delimiter that appears most in the first line with any content, none when none of
+/// DETECTED_DELIMITERS do
+fn detect_delimiter(start: &[u8]) -> Option<u8> {
+    let line = start
+        .split(|byte| *byte == b'\n')
+        .find(|line| !line.trim_ascii().is_empty())?;
+
+    DETECTED_DELIMITERS
+        .iter()
+        .map(|delimiter| {
+            (
+                line.iter().filter(|byte| *byte == delimiter).count(),
+                *delimiter,
+            )
+        })
+        .filter(|(count, _)| *count > 0)
+        .max_by_key(|(count, _)| *count)
+        .map(|(_, delimiter)| delimiter)
+}
+
+/// The source of the records in the input, which is in the format. Csv and tsv are parsed as the
+/// options describe.
+pub fn open_source<'a>(
+    input: Box<dyn Read + 'a>,
+    format: InputFormat,
+    options: &CsvOptions,
+) -> SourceResult<Records<'a>> {
+    let default = match format {
+        InputFormat::Csv => b',',
+        InputFormat::Tsv => b'\t',
+        InputFormat::Jsonl => return Ok(Box::new(jsonl_records(input))),
+    };
+
+    let mut input = BufReader::new(input);
+    let delimiter = match options.delimiter {
+        Some(delimiter) => delimiter.resolve(&mut input, default)?,
+        None => default,
+    };
+
+    options
+        .backend
+        .parser()
+        .records(Box::new(input), delimiter, options)
+}
+
+/// The records in JSON lines, along with the line each was read from. Blank lines are skipped.
+fn jsonl_records(input: impl Read) -> impl Iterator<Item = SourceResult<(u64, Record)>> {
+    BufReader::new(input)
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index as u64 + 1, line))
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(line, result)| {
+            let text = result.map_err(|err| SourceError::Io(err.to_string()))?;
+            let record = serde_json::from_str(&text).map_err(|err| SourceError::Parse {
+                line,
+                message: err.to_string(),
+            })?;
+
+            Ok((line, record))
+        })
+}
+
+/// Parses delimited transaction data (csv or tsv) into records
+pub trait RecordParser {
+    /// The records in the input, with its columns separated by the delimiter. It starts with a
+    /// header row, whose columns are renamed by the options' mapping, unless the options say it
+    /// has no headers, when its columns are HEADERLESS_COLUMNS. Rows that can't be parsed are
+    /// errors with the line they were read from, so they can be rejected or end the run.
+    fn records<'a>(
+        &self,
+        input: Box<dyn Read + 'a>,
+        delimiter: u8,
+        options: &CsvOptions,
+    ) -> SourceResult<Records<'a>>;
+}
+
+/// The implementations of RecordParser that can be chosen when running the engine
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CsvBackend {
+    /// The csv crate, which handles quoting and every other corner of the format
+    #[default]
+    Csv,
+
+    /// An experimental parser that splits each line on the delimiter. It's faster, but rows with
+    /// quoted fields are rejected.
+    Fast,
+}
+
+impl CsvBackend {
+    /// The parser for the backend
+    pub fn parser(&self) -> Box<dyn RecordParser> {
+        match self {
+            CsvBackend::Csv => Box::new(CsvParser),
+            CsvBackend::Fast => Box::new(FastParser),
+        }
+    }
+}
+
+impl FromStr for CsvBackend {
+    type Err = CliError;
+
+    fn from_str(backend: &str) -> CliResult<Self> {
+        match backend.to_lowercase().as_str() {
+            "csv" => Ok(CsvBackend::Csv),
+            "fast" => Ok(CsvBackend::Fast),
+            _ => Err(CliError::InvalidValue(
+                "--csv-backend".to_string(),
+                backend.to_string(),
+            )),
+        }
+    }
+}
+
+/// Parses records with the csv crate, trimming whitespace and allowing missing values
+pub struct CsvParser;
+
+impl RecordParser for CsvParser {
+    fn records<'a>(
+        &self,
+        input: Box<dyn Read + 'a>,
+        delimiter: u8,
+        options: &CsvOptions,
+    ) -> SourceResult<Records<'a>> {
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::Fields)
+            .flexible(true)
+            .has_headers(!options.no_headers)
+            .delimiter(delimiter)
+            .from_reader(input);
+        // rows are deserialized by name, so rows without a header are given the positional names
+        let headers: StringRecord = match options.no_headers {
+            false => {
+                let header = reader.headers().map_err(SourceError::from)?;
+                header
+                    .iter()
+                    .map(|name| options.columns.column(name))
+                    .collect()
+            }
+            true => StringRecord::from(HEADERLESS_COLUMNS.to_vec()),
+        };
+        let amount_column = headers.iter().position(|name| name == "amount");
+        let lenient_amounts = options.lenient_amounts;
+
+        Ok(Box::new(reader.into_records().map(move |result| {
+            let row = result.map_err(SourceError::from)?;
+            let line = row.position().map_or(0, |position| position.line());
+            let row = match (lenient_amounts, amount_column) {
+                (Some(decimal), Some(column)) => normalize_row(&row, column, decimal)
+                    .map_err(|message| SourceError::Parse { line, message })?,
+                _ => row,
+            };
+            let record = row
+                .deserialize(Some(&headers))
+                .map_err(|err| SourceError::Parse {
+                    line,
+                    message: err.to_string(),
+                })?;
+
+            Ok((line, record))
+        })))
+    }
+}
+
+/// The row with its amount, in the column, rewritten as a plain decimal, or why it can't be
+fn normalize_row(
+    row: &StringRecord,
+    column: usize,
+    decimal: DecimalSeparator,
+) -> Result<StringRecord, String> {
+    let mut normalized = StringRecord::with_capacity(row.as_slice().len(), row.len());
+    for (index, field) in row.iter().enumerate() {
+        match index == column && !field.is_empty() {
+            true => normalized.push_field(&lenient_amount(field, decimal)?),
+            false => normalized.push_field(field),
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// The amount written for people as a plain decimal, or why it can't be
+fn lenient_amount(value: &str, decimal: DecimalSeparator) -> Result<String, String> {
+    normalize_amount(value, decimal)
+        .ok_or_else(|| format!("invalid value `{}` for field `amount`", value))
+}
+
+/// Parses records by splitting each line on the delimiter, without going through serde. Quoted
+/// fields aren't supported, so rows containing a quote are errors.
+pub struct FastParser;
+
+impl RecordParser for FastParser {
+    fn records<'a>(
+        &self,
+        input: Box<dyn Read + 'a>,
+        delimiter: u8,
+        options: &CsvOptions,
+    ) -> SourceResult<Records<'a>> {
+        let mut lines = FastLines {
+            reader: BufReader::new(input),
+            buffer: String::new(),
+            line: 0,
+        };
+
+        let columns = match options.no_headers {
+            false => match lines.next_line()? {
+                Some((_, header)) => {
+                    let names: Vec<&str> = header
+                        .split(delimiter as char)
+                        .map(|name| options.columns.column(name))
+                        .collect();
+                    Columns::from_names(&names)
+                }
+                None => Columns::default(),
+            },
+            true => Columns::from_names(&HEADERLESS_COLUMNS),
+        };
+        let columns = Columns {
+            lenient_amounts: options.lenient_amounts,
+            ..columns
+        };
+
+        Ok(Box::new(std::iter::from_fn(move || {
+            let (line, text) = match lines.next_line() {
+                Ok(Some(read)) => read,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+
+            Some(
+                columns
+                    .record(text, delimiter as char)
+                    .map(|record| (line, record))
+                    .map_err(|message| SourceError::Parse { line, message }),
+            )
+        })))
+    }
+}
+
+/// Reads the lines of the input into a reused buffer, skipping blank lines as the csv crate does
+struct FastLines<R> {
+    /// The input being read
+    reader: BufReader<R>,
+
+    /// The line that was read last, without its line ending
+    buffer: String,
+
+    /// The number of the line that was read last
+    line: u64,
+}
+
+impl<R: Read> FastLines<R> {
+    /// The next line that isn't blank along with its number, or none at the end of the input
+    fn next_line(&mut self) -> SourceResult<Option<(u64, &str)>> {
+        loop {
+            self.buffer.clear();
+            let read = self
+                .reader
+                .read_line(&mut self.buffer)
+                .map_err(|err| SourceError::Io(err.to_string()))?;
+            if read == 0 {
+                return Ok(None);
+            }
+
+            self.line += 1;
+            if !self.buffer.trim_end_matches(['\r', '\n']).is_empty() {
+                break;
+            }
+        }
+
+        Ok(Some((
+            self.line,
+            self.buffer.trim_end_matches(['\r', '\n']),
+        )))
+    }
+}
+
+/// Where each field of a record is found in a row, taken from the header
+#[derive(Debug, Default)]
+pub(crate) struct Columns {
+    /// The type column, none when the header doesn't have one
+    transaction_type: Option<usize>,
+
+    /// The client column
+    client: Option<usize>,
+
+    /// The tx column
+    tx: Option<usize>,
+
+    /// The amount column
+    amount: Option<usize>,
+
+    /// The reason column
+    reason: Option<usize>,
+
+    /// The timestamp column
+    timestamp: Option<usize>,
+
+    /// The idempotency_key column
+    idempotency_key: Option<usize>,
+
+    /// The destination column
+    destination: Option<usize>,
+
+    /// The decimal separator of amounts that are read leniently, none when they're read as plain
+    /// decimals
+    lenient_amounts: Option<DecimalSeparator>,
+}
+
+impl Columns {
+    /// Finds the columns in the header row
+    pub(crate) fn new(header: &str, delimiter: char) -> Self {
+        let names: Vec<&str> = header.split(delimiter).collect();
+        Columns::from_names(&names)
+    }
+
+    /// Finds the columns in the names of a row's columns, in order
+    pub(crate) fn from_names(names: &[&str]) -> Self {
+        let position = |name: &str| names.iter().position(|column| *column == name);
+
+        Columns {
+            transaction_type: position("type"),
+            client: position("client"),
+            tx: position("tx"),
+            amount: position("amount"),
+            reason: position("reason"),
+            timestamp: position("timestamp"),
+            idempotency_key: position("idempotency_key"),
+            destination: position("destination"),
+            lenient_amounts: None,
+        }
+    }
+
+    /// Parses the value of the amount column
+    fn amount(&self, value: &str) -> Result<f32, String> {
+        match self.lenient_amounts {
+            Some(decimal) => parse(&lenient_amount(value, decimal)?, "amount"),
+            None => parse(value, "amount"),
+        }
+    }
+
+    /// Parses a row into a record, or explains why it can't be
+    pub(crate) fn record(&self, text: &str, delimiter: char) -> Result<Record, String> {
+        if text.contains('"') {
+            return Err("quoted fields aren't supported by the fast csv backend".to_string());
+        }
+
+        let fields: Vec<&str> = text.split(delimiter).map(str::trim).collect();
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|index| fields.get(index).copied())
+                .filter(|value| !value.is_empty())
+        };
+        let required =
+            |column, name: &str| field(column).ok_or_else(|| format!("missing field `{}`", name));
+
+        let name = required(self.transaction_type, "type")?;
+        let transaction_type =
+            transaction_type(name).ok_or_else(|| format!("unknown transaction type `{}`", name))?;
+
+        Ok(Record {
+            transaction_type,
+            client_id: parse(required(self.client, "client")?, "client")?,
+            transaction_id: parse(required(self.tx, "tx")?, "tx")?,
+            amount: field(self.amount)
+                .map(|value| self.amount(value))
+                .transpose()?,
+            reason: field(self.reason).map(str::to_string),
+            timestamp: field(self.timestamp).map(parse_timestamp).transpose()?,
+            idempotency_key: field(self.idempotency_key).map(str::to_string),
+            destination: field(self.destination)
+                .map(|value| parse(value, "destination"))
+                .transpose()?,
+        })
+    }
+}
+
+/// The transaction type with the name, as it's written in transaction files
+fn transaction_type(name: &str) -> Option<TransactionType> {
+    [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Adjustment,
+        TransactionType::Void,
+        TransactionType::Escheated,
+        TransactionType::Transfer,
+        TransactionType::Refund,
+        TransactionType::Unlock,
+    ]
+    .into_iter()
+    .find(|transaction_type| transaction_type.name() == name)
+}
+
+/// Parses the value of a field
+fn parse<T: FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for field `{}`", value, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SourceError;
+    use crate::format::InputFormat;
+    use crate::generator::{generate, GeneratorConfig};
+    use crate::mapper::{normalize_amount, DecimalSeparator, Record};
+    use crate::parser::{
+        detect_delimiter, open_source, ColumnMapping, CsvBackend, CsvOptions, Delimiter,
+    };
+    use std::io::Cursor;
+
+    /// The records a backend parses from the input, with parse errors reduced to their line
+    fn parse(backend: CsvBackend, input: &[u8], delimiter: u8) -> Vec<Result<(u64, Record), u64>> {
+        backend
+            .parser()
+            .records(Box::new(input), delimiter, &CsvOptions::default())
+            .unwrap()
+            .map(|result| {
+                result.map_err(|err| match err {
+                    SourceError::Parse { line, .. } => line,
+                    err => panic!("unexpected error {}", err),
+                })
+            })
+            .collect()
+    }
+
+    // Tests that both backends parse the same records from the same input, including whitespace,
+    // missing amounts and rows that can't be parsed. The csv crate numbers a row that follows a
+    // blank line or a crlf line ending by the line before it, so those are left out.
+    #[test]
+    fn test_backends_agree() {
+        let input = "type,client,tx,amount\n\
+            deposit, 1, 1, 10.5\n\
+            dispute,1,1,\n\
+            resolve,1,1\n\
+            withdrawal,2,x,1.0\n\
+            reversal,2,3,1.0\n";
+        let csv = parse(CsvBackend::Csv, input.as_bytes(), b',');
+        let fast = parse(CsvBackend::Fast, input.as_bytes(), b',');
+
+        assert_eq!(csv[0], Ok((2, Record::deposit(1, 1, 10.5))));
+        assert_eq!(csv[2], Ok((4, Record::resolve(1, 1))));
+        assert_eq!(&csv[3..], [Err(5), Err(6)]);
+
+        assert_eq!(csv, fast);
+    }
+
+    // Tests that both backends parse the generated workload, and tsv, identically
+    #[test]
+    fn test_backends_agree_on_generated_file() {
+        let mut input = vec![];
+        let config = GeneratorConfig {
+            invalid_rate: 0.05,
+            ..Default::default()
+        };
+        generate(&config, &mut input).unwrap();
+
+        let csv = parse(CsvBackend::Csv, &input, b',');
+        assert_eq!(csv, parse(CsvBackend::Fast, &input, b','));
+        assert_eq!(csv.len(), 1_000);
+
+        let tsv = String::from_utf8(input).unwrap().replace(',', "\t");
+        assert_eq!(parse(CsvBackend::Fast, tsv.as_bytes(), b'\t'), csv);
+    }
+
+    // Tests that the fast backend rejects quoted fields, which only the csv backend supports
+    #[test]
+    fn test_fast_backend_quoted_fields() {
+        let input = b"type,client,tx,amount,reason\nadjustment,1,1,1.0,\"fee, refunded\"\n";
+
+        assert!(parse(CsvBackend::Csv, input, b',')[0].is_ok());
+        assert_eq!(parse(CsvBackend::Fast, input, b','), [Err(2)]);
+    }
+
+    // Tests that each format is opened as a source of the same records
+    #[test]
+    fn test_open_source() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,10.5\ndispute,1,1,\n";
+        let jsonl = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":10.5}\n\n\
+            {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n";
+        let records = |input: &str, format| -> Vec<Record> {
+            open_source(
+                Box::new(Cursor::new(input.to_string())),
+                format,
+                &CsvOptions::default(),
+            )
+            .unwrap()
+            .map(|result| result.unwrap().1)
+            .collect()
+        };
+
+        let expected = vec![Record::deposit(1, 1, 10.5), Record::dispute(1, 1)];
+        assert_eq!(records(csv, InputFormat::Csv), expected);
+        assert_eq!(records(&csv.replace(',', "\t"), InputFormat::Tsv), expected);
+        assert_eq!(records(jsonl, InputFormat::Jsonl), expected);
+    }
+
+    // Tests that both backends read the columns of a file without a header by position, taking
+    // the first row as a record rather than a header
+    #[test]
+    fn test_headerless() {
+        let input = "deposit,1,1,10.5
+dispute,1,1
+withdrawal,2,x,1.0
+";
+        for backend in [CsvBackend::Csv, CsvBackend::Fast] {
+            let options = CsvOptions {
+                backend,
+                no_headers: true,
+                ..Default::default()
+            };
+            let source = open_source(Box::new(input.as_bytes()), InputFormat::Csv, &options);
+            let records: Vec<_> = source
+                .unwrap()
+                .map(|result| result.map_err(|err| err.to_string()))
+                .collect();
+
+            assert_eq!(records[0], Ok((1, Record::deposit(1, 1, 10.5))));
+            assert_eq!(records[1], Ok((2, Record::dispute(1, 1))));
+            assert!(records[2].is_err());
+        }
+    }
+
+    // Tests that both backends read columns separated by a given delimiter, or by the one that's
+    // detected from the header row
+    #[test]
+    fn test_delimiter() {
+        let input = "type;client;tx;amount\ndeposit;1;1;10.5\ndispute;1;1\n";
+        for backend in [CsvBackend::Csv, CsvBackend::Fast] {
+            for delimiter in [Delimiter::Fixed(b';'), Delimiter::Auto] {
+                let options = CsvOptions {
+                    backend,
+                    delimiter: Some(delimiter),
+                    ..Default::default()
+                };
+                let source = open_source(Box::new(input.as_bytes()), InputFormat::Csv, &options);
+                let records: Vec<_> = source.unwrap().map(|result| result.unwrap()).collect();
+
+                let expected = vec![(2, Record::deposit(1, 1, 10.5)), (3, Record::dispute(1, 1))];
+                assert_eq!(records, expected);
+            }
+        }
+
+        assert_eq!(detect_delimiter(b"\ntype\tclient\ttx\n"), Some(b'\t'));
+        assert_eq!(detect_delimiter(b"type|client|tx,amount\n"), Some(b'|'));
+        assert_eq!(detect_delimiter(b"type client tx\n"), None);
+    }
+
+    // Tests that both backends read columns with their own headers by the names they're renamed
+    // to, and that only the columns records are read from can be renamed to
+    #[test]
+    fn test_column_mapping() {
+        let input = "txn_type,customer,txn_id,value\ndeposit,1,1,10.5\ndispute,1,1,\n";
+        let mapping = "txn_type = \"type\"
+customer = \"client\"
+txn_id = \"tx\"
+";
+        let mut columns = ColumnMapping::parse(mapping).unwrap();
+        columns.rename("value", "amount").unwrap();
+
+        for backend in [CsvBackend::Csv, CsvBackend::Fast] {
+            let options = CsvOptions {
+                backend,
+                columns: columns.clone(),
+                ..Default::default()
+            };
+            let source = open_source(Box::new(input.as_bytes()), InputFormat::Csv, &options);
+            let records: Vec<_> = source.unwrap().map(|result| result.unwrap()).collect();
+
+            let expected = vec![(2, Record::deposit(1, 1, 10.5)), (3, Record::dispute(1, 1))];
+            assert_eq!(records, expected);
+        }
+
+        assert_eq!(
+            ColumnMapping::parse("customer = \"customer\""),
+            Err("`customer` isn't a column records are read from".to_string())
+        );
+        assert!(ColumnMapping::parse("customer = 1").is_err());
+    }
+
+    // Tests that both backends read amounts written for people when they're read leniently, with
+    // either decimal separator, and still reject values that aren't amounts
+    #[test]
+    fn test_lenient_amounts() {
+        let input = "type,client,tx,amount
+deposit,1,1,\"$1,234.56\"
+deposit,1,2,99.99 USD
+withdrawal,1,3,-€5
+deposit,1,4,12$34
+";
+        for backend in [CsvBackend::Csv, CsvBackend::Fast] {
+            let options = CsvOptions {
+                backend,
+                lenient_amounts: Some(DecimalSeparator::Point),
+                ..Default::default()
+            };
+            let source = open_source(Box::new(input.as_bytes()), InputFormat::Csv, &options);
+            let records: Vec<_> = source
+                .unwrap()
+                .map(|result| result.map(|(_, record)| record.amount).map_err(|_| ()))
+                .collect();
+
+            // the fast backend doesn't support quoted fields
+            if backend == CsvBackend::Csv {
+                assert_eq!(records[0], Ok(Some(1234.56)));
+            }
+            assert_eq!(records[1..], [Ok(Some(99.99)), Ok(Some(-5.0)), Err(())]);
+        }
+
+        let input = "type,client,tx,amount\ndeposit,1,5,\"1,50\"\n";
+        let options = CsvOptions {
+            lenient_amounts: Some(DecimalSeparator::Point),
+            ..Default::default()
+        };
+        let source = open_source(Box::new(input.as_bytes()), InputFormat::Csv, &options);
+        let records: Vec<_> = source.unwrap().collect();
+        assert!(matches!(
+            records[..],
+            [Err(SourceError::Parse { line: 2, .. })]
+        ));
+
+        let normalize = |value| normalize_amount(value, DecimalSeparator::Comma);
+        assert_eq!(normalize("1.234,56 €"), Some("1234.56".to_string()));
+        assert_eq!(normalize("1 234,5"), Some("1234.5".to_string()));
+        assert_eq!(normalize("EUR"), None);
+        let normalized = normalize_amount("CHF 1'000.25", DecimalSeparator::Point);
+        assert_eq!(normalized, Some("1000.25".to_string()));
+
+        // a separator that doesn't group the thousands is ambiguous, so the amount is rejected
+        // rather than read as something else
+        let normalize = |value| normalize_amount(value, DecimalSeparator::Point);
+        assert_eq!(normalize("1,234,567.5"), Some("1234567.5".to_string()));
+        assert_eq!(normalize("1,50"), None);
+        assert_eq!(normalize("1.234,5"), None);
+        assert_eq!(normalize("12,34,567"), None);
+        assert_eq!(normalize("1,234 567"), None);
+        assert_eq!(normalize("1.2.3"), None);
+        assert_eq!(normalize(",234"), None);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::error::{CliError, CliResult};
+use std::str::FromStr;
+
+/// Decides which partition (e.g. a shard of the shared engine) holds each client's account.
+/// Implementing it lets the engine's shards line up with an existing sharding of the same clients,
+/// such as a database's, so a client's transactions never cross shards.
+pub trait ClientPartitioner: Send + Sync {
+    /// The partition that holds the client, which must be less than the number of partitions
+    fn partition(&self, client_id: u16, partitions: usize) -> usize;
+}
+
+/// Hashes clients onto partitions with the identity hash, i.e. a client's partition is its id
+/// modulo the number of partitions. Consecutive ids are spread evenly across the partitions.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HashPartitioner;
+
+impl ClientPartitioner for HashPartitioner {
+    fn partition(&self, client_id: u16, partitions: usize) -> usize {
+        client_id as usize % partitions.max(1)
+    }
+}
+
+/// Splits clients into contiguous ranges of ids, each partition holding the clients from its
+/// start up to the start of the next one. Clients below the first start are in the first
+/// partition, and the last partition holds every range beyond the number of partitions.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RangePartitioner {
+    /// The first client id of each partition, in ascending order
+    starts: Vec<u16>,
+}
+
+impl RangePartitioner {
+    /// Creates a partitioner from the first client id of each partition, which must be ascending
+    pub fn new(starts: Vec<u16>) -> Option<Self> {
+        let ascending = starts.windows(2).all(|pair| pair[0] < pair[1]);
+        (ascending && !starts.is_empty()).then_some(RangePartitioner { starts })
+    }
+
+    /// The number of ranges, the number of partitions needed for each range to have its own
+    pub fn ranges(&self) -> usize {
+        self.starts.len()
+    }
+}
+
+impl ClientPartitioner for RangePartitioner {
+    fn partition(&self, client_id: u16, partitions: usize) -> usize {
+        let range = self.starts.partition_point(|start| *start <= client_id);
+
+        range.saturating_sub(1).min(partitions.max(1) - 1)
+    }
+}
+
+impl FromStr for RangePartitioner {
+    type Err = CliError;
+
+    /// Parses the comma separated first client id of each partition, e.g. `0,1000,5000`
+    fn from_str(starts: &str) -> CliResult<Self> {
+        let invalid = || CliError::InvalidValue("--shard-ranges".to_string(), starts.to_string());
+
+        let starts = starts
+            .split(',')
+            .map(|start| start.trim().parse().map_err(|_| invalid()))
+            .collect::<CliResult<Vec<u16>>>()?;
+
+        RangePartitioner::new(starts).ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::partition::{ClientPartitioner, HashPartitioner, RangePartitioner};
+
+    // Tests that clients are hashed or ranged onto the expected partitions
+    #[test]
+    fn test_partition() {
+        assert_eq!(HashPartitioner.partition(17, 16), 1);
+        assert_eq!(HashPartitioner.partition(17, 0), 0);
+
+        let ranges: RangePartitioner = "100, 1000, 5000".parse().unwrap();
+        assert_eq!(ranges.ranges(), 3);
+        assert_eq!(ranges.partition(0, 3), 0);
+        assert_eq!(ranges.partition(999, 3), 0);
+        assert_eq!(ranges.partition(1000, 3), 1);
+        assert_eq!(ranges.partition(u16::MAX, 3), 2);
+        // ranges beyond the number of partitions are held by the last one
+        assert_eq!(ranges.partition(u16::MAX, 2), 1);
+
+        assert!("1000,100".parse::<RangePartitioner>().is_err());
+        assert!("".parse::<RangePartitioner>().is_err());
+        assert!("0,x".parse::<RangePartitioner>().is_err());
+    }
+}
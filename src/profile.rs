@@ -0,0 +1,256 @@
+use crate::error::{SourceError, SourceResult};
+use csv::{ReaderBuilder, Trim};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The columns that distinct values are counted for, the others are too unique to be useful
+const DISTINCT_COLUMNS: [&str; 2] = ["type", "client"];
+
+/// Transaction types that reference an earlier transaction, so their tx ids are expected to repeat
+const REFERENCING_TYPES: [&str; 3] = ["dispute", "resolve", "chargeback"];
+
+/// Data quality statistics for a file of transactions, gathered without applying any of them
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Profile {
+    /// The number of rows, excluding the header
+    pub rows: usize,
+
+    /// The statistics for each column, in the order of the header
+    pub columns: Vec<ColumnProfile>,
+
+    /// How many amounts have each number of decimal places
+    pub amount_precision: BTreeMap<usize, usize>,
+
+    /// The number of rows that reuse the tx id of an earlier deposit, withdrawal or adjustment
+    pub duplicate_tx: usize,
+
+    /// The proportion of rows that reuse the tx id of an earlier transaction
+    pub duplicate_tx_rate: f64,
+}
+
+/// Data quality statistics for a single column
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ColumnProfile {
+    /// The name of the column, as it appears in the header
+    pub name: String,
+
+    /// The number of rows where the column is empty or missing
+    pub nulls: usize,
+
+    /// The proportion of rows where the column is empty or missing
+    pub null_rate: f64,
+
+    /// The smallest numeric value, when every value in the column is numeric
+    pub min: Option<f64>,
+
+    /// The largest numeric value, when every value in the column is numeric
+    pub max: Option<f64>,
+
+    /// The number of distinct values, only counted for the type and client columns
+    pub distinct: Option<usize>,
+}
+
+/// Accumulates the statistics of a column as rows are read
+#[derive(Default)]
+struct ColumnStats {
+    /// The number of empty or missing values
+    nulls: usize,
+
+    /// The smallest and largest values, until a non-numeric value is found
+    range: Option<(f64, f64)>,
+
+    /// Whether every value seen so far is numeric
+    numeric: bool,
+
+    /// The distinct values, for the columns they're counted for
+    distinct: Option<HashSet<String>>,
+}
+
+impl ColumnStats {
+    /// Creates the stats for a column with the given name
+    fn new(name: &str) -> Self {
+        ColumnStats {
+            numeric: true,
+            distinct: DISTINCT_COLUMNS.contains(&name).then(HashSet::new),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a value to the stats
+    fn add(&mut self, value: Option<&str>) {
+        let value = match value.filter(|value| !value.is_empty()) {
+            Some(value) => value,
+            None => {
+                self.nulls += 1;
+                return;
+            }
+        };
+
+        if let Some(distinct) = self.distinct.as_mut() {
+            distinct.insert(value.to_lowercase());
+        }
+
+        match (self.numeric, value.parse::<f64>()) {
+            (true, Ok(number)) => {
+                let (min, max) = self.range.unwrap_or((number, number));
+                self.range = Some((min.min(number), max.max(number)));
+            }
+            _ => {
+                self.numeric = false;
+                self.range = None;
+            }
+        }
+    }
+
+    /// Finishes the stats for a column
+    fn into_profile(self, name: &str, rows: usize) -> ColumnProfile {
+        ColumnProfile {
+            name: name.to_string(),
+            nulls: self.nulls,
+            null_rate: rate(self.nulls, rows),
+            min: self.range.map(|(min, _)| min),
+            max: self.range.map(|(_, max)| max),
+            distinct: self.distinct.map(|distinct| distinct.len()),
+        }
+    }
+}
+
+/// Reads every row of a csv and gathers its data quality statistics. Rows are profiled as raw
+/// text, so malformed values are counted rather than stopping the profile.
+pub fn profile_csv(file_path: impl AsRef<Path>) -> SourceResult<Profile> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::Fields)
+        .flexible(true)
+        .from_path(file_path)
+        .map_err(SourceError::from)?;
+    let headers = reader.headers().map_err(SourceError::from)?.clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let (type_column, tx_column, amount_column) = (column("type"), column("tx"), column("amount"));
+
+    let mut stats: Vec<ColumnStats> = headers.iter().map(ColumnStats::new).collect();
+    let mut amount_precision = BTreeMap::new();
+    let mut seen_tx = HashSet::new();
+    let mut duplicate_tx = 0;
+    let mut rows = 0;
+
+    for result in reader.records() {
+        let row = result.map_err(SourceError::from)?;
+        rows += 1;
+
+        for (index, column_stats) in stats.iter_mut().enumerate() {
+            column_stats.add(row.get(index));
+        }
+
+        if let Some(amount) = amount_column.and_then(|index| row.get(index)) {
+            if !amount.is_empty() {
+                let places = amount
+                    .split_once('.')
+                    .map_or(0, |(_, decimals)| decimals.len());
+                *amount_precision.entry(places).or_insert(0) += 1;
+            }
+        }
+
+        // disputes, resolves and chargebacks are expected to reuse the tx id they reference
+        let transaction_type = type_column.and_then(|index| row.get(index)).unwrap_or("");
+        if REFERENCING_TYPES.contains(&transaction_type.to_lowercase().as_str()) {
+            continue;
+        }
+
+        if let Some(tx) = tx_column.and_then(|index| row.get(index)) {
+            if !seen_tx.insert(tx.to_string()) {
+                duplicate_tx += 1;
+            }
+        }
+    }
+
+    Ok(Profile {
+        rows,
+        columns: stats
+            .into_iter()
+            .zip(headers.iter())
+            .map(|(column_stats, name)| column_stats.into_profile(name, rows))
+            .collect(),
+        amount_precision,
+        duplicate_tx,
+        duplicate_tx_rate: rate(duplicate_tx, rows),
+    })
+}
+
+/// Writes a profile as JSON to the output file, or std out when one isn't provided
+pub fn write_profile(profile: &Profile, output_path: Option<&Path>) -> SourceResult<()> {
+    let mut writer: Box<dyn Write> = match output_path {
+        Some(output_path) => {
+            let file = File::create(output_path).map_err(|err| SourceError::Io(err.to_string()))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(io::stdout()),
+    };
+
+    serde_json::to_writer_pretty(&mut writer, profile)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+    writeln!(writer).map_err(|err| SourceError::Io(err.to_string()))?;
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// The proportion of rows, zero when there aren't any
+fn rate(count: usize, rows: usize) -> f64 {
+    match rows {
+        0 => 0.0,
+        rows => count as f64 / rows as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::profile_csv;
+    use crate::test_helpers::*;
+    use approx::assert_relative_eq;
+    use std::collections::BTreeMap;
+    use std::io::Error;
+
+    // Tests that null rates, ranges, distinct counts, precision and duplicate tx ids are profiled
+    #[test]
+    fn test_profile_csv() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,1.5",
+            "deposit,2,2,10.25",
+            "withdrawal,1,2,",
+            "dispute,2,2,",
+            "deposit,x,3,2",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let profile = profile_csv(&file_path_str).unwrap();
+
+        assert_eq!(profile.rows, 5);
+        assert_eq!(profile.duplicate_tx, 1);
+        assert_relative_eq!(profile.duplicate_tx_rate, 0.2);
+        assert_eq!(
+            profile.amount_precision,
+            BTreeMap::from([(0, 1), (1, 1), (2, 1)])
+        );
+
+        let [transaction_type, client, tx, amount] = &profile.columns[..] else {
+            panic!("expected 4 columns");
+        };
+        assert_eq!(transaction_type.distinct, Some(3));
+        assert_eq!(client.distinct, Some(3));
+        assert_eq!((client.min, client.max), (None, None));
+        assert_eq!((tx.min, tx.max, tx.distinct), (Some(1.0), Some(3.0), None));
+        assert_eq!(amount.nulls, 2);
+        assert_relative_eq!(amount.null_rate, 0.4);
+        assert_eq!((amount.min, amount.max), (Some(1.5), Some(10.25)));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use crate::mapper::{Account, LockState, Record, TransactionType};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Fraud rules that quarantine a client once their transactions cross them, each rule is optional.
+/// A quarantined client's deposits are held and their withdrawals rejected until an operator
+/// releases them.
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct QuarantineRules {
+    /// Quarantines a client once this many of their transactions have been disputed in the run
+    pub disputes: Option<u32>,
+
+    /// Quarantines a client once this many of their transactions have been charged back in the run
+    pub chargebacks: Option<u32>,
+}
+
+/// Whether a client entered or left quarantine
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuarantineChange {
+    /// The rule quarantined the client
+    Entered { rule: String },
+
+    /// The operator released the client, along with the deposits that were held
+    Released { operator: String, deposits: usize },
+}
+
+/// A client entering or leaving quarantine, as it's reported once the run finishes
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantineEvent {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The transaction that crossed the rule, none for a release
+    pub tx: Option<u32>,
+
+    /// What happened to the client
+    pub change: QuarantineChange,
+}
+
+impl fmt::Display for QuarantineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.change {
+            QuarantineChange::Entered { rule } => write!(
+                f,
+                "client {} entered quarantine on transaction {} ({})",
+                self.client,
+                self.tx.unwrap_or_default(),
+                rule
+            ),
+            QuarantineChange::Released { operator, deposits } => write!(
+                f,
+                "client {} was released from quarantine by {}, releasing {} held deposits",
+                self.client, operator, deposits
+            ),
+        }
+    }
+}
+
+/// Counts the disputes and chargebacks of each client, quarantining them when they cross a rule
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    /// The rules clients are quarantined by
+    rules: QuarantineRules,
+
+    /// The number of disputes and chargebacks of each client during the run
+    counts: HashMap<u16, (u32, u32)>,
+
+    /// Every client that entered or left quarantine, in the order it happened
+    events: Vec<QuarantineEvent>,
+}
+
+impl Quarantine {
+    /// Creates a quarantine that applies the rules
+    pub fn new(rules: QuarantineRules) -> Self {
+        Quarantine {
+            rules,
+            ..Default::default()
+        }
+    }
+
+    /// Counts an applied record towards its client's rules, quarantining the account when the
+    /// record crosses one. Accounts that are already locked are left as they are.
+    pub fn observe(&mut self, record: &Record, account: &mut Account) {
+        let counts = self.counts.entry(record.client_id).or_default();
+        let rule = match record.transaction_type {
+            TransactionType::Dispute => {
+                counts.0 += 1;
+                crossed(self.rules.disputes, counts.0, "disputes")
+            }
+            TransactionType::Chargeback => {
+                counts.1 += 1;
+                crossed(self.rules.chargebacks, counts.1, "chargebacks")
+            }
+            _ => None,
+        };
+
+        if let (Some(rule), false) = (rule, account.lock_state.is_locked()) {
+            account.lock_state = LockState::Quarantine {
+                rule: rule.clone(),
+                held: vec![],
+            };
+            self.events.push(QuarantineEvent {
+                client: record.client_id,
+                tx: Some(record.transaction_id),
+                change: QuarantineChange::Entered { rule },
+            });
+        }
+    }
+
+    /// Reports an operator releasing a client from quarantine
+    pub fn release(&mut self, client_id: u16, operator: &str, deposits: usize) {
+        self.events.push(QuarantineEvent {
+            client: client_id,
+            tx: None,
+            change: QuarantineChange::Released {
+                operator: operator.to_string(),
+                deposits,
+            },
+        });
+    }
+
+    /// Returns every client that entered or left quarantine so far
+    pub fn take_events(&mut self) -> Vec<QuarantineEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// The rule, when the count has just reached its threshold
+fn crossed(threshold: Option<u32>, count: u32, name: &str) -> Option<String> {
+    threshold
+        .filter(|threshold| count == *threshold)
+        .map(|threshold| format!("{} {}", threshold, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::{Account, LockState, Record};
+    use crate::quarantine::{Quarantine, QuarantineChange, QuarantineRules};
+
+    // Tests that a client is quarantined once, by the record that crosses the rule
+    #[test]
+    fn test_quarantine_observe() {
+        let mut quarantine = Quarantine::new(QuarantineRules {
+            disputes: Some(2),
+            chargebacks: None,
+        });
+
+        let mut account = Account::default();
+        quarantine.observe(&Record::dispute(1, 1), &mut account);
+        assert_eq!(account.lock_state, LockState::Unlocked);
+        quarantine.observe(&Record::chargeback(1, 1), &mut account);
+        assert_eq!(account.lock_state, LockState::Unlocked);
+        quarantine.observe(&Record::dispute(1, 2), &mut account);
+        assert_eq!(
+            account.lock_state,
+            LockState::Quarantine {
+                rule: "2 disputes".to_string(),
+                held: vec![],
+            }
+        );
+        quarantine.observe(&Record::dispute(1, 3), &mut account);
+
+        // the rules are counted for each client
+        let mut other = Account::default();
+        quarantine.observe(&Record::dispute(2, 4), &mut other);
+        assert_eq!(other.lock_state, LockState::Unlocked);
+
+        quarantine.release(1, "alice", 0);
+        let events = quarantine.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].tx, Some(2));
+        assert_eq!(
+            events[0].to_string(),
+            "client 1 entered quarantine on transaction 2 (2 disputes)"
+        );
+        assert!(
+            matches!(&events[1].change, QuarantineChange::Released { operator, .. }
+            if operator == "alice")
+        );
+        assert!(quarantine.take_events().is_empty());
+    }
+}
@@ -1,136 +1,996 @@
+use crate::admin::{load_admin_operations, AdminPhase};
+use crate::alerts::{log_alerts, write_alerts_report};
+use crate::annotate::annotate;
+use crate::anonymize::Anonymizer;
+use crate::audit::AuditLog;
+use crate::breaker::CircuitBreaker;
+use crate::cli::{CliArgs, Command};
+use crate::clients::{
+    load_account_flags, load_client_countries, load_client_ids, write_clients_report,
+};
+use crate::config::{EngineConfig, OrderingPolicy};
+use crate::currency::Currency;
+use crate::cutover::DailyCutover;
+use crate::emit::emit_events_to;
+use crate::engine::{order_resolves_first, Engine};
+use crate::error::{
+    CliError, CliResult, EngineError, EngineResult, ExitReport, SourceError, SourceResult,
+};
+use crate::escheat::write_escheatment_report;
+use crate::fields::FieldFormatter;
+use crate::format::{open_input, InputFormat};
+use crate::generator::generate;
+use crate::graph::export_graph;
+use crate::handover::{take_over, HandoverListener};
+use crate::idempotency::IdempotencyKeys;
+use crate::index::{find_transactions, save_index};
+use crate::journal::Journal;
+use crate::lint::{lint_csv, write_lint};
+use crate::lock::StateLock;
+use crate::logging::init_tracing;
+use crate::losses::{write_loss_report, DEFAULT_LOSS_ACCOUNT};
 use crate::mapper::{
-    Account, AccountRecord, ReaderError, ReaderResult, Record, TransactionType,
-    VALID_FILE_EXTENSION,
+    Account, AccountRecord, AccountRecordV2, OutputFormat, OutputVersion, Record, TransactionType,
 };
-use anyhow::Result;
-use csv::{ReaderBuilder, Trim};
+use crate::merge::{expand_pattern, MergedSource};
+use crate::metadata::{write_summary, DigestWriter, RunMetadata, RunSummary};
+use crate::migrate::migrate_state;
+use crate::olap::{OlapExport, OLAP_FILES};
+use crate::parser::{open_source, CsvOptions, CsvParser, RecordParser, TransactionSource};
+use crate::profile::{profile_csv, write_profile};
+use crate::reconcile::{write_reconciliation, Postings, Reconciliation};
+use crate::results::TxResults;
+use crate::runbook::{run_runbook, Runbook};
+use crate::screening::write_compliance_report;
+use crate::server::{serve, serve_readonly, DisputeDesk, DEFAULT_ADDR, DEFAULT_READONLY_ADDR};
+use crate::shared::{SharedEngine, DEFAULT_SHARD_COUNT};
+use crate::sink::{output_sink, write_accounts_to};
+use crate::spill::DiskStore;
+use crate::state::{
+    diff_accounts, export_state, import_state, load_state, save_state, snapshot_balances,
+};
+use crate::stats::{write_stats, RunStats};
+use crate::storage::{open_store, StoreLocation, DEFAULT_STORE_DEMOTE_AFTER};
+use crate::tcp::serve_tcp;
+use crate::trends::{load_runs, trends, write_trends};
+use crate::watch::watch_directory;
+use serde::Serialize;
 use std::collections::HashMap;
-use std::path::Path;
-use std::{env, io};
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, debug_span, info_span, trace};
 
 /// Executes all of the logic for the payment engine. Reads data from a file, maps this data
-/// to client's and their accounts, then prints to std out.
-pub(crate) fn run() -> Result<()> {
-    // read data from a csv
-    let file_path = get_file_path(env::args().collect())?;
-    let client_id_and_account_map: HashMap<u16, Account> = read_transactions_from_csv(&file_path)?;
+/// to client's and their accounts, then prints to std out. Every error that occurs is aggregated
+/// into the returned report.
+pub fn run() -> ExitReport {
+    let mut report = ExitReport::default();
+
+    if let Err(err) = execute(&mut report) {
+        report.fatal = Some(err);
+    }
 
-    // write data to std out
-    write_accounts_to_csv(client_id_and_account_map)?;
+    report
+}
 
-    Ok(())
+/// Runs the subcommand selected by the command line arguments
+fn execute(report: &mut ExitReport) -> EngineResult<()> {
+    let args = CliArgs::parse(env::args_os())?;
+    let _exporter = init_tracing(args.verbosity, args.otlp_endpoint.as_deref())?;
+    let file_path = args.file_path.as_path();
+    let output_path = args.output_path.as_deref();
+    report.strict = args.strict;
+    report.quiet = args.quiet;
+    report.redaction = args.redaction.clone();
+
+    match args.command {
+        Command::Process if args.watch.is_some() => {
+            watch_directory(args.watch.as_deref().unwrap_or(file_path), &args)
+        }
+        Command::Process | Command::Validate | Command::Report => process_file(&args, report),
+        Command::ExportState => export_state(file_path, output_path, args.state_format),
+        Command::ImportState => import_state(file_path, output_path, args.state_format),
+        Command::Profile => profile_file(&args, output_path),
+        Command::Lint => lint_file(&args, output_path),
+        Command::Generate => generate_file(&args),
+        Command::Annotate => annotate(file_path, &args.annotation, args.clock().as_ref()),
+        Command::MigrateState => {
+            migrate_state(file_path, output_path, args.from_version).map(|_| ())
+        }
+        Command::Find => Ok(find_transactions(file_path, output_path, &args.find)?),
+        Command::Trends => {
+            let points = trends(&load_runs(file_path)?, &args.trends);
+            Ok(write_trends(&points, output_path, args.trends.chart)?)
+        }
+        Command::Serve if args.tcp.is_some() => {
+            let accounts = match &args.load_state {
+                Some(state_path) => load_state(state_path)?,
+                None => HashMap::new(),
+            };
+            let journal = Journal::open(file_path)?.with_clock(args.clock());
+            // each range has its own shard, unless the number of shards is given
+            let shard_count = match (&args.shard_ranges, args.shards) {
+                (_, Some(shards)) => shards,
+                (Some(ranges), None) => ranges.ranges(),
+                (None, None) => DEFAULT_SHARD_COUNT,
+            };
+            let mut engine = SharedEngine::new(accounts, shard_count, args.config.clone(), journal);
+            if let Some(ranges) = &args.shard_ranges {
+                engine = engine.with_partitioner(ranges.clone());
+            }
+            if let Some(keys_path) = &args.idempotency_keys {
+                engine = engine.with_idempotency_keys(IdempotencyKeys::open(keys_path)?);
+            }
+            serve_tcp(Arc::new(engine), args.tcp.as_deref().unwrap_or_default())
+        }
+        Command::Serve => {
+            let disputes = dispute_desk(&args)?;
+            let handover = args.handover_socket.as_deref().map(HandoverListener::bind);
+            let addr = args.addr.as_deref().unwrap_or(DEFAULT_ADDR);
+            serve(file_path, addr, Some(disputes), handover.transpose()?)
+        }
+        Command::ServeReadonly => serve_readonly(
+            file_path,
+            args.addr.as_deref().unwrap_or(DEFAULT_READONLY_ADDR),
+        ),
+        Command::EmitEvents => {
+            emit_events_to(file_path, args.sink, output_path, &args.kafka).map(|_| ())
+        }
+        Command::Graph => Ok(export_graph(file_path, output_path, args.graph_format).map(|_| ())?),
+        Command::Run => run_runbook(&Runbook::load(file_path)?, &args, report),
+    }
 }
 
-/// Retrieves the file path from the provided command line arguments
-fn get_file_path(args: Vec<String>) -> ReaderResult<String> {
-    // error when an argument for file path wasn't provided
-    if args.len() < 2 {
-        return Err(ReaderError::MissingArgError);
+/// Reads data from a csv and writes the resulting accounts to std out. Records that can't be
+/// applied are added to the report, any other error ends execution.
+fn process_file(args: &CliArgs, report: &mut ExitReport) -> EngineResult<()> {
+    let inputs = get_inputs(args)?;
+
+    // close onboarding to the clients in the metadata file, when one was provided
+    let mut config = args.config.clone();
+    if let Some(clients_path) = &args.onboarded_clients {
+        config.onboarded_clients = Some(load_client_ids(clients_path)?);
+    }
+
+    // amounts are checked against, and output in, the currency's minor unit
+    if let Some(code) = &args.currency {
+        config.currency = Some(Currency::resolve(code, &args.minor_unit_overrides)?);
+    }
+
+    // operational overrides for individual accounts, when an admin sidecar file was provided
+    if let Some(flags_path) = &args.admin_flags {
+        config.account_flags = load_account_flags(flags_path)?;
+    }
+
+    // transactions for screened clients are held for compliance review, rather than processed
+    if let Some(metadata_path) = &args.client_metadata {
+        config.screening.client_countries = load_client_countries(metadata_path)?;
+    }
+    if let Some(denylist_path) = &args.denylist {
+        config.screening.denylist = load_client_ids(denylist_path)?;
+    }
+
+    // everything that needs the current time asks the same clock, so runs can be reproduced
+    let clock = args.clock();
+    let mut metadata = RunMetadata::start(&config, clock.as_ref());
+    for (file_path, _) in &inputs {
+        metadata.add_input(file_path);
+    }
+    let sidecar_paths = [
+        &args.onboarded_clients,
+        &args.admin_flags,
+        &args.client_metadata,
+        &args.denylist,
+    ];
+    for sidecar_path in sidecar_paths.into_iter().flatten() {
+        metadata.add_input(sidecar_path);
+    }
+
+    // hold the state files for the whole run, so a concurrent run can't lose this run's updates
+    let save_path = args.save_state.as_deref().filter(|_| !args.simulate);
+    let _state_lock = StateLock::acquire(args.load_state.as_deref(), save_path, args.lock_mode)?;
+
+    // start from the state saved by a previous run, if there is one
+    let loaded_account_map = match &args.load_state {
+        Some(state_path) => {
+            metadata.add_input(state_path);
+            load_state(state_path)?
+        }
+        None => HashMap::new(),
+    };
+
+    // outputs and reports only contain pseudonyms, when anonymizing them
+    let anonymizer = match &args.anonymize {
+        Some(key_path) if args.anonymize_notes => Some(Anonymizer::load(key_path)?.with_notes()),
+        Some(key_path) => Some(Anonymizer::load(key_path)?),
+        None => None,
+    };
+
+    // when simulating against loaded state, remember the balances so only the changes are output.
+    // the alert rules compare against them too.
+    let show_changes = args.simulate && args.load_state.is_some();
+    let balances_before =
+        (show_changes || !args.alert_rules.is_empty()).then(|| match &anonymizer {
+            Some(anonymizer) => snapshot_balances(&anonymizer.accounts(&loaded_account_map)),
+            None => snapshot_balances(&loaded_account_map),
+        });
+
+    // journal every transaction that's applied, when a journal file was provided
+    let journal = match (&args.journal, args.journal_batch) {
+        (Some(journal_path), Some(batch)) => Journal::open_batched(journal_path, batch)?,
+        (Some(journal_path), None) => Journal::open(journal_path)?,
+        (None, _) => Journal::default(),
+    }
+    .with_clock(Arc::clone(&clock));
+    let journal_stats = journal.stats();
+    let account_flags = config.account_flags.clone();
+    let currency = config.currency.clone();
+    let mut engine = Engine::new(loaded_account_map, config, journal);
+    // accounts are only written to a persistent store once they're demoted, so demotion is
+    // always enabled for one
+    let demote_after = match &args.store {
+        StoreLocation::Memory => args.demote_after,
+        store => {
+            engine = engine.with_account_store(open_store(store)?);
+            Some(args.demote_after.unwrap_or(DEFAULT_STORE_DEMOTE_AFTER))
+        }
+    };
+    if let Some(demote_after) = demote_after {
+        engine = engine.with_cold_storage(demote_after);
+    }
+    if let Some(max_resident) = args.max_resident_transactions {
+        let store = DiskStore::new(args.spill_dir.as_deref())?;
+        engine = engine.with_transaction_spill(max_resident, Box::new(store));
+    }
+    if args.retry_withdrawals || args.retry_window.is_some() {
+        engine = engine.with_withdrawal_retries(args.retry_window);
+    }
+    if let Some(window) = args.dispute_buffer {
+        engine = engine.with_dispute_buffer(window);
+    }
+    if let Some(days_dir) = &args.daily_cutover {
+        let days = DailyCutover::new(days_dir, args.cutover_hour)
+            .with_currency(currency.clone())
+            .with_anonymizer(anonymizer.clone());
+        engine = engine.with_daily_cutover(days);
+    }
+    if let Some(olap_dir) = &args.olap_export {
+        let olap = OlapExport::new(olap_dir, args.cutover_hour, clock.now_secs())
+            .with_currency(currency.clone())
+            .with_anonymizer(anonymizer.clone());
+        engine = engine.with_olap_export(olap);
+    }
+    if let Some(results_path) = &args.tx_results {
+        engine = engine.with_tx_results(TxResults::create(results_path)?);
+    }
+    if let Some(audit_path) = &args.audit_log {
+        engine = engine.with_audit_log(AuditLog::open(audit_path)?.with_clock(Arc::clone(&clock)));
+    }
+
+    // the sweep runs through the engine, so the funds it moves are audited
+    if args.escheatment.after_days.is_some() {
+        engine = engine.with_escheatment(args.escheatment.clone(), Arc::clone(&clock));
+    }
+
+    // an obviously corrupt or wrongly scoped file halts the run before the saved state is
+    // touched. The accounts are checkpointed beside the saved state when there's no checkpoint.
+    if args.breaker.is_set() {
+        let mut limits = args.breaker.clone();
+        if limits.checkpoint.is_none() {
+            limits.checkpoint = save_path.map(tripped_checkpoint_path);
+        }
+        engine = engine.with_circuit_breaker(CircuitBreaker::new(limits));
+    }
+
+    // a simulation checks the keys that have been applied, without adding to them
+    if let Some(keys_path) = &args.idempotency_keys {
+        metadata.add_input(keys_path);
+        let keys = if args.simulate {
+            IdempotencyKeys::load(keys_path)?
+        } else {
+            IdempotencyKeys::open(keys_path)?
+        };
+        engine = engine.with_idempotency_keys(keys);
+    }
+
+    // operators' changes to accounts are applied before or after the records
+    if let Some(admin_ops_path) = &args.admin_ops {
+        metadata.add_input(admin_ops_path);
+        let operations = load_admin_operations(admin_ops_path)?;
+        engine = engine.with_admin_operations(operations, args.admin_ops_phase);
+    }
+
+    // the money each record moves is posted, so the accounts can be reconciled with it
+    if args.command == Command::Report && args.reconcile {
+        report.postings = Some(Postings::default());
+    }
+
+    // read data from the files, as one stream of records when there's more than one
+    let started = Instant::now();
+    let client_id_and_account_map: HashMap<u16, Account> =
+        read_transactions_from_files(&inputs, &args.csv, engine, report)?;
+    let elapsed = started.elapsed();
+    report.journal = journal_stats.map(|stats| stats.summary());
+
+    // funds that were held for too long were moved to the holding account once the records were
+    // applied. Regulators need the real client ids, so the report is never anonymized.
+    if let Some(report_path) = &args.escheatment.report {
+        write_escheatment_report(report_path, &report.escheatments)?;
+        metadata.add_output(report_path);
+    }
+    if let Some(postings) = report.postings.as_mut() {
+        postings.escheat(&report.escheatments);
+    }
+    let reconciliation = report
+        .postings
+        .as_ref()
+        .map(|postings| Reconciliation::new(postings, &client_id_and_account_map));
+
+    // the saved state always keeps the real client ids
+    let anonymized_account_map = anonymizer
+        .as_ref()
+        .map(|anonymizer| anonymizer.accounts(&client_id_and_account_map));
+    let output_account_map = anonymized_account_map
+        .as_ref()
+        .unwrap_or(&client_id_and_account_map);
+
+    // write the accounts, or the summary of the run, to the output file or std out. Validating
+    // only reports the records that were rejected.
+    if args.command != Command::Validate {
+        let output_path = args.output_path.as_deref();
+        let mut output = DigestWriter::new(open_output(output_path)?);
+        match (args.command, &balances_before) {
+            (Command::Report, _) => match &reconciliation {
+                Some(reconciliation) => write_reconciliation(&mut output, reconciliation)?,
+                None => {
+                    let summary = RunSummary::new(
+                        &client_id_and_account_map,
+                        report.records,
+                        report.rejections.len() as u64,
+                    );
+                    write_summary(&mut output, &summary)?
+                }
+            },
+            (_, Some(before)) if show_changes => {
+                let diffs = diff_accounts(before, output_account_map).into_iter();
+                let (format, fields) = (args.output_format, args.field_formatter());
+                match &currency {
+                    Some(currency) => {
+                        let diffs = diffs.map(|diff| diff.in_currency(currency));
+                        write_rows(&mut output, diffs, format, fields)?
+                    }
+                    None => write_rows(&mut output, diffs, format, fields)?,
+                }
+            }
+            _ => write_accounts(
+                &mut output,
+                output_account_map,
+                currency.as_ref(),
+                args.output_version,
+                args.output_format,
+                args.field_formatter(),
+            )?,
+        }
+        let output_name = output_path.map_or("stdout".into(), |path| path.to_string_lossy());
+        metadata.add_output_digest(&output_name, output.digest());
+    }
+
+    // a simulation should never modify the saved state
+    if let (Some(state_path), false) = (&args.save_state, args.simulate) {
+        save_state(state_path, &client_id_and_account_map)?;
+        metadata.add_output(state_path);
+    }
+
+    if let (Some(index_path), false) = (&args.save_index, args.simulate) {
+        save_index(index_path, &client_id_and_account_map)?;
+        metadata.add_output(index_path);
+    }
+
+    if let Some(before) = &balances_before {
+        report.alerts = args.alert_rules.evaluate(before, output_account_map);
+    }
+
+    if let Some(report_path) = &args.alerts_report {
+        write_alerts_report(report_path, &report.alerts)?;
+        metadata.add_output(report_path);
+    }
+
+    if let Some(log_path) = &args.alert_log {
+        log_alerts(log_path, &report.alerts)?;
+    }
+
+    if let Some(report_path) = &args.loss_report {
+        let ledger_account = args.loss_account.as_deref().unwrap_or(DEFAULT_LOSS_ACCOUNT);
+        match &anonymizer {
+            Some(anonymizer) => {
+                let losses = report
+                    .losses
+                    .map_clients(|client_id| anonymizer.client(client_id));
+                write_loss_report(report_path, &losses, ledger_account)?
+            }
+            None => write_loss_report(report_path, &report.losses, ledger_account)?,
+        }
+        metadata.add_output(report_path);
+    }
+
+    // the compliance team needs the real client ids, so the report is never anonymized
+    if let Some(report_path) = &args.compliance_report {
+        write_compliance_report(report_path, &report.rejections)?;
+        metadata.add_output(report_path);
     }
 
-    let path = Path::new(&args[1]);
+    if let Some(rejections_path) = &args.rejections {
+        report.write_rejections(rejections_path)?;
+        metadata.add_output(rejections_path);
+    }
+    if let Some(results_path) = &args.tx_results {
+        metadata.add_output(results_path);
+    }
+    if let Some(audit_path) = &args.audit_log {
+        metadata.add_output(audit_path);
+    }
+
+    // the figures are reported along with the rejections, or written to their own file
+    if args.stats || args.stats_out.is_some() {
+        let stats = RunStats::new(report, &client_id_and_account_map, elapsed);
+        if let Some(stats_path) = &args.stats_out {
+            write_stats(open_output(Some(stats_path))?, &stats)?;
+            metadata.add_output(stats_path);
+        }
+        if args.stats {
+            report.stats = Some(stats);
+        }
+    }
 
-    // error when the file extension is incorrect
-    match path.extension() {
-        // if a file extension was provided, check that it's valid
-        Some(extension) => {
-            // non csv files are considered invalid
-            if extension != VALID_FILE_EXTENSION {
-                return Err(ReaderError::InvalidExtensionError);
+    if let Some(report_path) = &args.clients_report {
+        match &anonymizer {
+            Some(anonymizer) => {
+                let account_flags = anonymizer.flags(&account_flags);
+                write_clients_report(report_path, output_account_map, &account_flags)?
             }
+            None => write_clients_report(report_path, output_account_map, &account_flags)?,
+        }
+        metadata.add_output(report_path);
+    }
+
+    if let Some(days_dir) = &args.daily_cutover {
+        for summary in &report.days {
+            metadata.add_output(days_dir.join(format!("closing-{}.csv", summary.date)));
+        }
+        metadata.add_output(days_dir.join("days.csv"));
+    }
+    if let Some(olap_dir) = &args.olap_export {
+        for file_name in OLAP_FILES {
+            metadata.add_output(olap_dir.join(file_name));
+        }
+    }
+
+    if let Some(journal_path) = &args.journal {
+        metadata.add_output(journal_path);
+    }
+
+    if let (Some(keys_path), false) = (&args.idempotency_keys, args.simulate) {
+        metadata.add_output(keys_path);
+    }
+
+    if let Some(metadata_path) = &args.run_metadata {
+        metadata.set_summary(RunSummary::new(
+            &client_id_and_account_map,
+            report.records,
+            report.rejections.len() as u64,
+        ));
+        metadata.write(metadata_path, clock.as_ref())?;
+    }
+
+    // the outputs are still written when the run doesn't reconcile, so it can be investigated
+    match reconciliation.filter(|reconciliation| !reconciliation.is_balanced()) {
+        Some(reconciliation) => {
+            Err(SourceError::Unreconciled(reconciliation.discrepancies.len()).into())
         }
-        None => return Err(ReaderError::InvalidExtensionError),
+        None => Ok(()),
+    }
+}
+
+/// Where the accounts are checkpointed when the circuit breaker trips, e.g. state.bin.tripped
+fn tripped_checkpoint_path(state_path: &Path) -> PathBuf {
+    let mut checkpoint_path = OsString::from(state_path.as_os_str());
+    checkpoint_path.push(".tripped");
+
+    PathBuf::from(checkpoint_path)
+}
+
+/// Applies the transactions and disputes submitted to the server to the state taken over from a
+/// running server, or the loaded state, or to new accounts when there isn't any, journaling them
+/// to the journal the server reads from
+fn dispute_desk(args: &CliArgs) -> EngineResult<DisputeDesk> {
+    let accounts = match (&args.take_over, &args.load_state) {
+        (Some(socket_path), _) => take_over(socket_path, &args.file_path, &args.config)?,
+        (None, Some(state_path)) => load_state(state_path)?,
+        (None, None) => HashMap::new(),
     };
+    let clock = args.clock();
+    let journal = Journal::open(&args.file_path)?.with_clock(Arc::clone(&clock));
+    let mut engine = Engine::new(accounts, args.config.clone(), journal);
+    if let Some(keys_path) = &args.idempotency_keys {
+        engine = engine.with_idempotency_keys(IdempotencyKeys::open(keys_path)?);
+    }
+
+    let mut disputes = DisputeDesk::new(engine, &args.file_path, clock);
+    if let Some(save_path) = &args.save_state {
+        disputes = disputes.with_save_state(save_path);
+    }
+
+    Ok(disputes)
+}
+
+/// Generates a file of transactions at the file path
+fn generate_file(args: &CliArgs) -> EngineResult<()> {
+    let file = File::create(&args.file_path).map_err(|err| SourceError::Io(err.to_string()))?;
+    generate(&args.generator, BufWriter::new(file))?;
+
+    Ok(())
+}
+
+/// Profiles the data quality of a csv, writing the report to the output file or std out
+fn profile_file(args: &CliArgs, output_path: Option<&Path>) -> EngineResult<()> {
+    let file_path = get_file_path(args)?;
+    write_profile(&profile_csv(&file_path)?, output_path)?;
+
+    Ok(())
+}
+
+/// Checks a csv for problems, writing them to the output file or std out. The run fails when
+/// any are found, so bad files can be rejected before they're processed.
+fn lint_file(args: &CliArgs, output_path: Option<&Path>) -> EngineResult<()> {
+    let file_path = get_file_path(args)?;
+    let lint = lint_csv(&file_path)?;
+    write_lint(&lint, output_path)?;
+
+    if !lint.is_clean() {
+        return Err(SourceError::Lint(lint.issues.len()).into());
+    }
+
+    Ok(())
+}
+
+/// Retrieves the file path from the provided command line arguments, once the file has been
+/// confirmed to contain data in a readable format
+fn get_file_path(args: &CliArgs) -> CliResult<PathBuf> {
+    get_input(args).map(|(path, _)| path)
+}
+
+/// Retrieves the file path from the provided command line arguments along with the format of the
+/// data in it, once the file has been confirmed to be readable
+fn get_input(args: &CliArgs) -> CliResult<(PathBuf, InputFormat)> {
+    check_input(args, &args.file_path)
+}
+
+/// Retrieves every file to read from the command line arguments along with the format of each,
+/// see get_input. Glob patterns are expanded into the files they match.
+fn get_inputs(args: &CliArgs) -> CliResult<Vec<(PathBuf, InputFormat)>> {
+    let mut inputs = vec![];
+    for pattern in iter::once(&args.file_path).chain(&args.more_file_paths) {
+        for path in expand_pattern(pattern)? {
+            inputs.push(check_input(args, &path)?);
+        }
+    }
+
+    Ok(inputs)
+}
+
+/// Detects the format of the data in a file, once the file has been confirmed to exist
+fn check_input(args: &CliArgs, path: &Path) -> CliResult<(PathBuf, InputFormat)> {
+    // error when the file isn't in a format we can read
+    let format = args.format_detector().detect(path)?;
 
     // error when the file doesn't exist
     if !path.exists() {
-        return Err(ReaderError::NonExistentFileError(args[1].to_string()));
+        return Err(CliError::NonExistentFile(path.display().to_string()));
     }
 
-    Ok(args[1].to_string())
+    Ok((path.to_path_buf(), format))
 }
 
-/// Reads transaction data from a csv and returns a HashMap of client_id -> Account
-fn read_transactions_from_csv(file_path: &String) -> Result<HashMap<u16, Account>> {
-    // build a CSV reader that accounts for whitespace, and missing values
-    let mut reader = ReaderBuilder::new()
-        .trim(Trim::Fields)
-        .flexible(true)
-        .from_path(file_path)?;
-
-    // Iterate through the records. For each record, add an entry (Account) in the HashMap. If the entry
-    // already exists, update its values using the record data
-    let transactions_map = reader.deserialize().fold(
-        HashMap::new(),
-        |mut id_to_account_map_accum: HashMap<u16, Account>, result| {
-            let record: Record = result
-                .expect("Record should be structured like this: deposit,33,52,5492.9228 or this: resolve,21,2,");
-
-            // if the Account isn't already in our HashMap, add it using Account::default()
-            let entry = id_to_account_map_accum
-                .entry(record.client_id)
-                .or_insert_with(|| Account::default());
-
-            process_transaction_record(&record, entry)
-                .expect("failed to process transaction");
-
-            id_to_account_map_accum
-        },
-    );
-
-    Ok(transactions_map)
+/// Applies the transactions in a csv with the default config, returning the resulting accounts
+/// ordered by client id. Records that can't be applied to their account are skipped, an `Engine`
+/// can be used directly when they matter.
+///
+/// ```
+/// let accounts = plutus_engine::process_csv_str("type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+/// assert_eq!(accounts[0].total, 10.0);
+/// ```
+pub fn process_csv_str(csv: &str) -> EngineResult<Vec<AccountRecord>> {
+    process_reader(csv.as_bytes())
+}
+
+/// Applies the transactions read from a csv source with the default config, returning the
+/// resulting accounts ordered by client id. Records that can't be applied are skipped, as with
+/// `process_csv_str`.
+pub fn process_reader<R: Read>(reader: R) -> EngineResult<Vec<AccountRecord>> {
+    let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+    let mut report = ExitReport::default();
+    let records = CsvParser.records(Box::new(reader), b',', &CsvOptions::default())?;
+    let account_map = apply_records(records, engine, &mut report)?;
+
+    let mut records: Vec<AccountRecord> = account_map
+        .iter()
+        .map(|(client_id, account)| AccountRecord::new(*client_id, account))
+        .collect();
+    records.sort_by_key(|record| record.client);
+
+    Ok(records)
+}
+
+/// Reads transaction data from a file in the given format, decompressing it when it's gzip
+/// compressed, applies it using the engine and returns a HashMap of client_id -> Account. Csv and
+/// tsv are parsed as the options describe. Records that can't be applied to their account are
+/// added to the report.
+pub(crate) fn read_transactions_from_file(
+    file_path: &Path,
+    format: InputFormat,
+    csv: &CsvOptions,
+    engine: Engine,
+    report: &mut ExitReport,
+) -> EngineResult<HashMap<u16, Account>> {
+    let _file = info_span!("file", path = %file_path.display(), ?format).entered();
+    let records = open_source(open_input(file_path)?, format, csv)?;
+    report.read_from(file_path);
+
+    apply_records(records, engine, report)
+}
+
+/// Applies the transactions in several files as one stream of records, merged by their
+/// timestamps, see merge::MergedSource. Rejections are reported against the file, and the line
+/// within it, that the record was read from.
+pub(crate) fn read_transactions_from_files(
+    inputs: &[(PathBuf, InputFormat)],
+    csv: &CsvOptions,
+    engine: Engine,
+    report: &mut ExitReport,
+) -> EngineResult<HashMap<u16, Account>> {
+    let _files = info_span!("files", count = inputs.len()).entered();
+    let mut sources = vec![];
+    for (file_path, format) in inputs {
+        let _file = info_span!("file", path = %file_path.display(), ?format).entered();
+        sources.push(open_source(open_input(file_path)?, *format, csv)?);
+        debug!("opened");
+    }
+    let records = MergedSource::new(sources);
+    let file_names = inputs
+        .iter()
+        .map(|(file_path, _)| file_path.display().to_string());
+    report.read_from_all(file_names.collect(), records.cursor());
+
+    apply_records(records, engine, report)
 }
 
-/// Triggers the relevant logic for updating a client's account, using a record (Record)
-fn process_transaction_record(record: &Record, account: &mut Account) -> Result<(), anyhow::Error> {
-    match record.transaction_type {
-        TransactionType::Deposit => {
-            // the amount field is optional, only process it when it's been defined
-            if let Some(amount) = record.amount {
-                account.deposit(amount, record.transaction_id)
+/// Applies each record using the engine, see read_transactions_from_file
+pub(crate) fn apply_records(
+    records: impl TransactionSource,
+    mut engine: Engine,
+    report: &mut ExitReport,
+) -> EngineResult<HashMap<u16, Account>> {
+    // records that share a timestamp are batched up when they might need to be reordered
+    let reorder = engine.config().ordering_policy == OrderingPolicy::ResolvesFirst;
+    let mut batch: Vec<(u64, Record)> = vec![];
+
+    if let Some(postings) = report.postings.as_mut() {
+        *postings = Postings::open(&engine.accounts()?);
+    }
+
+    // admin operations are applied in their own phase, either side of the records
+    apply_admin_operations(&mut engine, AdminPhase::Before, report)?;
+
+    // Iterate through the records, applying each one to its client's account
+    let skip_malformed = engine.config().skip_malformed;
+    for result in records {
+        report.records += 1;
+        let (line, record) = match result {
+            Ok(read) => read,
+            Err(SourceError::Parse { line, message }) if skip_malformed => {
+                debug!(line, %message, "skipped malformed row");
+                let err = SourceError::Parse { line, message }.into();
+                engine.write_result(line, None, Err(&err))?;
+                report.reject(line, err);
+                continue;
             }
-        }
-        TransactionType::Withdrawal => {
-            // the amount field is optional, only process it when it's been defined
-            if let Some(amount) = record.amount {
-                account.withdraw(amount, record.transaction_id)?;
+            Err(err) => {
+                // a row that can't be parsed is reported against the file it's in
+                if let SourceError::Parse { line, .. } = &err {
+                    report.fail_at(*line);
+                }
+                return Err(err.into());
             }
+        };
+
+        if !reorder {
+            apply_record(&mut engine, line, &record, report)?;
+            continue;
+        }
+
+        let same_batch = batch.last().is_some_and(|(_, last)| {
+            last.timestamp.is_some() && last.timestamp == record.timestamp
+        });
+        if !same_batch {
+            apply_batch(&mut engine, &mut batch, report)?;
+        }
+        batch.push((line, record));
+    }
+    apply_batch(&mut engine, &mut batch, report)?;
+    apply_admin_operations(&mut engine, AdminPhase::After, report)?;
+    engine.check_limits()?;
+
+    report.retries = engine.take_retry_outcomes();
+    report.buffered = engine.take_buffered_disputes();
+    report.losses = engine.take_losses();
+    report.quarantine = engine.take_quarantine_events();
+    report.days = engine.finish_days()?;
+    report.escheatments = engine.escheat()?;
+    report.audit_head = engine.audit_head();
+    engine.into_accounts()
+}
+
+/// Applies a record using the engine. A record that can't be applied shouldn't stop the remaining
+/// records from being processed, so it's added to the report, along with any warnings about it.
+fn apply_record(
+    engine: &mut Engine,
+    line: u64,
+    record: &Record,
+    report: &mut ExitReport,
+) -> EngineResult<()> {
+    let _record = debug_span!(
+        "record",
+        line,
+        r#type = record.transaction_type.name(),
+        client = record.client_id,
+        tx = record.transaction_id,
+    )
+    .entered();
+
+    // a transfer changes its destination's balances too
+    let destination = record
+        .destination
+        .filter(|_| record.transaction_type == TransactionType::Transfer);
+    let before = snapshot_accounts(engine, report, [Some(record.client_id), destination])?;
+
+    let result = engine.process(record);
+    post_accounts(engine, report, before)?;
+    report.tally.add(record, result.is_ok());
+    for warning in engine.take_warnings() {
+        debug!(code = warning.code(), "warning: {}", warning);
+        report.warn(line, warning);
+    }
+
+    let outcome = match result {
+        Ok(()) => {
+            trace!("applied");
+            engine
+                .check_invariants(line, record)
+                .and_then(|_| engine.write_result(line, Some(record), Ok(())))
+        }
+        Err(EngineError::Ledger(err)) => {
+            debug!(code = err.code(), "rejected: {}", err);
+            let err = err.into();
+            let written = engine.write_result(line, Some(record), Err(&err));
+            report.reject(line, err);
+            written
+        }
+        Err(err) => Err(err),
+    };
+
+    // an error that ends the run is reported against the record that raised it
+    if outcome.is_err() {
+        report.fail_at(line);
+    }
+    outcome
+}
+
+/// Applies the admin operations for the phase. Like records, an operation that can't be applied is
+/// added to the report with its line in the admin operations file.
+fn apply_admin_operations(
+    engine: &mut Engine,
+    phase: AdminPhase,
+    report: &mut ExitReport,
+) -> EngineResult<()> {
+    for (line, operation) in engine.take_admin_operations(phase) {
+        let before = snapshot_accounts(engine, report, [Some(operation.client)])?;
+        let result = engine.apply_admin(&operation);
+        post_accounts(engine, report, before)?;
+
+        match result {
+            Ok(()) => {}
+            Err(EngineError::Ledger(err)) => report.reject_in(None, line, err),
+            Err(err) => return Err(err),
         }
-        TransactionType::Dispute => account.dispute(record.transaction_id),
-        TransactionType::Resolve => account.resolve(record.transaction_id),
-        TransactionType::Chargeback => account.chargeback(record.transaction_id),
     }
 
     Ok(())
 }
 
-/// Writes client account data to a csv
-fn write_accounts_to_csv(account_map: HashMap<u16, Account>) -> Result<()> {
-    let mut writer = csv::Writer::from_writer(io::stdout());
+/// The balances of the clients' accounts before a record or admin operation is applied to them,
+/// none when the run isn't reconciled
+fn snapshot_accounts(
+    engine: &Engine,
+    report: &ExitReport,
+    clients: impl IntoIterator<Item = Option<u16>>,
+) -> EngineResult<Vec<(u16, Option<AccountRecord>)>> {
+    if report.postings.is_none() {
+        return Ok(vec![]);
+    }
+
+    let clients = clients.into_iter().flatten();
+    clients
+        .map(|client| Ok((client, engine.account(client)?)))
+        .collect()
+}
+
+/// Posts the movement of the balances of the accounts that were snapshot before a record or admin
+/// operation was applied to them
+fn post_accounts(
+    engine: &Engine,
+    report: &mut ExitReport,
+    before: Vec<(u16, Option<AccountRecord>)>,
+) -> EngineResult<()> {
+    let Some(postings) = report.postings.as_mut() else {
+        return Ok(());
+    };
 
-    for (client_id, account) in account_map {
-        // serialize AccountRecord as CSV record
-        writer.serialize(AccountRecord {
-            client: client_id,
-            available: account.available_funds,
-            held: account.held_funds,
-            total: account.total_funds,
-            locked: account.is_locked,
-        })?;
+    for (client, before) in before {
+        if let Some(after) = engine.account(client)? {
+            postings.post(before.as_ref(), &after);
+        }
     }
 
-    writer.flush()?;
+    Ok(())
+}
+
+/// Reorders a batch of records that share a timestamp so resolves come first, then applies them,
+/// leaving the batch empty
+fn apply_batch(
+    engine: &mut Engine,
+    batch: &mut Vec<(u64, Record)>,
+    report: &mut ExitReport,
+) -> EngineResult<()> {
+    order_resolves_first(batch);
+    for (line, record) in batch.drain(..) {
+        apply_record(engine, line, &record, report)?;
+    }
 
     Ok(())
 }
 
+/// Opens the file the run's output is written to, or std out when there isn't one
+fn open_output(output_path: Option<&Path>) -> SourceResult<Box<dyn Write>> {
+    match output_path {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|err| SourceError::Io(format!("{}: {}", path.display(), err)))?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+/// Writes client account data in the output format, with its amounts and booleans rendered by
+/// the field formatter when there is one
+pub(crate) fn write_accounts(
+    output: impl Write,
+    account_map: &HashMap<u16, Account>,
+    currency: Option<&Currency>,
+    version: OutputVersion,
+    format: OutputFormat,
+    fields: Option<&dyn FieldFormatter>,
+) -> EngineResult<()> {
+    if version == OutputVersion::V2 {
+        let records = account_map
+            .iter()
+            .map(|(client_id, account)| AccountRecordV2::new(*client_id, account));
+
+        return match currency {
+            Some(currency) => {
+                let records = records.map(|record| record.in_currency(currency));
+                write_rows(output, records, format, fields)
+            }
+            None => write_rows(output, records, format, fields),
+        };
+    }
+
+    write_accounts_to(
+        output_sink(output, format, fields).as_mut(),
+        account_map,
+        currency,
+    )
+}
+
+/// Serializes each of the rows in the output format and writes them to the output, through the
+/// format's sink. Balances are rounded to the same four decimal places whichever format they're
+/// written in, before they're rendered by the field formatter.
+fn write_rows<T: Serialize>(
+    output: impl Write,
+    rows: impl IntoIterator<Item = T>,
+    format: OutputFormat,
+    fields: Option<&dyn FieldFormatter>,
+) -> EngineResult<()> {
+    let mut sink = output_sink(output, format, fields);
+    for row in rows {
+        sink.write_account(&row)?;
+    }
+
+    sink.finish()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::mapper::{Account, ReaderError, Transaction, TransactionType};
-    use crate::reader::{get_file_path, process_transaction_record, read_transactions_from_csv};
+    use crate::admin::{AdminAction, AdminOperation, AdminPhase};
+    use crate::audit::{hash_line, AuditLog};
+    use crate::breaker::{BreakerLimits, CircuitBreaker};
+    use crate::cli::CliArgs;
+    use crate::clients::AccountFlags;
+    use crate::clock::FixedClock;
+    use crate::config::{
+        DisputeAmountPolicy, EngineConfig, EnginePolicy, FrozenAccountPolicy, InvariantChecks,
+        OrderingPolicy, PolicyAction, WithdrawalPolicy,
+    };
+    use crate::currency::Currency;
+    use crate::cutover::{DailyCutover, DaySummary};
+    use crate::engine::{order_resolves_first, process_transaction_record, Engine};
+    use crate::error::{
+        CliError, EngineError, EngineResult, ExitReport, LedgerError, Rejection, SourceError,
+    };
+    use crate::escheat::EscheatmentSettings;
+    use crate::format::InputFormat;
+    use crate::generator::{generate, GeneratorConfig};
+    use crate::journal::{parse_entry, Journal, JournalEntry};
+    use crate::losses::ClientLoss;
+    use crate::mapper::{
+        Account, LockState, OutputFormat, OutputVersion, Record, Transaction, TransactionType,
+    };
+    use crate::olap::{OlapExport, OLAP_FILES};
+    use crate::parser::{CsvBackend, CsvOptions};
+    use crate::quarantine::QuarantineRules;
+    use crate::reader::{
+        get_file_path, get_inputs, process_csv_str, process_reader, read_transactions_from_file,
+        read_transactions_from_files, write_accounts,
+    };
+    use crate::reconcile::{Postings, Reconciliation};
+    use crate::reorder::{BufferOutcome, BufferWindow};
+    use crate::results::TxResults;
+    use crate::retry::RetryOutcome;
+    use crate::screening::{HoldReason, Screening};
+    use crate::spill::DiskStore;
+    use crate::state::load_state;
+    use crate::stats::RunStats;
     use crate::test_helpers::*;
     use approx::assert_relative_eq;
-    use std::io::Error;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use futures_core::Stream;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::fs::{self, File};
+    use std::io::{Error, Write};
+    use std::path::{Path, PathBuf};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Reads a csv of transactions, as process_file does for a file detected as csv
+    fn read_transactions_from_csv(
+        file_path: impl AsRef<Path>,
+        engine: Engine,
+        report: &mut ExitReport,
+    ) -> EngineResult<HashMap<u16, Account>> {
+        let csv = CsvOptions::default();
+        read_transactions_from_file(file_path.as_ref(), InputFormat::Csv, &csv, engine, report)
+    }
 
     // Tests that available_funds, total_funds and successful_transactions are increased as expected
     #[test]
@@ -141,6 +1001,10 @@ mod tests {
         let expected_transaction = Transaction {
             amount,
             current_state: TransactionType::Deposit,
+            disputed_amount: None,
+            held_since: None,
+            transaction_type: TransactionType::Deposit,
+            timestamp: None,
         };
 
         let mut account = Account::default();
@@ -164,12 +1028,14 @@ mod tests {
         let withdrawal_amount = 800.3196;
         let available_amount = 800.3195;
 
-        let mut account = Account::default();
-        account.available_funds = available_amount;
+        let mut account = Account {
+            available_funds: available_amount,
+            ..Default::default()
+        };
 
         let result = account.withdraw(800.3196, 0).unwrap_err();
         let expected_reader_error =
-            ReaderError::InsufficientFundsError(withdrawal_amount, available_amount);
+            LedgerError::InsufficientFunds(withdrawal_amount, available_amount);
 
         assert_eq!(result, expected_reader_error);
         assert_eq!(account.available_funds, available_amount);
@@ -188,11 +1054,17 @@ mod tests {
         let expected_transaction = Transaction {
             amount: decrease_amount,
             current_state: TransactionType::Withdrawal,
+            disputed_amount: None,
+            held_since: None,
+            transaction_type: TransactionType::Withdrawal,
+            timestamp: None,
         };
 
-        let mut account = Account::default();
-        account.available_funds = available_amount;
-        account.total_funds = total_funds_amount;
+        let mut account = Account {
+            available_funds: available_amount,
+            total_funds: total_funds_amount,
+            ..Default::default()
+        };
 
         account
             .withdraw(decrease_amount, transaction_id)
@@ -219,14 +1091,20 @@ mod tests {
         let held_funds = 74.25;
         let transaction_id = 5;
 
-        let mut account = Account::default();
-        account.available_funds = available_funds;
-        account.held_funds = held_funds;
+        let mut account = Account {
+            available_funds,
+            held_funds,
+            ..Default::default()
+        };
         account.successful_transactions.insert(
             transaction_id,
             Transaction {
                 amount: 150.0,
                 current_state: TransactionType::Dispute,
+                disputed_amount: None,
+                held_since: None,
+                transaction_type: TransactionType::Deposit,
+                timestamp: None,
             },
         );
 
@@ -243,6 +1121,34 @@ mod tests {
         )
     }
 
+    // Tests that a transaction can go through another dispute once its last one was resolved,
+    // while a dispute of a transaction that's still being disputed changes nothing
+    #[test]
+    fn test_dispute_cycles() {
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+
+        for _ in 0..2 {
+            account.dispute(1);
+            account.dispute(1);
+            assert_relative_eq!(account.available_funds, 0.0);
+            assert_relative_eq!(account.held_funds, 100.0);
+
+            account.resolve(1).expect("ok");
+            assert_account(&account, 100.0, 100.0, true);
+            assert_relative_eq!(account.held_funds, 0.0);
+        }
+
+        account.dispute(1);
+        account.chargeback(1).expect("ok");
+        account.dispute(1);
+        assert_account(&account, 0.0, 0.0, true);
+        assert_eq!(
+            account.successful_transactions[&1].current_state,
+            TransactionType::Chargeback
+        );
+    }
+
     // Tests that available_funds and held_funds are updated correctly, when a transaction is disputed
     #[test]
     fn test_valid_dispute() {
@@ -273,7 +1179,7 @@ mod tests {
         let mut account = Account::default();
         account.deposit(deposit_amount, transaction_id);
 
-        account.resolve(transaction_id);
+        account.resolve(transaction_id).expect("ok");
 
         assert_dispute_or_resolve(
             &account,
@@ -295,7 +1201,7 @@ mod tests {
         account.deposit(deposit_amount, transaction_id);
         account.dispute(transaction_id);
 
-        account.resolve(transaction_id);
+        account.resolve(transaction_id).expect("ok");
 
         assert_dispute_or_resolve(
             &account,
@@ -306,9 +1212,42 @@ mod tests {
         )
     }
 
+    // Tests that resolves and chargebacks which would leave negative held funds are rejected,
+    // leaving the account unchanged, and that float error alone doesn't reject them
+    #[test]
+    fn test_held_funds_underflow() {
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+        account.dispute(1);
+        // e.g. state that was edited by hand
+        account.held_funds = 40.0;
+        let before = account.clone();
+
+        assert_eq!(
+            account.resolve(1),
+            Err(LedgerError::HeldFundsUnderflow(1, 100.0, 40.0))
+        );
+        assert_eq!(
+            account.chargeback(1),
+            Err(LedgerError::HeldFundsUnderflow(1, 100.0, 40.0))
+        );
+        assert_eq!(account, before);
+
+        let mut account = Account::default();
+        for (transaction_id, amount) in [(2, 0.1), (3, 0.2), (4, 0.7)] {
+            account.deposit(amount, transaction_id);
+            account.dispute(transaction_id);
+        }
+        account.resolve(3).expect("ok");
+        account.chargeback(4).expect("ok");
+        account.resolve(2).expect("ok");
+        assert_relative_eq!(account.held_funds, 0.0);
+    }
+
     // Tests that an account is unchanged when a chargeback is attempted for a transaction that is
     // not currently being disputed
     #[test]
+    #[allow(clippy::excessive_precision, clippy::inconsistent_digit_grouping)]
     fn test_chargeback_non_disputed_transaction() {
         let initial_amount = 1_000.94565;
         let increase_amount = 100.28313;
@@ -320,14 +1259,14 @@ mod tests {
         account.deposit(initial_amount, 0);
         account.deposit(increase_amount, transaction_id);
 
-        account.chargeback(transaction_id);
+        account.chargeback(transaction_id).expect("ok");
 
         assert_relative_eq!(account.available_funds, expected_amount);
         assert_chargeback(
             &account,
             0.0,
             expected_amount,
-            !account.is_locked,
+            !account.lock_state.is_locked(),
             transaction_id,
             TransactionType::Deposit,
         );
@@ -345,13 +1284,13 @@ mod tests {
         account.deposit(increase_amount, transaction_id);
         account.dispute(transaction_id);
 
-        account.chargeback(transaction_id);
+        account.chargeback(transaction_id).expect("ok");
 
         assert_chargeback(
             &account,
             0.0,
             initial_amount,
-            account.is_locked,
+            account.lock_state.is_locked(),
             transaction_id,
             TransactionType::Chargeback,
         );
@@ -363,8 +1302,8 @@ mod tests {
         let env_args = vec![vec![], vec!["".to_string()]];
 
         for args in env_args.into_iter() {
-            let result = get_file_path(args).unwrap_err();
-            let expected_reader_error = ReaderError::MissingArgError;
+            let result = CliArgs::parse(args).unwrap_err();
+            let expected_reader_error = CliError::MissingArg;
 
             assert_eq!(result, expected_reader_error);
         }
@@ -373,10 +1312,10 @@ mod tests {
     // Tests that the expected error is returned when the file path leads to a non csv file
     #[test]
     fn test_get_file_path_invalid_extension() {
-        let args = vec!["".to_string(), "someFile.txt".to_string()];
-        let result = get_file_path(args).unwrap_err();
+        let args = CliArgs::parse(vec!["".to_string(), "someFile.txt".to_string()]).unwrap();
+        let result = get_file_path(&args).unwrap_err();
 
-        let expected_reader_error = ReaderError::InvalidExtensionError;
+        let expected_reader_error = CliError::InvalidExtension;
 
         assert_eq!(result, expected_reader_error);
     }
@@ -385,11 +1324,10 @@ mod tests {
     #[test]
     fn test_get_file_path_non_existent_file() {
         let non_existent_file = "nonExistentFile.csv";
-        let args = vec!["".to_string(), non_existent_file.to_string()];
-        let result = get_file_path(args).unwrap_err();
+        let args = CliArgs::parse(vec!["".to_string(), non_existent_file.to_string()]).unwrap();
+        let result = get_file_path(&args).unwrap_err();
 
-        let expected_reader_error =
-            ReaderError::NonExistentFileError(non_existent_file.to_string());
+        let expected_reader_error = CliError::NonExistentFile(non_existent_file.to_string());
 
         assert_eq!(result, expected_reader_error);
     }
@@ -401,8 +1339,8 @@ mod tests {
         let file_name = "mock-transactions.csv";
         let (file_path_str, dir, file) = create_temp_file(file_name)?;
 
-        let args = vec!["".to_string(), file_path_str];
-        let result = get_file_path(args).unwrap();
+        let args = CliArgs::parse(vec!["".to_string(), file_path_str]).unwrap();
+        let result = get_file_path(&args).unwrap();
 
         // we expect the result to end with the file name
         assert!(result.ends_with(file_name));
@@ -413,6 +1351,35 @@ mod tests {
         Ok(())
     }
 
+    // Tests that get_file_path accepts a file without a csv extension, when the format is forced
+    #[test]
+    fn test_get_file_path_forced_format() -> Result<(), Error> {
+        let (file_path_str, dir, file) = create_temp_file("mock-transactions.txt")?;
+
+        let strict_args = CliArgs::parse(vec!["".to_string(), file_path_str.clone()]).unwrap();
+        assert_eq!(
+            get_file_path(&strict_args).unwrap_err(),
+            CliError::InvalidExtension
+        );
+
+        let forced_args = CliArgs::parse(vec![
+            "".to_string(),
+            "--force-format".to_string(),
+            "csv".to_string(),
+            file_path_str.clone(),
+        ])
+        .unwrap();
+        assert_eq!(
+            get_file_path(&forced_args).unwrap(),
+            PathBuf::from(file_path_str)
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
     // Tests that account data is correctly being read in from a file, for two different client accounts
     #[test]
     fn test_read_valid_transactions_from_csv_for_clients() -> Result<(), Error> {
@@ -470,14 +1437,19 @@ mod tests {
             [76.984, 21.56, 79.23, 31.84, 47.81, 8.0],
         ];
 
-        let client_account_map = read_transactions_from_csv(&file_path_str).unwrap();
+        let client_account_map = read_transactions_from_csv(
+            &file_path_str,
+            Engine::default(),
+            &mut ExitReport::default(),
+        )
+        .unwrap();
 
         for (index, expected_client_id) in expected_client_ids.iter().enumerate() {
             let account = client_account_map.get(expected_client_id).unwrap();
             let expected_funds = expected_account_funds[index];
 
             assert_account(
-                &account,
+                account,
                 expected_funds,
                 expected_funds,
                 !account.successful_transactions.is_empty(),
@@ -494,6 +1466,10 @@ mod tests {
                 let expected_account_transaction = Transaction {
                     amount: transaction_amount,
                     current_state: transaction_type,
+                    disputed_amount: None,
+                    held_since: None,
+                    transaction_type,
+                    timestamp: None,
                 };
 
                 assert_eq!(*account_transaction, expected_account_transaction);
@@ -515,11 +1491,15 @@ mod tests {
         let expected_transaction = Transaction {
             amount,
             current_state: TransactionType::Deposit,
+            disputed_amount: None,
+            held_since: None,
+            transaction_type: TransactionType::Deposit,
+            timestamp: None,
         };
 
         let mut account = Account::default();
 
-        process_transaction_record(&record, &mut account).expect("ok");
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
 
         assert_account(
             &account,
@@ -539,7 +1519,7 @@ mod tests {
         let record = dummy_record(TransactionType::Deposit, None);
         let mut account = Account::default();
 
-        process_transaction_record(&record, &mut account).expect("ok");
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
 
         assert_account(
             &account,
@@ -560,12 +1540,16 @@ mod tests {
         let expected_transaction = Transaction {
             amount,
             current_state: TransactionType::Withdrawal,
+            disputed_amount: None,
+            held_since: None,
+            transaction_type: TransactionType::Withdrawal,
+            timestamp: None,
         };
 
         let mut account = Account::default();
         account.deposit(initial_balance, 1);
 
-        process_transaction_record(&record, &mut account).expect("ok");
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
 
         assert_account(
             &account,
@@ -585,7 +1569,7 @@ mod tests {
         let record = dummy_record(TransactionType::Withdrawal, None);
         let mut account = Account::default();
 
-        process_transaction_record(&record, &mut account).expect("ok");
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
 
         assert_account(
             &account,
@@ -604,12 +1588,16 @@ mod tests {
         let expected_transaction = Transaction {
             amount: initial_balance,
             current_state: TransactionType::Dispute,
+            disputed_amount: None,
+            held_since: None,
+            transaction_type: TransactionType::Deposit,
+            timestamp: None,
         };
 
         let mut account = Account::default();
         account.deposit(initial_balance, 0);
 
-        process_transaction_record(&record, &mut account).expect("ok");
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
 
         assert_account(
             &account,
@@ -633,13 +1621,17 @@ mod tests {
         let expected_transaction = Transaction {
             amount: initial_balance,
             current_state: TransactionType::Resolve,
+            disputed_amount: None,
+            held_since: None,
+            transaction_type: TransactionType::Deposit,
+            timestamp: None,
         };
 
         let mut account = Account::default();
         account.deposit(initial_balance, 0);
         account.dispute(0);
 
-        process_transaction_record(&record, &mut account).expect("ok");
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
 
         assert_account(
             &account,
@@ -663,13 +1655,17 @@ mod tests {
         let expected_transaction = Transaction {
             amount: initial_balance,
             current_state: TransactionType::Chargeback,
+            disputed_amount: None,
+            held_since: None,
+            transaction_type: TransactionType::Deposit,
+            timestamp: None,
         };
 
         let mut account = Account::default();
         account.deposit(initial_balance, 0);
         account.dispute(0);
 
-        process_transaction_record(&record, &mut account).expect("ok");
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
 
         assert_account(
             &account,
@@ -679,10 +1675,2210 @@ mod tests {
         );
 
         assert_eq!(account.held_funds, 0.0);
-        assert!(account.is_locked);
+        assert!(account.lock_state.is_locked());
         assert_eq!(
             account.successful_transactions.get(&0),
             Some(&expected_transaction)
         );
     }
-}
\ No newline at end of file
+
+    // Tests that disputing a withdrawal holds its funds on top of the available funds, that a
+    // resolve lets the withdrawal stand and that a chargeback credits the funds back
+    #[test]
+    fn test_process_withdrawal_disputes() {
+        let config = EngineConfig::default();
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+        account.withdraw(40.0, 2).unwrap();
+
+        let dispute = Record::dispute(0, 2);
+        process_transaction_record(&dispute, &mut account, &config).expect("ok");
+        assert_relative_eq!(account.available_funds, 60.0);
+        assert_relative_eq!(account.held_funds, 40.0);
+        assert_relative_eq!(account.total_funds, 100.0);
+
+        process_transaction_record(&Record::resolve(0, 2), &mut account, &config).expect("ok");
+        assert_account(&account, 60.0, 60.0, true);
+
+        process_transaction_record(&dispute, &mut account, &config).expect("ok");
+        process_transaction_record(&Record::chargeback(0, 2), &mut account, &config).expect("ok");
+        assert_account(&account, 100.0, 100.0, true);
+        assert_eq!(account.held_funds, 0.0);
+        assert!(account.lock_state.is_locked());
+    }
+
+    // Tests that a record which can't be applied is added to the report, without stopping the
+    // remaining records from being processed
+    #[test]
+    fn test_read_transactions_from_csv_rejects_insufficient_funds() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec!["deposit,1,1,10.0", "withdrawal,1,2,25.0", "deposit,1,3,5.0"];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, Engine::default(), &mut report).unwrap();
+
+        assert_relative_eq!(client_account_map.get(&1).unwrap().available_funds, 15.0);
+        assert_eq!(
+            report.rejections,
+            vec![Rejection {
+                line: 3,
+                file: Some(file_path_str.clone()),
+                error: EngineError::Ledger(LedgerError::InsufficientFunds(25.0, 10.0)),
+            }]
+        );
+        assert_eq!(report.exit_code(), 0);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a malformed record ends the run with a parse error that carries its line number,
+    // and that the report names the file and line it was read from
+    #[test]
+    fn test_read_transactions_from_csv_malformed_record() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec!["deposit,1,1,10.0", "deposit,1,two,5.0"];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let mut report = ExitReport::default();
+        let err =
+            read_transactions_from_csv(&file_path_str, Engine::default(), &mut report).unwrap_err();
+
+        assert!(matches!(
+            err,
+            EngineError::Source(SourceError::Parse { line: 3, .. })
+        ));
+        assert_eq!(err.code(), 21);
+        assert_eq!(report.fatal_at, Some((Some(file_path_str.clone()), 3)));
+
+        report.fatal = Some(err);
+        assert!(report.to_string().starts_with(&format!(
+            "Error executing run at line 3 of {}! [21]",
+            file_path_str
+        )));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the exit code of a report is taken from its fatal error, or its first rejected
+    // record when it's strict
+    #[test]
+    fn test_exit_report_exit_code() {
+        let mut report = ExitReport::default();
+        report.reject(2, LedgerError::InsufficientFunds(1.0, 0.0));
+        assert_eq!(report.exit_code(), 0);
+
+        report.strict = true;
+        assert_eq!(report.exit_code(), 30);
+
+        report.fatal = Some(CliError::MissingArg.into());
+        assert_eq!(report.exit_code(), 10);
+
+        // a quiet report only includes the fatal error
+        report.quiet = true;
+        assert_eq!(
+            report.to_string(),
+            "Error executing run! [10] An argument for file path must be provided, like so: \
+             cargo run -- some_file_path\n"
+        );
+    }
+
+    // Tests that redacted fields are masked in the report and the rejections file, while the
+    // accounts stay exact
+    #[test]
+    fn test_exit_report_redaction() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,idempotency_key")?;
+        writeln!(file, "deposit,1,1,25.0,key-1")?;
+        writeln!(file, "withdrawal,1,2,300.0,")?;
+        writeln!(file, "deposit,1,3,5.0,key-1")?;
+
+        let mut report = ExitReport {
+            redaction: "amounts:bucket,descriptions".parse().unwrap(),
+            ..Default::default()
+        };
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, Engine::default(), &mut report).unwrap();
+        assert_account(client_account_map.get(&1).unwrap(), 25.0, 25.0, true);
+
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "Rejected record on line 3 of {0} [30]: Failed withdrawal, amount: 100..1000 is \
+                 greater than available funds: 10..100\n\
+                 Rejected record on line 4 of {0} [142]: Transaction 3 reuses idempotency key ***, \
+                 which has already been applied\n",
+                file_path_str
+            )
+        );
+
+        let rejections_path = dir.path().join("rejections.csv");
+        report.write_rejections(&rejections_path).unwrap();
+        let rejections = fs::read_to_string(&rejections_path)?;
+        assert!(rejections.contains("100..1000") && !rejections.contains("300"));
+
+        Ok(())
+    }
+
+    // Tests that adjustments credit and debit the available and total funds, without being
+    // recorded as disputable transactions
+    #[test]
+    fn test_adjust() {
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+
+        account.adjust(25.5).expect("ok");
+        assert_account(
+            &account,
+            125.5,
+            125.5,
+            account.successful_transactions.len() == 1,
+        );
+
+        account.adjust(-50.0).expect("ok");
+        assert_account(
+            &account,
+            75.5,
+            75.5,
+            account.successful_transactions.len() == 1,
+        );
+
+        let result = account.adjust(-80.0).unwrap_err();
+        assert_eq!(result, LedgerError::InsufficientFunds(80.0, 75.5));
+        assert_account(
+            &account,
+            75.5,
+            75.5,
+            account.successful_transactions.len() == 1,
+        );
+    }
+
+    // Tests that adjustments are only processed when admin operations are allowed and a reason
+    // code has been provided
+    #[test]
+    fn test_process_adjustment_transaction() {
+        let mut record = dummy_record(TransactionType::Adjustment, Some(-20.0));
+        let admin_config = EngineConfig {
+            allow_admin_ops: true,
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        account.deposit(200.0, 1);
+
+        let result = process_transaction_record(&record, &mut account, &EngineConfig::default());
+        assert_eq!(
+            result,
+            Err(LedgerError::AdminOpsDisabled(TransactionType::Adjustment))
+        );
+
+        let result = process_transaction_record(&record, &mut account, &admin_config);
+        assert_eq!(result, Err(LedgerError::MissingReason(0)));
+        assert_account(&account, 200.0, 200.0, true);
+
+        record.reason = Some("DUPLICATE_CREDIT".to_string());
+        process_transaction_record(&record, &mut account, &admin_config).expect("ok");
+        assert_account(&account, 180.0, 180.0, true);
+    }
+
+    // Tests that unlocks lift a chargeback's lock, only when admin operations are allowed and a
+    // reason code has been provided
+    #[test]
+    fn test_process_unlock_transaction() {
+        let admin_config = EngineConfig {
+            allow_admin_ops: true,
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        account.deposit(200.0, 1);
+        account.deposit(100.0, 2);
+        account.dispute(2);
+        account.chargeback(2).expect("ok");
+        assert!(account.lock_state.is_locked());
+
+        let mut record = dummy_record(TransactionType::Unlock, None);
+        let result = process_transaction_record(&record, &mut account, &EngineConfig::default());
+        assert_eq!(
+            result,
+            Err(LedgerError::AdminOpsDisabled(TransactionType::Unlock))
+        );
+
+        let result = process_transaction_record(&record, &mut account, &admin_config);
+        assert_eq!(result, Err(LedgerError::MissingReason(0)));
+        assert_eq!(account.lock_state, LockState::ChargebackLock { tx: 2 });
+
+        record.reason = Some("CHARGEBACK_REVIEWED".to_string());
+        process_transaction_record(&record, &mut account, &admin_config).expect("ok");
+        assert_eq!(account.lock_state, LockState::Unlocked);
+        assert_eq!(account.transaction_count, 1);
+
+        let withdrawal = Record::withdrawal(0, 3, 50.0);
+        process_transaction_record(&withdrawal, &mut account, &admin_config).expect("ok");
+        assert_account(&account, 150.0, 150.0, true);
+    }
+
+    // Tests that rows referencing another client's transaction are rejected, including
+    // transactions from loaded state, and that the owner's account is left alone
+    #[test]
+    fn test_dispute_other_clients_transaction() {
+        let mut engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        engine.process(&Record::deposit(1, 1, 50.0)).unwrap();
+        engine.process(&Record::deposit(2, 2, 20.0)).unwrap();
+        assert_eq!(
+            engine.process(&Record::dispute(2, 1)),
+            Err(EngineError::Ledger(LedgerError::ClientMismatch(1, 2, 1)))
+        );
+        // transactions that were never seen are still ignored
+        engine.process(&Record::dispute(2, 99)).unwrap();
+        let accounts = engine.into_accounts().unwrap();
+        assert_account(&accounts[&1], 50.0, 50.0, true);
+        assert_account(&accounts[&2], 20.0, 20.0, true);
+
+        let mut engine = Engine::new(accounts, EngineConfig::default(), Journal::default());
+        assert_eq!(
+            engine.process(&Record::chargeback(1, 2)),
+            Err(EngineError::Ledger(LedgerError::ClientMismatch(2, 1, 2)))
+        );
+        engine.process(&Record::dispute(1, 1)).unwrap();
+        let accounts = engine.into_accounts().unwrap();
+        assert_account(&accounts[&1], 0.0, 50.0, true);
+    }
+
+    // Tests that deposits and withdrawals reusing a transaction id are rejected rather than
+    // crediting or debiting the account again, whichever client sends them
+    #[test]
+    fn test_duplicate_transaction_ids() {
+        let mut engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        engine.process(&Record::deposit(1, 1, 50.0)).unwrap();
+        for record in [
+            Record::deposit(1, 1, 50.0),
+            Record::deposit(2, 1, 5.0),
+            Record::withdrawal(1, 1, 10.0),
+        ] {
+            assert_eq!(
+                engine.process(&record),
+                Err(EngineError::Ledger(LedgerError::DuplicateTransaction(1)))
+            );
+        }
+        let accounts = engine.into_accounts().unwrap();
+        assert_account(&accounts[&1], 50.0, 50.0, true);
+
+        // transactions from loaded state have been seen too
+        let mut engine = Engine::new(accounts, EngineConfig::default(), Journal::default());
+        assert!(engine.process(&Record::deposit(1, 1, 50.0)).is_err());
+        engine.process(&Record::deposit(1, 2, 50.0)).unwrap();
+        let accounts = engine.into_accounts().unwrap();
+        assert_account(&accounts[&1], 100.0, 100.0, true);
+    }
+
+    // Tests that records built with the constructors are applied in the same way as records read
+    // from a file
+    #[test]
+    fn test_record_constructors() {
+        let records = [
+            Record::deposit(1, 1, 100.0),
+            Record::withdrawal(1, 2, 30.0),
+            Record::deposit(1, 3, 20.0),
+            Record::dispute(1, 3),
+            Record::resolve(1, 3),
+            Record::dispute(1, 1),
+            Record::chargeback(1, 1),
+            Record::adjustment(1, 4, 5.0, "GOODWILL"),
+        ];
+
+        let mut engine = Engine::new(
+            HashMap::from([(1, Account::with_balances(10.0, 2.5))]),
+            EngineConfig {
+                allow_admin_ops: true,
+                ..Default::default()
+            },
+            Default::default(),
+        );
+
+        for record in records.iter() {
+            engine.process(record).expect("ok");
+        }
+
+        let account = engine.into_accounts().unwrap().remove(&1).unwrap();
+
+        assert_relative_eq!(account.available_funds, 5.0);
+        assert_relative_eq!(account.held_funds, 2.5);
+        assert_relative_eq!(account.total_funds, 7.5);
+        assert!(account.lock_state.is_locked());
+        assert_eq!(records[7].reason, Some("GOODWILL".to_string()));
+    }
+
+    // Tests that held funds can't be withdrawn with the default withdrawal policy
+    #[test]
+    fn test_withdraw_available_only_policy() {
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+        account.deposit(50.0, 2);
+        account.dispute(2);
+
+        let result = account.withdraw_with_policy(120.0, 3, WithdrawalPolicy::AvailableOnly);
+        assert_eq!(result, Err(LedgerError::InsufficientFunds(120.0, 100.0)));
+
+        account
+            .withdraw_with_policy(100.0, 3, WithdrawalPolicy::AvailableOnly)
+            .expect("ok");
+        assert_account(&account, 0.0, 50.0, true);
+    }
+
+    // Tests that held funds can be withdrawn up to the total funds, with the include held policy
+    #[test]
+    fn test_withdraw_include_held_policy() {
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+        account.deposit(50.0, 2);
+        account.dispute(2);
+
+        let result = account.withdraw_with_policy(160.0, 3, WithdrawalPolicy::IncludeHeld);
+        assert_eq!(result, Err(LedgerError::InsufficientFunds(160.0, 150.0)));
+
+        account
+            .withdraw_with_policy(120.0, 3, WithdrawalPolicy::IncludeHeld)
+            .expect("ok");
+        assert_account(&account, -20.0, 30.0, true);
+        assert_relative_eq!(account.held_funds, 50.0);
+
+        // the total goes negative once the held funds are charged back
+        account.chargeback(2).expect("ok");
+        assert_relative_eq!(account.total_funds, -20.0);
+    }
+
+    // Tests that no withdrawals are allowed while a dispute is open, with the freeze policy
+    #[test]
+    fn test_withdraw_freeze_during_dispute_policy() {
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+        account.deposit(50.0, 2);
+        account.dispute(2);
+
+        let result = account.withdraw_with_policy(10.0, 3, WithdrawalPolicy::FreezeDuringDispute);
+        assert_eq!(result, Err(LedgerError::OpenDispute(3)));
+        assert_account(&account, 100.0, 150.0, true);
+
+        account.resolve(2).expect("ok");
+        account
+            .withdraw_with_policy(10.0, 3, WithdrawalPolicy::FreezeDuringDispute)
+            .expect("ok");
+        assert_account(&account, 140.0, 140.0, true);
+    }
+
+    // Tests that the configured withdrawal policy is used when processing a withdrawal
+    #[test]
+    fn test_process_withdrawal_transaction_with_policy() {
+        let record = dummy_record(TransactionType::Withdrawal, Some(120.0));
+        let config = EngineConfig {
+            withdrawal_policy: WithdrawalPolicy::IncludeHeld,
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+        account.deposit(50.0, 2);
+        account.dispute(2);
+
+        process_transaction_record(&record, &mut account, &config).expect("ok");
+
+        assert_account(&account, -20.0, 30.0, true);
+    }
+
+    // Tests that transactions for clients that haven't been onboarded are rejected while
+    // onboarding is closed, without creating an account for them
+    #[test]
+    fn test_read_transactions_from_csv_closed_onboarding() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec!["deposit,1,1,10.0", "deposit,2,2,5.0", "deposit,1,3,2.5"];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let config = EngineConfig {
+            onboarded_clients: Some(BTreeSet::from([1])),
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), config, Journal::default());
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let account = client_account_map.get(&1).unwrap();
+        assert_eq!(account.first_seen_tx, Some(1));
+        assert_eq!(account.transaction_count, 2);
+        assert!(!client_account_map.contains_key(&2));
+        assert_eq!(
+            report.rejections,
+            vec![Rejection {
+                line: 3,
+                file: Some(file_path_str.clone()),
+                error: EngineError::Ledger(LedgerError::UnknownClient(2)),
+            }]
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that only the records applied to an account count towards its activity, not the ones
+    // that were rejected or ignored
+    #[test]
+    fn test_read_transactions_from_csv_activity() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "withdrawal,1,1,5.0",
+            "deposit,1,2,",
+            "deposit,1,3,10.0",
+            "dispute,1,9,",
+            "dispute,1,3,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, Engine::default(), &mut report).unwrap();
+
+        let account = client_account_map.get(&1).unwrap();
+        assert_eq!(account.first_seen_tx, Some(3));
+        assert_eq!(account.transaction_count, 2);
+        assert_eq!(report.rejections.len(), 1);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that deposits and withdrawals from the same run are reversed by voids, while settled
+    // transactions, spent deposits and transactions that were already voided can't be voided
+    #[test]
+    fn test_read_transactions_from_csv_void() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,2,50.0",
+            "withdrawal,1,3,20.0",
+            "void,1,3,",
+            "void,1,1,",
+            "deposit,1,4,10.0",
+            "withdrawal,1,5,56.0",
+            "void,1,4,",
+            "dispute,1,3,",
+            "void,1,3,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let mut settled = Account::default();
+        settled.deposit(5.0, 1);
+        let loaded = HashMap::from([(1, settled)]);
+        let engine = Engine::new(loaded, EngineConfig::default(), Journal::default());
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let account = client_account_map.get(&1).unwrap();
+        assert_account(account, 9.0, 9.0, true);
+        assert_relative_eq!(account.held_funds, 0.0);
+        assert_eq!(
+            account.successful_transactions[&3].current_state,
+            TransactionType::Void
+        );
+        assert_eq!(
+            report.rejections,
+            vec![
+                Rejection {
+                    line: 5,
+                    file: Some(file_path_str.clone()),
+                    error: EngineError::Ledger(LedgerError::Settled(1)),
+                },
+                Rejection {
+                    line: 8,
+                    file: Some(file_path_str.clone()),
+                    error: EngineError::Ledger(LedgerError::InsufficientFunds(10.0, 9.0)),
+                },
+                Rejection {
+                    line: 10,
+                    file: Some(file_path_str.clone()),
+                    error: EngineError::Ledger(LedgerError::InvalidTransition(
+                        3,
+                        TransactionType::Void,
+                        TransactionType::Void,
+                    )),
+                },
+            ]
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that voids are only applied before the settlement cutoff
+    #[test]
+    fn test_process_void_settlement_cutoff() {
+        let config = EngineConfig {
+            settlement_cutoff: Some(1_000),
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        let deposit = dummy_record(TransactionType::Deposit, Some(50.0));
+        process_transaction_record(&deposit, &mut account, &config).expect("ok");
+
+        let mut void = dummy_record(TransactionType::Void, None);
+        assert_eq!(
+            process_transaction_record(&void, &mut account, &config),
+            Err(LedgerError::Settled(0))
+        );
+
+        void.timestamp = Some(1_000);
+        assert_eq!(
+            process_transaction_record(&void, &mut account, &config),
+            Err(LedgerError::Settled(0))
+        );
+
+        void.timestamp = Some(999);
+        process_transaction_record(&void, &mut account, &config).expect("ok");
+        assert_account(&account, 0.0, 0.0, true);
+    }
+
+    // Tests that disputes are only applied within the window after the transaction they reference
+    #[test]
+    fn test_process_dispute_window() {
+        let config = EngineConfig {
+            dispute_window: Some(86_400),
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        let mut deposit = dummy_record(TransactionType::Deposit, Some(50.0));
+        deposit.timestamp = Some(1_000);
+        process_transaction_record(&deposit, &mut account, &config).expect("ok");
+
+        let mut dispute = dummy_record(TransactionType::Dispute, None);
+        dispute.timestamp = Some(87_401);
+        assert_eq!(
+            process_transaction_record(&dispute, &mut account, &config),
+            Err(LedgerError::DisputeWindowExpired(0, 86_401, 86_400))
+        );
+        assert_account(&account, 50.0, 50.0, true);
+
+        dispute.timestamp = Some(87_400);
+        process_transaction_record(&dispute, &mut account, &config).expect("ok");
+        assert_account(&account, 0.0, 50.0, true);
+    }
+
+    // Tests that deposits, withdrawals and transfers of zero or less are rejected, leaving the
+    // account unchanged
+    #[test]
+    fn test_process_non_positive_amounts() {
+        let config = EngineConfig::default();
+        let mut account = Account::default();
+        let deposit = dummy_record(TransactionType::Deposit, Some(50.0));
+        process_transaction_record(&deposit, &mut account, &config).expect("ok");
+
+        for (transaction_type, amount) in [
+            (TransactionType::Deposit, -500.0),
+            (TransactionType::Deposit, 0.0),
+            (TransactionType::Withdrawal, -5.0),
+            (TransactionType::Withdrawal, f32::NAN),
+            (TransactionType::Transfer, -5.0),
+        ] {
+            let mut record = dummy_record(transaction_type, Some(amount));
+            record.transaction_id = 1;
+            record.destination = Some(2);
+            let result = process_transaction_record(&record, &mut account, &config);
+            assert!(matches!(result, Err(LedgerError::NonPositiveAmount(1, _))));
+            assert_account(&account, 50.0, 50.0, true);
+        }
+
+        let mut withdrawal = dummy_record(TransactionType::Withdrawal, Some(5.0));
+        withdrawal.transaction_id = 1;
+        process_transaction_record(&withdrawal, &mut account, &config).expect("ok");
+        assert_account(&account, 45.0, 45.0, true);
+    }
+
+    // Tests that a record leaving its account's balances inconsistent ends the run with its line,
+    // and that available funds are only checked when they've been asked for
+    #[test]
+    fn test_read_transactions_from_csv_check_invariants() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec!["deposit,1,1,10.0", "deposit,2,2,5.0"];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        // the loaded state was edited by hand, so client 2's total doesn't match its balances
+        let accounts = HashMap::from([(
+            2,
+            Account {
+                total_funds: 1.0,
+                ..Account::with_balances(-3.0, 0.0)
+            },
+        )]);
+        let config = EngineConfig {
+            invariants: InvariantChecks {
+                enabled: true,
+                available_non_negative: false,
+            },
+            ..Default::default()
+        };
+        let engine = Engine::new(accounts, config, Journal::default());
+        let mut report = ExitReport::default();
+        let result = read_transactions_from_csv(&file_path_str, engine, &mut report);
+        assert_eq!(
+            result.unwrap_err(),
+            EngineError::Source(SourceError::Invariant {
+                line: 3,
+                client: 2,
+                message: "total funds 6 aren't the sum of available funds 2 and held funds 0"
+                    .to_string(),
+            })
+        );
+
+        let mut account = Account::with_balances(-3.0, 0.0);
+        assert_eq!(account.broken_invariant(false), None);
+        assert_eq!(
+            account.broken_invariant(true),
+            Some("available funds -3 are negative".to_string())
+        );
+
+        // float error alone doesn't break an invariant
+        account.held_funds = -0.00001;
+        account.total_funds = -3.00001;
+        assert_eq!(account.broken_invariant(false), None);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that each edge case is ignored, warned about or rejected as the engine policy says,
+    // and that warnings don't count as rejections
+    #[test]
+    fn test_read_transactions_from_csv_engine_policy() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,10.0",
+            "deposit,1,3,5.0",
+            "withdrawal,1,2,4.0",
+            "dispute,1,9,",
+            "resolve,1,1,",
+            "dispute,1,2,",
+            "dispute,1,3,",
+            "chargeback,1,3,",
+            "deposit,1,4,5.0",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let config = EngineConfig {
+            policy: EnginePolicy {
+                unknown_transaction: PolicyAction::Warn,
+                not_disputed: PolicyAction::Error,
+                locked_deposit: PolicyAction::Error,
+                withdrawal_dispute: PolicyAction::Ignore,
+            },
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), config, Journal::default());
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let account = &client_account_map[&1];
+        assert_account(account, 6.0, 6.0, true);
+        assert_eq!(account.lock_state, LockState::ChargebackLock { tx: 3 });
+        assert_eq!(
+            account.successful_transactions[&2].current_state,
+            TransactionType::Withdrawal
+        );
+        assert_eq!(
+            report.warnings,
+            vec![Rejection {
+                line: 5,
+                file: Some(file_path_str.clone()),
+                error: LedgerError::UnknownTransaction(9).into(),
+            }]
+        );
+        assert_eq!(
+            report.rejections,
+            vec![
+                Rejection {
+                    line: 6,
+                    file: Some(file_path_str.clone()),
+                    error: LedgerError::NotDisputed(1).into(),
+                },
+                Rejection {
+                    line: 10,
+                    file: Some(file_path_str.clone()),
+                    error: LedgerError::AccountLocked(
+                        1,
+                        TransactionType::Deposit,
+                        "chargeback-lock"
+                    )
+                    .into(),
+                },
+            ]
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that transactions for screened clients are held for compliance review, without
+    // creating an account for them
+    #[test]
+    fn test_read_transactions_from_csv_compliance_screening() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec!["deposit,1,1,10.0", "deposit,2,2,5.0", "deposit,3,3,2.5"];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let config = EngineConfig {
+            screening: Screening {
+                restricted_countries: BTreeSet::from(["KP".to_string()]),
+                client_countries: BTreeMap::from([(1, "GB".to_string()), (2, "KP".to_string())]),
+                denylist: BTreeSet::from([3]),
+            },
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), config, Journal::default());
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert_eq!(client_account_map.keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(
+            report.rejections,
+            vec![
+                Rejection {
+                    line: 3,
+                    file: Some(file_path_str.clone()),
+                    error: EngineError::Ledger(LedgerError::ComplianceHold(
+                        2,
+                        2,
+                        HoldReason::RestrictedCountry("KP".to_string())
+                    )),
+                },
+                Rejection {
+                    line: 4,
+                    file: Some(file_path_str.clone()),
+                    error: EngineError::Ledger(LedgerError::ComplianceHold(
+                        3,
+                        3,
+                        HoldReason::Denylisted
+                    )),
+                },
+            ]
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that disputes arriving before the transaction they reference are applied once it
+    // arrives, and expire once they've been parked for longer than the window
+    #[test]
+    fn test_read_transactions_from_csv_dispute_buffer() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "dispute,1,2,",
+            "deposit,1,1,5.0",
+            "deposit,1,2,3.0",
+            "dispute,1,5,",
+            "deposit,1,3,1.0",
+            "deposit,1,4,1.0",
+            "deposit,1,5,2.0",
+            "chargeback,2,9,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_dispute_buffer(BufferWindow::Records(2));
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert!(report.rejections.is_empty());
+        assert_eq!(
+            report.buffered,
+            vec![
+                BufferOutcome {
+                    client: 1,
+                    tx: 2,
+                    transaction_type: TransactionType::Dispute,
+                    applied: true,
+                },
+                BufferOutcome {
+                    client: 1,
+                    tx: 5,
+                    transaction_type: TransactionType::Dispute,
+                    applied: false,
+                },
+                BufferOutcome {
+                    client: 2,
+                    tx: 9,
+                    transaction_type: TransactionType::Chargeback,
+                    applied: false,
+                },
+            ]
+        );
+        assert_account(client_account_map.get(&1).unwrap(), 9.0, 12.0, true);
+        assert_relative_eq!(client_account_map.get(&1).unwrap().held_funds, 3.0);
+        assert!(!client_account_map.contains_key(&2));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that withdrawals rejected for insufficient funds are retried after the client's later
+    // deposits, and expire once they've been parked for longer than the window
+    #[test]
+    fn test_read_transactions_from_csv_withdrawal_retries() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "withdrawal,1,1,5.0",
+            "withdrawal,2,2,5.0",
+            "deposit,1,3,3.0",
+            "deposit,1,4,3.0",
+            "deposit,2,5,1.0",
+            "deposit,2,6,10.0",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_withdrawal_retries(Some(3));
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert!(report.rejections.is_empty());
+        assert_eq!(
+            report.retries,
+            vec![
+                RetryOutcome {
+                    client: 1,
+                    tx: 1,
+                    attempts: 3,
+                    succeeded: true,
+                },
+                RetryOutcome {
+                    client: 2,
+                    tx: 2,
+                    attempts: 2,
+                    succeeded: false,
+                },
+            ]
+        );
+        assert_account(client_account_map.get(&1).unwrap(), 1.0, 1.0, true);
+        assert_account(client_account_map.get(&2).unwrap(), 11.0, 11.0, true);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that admin operations are applied in their phase and journaled with their operator,
+    // and that operations on clients without an account are rejected with their line
+    #[test]
+    fn test_read_transactions_from_csv_admin_operations() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        add_transactions_to_temp_file(vec!["deposit,1,1,10.0", "dispute,1,1,"], &mut file)?;
+
+        let operation = |op, client, tx, amount| AdminOperation {
+            op,
+            client,
+            tx,
+            amount,
+            operator: "alice".to_string(),
+            recorded_at_ms: 0,
+        };
+        let operations = vec![
+            (2, operation(AdminAction::ForceResolve, 1, Some(1), None)),
+            (3, operation(AdminAction::Close, 2, None, None)),
+        ];
+
+        let journal_path = dir.path().join("journal.log");
+        let journal = Journal::open(&journal_path).unwrap();
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), journal)
+            .with_admin_operations(operations.clone(), AdminPhase::After);
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert_account(client_account_map.get(&1).unwrap(), 10.0, 10.0, true);
+        assert_eq!(
+            report.rejections,
+            vec![Rejection {
+                line: 3,
+                file: None,
+                error: LedgerError::NoAccount(2).into(),
+            }]
+        );
+        let journal = fs::read_to_string(&journal_path)?;
+        let last = journal.lines().last().map(parse_entry).unwrap().unwrap();
+        assert!(matches!(last, JournalEntry::Operation(op) if op.operator == "alice"));
+
+        // applied before the records, a limit is in place for the withdrawal
+        let accounts = HashMap::from([(1, Account::with_balances(10.0, 0.0))]);
+        let operations = vec![(2, operation(AdminAction::SetLimit, 1, None, Some(5.0)))];
+        let engine = Engine::new(accounts, EngineConfig::default(), Journal::default())
+            .with_admin_operations(operations, AdminPhase::Before);
+        let mut file = File::create(&file_path_str)?;
+        add_transactions_to_temp_file(vec!["withdrawal,1,2,6.0"], &mut file)?;
+        let mut report = ExitReport::default();
+        read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert_eq!(
+            report.rejections[0].error,
+            LedgerError::OverLimit(2, 6.0, 5.0).into()
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a client crossing a fraud rule is quarantined, holding their deposits and
+    // rejecting their withdrawals until an operator releases them
+    #[test]
+    fn test_read_transactions_from_csv_quarantine() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,10.0",
+            "dispute,1,1,",
+            "resolve,1,1,",
+            "deposit,1,2,5.0",
+            "withdrawal,1,3,1.0",
+            "deposit,2,4,1.0",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let release = |client| AdminOperation {
+            op: AdminAction::Release,
+            client,
+            tx: None,
+            amount: None,
+            operator: "alice".to_string(),
+            recorded_at_ms: 0,
+        };
+        let config = EngineConfig {
+            quarantine: QuarantineRules {
+                disputes: Some(1),
+                chargebacks: None,
+            },
+            ..Default::default()
+        };
+        let operations = vec![(2, release(1)), (3, release(2))];
+        let engine = Engine::new(HashMap::new(), config, Journal::default())
+            .with_admin_operations(operations, AdminPhase::After);
+        let mut report = ExitReport::default();
+        let accounts = read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert_account(&accounts[&1], 15.0, 15.0, true);
+        assert_eq!(accounts[&1].lock_state, LockState::Unlocked);
+        assert_eq!(
+            report.rejections,
+            vec![
+                Rejection {
+                    line: 6,
+                    file: Some(file_path_str.clone()),
+                    error: LedgerError::AccountLocked(1, TransactionType::Withdrawal, "quarantine")
+                        .into(),
+                },
+                Rejection {
+                    line: 3,
+                    file: None,
+                    error: LedgerError::NotQuarantined(2).into(),
+                },
+            ]
+        );
+        assert_eq!(report.quarantine.len(), 2);
+        assert_eq!(
+            report.quarantine[1].to_string(),
+            "client 1 was released from quarantine by alice, releasing 1 held deposits"
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that spilling all but a few transactions to disk gives the same accounts as keeping
+    // them all in memory, as disputes of spilled transactions still find them
+    #[test]
+    fn test_read_transactions_from_csv_transaction_spill() -> Result<(), Error> {
+        let (file_path_str, dir, file) = create_temp_file("transactions.csv")?;
+        let config = GeneratorConfig {
+            dispute_probability: 0.2,
+            ..Default::default()
+        };
+        generate(&config, &file).unwrap();
+
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        let mut report = ExitReport::default();
+        let expected = read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let store = DiskStore::new(Some(dir.path())).unwrap();
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_transaction_spill(10, Box::new(store));
+        let mut spilled_report = ExitReport::default();
+        let accounts =
+            read_transactions_from_csv(&file_path_str, engine, &mut spilled_report).unwrap();
+
+        assert_eq!(accounts, expected);
+        assert_eq!(spilled_report.rejections, report.rejections);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the circuit breaker halts the run once a limit is exceeded, checkpointing the
+    // accounts as they were before the record that tripped it
+    #[test]
+    fn test_read_transactions_from_csv_circuit_breaker() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,10.0",
+            "withdrawal,1,2,20.0",
+            "deposit,2,3,1000000.0",
+            "deposit,2,4,1.0",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+        let checkpoint_path = dir.path().join("state.bin.tripped");
+
+        let limits = BreakerLimits {
+            max_total_movement: Some(1_000.0),
+            checkpoint: Some(checkpoint_path.clone()),
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_circuit_breaker(CircuitBreaker::new(limits));
+        let mut report = ExitReport::default();
+        let err = read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap_err();
+
+        assert_eq!(err.code(), 26);
+        assert_eq!(report.records, 3);
+        let checkpoint = load_state(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.len(), 1);
+        assert_account(&checkpoint[&1], 10.0, 10.0, true);
+
+        // half of the rows were rejected, which is only checked once the run has finished
+        let limits = BreakerLimits {
+            max_rows: Some(4),
+            max_rejects_pct: Some(20.0),
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_circuit_breaker(CircuitBreaker::new(limits));
+        let mut report = ExitReport::default();
+        let err = read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::Source(SourceError::Tripped(
+                "1 of 4 rows were rejected, more than 20%".to_string()
+            ))
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the funds reversed by chargebacks are tracked for each client, ignoring
+    // chargebacks of transactions that aren't disputed
+    #[test]
+    fn test_read_transactions_from_csv_chargeback_losses() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,10.0",
+            "deposit,1,2,2.5",
+            "deposit,2,3,4.0",
+            "dispute,1,1,",
+            "chargeback,1,1,",
+            "chargeback,2,3,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        let mut report = ExitReport::default();
+        read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert_eq!(
+            report.losses.get(1),
+            Some(&ClientLoss {
+                chargebacks: 1,
+                amount: 10.0,
+            })
+        );
+        assert_eq!(report.losses.get(2), None);
+        assert_relative_eq!(report.losses.total(), 10.0);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a csv can be processed straight from a string, with rejected records skipped and
+    // the accounts ordered by client id
+    #[test]
+    fn test_process_csv_str() {
+        let accounts = process_csv_str(
+            "type,client,tx,amount\n\
+             deposit, 2, 1, 5.0\n\
+             deposit, 1, 2, 10.0\n\
+             withdrawal, 1, 3, 20.0\n\
+             dispute, 2, 1,\n",
+        )
+        .unwrap();
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].client, 1);
+        assert_relative_eq!(accounts[0].available, 10.0);
+        assert_eq!(accounts[1].client, 2);
+        assert_relative_eq!(accounts[1].held, 5.0);
+
+        assert!(matches!(
+            process_reader("type,client,tx,amount\nreversal,1,1,1.0\n".as_bytes()),
+            Err(EngineError::Source(SourceError::Parse { line: 2, .. }))
+        ));
+    }
+
+    // Tests that the records applied from a file are tallied for the run's figures, with rejected
+    // withdrawals counted but not withdrawn
+    #[test]
+    fn test_run_stats() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,10.0",
+            "deposit,2,2,2.5",
+            "withdrawal,1,3,4.0",
+            "withdrawal,2,4,5.0",
+            "dispute,2,2,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        let mut report = ExitReport::default();
+        let accounts = read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let stats = RunStats::new(&report, &accounts, Duration::from_secs(2));
+        assert_eq!(stats.rows_read, 5);
+        assert_eq!(stats.rows_rejected, 1);
+        assert_eq!(
+            stats.transactions,
+            BTreeMap::from([("deposit", 2), ("dispute", 1), ("withdrawal", 2)])
+        );
+        assert_relative_eq!(stats.deposited, 12.5);
+        assert_relative_eq!(stats.withdrawn, 4.0);
+        assert_relative_eq!(stats.held, 2.5);
+        assert_relative_eq!(stats.throughput, 2.5);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that gzip compressed JSON lines and tsv are read the same as csv
+    #[test]
+    fn test_read_transactions_from_file_formats() -> Result<(), Error> {
+        let (file_path_str, dir, file) = create_temp_file("transactions.jsonl.gz")?;
+        let mut encoder = GzEncoder::new(File::create(&file_path_str)?, Compression::default());
+        writeln!(
+            encoder,
+            r#"{{"type":"deposit","client":1,"tx":1,"amount":10.0}}"#
+        )?;
+        writeln!(encoder)?;
+        writeln!(
+            encoder,
+            r#"{{"type":"withdrawal","client":1,"tx":2,"amount":4.0}}"#
+        )?;
+        writeln!(encoder, r#"{{"type":"dispute","client":1,"tx":1}}"#)?;
+        encoder.finish()?;
+
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        let mut report = ExitReport::default();
+        let client_account_map = read_transactions_from_file(
+            Path::new(&file_path_str),
+            InputFormat::Jsonl,
+            &CsvOptions::default(),
+            engine,
+            &mut report,
+        )
+        .unwrap();
+        assert_account(client_account_map.get(&1).unwrap(), -4.0, 6.0, true);
+        assert_eq!(report.records, 3);
+
+        let tsv_path = dir.path().join("transactions.tsv");
+        fs::write(
+            &tsv_path,
+            "type\tclient\ttx\tamount\ndeposit\t2\t3\t5.5\nreversal\t2\t4\t1.0\n",
+        )?;
+        for backend in [CsvBackend::Csv, CsvBackend::Fast] {
+            let csv = CsvOptions {
+                backend,
+                ..Default::default()
+            };
+            let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+            let mut report = ExitReport::default();
+            assert!(matches!(
+                read_transactions_from_file(&tsv_path, InputFormat::Tsv, &csv, engine, &mut report),
+                Err(EngineError::Source(SourceError::Parse { line: 3, .. }))
+            ));
+            assert_eq!(report.records, 2);
+        }
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that malformed rows are rejected and the valid rows around them still applied, when
+    // skipping them, and that every rejection is written to the rejections file
+    #[test]
+    fn test_read_transactions_from_csv_skip_malformed() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount")?;
+        writeln!(file, "deposit,1,1,10.0")?;
+        writeln!(file, "reversal,1,2,1.0")?;
+        writeln!(file, "deposit,one,3,2.0")?;
+        writeln!(file, "withdrawal,1,4,20.0")?;
+        writeln!(file, "withdrawal,1,5,4.0")?;
+
+        let config = EngineConfig {
+            skip_malformed: true,
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), config, Journal::default());
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+        assert_account(client_account_map.get(&1).unwrap(), 6.0, 6.0, true);
+        assert_eq!(report.records, 5);
+        assert_eq!(report.malformed(), 2);
+        let lines: Vec<u64> = report
+            .rejections
+            .iter()
+            .map(|rejection| rejection.line)
+            .collect();
+        assert_eq!(lines, vec![3, 4, 5]);
+        assert!(report.to_string().contains("Skipped 2 malformed records"));
+
+        let rejections_path = dir.path().join("rejections.csv");
+        report.write_rejections(&rejections_path).unwrap();
+        let rejections = fs::read_to_string(&rejections_path)?;
+        let codes: Vec<&str> = rejections
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(2).unwrap())
+            .collect();
+        assert_eq!(codes, vec!["21", "21", "30"]);
+        assert!(rejections.starts_with(&format!("file,line,code,error\n{},3,", file_path_str)));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a record that did nothing is logged at debug, within the span of its file and
+    // record, with why it did nothing
+    #[test]
+    fn test_read_transactions_from_csv_tracing() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec!["deposit,1,1,10.0", "dispute,1,9,", "withdrawal,1,2,50.0"];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter("plutus_engine=debug")
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+            let mut report = ExitReport::default();
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("DEBUG"));
+        assert!(lines[0].contains("file{path="));
+        assert!(lines[0].contains("record{line=3 type=\"dispute\" client=1 tx=9}"));
+        assert!(lines[0].contains("Transaction 9 isn't in the client's account"));
+        assert!(lines[1].contains("record{line=4 type=\"withdrawal\" client=1 tx=2}"));
+        assert!(lines[1].contains("rejected"));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the result of every record is written with the line it was read from, including
+    // the rows that couldn't be parsed and the ones that had no effect
+    #[test]
+    fn test_read_transactions_from_csv_tx_results() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,10.0",
+            "deposit,one,2,2.0",
+            "withdrawal,1,3,20.0",
+            "dispute,1,9,",
+            "deposit,1,1,3.0",
+            "dispute,1,1,",
+            "dispute,1,1,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let results_path = dir.path().join("results.jsonl");
+        let config = EngineConfig {
+            skip_malformed: true,
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), config, Journal::default())
+            .with_tx_results(TxResults::create(&results_path).unwrap());
+        let mut report = ExitReport::default();
+        read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let results: Vec<(u64, String, String)> = fs::read_to_string(&results_path)?
+            .lines()
+            .map(|line| {
+                let result: serde_json::Value = serde_json::from_str(line).unwrap();
+                let reason = result["reason"].as_str().unwrap_or_default().to_string();
+                (
+                    result["line"].as_u64().unwrap(),
+                    result["status"].to_string(),
+                    reason,
+                )
+            })
+            .collect();
+        let expected = [
+            (2, "accepted", ""),
+            (3, "rejected", "Parse"),
+            (4, "rejected", "InsufficientFunds"),
+            (5, "ignored", "UnknownTransaction"),
+            (6, "rejected", "DuplicateTransaction"),
+            (7, "accepted", ""),
+            (8, "ignored", "NoChange"),
+        ];
+        let expected: Vec<(u64, String, String)> = expected
+            .iter()
+            .map(|(line, status, reason)| (*line, format!("\"{}\"", status), reason.to_string()))
+            .collect();
+        assert_eq!(results, expected);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that every mutation is audited with the balances before and after it, both sides of a
+    // transfer included, and that rejected records aren't
+    #[test]
+    fn test_read_transactions_from_csv_audit_log() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,destination,timestamp")?;
+        for transaction in [
+            "deposit,1,1,10.0,,",
+            "withdrawal,1,2,20.0,,",
+            "transfer,1,3,4.0,2,",
+            "dispute,1,1,,,",
+            "chargeback,1,1,,,",
+            "deposit,3,4,5.0,,0",
+            "dispute,3,4,,,0",
+        ] {
+            writeln!(file, "{}", transaction)?;
+        }
+
+        let audit_path = dir.path().join("audit.jsonl");
+        let clock = Arc::new(FixedClock::new(1_000));
+        let audit = AuditLog::open(&audit_path)
+            .unwrap()
+            .with_clock(clock.clone());
+        let escheatment = EscheatmentSettings {
+            after_days: Some(180),
+            holding_account: Some(999),
+            as_of: Some(200 * 86_400),
+            report: None,
+        };
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_audit_log(audit)
+            .with_escheatment(escheatment, clock);
+        let mut report = ExitReport::default();
+        read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let events: Vec<serde_json::Value> = fs::read_to_string(&audit_path)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let summary: Vec<(&str, u64, f64, f64, &str)> = events
+            .iter()
+            .map(|event| {
+                (
+                    event["type"].as_str().unwrap(),
+                    event["client"].as_u64().unwrap(),
+                    event["before"]["total"].as_f64().unwrap(),
+                    event["after"]["total"].as_f64().unwrap(),
+                    event["state"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("deposit", 1, 0.0, 10.0, "unlocked"),
+                ("transfer", 1, 10.0, 6.0, "unlocked"),
+                ("transfer", 2, 0.0, 4.0, "unlocked"),
+                ("dispute", 1, 6.0, 6.0, "unlocked"),
+                ("chargeback", 1, 6.0, -4.0, "chargeback-lock"),
+                ("deposit", 3, 0.0, 5.0, "unlocked"),
+                ("dispute", 3, 5.0, 5.0, "unlocked"),
+                // the escheatment sweep moves the held funds to the holding account
+                ("escheated", 3, 5.0, 0.0, "unlocked"),
+                ("escheated", 999, 0.0, 5.0, "unlocked"),
+            ]
+        );
+        assert_eq!(report.escheatments.len(), 1);
+        assert_eq!(events[7]["tx"], 4);
+        assert_eq!(events[8]["tx"], serde_json::Value::Null);
+        assert_eq!(events[3]["after"]["held"], 10.0);
+        assert_eq!(events[4]["recorded_at_ms"], 1_000);
+        let last_line = fs::read_to_string(&audit_path)?
+            .lines()
+            .last()
+            .unwrap()
+            .to_string();
+        assert_eq!(report.audit_head, Some(hash_line(&last_line)));
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that timestamps are read as RFC 3339, epoch milliseconds or epoch seconds by either
+    // backend, and kept on the deposits and withdrawals they belong to
+    #[test]
+    fn test_read_transactions_from_csv_timestamps() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,timestamp")?;
+        writeln!(file, "deposit,1,1,10.0,2024-03-01T09:30:00Z")?;
+        writeln!(file, "withdrawal,1,2,4.0,1709285460000")?;
+        writeln!(file, "deposit,1,3,1.0,1709285520")?;
+        writeln!(file, "deposit,1,4,1.0,")?;
+
+        for backend in [CsvBackend::Csv, CsvBackend::Fast] {
+            let csv = CsvOptions {
+                backend,
+                ..Default::default()
+            };
+            let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+            let mut report = ExitReport::default();
+            let file_path = Path::new(&file_path_str);
+            let format = InputFormat::Csv;
+            let client_account_map =
+                read_transactions_from_file(file_path, format, &csv, engine, &mut report).unwrap();
+
+            let transactions = &client_account_map[&1].successful_transactions;
+            assert_eq!(transactions[&1].timestamp, Some(1_709_285_400));
+            assert_eq!(transactions[&2].timestamp, Some(1_709_285_460));
+            assert_eq!(transactions[&3].timestamp, Some(1_709_285_520));
+            assert_eq!(transactions[&4].timestamp, None);
+        }
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that several files are read as one stream, merged by their timestamps, whatever the
+    // order of their columns
+    #[test]
+    fn test_read_transactions_from_files() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("a.csv")?;
+        writeln!(file, "type,client,tx,amount,timestamp")?;
+        writeln!(file, "deposit,1,1,5.0,100")?;
+        writeln!(file, "withdrawal,1,2,8.0,300")?;
+        let mut other = File::create(dir.path().join("b.csv"))?;
+        writeln!(other, "timestamp,type,client,tx,amount")?;
+        writeln!(other, "200,deposit,1,3,5.0")?;
+        writeln!(other, "400,withdrawal,1,4,8.0")?;
+
+        let args = CliArgs {
+            file_path: dir.path().join("*.csv"),
+            ..Default::default()
+        };
+        let inputs = get_inputs(&args).unwrap();
+        assert_eq!(
+            inputs,
+            vec![
+                (PathBuf::from(&file_path_str), InputFormat::Csv),
+                (dir.path().join("b.csv"), InputFormat::Csv),
+            ]
+        );
+
+        // the withdrawal of tx 2 comes after the deposit in the other file
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        let mut report = ExitReport::default();
+        let csv = CsvOptions::default();
+        let client_account_map =
+            read_transactions_from_files(&inputs, &csv, engine, &mut report).unwrap();
+        assert_account(&client_account_map[&1], 2.0, 2.0, true);
+        assert_eq!(
+            report.rejections,
+            vec![Rejection {
+                line: 3,
+                file: Some(dir.path().join("b.csv").display().to_string()),
+                error: EngineError::Ledger(LedgerError::InsufficientFunds(8.0, 2.0)),
+            }]
+        );
+
+        drop((file, other));
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that refunds credit a withdrawal back once, and are rejected for anything else
+    #[test]
+    fn test_read_transactions_from_csv_refunds() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        let transactions = vec![
+            "deposit,1,1,10.0",
+            "withdrawal,1,2,4.0",
+            "refund,1,2,",
+            "refund,1,2,",
+            "refund,1,1,",
+            "withdrawal,1,3,1.0",
+            "dispute,1,3,",
+            "refund,1,3,",
+            "refund,1,9,",
+            "dispute,1,2,",
+        ];
+        add_transactions_to_temp_file(transactions, &mut file)?;
+
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, Engine::default(), &mut report).unwrap();
+
+        let account = client_account_map.get(&1).unwrap();
+        assert_account(account, 9.0, 10.0, true);
+        assert_relative_eq!(account.held_funds, 1.0);
+        assert_eq!(
+            account.successful_transactions[&2].current_state,
+            TransactionType::Refund
+        );
+        let rejection = |line, transaction_id, state| Rejection {
+            line,
+            file: Some(file_path_str.clone()),
+            error: LedgerError::NotRefundable(transaction_id, state).into(),
+        };
+        assert_eq!(
+            report.rejections,
+            vec![
+                rejection(5, 2, TransactionType::Refund),
+                rejection(6, 1, TransactionType::Deposit),
+                rejection(9, 3, TransactionType::Dispute),
+            ]
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that the money moved by each record, including both sides of a transfer and a
+    // chargeback, is posted on top of the loaded accounts, so the accounts reconcile with it
+    #[test]
+    fn test_reconcile() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,destination")?;
+        for transaction in [
+            "deposit,1,1,10.0,",
+            "deposit,2,2,5.0,",
+            "withdrawal,1,3,2.0,",
+            "withdrawal,1,4,20.0,",
+            "transfer,1,5,4.0,2",
+            "dispute,2,2,,",
+            "chargeback,2,2,,",
+        ] {
+            writeln!(file, "{}", transaction)?;
+        }
+
+        let mut loaded = Account::default();
+        loaded.deposit(3.0, 100);
+        let loaded_account_map = HashMap::from([(9, loaded)]);
+        let engine = Engine::new(
+            loaded_account_map,
+            EngineConfig::default(),
+            Journal::default(),
+        );
+        let mut report = ExitReport {
+            postings: Some(Postings::default()),
+            ..Default::default()
+        };
+        let accounts = read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let reconciliation = Reconciliation::new(report.postings.as_ref().unwrap(), &accounts);
+        assert!(reconciliation.is_balanced());
+        assert_relative_eq!(reconciliation.total_credits, 19.0);
+        assert_relative_eq!(reconciliation.total_debits, 11.0);
+        assert_relative_eq!(reconciliation.total_held, 0.0);
+        assert_relative_eq!(reconciliation.net_position, 11.0);
+        assert_relative_eq!(reconciliation.closing_total, 11.0);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that transfers move funds between clients, and that a rejected transfer changes neither
+    // account
+    #[test]
+    fn test_read_transactions_from_csv_transfers() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,destination")?;
+        for transaction in [
+            "deposit,1,1,10.0,",
+            "deposit,3,2,5.0,",
+            "dispute,3,2,,",
+            "chargeback,3,2,,",
+            "transfer,1,3,4.0,2",
+            "transfer,1,4,7.0,2",
+            "transfer,1,5,1.0,3",
+            "transfer,3,6,1.0,1",
+            "transfer,1,7,1.0,",
+            "transfer,2,3,1.0,1",
+            "transfer,1,8,100.0,7",
+        ] {
+            writeln!(file, "{}", transaction)?;
+        }
+
+        let transfer = TransactionType::Transfer;
+        let locked = |client| LedgerError::AccountLocked(client, transfer, "chargeback-lock");
+        for backend in [CsvBackend::Csv, CsvBackend::Fast] {
+            let csv = CsvOptions {
+                backend,
+                ..Default::default()
+            };
+            let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+            let mut report = ExitReport::default();
+            let file_path = Path::new(&file_path_str);
+            let format = InputFormat::Csv;
+            let client_account_map =
+                read_transactions_from_file(file_path, format, &csv, engine, &mut report).unwrap();
+
+            assert_account(client_account_map.get(&1).unwrap(), 6.0, 6.0, true);
+            assert_account(client_account_map.get(&2).unwrap(), 4.0, 4.0, true);
+            assert_account(client_account_map.get(&3).unwrap(), 0.0, 0.0, true);
+            // the failed transfer to client 7 didn't open an account for it
+            assert_eq!(client_account_map.len(), 3);
+            let rejection = |line, error: LedgerError| Rejection {
+                line,
+                file: Some(file_path_str.clone()),
+                error: error.into(),
+            };
+            assert_eq!(
+                report.rejections,
+                vec![
+                    rejection(7, LedgerError::InsufficientFunds(7.0, 6.0)),
+                    rejection(8, locked(3)),
+                    rejection(9, locked(3)),
+                    rejection(10, LedgerError::InvalidDestination(7)),
+                    rejection(11, LedgerError::DuplicateTransaction(3)),
+                    rejection(12, LedgerError::InsufficientFunds(100.0, 6.0)),
+                ]
+            );
+        }
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a withdrawal which shares a timestamp with the resolve that follows it succeeds
+    // when resolves are applied first, and is rejected otherwise
+    #[test]
+    fn test_read_transactions_from_csv_resolves_first() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,timestamp")?;
+        for transaction in [
+            "deposit,1,1,10.0,100",
+            "dispute,1,1,,101",
+            "withdrawal,1,2,4.0,102",
+            "resolve,1,1,,102",
+            "withdrawal,1,3,4.0,103",
+            "withdrawal,1,4,4.0,104",
+        ] {
+            writeln!(file, "{}", transaction)?;
+        }
+
+        let config = EngineConfig {
+            ordering_policy: OrderingPolicy::ResolvesFirst,
+            ..Default::default()
+        };
+        let engine = Engine::new(HashMap::new(), config, Journal::default());
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert_account(client_account_map.get(&1).unwrap(), 2.0, 2.0, true);
+        assert_eq!(
+            report.rejections,
+            vec![Rejection {
+                line: 7,
+                file: Some(file_path_str.clone()),
+                error: EngineError::Ledger(LedgerError::InsufficientFunds(4.0, 2.0)),
+            }]
+        );
+
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+        let mut report = ExitReport::default();
+        read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+        assert_eq!(report.rejections[0].line, 4);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a resolve only moves ahead of the withdrawals for the same client that it directly
+    // follows
+    #[test]
+    fn test_order_resolves_first() {
+        let mut batch: Vec<(u64, Record)> = vec![
+            (1, Record::dispute(1, 1)),
+            (2, Record::withdrawal(1, 2, 1.0)),
+            (3, Record::withdrawal(2, 3, 1.0)),
+            (4, Record::withdrawal(1, 4, 1.0)),
+            (5, Record::resolve(1, 1)),
+            (6, Record::withdrawal(1, 5, 1.0)),
+        ];
+
+        order_resolves_first(&mut batch);
+
+        let lines: Vec<u64> = batch.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![1, 5, 2, 3, 4, 6]);
+    }
+
+    // Tests that a dispute whose amount doesn't match the disputed transaction is rejected when
+    // amounts are validated, and ignored otherwise
+    #[test]
+    fn test_process_dispute_transaction_validated_amount() {
+        let record = dummy_record(TransactionType::Dispute, Some(40.0));
+        let config = EngineConfig {
+            dispute_amount_policy: DisputeAmountPolicy::Validate,
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        account.deposit(50.0, 0);
+
+        let result = process_transaction_record(&record, &mut account, &config);
+        assert_eq!(result, Err(LedgerError::AmountMismatch(0, 40.0, 50.0)));
+        assert_relative_eq!(account.held_funds, 0.0);
+
+        process_transaction_record(&record, &mut account, &EngineConfig::default()).expect("ok");
+        assert_relative_eq!(account.held_funds, 50.0);
+    }
+
+    // Tests that a partial dispute only holds its amount, and that the chargeback which settles it
+    // only reverses the held amount
+    #[test]
+    fn test_process_partial_dispute_transaction() {
+        let config = EngineConfig {
+            dispute_amount_policy: DisputeAmountPolicy::Partial,
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        account.deposit(50.0, 0);
+
+        let too_much = dummy_record(TransactionType::Dispute, Some(60.0));
+        let result = process_transaction_record(&too_much, &mut account, &config);
+        assert_eq!(result, Err(LedgerError::InvalidDisputeAmount(0, 60.0)));
+
+        let dispute = dummy_record(TransactionType::Dispute, Some(20.0));
+        process_transaction_record(&dispute, &mut account, &config).expect("ok");
+        assert_relative_eq!(account.available_funds, 30.0);
+        assert_relative_eq!(account.held_funds, 20.0);
+
+        let chargeback = dummy_record(TransactionType::Chargeback, Some(50.0));
+        process_transaction_record(&chargeback, &mut account, &config).expect("ok");
+        assert_account(&account, 30.0, 30.0, true);
+        assert_relative_eq!(account.held_funds, 0.0);
+        assert!(account.lock_state.is_locked());
+    }
+
+    // Tests that deposits to an account under review are held until they're resolved, and that
+    // chargebacks leave an account flagged do not lock unlocked
+    #[test]
+    fn test_process_transactions_with_account_flags() {
+        let config = EngineConfig {
+            account_flags: BTreeMap::from([(
+                0,
+                AccountFlags {
+                    under_review: true,
+                    do_not_lock: true,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        let deposit = dummy_record(TransactionType::Deposit, Some(50.0));
+        process_transaction_record(&deposit, &mut account, &config).expect("ok");
+        assert_relative_eq!(account.available_funds, 0.0);
+        assert_relative_eq!(account.held_funds, 50.0);
+        assert_relative_eq!(account.total_funds, 50.0);
+
+        let chargeback = dummy_record(TransactionType::Chargeback, None);
+        process_transaction_record(&chargeback, &mut account, &config).expect("ok");
+        assert_account(&account, 0.0, 0.0, true);
+        assert!(!account.lock_state.is_locked());
+    }
+
+    // Tests that a multi-day file is split into business days at the cutover hour, with balances
+    // carried forward and each day's closing balances written
+    #[test]
+    fn test_read_transactions_from_csv_daily_cutover() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,timestamp")?;
+        for transaction in [
+            // 2024-03-01 01:00, before the cutover so still on 2024-02-29
+            "deposit,1,1,10.0,1709254800",
+            // 2024-03-01 03:00
+            "deposit,2,2,5.0,1709262000",
+            "withdrawal,1,3,20.0,1709265600",
+            // 2024-03-03 12:00, the day in between had no activity
+            "dispute,1,1,,1709467200",
+        ] {
+            writeln!(file, "{}", transaction)?;
+        }
+
+        let days_dir = dir.path().join("days");
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_daily_cutover(DailyCutover::new(&days_dir, 2));
+        let mut report = ExitReport::default();
+        let client_account_map =
+            read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        assert_account(client_account_map.get(&1).unwrap(), 0.0, 10.0, true);
+        assert_eq!(
+            report.days,
+            vec![
+                DaySummary {
+                    date: "2024-02-29".to_string(),
+                    records: 1,
+                    rejections: 0,
+                    accounts: 1,
+                    total_funds: 10.0,
+                    held_funds: 0.0,
+                    locked_accounts: 0,
+                },
+                DaySummary {
+                    date: "2024-03-01".to_string(),
+                    records: 2,
+                    rejections: 1,
+                    accounts: 2,
+                    total_funds: 15.0,
+                    held_funds: 0.0,
+                    locked_accounts: 0,
+                },
+                DaySummary {
+                    date: "2024-03-03".to_string(),
+                    records: 1,
+                    rejections: 0,
+                    accounts: 2,
+                    total_funds: 15.0,
+                    held_funds: 10.0,
+                    locked_accounts: 0,
+                },
+            ]
+        );
+
+        assert_eq!(
+            fs::read_to_string(days_dir.join("closing-2024-03-01.csv"))?,
+            "client,available,held,total,locked
+1,10.0,0.0,10.0,false
+2,5.0,0.0,5.0,false
+"
+        );
+        assert!(days_dir.join("closing-2024-03-03.csv").exists());
+        assert!(!days_dir.join("closing-2024-03-02.csv").exists());
+        assert_eq!(
+            fs::read_to_string(days_dir.join("days.csv"))?
+                .lines()
+                .count(),
+            4
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a run is exported as Parquet tables once the accounts are collected, with a
+    // balance snapshot of each business day
+    #[test]
+    fn test_read_transactions_from_csv_olap_export() -> Result<(), Error> {
+        let (file_path_str, dir, mut file) = create_temp_file("transactions.csv")?;
+        writeln!(file, "type,client,tx,amount,timestamp")?;
+        for transaction in [
+            "deposit,1,1,10.0,1709254800",
+            "withdrawal,1,2,20.0,1709262000",
+            "deposit,2,3,5.0,1709467200",
+        ] {
+            writeln!(file, "{}", transaction)?;
+        }
+
+        let olap_dir = dir.path().join("olap");
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default())
+            .with_olap_export(OlapExport::new(&olap_dir, 0, 0));
+        let mut report = ExitReport::default();
+        read_transactions_from_csv(&file_path_str, engine, &mut report).unwrap();
+
+        let rows = |file_name: &str| -> usize {
+            let file = File::open(olap_dir.join(file_name)).unwrap();
+            SerializedFileReader::new(file)
+                .unwrap()
+                .metadata()
+                .file_metadata()
+                .num_rows() as usize
+        };
+        assert_eq!(rows(OLAP_FILES[0]), 3);
+        assert_eq!(rows(OLAP_FILES[1]), 2);
+        assert_eq!(rows(OLAP_FILES[2]), 2);
+        // client 1 closed 2024-03-01, then both clients closed 2024-03-03
+        assert_eq!(rows(OLAP_FILES[3]), 3);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that locked accounts only permit the transactions their lock allows, when frozen
+    // accounts are allowed deposits, and that locks from the admin sidecar file are put on
+    // accounts when they're first processed
+    #[test]
+    fn test_process_transactions_with_account_locks() {
+        let config = EngineConfig {
+            frozen_account_policy: FrozenAccountPolicy::AllowDeposits,
+            account_flags: BTreeMap::from([(
+                2,
+                AccountFlags {
+                    lock: Some(LockState::AdminLock {
+                        rule: "case-4512".to_string(),
+                    }),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let mut engine = Engine::new(HashMap::new(), config, Journal::default());
+
+        engine.process(&Record::deposit(1, 1, 50.0)).unwrap();
+        engine.process(&Record::dispute(1, 1)).unwrap();
+        engine.process(&Record::chargeback(1, 1)).unwrap();
+        engine.process(&Record::deposit(1, 2, 20.0)).unwrap();
+        assert_eq!(
+            engine.process(&Record::withdrawal(1, 3, 5.0)),
+            Err(EngineError::Ledger(LedgerError::AccountLocked(
+                1,
+                TransactionType::Withdrawal,
+                "chargeback-lock"
+            )))
+        );
+        assert_eq!(
+            engine.process(&Record::deposit(2, 4, 5.0)),
+            Err(EngineError::Ledger(LedgerError::AccountLocked(
+                2,
+                TransactionType::Deposit,
+                "admin-lock"
+            )))
+        );
+
+        let accounts = engine.into_accounts().unwrap();
+        assert_eq!(accounts[&1].lock_state, LockState::ChargebackLock { tx: 1 });
+        assert_relative_eq!(accounts[&1].total_funds, 20.0);
+        assert_eq!(
+            accounts[&2].lock_state.trigger(),
+            Some("case-4512".to_string())
+        );
+
+        let mut output = vec![];
+        let (version, format) = (OutputVersion::V2, OutputFormat::Csv);
+        write_accounts(&mut output, &accounts, None, version, format, None).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("client,available,held,total,locked,lock_state,lock_trigger\n"));
+        assert!(output.contains("1,20.0,0.0,20.0,true,chargeback-lock,tx 1\n"));
+        assert!(output.contains("2,0.0,0.0,0.0,true,admin-lock,case-4512\n"));
+    }
+
+    // Tests that accounts frozen by a chargeback reject deposits too by default, while disputes of
+    // their other transactions still go through
+    #[test]
+    fn test_process_transactions_reject_all_when_frozen() {
+        assert_eq!(
+            EngineConfig::default().frozen_account_policy,
+            FrozenAccountPolicy::RejectAll
+        );
+        let mut engine = Engine::new(HashMap::new(), EngineConfig::default(), Journal::default());
+
+        engine.process(&Record::deposit(1, 1, 50.0)).unwrap();
+        engine.process(&Record::deposit(1, 2, 30.0)).unwrap();
+        engine.process(&Record::dispute(1, 1)).unwrap();
+        engine.process(&Record::chargeback(1, 1)).unwrap();
+        for record in [Record::deposit(1, 3, 20.0), Record::void(1, 2)] {
+            assert_eq!(
+                engine.process(&record),
+                Err(EngineError::Ledger(LedgerError::AccountLocked(
+                    1,
+                    record.transaction_type,
+                    "chargeback-lock"
+                )))
+            );
+        }
+        engine.process(&Record::dispute(1, 2)).unwrap();
+
+        let accounts = engine.into_accounts().unwrap();
+        assert_account(&accounts[&1], 0.0, 30.0, true);
+    }
+
+    // Tests that amounts with more decimal places than the currency's minor unit are rejected
+    #[test]
+    fn test_process_transaction_currency_precision() {
+        let config = EngineConfig {
+            currency: Some(Currency::resolve("JPY", &BTreeMap::new()).unwrap()),
+            ..Default::default()
+        };
+
+        let mut account = Account::default();
+        let too_precise = dummy_record(TransactionType::Deposit, Some(100.5));
+        let result = process_transaction_record(&too_precise, &mut account, &config);
+        assert_eq!(result, Err(LedgerError::InvalidPrecision(0, 100.5)));
+
+        let whole = dummy_record(TransactionType::Deposit, Some(100.0));
+        process_transaction_record(&whole, &mut account, &config).expect("ok");
+        assert_account(&account, 100.0, 100.0, true);
+    }
+
+    // Tests that accounts are written as a JSON array or JSONL, with the same precision as the csv
+    #[test]
+    fn test_write_accounts_json() {
+        let accounts = HashMap::from([(1, Account::with_balances(1.23456, 0.5))]);
+
+        let mut output = vec![];
+        let (version, format) = (OutputVersion::V1, OutputFormat::Json);
+        write_accounts(&mut output, &accounts, None, version, format, None).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "[{\"client\":1,\"available\":1.2346,\"held\":0.5,",
+                "\"total\":1.7346,\"locked\":false}]\n"
+            )
+        );
+
+        let accounts = HashMap::from([(1, Account::with_balances(0.00001, 0.0))]);
+        let mut output = vec![];
+        let (version, format) = (OutputVersion::V2, OutputFormat::Jsonl);
+        write_accounts(&mut output, &accounts, None, version, format, None).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with("{\"client\":1,\"available\":0.0,\"held\":0.0,"));
+    }
+
+    /// Streams the records received from a channel, as a network source would
+    struct ChannelStream(mpsc::Receiver<Record>);
+
+    impl Stream for ChannelStream {
+        type Item = Record;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Record>> {
+            self.0.poll_recv(cx)
+        }
+    }
+
+    // Tests that records from an async source are applied as they arrive, with the same result as
+    // applying them one at a time, and that rejections are reported by their position in the stream
+    #[test]
+    fn test_process_stream() {
+        let records = vec![
+            Record::deposit(1, 1, 10.0),
+            Record::withdrawal(1, 2, 15.0),
+            Record::deposit(2, 3, 5.0),
+            Record::dispute(1, 1),
+            Record::withdrawal(2, 4, 2.5),
+            Record::chargeback(1, 1),
+        ];
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (sender, receiver) = mpsc::channel(2);
+        let sent = records.clone();
+        runtime.spawn(async move {
+            for record in sent {
+                sender.send(record).await.unwrap();
+            }
+        });
+
+        let mut engine = Engine::default();
+        let mut report = ExitReport::default();
+        runtime
+            .block_on(engine.process_stream(ChannelStream(receiver), &mut report))
+            .unwrap();
+
+        assert_eq!(report.records, 6);
+        assert_eq!(report.rejections.len(), 1);
+        assert_eq!(report.rejections[0].line, 2);
+        assert!(matches!(
+            report.rejections[0].error,
+            EngineError::Ledger(LedgerError::InsufficientFunds(..))
+        ));
+
+        let mut expected = Engine::default();
+        for record in &records {
+            let _ = expected.process(record);
+        }
+        assert_eq!(
+            engine.into_accounts().unwrap(),
+            expected.into_accounts().unwrap()
+        );
+    }
+}
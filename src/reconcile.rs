@@ -0,0 +1,247 @@
+use crate::error::{SourceError, SourceResult};
+use crate::escheat::Escheatment;
+use crate::mapper::{Account, AccountRecord};
+use round::round;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// The money that moved through the accounts while a run applied its records. Every record (and
+/// admin operation) posts the movement of the balances of the accounts it changed, so the
+/// postings can be compared against the balances the run closes with.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Postings {
+    /// The total funds across every account before any record was applied
+    opening_total: f64,
+
+    /// The held funds across every account before any record was applied
+    opening_held: f64,
+
+    /// The funds that records added to accounts' totals
+    credits: f64,
+
+    /// The funds that records took out of accounts' totals
+    debits: f64,
+
+    /// The net change records made to accounts' held funds
+    held: f64,
+}
+
+impl Postings {
+    /// Starts posting against the accounts a run opens with (e.g. a loaded state)
+    pub fn open(accounts: &[AccountRecord]) -> Self {
+        Postings {
+            opening_total: accounts.iter().map(|account| account.total as f64).sum(),
+            opening_held: accounts.iter().map(|account| account.held as f64).sum(),
+            ..Default::default()
+        }
+    }
+
+    /// Posts the movement of an account's balances, none before when it was opened by the record
+    pub fn post(&mut self, before: Option<&AccountRecord>, after: &AccountRecord) {
+        let (total_before, held_before) = before.map_or((0.0, 0.0), |before| {
+            (before.total as f64, before.held as f64)
+        });
+
+        let moved = after.total as f64 - total_before;
+        if moved > 0.0 {
+            self.credits += moved;
+        } else {
+            self.debits -= moved;
+        }
+        self.held += after.held as f64 - held_before;
+    }
+
+    /// Posts the held funds that were swept to the holding account once the records were applied.
+    /// They're moved between accounts, so only the held funds change.
+    pub fn escheat(&mut self, escheatments: &[Escheatment]) {
+        self.held -= escheatments
+            .iter()
+            .map(|escheatment| escheatment.amount as f64)
+            .sum::<f64>();
+    }
+}
+
+/// A figure the postings expected that the closing balances don't match
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Discrepancy {
+    /// Which of the closing balances it is, e.g. total
+    pub balance: &'static str,
+
+    /// What the postings say it should be
+    pub expected: f64,
+
+    /// What the accounts closed with
+    pub actual: f64,
+}
+
+/// Proves that a run didn't create or destroy money, by comparing what its records posted to the
+/// balances its accounts closed with. Balances are compared at four decimal places, so float
+/// error alone isn't a discrepancy.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Reconciliation {
+    /// The funds that records added to accounts
+    pub total_credits: f64,
+
+    /// The funds that records took out of accounts
+    pub total_debits: f64,
+
+    /// The funds that should be held, from the opening held funds and what records held or
+    /// released
+    pub total_held: f64,
+
+    /// The funds the accounts should hold in total, the opening total plus the credits less the
+    /// debits
+    pub net_position: f64,
+
+    /// The available funds across every account at the close of the run
+    pub closing_available: f64,
+
+    /// The held funds across every account at the close of the run
+    pub closing_held: f64,
+
+    /// The total funds across every account at the close of the run
+    pub closing_total: f64,
+
+    /// The balances that don't match, empty when the run reconciles
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl Reconciliation {
+    /// Compares the postings with the balances of the accounts the run closed with
+    pub fn new(postings: &Postings, accounts: &HashMap<u16, Account>) -> Self {
+        let sum = |balance: fn(&Account) -> f32| {
+            round(
+                accounts
+                    .values()
+                    .map(|account| balance(account) as f64)
+                    .sum(),
+                4,
+            )
+        };
+        let closing_available = sum(|account| account.available_funds);
+        let closing_held = sum(|account| account.held_funds);
+        let closing_total = sum(|account| account.total_funds);
+
+        let total_held = round(postings.opening_held + postings.held, 4);
+        let net_position = round(
+            postings.opening_total + postings.credits - postings.debits,
+            4,
+        );
+
+        // the available funds are whatever of the total isn't held
+        let expected = [
+            ("total", net_position, closing_total),
+            ("held", total_held, closing_held),
+            (
+                "available",
+                round(closing_total - closing_held, 4),
+                closing_available,
+            ),
+        ];
+        let discrepancies = expected
+            .into_iter()
+            .filter(|(_, expected, actual)| round(expected - actual, 4) != 0.0)
+            .map(|(balance, expected, actual)| Discrepancy {
+                balance,
+                expected,
+                actual,
+            })
+            .collect();
+
+        Reconciliation {
+            total_credits: round(postings.credits, 4),
+            total_debits: round(postings.debits, 4),
+            total_held,
+            net_position,
+            closing_available,
+            closing_held,
+            closing_total,
+            discrepancies,
+        }
+    }
+
+    /// Whether the closing balances match the postings
+    pub fn is_balanced(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Writes a reconciliation as JSON
+pub fn write_reconciliation(
+    mut output: impl Write,
+    reconciliation: &Reconciliation,
+) -> SourceResult<()> {
+    serde_json::to_writer_pretty(&mut output, reconciliation)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+    writeln!(output).map_err(|err| SourceError::Io(err.to_string()))?;
+
+    output
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::{Account, AccountRecord};
+    use crate::reconcile::{Discrepancy, Postings, Reconciliation};
+    use std::collections::HashMap;
+
+    // Tests that the postings reconcile with the balances they were posted from, and that money
+    // that appears outside of a posting, or held funds that don't add up, are discrepancies
+    #[test]
+    fn test_reconciliation() {
+        let mut opening = Account::default();
+        opening.deposit(5.0, 1);
+        let mut postings = Postings::open(&[AccountRecord::new(1, &opening)]);
+
+        let mut account = opening.clone();
+        account.deposit(10.0, 2);
+        postings.post(
+            Some(&AccountRecord::new(1, &opening)),
+            &AccountRecord::new(1, &account),
+        );
+        let before = AccountRecord::new(1, &account);
+        account.withdraw(3.0, 3).unwrap();
+        account.dispute(2);
+        postings.post(Some(&before), &AccountRecord::new(1, &account));
+
+        let mut accounts = HashMap::from([(1, account.clone())]);
+        let reconciliation = Reconciliation::new(&postings, &accounts);
+        assert!(reconciliation.is_balanced());
+        assert_eq!(reconciliation.total_credits, 10.0);
+        assert_eq!(reconciliation.total_debits, 3.0);
+        assert_eq!(reconciliation.total_held, 10.0);
+        assert_eq!(reconciliation.net_position, 12.0);
+
+        // funds that were never posted
+        let unposted = Account {
+            available_funds: 1.5,
+            total_funds: 1.5,
+            ..Default::default()
+        };
+        accounts.insert(2, unposted);
+        account.held_funds += 1.0;
+        accounts.insert(1, account);
+        assert_eq!(
+            Reconciliation::new(&postings, &accounts).discrepancies,
+            vec![
+                Discrepancy {
+                    balance: "total",
+                    expected: 12.0,
+                    actual: 13.5,
+                },
+                Discrepancy {
+                    balance: "held",
+                    expected: 10.0,
+                    actual: 11.0,
+                },
+                Discrepancy {
+                    balance: "available",
+                    expected: 2.5,
+                    actual: 3.5,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use crate::alerts::{Alert, AlertKind};
+use crate::error::{CliError, CliResult, EngineError, LedgerError, SourceError};
+use crate::losses::LossLedger;
+use crate::quarantine::{QuarantineChange, QuarantineEvent};
+use crate::stats::RunStats;
+use std::str::FromStr;
+
+/// What a masked field is replaced with
+const MASK: &str = "***";
+
+/// How redacted amounts are rendered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountRedaction {
+    /// Amounts are replaced by a mask
+    Mask,
+
+    /// Amounts are replaced by their order of magnitude, e.g. 250 is shown as 100..1000
+    Bucket,
+}
+
+/// The fields that are redacted from the run's logs, warnings and rejections report, so the engine
+/// can run on shared infrastructure. The financial outputs (balances, state, journal) stay exact.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Redaction {
+    /// How amounts are redacted, they're shown exactly when none
+    pub amounts: Option<AmountRedaction>,
+
+    /// Whether free text read from the input is masked, i.e. reason codes, operators, idempotency
+    /// keys and the values of malformed fields
+    pub descriptions: bool,
+}
+
+impl FromStr for Redaction {
+    type Err = CliError;
+
+    /// Parses a comma separated list of the fields to redact, `amounts` (or `amounts:bucket`) and
+    /// `descriptions`
+    fn from_str(fields: &str) -> CliResult<Self> {
+        let mut redaction = Redaction::default();
+        for field in fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+        {
+            match field {
+                "amounts" | "amounts:mask" => redaction.amounts = Some(AmountRedaction::Mask),
+                "amounts:bucket" => redaction.amounts = Some(AmountRedaction::Bucket),
+                "descriptions" => redaction.descriptions = true,
+                _ => {
+                    return Err(CliError::InvalidValue(
+                        "--redact".to_string(),
+                        field.to_string(),
+                    ))
+                }
+            }
+        }
+
+        Ok(redaction)
+    }
+}
+
+impl Redaction {
+    /// Renders an amount
+    pub fn amount(&self, amount: f32) -> String {
+        match self.amounts {
+            None => amount.to_string(),
+            Some(AmountRedaction::Mask) => MASK.to_string(),
+            Some(AmountRedaction::Bucket) => bucket(amount as f64),
+        }
+    }
+
+    /// Renders a piece of free text
+    pub fn text(&self, text: &str) -> String {
+        if self.descriptions {
+            MASK.to_string()
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Renders an error, the same as its message when nothing is redacted
+    pub fn error(&self, err: &EngineError) -> String {
+        let amount = |amount: &f32| self.amount(*amount);
+
+        match err {
+            EngineError::Ledger(LedgerError::InsufficientFunds(requested, available)) => format!(
+                "Failed withdrawal, amount: {} is greater than available funds: {}",
+                amount(requested),
+                amount(available)
+            ),
+            EngineError::Ledger(LedgerError::AmountMismatch(tx, row, original)) => format!(
+                "Transaction {} has an amount of {}, but the row referencing it has {}",
+                tx,
+                amount(original),
+                amount(row)
+            ),
+            EngineError::Ledger(LedgerError::InvalidDisputeAmount(tx, disputed)) => format!(
+                "Invalid amount {} for a partial dispute of transaction {}",
+                amount(disputed),
+                tx
+            ),
+            EngineError::Ledger(LedgerError::InvalidPrecision(tx, precise)) => format!(
+                "Transaction {} has an amount of {}, which is too precise for the currency",
+                tx,
+                amount(precise)
+            ),
+            EngineError::Ledger(LedgerError::DuplicateKey(key, tx)) => format!(
+                "Transaction {} reuses idempotency key {}, which has already been applied",
+                tx,
+                self.text(key)
+            ),
+            EngineError::Ledger(LedgerError::HeldFundsUnderflow(tx, released, held)) => format!(
+                "Transaction {} would release {} of held funds, but the account only holds {}",
+                tx,
+                amount(released),
+                amount(held)
+            ),
+            EngineError::Ledger(LedgerError::OverLimit(tx, requested, limit)) => format!(
+                "Failed withdrawal {}, amount: {} is over the account's limit of {}",
+                tx,
+                amount(requested),
+                amount(limit)
+            ),
+            EngineError::Source(SourceError::Parse { line, message }) => {
+                format!(
+                    "Malformed record on line {}: {}",
+                    line,
+                    self.parse_message(message)
+                )
+            }
+            _ => err.to_string(),
+        }
+    }
+
+    /// Renders an alert, percentages aren't amounts so they're always shown
+    pub fn alert(&self, alert: &Alert) -> String {
+        match alert.kind {
+            AlertKind::TotalChange => alert.to_string(),
+            AlertKind::AvailableBelow => format!(
+                "client {} available funds {} are below {}",
+                alert.client,
+                self.amount(alert.value),
+                self.amount(alert.threshold)
+            ),
+            AlertKind::HeldAbove => format!(
+                "client {} held funds {} are above {}",
+                alert.client,
+                self.amount(alert.value),
+                self.amount(alert.threshold)
+            ),
+        }
+    }
+
+    /// Renders a quarantine event
+    pub fn quarantine(&self, event: &QuarantineEvent) -> String {
+        match &event.change {
+            QuarantineChange::Released { operator, deposits } => format!(
+                "client {} was released from quarantine by {}, releasing {} held deposits",
+                event.client,
+                self.text(operator),
+                deposits
+            ),
+            QuarantineChange::Entered { .. } => event.to_string(),
+        }
+    }
+
+    /// Renders the summary of the chargeback losses
+    pub fn losses(&self, losses: &LossLedger) -> String {
+        format!(
+            "{} reversed by {} chargebacks across {} clients",
+            self.amount(losses.total()),
+            losses.chargebacks(),
+            losses.clients()
+        )
+    }
+
+    /// Renders the aggregate figures of a run
+    pub fn stats(&self, stats: &RunStats) -> String {
+        let transactions: Vec<String> = stats
+            .transactions
+            .iter()
+            .map(|(name, count)| format!("{} {}", count, name))
+            .collect();
+
+        format!(
+            "{} rows read ({}), {} rejected, {} deposited, {} withdrawn, {} held, {} locked \
+             accounts, {:.0} tx/sec",
+            stats.rows_read,
+            transactions.join(", "),
+            stats.rows_rejected,
+            self.amount(stats.deposited as f32),
+            self.amount(stats.withdrawn as f32),
+            self.amount(stats.held as f32),
+            stats.locked_accounts,
+            stats.throughput
+        )
+    }
+
+    /// Masks the value a malformed row's message quotes, when the field it was read from is
+    /// redacted
+    fn parse_message(&self, message: &str) -> String {
+        if let Some((value, field)) = message
+            .strip_prefix("invalid value `")
+            .and_then(|rest| rest.strip_suffix('`'))
+            .and_then(|rest| rest.split_once("` for field `"))
+        {
+            let value = match field {
+                "amount" if self.amounts.is_some() => MASK.to_string(),
+                "amount" => value.to_string(),
+                _ => self.text(value),
+            };
+            return format!("invalid value `{}` for field `{}`", value, field);
+        }
+
+        match message.strip_prefix("unknown transaction type `") {
+            Some(_) if self.descriptions => format!("unknown transaction type `{}`", MASK),
+            _ => message.to_string(),
+        }
+    }
+}
+
+/// The order of magnitude an amount is in, as a range, e.g. 100..1000 or -10..-1
+fn bucket(amount: f64) -> String {
+    let magnitude = amount.abs();
+    if magnitude == 0.0 {
+        return "0".to_string();
+    }
+    if magnitude < 1.0 {
+        return if amount < 0.0 { "-1..0" } else { "0..1" }.to_string();
+    }
+
+    let mut lower = 1.0;
+    while lower * 10.0 <= magnitude {
+        lower *= 10.0;
+    }
+
+    if amount < 0.0 {
+        format!("-{}..-{}", lower * 10.0, lower)
+    } else {
+        format!("{}..{}", lower, lower * 10.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alerts::{Alert, AlertKind};
+    use crate::error::{EngineError, LedgerError, SourceError};
+    use crate::mapper::TransactionType;
+    use crate::redact::{AmountRedaction, Redaction};
+
+    // Tests that nothing is changed when no field is redacted
+    #[test]
+    fn test_redaction_unset() {
+        let redaction = Redaction::default();
+        let errors: Vec<EngineError> = vec![
+            LedgerError::InsufficientFunds(10.5, 2.0).into(),
+            LedgerError::AmountMismatch(1, 2.0, 3.0).into(),
+            LedgerError::InvalidDisputeAmount(1, 0.1).into(),
+            LedgerError::InvalidPrecision(1, 0.001).into(),
+            LedgerError::DuplicateKey("key-1".to_string(), 4).into(),
+            LedgerError::HeldFundsUnderflow(1, 5.0, 4.0).into(),
+            LedgerError::OverLimit(1, 500.0, 100.0).into(),
+            LedgerError::AdminOpsDisabled(TransactionType::Adjustment).into(),
+            SourceError::Parse {
+                line: 3,
+                message: "invalid value `1.2.3` for field `amount`".to_string(),
+            }
+            .into(),
+        ];
+
+        for err in &errors {
+            assert_eq!(redaction.error(err), err.to_string());
+        }
+    }
+
+    // Tests that amounts are masked or bucketed, and descriptions masked
+    #[test]
+    fn test_redaction() {
+        let redaction: Redaction = "amounts:bucket, descriptions".parse().unwrap();
+        assert_eq!(redaction.amounts, Some(AmountRedaction::Bucket));
+        assert!(redaction.descriptions);
+        assert!("amounts,notes".parse::<Redaction>().is_err());
+
+        assert_eq!(redaction.amount(250.0), "100..1000");
+        assert_eq!(redaction.amount(10.0), "10..100");
+        assert_eq!(redaction.amount(-3.5), "-10..-1");
+        assert_eq!(redaction.amount(0.25), "0..1");
+        assert_eq!(redaction.amount(0.0), "0");
+        assert_eq!(
+            redaction.error(&LedgerError::InsufficientFunds(250.0, 12.0).into()),
+            "Failed withdrawal, amount: 100..1000 is greater than available funds: 10..100"
+        );
+        assert_eq!(
+            redaction.error(&LedgerError::DuplicateKey("order-77".to_string(), 4).into()),
+            "Transaction 4 reuses idempotency key ***, which has already been applied"
+        );
+
+        let redaction: Redaction = "amounts".parse().unwrap();
+        assert_eq!(
+            redaction.error(&LedgerError::OverLimit(7, 500.0, 100.0).into()),
+            "Failed withdrawal 7, amount: *** is over the account's limit of ***"
+        );
+        let malformed = |message: &str| -> EngineError {
+            SourceError::Parse {
+                line: 3,
+                message: message.to_string(),
+            }
+            .into()
+        };
+        assert_eq!(
+            redaction.error(&malformed("invalid value `1.2.3` for field `amount`")),
+            "Malformed record on line 3: invalid value `***` for field `amount`"
+        );
+        // only amounts are redacted, so other fields are shown
+        assert_eq!(
+            redaction.error(&malformed("invalid value `x` for field `client`")),
+            "Malformed record on line 3: invalid value `x` for field `client`"
+        );
+
+        let alert = Alert {
+            client: 2,
+            kind: AlertKind::HeldAbove,
+            value: 120.0,
+            threshold: 100.0,
+        };
+        assert_eq!(
+            redaction.alert(&alert),
+            "client 2 held funds *** are above ***"
+        );
+        let alert = Alert {
+            kind: AlertKind::TotalChange,
+            ..alert
+        };
+        assert_eq!(redaction.alert(&alert), alert.to_string());
+    }
+}
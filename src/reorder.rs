@@ -0,0 +1,283 @@
+use crate::mapper::{Record, TransactionType};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// The number of records between sweeps of the rows that have expired, so rows referencing
+/// transactions that never arrive don't build up
+const SWEEP_INTERVAL: u64 = 1_000;
+
+/// How long a row can wait for the transaction it references
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferWindow {
+    /// A number of records
+    Records(u64),
+
+    /// A number of seconds, measured by the records' timestamps. Rows without a timestamp wait
+    /// for the rest of the run.
+    Seconds(u64),
+}
+
+impl FromStr for BufferWindow {
+    type Err = ParseIntError;
+
+    /// Parses a number of records (e.g. `1000`), or a number of seconds (e.g. `300s`)
+    fn from_str(window: &str) -> Result<Self, ParseIntError> {
+        match window.strip_suffix('s') {
+            Some(seconds) => seconds.parse().map(BufferWindow::Seconds),
+            None => window.parse().map(BufferWindow::Records),
+        }
+    }
+}
+
+/// Dispute, resolve, chargeback, void and refund rows that arrived before the deposit or withdrawal they
+/// reference, which streamed inputs don't always deliver in order. They're parked until the
+/// transaction arrives, then applied in the order they were received, rather than being dropped.
+#[derive(Debug)]
+pub struct DisputeBuffer {
+    /// How long a row waits for its transaction
+    window: BufferWindow,
+
+    /// The number of records that have been applied
+    clock: u64,
+
+    /// The latest timestamp of the records that have been applied
+    latest: Option<u64>,
+
+    /// The parked rows, keyed by the transaction they reference, in the order they were received
+    parked: HashMap<u32, Vec<ParkedRow>>,
+
+    /// What happened to the rows that are no longer parked
+    outcomes: Vec<BufferOutcome>,
+}
+
+/// A row waiting for the transaction it references
+#[derive(Debug)]
+struct ParkedRow {
+    /// The row as it was read
+    record: Record,
+
+    /// When the row was parked, on the buffer's clock
+    parked_at: u64,
+}
+
+/// What eventually happened to a row that was parked
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BufferOutcome {
+    /// The client of the row
+    pub client: u16,
+
+    /// The transaction the row referenced
+    pub tx: u32,
+
+    /// The type of the row, a dispute, resolve, chargeback, void or refund
+    pub transaction_type: TransactionType,
+
+    /// Whether the row was applied once the transaction arrived
+    pub applied: bool,
+}
+
+impl fmt::Display for BufferOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.applied {
+            "was applied once the transaction arrived"
+        } else {
+            "expired before the transaction arrived"
+        };
+        write!(
+            f,
+            "{} of tx {} for client {} {}",
+            self.transaction_type.name(),
+            self.tx,
+            self.client,
+            status
+        )
+    }
+}
+
+impl DisputeBuffer {
+    /// Creates a buffer that parks rows for the window
+    pub fn new(window: BufferWindow) -> Self {
+        DisputeBuffer {
+            window,
+            clock: 0,
+            latest: None,
+            parked: HashMap::new(),
+            outcomes: vec![],
+        }
+    }
+
+    /// Whether the row should be parked, it references a transaction that hasn't been seen
+    pub fn should_park(&self, record: &Record, is_known: impl Fn(u32) -> bool) -> bool {
+        record.transaction_type.references_transaction() && !is_known(record.transaction_id)
+    }
+
+    /// Parks a row until the transaction it references arrives
+    pub fn park(&mut self, record: &Record) {
+        self.parked
+            .entry(record.transaction_id)
+            .or_default()
+            .push(ParkedRow {
+                record: record.clone(),
+                parked_at: self.clock,
+            });
+    }
+
+    /// Advances the clocks once a record has been applied, occasionally expiring the rows that
+    /// have waited for longer than the window
+    pub fn tick(&mut self, timestamp: Option<u64>) {
+        self.clock += 1;
+        self.latest = self.latest.max(timestamp);
+
+        if self.clock.is_multiple_of(SWEEP_INTERVAL) {
+            self.expire();
+        }
+    }
+
+    /// Takes the rows waiting for the transaction once it's arrived, in the order they were
+    /// received. Rows that have waited for longer than the window expire instead.
+    pub fn release(&mut self, transaction_id: u32) -> Vec<Record> {
+        let Some(rows) = self.parked.remove(&transaction_id) else {
+            return vec![];
+        };
+
+        let (expired, released): (Vec<ParkedRow>, Vec<ParkedRow>) =
+            rows.into_iter().partition(|row| self.has_expired(row));
+        for row in &expired {
+            self.outcomes.push(outcome(&row.record, false));
+        }
+
+        released.into_iter().map(|row| row.record).collect()
+    }
+
+    /// Records whether a released row was applied
+    pub fn settle(&mut self, record: &Record, applied: bool) {
+        self.outcomes.push(outcome(record, applied));
+    }
+
+    /// Expires the rows that are still parked, returning what happened to every row that was
+    /// parked, ordered by client then transaction
+    pub fn finish(mut self) -> Vec<BufferOutcome> {
+        for row in self.parked.values().flatten() {
+            self.outcomes.push(outcome(&row.record, false));
+        }
+
+        self.outcomes
+            .sort_by_key(|outcome| (outcome.client, outcome.tx));
+        self.outcomes
+    }
+
+    /// Expires the rows that have waited for longer than the window
+    fn expire(&mut self) {
+        let parked = std::mem::take(&mut self.parked);
+        let mut still_parked: HashMap<u32, Vec<ParkedRow>> = HashMap::new();
+        for (transaction_id, rows) in parked {
+            for row in rows {
+                if self.has_expired(&row) {
+                    self.outcomes.push(outcome(&row.record, false));
+                } else {
+                    still_parked.entry(transaction_id).or_default().push(row);
+                }
+            }
+        }
+
+        self.parked = still_parked;
+    }
+
+    /// Whether a row has waited for longer than the window
+    fn has_expired(&self, row: &ParkedRow) -> bool {
+        match self.window {
+            BufferWindow::Records(records) => self.clock - row.parked_at > records,
+            BufferWindow::Seconds(seconds) => match (self.latest, row.record.timestamp) {
+                (Some(latest), Some(timestamp)) => latest.saturating_sub(timestamp) > seconds,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Describes what happened to a parked row
+fn outcome(record: &Record, applied: bool) -> BufferOutcome {
+    BufferOutcome {
+        client: record.client_id,
+        tx: record.transaction_id,
+        transaction_type: record.transaction_type,
+        applied,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::{Record, TransactionType};
+    use crate::reorder::{BufferOutcome, BufferWindow, DisputeBuffer};
+
+    // Tests that rows are parked until their transaction arrives, and expire after the window
+    #[test]
+    fn test_dispute_buffer() {
+        assert_eq!("1000".parse(), Ok(BufferWindow::Records(1000)));
+        assert_eq!("300s".parse(), Ok(BufferWindow::Seconds(300)));
+        assert!("5m".parse::<BufferWindow>().is_err());
+
+        let mut buffer = DisputeBuffer::new(BufferWindow::Records(2));
+        let known = |transaction_id| transaction_id == 1;
+        assert!(!buffer.should_park(&Record::dispute(1, 1), known));
+        assert!(!buffer.should_park(&Record::deposit(1, 2, 5.0), known));
+        assert!(buffer.should_park(&Record::dispute(1, 2), known));
+
+        buffer.park(&Record::dispute(1, 2));
+        buffer.tick(None);
+        buffer.park(&Record::dispute(1, 3));
+        buffer.tick(None);
+        assert_eq!(buffer.release(2), vec![Record::dispute(1, 2)]);
+        assert!(buffer.release(2).is_empty());
+        buffer.settle(&Record::dispute(1, 2), true);
+
+        // the dispute of tx 3 has waited for 3 records by the time it arrives
+        buffer.tick(None);
+        buffer.tick(None);
+        assert!(buffer.release(3).is_empty());
+
+        buffer.park(&Record::chargeback(2, 4));
+        assert_eq!(
+            buffer.finish(),
+            vec![
+                BufferOutcome {
+                    client: 1,
+                    tx: 2,
+                    transaction_type: TransactionType::Dispute,
+                    applied: true,
+                },
+                BufferOutcome {
+                    client: 1,
+                    tx: 3,
+                    transaction_type: TransactionType::Dispute,
+                    applied: false,
+                },
+                BufferOutcome {
+                    client: 2,
+                    tx: 4,
+                    transaction_type: TransactionType::Chargeback,
+                    applied: false,
+                },
+            ]
+        );
+    }
+
+    // Tests that a time window is measured by the records' timestamps
+    #[test]
+    fn test_dispute_buffer_seconds() {
+        let mut buffer = DisputeBuffer::new(BufferWindow::Seconds(60));
+        let dispute = |transaction_id, timestamp| Record {
+            timestamp: Some(timestamp),
+            ..Record::dispute(1, transaction_id)
+        };
+
+        buffer.park(&dispute(1, 1_000));
+        buffer.park(&dispute(2, 1_030));
+        buffer.tick(Some(1_080));
+        assert!(buffer.release(1).is_empty());
+        assert_eq!(buffer.release(2), vec![dispute(2, 1_030)]);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::error::{EngineError, EngineResult, SourceError, SourceResult};
+use crate::mapper::{Account, Record, TransactionType};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// What happened to an input record
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus {
+    /// The record was applied to its account
+    Accepted,
+
+    /// The record couldn't be applied, and its account is unchanged
+    Rejected,
+
+    /// The record was applied, but it had no effect on its account
+    Ignored,
+}
+
+/// Why a record that was applied had no effect on its account
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub enum IgnoreReason {
+    /// A deposit, withdrawal or adjustment without an amount
+    MissingAmount,
+
+    /// A row referencing a transaction that isn't in its client's account
+    UnknownTransaction,
+
+    /// A row that left its account as it was, e.g. a dispute of a transaction already disputed
+    NoChange,
+}
+
+/// The fate of a single input record, along with the line it was read from
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TxResult {
+    /// The line the record was read from
+    pub line: u64,
+
+    /// The client of the record, none when it couldn't be parsed
+    pub client: Option<u16>,
+
+    /// The transaction ID of the record, none when it couldn't be parsed
+    pub tx: Option<u32>,
+
+    /// The type of the record, none when it couldn't be parsed
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+
+    /// When the record occurred, as a unix timestamp, when it had one
+    pub timestamp: Option<u64>,
+
+    /// Whether the record was accepted, rejected or ignored
+    pub status: TxStatus,
+
+    /// The code of the error the record was rejected with
+    pub code: Option<i32>,
+
+    /// Why the record was rejected or ignored, e.g. InsufficientFunds
+    pub reason: Option<String>,
+}
+
+impl TxResult {
+    /// The result of a record that was applied, which was ignored when there's a reason
+    pub fn applied(line: u64, record: &Record, ignored: Option<IgnoreReason>) -> Self {
+        TxResult {
+            line,
+            client: Some(record.client_id),
+            tx: Some(record.transaction_id),
+            transaction_type: Some(record.transaction_type),
+            timestamp: record.timestamp,
+            status: match ignored {
+                Some(_) => TxStatus::Ignored,
+                None => TxStatus::Accepted,
+            },
+            code: None,
+            reason: ignored.map(|reason| format!("{:?}", reason)),
+        }
+    }
+
+    /// The result of a record that was rejected with the error, which may not have been parsed
+    pub fn rejected(line: u64, record: Option<&Record>, error: &EngineError) -> Self {
+        TxResult {
+            line,
+            client: record.map(|record| record.client_id),
+            tx: record.map(|record| record.transaction_id),
+            transaction_type: record.map(|record| record.transaction_type),
+            timestamp: record.and_then(|record| record.timestamp),
+            status: TxStatus::Rejected,
+            code: Some(error.code()),
+            reason: Some(error.variant_name()),
+        }
+    }
+}
+
+/// Writes the result of every input record as JSON lines, in the order they were applied
+pub struct TxResults {
+    /// Where the results are written to
+    output: BufWriter<File>,
+}
+
+impl TxResults {
+    /// Creates the file the results are written to
+    pub fn create(file_path: &Path) -> SourceResult<Self> {
+        let file = File::create(file_path)
+            .map_err(|err| SourceError::Io(format!("{}: {}", file_path.display(), err)))?;
+
+        Ok(TxResults {
+            output: BufWriter::new(file),
+        })
+    }
+
+    /// Writes a record's result
+    pub fn write(&mut self, result: &TxResult) -> EngineResult<()> {
+        serde_json::to_writer(&mut self.output, result)
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+        writeln!(self.output).map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Flushes the results once every record has been applied
+    pub fn finish(mut self) -> EngineResult<()> {
+        self.output
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// The parts of an account a record can change, taken before and after it's applied to tell
+/// whether it was ignored
+#[derive(Debug, PartialEq)]
+pub(crate) struct Footprint {
+    /// The available, held and total funds
+    balances: [f32; 3],
+
+    /// The name of the account's lock state
+    lock_state: &'static str,
+
+    /// The state of the transaction the record creates or references, when it's in the account
+    transaction: Option<TransactionType>,
+}
+
+impl Footprint {
+    /// Takes the parts of the account the record can change
+    pub(crate) fn of(record: &Record, account: &Account) -> Self {
+        Footprint {
+            balances: [
+                account.available_funds,
+                account.held_funds,
+                account.total_funds,
+            ],
+            lock_state: account.lock_state.name(),
+            transaction: account
+                .successful_transactions
+                .get(&record.transaction_id)
+                .map(|transaction| transaction.current_state),
+        }
+    }
+
+    /// Why the record had no effect once it's been applied to the account, none when it did
+    pub(crate) fn ignored(&self, record: &Record, account: &Account) -> Option<IgnoreReason> {
+        if *self != Footprint::of(record, account) {
+            return None;
+        }
+
+        if !record.transaction_type.references_transaction() && record.amount.is_none() {
+            Some(IgnoreReason::MissingAmount)
+        } else if self.transaction.is_none() && record.transaction_type.references_transaction() {
+            Some(IgnoreReason::UnknownTransaction)
+        } else {
+            Some(IgnoreReason::NoChange)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{EngineError, LedgerError};
+    use crate::mapper::{Account, Record, TransactionType};
+    use crate::results::{Footprint, IgnoreReason, TxResult, TxStatus};
+
+    // Tests that records with no effect on their account are told apart from ones that had one
+    #[test]
+    fn test_footprint_ignored() {
+        let mut account = Account::default();
+        let deposit = Record::deposit(1, 1, 5.0);
+        let before = Footprint::of(&deposit, &account);
+        account.deposit(5.0, 1);
+        assert_eq!(before.ignored(&deposit, &account), None);
+
+        let missing_amount = Record {
+            amount: None,
+            ..Record::deposit(1, 2, 0.0)
+        };
+        let before = Footprint::of(&missing_amount, &account);
+        assert_eq!(
+            before.ignored(&missing_amount, &account),
+            Some(IgnoreReason::MissingAmount)
+        );
+
+        let unknown = Record::dispute(1, 3);
+        let before = Footprint::of(&unknown, &account);
+        assert_eq!(
+            before.ignored(&unknown, &account),
+            Some(IgnoreReason::UnknownTransaction)
+        );
+
+        account.dispute(1);
+        let dispute = Record::dispute(1, 1);
+        let before = Footprint::of(&dispute, &account);
+        account.dispute(1);
+        assert_eq!(
+            before.ignored(&dispute, &account),
+            Some(IgnoreReason::NoChange)
+        );
+    }
+
+    // Tests that results are written with the reason a record was rejected or ignored
+    #[test]
+    fn test_tx_result() {
+        let rejected = TxResult::rejected(
+            4,
+            Some(&Record::withdrawal(1, 2, 5.0)),
+            &EngineError::Ledger(LedgerError::InsufficientFunds(5.0, 1.0)),
+        );
+        assert_eq!(rejected.status, TxStatus::Rejected);
+        assert_eq!(
+            serde_json::to_string(&rejected).unwrap(),
+            concat!(
+                r#"{"line":4,"client":1,"tx":2,"type":"withdrawal","timestamp":null,"#,
+                r#""status":"rejected","code":30,"reason":"InsufficientFunds"}"#
+            )
+        );
+
+        let unknown = Some(IgnoreReason::UnknownTransaction);
+        let ignored = TxResult::applied(5, &Record::dispute(1, 3), unknown);
+        assert_eq!(ignored.transaction_type, Some(TransactionType::Dispute));
+        assert_eq!(
+            serde_json::to_string(&ignored).unwrap(),
+            concat!(
+                r#"{"line":5,"client":1,"tx":3,"type":"dispute","timestamp":null,"#,
+                r#""status":"ignored","code":null,"reason":"UnknownTransaction"}"#
+            )
+        );
+    }
+}
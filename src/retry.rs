@@ -0,0 +1,170 @@
+use crate::audit::{AuditBalances, AuditEvent, AuditLog};
+use crate::config::EngineConfig;
+use crate::engine::process_transaction_record;
+use crate::error::SourceResult;
+use crate::journal::{AccountEvent, Journal};
+use crate::mapper::{Account, Record};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// Withdrawals that were rejected for insufficient funds, parked so they can be re-presented once
+/// their client has made another deposit. This matches how some processors retry debits.
+#[derive(Debug, Default)]
+pub struct RetryQueue {
+    /// The number of records a withdrawal stays parked for, it's parked for the rest of the run
+    /// when there isn't one
+    window: Option<u64>,
+
+    /// The number of records that have been applied
+    clock: u64,
+
+    /// The parked withdrawals of each client, in the order they were rejected
+    parked: HashMap<u16, VecDeque<ParkedWithdrawal>>,
+
+    /// What happened to the withdrawals that are no longer parked
+    outcomes: Vec<RetryOutcome>,
+}
+
+/// A withdrawal waiting to be retried
+#[derive(Debug)]
+struct ParkedWithdrawal {
+    /// The withdrawal as it was read
+    record: Record,
+
+    /// When the withdrawal was parked, on the queue's clock
+    parked_at: u64,
+
+    /// The number of times the withdrawal has been presented, including the first
+    attempts: u32,
+}
+
+/// What eventually happened to a withdrawal that was parked
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RetryOutcome {
+    /// The client the withdrawal was for
+    pub client: u16,
+
+    /// The withdrawal's transaction ID
+    pub tx: u32,
+
+    /// The number of times the withdrawal was presented, including the first
+    pub attempts: u32,
+
+    /// Whether the withdrawal was eventually applied
+    pub succeeded: bool,
+}
+
+impl fmt::Display for RetryOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.succeeded {
+            "succeeded"
+        } else {
+            "expired"
+        };
+        write!(
+            f,
+            "withdrawal {} for client {} {} after {} attempts",
+            self.tx, self.client, status, self.attempts
+        )
+    }
+}
+
+impl RetryQueue {
+    /// Creates a queue that keeps withdrawals parked for the given number of records, or for the
+    /// rest of the run
+    pub fn new(window: Option<u64>) -> Self {
+        RetryQueue {
+            window,
+            ..Default::default()
+        }
+    }
+
+    /// Advances the clock once a record has been applied
+    pub fn tick(&mut self) {
+        self.clock += 1;
+    }
+
+    /// Parks a withdrawal that was rejected for insufficient funds
+    pub fn park(&mut self, record: &Record) {
+        self.parked
+            .entry(record.client_id)
+            .or_default()
+            .push_back(ParkedWithdrawal {
+                record: record.clone(),
+                parked_at: self.clock,
+                attempts: 1,
+            });
+    }
+
+    /// Re-presents a client's parked withdrawals in the order they were rejected, journaling (and
+    /// auditing) each one that's applied. Withdrawals that have been parked for longer than the
+    /// window expire.
+    pub fn retry(
+        &mut self,
+        account: &mut Account,
+        client_id: u16,
+        config: &EngineConfig,
+        journal: &mut Journal,
+        mut audit: Option<&mut AuditLog>,
+    ) -> SourceResult<()> {
+        let Some(parked) = self.parked.remove(&client_id) else {
+            return Ok(());
+        };
+
+        let mut still_parked = VecDeque::new();
+        for mut withdrawal in parked {
+            if self.has_expired(&withdrawal) {
+                self.outcomes.push(outcome(&withdrawal, false));
+                continue;
+            }
+
+            withdrawal.attempts += 1;
+            let before = AuditBalances::of(account);
+            match process_transaction_record(&withdrawal.record, account, config) {
+                Ok(()) => {
+                    journal.record(AccountEvent::new(&withdrawal.record, account))?;
+                    if let Some(audit) = audit.as_deref_mut() {
+                        audit.record(AuditEvent::applied(&withdrawal.record, before, account))?;
+                    }
+                    self.outcomes.push(outcome(&withdrawal, true));
+                }
+                Err(_) => still_parked.push_back(withdrawal),
+            }
+        }
+
+        if !still_parked.is_empty() {
+            self.parked.insert(client_id, still_parked);
+        }
+
+        Ok(())
+    }
+
+    /// Expires the withdrawals that are still parked, returning what happened to every withdrawal
+    /// that was parked, ordered by client then transaction
+    pub fn finish(mut self) -> Vec<RetryOutcome> {
+        for withdrawal in self.parked.values().flatten() {
+            self.outcomes.push(outcome(withdrawal, false));
+        }
+
+        self.outcomes
+            .sort_by_key(|outcome| (outcome.client, outcome.tx));
+        self.outcomes
+    }
+
+    /// Whether a withdrawal has been parked for longer than the window
+    fn has_expired(&self, withdrawal: &ParkedWithdrawal) -> bool {
+        self.window
+            .is_some_and(|window| self.clock - withdrawal.parked_at > window)
+    }
+}
+
+/// Describes what happened to a parked withdrawal
+fn outcome(withdrawal: &ParkedWithdrawal, succeeded: bool) -> RetryOutcome {
+    RetryOutcome {
+        client: withdrawal.record.client_id,
+        tx: withdrawal.record.transaction_id,
+        attempts: withdrawal.attempts,
+        succeeded,
+    }
+}
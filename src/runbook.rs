@@ -0,0 +1,515 @@
+use crate::admin::{load_admin_operations, AdminPhase};
+use crate::cli::CliArgs;
+use crate::config::EngineConfig;
+use crate::currency::Currency;
+use crate::engine::Engine;
+use crate::error::{EngineResult, ExitReport, SourceError, SourceResult};
+use crate::journal::Journal;
+use crate::mapper::Account;
+use crate::metadata::{write_summary, RunSummary};
+use crate::reader::{apply_records, read_transactions_from_file, write_accounts};
+use crate::state::{load_state, write_state, StateFormat};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use toml_edit::{DocumentMut, Item, Table};
+
+/// The names stages are written with in a runbook, listed when a stage isn't recognised
+const STAGE_NAMES: &str =
+    "load-state, admin-ops, process, write-accounts, write-summary or save-state";
+
+/// A stage of a runbook. The stages are run in the order they're listed, each one starting from
+/// the accounts the stage before it left.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    /// Replaces the accounts with the state saved by an earlier run
+    LoadState(PathBuf),
+
+    /// Applies the operations in an admin operations file
+    AdminOps(PathBuf),
+
+    /// Applies the transactions in each of the files, one file after another
+    Process(Vec<PathBuf>),
+
+    /// Writes the accounts in the output format
+    WriteAccounts(PathBuf),
+
+    /// Writes the headline figures of the run so far as JSON
+    WriteSummary(PathBuf),
+
+    /// Saves the accounts, so a later run can load them
+    SaveState(PathBuf),
+}
+
+/// An ordered pipeline of stages read from a TOML file, so a run that loads state, applies admin
+/// operations, processes several files and writes reports is one invocation rather than a script.
+/// Each `[[stage]]` table has a single key naming the stage, and relative paths are resolved
+/// against the directory the runbook is in:
+///
+/// ```toml
+/// checkpoint = "nightly.checkpoint"
+///
+/// [[stage]]
+/// load-state = "state.bin"
+///
+/// [[stage]]
+/// process = ["a.csv", "b.csv"]
+///
+/// [[stage]]
+/// write-accounts = "accounts.csv"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Runbook {
+    /// Where the progress of the run is saved after each stage, when it's resumable
+    pub checkpoint: Option<PathBuf>,
+
+    /// The stages, in the order they're run
+    pub stages: Vec<Stage>,
+
+    /// The SHA-256 of the runbook, so a checkpoint is only resumed by the runbook that saved it
+    digest: Vec<u8>,
+}
+
+impl Runbook {
+    /// Reads the runbook in a TOML file
+    pub fn load(file_path: &Path) -> SourceResult<Self> {
+        let text = fs::read_to_string(file_path).map_err(|err| runbook_error(file_path, err))?;
+        let base = file_path.parent().unwrap_or(Path::new(""));
+
+        Runbook::parse(&text, base).map_err(|err| runbook_error(file_path, err))
+    }
+
+    /// Parses a runbook, resolving relative paths against the base directory
+    pub fn parse(text: &str, base: &Path) -> Result<Self, String> {
+        let document: DocumentMut = text.parse().map_err(|err| format!("{}", err))?;
+
+        if let Some((key, _)) = document
+            .iter()
+            .find(|(key, _)| *key != "checkpoint" && *key != "stage")
+        {
+            return Err(format!("unknown key `{}`", key));
+        }
+
+        let checkpoint = match document.get("checkpoint") {
+            Some(item) => Some(base.join(path(item, "checkpoint")?)),
+            None => None,
+        };
+
+        let stages = match document.get("stage") {
+            Some(item) => item
+                .as_array_of_tables()
+                .ok_or("stages must be written as [[stage]] tables")?
+                .iter()
+                .enumerate()
+                .map(|(index, table)| parse_stage(index + 1, table, base))
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+
+        Ok(Runbook {
+            checkpoint,
+            stages,
+            digest: Sha256::digest(text.as_bytes()).to_vec(),
+        })
+    }
+}
+
+/// Parses the stage with the given number, which must have a single key naming it
+fn parse_stage(number: usize, table: &Table, base: &Path) -> Result<Stage, String> {
+    let mut entries = table.iter();
+    let (name, item) = match (entries.next(), entries.next()) {
+        (Some(entry), None) => entry,
+        _ => {
+            return Err(format!(
+                "stage {} must have exactly one key, naming the stage",
+                number
+            ))
+        }
+    };
+    let file = |item: &Item| path(item, name).map(|file| base.join(file));
+
+    match name {
+        "load-state" => Ok(Stage::LoadState(file(item)?)),
+        "admin-ops" => Ok(Stage::AdminOps(file(item)?)),
+        "process" => match item.as_array() {
+            Some(files) => files
+                .iter()
+                .map(|value| {
+                    value
+                        .as_str()
+                        .map(|value| base.join(value))
+                        .ok_or_else(|| format!("`{}` must be a path or a list of paths", name))
+                })
+                .collect::<Result<_, _>>()
+                .map(Stage::Process),
+            None => Ok(Stage::Process(vec![file(item)?])),
+        },
+        "write-accounts" => Ok(Stage::WriteAccounts(file(item)?)),
+        "write-summary" => Ok(Stage::WriteSummary(file(item)?)),
+        "save-state" => Ok(Stage::SaveState(file(item)?)),
+        _ => Err(format!(
+            "stage {} is `{}`, which isn't one of {}",
+            number, name, STAGE_NAMES
+        )),
+    }
+}
+
+/// The path that's the value of the key
+fn path(item: &Item, key: &str) -> Result<PathBuf, String> {
+    item.as_str()
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("`{}` must be a path", key))
+}
+
+/// How far a runbook has got, saved as its checkpoint after each stage. Outputs are held back
+/// until every stage has run, so the ones written so far are part of it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Progress {
+    /// The SHA-256 of the runbook being run
+    digest: Vec<u8>,
+
+    /// The number of stages that have finished
+    completed: usize,
+
+    /// The client accounts, keyed by client id
+    accounts: HashMap<u16, Account>,
+
+    /// The outputs waiting to be written, along with where they're written to
+    outputs: Vec<(PathBuf, Vec<u8>)>,
+
+    /// The number of records that were read
+    records: u64,
+
+    /// The number of records that were rejected
+    rejections: u64,
+}
+
+impl Progress {
+    /// Loads the progress saved by a run of the runbook that failed
+    fn resume(checkpoint_path: &Path, runbook: &Runbook) -> SourceResult<Self> {
+        let bytes =
+            fs::read(checkpoint_path).map_err(|err| checkpoint_error(checkpoint_path, err))?;
+        let progress: Progress =
+            bincode::deserialize(&bytes).map_err(|err| checkpoint_error(checkpoint_path, err))?;
+
+        if progress.digest != runbook.digest {
+            return Err(checkpoint_error(
+                checkpoint_path,
+                "it was saved by a different runbook, delete it to start the run over",
+            ));
+        }
+
+        Ok(progress)
+    }
+
+    /// Runs a stage, adding any records it rejects to the report
+    fn run(
+        &mut self,
+        stage: &Stage,
+        args: &CliArgs,
+        config: &EngineConfig,
+        report: &mut ExitReport,
+    ) -> EngineResult<()> {
+        let (records, rejections) = (report.records, report.rejections.len());
+
+        match stage {
+            Stage::LoadState(state_path) => self.accounts = load_state(state_path)?,
+            Stage::AdminOps(admin_ops_path) => {
+                let operations = load_admin_operations(admin_ops_path)?;
+                let engine = self
+                    .engine(config)
+                    .with_admin_operations(operations, AdminPhase::Before);
+                self.accounts = apply_records(std::iter::empty(), engine, report)?;
+            }
+            Stage::Process(file_paths) => {
+                for file_path in file_paths {
+                    let format = args.format_detector().detect(file_path)?;
+                    let engine = self.engine(config);
+                    self.accounts =
+                        read_transactions_from_file(file_path, format, &args.csv, engine, report)?;
+                }
+            }
+            Stage::WriteAccounts(output_path) => {
+                let mut output = vec![];
+                write_accounts(
+                    &mut output,
+                    &self.accounts,
+                    config.currency.as_ref(),
+                    args.output_version,
+                    args.output_format,
+                    args.field_formatter(),
+                )?;
+                self.outputs.push((output_path.clone(), output));
+            }
+            Stage::WriteSummary(output_path) => {
+                let summary = RunSummary::new(&self.accounts, self.records, self.rejections);
+                let mut output = vec![];
+                write_summary(&mut output, &summary)?;
+                self.outputs.push((output_path.clone(), output));
+            }
+            Stage::SaveState(state_path) => {
+                let mut output = vec![];
+                write_state(&mut output, &self.accounts, StateFormat::Binary)
+                    .map_err(|err| SourceError::State(state_path.display().to_string(), err))?;
+                self.outputs.push((state_path.clone(), output));
+            }
+        }
+
+        self.records += report.records - records;
+        self.rejections += (report.rejections.len() - rejections) as u64;
+
+        Ok(())
+    }
+
+    /// An engine that applies transactions to the accounts, leaving them empty until the engine
+    /// returns them
+    fn engine(&mut self, config: &EngineConfig) -> Engine {
+        Engine::new(
+            std::mem::take(&mut self.accounts),
+            config.clone(),
+            Journal::default(),
+        )
+    }
+
+    /// Saves the progress as the checkpoint
+    fn save(&self, checkpoint_path: &Path) -> SourceResult<()> {
+        let bytes =
+            bincode::serialize(self).map_err(|err| checkpoint_error(checkpoint_path, err))?;
+
+        write_file(checkpoint_path, &bytes)
+    }
+}
+
+/// Runs each stage of the runbook in order. Outputs are only written once every stage has
+/// succeeded, so a run that fails leaves the files it would have written as they were. When the
+/// runbook has a checkpoint, the progress is saved after each stage and a run that failed resumes
+/// after the last stage that finished. The checkpoint is deleted once the run succeeds.
+pub fn run_runbook(runbook: &Runbook, args: &CliArgs, report: &mut ExitReport) -> EngineResult<()> {
+    // amounts are checked against, and output in, the currency's minor unit
+    let mut config = args.config.clone();
+    if let Some(code) = &args.currency {
+        config.currency = Some(Currency::resolve(code, &args.minor_unit_overrides)?);
+    }
+
+    let mut progress = match &runbook.checkpoint {
+        Some(checkpoint_path) if checkpoint_path.exists() => {
+            Progress::resume(checkpoint_path, runbook)?
+        }
+        _ => Progress {
+            digest: runbook.digest.clone(),
+            ..Default::default()
+        },
+    };
+
+    for stage in runbook.stages.iter().skip(progress.completed) {
+        progress.run(stage, args, &config, report)?;
+        progress.completed += 1;
+
+        if let Some(checkpoint_path) = &runbook.checkpoint {
+            progress.save(checkpoint_path)?;
+        }
+    }
+
+    for (output_path, output) in &progress.outputs {
+        write_file(output_path, output)?;
+    }
+
+    if let Some(checkpoint_path) = &runbook.checkpoint {
+        fs::remove_file(checkpoint_path).map_err(|err| checkpoint_error(checkpoint_path, err))?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the file with the contents, writing them to a temporary file in the same directory
+/// first so the file is never left half written
+fn write_file(file_path: &Path, contents: &[u8]) -> SourceResult<()> {
+    let io_error =
+        |err: std::io::Error| SourceError::Io(format!("{}: {}", file_path.display(), err));
+    let dir = match file_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut file = NamedTempFile::new_in(dir).map_err(io_error)?;
+    file.write_all(contents).map_err(io_error)?;
+    file.persist(file_path).map_err(|err| io_error(err.error))?;
+
+    Ok(())
+}
+
+/// An error reading the runbook
+fn runbook_error(file_path: &Path, err: impl ToString) -> SourceError {
+    SourceError::Runbook(file_path.display().to_string(), err.to_string())
+}
+
+/// An error loading or saving the checkpoint
+fn checkpoint_error(file_path: &Path, err: impl ToString) -> SourceError {
+    SourceError::State(file_path.display().to_string(), err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::CliArgs;
+    use crate::error::{EngineError, ExitReport, SourceError};
+    use crate::mapper::Account;
+    use crate::metadata::RunSummary;
+    use crate::runbook::{run_runbook, Runbook, Stage};
+    use crate::state::{load_state, save_state};
+    use crate::test_helpers::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // Tests that each stage is parsed, with its paths resolved against the runbook's directory
+    #[test]
+    fn test_parse_runbook() {
+        let text = r#"
+            checkpoint = "run.checkpoint"
+
+            [[stage]]
+            load-state = "state.bin"
+
+            [[stage]]
+            admin-ops = "ops.csv"
+
+            [[stage]]
+            process = ["a.csv", "/data/b.csv"]
+
+            [[stage]]
+            process = "c.csv"
+
+            [[stage]]
+            write-accounts = "accounts.csv"
+
+            [[stage]]
+            write-summary = "summary.json"
+
+            [[stage]]
+            save-state = "state.bin"
+        "#;
+        let runbook = Runbook::parse(text, Path::new("runs")).unwrap();
+
+        assert_eq!(
+            runbook.checkpoint,
+            Some(PathBuf::from("runs/run.checkpoint"))
+        );
+        assert_eq!(
+            runbook.stages,
+            [
+                Stage::LoadState("runs/state.bin".into()),
+                Stage::AdminOps("runs/ops.csv".into()),
+                Stage::Process(vec!["runs/a.csv".into(), "/data/b.csv".into()]),
+                Stage::Process(vec!["runs/c.csv".into()]),
+                Stage::WriteAccounts("runs/accounts.csv".into()),
+                Stage::WriteSummary("runs/summary.json".into()),
+                Stage::SaveState("runs/state.bin".into()),
+            ]
+        );
+    }
+
+    // Tests that runbooks with unknown stages, or stages that don't name exactly one thing to do,
+    // are rejected
+    #[test]
+    fn test_parse_runbook_invalid() {
+        let base = Path::new("");
+        let err = Runbook::parse("[[stage]]\naccrue-fees = true\n", base).unwrap_err();
+        assert!(err.starts_with("stage 1 is `accrue-fees`"));
+
+        let err = Runbook::parse("[[stage]]\nprocess = 1\n", base).unwrap_err();
+        assert_eq!(err, "`process` must be a path");
+
+        let text = "[[stage]]\nload-state = \"a.bin\"\nsave-state = \"b.bin\"\n";
+        let err = Runbook::parse(text, base).unwrap_err();
+        assert_eq!(err, "stage 1 must have exactly one key, naming the stage");
+
+        assert_eq!(
+            Runbook::parse("stages = []\n", base).unwrap_err(),
+            "unknown key `stages`"
+        );
+        assert!(Runbook::parse("[[stage]\n", base).is_err());
+    }
+
+    // Tests that the stages are run in order, carrying the accounts from one to the next, and that
+    // the outputs are only written once every stage has succeeded. A run that fails resumes from
+    // its checkpoint after the last stage that finished.
+    #[test]
+    fn test_run_runbook() {
+        let (state_path, dir, _) = create_temp_file("state.bin").unwrap();
+        save_state(
+            &state_path,
+            &HashMap::from([(1, Account::with_balances(5.0, 0.0))]),
+        )
+        .unwrap();
+
+        let write = |name: &str, contents: &str| {
+            fs::write(dir.path().join(name), contents).unwrap();
+        };
+        write(
+            "a.csv",
+            "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,3.0\n",
+        );
+        write(
+            "ops.csv",
+            "op,client,tx,amount,operator\nset-limit,1,,1.0,alice\n",
+        );
+        write(
+            "runbook.toml",
+            r#"
+            checkpoint = "run.checkpoint"
+
+            [[stage]]
+            load-state = "state.bin"
+
+            [[stage]]
+            process = ["a.csv", "b.csv"]
+
+            [[stage]]
+            admin-ops = "ops.csv"
+
+            [[stage]]
+            write-summary = "summary.json"
+
+            [[stage]]
+            save-state = "state.bin"
+            "#,
+        );
+
+        // b.csv doesn't exist yet, so the run fails without writing anything
+        let runbook = Runbook::load(&dir.path().join("runbook.toml")).unwrap();
+        let args = CliArgs::default();
+        let mut report = ExitReport::default();
+        let result = run_runbook(&runbook, &args, &mut report);
+        assert!(matches!(
+            result,
+            Err(EngineError::Source(SourceError::Io(_)))
+        ));
+        assert!(!dir.path().join("summary.json").exists());
+        assert_eq!(load_state(&state_path).unwrap()[&1].total_funds, 5.0);
+        assert!(dir.path().join("run.checkpoint").exists());
+
+        // the run resumes after loading the state, so the process stage starts over from it
+        write(
+            "b.csv",
+            "type,client,tx,amount\nwithdrawal,1,3,2.0\nwithdrawal,2,4,5.0\n",
+        );
+        let mut report = ExitReport::default();
+        run_runbook(&runbook, &args, &mut report).unwrap();
+        assert_eq!(report.records, 4);
+        assert_eq!(report.rejections.len(), 1);
+
+        let accounts = load_state(&state_path).unwrap();
+        assert_account(&accounts[&1], 13.0, 13.0, true);
+        assert_eq!(accounts[&1].withdrawal_limit, Some(1.0));
+        assert_account(&accounts[&2], 3.0, 3.0, true);
+
+        let summary: RunSummary =
+            serde_json::from_slice(&fs::read(dir.path().join("summary.json")).unwrap()).unwrap();
+        assert_eq!((summary.records, summary.rejections), (4, 1));
+        assert!(!dir.path().join("run.checkpoint").exists());
+    }
+}
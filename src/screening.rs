@@ -0,0 +1,202 @@
+use crate::error::{EngineError, LedgerError, LedgerResult, Rejection, SourceError, SourceResult};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+
+/// Compliance screening of the clients that transactions are applied to. Transactions for clients
+/// in a restricted country, or on the denylist, are held rather than processed.
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct Screening {
+    /// The ISO 3166 codes of the countries that transactions are held for
+    pub restricted_countries: BTreeSet<String>,
+
+    /// The country of each client, from the clients metadata file
+    pub client_countries: BTreeMap<u16, String>,
+
+    /// The clients that transactions are always held for
+    pub denylist: BTreeSet<u16>,
+}
+
+/// Why a transaction was held for compliance review
+#[derive(Debug, Clone, PartialEq)]
+pub enum HoldReason {
+    /// The client is on the denylist
+    Denylisted,
+
+    /// The client is in a restricted country
+    RestrictedCountry(String),
+}
+
+impl fmt::Display for HoldReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HoldReason::Denylisted => write!(f, "the client is on the denylist"),
+            HoldReason::RestrictedCountry(country) => {
+                write!(f, "the client is in a restricted country ({})", country)
+            }
+        }
+    }
+}
+
+/// A held transaction, as output to the compliance hold report
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ComplianceHold {
+    /// The line of the file that the transaction was read from
+    pub line: u64,
+
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The unique ID of the transaction
+    pub tx: u32,
+
+    /// The country of the client, when the client is in a restricted country
+    pub country: Option<String>,
+
+    /// Why the transaction was held
+    pub reason: String,
+}
+
+impl Screening {
+    /// Errors when transactions for the client must be held for compliance review
+    pub fn screen(&self, client_id: u16, transaction_id: u32) -> LedgerResult<()> {
+        if self.denylist.contains(&client_id) {
+            return Err(LedgerError::ComplianceHold(
+                client_id,
+                transaction_id,
+                HoldReason::Denylisted,
+            ));
+        }
+
+        match self.client_countries.get(&client_id) {
+            Some(country) if self.restricted_countries.contains(country) => {
+                Err(LedgerError::ComplianceHold(
+                    client_id,
+                    transaction_id,
+                    HoldReason::RestrictedCountry(country.clone()),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses a comma separated list of ISO 3166 country codes, e.g. IR,KP
+pub fn parse_countries(value: &str) -> BTreeSet<String> {
+    value
+        .split(',')
+        .map(|country| country.trim().to_uppercase())
+        .filter(|country| !country.is_empty())
+        .collect()
+}
+
+/// Writes the transactions that were held for compliance review to a csv, in the order they were
+/// read. Every other rejection is left out.
+pub fn write_compliance_report(
+    file_path: impl AsRef<Path>,
+    rejections: &[Rejection],
+) -> SourceResult<()> {
+    let mut writer = csv::Writer::from_path(file_path).map_err(SourceError::from)?;
+
+    for rejection in rejections {
+        if let EngineError::Ledger(LedgerError::ComplianceHold(client, tx, reason)) =
+            &rejection.error
+        {
+            let country = match reason {
+                HoldReason::RestrictedCountry(country) => Some(country.clone()),
+                HoldReason::Denylisted => None,
+            };
+            writer
+                .serialize(ComplianceHold {
+                    line: rejection.line,
+                    client: *client,
+                    tx: *tx,
+                    country,
+                    reason: reason.to_string(),
+                })
+                .map_err(SourceError::from)?;
+        }
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{LedgerError, Rejection};
+    use crate::screening::{parse_countries, write_compliance_report, HoldReason, Screening};
+    use crate::test_helpers::*;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs;
+    use std::io::Error;
+
+    /// Screening that restricts IR and denylists client 4
+    fn screening() -> Screening {
+        Screening {
+            restricted_countries: parse_countries("ir, KP,"),
+            client_countries: BTreeMap::from([(1, "GB".to_string()), (2, "IR".to_string())]),
+            denylist: BTreeSet::from([4]),
+        }
+    }
+
+    // Tests that clients in restricted countries, or on the denylist, are held
+    #[test]
+    fn test_screen() {
+        let screening = screening();
+
+        assert_eq!(screening.screen(1, 10), Ok(()));
+        assert_eq!(screening.screen(3, 11), Ok(()));
+        assert_eq!(
+            screening.screen(2, 12),
+            Err(LedgerError::ComplianceHold(
+                2,
+                12,
+                HoldReason::RestrictedCountry("IR".to_string())
+            ))
+        );
+        assert_eq!(
+            screening.screen(4, 13),
+            Err(LedgerError::ComplianceHold(4, 13, HoldReason::Denylisted))
+        );
+    }
+
+    // Tests that only the held transactions are written to the report
+    #[test]
+    fn test_write_compliance_report() -> Result<(), Error> {
+        let (file_path, dir, file) = create_temp_file("holds.csv")?;
+
+        let rejections = vec![
+            Rejection {
+                line: 2,
+                file: None,
+                error: LedgerError::UnknownClient(9).into(),
+            },
+            Rejection {
+                line: 3,
+                file: None,
+                error: screening().screen(2, 12).unwrap_err().into(),
+            },
+            Rejection {
+                line: 5,
+                file: None,
+                error: screening().screen(4, 13).unwrap_err().into(),
+            },
+        ];
+        write_compliance_report(&file_path, &rejections).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file_path)?,
+            "line,client,tx,country,reason\n\
+             3,2,12,IR,the client is in a restricted country (IR)\n\
+             5,4,13,,the client is on the denylist\n"
+        );
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+}
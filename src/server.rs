@@ -0,0 +1,1251 @@
+use crate::clock::Clock;
+use crate::engine::Engine;
+use crate::error::{EngineError, EngineResult, LedgerError, SourceError};
+use crate::handover::{Checkpoint, Handover, HandoverListener};
+use crate::index::{IndexEntry, TransactionState};
+use crate::journal::{parse_entry, AccountEvent, JournalEntry};
+use crate::mapper::{Account, AccountRecord, Record, TransactionType};
+use crate::state::{load_state, save_state};
+use crate::telemetry::TraceContext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tiny_http::{Header, Request, Response, Server};
+use tracing::{info_span, warn};
+
+/// The address the server listens on when --addr isn't provided
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+/// The address the read-only server listens on when --addr isn't provided, so it can run
+/// alongside the journal server
+pub const DEFAULT_READONLY_ADDR: &str = "127.0.0.1:8081";
+
+/// The number of events, or accounts, in a page when a limit isn't requested
+const DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// The most events, or accounts, that can be requested in a single page
+const MAX_PAGE_LIMIT: usize = 1_000;
+
+/// How often the server checks on a handover to a new process, while it's waiting for requests
+const HANDOVER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A page of a client's applied events, in the order they were applied
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TimelinePage {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The events in this page
+    pub events: Vec<AccountEvent>,
+
+    /// The offset to request the next page with, when there are more events
+    pub next_offset: Option<usize>,
+}
+
+/// Filters and paginates a client's timeline, parsed from the query string
+#[derive(Debug, PartialEq)]
+pub struct TimelineQuery {
+    /// Only events recorded at or after this time (milliseconds since the unix epoch)
+    pub from: Option<u64>,
+
+    /// Only events recorded at or before this time (milliseconds since the unix epoch)
+    pub to: Option<u64>,
+
+    /// The number of matching events to skip
+    pub offset: usize,
+
+    /// The most events to return
+    pub limit: usize,
+}
+
+impl Default for TimelineQuery {
+    fn default() -> Self {
+        TimelineQuery {
+            from: None,
+            to: None,
+            offset: 0,
+            limit: DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+impl TimelineQuery {
+    /// Parses a query string (e.g. from=1&limit=10), unknown parameters are ignored
+    pub fn parse(query: &str) -> Result<Self, HttpError> {
+        let mut timeline_query = TimelineQuery::default();
+
+        for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match name {
+                "from" => timeline_query.from = Some(parse_param(name, value)?),
+                "to" => timeline_query.to = Some(parse_param(name, value)?),
+                "offset" => timeline_query.offset = parse_param(name, value)?,
+                "limit" => {
+                    timeline_query.limit = parse_param::<usize>(name, value)?.min(MAX_PAGE_LIMIT)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(timeline_query)
+    }
+
+    /// Whether an event was recorded within the requested time range
+    fn contains(&self, event: &AccountEvent) -> bool {
+        self.from.is_none_or(|from| event.recorded_at_ms >= from)
+            && self.to.is_none_or(|to| event.recorded_at_ms <= to)
+    }
+}
+
+/// The field the accounts listing is sorted by
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum AccountSort {
+    /// The client id
+    #[default]
+    Client,
+
+    /// The available funds
+    Available,
+
+    /// The held funds
+    Held,
+
+    /// The total funds
+    Total,
+}
+
+impl std::str::FromStr for AccountSort {
+    type Err = HttpError;
+
+    fn from_str(sort: &str) -> Result<Self, HttpError> {
+        match sort {
+            "client" => Ok(AccountSort::Client),
+            "available" => Ok(AccountSort::Available),
+            "held" => Ok(AccountSort::Held),
+            "total" => Ok(AccountSort::Total),
+            _ => Err(invalid_param("sort", sort)),
+        }
+    }
+}
+
+/// A page of the accounts listing
+#[derive(Debug, Serialize, PartialEq)]
+pub struct AccountsPage<'a> {
+    /// The balances of the accounts in this page
+    pub accounts: Vec<&'a AccountRecord>,
+
+    /// The cursor to request the next page with, when there are more accounts
+    pub next_cursor: Option<String>,
+}
+
+/// Filters, sorts and paginates the accounts listing, parsed from the query string. Pages are
+/// found by the sort key of the last account in the previous page (the cursor), rather than an
+/// offset, so accounts that are added while the listing is browsed don't shift the pages.
+#[derive(Debug, PartialEq)]
+pub struct AccountsQuery {
+    /// Only accounts that are (or aren't) locked
+    pub locked: Option<bool>,
+
+    /// Only accounts holding at least this much
+    pub min_held: Option<f32>,
+
+    /// The field the accounts are sorted by, ties are broken by client id
+    pub sort: AccountSort,
+
+    /// Whether the accounts are sorted in descending order
+    pub descending: bool,
+
+    /// The sort key and client of the last account in the previous page
+    pub after: Option<(f32, u16)>,
+
+    /// The most accounts to return
+    pub limit: usize,
+}
+
+impl Default for AccountsQuery {
+    fn default() -> Self {
+        AccountsQuery {
+            locked: None,
+            min_held: None,
+            sort: AccountSort::Client,
+            descending: false,
+            after: None,
+            limit: DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+impl AccountsQuery {
+    /// Parses a query string (e.g. locked=true&sort=held&order=desc), unknown parameters are
+    /// ignored
+    pub fn parse(query: &str) -> Result<Self, HttpError> {
+        let mut accounts_query = AccountsQuery::default();
+
+        for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match name {
+                "locked" => accounts_query.locked = Some(parse_param(name, value)?),
+                "min_held" => accounts_query.min_held = Some(parse_param(name, value)?),
+                "sort" => accounts_query.sort = parse_param(name, value)?,
+                "order" => {
+                    accounts_query.descending = match value {
+                        "asc" => false,
+                        "desc" => true,
+                        _ => return Err(invalid_param(name, value)),
+                    }
+                }
+                "cursor" => {
+                    let (key, client) = value
+                        .split_once(':')
+                        .ok_or_else(|| invalid_param(name, value))?;
+                    accounts_query.after =
+                        Some((parse_param(name, key)?, parse_param(name, client)?));
+                }
+                "limit" => {
+                    accounts_query.limit = parse_param::<usize>(name, value)?.min(MAX_PAGE_LIMIT)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(accounts_query)
+    }
+
+    /// Returns the requested page of the accounts. The accounts are only borrowed, so a listing
+    /// doesn't copy every account.
+    pub fn page<'a>(&self, accounts: &'a [AccountRecord]) -> AccountsPage<'a> {
+        let mut matching: Vec<&AccountRecord> = accounts
+            .iter()
+            .filter(|record| self.contains(record))
+            .collect();
+        matching.sort_by(|a, b| self.compare(self.key(a), self.key(b)));
+
+        let start = match self.after {
+            Some(after) => {
+                matching.partition_point(|record| self.compare(self.key(record), after).is_le())
+            }
+            None => 0,
+        };
+        let end = (start + self.limit).min(matching.len());
+        let next_cursor = (end < matching.len() && end > start).then(|| {
+            let (key, client) = self.key(matching[end - 1]);
+            format!("{}:{}", key, client)
+        });
+
+        AccountsPage {
+            accounts: matching[start..end].to_vec(),
+            next_cursor,
+        }
+    }
+
+    /// Whether an account matches the filters
+    fn contains(&self, record: &AccountRecord) -> bool {
+        self.locked.is_none_or(|locked| record.locked == locked)
+            && self.min_held.is_none_or(|min_held| record.held >= min_held)
+    }
+
+    /// The sort key of an account, along with its client to break ties
+    fn key(&self, record: &AccountRecord) -> (f32, u16) {
+        let key = match self.sort {
+            AccountSort::Client => record.client as f32,
+            AccountSort::Available => record.available,
+            AccountSort::Held => record.held,
+            AccountSort::Total => record.total,
+        };
+
+        (key, record.client)
+    }
+
+    /// Compares two sort keys in the requested order
+    fn compare(&self, a: (f32, u16), b: (f32, u16)) -> std::cmp::Ordering {
+        let ordering = a.0.total_cmp(&b.0).then(a.1.cmp(&b.1));
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// An error response, along with its status code
+#[derive(Debug, PartialEq)]
+pub struct HttpError {
+    /// The status code of the response
+    pub status: u16,
+
+    /// Explains what went wrong
+    pub message: String,
+}
+
+impl HttpError {
+    /// The request was invalid
+    fn bad_request(message: impl Into<String>) -> Self {
+        HttpError {
+            status: 400,
+            message: message.into(),
+        }
+    }
+
+    /// Nothing exists at the requested path
+    fn not_found() -> Self {
+        HttpError {
+            status: 404,
+            message: "Not found".to_string(),
+        }
+    }
+
+    /// The transaction a dispute refers to doesn't exist
+    fn transaction_not_found(tx: u32) -> Self {
+        HttpError {
+            status: 404,
+            message: format!("Transaction {} not found", tx),
+        }
+    }
+
+    /// The request tried to change something that can only be read
+    fn read_only() -> Self {
+        HttpError {
+            status: 405,
+            message: "The snapshot is read-only".to_string(),
+        }
+    }
+
+    /// The caller isn't allowed to do this
+    fn forbidden(message: impl Into<String>) -> Self {
+        HttpError {
+            status: 403,
+            message: message.into(),
+        }
+    }
+
+    /// The request can't be made in the current state of what it refers to
+    fn conflict(message: impl Into<String>) -> Self {
+        HttpError {
+            status: 409,
+            message: message.into(),
+        }
+    }
+
+    /// The request was valid, but the engine wouldn't apply it
+    fn unprocessable(message: impl Into<String>) -> Self {
+        HttpError {
+            status: 422,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<EngineError> for HttpError {
+    fn from(err: EngineError) -> Self {
+        match err {
+            EngineError::Ledger(LedgerError::ClientMismatch(..)) => {
+                HttpError::forbidden(err.to_string())
+            }
+            EngineError::Ledger(err) => HttpError::unprocessable(err.to_string()),
+            err => HttpError {
+                status: 500,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+impl From<SourceError> for HttpError {
+    fn from(err: SourceError) -> Self {
+        HttpError {
+            status: 500,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// A client's request to dispute one of their transactions, the body of POST /disputes
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct DisputeRequest {
+    /// The client submitting the dispute, who must own the transaction
+    pub client: u16,
+
+    /// The transaction being disputed
+    pub tx: u32,
+
+    /// The portion of the transaction that's disputed, used when partial disputes are enabled
+    #[serde(default)]
+    pub amount: Option<f32>,
+}
+
+/// Where a disputed transaction is in its lifecycle, the body of a dispute that was opened
+#[derive(Debug, Serialize, PartialEq)]
+pub struct DisputeStatus {
+    /// The unique ID of the client
+    pub client: u16,
+
+    /// The transaction that's disputed
+    pub tx: u32,
+
+    /// The state the transaction is in, disputed until it's resolved or charged back
+    pub state: TransactionState,
+
+    /// The funds held while the transaction is disputed
+    pub held: f32,
+
+    /// When the funds were first held, as a unix timestamp
+    pub held_since: Option<u64>,
+}
+
+/// Applies the transactions and disputes submitted to the server through the engine, so the
+/// server can be a small payments service, and the system of record for a dispute portal. Only the
+/// owner of a settled deposit can dispute it, and only when it isn't already disputed (or charged
+/// back, voided or escheated). Everything that's applied is journaled, so it shows up in the
+/// client's timeline.
+pub struct DisputeDesk {
+    /// Applies the transactions and disputes to the accounts, journaling each one
+    engine: Engine,
+
+    /// The journal the engine appends to
+    journal_path: PathBuf,
+
+    /// Tells the time disputes are opened at
+    clock: Arc<dyn Clock>,
+
+    /// Where the accounts are saved after each transaction or dispute that's applied, when
+    /// they're saved
+    save_path: Option<PathBuf>,
+
+    /// The balances of every account as of the last change, ordered by client. Listings are
+    /// answered from it, so they see a consistent state without copying the accounts each time.
+    listing: Option<Vec<AccountRecord>>,
+}
+
+impl DisputeDesk {
+    /// Opens disputes using an engine that journals to the journal file. Every transaction in
+    /// the engine's accounts was loaded from state, so they've all settled.
+    pub fn new(engine: Engine, journal_path: &Path, clock: Arc<dyn Clock>) -> Self {
+        DisputeDesk {
+            engine,
+            journal_path: journal_path.to_path_buf(),
+            clock,
+            save_path: None,
+            listing: None,
+        }
+    }
+
+    /// Saves the accounts after each transaction or dispute that's applied, so they survive a
+    /// restart
+    pub fn with_save_state(mut self, save_path: &Path) -> Self {
+        self.save_path = Some(save_path.to_path_buf());
+        self
+    }
+
+    /// Validates a dispute and opens it through the engine, returning its lifecycle state
+    pub fn open(&mut self, request: &DisputeRequest) -> Result<DisputeStatus, HttpError> {
+        let (client, tx) = (request.client, request.tx);
+        info_span!("validate").in_scope(|| self.check(request))?;
+
+        let record = Record {
+            amount: request.amount,
+            timestamp: Some(self.clock.now_secs()),
+            ..Record::dispute(client, tx)
+        };
+        self.engine.process(&record)?;
+        self.commit()?;
+
+        let transaction = self
+            .engine
+            .transaction(client, tx)?
+            .ok_or_else(|| HttpError::transaction_not_found(tx))?;
+        Ok(DisputeStatus {
+            client,
+            tx,
+            state: transaction.current_state.into(),
+            held: transaction.held_amount(),
+            held_since: transaction.held_since,
+        })
+    }
+
+    /// Checks that the transaction of a dispute belongs to its client and can be disputed. Whether
+    /// it's within the dispute window, or a withdrawal can be disputed at all, is checked by the
+    /// engine when it applies the dispute, as it is for any other dispute.
+    fn check(&mut self, request: &DisputeRequest) -> Result<(), HttpError> {
+        let (client, tx) = (request.client, request.tx);
+        match self.engine.owner(tx) {
+            None => return Err(HttpError::transaction_not_found(tx)),
+            Some(owner) if owner != client => {
+                return Err(HttpError::forbidden(format!(
+                    "Transaction {} doesn't belong to client {}",
+                    tx, client
+                )))
+            }
+            Some(_) => {}
+        }
+
+        let transaction = self
+            .engine
+            .transaction(client, tx)?
+            .ok_or_else(|| HttpError::transaction_not_found(tx))?;
+        match transaction.current_state {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Resolve => {}
+            TransactionType::Dispute => {
+                return Err(HttpError::conflict(format!(
+                    "Transaction {} is already disputed",
+                    tx
+                )))
+            }
+            state => {
+                return Err(HttpError::conflict(format!(
+                    "Transaction {} can't be disputed, it's {:?}",
+                    tx,
+                    TransactionState::from(state)
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a transaction through the engine, returning the client's balances afterwards. A
+    /// transaction without a timestamp happened now, so the dispute window can be measured from it.
+    pub fn submit(&mut self, record: &Record) -> Result<AccountRecord, HttpError> {
+        let record = Record {
+            timestamp: record.timestamp.or(Some(self.clock.now_secs())),
+            ..record.clone()
+        };
+        self.engine.process(&record)?;
+        self.commit()?;
+
+        self.engine
+            .account(record.client_id)?
+            .ok_or_else(HttpError::not_found)
+    }
+
+    /// The current balances of a client
+    pub fn account(&self, client: u16) -> Result<AccountRecord, HttpError> {
+        self.engine
+            .account(client)?
+            .ok_or_else(HttpError::not_found)
+    }
+
+    /// The current balances of every account, ordered by client. They're only read from the
+    /// engine again once something has been applied.
+    pub fn accounts(&mut self) -> Result<&[AccountRecord], HttpError> {
+        if self.listing.is_none() {
+            self.listing = Some(self.engine.accounts()?);
+        }
+
+        Ok(self.listing.as_deref().unwrap_or_default())
+    }
+
+    /// The accounts as of the end of the journal, so another process can take over from this one
+    pub fn checkpoint(&mut self) -> EngineResult<Checkpoint> {
+        Ok(Checkpoint {
+            journal_offset: self.journal_offset()?,
+            accounts: self.engine.snapshot()?,
+        })
+    }
+
+    /// The length of the journal, once everything that's been applied has been written to it
+    pub fn journal_offset(&mut self) -> EngineResult<u64> {
+        self.engine.flush_journal()?;
+
+        let metadata = std::fs::metadata(&self.journal_path)
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(metadata.len())
+    }
+
+    /// Journals what was just applied, so it shows up in the timeline straight away, and saves the
+    /// accounts when they're saved
+    fn commit(&mut self) -> Result<(), HttpError> {
+        let _persist = info_span!("persist").entered();
+        self.listing = None;
+        self.engine.flush_journal()?;
+
+        if let Some(save_path) = &self.save_path {
+            save_state(save_path, &self.engine.snapshot()?)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the events of a client from the journal, returning the requested page. The journal is
+/// read on every request, so events appended by a running engine are included.
+pub fn timeline(
+    journal_path: &Path,
+    client: u16,
+    query: &TimelineQuery,
+) -> Result<TimelinePage, HttpError> {
+    let file = File::open(journal_path).map_err(|err| SourceError::Io(err.to_string()))?;
+    let mut events = vec![];
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| SourceError::Io(err.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = parse_entry(&line).map_err(|err| SourceError::Parse {
+            line: index as u64 + 1,
+            message: err.to_string(),
+        })?;
+        if let JournalEntry::Event(event) = entry {
+            if event.client == client && query.contains(&event) {
+                events.push(event);
+            }
+        }
+    }
+
+    // the journal is appended to in order, but a clock can step backwards
+    events.sort_by_key(|event| event.recorded_at_ms);
+
+    let end = query.offset + query.limit;
+    let next_offset = (events.len() > end).then_some(end);
+    let events = events
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .collect();
+
+    Ok(TimelinePage {
+        client,
+        events,
+        next_offset,
+    })
+}
+
+/// Serves the API until the process is stopped, answering one request at a time. Disputes can
+/// only be submitted when there's a dispute desk.
+///
+/// With a handover listener, a new process can take over the dispute desk's accounts. It's sent
+/// a checkpoint while requests are still answered, and once it's loaded it the requests that were
+/// already accepted are answered, the address is released and this returns. The new process then
+/// replays whatever was journaled after the checkpoint.
+pub fn serve(
+    journal_path: &Path,
+    addr: &str,
+    mut disputes: Option<DisputeDesk>,
+    mut handover: Option<HandoverListener>,
+) -> EngineResult<()> {
+    let server = Server::http(addr).map_err(|err| SourceError::Io(err.to_string()))?;
+    eprintln!("Listening on http://{}", addr);
+    let mut pending: Option<Handover> = None;
+
+    loop {
+        let request = match handover {
+            Some(_) => server.recv_timeout(HANDOVER_POLL_INTERVAL),
+            None => server.recv().map(Some),
+        }
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+        if let Some(request) = request {
+            answer(journal_path, disputes.as_mut(), request)?;
+        }
+
+        let (Some(listener), Some(desk)) = (&handover, disputes.as_mut()) else {
+            continue;
+        };
+        let Some(next) = pending.as_mut() else {
+            pending = listener.accept()?;
+            if let Some(next) = pending.as_mut() {
+                next.send_checkpoint(desk.checkpoint()?)?;
+            }
+            continue;
+        };
+
+        match next.is_ready() {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(err) => {
+                // the new process went away, so this one carries on serving
+                warn!("Abandoned handover: {}", err);
+                pending = None;
+                continue;
+            }
+        }
+
+        while let Some(request) = server
+            .try_recv()
+            .map_err(|err| SourceError::Io(err.to_string()))?
+        {
+            answer(journal_path, disputes.as_mut(), request)?;
+        }
+        let journal_offset = match disputes.as_mut() {
+            Some(desk) => desk.journal_offset()?,
+            None => 0,
+        };
+
+        // the new process binds the address, and the socket, once they're released
+        drop(server);
+        handover.take();
+        if let Some(next) = pending.take() {
+            next.release(journal_offset)?;
+        }
+        eprintln!("Handed over to the new process");
+
+        return Ok(());
+    }
+}
+
+/// Reads the body of a request and sends the response
+fn answer(
+    journal_path: &Path,
+    disputes: Option<&mut DisputeDesk>,
+    mut request: Request,
+) -> EngineResult<()> {
+    let span = info_span!(
+        "request",
+        method = request.method().as_str(),
+        url = request.url()
+    );
+    TraceContext::from_headers(request.headers()).follow(&span);
+    let _request = span.enter();
+
+    let mut body = String::new();
+    let response = match request.as_reader().read_to_string(&mut body) {
+        Ok(_) => {
+            let method = request.method().as_str();
+            route(journal_path, disputes, method, request.url(), &body)
+        }
+        Err(err) => Err(HttpError::bad_request(err.to_string())),
+    };
+
+    respond(request, response)
+}
+
+/// Serves balance and transaction queries over a state snapshot until the process is stopped.
+/// The snapshot is loaded once, so a batch job can save newer state without the answers changing.
+pub fn serve_readonly(state_path: &Path, addr: &str) -> EngineResult<()> {
+    let accounts = load_state(state_path)?;
+    let server = Server::http(addr).map_err(|err| SourceError::Io(err.to_string()))?;
+    eprintln!(
+        "Serving {} read-only on http://{}",
+        state_path.display(),
+        addr
+    );
+
+    for request in server.incoming_requests() {
+        let response = query_snapshot(&accounts, request.method().as_str(), request.url());
+        respond(request, response)?;
+    }
+
+    Ok(())
+}
+
+/// Answers a request against a state snapshot, returning the body of the response. Only GET
+/// requests are accepted, as nothing can be changed.
+pub fn query_snapshot(
+    accounts: &HashMap<u16, Account>,
+    method: &str,
+    url: &str,
+) -> Result<String, HttpError> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    if method != "GET" {
+        return Err(HttpError::read_only());
+    }
+
+    let body = match segments.as_slice() {
+        ["accounts"] => {
+            let mut records: Vec<AccountRecord> = accounts
+                .iter()
+                .map(|(client_id, account)| AccountRecord::new(*client_id, account))
+                .collect();
+            records.sort_by_key(|record| record.client);
+
+            serde_json::to_string(&records)
+        }
+        ["accounts", client] => {
+            let client = parse_param("client", client)?;
+            let account = accounts.get(&client).ok_or_else(HttpError::not_found)?;
+
+            serde_json::to_string(&AccountRecord::new(client, account))
+        }
+        ["accounts", client, "transactions"] => {
+            let client = parse_param("client", client)?;
+            let account = accounts.get(&client).ok_or_else(HttpError::not_found)?;
+            let state = query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| *name == "state")
+                .map(|(name, value)| parse_param::<TransactionState>(name, value))
+                .transpose()?;
+
+            let mut entries: Vec<IndexEntry> = account
+                .successful_transactions
+                .iter()
+                .map(|(tx, transaction)| IndexEntry::new(client, *tx, transaction))
+                .filter(|entry| state.is_none_or(|state| entry.state == state))
+                .collect();
+            entries.sort_by_key(|entry| entry.tx);
+
+            serde_json::to_string(&entries)
+        }
+        _ => return Err(HttpError::not_found()),
+    };
+
+    body.map_err(|err| SourceError::Io(err.to_string()).into())
+}
+
+/// Dispatches a request to the endpoint for its path, returning the body of the response.
+/// Transactions and accounts are only served when there's a dispute desk.
+pub fn route(
+    journal_path: &Path,
+    disputes: Option<&mut DisputeDesk>,
+    method: &str,
+    url: &str,
+    body: &str,
+) -> Result<String, HttpError> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let body = match (method, segments.as_slice()) {
+        ("GET", ["accounts", client, "timeline"]) => {
+            let client = parse_param("client", client)?;
+            let page = timeline(journal_path, client, &TimelineQuery::parse(query)?)?;
+
+            serde_json::to_string(&page)
+        }
+        ("POST", ["disputes"]) => {
+            let disputes = disputes.ok_or_else(|| HttpError {
+                status: 405,
+                message: "Disputes can only be submitted when the server has loaded state"
+                    .to_string(),
+            })?;
+            let request: DisputeRequest = info_span!("parse")
+                .in_scope(|| serde_json::from_str(body))
+                .map_err(|err| HttpError::bad_request(format!("Invalid dispute: {}", err)))?;
+
+            serde_json::to_string(&disputes.open(&request)?)
+        }
+        ("POST", ["transactions"]) => {
+            let disputes = disputes.ok_or_else(HttpError::not_found)?;
+            let record: Record = info_span!("parse")
+                .in_scope(|| serde_json::from_str(body))
+                .map_err(|err| HttpError::bad_request(format!("Invalid transaction: {}", err)))?;
+
+            serde_json::to_string(&disputes.submit(&record)?)
+        }
+        ("GET", ["accounts"]) => {
+            let query = AccountsQuery::parse(query)?;
+            let disputes = disputes.ok_or_else(HttpError::not_found)?;
+
+            serde_json::to_string(&query.page(disputes.accounts()?))
+        }
+        ("GET", ["accounts", client]) => {
+            let client = parse_param("client", client)?;
+
+            serde_json::to_string(&disputes.ok_or_else(HttpError::not_found)?.account(client)?)
+        }
+        _ => return Err(HttpError::not_found()),
+    };
+
+    body.map_err(|err| SourceError::Io(err.to_string()).into())
+}
+
+/// Sends the body of a successful response as JSON, or the error
+fn respond(request: Request, response: Result<String, HttpError>) -> EngineResult<()> {
+    let (status, body) = match response {
+        Ok(body) => (200, body),
+        Err(err) => (
+            err.status,
+            serde_json::json!({ "error": err.message }).to_string(),
+        ),
+    };
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .expect("the content type header is valid");
+
+    request
+        .respond(
+            Response::from_string(body)
+                .with_status_code(status)
+                .with_header(content_type),
+        )
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+
+    Ok(())
+}
+
+/// Parses a parameter of the request
+fn parse_param<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, HttpError> {
+    value.parse().map_err(|_| invalid_param(name, value))
+}
+
+/// A parameter of the request has a value that can't be used
+fn invalid_param(name: &str, value: &str) -> HttpError {
+    HttpError::bad_request(format!("Invalid value for {}: {}", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::FixedClock;
+    use crate::config::EngineConfig;
+    use crate::engine::Engine;
+    use crate::error::LedgerError;
+    use crate::journal::{AccountEvent, Journal};
+    use crate::mapper::{Account, AccountRecord, Record, TransactionType};
+    use crate::server::{
+        query_snapshot, route, timeline, AccountsPage, AccountsQuery, DisputeDesk, HttpError,
+        TimelineQuery,
+    };
+    use crate::state::load_state;
+    use crate::test_helpers::LogBuffer;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::{Error, Write};
+    use std::path::Path;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    /// Writes the events to a journal file
+    fn write_journal(path: &Path, events: &[AccountEvent]) -> Result<(), Error> {
+        let mut file = File::create(path)?;
+        for event in events {
+            writeln!(file, "{}", serde_json::to_string(event)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates an event for a deposit, recorded at the given time
+    fn event(client: u16, tx: u32, recorded_at_ms: u64) -> AccountEvent {
+        AccountEvent {
+            recorded_at_ms,
+            ..AccountEvent::new(&Record::deposit(client, tx, 1.0), &Account::default())
+        }
+    }
+
+    // Tests that a client's events are filtered by time, ordered and paginated
+    #[test]
+    fn test_timeline() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join("journal.log");
+        write_journal(
+            &journal_path,
+            &[
+                event(1, 1, 10),
+                event(2, 2, 20),
+                event(1, 3, 40),
+                event(1, 4, 30),
+                event(1, 5, 50),
+            ],
+        )?;
+
+        let query = TimelineQuery::parse("from=20&to=50&limit=2").unwrap();
+        let page = timeline(&journal_path, 1, &query).unwrap();
+        let txs: Vec<u32> = page.events.iter().map(|event| event.tx).collect();
+        assert_eq!(txs, vec![4, 3]);
+        assert_eq!(page.next_offset, Some(2));
+
+        let query = TimelineQuery::parse("from=20&to=50&limit=2&offset=2").unwrap();
+        let page = timeline(&journal_path, 1, &query).unwrap();
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.next_offset, None);
+
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that invalid parameters and missing journals are reported with their status codes
+    #[test]
+    fn test_timeline_errors() {
+        assert_eq!(
+            TimelineQuery::parse("limit=ten").unwrap_err(),
+            HttpError {
+                status: 400,
+                message: "Invalid value for limit: ten".to_string(),
+            }
+        );
+        assert_eq!(TimelineQuery::parse("limit=5000").unwrap().limit, 1_000);
+
+        let missing = timeline(Path::new("missing.log"), 1, &TimelineQuery::default());
+        assert_eq!(missing.unwrap_err().status, 500);
+    }
+
+    // Tests that balances and transactions are answered from the snapshot, and that nothing can
+    // be changed through it
+    #[test]
+    fn test_query_snapshot() {
+        let mut account = Account::default();
+        account.deposit(10.0, 1);
+        account.deposit(5.0, 2);
+        account.dispute(2);
+        let accounts = HashMap::from([(1, account), (2, Account::default())]);
+
+        let balances = query_snapshot(&accounts, "GET", "/accounts/1").unwrap();
+        assert_eq!(
+            balances,
+            r#"{"client":1,"available":10.0,"held":5.0,"total":15.0,"locked":false}"#
+        );
+
+        let all = query_snapshot(&accounts, "GET", "/accounts").unwrap();
+        assert!(all.starts_with(r#"[{"client":1,"#));
+
+        let disputed =
+            query_snapshot(&accounts, "GET", "/accounts/1/transactions?state=disputed").unwrap();
+        assert_eq!(
+            disputed,
+            r#"[{"client":1,"tx":2,"amount":5.0,"state":"disputed","timestamp":null}]"#
+        );
+
+        assert_eq!(
+            query_snapshot(&accounts, "GET", "/accounts/3")
+                .unwrap_err()
+                .status,
+            404
+        );
+        assert_eq!(
+            query_snapshot(&accounts, "GET", "/accounts/1/transactions?state=lost")
+                .unwrap_err()
+                .status,
+            400
+        );
+        assert_eq!(
+            query_snapshot(&accounts, "POST", "/accounts/1").unwrap_err(),
+            HttpError {
+                status: 405,
+                message: "The snapshot is read-only".to_string(),
+            }
+        );
+    }
+
+    // Tests that accounts are filtered and sorted, and that the cursor picks up where the previous
+    // page left off, even when accounts are added in between
+    #[test]
+    fn test_accounts_query() {
+        let mut accounts = vec![];
+        for (client, held) in [(1, 5.0), (2, 0.0), (3, 7.5), (4, 5.0), (5, 1.0)] {
+            let mut account = Account::default();
+            account.deposit(held, 1);
+            account.dispute(1);
+            accounts.push(AccountRecord::new(client, &account));
+        }
+        let clients = |page: &AccountsPage| -> Vec<u16> {
+            page.accounts.iter().map(|record| record.client).collect()
+        };
+
+        let query = AccountsQuery::parse("sort=held&order=desc&min_held=1&limit=2").unwrap();
+        let page = query.page(&accounts);
+        assert_eq!(clients(&page), [3, 4]);
+        assert_eq!(page.next_cursor, Some("5:4".to_string()));
+
+        // a new account that sorts before the cursor doesn't shift the next page
+        let mut account = Account::default();
+        account.deposit(9.0, 1);
+        account.dispute(1);
+        accounts.push(AccountRecord::new(6, &account));
+
+        let query = AccountsQuery::parse("sort=held&order=desc&min_held=1&limit=2&cursor=5:4");
+        let page = query.unwrap().page(&accounts);
+        assert_eq!(clients(&page), [1, 5]);
+        assert_eq!(page.next_cursor, None);
+
+        let page = AccountsQuery::parse("locked=true").unwrap().page(&accounts);
+        assert!(page.accounts.is_empty());
+        let page = AccountsQuery::default().page(&accounts);
+        assert_eq!(clients(&page), [1, 2, 3, 4, 5, 6]);
+
+        assert_eq!(AccountsQuery::parse("sort=name").unwrap_err().status, 400);
+        assert_eq!(AccountsQuery::parse("cursor=5").unwrap_err().status, 400);
+        assert_eq!(AccountsQuery::parse("order=up").unwrap_err().status, 400);
+    }
+
+    // Tests that only the owner of a deposit that isn't disputed, and was made within the dispute
+    // window, can dispute it, and that the dispute is applied, journaled and saved
+    #[test]
+    fn test_submit_dispute() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join("journal.log");
+        let state_path = dir.path().join("state.bin");
+        let now_secs = 100 * 86_400;
+        let now_ms = now_secs * 1_000;
+
+        let mut client = Account::default();
+        client.deposit(10.0, 1);
+        client.deposit(5.0, 2);
+        client.withdraw(3.0, 3).unwrap();
+        client.deposit(2.0, 5);
+        client.void(5).unwrap();
+        client
+            .successful_transactions
+            .get_mut(&1)
+            .unwrap()
+            .timestamp = Some(now_secs - 86_400);
+        client
+            .successful_transactions
+            .get_mut(&2)
+            .unwrap()
+            .timestamp = Some(0);
+        let mut other = Account::default();
+        other.deposit(1.0, 4);
+        let accounts = HashMap::from([(1, client), (2, other)]);
+        let config = EngineConfig {
+            dispute_window: Some(30 * 86_400),
+            ..Default::default()
+        };
+
+        let clock = Arc::new(FixedClock::new(now_ms));
+        let journal = Journal::open(&journal_path)
+            .unwrap()
+            .with_clock(clock.clone());
+        let engine = Engine::new(accounts, config, journal);
+        let mut desk = DisputeDesk::new(engine, &journal_path, clock).with_save_state(&state_path);
+        let mut submit =
+            |body: &str| route(&journal_path, Some(&mut desk), "POST", "/disputes", body);
+
+        assert_eq!(
+            submit(r#"{"client":1,"tx":1}"#).unwrap(),
+            r#"{"client":1,"tx":1,"state":"disputed","held":10.0,"held_since":8640000}"#
+        );
+        assert_eq!(
+            submit(r#"{"client":1,"tx":1}"#).unwrap_err(),
+            HttpError {
+                status: 409,
+                message: "Transaction 1 is already disputed".to_string(),
+            }
+        );
+        assert_eq!(submit(r#"{"client":2,"tx":1}"#).unwrap_err().status, 403);
+        assert_eq!(submit(r#"{"client":1,"tx":9}"#).unwrap_err().status, 404);
+        // withdrawals can be disputed like they can in a batch run, voided transactions can't
+        assert_eq!(
+            submit(r#"{"client":1,"tx":3}"#).unwrap(),
+            r#"{"client":1,"tx":3,"state":"disputed","held":3.0,"held_since":8640000}"#
+        );
+        assert_eq!(submit(r#"{"client":1,"tx":5}"#).unwrap_err().status, 409);
+        // deposits made too long ago are outside the window, those without a timestamp aren't
+        assert_eq!(
+            submit(r#"{"client":1,"tx":2}"#).unwrap_err(),
+            HttpError {
+                status: 422,
+                message: LedgerError::DisputeWindowExpired(2, now_secs, 30 * 86_400).to_string(),
+            }
+        );
+        assert!(submit(r#"{"client":2,"tx":4}"#).is_ok());
+        assert_eq!(submit(r#"{"client":1}"#).unwrap_err().status, 400);
+
+        let saved = load_state(&state_path).unwrap();
+        assert_eq!(saved[&1].held_funds, 13.0);
+        assert_eq!(saved[&1].total_funds, 15.0);
+
+        let page = timeline(&journal_path, 1, &TimelineQuery::default()).unwrap();
+        let last = page.events.last().unwrap();
+        assert_eq!(
+            (last.tx, last.transaction_type),
+            (3, TransactionType::Dispute)
+        );
+
+        let without_desk = route(&journal_path, None, "POST", "/disputes", "{}");
+        assert_eq!(without_desk.unwrap_err().status, 405);
+
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that transactions are applied and journaled, and that balances are answered from the
+    // engine's current accounts
+    #[test]
+    fn test_transactions_api() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join("journal.log");
+        let clock = Arc::new(FixedClock::new(0));
+        let journal = Journal::open(&journal_path).unwrap();
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), journal);
+        let mut desk = DisputeDesk::new(engine, &journal_path, clock);
+        let mut request = |method: &str, url: &str, body: &str| {
+            route(&journal_path, Some(&mut desk), method, url, body)
+        };
+
+        assert_eq!(
+            request(
+                "POST",
+                "/transactions",
+                r#"{"type":"deposit","client":1,"tx":1,"amount":10}"#
+            )
+            .unwrap(),
+            r#"{"client":1,"available":10.0,"held":0.0,"total":10.0,"locked":false}"#
+        );
+        request(
+            "POST",
+            "/transactions",
+            r#"{"type":"deposit","client":2,"tx":2,"amount":1}"#,
+        )
+        .unwrap();
+        let overdraft = r#"{"type":"withdrawal","client":1,"tx":3,"amount":20}"#;
+        assert_eq!(
+            request("POST", "/transactions", overdraft)
+                .unwrap_err()
+                .status,
+            422
+        );
+        assert_eq!(
+            request("POST", "/transactions", "{}").unwrap_err().status,
+            400
+        );
+
+        assert!(request("GET", "/accounts/1", "")
+            .unwrap()
+            .contains(r#""available":10.0"#));
+        assert_eq!(request("GET", "/accounts/3", "").unwrap_err().status, 404);
+        let all = request("GET", "/accounts", "").unwrap();
+        assert!(all.starts_with(r#"{"accounts":[{"client":1,"#));
+        assert!(all.ends_with(r#""total":1.0,"locked":false}],"next_cursor":null}"#));
+
+        let page = timeline(&journal_path, 1, &TimelineQuery::default()).unwrap();
+        assert_eq!(page.events.len(), 1);
+
+        let without_desk = route(&journal_path, None, "GET", "/accounts", "");
+        assert_eq!(without_desk.unwrap_err().status, 404);
+
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that a transaction submitted to the API is traced through each of its stages in
+    // order, and that one that's rejected isn't persisted
+    #[test]
+    fn test_transaction_stages() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join("journal.log");
+        let journal = Journal::open(&journal_path).unwrap();
+        let engine = Engine::new(HashMap::new(), EngineConfig::default(), journal);
+        let mut desk = DisputeDesk::new(engine, &journal_path, Arc::new(FixedClock::new(0)));
+
+        let stages = |body: &str, desk: &mut DisputeDesk| {
+            let logs = LogBuffer::default();
+            let writer = logs.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_env_filter("plutus_engine=info")
+                .with_span_events(FmtSpan::CLOSE)
+                .with_writer(move || writer.clone())
+                .with_ansi(false)
+                .with_level(false)
+                .with_target(false)
+                .without_time()
+                .finish();
+            tracing::subscriber::with_default(subscriber, || {
+                let _ = route(&journal_path, Some(desk), "POST", "/transactions", body);
+            });
+
+            let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+            logs.lines()
+                .filter_map(|line| line.split(':').next().map(str::to_string))
+                .collect::<Vec<String>>()
+        };
+
+        let deposit = r#"{"type":"deposit","client":1,"tx":1,"amount":10}"#;
+        assert_eq!(
+            stages(deposit, &mut desk),
+            ["parse", "validate", "apply", "persist"]
+        );
+        let overdraft = r#"{"type":"withdrawal","client":1,"tx":2,"amount":20}"#;
+        assert_eq!(stages(overdraft, &mut desk), ["parse", "validate", "apply"]);
+        assert_eq!(stages("{}", &mut desk), ["parse"]);
+
+        dir.close()?;
+
+        Ok(())
+    }
+}
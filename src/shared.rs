@@ -0,0 +1,480 @@
+use crate::config::EngineConfig;
+use crate::engine::{
+    apply_sidecar_lock, check_destination, check_unsettled, process_transaction_record,
+    settled_transactions,
+};
+use crate::error::{EngineError, EngineResult, SourceError};
+use crate::idempotency::IdempotencyKeys;
+use crate::journal::{AccountEvent, Journal};
+use crate::mapper::{Account, AccountRecord, Record};
+use crate::owners::TransactionOwners;
+use crate::partition::{ClientPartitioner, HashPartitioner};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use tracing::info_span;
+
+/// The number of shards used by SharedEngine::default
+pub const DEFAULT_SHARD_COUNT: usize = 16;
+
+/// A handle to the engine that can be shared between threads (e.g. behind an `Arc`), so records
+/// can be processed from many request handlers at once.
+///
+/// Accounts are split into shards by client id, each behind its own RwLock, so records for
+/// clients in different shards are applied in parallel. Clients are hashed onto the shards unless
+/// another `ClientPartitioner` is provided.
+///
+/// Ordering guarantees:
+/// - records for the same client are applied one at a time, in the order their `process` calls
+///   acquire the client's shard
+/// - there is no ordering between records for different clients
+/// - events are journaled while the shard is held, so the journal preserves the order in which
+///   each client's records were applied
+/// - a transfer holds the shards of both of its clients while it's applied, taking them in shard
+///   order so transfers between the same clients in opposite directions can't deadlock
+/// - `accounts` reads one shard at a time, so it may observe some records applied in other
+///   shards while it runs, but never a partially applied record
+pub struct SharedEngine {
+    /// The client accounts, each shard holds the clients whose id maps to it
+    shards: Vec<RwLock<HashMap<u16, Account>>>,
+
+    /// Decides which shard holds each client
+    partitioner: Box<dyn ClientPartitioner>,
+
+    /// Settings that control how transactions are applied
+    config: EngineConfig,
+
+    /// Where applied transactions are journaled
+    journal: Mutex<Journal>,
+
+    /// The transactions that settled before the engine was created, which can no longer be voided
+    settled: HashSet<(u16, u32)>,
+
+    /// The client each transaction belongs to, shared by every shard so a transaction id can only
+    /// be used once across all of them
+    owners: RwLock<TransactionOwners>,
+
+    /// The idempotency keys of the records that have been applied
+    idempotency_keys: Mutex<IdempotencyKeys>,
+}
+
+impl Default for SharedEngine {
+    fn default() -> Self {
+        SharedEngine::new(
+            HashMap::new(),
+            DEFAULT_SHARD_COUNT,
+            EngineConfig::default(),
+            Journal::default(),
+        )
+    }
+}
+
+impl SharedEngine {
+    /// Creates an engine that applies transactions to the given accounts, split into a number of
+    /// shards (at least one)
+    pub fn new(
+        accounts: HashMap<u16, Account>,
+        shard_count: usize,
+        config: EngineConfig,
+        journal: Journal,
+    ) -> Self {
+        let settled = settled_transactions(&accounts);
+        let owners = RwLock::new(TransactionOwners::of(&accounts));
+        let accounts = accounts
+            .into_iter()
+            .map(|(client_id, mut account)| {
+                apply_sidecar_lock(&mut account, config.account_flags.get(&client_id));
+                (client_id, account)
+            })
+            .collect();
+
+        SharedEngine {
+            shards: partition(accounts, shard_count.max(1), &HashPartitioner),
+            partitioner: Box::new(HashPartitioner),
+            config,
+            journal: Mutex::new(journal),
+            settled,
+            owners,
+            idempotency_keys: Mutex::default(),
+        }
+    }
+
+    /// Partitions the clients onto the shards with the partitioner, rather than hashing them
+    pub fn with_partitioner(mut self, partitioner: impl ClientPartitioner + 'static) -> Self {
+        let count = self.shards.len();
+        let accounts = self
+            .shards
+            .drain(..)
+            // the engine hasn't been shared yet, so its locks can't have been poisoned
+            .flat_map(|shard| shard.into_inner().unwrap_or_else(|err| err.into_inner()))
+            .collect();
+
+        self.shards = partition(accounts, count, &partitioner);
+        self.partitioner = Box::new(partitioner);
+        self
+    }
+
+    /// Applies each idempotency key at most once, including the keys applied by earlier runs
+    pub fn with_idempotency_keys(mut self, keys: IdempotencyKeys) -> Self {
+        self.idempotency_keys = Mutex::new(keys);
+        self
+    }
+
+    /// Applies a record to its client's account. A LedgerError means the record was rejected and
+    /// the account is unchanged, any other error means the record couldn't be journaled.
+    ///
+    /// The id of a record that creates a transaction is reserved before it's applied, so the same
+    /// id sent to two shards at once is only applied once, and released if the record is rejected.
+    /// Records with an idempotency key are applied one at a time, for the same reason.
+    pub fn process(&self, record: &Record) -> EngineResult<()> {
+        let key = record.idempotency_key.as_deref();
+        let mut keys = match key {
+            Some(_) => Some(self.idempotency_keys.lock().map_err(poisoned)?),
+            None => None,
+        };
+        if let Some(keys) = keys.as_ref() {
+            keys.check(key, record.transaction_id)?;
+        }
+
+        let credit = info_span!("validate").in_scope(|| -> EngineResult<_> {
+            self.config.check_onboarded(record.client_id)?;
+            self.config
+                .screening
+                .screen(record.client_id, record.transaction_id)?;
+            let credit = record.transfer_credit()?;
+            let mut owners = self.owners.write().map_err(poisoned)?;
+            owners.check_owner(record)?;
+            owners.reserve(record)?;
+
+            Ok(credit)
+        })?;
+
+        let result = self.apply(record, credit);
+        if matches!(result, Err(EngineError::Ledger(_))) {
+            self.owners.write().map_err(poisoned)?.release(record);
+        }
+        if let (Ok(()), Some(keys)) = (&result, keys.as_mut()) {
+            keys.insert(key)?;
+        }
+
+        result
+    }
+
+    /// Applies a record whose transaction id has been reserved, along with the side of it that's
+    /// applied to the destination when it's a transfer
+    fn apply(&self, record: &Record, credit: Option<Record>) -> EngineResult<()> {
+        if let Some(credit) = credit {
+            return info_span!("apply").in_scope(|| self.process_transfer(record, &credit));
+        }
+
+        // the shard is held until the record is journaled, so the journal keeps the client's order
+        let shard = info_span!("apply").in_scope(|| -> EngineResult<_> {
+            let mut shard = self.shard(record.client_id).write().map_err(poisoned)?;
+            let account = shard.entry(record.client_id).or_default();
+            apply_sidecar_lock(account, self.config.account_flags.get(&record.client_id));
+
+            check_unsettled(record, account, &self.settled)?;
+            process_transaction_record(record, account, &self.config)?;
+            Ok(shard)
+        })?;
+
+        info_span!("persist").in_scope(|| {
+            self.journal
+                .lock()
+                .map_err(poisoned)?
+                .record(AccountEvent::new(record, &shard[&record.client_id]))?;
+
+            Ok(())
+        })
+    }
+
+    /// Applies a transfer to the accounts of both of its clients, debiting the source and
+    /// crediting the destination, or changing neither
+    fn process_transfer(&self, record: &Record, credit: &Record) -> EngineResult<()> {
+        self.config.check_onboarded(credit.client_id)?;
+        self.config
+            .screening
+            .screen(credit.client_id, credit.transaction_id)?;
+
+        // the shards are held in order, both clients may be in the same one
+        let source = self.shard_index(record.client_id);
+        let destination = self.shard_index(credit.client_id);
+        let first = source.min(destination);
+        let mut shards = vec![self.shards[first].write().map_err(poisoned)?];
+        if source != destination {
+            shards.push(
+                self.shards[source.max(destination)]
+                    .write()
+                    .map_err(poisoned)?,
+            );
+        }
+        let held = |index: usize| usize::from(index != first);
+
+        // an unknown destination is only opened once its source has been debited, so a rejected
+        // transfer doesn't add an account
+        let mut unknown = Account::default();
+        let account = shards[held(destination)]
+            .get_mut(&credit.client_id)
+            .unwrap_or(&mut unknown);
+        apply_sidecar_lock(account, self.config.account_flags.get(&credit.client_id));
+        check_destination(credit, account, &self.config)?;
+
+        let mut journal = self.journal.lock().map_err(poisoned)?;
+        for (side, shard) in [(record, source), (credit, destination)] {
+            let flags = self.config.account_flags.get(&side.client_id);
+            let account = shards[held(shard)].entry(side.client_id).or_default();
+            apply_sidecar_lock(account, flags);
+            process_transaction_record(side, account, &self.config)?;
+            journal.record(AccountEvent::new(side, account))?;
+        }
+
+        Ok(())
+    }
+
+    /// The current output record of a client's account, if the client has been seen
+    pub fn account(&self, client_id: u16) -> EngineResult<Option<AccountRecord>> {
+        let shard = self.shard(client_id).read().map_err(poisoned)?;
+
+        Ok(shard
+            .get(&client_id)
+            .map(|account| AccountRecord::new(client_id, account)))
+    }
+
+    /// The current output records of every account, ordered by client id
+    pub fn accounts(&self) -> EngineResult<Vec<AccountRecord>> {
+        let mut records = vec![];
+
+        for shard in self.shards.iter() {
+            let shard = shard.read().map_err(poisoned)?;
+            records.extend(
+                shard
+                    .iter()
+                    .map(|(client_id, account)| AccountRecord::new(*client_id, account)),
+            );
+        }
+
+        records.sort_by_key(|record| record.client);
+
+        Ok(records)
+    }
+
+    /// Finishes processing, returning the client accounts
+    pub fn into_accounts(self) -> EngineResult<HashMap<u16, Account>> {
+        self.journal.into_inner().map_err(poisoned)?.flush()?;
+
+        let mut accounts = HashMap::new();
+        for shard in self.shards {
+            accounts.extend(shard.into_inner().map_err(poisoned)?);
+        }
+
+        Ok(accounts)
+    }
+
+    /// The shard that holds a client's account
+    fn shard(&self, client_id: u16) -> &RwLock<HashMap<u16, Account>> {
+        &self.shards[self.shard_index(client_id)]
+    }
+
+    /// The position of the shard that holds a client's account
+    fn shard_index(&self, client_id: u16) -> usize {
+        self.partitioner.partition(client_id, self.shards.len())
+    }
+}
+
+/// Splits the accounts into a number of shards with the partitioner
+fn partition(
+    accounts: HashMap<u16, Account>,
+    count: usize,
+    partitioner: &dyn ClientPartitioner,
+) -> Vec<RwLock<HashMap<u16, Account>>> {
+    let mut shards: Vec<HashMap<u16, Account>> = vec![HashMap::new(); count];
+    for (client_id, account) in accounts {
+        shards[partitioner.partition(client_id, count)].insert(client_id, account);
+    }
+
+    shards.into_iter().map(RwLock::new).collect()
+}
+
+/// A lock is poisoned when a thread panicked while holding it, the data it guards can't be trusted
+fn poisoned<T>(_err: T) -> SourceError {
+    SourceError::Io("a thread panicked while processing a record".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EngineConfig;
+    use crate::error::{EngineError, LedgerError};
+    use crate::journal::Journal;
+    use crate::mapper::{Account, Record};
+    use crate::partition::RangePartitioner;
+    use crate::shared::SharedEngine;
+    use approx::assert_relative_eq;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Tests that records processed from many threads at once are all applied to their accounts
+    #[test]
+    fn test_process_from_many_threads() {
+        let engine = Arc::new(SharedEngine::default());
+
+        let handles: Vec<_> = (0..8u32)
+            .map(|thread_id| {
+                let engine = Arc::clone(&engine);
+
+                thread::spawn(move || {
+                    for i in 0..100u32 {
+                        let client_id = (i % 20) as u16;
+                        engine
+                            .process(&Record::deposit(client_id, thread_id * 100 + i, 1.5))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let records = engine.accounts().unwrap();
+        assert_eq!(records.len(), 20);
+        assert!(records
+            .windows(2)
+            .all(|pair| pair[0].client < pair[1].client));
+        for record in records.iter() {
+            assert_relative_eq!(record.total, 60.0);
+        }
+
+        let accounts = Arc::try_unwrap(engine)
+            .ok()
+            .unwrap()
+            .into_accounts()
+            .unwrap();
+        assert_eq!(accounts.len(), 20);
+        assert_eq!(accounts[&3].successful_transactions.len(), 40);
+    }
+
+    // Tests that rejected records leave the account unchanged
+    #[test]
+    fn test_process_rejected_record() {
+        let engine = SharedEngine::default();
+        engine.process(&Record::deposit(1, 1, 10.0)).unwrap();
+
+        let result = engine.process(&Record::withdrawal(1, 2, 15.0));
+
+        assert_eq!(
+            result,
+            Err(EngineError::Ledger(LedgerError::InsufficientFunds(
+                15.0, 10.0
+            )))
+        );
+        assert_relative_eq!(engine.account(1).unwrap().unwrap().available, 10.0);
+        assert_eq!(engine.account(2).unwrap(), None);
+    }
+
+    // Tests that a deposit replayed with the same transaction id, from any client, is rejected
+    // without crediting the account again, and that a rejected record doesn't use up its id
+    #[test]
+    fn test_process_duplicate_transaction() {
+        let ranges = RangePartitioner::new(vec![0, 1000]).unwrap();
+        let engine = SharedEngine::new(
+            HashMap::new(),
+            2,
+            EngineConfig::default(),
+            Journal::default(),
+        )
+        .with_partitioner(ranges);
+        engine.process(&Record::deposit(1, 1, 10.0)).unwrap();
+
+        let duplicate = Err(EngineError::Ledger(LedgerError::DuplicateTransaction(1)));
+        assert_eq!(engine.process(&Record::deposit(1, 1, 10.0)), duplicate);
+        assert_eq!(engine.process(&Record::deposit(1500, 1, 10.0)), duplicate);
+        assert_relative_eq!(engine.account(1).unwrap().unwrap().available, 10.0);
+        assert_eq!(engine.account(1500).unwrap(), None);
+
+        assert!(engine.process(&Record::withdrawal(1, 2, 50.0)).is_err());
+        engine.process(&Record::withdrawal(1, 2, 5.0)).unwrap();
+        assert_relative_eq!(engine.account(1).unwrap().unwrap().available, 5.0);
+    }
+
+    // Tests that disputes, resolves and chargebacks of another client's transaction are rejected,
+    // even when the clients are in different shards
+    #[test]
+    fn test_process_client_mismatch() {
+        let ranges = RangePartitioner::new(vec![0, 1000]).unwrap();
+        let engine = SharedEngine::new(
+            HashMap::new(),
+            2,
+            EngineConfig::default(),
+            Journal::default(),
+        )
+        .with_partitioner(ranges);
+        engine.process(&Record::deposit(1, 1, 10.0)).unwrap();
+        engine.process(&Record::deposit(1500, 2, 5.0)).unwrap();
+
+        for record in [
+            Record::dispute(1500, 1),
+            Record::resolve(1500, 1),
+            Record::chargeback(1500, 1),
+        ] {
+            assert_eq!(
+                engine.process(&record),
+                Err(EngineError::Ledger(LedgerError::ClientMismatch(1, 1500, 1)))
+            );
+        }
+        assert_relative_eq!(engine.account(1).unwrap().unwrap().held, 0.0);
+
+        engine.process(&Record::dispute(1, 1)).unwrap();
+        assert_relative_eq!(engine.account(1).unwrap().unwrap().held, 10.0);
+    }
+
+    // Tests that a partitioner moves the accounts onto its shards, and that records are applied
+    // to the shard it chooses
+    #[test]
+    fn test_with_partitioner() {
+        let mut account = Account::default();
+        account.deposit(5.0, 1);
+        let accounts = HashMap::from([(1500, account)]);
+        let ranges = RangePartitioner::new(vec![0, 1000]).unwrap();
+        let engine = SharedEngine::new(accounts, 2, EngineConfig::default(), Journal::default())
+            .with_partitioner(ranges);
+
+        assert!(engine.shards[1].read().unwrap().contains_key(&1500));
+        engine.process(&Record::deposit(999, 2, 1.0)).unwrap();
+        engine.process(&Record::deposit(1500, 3, 1.0)).unwrap();
+        assert!(engine.shards[0].read().unwrap().contains_key(&999));
+        assert_relative_eq!(engine.account(1500).unwrap().unwrap().available, 6.0);
+    }
+
+    // Tests that a transfer between clients in different shards is applied to both accounts, and
+    // that a rejected one leaves both unchanged
+    #[test]
+    fn test_process_transfer() {
+        let ranges = RangePartitioner::new(vec![0, 1000]).unwrap();
+        let engine = SharedEngine::new(
+            HashMap::new(),
+            2,
+            EngineConfig::default(),
+            Journal::default(),
+        )
+        .with_partitioner(ranges);
+        engine.process(&Record::deposit(1500, 1, 10.0)).unwrap();
+
+        engine.process(&Record::transfer(1500, 2, 4.0, 1)).unwrap();
+        engine.process(&Record::transfer(1, 3, 1.5, 1500)).unwrap();
+        assert_eq!(
+            engine.process(&Record::transfer(1, 4, 5.0, 1500)),
+            Err(EngineError::Ledger(LedgerError::InsufficientFunds(
+                5.0, 2.5
+            )))
+        );
+
+        assert_relative_eq!(engine.account(1).unwrap().unwrap().available, 2.5);
+        assert_relative_eq!(engine.account(1500).unwrap().unwrap().available, 7.5);
+
+        // the destination isn't opened when the transfer to it fails
+        assert!(engine.process(&Record::transfer(1, 5, 50.0, 7)).is_err());
+        assert_eq!(engine.account(7).unwrap(), None);
+        assert_eq!(engine.accounts().unwrap().len(), 2);
+    }
+}
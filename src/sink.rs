@@ -0,0 +1,245 @@
+use crate::currency::Currency;
+use crate::error::{EngineResult, SourceError};
+use crate::fields::{FieldFormatter, Formatted};
+use crate::mapper::{Account, AccountRecord, OutputFormat};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
+
+/// Somewhere the accounts are written to once a run finishes, e.g. a file, std out, a database or
+/// a network service. Sinks take the output records of the accounts (AccountRecord unless another
+/// version of the output is chosen), in no particular order.
+pub trait OutputSink<T: Serialize = AccountRecord> {
+    /// Writes a client's account to the sink
+    fn write_account(&mut self, record: &T) -> EngineResult<()>;
+
+    /// Ensures every account has been written, once the last one has been
+    fn finish(&mut self) -> EngineResult<()>;
+}
+
+/// Writes accounts as comma separated values, with a header row
+pub struct CsvSink<'a, W: Write> {
+    /// Where the accounts are written to
+    writer: csv::Writer<W>,
+
+    /// Renders the accounts' amounts and booleans, when there is one
+    fields: Option<&'a dyn FieldFormatter>,
+}
+
+impl CsvSink<'_, Stdout> {
+    /// Creates a sink that writes to std out, the default output
+    pub fn stdout() -> Self {
+        CsvSink::new(io::stdout(), None)
+    }
+}
+
+impl<'a, W: Write> CsvSink<'a, W> {
+    /// Creates a sink that writes to the given writer, rendering fields with the formatter
+    pub fn new(output: W, fields: Option<&'a dyn FieldFormatter>) -> Self {
+        CsvSink {
+            writer: csv::Writer::from_writer(output),
+            fields,
+        }
+    }
+}
+
+impl<T: Serialize, W: Write> OutputSink<T> for CsvSink<'_, W> {
+    fn write_account(&mut self, record: &T) -> EngineResult<()> {
+        self.writer
+            .serialize(Formatted::new(record, self.fields))
+            .map_err(SourceError::from)?;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> EngineResult<()> {
+        self.writer
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Writes accounts as a single JSON array, which is closed once every account has been written
+pub struct JsonSink<'a, W: Write> {
+    /// Where the accounts are written to
+    output: W,
+
+    /// The number of accounts written so far
+    written: usize,
+
+    /// Renders the accounts' amounts and booleans, when there is one
+    fields: Option<&'a dyn FieldFormatter>,
+}
+
+impl<'a, W: Write> JsonSink<'a, W> {
+    /// Creates a sink that writes to the given writer, rendering fields with the formatter
+    pub fn new(output: W, fields: Option<&'a dyn FieldFormatter>) -> Self {
+        JsonSink {
+            output,
+            written: 0,
+            fields,
+        }
+    }
+}
+
+impl<T: Serialize, W: Write> OutputSink<T> for JsonSink<'_, W> {
+    fn write_account(&mut self, record: &T) -> EngineResult<()> {
+        let separator = if self.written == 0 { "[" } else { "," };
+        self.output
+            .write_all(separator.as_bytes())
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+        serde_json::to_writer(&mut self.output, &Formatted::new(record, self.fields))
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+        self.written += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> EngineResult<()> {
+        let end = if self.written == 0 { "[]" } else { "]" };
+        writeln!(self.output, "{}", end)
+            .and_then(|_| self.output.flush())
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Writes each account as a JSON object, one per line
+pub struct JsonlSink<'a, W: Write> {
+    /// Where the accounts are written to
+    output: W,
+
+    /// Renders the accounts' amounts and booleans, when there is one
+    fields: Option<&'a dyn FieldFormatter>,
+}
+
+impl<'a, W: Write> JsonlSink<'a, W> {
+    /// Creates a sink that writes to the given writer, rendering fields with the formatter
+    pub fn new(output: W, fields: Option<&'a dyn FieldFormatter>) -> Self {
+        JsonlSink { output, fields }
+    }
+}
+
+impl<T: Serialize, W: Write> OutputSink<T> for JsonlSink<'_, W> {
+    fn write_account(&mut self, record: &T) -> EngineResult<()> {
+        serde_json::to_writer(&mut self.output, &Formatted::new(record, self.fields))
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+        writeln!(self.output).map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> EngineResult<()> {
+        self.output
+            .flush()
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Writes every account to the sink, then finishes it. Balances are rounded to the currency's minor
+/// unit, when there is one.
+pub fn write_accounts_to(
+    sink: &mut dyn OutputSink,
+    account_map: &HashMap<u16, Account>,
+    currency: Option<&Currency>,
+) -> EngineResult<()> {
+    for (client_id, account) in account_map {
+        let record = AccountRecord::new(*client_id, account);
+        match currency {
+            Some(currency) => sink.write_account(&record.in_currency(currency))?,
+            None => sink.write_account(&record)?,
+        }
+    }
+
+    sink.finish()
+}
+
+/// The sink that writes the output format to the writer, rendering fields with the formatter
+pub fn output_sink<'a, T: Serialize>(
+    output: impl Write + 'a,
+    format: OutputFormat,
+    fields: Option<&'a dyn FieldFormatter>,
+) -> Box<dyn OutputSink<T> + 'a> {
+    match format {
+        OutputFormat::Csv => Box::new(CsvSink::new(output, fields)),
+        OutputFormat::Json => Box::new(JsonSink::new(output, fields)),
+        OutputFormat::Jsonl => Box::new(JsonlSink::new(output, fields)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::EngineResult;
+    use crate::mapper::{Account, AccountRecord, OutputFormat};
+    use crate::sink::{output_sink, write_accounts_to, OutputSink};
+    use std::collections::HashMap;
+
+    /// Keeps the accounts it's given, as a database or network sink would send them on
+    #[derive(Default)]
+    struct CollectingSink {
+        /// The accounts that have been written
+        records: Vec<AccountRecord>,
+
+        /// Whether the sink has been finished
+        finished: bool,
+    }
+
+    impl OutputSink for CollectingSink {
+        fn write_account(&mut self, record: &AccountRecord) -> EngineResult<()> {
+            self.records.push(record.clone());
+            Ok(())
+        }
+
+        fn finish(&mut self) -> EngineResult<()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    // Tests that each output format's sink writes the accounts, and that other sinks can be used
+    // in their place
+    #[test]
+    fn test_output_sinks() {
+        let record = AccountRecord {
+            client: 1,
+            available: 1.5,
+            held: 0.0,
+            total: 1.5,
+            locked: false,
+        };
+        let write = |format| {
+            let mut output = vec![];
+            let mut sink = output_sink(&mut output, format, None);
+            sink.write_account(&record).unwrap();
+            sink.write_account(&record).unwrap();
+            sink.finish().unwrap();
+            drop(sink);
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            write(OutputFormat::Csv),
+            "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n1,1.5,0.0,1.5,false\n"
+        );
+        let object = r#"{"client":1,"available":1.5,"held":0.0,"total":1.5,"locked":false}"#;
+        assert_eq!(
+            write(OutputFormat::Json),
+            format!("[{},{}]\n", object, object)
+        );
+        assert_eq!(
+            write(OutputFormat::Jsonl),
+            format!("{}\n{}\n", object, object)
+        );
+
+        let mut sink = CollectingSink::default();
+        let accounts = HashMap::from([(1, Account::with_balances(1.5, 0.0))]);
+        write_accounts_to(&mut sink, &accounts, None).unwrap();
+        assert_eq!(sink.records, vec![record]);
+        assert!(sink.finished);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::error::{SourceError, SourceResult};
+use crate::mapper::{Account, Transaction, TransactionType};
+use crate::storage::TieredAccounts;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Where transactions that haven't been referenced for a while are kept, outside of their account.
+/// Stores must be Send, so the engine can be shared between threads.
+pub trait TransactionStore: Send {
+    /// Stores a client's transaction until it's taken back
+    fn spill(
+        &mut self,
+        client_id: u16,
+        transaction_id: u32,
+        transaction: &Transaction,
+    ) -> SourceResult<()>;
+
+    /// Removes a client's transaction from the store, if it was spilled
+    fn take(&mut self, client_id: u16, transaction_id: u32) -> SourceResult<Option<Transaction>>;
+
+    /// Removes every transaction from the store, along with the client and id of each
+    fn drain(&mut self) -> SourceResult<Vec<(u16, u32, Transaction)>>;
+
+    /// The number of transactions in the store
+    fn len(&self) -> usize;
+
+    /// Whether the store is empty
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Appends spilled transactions to a temporary file, keeping only where each one was written in
+/// memory. The file is deleted once the store is dropped.
+pub struct DiskStore {
+    /// The file transactions are appended to
+    file: File,
+
+    /// The offset and length of each transaction that's in the file
+    index: HashMap<(u16, u32), (u64, u32)>,
+
+    /// The length of the file
+    end: u64,
+}
+
+impl DiskStore {
+    /// Creates a store backed by a temporary file in the directory, or the system's temporary
+    /// directory when one isn't provided
+    pub fn new(dir: Option<&Path>) -> SourceResult<Self> {
+        let file = match dir {
+            Some(dir) => tempfile::tempfile_in(dir),
+            None => tempfile::tempfile(),
+        }
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        Ok(DiskStore {
+            file,
+            index: HashMap::new(),
+            end: 0,
+        })
+    }
+
+    /// Reads the transaction that was written at the offset
+    fn read(&mut self, offset: u64, len: u32) -> SourceResult<Transaction> {
+        let mut bytes = vec![0; len as usize];
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.read_exact(&mut bytes))
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        bincode::deserialize(&bytes).map_err(|err| SourceError::Io(err.to_string()))
+    }
+}
+
+impl TransactionStore for DiskStore {
+    fn spill(
+        &mut self,
+        client_id: u16,
+        transaction_id: u32,
+        transaction: &Transaction,
+    ) -> SourceResult<()> {
+        let bytes =
+            bincode::serialize(transaction).map_err(|err| SourceError::Io(err.to_string()))?;
+        self.file
+            .seek(SeekFrom::Start(self.end))
+            .and_then(|_| self.file.write_all(&bytes))
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+
+        // the space of transactions that are taken back isn't reused
+        self.index
+            .insert((client_id, transaction_id), (self.end, bytes.len() as u32));
+        self.end += bytes.len() as u64;
+
+        Ok(())
+    }
+
+    fn take(&mut self, client_id: u16, transaction_id: u32) -> SourceResult<Option<Transaction>> {
+        match self.index.remove(&(client_id, transaction_id)) {
+            Some((offset, len)) => Ok(Some(self.read(offset, len)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn drain(&mut self) -> SourceResult<Vec<(u16, u32, Transaction)>> {
+        let mut transactions = vec![];
+        for ((client_id, transaction_id), (offset, len)) in std::mem::take(&mut self.index) {
+            transactions.push((client_id, transaction_id, self.read(offset, len)?));
+        }
+
+        Ok(transactions)
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+/// Caps the number of transactions that are kept in their accounts. The transactions that were
+/// referenced least recently are moved to the store once there are too many, and moved back when
+/// a row references them, so disputes still find them. Disputed transactions are never spilled,
+/// as the account's open disputes are checked on every withdrawal.
+pub struct TransactionSpill {
+    /// The most transactions that are kept in their accounts
+    max_resident: usize,
+
+    /// When each transaction that's kept in its account was last referenced
+    resident: HashMap<(u16, u32), u64>,
+
+    /// The transactions in the order they were referenced. A transaction that's been referenced
+    /// again since has a stale entry, which is skipped.
+    order: VecDeque<(u16, u32, u64)>,
+
+    /// Where the spilled transactions are kept
+    store: Box<dyn TransactionStore>,
+
+    /// The number of references that have been made
+    clock: u64,
+}
+
+impl TransactionSpill {
+    /// Keeps at most the given number of transactions in their accounts, spilling the rest to the
+    /// store
+    pub fn new(max_resident: usize, store: Box<dyn TransactionStore>) -> Self {
+        TransactionSpill {
+            max_resident,
+            resident: HashMap::new(),
+            order: VecDeque::new(),
+            store,
+            clock: 0,
+        }
+    }
+
+    /// Moves a spilled transaction back into its account, ahead of a row that references it
+    pub fn restore(
+        &mut self,
+        client_id: u16,
+        transaction_id: u32,
+        account: &mut Account,
+    ) -> SourceResult<()> {
+        if let Some(transaction) = self.store.take(client_id, transaction_id)? {
+            account
+                .successful_transactions
+                .insert(transaction_id, transaction);
+        }
+
+        Ok(())
+    }
+
+    /// Marks a transaction as the one referenced most recently
+    pub fn touch(&mut self, client_id: u16, transaction_id: u32) {
+        self.clock += 1;
+        self.resident
+            .insert((client_id, transaction_id), self.clock);
+        self.order
+            .push_back((client_id, transaction_id, self.clock));
+    }
+
+    /// Spills the transactions that were referenced least recently, until no more than the
+    /// maximum are kept in their accounts. Transactions of accounts in the cold tier are already
+    /// compacted, so they're left where they are.
+    pub fn evict(&mut self, accounts: &mut TieredAccounts) -> SourceResult<()> {
+        while self.resident.len() > self.max_resident {
+            let Some((client_id, transaction_id, touched)) = self.order.pop_front() else {
+                break;
+            };
+            let key = (client_id, transaction_id);
+            if self.resident.get(&key) != Some(&touched) {
+                continue;
+            }
+            self.resident.remove(&key);
+
+            let Some(account) = accounts.get_hot_mut(client_id) else {
+                continue;
+            };
+            let disputed = account
+                .successful_transactions
+                .get(&transaction_id)
+                .is_some_and(|transaction| transaction.current_state == TransactionType::Dispute);
+            if disputed {
+                continue;
+            }
+
+            if let Some(transaction) = account.successful_transactions.remove(&transaction_id) {
+                self.store.spill(client_id, transaction_id, &transaction)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves every spilled transaction back into its account
+    pub fn restore_all(&mut self, accounts: &mut TieredAccounts) -> SourceResult<()> {
+        for (client_id, transaction_id, transaction) in self.store.drain()? {
+            accounts
+                .get_mut(client_id)?
+                .successful_transactions
+                .insert(transaction_id, transaction);
+        }
+
+        Ok(())
+    }
+
+    /// The number of transactions that are spilled
+    pub fn spilled(&self) -> usize {
+        self.store.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::Account;
+    use crate::spill::{DiskStore, TransactionSpill, TransactionStore};
+    use crate::storage::TieredAccounts;
+    use std::collections::HashMap;
+
+    // Tests that transactions are spilled to disk and taken back unchanged
+    #[test]
+    fn test_disk_store() {
+        let mut account = Account::default();
+        account.deposit(10.0, 1);
+        account.deposit(2.5, 2);
+        account.dispute(2);
+
+        let mut store = DiskStore::new(None).unwrap();
+        for (transaction_id, transaction) in &account.successful_transactions {
+            store.spill(1, *transaction_id, transaction).unwrap();
+        }
+        assert_eq!(store.len(), 2);
+
+        let transaction = store.take(1, 2).unwrap();
+        assert_eq!(
+            transaction.as_ref(),
+            account.successful_transactions.get(&2)
+        );
+        assert_eq!(store.take(1, 2).unwrap(), None);
+        assert_eq!(store.take(2, 1).unwrap(), None);
+
+        let drained = store.drain().unwrap();
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].2, account.successful_transactions[&1]);
+        assert!(store.is_empty());
+    }
+
+    // Tests that the least recently referenced transactions are spilled, except for disputed ones,
+    // and that they're restored when they're referenced or the run finishes
+    #[test]
+    fn test_transaction_spill() {
+        let mut accounts = TieredAccounts::new(HashMap::new());
+        let mut spill = TransactionSpill::new(1, Box::new(DiskStore::new(None).unwrap()));
+
+        for transaction_id in 1..=3 {
+            accounts.get_mut(1).unwrap().deposit(1.0, transaction_id);
+            spill.touch(1, transaction_id);
+        }
+        accounts.get_mut(1).unwrap().dispute(1);
+        spill.evict(&mut accounts).unwrap();
+
+        let account = accounts.get_mut(1).unwrap();
+        let mut resident: Vec<u32> = account.successful_transactions.keys().copied().collect();
+        resident.sort();
+        assert_eq!(resident, [1, 3]);
+        assert_eq!(spill.spilled(), 1);
+
+        spill.restore(1, 2, account).unwrap();
+        account.dispute(2);
+        assert_eq!(account.held_funds, 2.0);
+        assert_eq!(spill.spilled(), 0);
+
+        // the dispute referenced 2 more recently than 3
+        spill.touch(1, 2);
+        spill.evict(&mut accounts).unwrap();
+        assert_eq!(spill.spilled(), 1);
+
+        for transaction_id in 4..=5 {
+            accounts.get_mut(1).unwrap().deposit(1.0, transaction_id);
+            spill.touch(1, transaction_id);
+        }
+        spill.evict(&mut accounts).unwrap();
+        assert_eq!(spill.spilled(), 2);
+
+        spill.restore_all(&mut accounts).unwrap();
+        let accounts = accounts.into_accounts().unwrap();
+        assert_eq!(accounts[&1].successful_transactions.len(), 5);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::error::{CliError, CliResult, EngineResult, SourceError, SourceResult};
+use crate::mapper::{Account, AccountDiff, AccountRecord};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Marks binary state as versioned, older engines wrote the accounts without it
+const STATE_MAGIC: &[u8] = b"PLUTUS";
+
+/// The version of the binary state format that's written. Versions 1 to 4 were written by older
+/// engines without a header, and they're upgraded with migrate-state along with versions 5 to 8.
+pub const STATE_VERSION: u16 = 9;
+
+/// The length of the header that versioned binary state starts with, the magic and the version
+pub(crate) const STATE_HEADER_LEN: usize = STATE_MAGIC.len() + 2;
+
+/// The formats that account state can be saved in
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum StateFormat {
+    /// A compact binary encoding, used by --load-state and --save-state
+    Binary,
+
+    /// Human readable JSON, for inspecting and hand crafting state
+    #[default]
+    Json,
+}
+
+impl FromStr for StateFormat {
+    type Err = CliError;
+
+    fn from_str(format: &str) -> CliResult<Self> {
+        match format.to_lowercase().as_str() {
+            "bin" | "binary" => Ok(StateFormat::Binary),
+            "json" => Ok(StateFormat::Json),
+            _ => Err(CliError::UnknownFormat(format.to_string())),
+        }
+    }
+}
+
+/// Loads the client accounts that were saved at the end of a previous run
+pub fn load_state(file_path: impl AsRef<Path>) -> SourceResult<HashMap<u16, Account>> {
+    load_state_as(file_path, StateFormat::Binary)
+}
+
+/// Saves the client accounts, including the transactions needed to dispute them in a later run
+pub fn save_state(
+    file_path: impl AsRef<Path>,
+    account_map: &HashMap<u16, Account>,
+) -> SourceResult<()> {
+    save_state_as(file_path, account_map, StateFormat::Binary)
+}
+
+/// Loads client accounts from a file in the given format
+pub fn load_state_as(
+    file_path: impl AsRef<Path>,
+    format: StateFormat,
+) -> SourceResult<HashMap<u16, Account>> {
+    let file_path = file_path.as_ref();
+    let file = File::open(file_path).map_err(|err| state_error(file_path, err))?;
+
+    read_state(BufReader::new(file), format).map_err(|err| state_error(file_path, err))
+}
+
+/// Saves client accounts to a file in the given format
+pub fn save_state_as(
+    file_path: impl AsRef<Path>,
+    account_map: &HashMap<u16, Account>,
+    format: StateFormat,
+) -> SourceResult<()> {
+    let file_path = file_path.as_ref();
+    let file = File::create(file_path).map_err(|err| state_error(file_path, err))?;
+
+    write_state(BufWriter::new(file), account_map, format)
+        .map_err(|err| state_error(file_path, err))
+}
+
+/// Converts a binary state file to another format, writing to std out when there's no output file
+pub fn export_state(
+    state_path: &Path,
+    output_path: Option<&Path>,
+    format: StateFormat,
+) -> EngineResult<()> {
+    let account_map = load_state(state_path)?;
+
+    match output_path {
+        Some(output_path) => save_state_as(output_path, &account_map, format)?,
+        None => write_state(io::stdout(), &account_map, format)
+            .map_err(|err| state_error(Path::new("std out"), err))?,
+    }
+
+    Ok(())
+}
+
+/// Converts a state file in the given format to the binary format used by --load-state
+pub fn import_state(
+    input_path: &Path,
+    output_path: Option<&Path>,
+    format: StateFormat,
+) -> EngineResult<()> {
+    let usage = "plutus import-state state.json state.bin";
+    let output_path = output_path.ok_or_else(|| CliError::MissingOutputPath(usage.to_string()))?;
+    let account_map = load_state_as(input_path, format)?;
+
+    save_state(output_path, &account_map)?;
+
+    Ok(())
+}
+
+/// Decodes client accounts in the given format
+pub(crate) fn read_state(
+    mut reader: impl Read,
+    format: StateFormat,
+) -> Result<HashMap<u16, Account>, String> {
+    match format {
+        StateFormat::Binary => {
+            let mut bytes = vec![];
+            reader
+                .read_to_end(&mut bytes)
+                .map_err(|err| err.to_string())?;
+            decode_binary(&bytes)
+        }
+        StateFormat::Json => serde_json::from_reader(reader).map_err(|err| err.to_string()),
+    }
+}
+
+/// Encodes client accounts in the given format. Accounts are ordered by client id, so the same
+/// state is always encoded in the same way.
+pub(crate) fn write_state(
+    mut writer: impl Write,
+    account_map: &HashMap<u16, Account>,
+    format: StateFormat,
+) -> Result<(), String> {
+    let ordered_accounts: BTreeMap<&u16, &Account> = account_map.iter().collect();
+
+    match format {
+        StateFormat::Binary => {
+            writer
+                .write_all(STATE_MAGIC)
+                .map_err(|err| err.to_string())?;
+            writer
+                .write_all(&STATE_VERSION.to_le_bytes())
+                .map_err(|err| err.to_string())?;
+            bincode::serialize_into(&mut writer, &ordered_accounts)
+                .map_err(|err| err.to_string())?
+        }
+        StateFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &ordered_accounts)
+                .map_err(|err| err.to_string())?;
+            writeln!(writer).map_err(|err| err.to_string())?;
+        }
+    }
+
+    writer.flush().map_err(|err| err.to_string())
+}
+
+/// Captures the output records of every account, so they can be compared once a run has finished
+pub fn snapshot_balances(account_map: &HashMap<u16, Account>) -> HashMap<u16, AccountRecord> {
+    account_map
+        .iter()
+        .map(|(client_id, account)| (*client_id, AccountRecord::new(*client_id, account)))
+        .collect()
+}
+
+/// Lists the accounts that changed relative to a snapshot, ordered by client id. Accounts that
+/// aren't in the snapshot are compared against an empty account.
+pub fn diff_accounts(
+    before: &HashMap<u16, AccountRecord>,
+    after: &HashMap<u16, Account>,
+) -> Vec<AccountDiff> {
+    let mut diffs: Vec<AccountDiff> = after
+        .iter()
+        .map(|(client_id, account)| {
+            let after_record = AccountRecord::new(*client_id, account);
+
+            match before.get(client_id) {
+                Some(before_record) => AccountDiff::new(before_record, &after_record),
+                None => AccountDiff::new(
+                    &AccountRecord::new(*client_id, &Account::default()),
+                    &after_record,
+                ),
+            }
+        })
+        .filter(AccountDiff::has_changes)
+        .collect();
+
+    diffs.sort_by_key(|diff| diff.client);
+
+    diffs
+}
+
+/// Decodes binary state, which must have been written with the current version of the format
+fn decode_binary(bytes: &[u8]) -> Result<HashMap<u16, Account>, String> {
+    match state_version(bytes) {
+        Some(STATE_VERSION) => {
+            bincode::deserialize(&bytes[STATE_HEADER_LEN..]).map_err(|err| err.to_string())
+        }
+        Some(version) if version < STATE_VERSION => Err(format!(
+            "version {} of the state format is out of date, upgrade it with migrate-state",
+            version
+        )),
+        Some(version) => Err(format!(
+            "version {} of the state format isn't supported, only version {} is",
+            version, STATE_VERSION
+        )),
+        None => Err(
+            "the state was written by an older engine, upgrade it with migrate-state".to_string(),
+        ),
+    }
+}
+
+/// The version of the format that binary state was written with, None when an older engine wrote
+/// it without a header
+pub(crate) fn state_version(bytes: &[u8]) -> Option<u16> {
+    match bytes.strip_prefix(STATE_MAGIC)? {
+        [low, high, ..] => Some(u16::from_le_bytes([*low, *high])),
+        _ => None,
+    }
+}
+
+/// Wraps any error raised while loading or saving state, along with the offending file
+fn state_error(file_path: &Path, err: impl ToString) -> SourceError {
+    SourceError::State(file_path.display().to_string(), err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::Account;
+    use crate::state::{
+        diff_accounts, export_state, import_state, load_state, save_state, snapshot_balances,
+        StateFormat,
+    };
+    use crate::test_helpers::*;
+    use approx::assert_relative_eq;
+    use std::collections::HashMap;
+    use std::io::Error;
+
+    // Tests that accounts, including their transactions, are unchanged after being saved and loaded
+    #[test]
+    fn test_save_and_load_state() -> Result<(), Error> {
+        let (file_path_str, dir, file) = create_temp_file("state.bin")?;
+
+        let mut account = Account::default();
+        account.deposit(100.0, 1);
+        account.dispute(1);
+
+        let account_map = HashMap::from([(7, account)]);
+        save_state(&file_path_str, &account_map).unwrap();
+
+        assert_eq!(load_state(&file_path_str).unwrap(), account_map);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that state exported as json can be imported back without any changes
+    #[test]
+    fn test_export_and_import_state() -> Result<(), Error> {
+        let (state_path, dir, file) = create_temp_file("state.bin")?;
+        let json_path = dir.path().join("state.json").display().to_string();
+        let imported_path = dir.path().join("imported.bin").display().to_string();
+
+        let mut account = Account::with_balances(12.5, 0.0);
+        account.deposit(7.25, 3);
+        account.dispute(3);
+        let account_map = HashMap::from([(2, account), (1, Account::default())]);
+        save_state(&state_path, &account_map).unwrap();
+
+        export_state(
+            state_path.as_ref(),
+            Some(json_path.as_ref()),
+            StateFormat::Json,
+        )
+        .unwrap();
+        let json = std::fs::read_to_string(&json_path)?;
+        assert!(json.find("\"1\"").unwrap() < json.find("\"2\"").unwrap());
+
+        import_state(
+            json_path.as_ref(),
+            Some(imported_path.as_ref()),
+            StateFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(load_state(&imported_path).unwrap(), account_map);
+
+        drop(file);
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that only accounts with changed balances or lock status are included in a diff
+    #[test]
+    fn test_diff_accounts() {
+        let mut unchanged = Account::default();
+        unchanged.deposit(10.0, 1);
+        let mut changed = Account::default();
+        changed.deposit(50.0, 2);
+
+        let mut account_map = HashMap::from([(1, unchanged), (2, changed)]);
+        let before = snapshot_balances(&account_map);
+
+        let account = account_map.get_mut(&2).unwrap();
+        account.dispute(2);
+        account.chargeback(2).expect("ok");
+        account_map.entry(3).or_default().deposit(5.0, 3);
+
+        let diffs = diff_accounts(&before, &account_map);
+
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].client, 2);
+        assert_relative_eq!(diffs[0].available_change, -50.0);
+        assert_relative_eq!(diffs[0].total_change, -50.0);
+        assert!(!diffs[0].was_locked && diffs[0].locked);
+        assert_eq!(diffs[1].client, 3);
+        assert_relative_eq!(diffs[1].available_change, 5.0);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::error::{ExitReport, SourceError, SourceResult};
+use crate::mapper::{Account, Record, TransactionType};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::Duration;
+
+/// The records read during a run, tallied as they're applied
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RecordTally {
+    /// The number of records of each type that were read, keyed by the name of the type
+    types: BTreeMap<&'static str, u64>,
+
+    /// The funds credited by the deposits that were accepted
+    deposited: f64,
+
+    /// The funds debited by the withdrawals that were accepted
+    withdrawn: f64,
+}
+
+impl RecordTally {
+    /// Tallies a record that was read, along with whether it was accepted
+    pub fn add(&mut self, record: &Record, accepted: bool) {
+        *self
+            .types
+            .entry(record.transaction_type.name())
+            .or_default() += 1;
+
+        let amount = record.amount.unwrap_or_default() as f64;
+        match (record.transaction_type, accepted) {
+            (TransactionType::Deposit, true) => self.deposited += amount,
+            (TransactionType::Withdrawal, true) => self.withdrawn += amount,
+            _ => {}
+        }
+    }
+}
+
+/// The aggregate figures of a run, written at the end of it with --stats or --stats-out
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct RunStats {
+    /// The number of rows that were read, including malformed ones
+    pub rows_read: u64,
+
+    /// The number of rows that were rejected
+    pub rows_rejected: u64,
+
+    /// The number of rows of each transaction type, keyed by the name of the type. Malformed rows
+    /// don't have a type, so they aren't counted.
+    pub transactions: BTreeMap<&'static str, u64>,
+
+    /// The funds credited by the deposits that were accepted
+    pub deposited: f64,
+
+    /// The funds debited by the withdrawals that were accepted
+    pub withdrawn: f64,
+
+    /// The held funds across every account at the end of the run
+    pub held: f64,
+
+    /// The number of accounts that are locked at the end of the run
+    pub locked_accounts: u64,
+
+    /// How long the records took to read and apply, in seconds
+    pub elapsed_secs: f64,
+
+    /// The number of rows read per second
+    pub throughput: f64,
+}
+
+impl RunStats {
+    /// The figures of a run, from the records in its report, the accounts it finished with and how
+    /// long its records took to apply
+    pub fn new(report: &ExitReport, accounts: &HashMap<u16, Account>, elapsed: Duration) -> Self {
+        let elapsed_secs = elapsed.as_secs_f64();
+
+        RunStats {
+            rows_read: report.records,
+            rows_rejected: report.rejections.len() as u64,
+            transactions: report.tally.types.clone(),
+            deposited: report.tally.deposited,
+            withdrawn: report.tally.withdrawn,
+            held: accounts
+                .values()
+                .map(|account| account.held_funds as f64)
+                .sum(),
+            locked_accounts: accounts
+                .values()
+                .filter(|account| account.lock_state.is_locked())
+                .count() as u64,
+            elapsed_secs,
+            throughput: match elapsed_secs > 0.0 {
+                true => report.records as f64 / elapsed_secs,
+                false => 0.0,
+            },
+        }
+    }
+}
+
+/// Writes the figures of a run as JSON
+pub fn write_stats(mut output: impl Write, stats: &RunStats) -> SourceResult<()> {
+    serde_json::to_writer_pretty(&mut output, stats)
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+    writeln!(output).map_err(|err| SourceError::Io(err.to_string()))?;
+
+    output
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{ExitReport, LedgerError};
+    use crate::mapper::{Account, Record};
+    use crate::stats::RunStats;
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    // Tests that rows are counted by type, and only accepted deposits and withdrawals add to the
+    // funds deposited and withdrawn
+    #[test]
+    fn test_run_stats() {
+        let mut report = ExitReport {
+            records: 5,
+            ..Default::default()
+        };
+        report.tally.add(&Record::deposit(1, 1, 10.0), true);
+        report.tally.add(&Record::deposit(1, 2, 5.0), true);
+        report.tally.add(&Record::withdrawal(1, 3, 4.0), true);
+        report.tally.add(&Record::withdrawal(1, 4, 40.0), false);
+        report.tally.add(&Record::dispute(1, 2), true);
+        report.reject(4, LedgerError::InsufficientFunds(40.0, 11.0));
+
+        let mut account = Account::default();
+        account.deposit(10.0, 1);
+        account.deposit(5.0, 2);
+        account.withdraw(4.0, 3).unwrap();
+        account.dispute(2);
+        let accounts = HashMap::from([(1, account)]);
+
+        let stats = RunStats::new(&report, &accounts, Duration::from_millis(500));
+        assert_eq!(
+            stats,
+            RunStats {
+                rows_read: 5,
+                rows_rejected: 1,
+                transactions: BTreeMap::from([("deposit", 2), ("dispute", 1), ("withdrawal", 2)]),
+                deposited: 15.0,
+                withdrawn: 4.0,
+                held: 5.0,
+                locked_accounts: 0,
+                elapsed_secs: 0.5,
+                throughput: 10.0,
+            }
+        );
+    }
+}
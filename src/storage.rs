@@ -0,0 +1,659 @@
+use crate::error::{CliError, CliResult, EngineResult, SourceError, SourceResult};
+use crate::mapper::{Account, AccountRecord};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The number of records an account can go untouched before it's written to a persistent store,
+/// when the demotion period isn't set
+pub const DEFAULT_STORE_DEMOTE_AFTER: u64 = 1_000;
+
+/// The accounts that a store iterates over, along with their clients
+pub type StoredAccounts<'a> = Box<dyn Iterator<Item = SourceResult<(u16, Account)>> + 'a>;
+
+/// Where client accounts are kept, e.g. in memory or in a database. The engine keeps the accounts
+/// that are in use as they are, and demotes the rest to a store, so backends can be swapped in
+/// without changing how records are applied. Stores must be Send, so the engine can be shared
+/// between threads.
+pub trait AccountStore: Send {
+    /// A copy of a client's account, if it's stored
+    fn get(&self, client_id: u16) -> SourceResult<Option<Account>>;
+
+    /// A copy of a client's account, storing an empty account for clients that haven't been seen
+    fn get_or_create(&mut self, client_id: u16) -> SourceResult<Account> {
+        if let Some(account) = self.get(client_id)? {
+            return Ok(account);
+        }
+
+        let account = Account::default();
+        self.update(client_id, &account)?;
+
+        Ok(account)
+    }
+
+    /// Stores a client's account, replacing the one that was stored
+    fn update(&mut self, client_id: u16, account: &Account) -> SourceResult<()>;
+
+    /// Removes a client's account from the store, if it was stored
+    fn take(&mut self, client_id: u16) -> SourceResult<Option<Account>>;
+
+    /// Copies of every stored account, along with their clients, in no particular order
+    fn iter(&self) -> StoredAccounts<'_>;
+
+    /// Whether a client's account is stored
+    fn contains(&self, client_id: u16) -> bool;
+
+    /// The number of stored accounts
+    fn len(&self) -> usize;
+
+    /// Whether the store is empty
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the accounts outlive the run (e.g. in a database), in which case every account is
+    /// stored once the run finishes
+    fn is_persistent(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps accounts in memory as they are, the simplest store
+impl AccountStore for HashMap<u16, Account> {
+    fn get(&self, client_id: u16) -> SourceResult<Option<Account>> {
+        Ok(HashMap::get(self, &client_id).cloned())
+    }
+
+    fn update(&mut self, client_id: u16, account: &Account) -> SourceResult<()> {
+        self.insert(client_id, account.clone());
+        Ok(())
+    }
+
+    fn take(&mut self, client_id: u16) -> SourceResult<Option<Account>> {
+        Ok(self.remove(&client_id))
+    }
+
+    fn iter(&self) -> StoredAccounts<'_> {
+        Box::new(HashMap::iter(self).map(|(client_id, account)| Ok((*client_id, account.clone()))))
+    }
+
+    fn contains(&self, client_id: u16) -> bool {
+        self.contains_key(&client_id)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+/// Keeps accounts in memory, encoded with bincode so they're more compact than live accounts
+#[derive(Default)]
+pub struct MemoryStore {
+    /// The encoded accounts, keyed by client id
+    accounts: HashMap<u16, Vec<u8>>,
+}
+
+impl AccountStore for MemoryStore {
+    fn get(&self, client_id: u16) -> SourceResult<Option<Account>> {
+        self.accounts
+            .get(&client_id)
+            .map(|bytes| decode(bytes))
+            .transpose()
+    }
+
+    fn update(&mut self, client_id: u16, account: &Account) -> SourceResult<()> {
+        self.accounts.insert(client_id, encode(account)?);
+        Ok(())
+    }
+
+    fn take(&mut self, client_id: u16) -> SourceResult<Option<Account>> {
+        self.accounts
+            .remove(&client_id)
+            .map(|bytes| decode(&bytes))
+            .transpose()
+    }
+
+    fn iter(&self) -> StoredAccounts<'_> {
+        Box::new(
+            self.accounts
+                .iter()
+                .map(|(client_id, bytes)| Ok((*client_id, decode(bytes)?))),
+        )
+    }
+
+    fn contains(&self, client_id: u16) -> bool {
+        self.accounts.contains_key(&client_id)
+    }
+
+    fn len(&self) -> usize {
+        self.accounts.len()
+    }
+}
+
+/// Where the accounts that are demoted out of memory are kept, as given to --store
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum StoreLocation {
+    /// Encoded in memory, see MemoryStore
+    #[default]
+    Memory,
+
+    /// In a SQLite database at the path, given as `sqlite://path.db`. Requires the `sqlite`
+    /// feature.
+    Sqlite(PathBuf),
+}
+
+impl FromStr for StoreLocation {
+    type Err = CliError;
+
+    fn from_str(location: &str) -> CliResult<Self> {
+        if location == "memory" {
+            return Ok(StoreLocation::Memory);
+        }
+
+        match location.strip_prefix("sqlite://") {
+            Some(db_path) if !db_path.is_empty() => Ok(StoreLocation::Sqlite(db_path.into())),
+            _ => Err(CliError::UnknownStore(location.to_string())),
+        }
+    }
+}
+
+/// Opens the store at the location
+pub fn open_store(location: &StoreLocation) -> EngineResult<Box<dyn AccountStore>> {
+    match location {
+        StoreLocation::Memory => Ok(Box::new(MemoryStore::default())),
+        StoreLocation::Sqlite(db_path) => sqlite_store(db_path),
+    }
+}
+
+/// Opens a SQLite database, replacing the accounts of an earlier run
+#[cfg(feature = "sqlite")]
+fn sqlite_store(db_path: &std::path::Path) -> EngineResult<Box<dyn AccountStore>> {
+    Ok(Box::new(sqlite::SqliteStore::open(db_path)?))
+}
+
+/// SQLite support wasn't compiled in
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_store(_db_path: &std::path::Path) -> EngineResult<Box<dyn AccountStore>> {
+    Err(CliError::UnavailableStore("sqlite".to_string()).into())
+}
+
+/// Holds client accounts in two tiers. Active (hot) accounts are kept as they are, while accounts
+/// that haven't been touched for a while are demoted to a compact, encoded (cold) tier. Most
+/// clients only appear a handful of times, so this keeps the transaction history of idle accounts
+/// from dominating memory. The cold tier is kept in memory unless another store is provided.
+pub struct TieredAccounts {
+    /// Accounts that have been touched recently, along with when they were last touched
+    hot: HashMap<u16, (Account, u64)>,
+
+    /// Accounts that haven't been touched for a while
+    cold: Box<dyn AccountStore>,
+
+    /// The number of records an account can go untouched before it's demoted, when demotion is
+    /// enabled
+    demote_after: Option<u64>,
+
+    /// The number of records that have been applied
+    clock: u64,
+}
+
+impl Default for TieredAccounts {
+    fn default() -> Self {
+        TieredAccounts {
+            hot: HashMap::new(),
+            cold: Box::new(MemoryStore::default()),
+            demote_after: None,
+            clock: 0,
+        }
+    }
+}
+
+impl TieredAccounts {
+    /// Holds the accounts in the hot tier, demotion is disabled
+    pub fn new(accounts: HashMap<u16, Account>) -> Self {
+        TieredAccounts {
+            hot: accounts
+                .into_iter()
+                .map(|(client_id, account)| (client_id, (account, 0)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Demotes accounts once they've gone untouched for the given number of records
+    pub fn with_demotion(mut self, after_records: u64) -> Self {
+        self.demote_after = Some(after_records.max(1));
+        self
+    }
+
+    /// Keeps the cold tier in the store rather than in memory
+    pub fn with_store(mut self, store: Box<dyn AccountStore>) -> Self {
+        self.cold = store;
+        self
+    }
+
+    /// Retrieves a client's account for updating, promoting it to the hot tier. Clients that
+    /// haven't been seen get an empty account.
+    pub fn get_mut(&mut self, client_id: u16) -> SourceResult<&mut Account> {
+        // the store is only asked for accounts that aren't hot, as it may be a database
+        if !self.hot.contains_key(&client_id) {
+            let account = self.cold.take(client_id)?.unwrap_or_default();
+            self.hot.insert(client_id, (account, 0));
+        }
+
+        let (account, last_touched) = self.hot.entry(client_id).or_default();
+        *last_touched = self.clock;
+
+        Ok(account)
+    }
+
+    /// Retrieves a client's account for updating when it's in the hot tier, leaving the tiers as
+    /// they are
+    pub fn get_hot_mut(&mut self, client_id: u16) -> Option<&mut Account> {
+        self.hot.get_mut(&client_id).map(|(account, _)| account)
+    }
+
+    /// Whether the client has an account, in either tier
+    pub fn contains(&self, client_id: u16) -> bool {
+        self.hot.contains_key(&client_id) || self.cold.contains(client_id)
+    }
+
+    /// Advances the clock once a record has been applied. Idle accounts are swept into the cold
+    /// tier once per demotion period, so the cost of a sweep is spread across its records.
+    pub fn tick(&mut self) -> SourceResult<()> {
+        self.clock += 1;
+
+        let demote_after = match self.demote_after {
+            Some(demote_after) if self.clock.is_multiple_of(demote_after) => demote_after,
+            _ => return Ok(()),
+        };
+
+        let idle: Vec<u16> = self
+            .hot
+            .iter()
+            .filter(|(_, (_, last_touched))| self.clock - last_touched >= demote_after)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        for client_id in idle {
+            if let Some((account, _)) = self.hot.remove(&client_id) {
+                self.cold.update(client_id, &account)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The number of accounts in the hot and cold tiers
+    pub fn len(&self) -> (usize, usize) {
+        (self.hot.len(), self.cold.len())
+    }
+
+    /// Whether there aren't any accounts in either tier
+    pub fn is_empty(&self) -> bool {
+        self.hot.is_empty() && self.cold.is_empty()
+    }
+
+    /// The output record of a client's account regardless of its tier, none when the client
+    /// hasn't been seen
+    pub fn record(&self, client_id: u16) -> SourceResult<Option<AccountRecord>> {
+        if let Some((account, _)) = self.hot.get(&client_id) {
+            return Ok(Some(AccountRecord::new(client_id, account)));
+        }
+
+        Ok(self
+            .cold
+            .get(client_id)?
+            .map(|account| AccountRecord::new(client_id, &account)))
+    }
+
+    /// The output records of every account regardless of their tier, leaving the tiers as they are
+    pub fn records(&self) -> SourceResult<Vec<AccountRecord>> {
+        let mut records: Vec<AccountRecord> = self
+            .hot
+            .iter()
+            .map(|(client_id, (account, _))| AccountRecord::new(*client_id, account))
+            .collect();
+
+        for stored in self.cold.iter() {
+            let (client_id, account) = stored?;
+            records.push(AccountRecord::new(client_id, &account));
+        }
+
+        Ok(records)
+    }
+
+    /// A copy of every account, regardless of its tier, leaving the tiers as they are
+    pub fn snapshot(&self) -> SourceResult<HashMap<u16, Account>> {
+        let mut accounts: HashMap<u16, Account> = self
+            .hot
+            .iter()
+            .map(|(client_id, (account, _))| (*client_id, account.clone()))
+            .collect();
+
+        for stored in self.cold.iter() {
+            let (client_id, account) = stored?;
+            accounts.insert(client_id, account);
+        }
+
+        Ok(accounts)
+    }
+
+    /// Decodes every account, returning them regardless of their tier. When the store outlives the
+    /// run, the hot accounts are stored first, so it holds every account.
+    pub fn into_accounts(mut self) -> SourceResult<HashMap<u16, Account>> {
+        if self.cold.is_persistent() {
+            for (client_id, (account, _)) in self.hot.drain() {
+                self.cold.update(client_id, &account)?;
+            }
+        }
+
+        let mut accounts: HashMap<u16, Account> = self
+            .hot
+            .into_iter()
+            .map(|(client_id, (account, _))| (client_id, account))
+            .collect();
+        for stored in self.cold.iter() {
+            let (client_id, account) = stored?;
+            accounts.insert(client_id, account);
+        }
+
+        Ok(accounts)
+    }
+}
+
+/// Encodes an account for the cold tier
+fn encode(account: &Account) -> SourceResult<Vec<u8>> {
+    bincode::serialize(account).map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// Decodes an account from the cold tier
+fn decode(bytes: &[u8]) -> SourceResult<Account> {
+    bincode::deserialize(bytes).map_err(|err| SourceError::Io(err.to_string()))
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use crate::error::{SourceError, SourceResult};
+    use crate::mapper::{Account, AccountRecord};
+    use crate::storage::{decode, encode, AccountStore, StoredAccounts};
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    /// The tables of the database. Each account is stored whole, along with its balances and the
+    /// index of its transactions, so they can be queried with SQL.
+    const SCHEMA: &str = "
+        PRAGMA journal_mode = WAL;
+        PRAGMA synchronous = NORMAL;
+        CREATE TABLE IF NOT EXISTS accounts (
+            client INTEGER PRIMARY KEY,
+            available REAL NOT NULL,
+            held REAL NOT NULL,
+            total REAL NOT NULL,
+            locked INTEGER NOT NULL,
+            account BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS transactions (
+            client INTEGER NOT NULL,
+            tx INTEGER NOT NULL,
+            amount REAL NOT NULL,
+            state TEXT NOT NULL,
+            disputed_amount REAL,
+            PRIMARY KEY (client, tx)
+        );
+        DELETE FROM accounts;
+        DELETE FROM transactions;
+    ";
+
+    /// Keeps accounts in a SQLite database, which holds every account once the run finishes
+    pub struct SqliteStore {
+        /// The connection to the database
+        connection: Connection,
+
+        /// Where the database is, for errors
+        db_path: String,
+
+        /// The clients whose accounts are in the database, so the engine can check for an account
+        /// without querying it
+        clients: HashSet<u16>,
+    }
+
+    impl SqliteStore {
+        /// Opens the database, creating it when it doesn't exist. The accounts of an earlier run
+        /// are removed, state is carried between runs with --load-state.
+        pub fn open(db_path: &Path) -> SourceResult<Self> {
+            let display_path = db_path.display().to_string();
+            let connection = Connection::open(db_path)
+                .and_then(|connection| connection.execute_batch(SCHEMA).map(|_| connection))
+                .map_err(|err| SourceError::State(display_path.clone(), err.to_string()))?;
+
+            Ok(SqliteStore {
+                connection,
+                db_path: display_path,
+                clients: HashSet::new(),
+            })
+        }
+
+        /// Wraps an error raised by the database
+        fn error(&self, err: rusqlite::Error) -> SourceError {
+            SourceError::State(self.db_path.clone(), err.to_string())
+        }
+    }
+
+    impl AccountStore for SqliteStore {
+        fn update(&mut self, client_id: u16, account: &Account) -> SourceResult<()> {
+            let bytes = encode(account)?;
+            let record = AccountRecord::new(client_id, account);
+
+            let write = || -> rusqlite::Result<()> {
+                let transaction = self.connection.unchecked_transaction()?;
+                transaction.execute(
+                    "INSERT OR REPLACE INTO accounts VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        client_id,
+                        record.available,
+                        record.held,
+                        record.total,
+                        record.locked,
+                        bytes
+                    ],
+                )?;
+                transaction.execute("DELETE FROM transactions WHERE client = ?1", [client_id])?;
+
+                let mut insert = transaction
+                    .prepare_cached("INSERT INTO transactions VALUES (?1, ?2, ?3, ?4, ?5)")?;
+                for (transaction_id, tx) in &account.successful_transactions {
+                    insert.execute(params![
+                        client_id,
+                        transaction_id,
+                        tx.amount,
+                        tx.current_state.name(),
+                        tx.disputed_amount
+                    ])?;
+                }
+                drop(insert);
+
+                transaction.commit()
+            };
+            write().map_err(|err| self.error(err))?;
+            self.clients.insert(client_id);
+
+            Ok(())
+        }
+
+        fn take(&mut self, client_id: u16) -> SourceResult<Option<Account>> {
+            let account = self.get(client_id)?;
+            if account.is_some() {
+                self.connection
+                    .execute("DELETE FROM accounts WHERE client = ?1", [client_id])
+                    .and_then(|_| {
+                        self.connection
+                            .execute("DELETE FROM transactions WHERE client = ?1", [client_id])
+                    })
+                    .map_err(|err| self.error(err))?;
+                self.clients.remove(&client_id);
+            }
+
+            Ok(account)
+        }
+
+        fn get(&self, client_id: u16) -> SourceResult<Option<Account>> {
+            if !self.clients.contains(&client_id) {
+                return Ok(None);
+            }
+
+            let bytes: Option<Vec<u8>> = self
+                .connection
+                .query_row(
+                    "SELECT account FROM accounts WHERE client = ?1",
+                    [client_id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|err| self.error(err))?;
+
+            bytes.map(|bytes| decode(&bytes)).transpose()
+        }
+
+        fn iter(&self) -> StoredAccounts<'_> {
+            let rows = || -> rusqlite::Result<Vec<(u16, Vec<u8>)>> {
+                let mut select = self
+                    .connection
+                    .prepare("SELECT client, account FROM accounts")?;
+                let rows = select.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            };
+
+            match rows() {
+                Ok(rows) => Box::new(
+                    rows.into_iter()
+                        .map(|(client_id, bytes)| Ok((client_id, decode(&bytes)?))),
+                ),
+                Err(err) => Box::new(std::iter::once(Err(self.error(err)))),
+            }
+        }
+
+        fn contains(&self, client_id: u16) -> bool {
+            self.clients.contains(&client_id)
+        }
+
+        fn len(&self) -> usize {
+            self.clients.len()
+        }
+
+        fn is_persistent(&self) -> bool {
+            true
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::mapper::Account;
+        use crate::storage::sqlite::SqliteStore;
+        use crate::storage::{AccountStore, TieredAccounts};
+        use rusqlite::Connection;
+        use std::collections::HashMap;
+        use tempfile::tempdir;
+
+        // Tests that demoted accounts are written to the database, and that it holds every
+        // account, with its balances and transactions, once the run finishes
+        #[test]
+        fn test_sqlite_store() {
+            let dir = tempdir().unwrap();
+            let db_path = dir.path().join("accounts.db");
+            let store = SqliteStore::open(&db_path).unwrap();
+            let mut accounts = TieredAccounts::new(HashMap::new())
+                .with_demotion(1)
+                .with_store(Box::new(store));
+
+            accounts.get_mut(1).unwrap().deposit(10.0, 1);
+            accounts.tick().unwrap();
+            assert_eq!(accounts.len(), (0, 1));
+            let account = accounts.get_mut(1).unwrap();
+            account.dispute(1);
+            accounts.get_mut(2).unwrap().deposit(5.0, 2);
+
+            let mut expected = Account::default();
+            expected.deposit(10.0, 1);
+            expected.dispute(1);
+            assert_eq!(accounts.into_accounts().unwrap()[&1], expected);
+
+            let connection = Connection::open(&db_path).unwrap();
+            let held: f32 = connection
+                .query_row("SELECT held FROM accounts WHERE client = 1", [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(held, 10.0);
+            let state: String = connection
+                .query_row("SELECT state FROM transactions WHERE tx = 1", [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(state, "dispute");
+
+            // a new run starts from an empty database
+            let store = SqliteStore::open(&db_path).unwrap();
+            assert_eq!(store.iter().count(), 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::Account;
+    use crate::storage::{AccountStore, MemoryStore, TieredAccounts};
+    use std::collections::HashMap;
+
+    // Tests that each in-memory store creates, updates and iterates over accounts the same way
+    #[test]
+    fn test_account_stores() {
+        let stores: Vec<Box<dyn AccountStore>> = vec![
+            Box::new(HashMap::<u16, Account>::new()),
+            Box::new(MemoryStore::default()),
+        ];
+
+        for mut store in stores {
+            let mut account = store.get_or_create(1).unwrap();
+            assert_eq!(account, Account::default());
+            assert!(store.contains(1));
+
+            account.deposit(10.0, 1);
+            store.update(1, &account).unwrap();
+            assert_eq!(store.get_or_create(1).unwrap(), account);
+            store.get_or_create(2).unwrap();
+
+            let mut stored: Vec<(u16, Account)> = store.iter().map(Result::unwrap).collect();
+            stored.sort_by_key(|(client_id, _)| *client_id);
+            assert_eq!(stored, vec![(1, account.clone()), (2, Account::default())]);
+
+            assert_eq!(store.take(1).unwrap(), Some(account));
+            assert_eq!(store.get(1).unwrap(), None);
+            assert_eq!(store.len(), 1);
+        }
+    }
+
+    // Tests that idle accounts are demoted to the cold tier, and promoted unchanged when touched
+    #[test]
+    fn test_demote_and_promote_accounts() {
+        let mut accounts = TieredAccounts::new(HashMap::new()).with_demotion(2);
+
+        accounts.get_mut(1).unwrap().deposit(10.0, 1);
+        accounts.tick().unwrap();
+        accounts.get_mut(2).unwrap().deposit(5.0, 2);
+        accounts.tick().unwrap();
+        assert_eq!(accounts.len(), (1, 1));
+
+        let account = accounts.get_mut(1).unwrap();
+        assert_eq!(account.successful_transactions.len(), 1);
+        account.dispute(1);
+        accounts.tick().unwrap();
+        accounts.tick().unwrap();
+        assert_eq!(accounts.len(), (0, 2));
+
+        let mut expected = Account::default();
+        expected.deposit(10.0, 1);
+        expected.dispute(1);
+        assert_eq!(accounts.into_accounts().unwrap()[&1], expected);
+    }
+}
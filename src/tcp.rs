@@ -0,0 +1,278 @@
+use crate::error::{EngineError, EngineResult, SourceError};
+use crate::mapper::Record;
+use crate::parser::Columns;
+use crate::shared::SharedEngine;
+use crate::telemetry::TraceContext;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use tracing::{info_span, warn};
+
+/// The columns of csv rows sent before a header, the same as a transaction file
+const DEFAULT_HEADER: &str = "type,client,tx,amount";
+
+/// The stack size of each connection's thread. Connections only parse a line at a time, so a small
+/// stack lets thousands of them be open at once.
+const CONNECTION_STACK_SIZE: usize = 64 * 1024;
+
+/// Applies transactions sent over TCP to the shared accounts until the process is stopped. Each
+/// connection is served by its own thread, see `serve_connection` for the line protocol.
+pub fn serve_tcp(engine: Arc<SharedEngine>, addr: &str) -> EngineResult<()> {
+    let listener = TcpListener::bind(addr).map_err(|err| SourceError::Io(err.to_string()))?;
+    eprintln!("Listening on tcp://{}", addr);
+
+    accept_connections(listener, engine)
+}
+
+/// Serves every connection made to the listener, each on its own thread. A connection that can't
+/// be accepted is skipped, so one misbehaving client can't stop the server.
+pub fn accept_connections(listener: TcpListener, engine: Arc<SharedEngine>) -> EngineResult<()> {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let engine = Arc::clone(&engine);
+        thread::Builder::new()
+            .stack_size(CONNECTION_STACK_SIZE)
+            .spawn(move || {
+                if let Err(err) = serve_stream(&engine, stream) {
+                    warn!("Closed connection: {}", err);
+                }
+            })
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Serves a single TCP connection
+fn serve_stream(engine: &SharedEngine, stream: TcpStream) -> EngineResult<()> {
+    let reader = stream
+        .try_clone()
+        .map_err(|err| SourceError::Io(err.to_string()))?;
+
+    serve_connection(engine, BufReader::new(reader), BufWriter::new(stream))
+}
+
+/// Answers each line of a connection with a line of its own, until the input ends:
+/// - `balances`: every account's balances as a JSON array, ordered by client
+/// - `balances <client>`: the client's balances as JSON, or null when it hasn't been seen
+/// - a JSON object: a record, as in a JSONL transaction file. Its traceparent and tracestate
+///   fields, when it has them, are the trace context its spans are exported under.
+/// - a csv header (starting with `type`): the columns of the csv rows that follow
+/// - anything else: a csv row, with the columns of a transaction file until a header is sent
+///
+/// Records, and headers, are answered with `ok`, or `error <code> <message>` when they're rejected
+/// or can't be parsed. Blank lines are skipped. Any other error (e.g. the journal can't be
+/// written) ends the connection.
+pub fn serve_connection(
+    engine: &SharedEngine,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> EngineResult<()> {
+    let mut columns = Columns::new(DEFAULT_HEADER, ',');
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.map_err(|err| SourceError::Io(err.to_string()))?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let line_number = index as u64 + 1;
+        let span = info_span!("message", line = line_number);
+        if line.starts_with('{') {
+            TraceContext::from_json(line).follow(&span);
+        }
+
+        let response = match span.in_scope(|| answer(engine, &mut columns, line_number, line)) {
+            Ok(response) => response,
+            Err(
+                err @ (EngineError::Ledger(_) | EngineError::Source(SourceError::Parse { .. })),
+            ) => {
+                format!("error {} {}", err.code(), err)
+            }
+            Err(err) => return Err(err),
+        };
+
+        writeln!(output, "{}", response)
+            .and_then(|_| output.flush())
+            .map_err(|err| SourceError::Io(err.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Answers a single line of a connection
+fn answer(
+    engine: &SharedEngine,
+    columns: &mut Columns,
+    line_number: u64,
+    line: &str,
+) -> EngineResult<String> {
+    let parse_error = |message: String| SourceError::Parse {
+        line: line_number,
+        message,
+    };
+
+    if line == "balances" {
+        return Ok(to_json(&engine.accounts()?));
+    }
+
+    if let Some(client) = line.strip_prefix("balances ") {
+        let client_id = client
+            .trim()
+            .parse()
+            .map_err(|_| parse_error(format!("invalid client `{}`", client.trim())))?;
+        return Ok(to_json(&engine.account(client_id)?));
+    }
+
+    if line.split(',').next().map(str::trim) == Some("type") {
+        *columns = Columns::new(line, ',');
+        return Ok("ok".to_string());
+    }
+
+    let record: Record = info_span!("parse").in_scope(|| match line.starts_with('{') {
+        true => serde_json::from_str(line).map_err(|err| parse_error(err.to_string())),
+        false => columns.record(line, ',').map_err(parse_error),
+    })?;
+
+    engine.process(&record)?;
+
+    Ok("ok".to_string())
+}
+
+/// Serializes a query's answer onto a single line
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    // account records are plain data, so they always serialize
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shared::SharedEngine;
+    use crate::tcp::{accept_connections, serve_connection};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Tests that csv and JSONL records are applied and answered, and that balances can be queried
+    #[test]
+    fn test_serve_connection() {
+        let engine = SharedEngine::default();
+        let input = "deposit,1,1,10.0\n\
+                     type,client,tx,amount,reason\n\
+                     withdrawal, 1, 2, 2.5,\n\
+                     {\"type\":\"deposit\",\"client\":2,\"tx\":3,\"amount\":1.0}\n\
+                     \n\
+                     withdrawal,2,4,5.0,\n\
+                     deposit,x,5,1.0,\n\
+                     balances 1\n\
+                     balances 3\n\
+                     balances\n";
+
+        let mut output = vec![];
+        serve_connection(&engine, input.as_bytes(), &mut output).unwrap();
+
+        let lines: Vec<String> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[..4], ["ok", "ok", "ok", "ok"]);
+        assert!(lines[4].starts_with("error 30 "));
+        assert!(lines[5].starts_with("error 21 "));
+
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.available, 7.5);
+        assert_eq!(lines[6], serde_json::to_string(&account).unwrap());
+        assert_eq!(lines[7], "null");
+
+        let accounts = engine.accounts().unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(lines[8], serde_json::to_string(&accounts).unwrap());
+    }
+
+    // Tests that a transaction id or idempotency key replayed over another connection is rejected
+    // like it is in a batch run, as is a row referencing another client's transaction
+    #[test]
+    fn test_serve_connection_replay() {
+        let engine = SharedEngine::default();
+        let first = "type,client,tx,amount,idempotency_key\n\
+                     deposit,1,1,10.0,\n\
+                     deposit,1,2,1.0,abc\n";
+        let replay = "type,client,tx,amount,idempotency_key\n\
+                      deposit,1,1,10.0,\n\
+                      deposit,2,1,5.0,\n\
+                      deposit,1,3,1.0,abc\n\
+                      dispute,2,1,,\n";
+
+        let answers = |input: &str| -> Vec<String> {
+            let mut output = vec![];
+            serve_connection(&engine, input.as_bytes(), &mut output).unwrap();
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        };
+        assert_eq!(answers(first), ["ok", "ok", "ok"]);
+
+        let lines = answers(replay);
+        assert_eq!(lines[0], "ok");
+        assert!(lines[1].starts_with("error 145 "));
+        assert!(lines[2].starts_with("error 145 "));
+        assert!(lines[3].starts_with("error 142 "));
+        assert!(lines[4].starts_with("error 144 "));
+
+        assert_eq!(engine.account(1).unwrap().unwrap().available, 11.0);
+        assert_eq!(engine.account(2).unwrap(), None);
+    }
+
+    // Tests that records sent over many concurrent connections are all applied
+    #[test]
+    fn test_accept_connections() {
+        let engine = Arc::new(SharedEngine::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::clone(&engine);
+        thread::spawn(move || accept_connections(listener, server));
+
+        let clients: Vec<_> = (0..64u16)
+            .map(|client_id| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    for tx in 0..10u32 {
+                        let tx = client_id as u32 * 10 + tx;
+                        writeln!(stream, "deposit,{},{},1.0", client_id % 8, tx).unwrap();
+
+                        let mut response = String::new();
+                        reader.read_line(&mut response).unwrap();
+                        assert_eq!(response, "ok\n");
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        writeln!(stream, "balances").unwrap();
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response).unwrap();
+
+        let accounts = engine.accounts().unwrap();
+        assert_eq!(accounts.len(), 8);
+        assert!(accounts.iter().all(|account| account.available == 80.0));
+        assert_eq!(
+            response.trim_end(),
+            serde_json::to_string(&accounts).unwrap()
+        );
+    }
+}
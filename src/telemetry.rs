@@ -0,0 +1,163 @@
+use crate::error::EngineResult;
+use serde::Deserialize;
+use tiny_http::Header;
+use tracing::Span;
+use tracing_subscriber::{Layer, Registry};
+
+/// The spans exported over OTLP, the stages of each request or message the servers handle
+pub const EXPORT_FILTER: &str = "plutus_engine=info";
+
+/// The name the engine's spans are exported under
+pub const SERVICE_NAME: &str = "plutus-engine";
+
+/// A layer of the subscriber, which is added to it before the logging
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The W3C trace context an incoming request, or message, was sent with, so the spans of handling
+/// it join the trace of whatever sent it
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+pub struct TraceContext {
+    /// The trace and span the request was sent from, e.g.
+    /// 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01
+    pub traceparent: Option<String>,
+
+    /// Vendor specific trace state, passed on as it was received
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// The trace context of an HTTP request, from its traceparent and tracestate headers
+    pub fn from_headers(headers: &[Header]) -> Self {
+        let header = |name: &'static str| {
+            headers
+                .iter()
+                .find(|header| header.field.equiv(name))
+                .map(|header| header.value.to_string())
+        };
+
+        TraceContext {
+            traceparent: header("traceparent"),
+            tracestate: header("tracestate"),
+        }
+    }
+
+    /// The trace context of a JSON message, from its traceparent and tracestate fields. A message
+    /// without them, or that isn't JSON, doesn't have one.
+    pub fn from_json(message: &str) -> Self {
+        serde_json::from_str(message).unwrap_or_default()
+    }
+
+    /// Makes the span a child of the span the request was sent from. Spans are only exported
+    /// with the otel feature, so without it this does nothing.
+    #[cfg(feature = "otel")]
+    pub fn follow(&self, span: &Span) {
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+        use std::collections::HashMap;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let Some(traceparent) = &self.traceparent else {
+            return;
+        };
+        let mut carrier = HashMap::from([("traceparent".to_string(), traceparent.clone())]);
+        if let Some(tracestate) = &self.tracestate {
+            carrier.insert("tracestate".to_string(), tracestate.clone());
+        }
+
+        // a malformed traceparent leaves the span as the root of a new trace
+        let _ = span.set_parent(TraceContextPropagator::new().extract(&carrier));
+    }
+
+    /// Makes the span a child of the span the request was sent from. Spans are only exported
+    /// with the otel feature, so without it this does nothing.
+    #[cfg(not(feature = "otel"))]
+    pub fn follow(&self, _span: &Span) {}
+}
+
+/// Exports spans over OTLP until it's dropped, when the spans still queued are sent
+pub struct Exporter {
+    /// Batches the spans and sends them from a background thread
+    #[cfg(feature = "otel")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+#[cfg(feature = "otel")]
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.shutdown() {
+            eprintln!("Failed to export spans: {}", err);
+        }
+    }
+}
+
+/// Creates the layer that exports the engine's spans to an OTLP/HTTP endpoint (e.g.
+/// http://localhost:4318/v1/traces), along with the exporter that sends them
+#[cfg(feature = "otel")]
+pub fn otlp_exporter(endpoint: &str) -> EngineResult<(BoxedLayer, Exporter)> {
+    use crate::error::CliError;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use tracing_subscriber::EnvFilter;
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|err| CliError::InvalidValue("--otlp-endpoint".to_string(), err.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(SERVICE_NAME))
+        .with_filter(EnvFilter::new(EXPORT_FILTER));
+
+    Ok((Box::new(layer), Exporter { provider }))
+}
+
+/// OTLP export wasn't compiled in
+#[cfg(not(feature = "otel"))]
+pub fn otlp_exporter(_endpoint: &str) -> EngineResult<(BoxedLayer, Exporter)> {
+    Err(crate::error::CliError::UnavailableExporter.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::TraceContext;
+    use tiny_http::Header;
+
+    // Tests that the trace context is read from the headers of a request, or the fields of a
+    // message, and that one without it doesn't have any
+    #[test]
+    fn test_trace_context() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let headers = [
+            Header::from_bytes("Content-Type", "application/json").unwrap(),
+            Header::from_bytes("TraceParent", traceparent).unwrap(),
+        ];
+        let context = TraceContext::from_headers(&headers);
+        assert_eq!(context.traceparent.as_deref(), Some(traceparent));
+        assert_eq!(context.tracestate, None);
+
+        let message = format!(
+            "{{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.0,\
+             \"traceparent\":\"{}\",\"tracestate\":\"vendor=1\"}}",
+            traceparent
+        );
+        let context = TraceContext::from_json(&message);
+        assert_eq!(context.traceparent.as_deref(), Some(traceparent));
+        assert_eq!(context.tracestate.as_deref(), Some("vendor=1"));
+
+        assert_eq!(
+            TraceContext::from_headers(&headers[..1]),
+            TraceContext::default()
+        );
+        assert_eq!(
+            TraceContext::from_json("deposit,1,1,1.0"),
+            TraceContext::default()
+        );
+    }
+}
@@ -2,6 +2,7 @@ use crate::mapper::{Account, Record, TransactionType};
 use approx::assert_relative_eq;
 use std::fs::File;
 use std::io::{Error, Write};
+use std::sync::{Arc, Mutex};
 use tempfile::{tempdir, TempDir};
 
 /// Helper for validating relevant fields for a basic account test
@@ -69,6 +70,10 @@ pub fn dummy_record(transaction_type: TransactionType, amount: Option<f32>) -> R
         client_id: 0,
         transaction_id: 0,
         amount,
+        reason: None,
+        timestamp: None,
+        idempotency_key: None,
+        destination: None,
     }
 }
 
@@ -99,4 +104,19 @@ pub fn add_transactions_to_temp_file(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+/// Collects what's logged by tracing, so tests can check it
+#[allow(dead_code)]
+#[derive(Clone, Default)]
+pub struct LogBuffer(pub Arc<Mutex<Vec<u8>>>);
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use serde::de::{self, Deserializer, Visitor};
+use std::fmt;
+
+/// Whole numbers from this one up are taken to be milliseconds since the unix epoch. As seconds
+/// they'd be after the year 5000, so no feed means them that way.
+const MILLIS_FROM: u64 = 100_000_000_000;
+
+/// Parses a timestamp into seconds since the unix epoch. It's either a whole number of seconds or
+/// milliseconds since the epoch, or an RFC 3339 date and time (e.g. `2024-03-01T09:30:00Z`).
+/// Fractions of a second are dropped.
+pub fn parse_timestamp(value: &str) -> Result<u64, String> {
+    match value.parse() {
+        Ok(number) => Ok(epoch_seconds(number)),
+        Err(_) => parse_rfc3339(value)
+            .ok_or_else(|| format!("invalid value `{}` for field `timestamp`", value)),
+    }
+}
+
+/// Parses a length of time into seconds, a whole number followed by its unit, `s`, `m`, `h` or
+/// `d` (e.g. `90d`). None when it isn't one.
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let unit = match value.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => return None,
+    };
+
+    value[..value.len() - 1]
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(unit)
+}
+
+/// Reads an optional timestamp in any of the forms parse_timestamp accepts, from a number or a
+/// string depending on the input format
+pub(crate) fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_option(TimestampVisitor)
+}
+
+/// Visits a timestamp that's a number, a string or missing
+struct TimestampVisitor;
+
+impl<'de> Visitor<'de> for TimestampVisitor {
+    type Value = Option<u64>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "seconds or milliseconds since the unix epoch, or an RFC 3339 date and time"
+        )
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(Some(epoch_seconds(value)))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        let value = u64::try_from(value)
+            .map_err(|_| E::custom(format!("timestamp `{}` is before the unix epoch", value)))?;
+
+        self.visit_u64(value)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        match value.trim() {
+            "" => Ok(None),
+            value => parse_timestamp(value).map(Some).map_err(E::custom),
+        }
+    }
+}
+
+/// Seconds since the unix epoch, from a number of seconds or milliseconds
+fn epoch_seconds(number: u64) -> u64 {
+    if number >= MILLIS_FROM {
+        number / 1_000
+    } else {
+        number
+    }
+}
+
+/// Parses an RFC 3339 date and time into seconds since the unix epoch, none when it isn't one or
+/// it's before the epoch
+fn parse_rfc3339(value: &str) -> Option<u64> {
+    let (date, time) = value.split_once(['T', 't', ' '])?;
+
+    let mut date = date.split('-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: i64 = date.next()?.parse().ok()?;
+    let day: i64 = date.next()?.parse().ok()?;
+    if date.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // the time is followed by Z, or the offset from UTC it's in
+    let zone_start = time.find(['Z', 'z', '+', '-'])?;
+    let (clock, zone) = time.split_at(zone_start);
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = zone[1..].split_once(':')?;
+            sign * (hours.parse::<i64>().ok()? * 3_600 + minutes.parse::<i64>().ok()? * 60)
+        }
+    };
+
+    let clock = clock.split_once('.').map_or(clock, |(whole, _)| whole);
+    let mut clock = clock.split(':');
+    let hours: i64 = clock.next()?.parse().ok()?;
+    let minutes: i64 = clock.next()?.parse().ok()?;
+    let seconds: i64 = clock.next()?.parse().ok()?;
+    if clock.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let local = days_from_civil(year, month, day) * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    u64::try_from(local - offset).ok()
+}
+
+/// The number of days since the unix epoch of a date in the proleptic Gregorian calendar, the
+/// inverse of cutover::civil_date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // shift the epoch to 0000-03-01, so leap days fall at the end of each 400 year era
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::Record;
+    use crate::timestamp::{parse_duration, parse_timestamp};
+
+    // Tests that timestamps are read as epoch seconds, epoch milliseconds or RFC 3339
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1709285400"), Ok(1_709_285_400));
+        assert_eq!(parse_timestamp("1709285400123"), Ok(1_709_285_400));
+        assert_eq!(parse_timestamp("2024-03-01T09:30:00Z"), Ok(1_709_285_400));
+        assert_eq!(
+            parse_timestamp("2024-03-01t09:30:00.750z"),
+            Ok(1_709_285_400)
+        );
+        assert_eq!(
+            parse_timestamp("2024-03-01T11:30:00+02:00"),
+            Ok(1_709_285_400)
+        );
+        assert_eq!(
+            parse_timestamp("2024-02-29T23:00:00-10:30"),
+            Ok(1_709_285_400)
+        );
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Ok(0));
+        assert!(parse_timestamp("1969-12-31T23:59:59Z").is_err());
+        assert!(parse_timestamp("2024-03-01T09:30:00").is_err());
+        assert!(parse_timestamp("2024-13-01T09:30:00Z").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+
+        let record: Record = serde_json::from_str(
+            r#"{"type":"deposit","client":1,"tx":1,"timestamp":"2024-03-01T09:30:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(record.timestamp, Some(1_709_285_400));
+        let record: Record =
+            serde_json::from_str(r#"{"type":"deposit","client":1,"tx":1,"timestamp":null}"#)
+                .unwrap();
+        assert_eq!(record.timestamp, None);
+    }
+
+    // Tests that lengths of time are read with their unit
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90d"), Some(7_776_000));
+        assert_eq!(parse_duration("12h"), Some(43_200));
+        assert_eq!(parse_duration("30m"), Some(1_800));
+        assert_eq!(parse_duration("45s"), Some(45));
+        assert_eq!(parse_duration("90"), None);
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration("-1d"), None);
+        assert_eq!(parse_duration("1w"), None);
+    }
+}
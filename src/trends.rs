@@ -0,0 +1,316 @@
+use crate::error::{SourceError, SourceResult};
+use crate::metadata::{RunMetadata, RunSummary};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The characters a text chart is drawn with, from the smallest value to the largest
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Settings for the trends report
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrendConfig {
+    /// The number of previous runs each run is compared against
+    pub trailing: usize,
+
+    /// How far (as a percentage) a figure can be from the trailing average before the run is
+    /// highlighted
+    pub deviation_pct: f64,
+
+    /// Whether to draw a text chart rather than writing a csv
+    pub chart: bool,
+}
+
+impl Default for TrendConfig {
+    fn default() -> Self {
+        TrendConfig {
+            trailing: 5,
+            deviation_pct: 50.0,
+            chart: false,
+        }
+    }
+}
+
+/// The figures that are tracked over time
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    TotalFunds,
+    OpenDisputes,
+    RejectRate,
+    LockedAccounts,
+}
+
+impl Metric {
+    /// Every metric, in the order they're reported
+    const ALL: [Metric; 4] = [
+        Metric::TotalFunds,
+        Metric::OpenDisputes,
+        Metric::RejectRate,
+        Metric::LockedAccounts,
+    ];
+
+    /// The name of the metric, as used in the report
+    fn name(self) -> &'static str {
+        match self {
+            Metric::TotalFunds => "total_funds",
+            Metric::OpenDisputes => "open_disputes",
+            Metric::RejectRate => "reject_rate",
+            Metric::LockedAccounts => "locked_accounts",
+        }
+    }
+
+    /// The value of the metric for a run
+    fn value(self, point: &TrendPoint) -> f64 {
+        match self {
+            Metric::TotalFunds => point.total_funds,
+            Metric::OpenDisputes => point.open_disputes as f64,
+            Metric::RejectRate => point.reject_rate,
+            Metric::LockedAccounts => point.locked_accounts as f64,
+        }
+    }
+}
+
+/// A run in the trends report, along with the figures that deviated from the trailing average
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TrendPoint {
+    /// The unique identifier of the run
+    pub run_id: String,
+
+    /// When the run started, in milliseconds since the unix epoch
+    pub started_at_ms: u64,
+
+    /// The total funds across every account
+    pub total_funds: f64,
+
+    /// The number of transactions that were being disputed
+    pub open_disputes: u64,
+
+    /// The fraction of the records that were rejected
+    pub reject_rate: f64,
+
+    /// The number of accounts that were locked
+    pub locked_accounts: u64,
+
+    /// The names of the figures that deviated sharply from the trailing average, separated by
+    /// semicolons
+    pub deviations: String,
+}
+
+impl TrendPoint {
+    /// Creates the point for a run, before it's been compared against the runs before it
+    fn new(run: &RunMetadata, summary: &RunSummary) -> Self {
+        TrendPoint {
+            run_id: run.run_id.clone(),
+            started_at_ms: run.started_at_ms,
+            total_funds: summary.total_funds,
+            open_disputes: summary.open_disputes,
+            reject_rate: summary.reject_rate,
+            locked_accounts: summary.locked_accounts,
+            deviations: String::new(),
+        }
+    }
+}
+
+/// Loads the metadata of every run in a directory that has a summary, ordered by when they
+/// started. Only json files are read.
+pub fn load_runs(dir_path: &Path) -> SourceResult<Vec<RunMetadata>> {
+    let entries = fs::read_dir(dir_path)
+        .map_err(|err| SourceError::Io(format!("{}: {}", dir_path.display(), err)))?;
+
+    let mut runs = vec![];
+    for entry in entries {
+        let path = entry
+            .map_err(|err| SourceError::Io(err.to_string()))?
+            .path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+
+        let file = File::open(&path).map_err(|err| SourceError::Io(err.to_string()))?;
+        let run: RunMetadata = serde_json::from_reader(io::BufReader::new(file))
+            .map_err(|err| SourceError::State(path.display().to_string(), err.to_string()))?;
+        if run.summary.is_some() {
+            runs.push(run);
+        }
+    }
+
+    runs.sort_by_key(|run| run.started_at_ms);
+    Ok(runs)
+}
+
+/// Compares each run against the average of the runs before it, within the trailing window
+pub fn trends(runs: &[RunMetadata], config: &TrendConfig) -> Vec<TrendPoint> {
+    let mut points: Vec<TrendPoint> = runs
+        .iter()
+        .filter_map(|run| {
+            run.summary
+                .as_ref()
+                .map(|summary| TrendPoint::new(run, summary))
+        })
+        .collect();
+
+    for index in 0..points.len() {
+        let trailing = &points[index.saturating_sub(config.trailing)..index];
+        let deviations: Vec<&str> = Metric::ALL
+            .into_iter()
+            .filter(|metric| deviates(*metric, &points[index], trailing, config.deviation_pct))
+            .map(Metric::name)
+            .collect();
+
+        points[index].deviations = deviations.join(";");
+    }
+
+    points
+}
+
+/// Whether a figure is further from the trailing average than the percentage allows. Runs
+/// without any previous runs, or with an average of zero, never deviate.
+fn deviates(metric: Metric, point: &TrendPoint, trailing: &[TrendPoint], pct: f64) -> bool {
+    if trailing.is_empty() {
+        return false;
+    }
+
+    let average = trailing.iter().map(|run| metric.value(run)).sum::<f64>() / trailing.len() as f64;
+    if average == 0.0 {
+        return false;
+    }
+
+    (metric.value(point) - average).abs() * 100.0 / average.abs() > pct
+}
+
+/// Writes the trends report as a csv, or as a text chart, to the output file or std out
+pub fn write_trends(
+    points: &[TrendPoint],
+    output_path: Option<&Path>,
+    chart: bool,
+) -> SourceResult<()> {
+    let output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(File::create(path).map_err(|err| SourceError::Io(err.to_string()))?),
+        None => Box::new(io::stdout()),
+    };
+
+    if chart {
+        return write_chart(points, output).map_err(|err| SourceError::Io(err.to_string()));
+    }
+
+    let mut writer = csv::Writer::from_writer(output);
+    for point in points {
+        writer.serialize(point).map_err(SourceError::from)?;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| SourceError::Io(err.to_string()))
+}
+
+/// Draws a line for each figure, with a spark for every run. Runs that deviated are marked with a
+/// caret underneath.
+fn write_chart(points: &[TrendPoint], mut output: impl Write) -> io::Result<()> {
+    writeln!(output, "{} runs", points.len())?;
+
+    for metric in Metric::ALL {
+        let values: Vec<f64> = points.iter().map(|point| metric.value(point)).collect();
+        let markers: String = points
+            .iter()
+            .map(|point| {
+                let deviated = point
+                    .deviations
+                    .split(';')
+                    .any(|name| name == metric.name());
+                if deviated {
+                    '^'
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+
+        writeln!(output, "{:<16}{}", metric.name(), sparkline(&values))?;
+        if markers.contains('^') {
+            writeln!(output, "{:<16}{}", "", markers.trim_end())?;
+        }
+    }
+
+    output.flush()
+}
+
+/// Draws the values as a line of sparks, scaled between the smallest and largest value
+fn sparkline(values: &[f64]) -> String {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    values
+        .iter()
+        .map(|value| {
+            let scale = if max > min {
+                (value - min) / (max - min)
+            } else {
+                0.0
+            };
+            SPARKS[(scale * (SPARKS.len() - 1) as f64).round() as usize]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::FixedClock;
+    use crate::config::EngineConfig;
+    use crate::metadata::{RunMetadata, RunSummary};
+    use crate::trends::{load_runs, sparkline, trends, TrendConfig};
+    use std::fs;
+    use std::io::Error;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    /// Writes the metadata of a run with the given figures to the directory
+    fn write_run(dir: &Path, started_at_ms: u64, total_funds: f64, locked_accounts: u64) {
+        let clock = FixedClock::new(started_at_ms);
+        let mut metadata = RunMetadata::start(&EngineConfig::default(), &clock);
+        metadata.set_summary(RunSummary {
+            total_funds,
+            locked_accounts,
+            ..Default::default()
+        });
+        metadata
+            .write(dir.join(format!("{}.json", started_at_ms)), &clock)
+            .unwrap();
+    }
+
+    // Tests that runs are compared against the trailing average, highlighting the figures that
+    // deviated sharply
+    #[test]
+    fn test_trends() -> Result<(), Error> {
+        let dir = tempdir()?;
+        write_run(dir.path(), 3, 400.0, 1);
+        write_run(dir.path(), 1, 100.0, 1);
+        write_run(dir.path(), 2, 110.0, 1);
+        fs::write(dir.path().join("notes.txt"), "ignored")?;
+
+        let runs = load_runs(dir.path()).unwrap();
+        assert_eq!(runs.len(), 3);
+
+        let config = TrendConfig {
+            trailing: 2,
+            ..Default::default()
+        };
+        let deviations: Vec<String> = trends(&runs, &config)
+            .into_iter()
+            .map(|point| point.deviations)
+            .collect();
+
+        assert_eq!(deviations, vec!["", "", "total_funds"]);
+
+        dir.close()?;
+
+        Ok(())
+    }
+
+    // Tests that values are scaled between the smallest and largest value
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[1.0, 5.0, 3.0]), "▁█▅");
+        assert_eq!(sparkline(&[2.0, 2.0]), "▁▁");
+    }
+}
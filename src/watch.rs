@@ -0,0 +1,249 @@
+use crate::cli::CliArgs;
+use crate::currency::Currency;
+use crate::engine::Engine;
+use crate::error::{EngineResult, ExitReport, SourceError, SourceResult};
+use crate::journal::Journal;
+use crate::lock::StateLock;
+use crate::mapper::Account;
+use crate::reader::read_transactions_from_file;
+use crate::state::{load_state, save_state};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often the drop directory is checked for files that have landed
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The directory within the drop directory that processed files are moved to, when an archive
+/// isn't provided
+const DEFAULT_ARCHIVE: &str = "archive";
+
+/// The directory within the archive that files which couldn't be processed are moved to
+const FAILED_DIR: &str = "failed";
+
+/// A directory that transaction files are dropped into as they arrive. A file has landed once its
+/// size is the same on two checks in a row, so one that's still being copied in isn't read.
+#[derive(Debug)]
+pub struct DropDirectory {
+    /// The directory files are dropped into
+    dir: PathBuf,
+
+    /// The directory files are moved to once they've been processed
+    archive: PathBuf,
+
+    /// The size of each file when the directory was last checked
+    sizes: HashMap<PathBuf, u64>,
+}
+
+impl DropDirectory {
+    /// Watches the directory, moving processed files to the archive, or `archive` within the
+    /// directory when one isn't provided
+    pub fn new(dir: &Path, archive: Option<&Path>) -> Self {
+        DropDirectory {
+            dir: dir.to_path_buf(),
+            archive: archive.map_or_else(|| dir.join(DEFAULT_ARCHIVE), Path::to_path_buf),
+            sizes: HashMap::new(),
+        }
+    }
+
+    /// The files that have landed since the last check, ordered by name. Directories are skipped,
+    /// so the archive can be within the drop directory.
+    pub fn landed(&mut self) -> SourceResult<Vec<PathBuf>> {
+        let entries = fs::read_dir(&self.dir).map_err(|err| watch_error(&self.dir, err))?;
+
+        let mut sizes = HashMap::new();
+        for entry in entries {
+            let entry = entry.map_err(|err| watch_error(&self.dir, err))?;
+            let metadata = entry
+                .metadata()
+                .map_err(|err| watch_error(&self.dir, err))?;
+            if metadata.is_file() {
+                sizes.insert(entry.path(), metadata.len());
+            }
+        }
+
+        let mut landed: Vec<PathBuf> = sizes
+            .iter()
+            .filter(|(path, size)| self.sizes.get(*path) == Some(size))
+            .map(|(path, _)| path.clone())
+            .collect();
+        landed.sort();
+
+        // a file that's landed is forgotten, so it's only seen again if it's dropped again
+        for path in &landed {
+            sizes.remove(path);
+        }
+        self.sizes = sizes;
+
+        Ok(landed)
+    }
+
+    /// Moves a file to the archive, or the failed directory within it when it couldn't be
+    /// processed, returning where it was moved to
+    pub fn archive(&self, file_path: &Path, failed: bool) -> SourceResult<PathBuf> {
+        let dir = match failed {
+            true => self.archive.join(FAILED_DIR),
+            false => self.archive.clone(),
+        };
+        fs::create_dir_all(&dir).map_err(|err| watch_error(&dir, err))?;
+
+        let archived_path = dir.join(file_path.file_name().unwrap_or_default());
+        fs::rename(file_path, &archived_path).map_err(|err| watch_error(file_path, err))?;
+
+        Ok(archived_path)
+    }
+}
+
+/// Processes each csv dropped into the directory as it lands, until the process is stopped. The
+/// accounts carry on from one file to the next, and are saved after each one when a state file
+/// was provided. Processed files are moved to the archive. A file that fails (or has any record
+/// rejected, with --strict) leaves the accounts as they were, and is moved to the failed directory
+/// within the archive instead. The report of each file is written to std err.
+pub fn watch_directory(dir: &Path, args: &CliArgs) -> EngineResult<()> {
+    // hold the state files for as long as the directory is watched
+    let save_path = args.save_state.as_deref().filter(|_| !args.simulate);
+    let _state_lock = StateLock::acquire(args.load_state.as_deref(), save_path, args.lock_mode)?;
+
+    // carry on from the saved state, when a previous run saved any
+    let mut accounts = match (&args.load_state, save_path) {
+        (Some(state_path), _) => load_state(state_path)?,
+        (None, Some(state_path)) if state_path.exists() => load_state(state_path)?,
+        (None, _) => HashMap::new(),
+    };
+
+    let mut drop_dir = DropDirectory::new(dir, args.archive.as_deref());
+    eprintln!("Watching {} for transaction files", dir.display());
+    loop {
+        ingest_landed(&mut drop_dir, &mut accounts, args)?;
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Processes the csvs that have landed in the directory, see watch_directory. Files that aren't in
+/// a format that can be read are left where they are.
+pub fn ingest_landed(
+    drop_dir: &mut DropDirectory,
+    accounts: &mut HashMap<u16, Account>,
+    args: &CliArgs,
+) -> EngineResult<()> {
+    // amounts are checked against, and output in, the currency's minor unit
+    let mut config = args.config.clone();
+    if let Some(code) = &args.currency {
+        config.currency = Some(Currency::resolve(code, &args.minor_unit_overrides)?);
+    }
+    let save_path = args.save_state.as_deref().filter(|_| !args.simulate);
+
+    for file_path in drop_dir.landed()? {
+        let Ok(format) = args.format_detector().detect(&file_path) else {
+            continue;
+        };
+
+        let mut report = ExitReport {
+            strict: args.strict,
+            quiet: args.quiet,
+            redaction: args.redaction.clone(),
+            ..Default::default()
+        };
+        let engine = Engine::new(accounts.clone(), config.clone(), Journal::default());
+        match read_transactions_from_file(&file_path, format, &args.csv, engine, &mut report) {
+            Ok(processed) => {
+                if report.exit_code() == 0 {
+                    *accounts = processed;
+                }
+            }
+            Err(err) => report.fatal = Some(err),
+        }
+
+        let failed = report.exit_code() != 0;
+        if !failed {
+            if let Some(state_path) = save_path {
+                save_state(state_path, accounts)?;
+            }
+        }
+        let archived_path = drop_dir.archive(&file_path, failed)?;
+
+        eprint!("{}", report);
+        let summary = format!(
+            "{}: {} records, {} rejected, moved to {}",
+            file_path.display(),
+            report.records,
+            report.rejections.len(),
+            archived_path.display()
+        );
+        if failed {
+            warn!("Failed {}", summary);
+        } else {
+            info!("Processed {}", summary);
+        }
+    }
+
+    Ok(())
+}
+
+/// The error raised when the drop directory, or the archive, can't be read or written
+fn watch_error(path: &Path, err: impl ToString) -> SourceError {
+    SourceError::Watch(path.display().to_string(), err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::CliArgs;
+    use crate::state::load_state;
+    use crate::test_helpers::*;
+    use crate::watch::{ingest_landed, DropDirectory};
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::io::{Error, Write};
+
+    // Tests that each file is processed once it's landed, carrying the accounts from one file to
+    // the next, and archived
+    #[test]
+    fn test_ingest_landed() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let state_path = dir.path().join("state.bin");
+        let drop_path = dir.path().join("drop");
+        fs::create_dir(&drop_path)?;
+        let args = CliArgs {
+            save_state: Some(state_path.clone()),
+            ..Default::default()
+        };
+        let mut drop_dir = DropDirectory::new(&drop_path, None);
+        let mut accounts = HashMap::new();
+
+        let mut file = File::create(drop_path.join("day1.csv"))?;
+        add_transactions_to_temp_file(vec!["deposit,1,1,10.0"], &mut file)?;
+        File::create(drop_path.join("notes.txt"))?;
+
+        // the file hasn't landed until its size has been seen to stay the same
+        ingest_landed(&mut drop_dir, &mut accounts, &args).unwrap();
+        assert!(accounts.is_empty());
+        ingest_landed(&mut drop_dir, &mut accounts, &args).unwrap();
+        assert_account(&accounts[&1], 10.0, 10.0, true);
+        assert!(drop_path.join("archive/day1.csv").exists());
+        assert!(!drop_path.join("day1.csv").exists());
+        assert!(drop_path.join("notes.txt").exists());
+        assert_account(&load_state(&state_path).unwrap()[&1], 10.0, 10.0, true);
+
+        // a file that can't be processed leaves the accounts and state as they were
+        let mut file = File::create(drop_path.join("day2.csv"))?;
+        add_transactions_to_temp_file(vec!["withdrawal,1,2,4.0"], &mut file)?;
+        let mut broken = File::create(drop_path.join("day2-fix.csv"))?;
+        writeln!(broken, "type,client,tx,amount")?;
+        writeln!(broken, "deposit,x,3,1.0")?;
+
+        ingest_landed(&mut drop_dir, &mut accounts, &args).unwrap();
+        ingest_landed(&mut drop_dir, &mut accounts, &args).unwrap();
+        assert_account(&accounts[&1], 6.0, 6.0, true);
+        assert!(drop_path.join("archive/day2.csv").exists());
+        assert!(drop_path.join("archive/failed/day2-fix.csv").exists());
+        assert_account(&load_state(&state_path).unwrap()[&1], 6.0, 6.0, true);
+
+        drop((file, broken));
+        dir.close()?;
+
+        Ok(())
+    }
+}